@@ -135,7 +135,10 @@ async fn main() -> Result<()> {
                     )
                     .await?
                     {
-                        Some(Response::GenerateTxAck(GenerateTxAck { cell_hash: Some(_hash) })) => {
+                        Some(Response::GenerateTxAck(GenerateTxAck {
+                            cell_hash: Some(_hash),
+                            ..
+                        })) => {
                             // info!("Ack hash: {}", hex::encode(_hash))
                         }
                         other => panic!("Unexpected: {:?}", other),
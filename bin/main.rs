@@ -1,19 +1,20 @@
 use tracing::info;
 use tracing_subscriber;
 
-use clap::{value_t, values_t, App, Arg};
+use clap::{App, Arg, ArgMatches, SubCommand};
 
-use zfx_subzero::server::node;
-use zfx_subzero::zfx_id;
-use zfx_subzero::Result;
+use zfx_subzero::server::config::NodeConfig;
+use zfx_subzero::server::{keygen, node};
+use zfx_subzero::{Error, Result};
 
+use std::path::Path;
 use std::str::FromStr;
 
 /// An entrypoint for starting up a [node](zfx_subzero::server::node::run).
 /// When running from a terminal, accepts the following list of parameters:
 /// * `--listener-ip` or `-a` - IP address and port of the node (ex. 127.0.0.1:1234).
 /// * `--bootstrap-peer` or `-b` - one or more addresses of running nodes of the network for bootstrapping
-/// in format <node_id>@<node_ip_address> (ex. 19Y53ymnBw4LWUpiAMUzPYmYqZmukRhNHm3VyAhzMqckRcuvkf@127.0.0.1:1234).
+/// in format <node_id>@<node_ip_address> (ex. 1tBz2oa6DCjDbVpC8KJijh7zw8gSvRLAiaUmXktmFAFS5fzXuU@127.0.0.1:1234).
 /// * `--keypair` or `-k` - a hex keypair for the node in String format.
 /// * `--use-tls` or `-t` (optional) - indicates whether to use TLS connection.
 /// If true, then `cert_path` and `pk_path` are mandatory parameters.
@@ -22,7 +23,25 @@ use std::str::FromStr;
 /// A sample of certificate can be found in `./deployment/test-certs/*.crt`.
 /// * `--priv-key-path` or `-p` (optional) - path to a private key for the node. Mandatory parameter if `use_tls` flag is true.
 /// A sample of private key can be found in `./deployment/test-certs/*.key`
-/// * `--id` - Id of a node in a hex String format (ex. 19Y53ymnBw4LWUpiAMUzPYmYqZmukRhNHm3VyAhzMqckRcuvkf).
+/// * `--id` - Id of a node in a hex String format (ex. 1tBz2oa6DCjDbVpC8KJijh7zw8gSvRLAiaUmXktmFAFS5fzXuU).
+/// * `--genesis-file` (optional) - path to a TOML or JSON file defining the initial allocations and stakers
+/// of a new network (see [GenesisConfig][zfx_subzero::alpha::genesis::GenesisConfig]).
+/// * `--http-gateway` (optional) - IP address and port of the read-only HTTP/JSON gateway
+/// (ex. 127.0.0.1:8080). Requires the `http_gateway` feature.
+/// * `--max-connections` (optional) - the maximum number of open inbound connections, beyond which
+/// only the bootstrap and known peers can connect.
+/// * `--listen-backlog` (optional) - the maximum number of pending connections in the listen queue.
+/// * `--snapshot-path` (optional) - path to a snapshot of the chain, which is restored on startup
+/// if the file exists, and saved when the node is shut down.
+/// * `--data-dir` (optional) - directory of the databases and the keypair of the node, kept in a
+/// subdirectory named after the node id. Defaults to the temporary directory of the system.
+/// * `--config` (optional) - path to a TOML file with the same settings
+/// (see [NodeConfig][zfx_subzero::server::config::NodeConfig]), which are overridden by the flags above.
+/// The limits of the consensus components can only be set in this file.
+///
+/// The `keygen` subcommand generates a fresh keypair and prints it along with the derived node id.
+/// With `--out-dir`, it also writes a self-signed TLS certificate and private key for the keypair
+/// into the given directory, to be used with `--cert-path` and `--priv-key-path`.
 fn main() -> Result<()> {
     tracing_subscriber::fmt()
         .with_level(true)
@@ -65,7 +84,6 @@ fn main() -> Result<()> {
                 .short("c")
                 .long("cert-path")
                 .value_name("CERT_PATH")
-                .requires("use-tls")
                 .takes_value(true),
         )
         .arg(
@@ -73,49 +91,90 @@ fn main() -> Result<()> {
                 .short("p")
                 .long("priv-key-path")
                 .value_name("PK_PATH")
-                .requires("use-tls")
                 .takes_value(true),
         )
         // FIXME this is a temporary workaround for tcp nodes
         .arg(Arg::with_name("node-id").long("id").value_name("NODE-ID").takes_value(true))
+        .arg(
+            Arg::with_name("genesis-file")
+                .long("genesis-file")
+                .value_name("GENESIS_FILE")
+                .takes_value(true),
+        )
+        .arg(Arg::with_name("config").long("config").value_name("CONFIG").takes_value(true))
+        .arg(
+            Arg::with_name("http-gateway")
+                .long("http-gateway")
+                .value_name("HTTP_GATEWAY")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-connections")
+                .long("max-connections")
+                .value_name("MAX_CONNECTIONS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("listen-backlog")
+                .long("listen-backlog")
+                .value_name("LISTEN_BACKLOG")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("snapshot-path")
+                .long("snapshot-path")
+                .value_name("SNAPSHOT_PATH")
+                .takes_value(true),
+        )
+        .arg(Arg::with_name("data-dir").long("data-dir").value_name("DATA_DIR").takes_value(true))
+        .subcommand(
+            SubCommand::with_name("keygen")
+                .about("Generates a node keypair and prints it along with the node id")
+                .arg(
+                    Arg::with_name("out-dir")
+                        .long("out-dir")
+                        .value_name("OUT_DIR")
+                        .help("Directory to write a TLS certificate and private key to")
+                        .takes_value(true),
+                ),
+        )
         .get_matches();
 
-    let listener_ip =
-        value_t!(matches.value_of("listener-ip"), String).unwrap_or_else(|e| e.exit());
-    let bootstrap_peers =
-        values_t!(matches.values_of("bootstrap-peer"), String).unwrap_or_else(|e| e.exit());
-    let keypair = match matches.value_of("keypair") {
-        Some(keypair_hex) => Some(String::from(keypair_hex)),
-        _ => None,
-    };
-    let use_tls = matches.is_present("use-tls");
-    let cert_path = if use_tls {
-        Some(value_t!(matches.value_of("cert-path"), String).unwrap_or_else(|e| e.exit()))
-    } else {
-        None
+    if let Some(keygen_matches) = matches.subcommand_matches("keygen") {
+        let out_dir = keygen_matches.value_of("out-dir").map(Path::new);
+        let keys = keygen::generate(out_dir).expect("failed to generate the node keys");
+        print!("{}", keys);
+        return Ok(());
+    }
+
+    let cli_config = NodeConfig {
+        listener_ip: matches.value_of("listener-ip").map(String::from),
+        bootstrap_peers: matches
+            .values_of("bootstrap-peer")
+            .map(|peers| peers.map(String::from).collect())
+            .unwrap_or_default(),
+        keypair: matches.value_of("keypair").map(String::from),
+        use_tls: if matches.is_present("use-tls") { Some(true) } else { None },
+        cert_path: matches.value_of("cert-path").map(String::from),
+        pk_path: matches.value_of("pk-path").map(String::from),
+        node_id: matches.value_of("node-id").map(String::from),
+        genesis_file: matches.value_of("genesis-file").map(String::from),
+        http_gateway: matches.value_of("http-gateway").map(String::from),
+        max_connections: parse_number(&matches, "max-connections")?,
+        listen_backlog: parse_number(&matches, "listen-backlog")?,
+        snapshot_path: matches.value_of("snapshot-path").map(String::from),
+        data_dir: matches.value_of("data-dir").map(String::from),
+        ..Default::default()
     };
-    let priv_key_path = if use_tls {
-        Some(value_t!(matches.value_of("pk-path"), String).unwrap_or_else(|e| e.exit()))
-    } else {
-        None
+    let config = match matches.value_of("config") {
+        Some(path) => NodeConfig::from_file(Path::new(path))?.override_with(cli_config),
+        None => cli_config,
     };
+    let node_args = config.validate()?;
 
-    let node_id = match matches.value_of("node-id") {
-        Some(node_str) => Some(zfx_id::Id::from_str(node_str).unwrap()),
-        _ => None,
-    };
     let sys = actix::System::new();
     sys.block_on(async move {
-        node::run(
-            listener_ip,
-            bootstrap_peers,
-            keypair,
-            use_tls,
-            cert_path,
-            priv_key_path,
-            node_id,
-        )
-        .unwrap();
+        let node = node_args.run().unwrap();
 
         let sig = if cfg!(unix) {
             use futures::future::FutureExt;
@@ -133,6 +192,7 @@ fn main() -> Result<()> {
             "Ctrl+C"
         };
         info!(target: "sub-zero", "Got {}, stopping...", sig);
+        node.shutdown(node::SHUTDOWN_TIMEOUT).await;
 
         actix::System::current().stop();
     });
@@ -140,3 +200,14 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Parses the numeric value of the flag `name`, if present
+fn parse_number<T: FromStr>(matches: &ArgMatches, name: &str) -> Result<Option<T>> {
+    match matches.value_of(name) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| Error::InvalidNodeConfig(format!("invalid {} {}", name, value))),
+        None => Ok(None),
+    }
+}
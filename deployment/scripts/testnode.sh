@@ -8,9 +8,9 @@ ZFX_ROOT="$(dirname $0)/../../"
 cd "$ZFX_ROOT"
 pwd
 
-node0_id=12My22AzQQosboCy6TCDFkTQwHTSuHhFN1VDcdDRPUe3H8j3DvY
-node1_id=19Y53ymnBw4LWUpiAMUzPYmYqZmukRhNHm3VyAhzMqckRcuvkf
-node2_id=1A2iUK1VQWMfvtmrBpXXkVJjM5eMWmTfMEcBx4TatSJeuoSH7n
+node0_id=19oHWYAEdSALT6pFZaxzNPj9bEfJFrJfxrU9pZp622FCV35tzy
+node1_id=1tBz2oa6DCjDbVpC8KJijh7zw8gSvRLAiaUmXktmFAFS5fzXuU
+node2_id=12HkbZqmLUnSQTmwa9EyjCnj9cLdgSMDkYczhD9WbV7pVybzb83
 
 node0_ip=127.0.0.1:1234
 node1_ip=127.0.0.1:1235
@@ -4,27 +4,35 @@ use crate::zfx_id::Id;
 
 use crate::colored::Colorize;
 
-use crate::client::{ClientRequest, ClientResponse};
+use crate::cell::{Cell, CellTypeRegistry};
+use crate::client::{self, ClientRequest, ClientResponse};
 use crate::hail::block::HailBlock;
 use crate::hail::{self, Hail};
 use crate::protocol::{Request, Response};
-use crate::server::{InitRouter, Router, ValidatorSet};
+use crate::server::{CommitteeIps, InitRouter, Router, ValidatorSet};
 use crate::sleet::{self, Sleet};
-use crate::storage::block;
+use crate::storage::{
+    self, block, DurabilityConfig, DurableStore, InMemoryStore, KvStore, SledStore,
+    SnapshotValidators,
+};
 use crate::{ice, ice::Ice};
 
 use super::block::{build_genesis, Block};
+use super::chain_config::ChainConfig;
+use super::genesis::GenesisConfig;
+use super::slash::{self, SlashEvidence};
 use super::state::State;
 use super::types::{BlockHash, VrfOutput};
-use super::Result;
+use super::{Error, Result};
 
 use actix::{Actor, Addr, Arbiter, AsyncContext, Context, Handler, Recipient};
 use actix::{ActorFutureExt, ResponseActFuture, WrapFuture};
-use tracing::{debug, info};
+use tracing::{debug, error, info};
 
 use std::collections::{hash_map::Entry, HashMap, HashSet};
 use std::net::SocketAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 /// The actor for `alpha` chain component which
 /// defines all chains known to nodes in the network and implements `Proof-of-Stake`.
@@ -40,7 +48,7 @@ pub struct Alpha {
     /// The id of the node.
     node_id: Id,
     /// The database root for storing blocks.
-    tree: sled::Db,
+    tree: Arc<dyn KvStore>,
     /// The address of the [Ice][crate::ice] actor.
     pub ice: Addr<Ice>,
     /// The address of the [Sleet][crate::sleet] actor.
@@ -51,6 +59,12 @@ pub struct Alpha {
     router: Option<Addr<Router>>,
     /// The `alpha` chain state.
     pub state: State,
+    /// The parameters of the chain.
+    config: ChainConfig,
+    /// The genesis configuration, if the network doesn't use the built-in genesis.
+    genesis_config: Option<GenesisConfig>,
+    /// The file the chain is restored from on startup, and saved to on [ExportSnapshot].
+    snapshot_path: Option<PathBuf>,
 }
 
 impl Alpha {
@@ -71,8 +85,99 @@ impl Alpha {
         sleet: Addr<Sleet>,
         hail: Addr<Hail>,
     ) -> Result<Self> {
-        let tree = sled::open(path)?;
-        Ok(Alpha { sender, node_id, tree, ice, sleet, hail, router: None, state: State::new() })
+        let tree = Arc::new(SledStore::new(sled::open(path)?));
+        Ok(Alpha {
+            sender,
+            node_id,
+            tree,
+            ice,
+            sleet,
+            hail,
+            router: None,
+            state: State::new(),
+            config: ChainConfig::default(),
+            genesis_config: None,
+            snapshot_path: None,
+        })
+    }
+
+    /// Builds the genesis block from `genesis_config` instead of the
+    /// [built-in genesis][build_genesis], and checks the signatures of the accepted cells for its
+    /// network.
+    pub fn with_genesis_config(mut self, genesis_config: GenesisConfig) -> Self {
+        self.state = self.state.with_chain_id(genesis_config.chain_id);
+        self.genesis_config = Some(genesis_config);
+        self
+    }
+
+    /// Checks the custom outputs of the accepted cells with `cell_types`, see
+    /// [State::with_cell_types].
+    pub fn with_cell_types(mut self, cell_types: CellTypeRegistry) -> Self {
+        self.state = self.state.with_cell_types(cell_types);
+        self
+    }
+
+    /// Persist the accepted blocks according to `durability` instead of relying on the
+    /// background flushing of the database.
+    pub fn with_durability(mut self, durability: DurabilityConfig) -> Self {
+        self.tree = Arc::new(DurableStore::new(self.tree, durability));
+        self
+    }
+
+    /// Restores the accepted blocks and the state from the snapshot at `snapshot_path` on startup,
+    /// if the file exists, instead of starting from the genesis. A snapshot is written there on
+    /// [ExportSnapshot].
+    pub fn with_snapshot_path(mut self, snapshot_path: PathBuf) -> Self {
+        self.snapshot_path = Some(snapshot_path);
+        self
+    }
+
+    /// Imports the snapshot at `path` into the `tree` and restores the state from its live cells
+    /// and validators. Returns the hash of the last accepted block.
+    ///
+    /// The `tree` is kept if it is at the last accepted block of the snapshot, as after a restart.
+    /// Throws [storage::Error::StaleSnapshot] if the `tree` is ahead of the snapshot.
+    fn import_snapshot(&mut self, path: &Path, genesis: BlockHash) -> storage::Result<BlockHash> {
+        let cells = InMemoryStore::new();
+        let (last_accepted, validators) =
+            storage::import_snapshot_file(&self.tree, &cells, path, genesis)?;
+        let mut live_cells = HashMap::new();
+        for kv in cells.iter() {
+            let (cell_ids, cell) = kv?;
+            let _ =
+                live_cells.insert(bincode::deserialize(&cell_ids)?, bincode::deserialize(&cell)?);
+        }
+        let (_, last) = block::get_last_accepted(&self.tree)?;
+        self.state = State::restore(last.height, live_cells, validators.validators)?
+            .with_chain_id(self.state.chain_id)
+            .with_cell_types(self.state.cell_types.clone());
+        Ok(last_accepted)
+    }
+
+    /// Writes the last accepted block, the live cells of the state, by their unspent cell ids,
+    /// and the validators to a snapshot at `path`.
+    fn export_snapshot(&self, path: &Path) -> storage::Result<()> {
+        let cells = InMemoryStore::new();
+        for (cell_ids, cell) in self.state.live_cells.iter() {
+            let _ = cells.insert(&bincode::serialize(cell_ids)?, bincode::serialize(cell)?)?;
+        }
+        let validators = SnapshotValidators { validators: self.state.validators.clone() };
+        storage::export_snapshot_file(&self.tree, &cells, validators, path)
+    }
+
+    /// Returns the cell recording `evidence`, or `None` if the equivocation is already slashed.
+    ///
+    /// Throws [Error::InvalidSlashEvidence] if the evidence is invalid or the validator has no
+    /// stake.
+    fn slash_cell(&self, evidence: &SlashEvidence) -> Result<Option<Cell>> {
+        let validator = evidence.validator()?;
+        if self.state.slashed.contains(&(validator, evidence.height)) {
+            return Ok(None);
+        }
+        if !self.state.validators.iter().any(|(id, _)| *id == validator) {
+            return Err(Error::InvalidSlashEvidence);
+        }
+        Ok(Some(slash::slash_cell(evidence)?))
     }
 
     /// Return a set of validators (nodes) [Id]s with staked capacity > 0.
@@ -89,9 +194,28 @@ impl Actor for Alpha {
     type Context = Context<Self>;
 
     fn started(&mut self, _ctx: &mut Context<Self>) {
+        let genesis = match &self.genesis_config {
+            Some(genesis_config) => genesis_config.build_genesis().unwrap(),
+            None => build_genesis().unwrap(),
+        };
+        // Restore the chain from a snapshot, before bootstrapping
+        if let Some(path) = self.snapshot_path.clone().filter(|path| path.exists()) {
+            match self.import_snapshot(&path, genesis.hash().unwrap()) {
+                Ok(last_accepted) => {
+                    info!("restored snapshot => {:?}", hex::encode(last_accepted));
+                    info!("{}", self.state.format());
+                    return;
+                }
+                Err(storage::Error::StaleSnapshot(height)) => info!(
+                    "[{}] the chain is at height {}, not restoring the stale snapshot",
+                    "alpha".yellow(),
+                    height
+                ),
+                Err(e) => error!("[{}] couldn't import snapshot: {}", "alpha".yellow(), e),
+            }
+        }
         // Check for the existence of `genesis` and write to the db if it is not present.
         if !block::exists_genesis(&self.tree) {
-            let genesis = build_genesis().unwrap();
             let hash = block::accept_genesis(&self.tree, genesis.clone()).unwrap();
             info!("accepted genesis => {:?}", hex::encode(hash));
             let genesis_state = self.state.apply(genesis).unwrap();
@@ -107,6 +231,27 @@ impl Actor for Alpha {
     }
 }
 
+/// Writes a snapshot of the chain to the [snapshot path](Alpha::with_snapshot_path), if any,
+/// sent when shutting down.
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "storage::Result<()>")]
+pub struct ExportSnapshot;
+
+impl Handler<ExportSnapshot> for Alpha {
+    type Result = storage::Result<()>;
+
+    fn handle(&mut self, _msg: ExportSnapshot, _ctx: &mut Context<Self>) -> Self::Result {
+        match self.snapshot_path.clone() {
+            Some(path) => {
+                let () = self.export_snapshot(&path)?;
+                info!("[{}] exported snapshot to {}", "alpha".yellow(), path.display());
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
 impl Handler<InitRouter> for Alpha {
     type Result = ();
 
@@ -139,15 +284,17 @@ impl Handler<QueryLastAccepted> for Alpha {
         // Read the last accepted final block (or genesis)
         let (_last_hash, last_block) = block::get_last_accepted(&self.tree).unwrap();
 
-        let send_to_client = self
-            .sender
-            .send(ClientRequest::Fanout { peers: msg.peers, request: Request::GetLastAccepted });
+        let send_to_client = self.sender.send(ClientRequest::Fanout {
+            peers: msg.peers,
+            request: Request::GetLastAccepted,
+            timeout: None,
+        });
         // Probe `k` peers for their last accepted block ignoring errors.
         let send_to_client = actix::fut::wrap_future::<_, Self>(send_to_client);
         let handle_response = send_to_client.map(move |result, _actor, ctx| {
             match result {
-                Ok(ClientResponse::Fanout(responses)) => {
-                    let v = responses
+                Ok(ClientResponse::Fanout(results)) => {
+                    let v = client::successful_responses(results)
                         .iter()
                         .filter_map(|response| {
                             if let Response::LastAccepted(last_accepted) = response {
@@ -238,7 +385,7 @@ impl Handler<ReceiveLastAccepted> for Alpha {
 
             let initialize = async move {
                 // Update the router's knowledge of validators
-                if let Some(addr) = router {
+                if let Some(addr) = router.as_ref() {
                     addr.send(ValidatorSet { validators }).await.unwrap();
                 }
                 // Send `ice` the most up to date information concerning the peers which
@@ -247,11 +394,17 @@ impl Handler<ReceiveLastAccepted> for Alpha {
                 let committee = ice_addr
                     .send(ice::LiveCommittee {
                         total_staking_capacity: state.total_staking_capacity,
-                        validators: state.validators.clone(),
+                        validators: state.committee().unwrap(),
                     })
                     .await
                     .unwrap();
 
+                // Let the server accept the connections of the committee when it is busy
+                if let Some(addr) = router {
+                    let ips = committee.sleet_validators.values().map(|(ip, _)| ip.ip()).collect();
+                    addr.send(CommitteeIps { ips }).await.unwrap();
+                }
+
                 // Convert the states live cells to a `CellHash` mapping for `sleet` (FIXME).
                 let mut map = HashMap::default();
                 for (_, cell) in state.live_cells.iter() {
@@ -263,6 +416,7 @@ impl Handler<ReceiveLastAccepted> for Alpha {
                     .send(sleet::LiveCommittee {
                         validators: committee.sleet_validators.clone(),
                         live_cells: map,
+                        height: state.height,
                     })
                     .await
                     .unwrap();
@@ -281,6 +435,8 @@ impl Handler<ReceiveLastAccepted> for Alpha {
                         total_staking_capacity: state.total_staking_capacity,
                         validators: committee.hail_validators.clone(),
                         vrf_out: msg.last_vrf_output,
+                        stake_owners: state.stake_owners().unwrap(),
+                        stakes: state.staking_periods().unwrap(),
                     })
                     .await
                     .unwrap();
@@ -306,6 +462,9 @@ pub struct LiveNetwork {
     pub self_id: Id,
     /// a list of peers which status changed to [Live][crate::ice::Choice::Live]
     pub live_peers: Vec<(Id, SocketAddr)>,
+    /// Whether the node rejoins the network after being isolated from it, in which case
+    /// `sleet` and `hail` bootstrap again from `live_peers`
+    pub rejoined: bool,
 }
 
 impl Handler<LiveNetwork> for Alpha {
@@ -320,6 +479,13 @@ impl Handler<LiveNetwork> for Alpha {
             peers.push((id, ip));
         }
 
+        // The node may have missed transactions and blocks while it was isolated
+        if msg.rejoined {
+            info!("[{}] rejoined the network, rebootstrapping", "alpha".yellow());
+            self.sleet.do_send(sleet::Rebootstrap { peers: peers.clone() });
+            self.hail.do_send(hail::Rebootstrap { peers: peers.clone() });
+        }
+
         // Initiate the process of fetching the last accepted block
         ctx.notify(QueryLastAccepted { peers })
     }
@@ -392,18 +558,112 @@ impl Handler<GetAncestors> for Alpha {
     }
 }
 
+/// A message to apply an accepted block to the `state`, rewarding its producer.
 #[derive(Debug, Clone, Serialize, Deserialize, Message)]
 #[rtype(result = "()")]
 pub struct AcceptedBlock {
     pub block: Block,
+    /// The validator which produced the block
+    pub producer: Id,
 }
 
 impl Handler<AcceptedBlock> for Alpha {
     type Result = ();
 
-    fn handle(&mut self, _msg: AcceptedBlock, _ctx: &mut Context<Self>) -> Self::Result {
+    fn handle(&mut self, msg: AcceptedBlock, _ctx: &mut Context<Self>) -> Self::Result {
         info!("[{}] received accepted block", "alpha".yellow());
 
-        // TODO
+        match self.state.accept_block(msg.block, &msg.producer, &self.config) {
+            Ok(state) => {
+                self.state = state;
+                info!("{}", self.state.format());
+                self.sleet.do_send(sleet::AcceptedHeight { height: self.state.height });
+                match self.state.staking_periods() {
+                    Ok(stakes) => self.hail.do_send(hail::LiveStakes { stakes }),
+                    Err(e) => error!("[{}] couldn't read the live stakes: {}", "alpha".yellow(), e),
+                }
+            }
+            Err(e) => error!("[{}] couldn't apply accepted block: {}", "alpha".yellow(), e),
+        }
+    }
+}
+
+/// Hands the evidence to [hail][crate::hail] in a [slash cell][slash::slash_cell], which is
+/// included in the next block produced by this validator. The stake of the validator is slashed
+/// once the block is accepted.
+impl Handler<SlashEvidence> for Alpha {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: SlashEvidence, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(cell) = self.slash_cell(&msg)? {
+            info!("[{}] submitted slash evidence at height {}", "alpha".yellow(), msg.height);
+            self.hail.do_send(hail::PendingSlash { cell });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::alpha::transfer::TransferOperation;
+    use crate::client::Client;
+    use crate::ice::dissemination::DisseminationComponent;
+    use crate::ice::Reservoir;
+    use crate::tls::upgrader::tcp_upgraders;
+
+    fn alpha(path: &Path, snapshot_path: &Path) -> Alpha {
+        let client = Client::new(tcp_upgraders().client).start();
+        let ip: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        let id = Id::from_ip(&ip);
+        let dc = DisseminationComponent::new().start();
+        let ice =
+            Ice::new(client.clone().recipient(), id, ip, Reservoir::new(), dc.recipient()).start();
+        let hail = Hail::new(client.clone().recipient(), id, vec![]).start();
+        let sleet =
+            Sleet::new(client.clone().recipient(), hail.clone().recipient(), id, ip, vec![])
+                .start();
+        Alpha::create(client.recipient(), id, path, ice, sleet, hail)
+            .unwrap()
+            .with_snapshot_path(snapshot_path.to_path_buf())
+    }
+
+    #[actix_rt::test]
+    async fn test_snapshot_after_restart() {
+        let path = std::env::temp_dir().join(format!("alpha-{}", Id::generate()));
+        let snapshot_path = std::env::temp_dir().join(format!("snapshot-{}", Id::generate()));
+        let config = ChainConfig::default();
+        let stakers = crate::alpha::initial_staker::genesis_stakers();
+        let genesis = build_genesis().unwrap();
+        let genesis_hash = genesis.hash().unwrap();
+
+        let (exported, last_hash) = {
+            let mut alpha = alpha(&path, &snapshot_path);
+            let _ = block::accept_genesis(&alpha.tree, genesis.clone()).unwrap();
+            alpha.state = alpha.state.apply(genesis.clone()).unwrap();
+            let owner = stakers[1].public_key_hash().unwrap();
+            let transfer_tx =
+                TransferOperation::new(genesis.cells[1].clone(), [1u8; 32], owner, 100)
+                    .transfer(&stakers[1].keypair)
+                    .unwrap();
+            let block = Block::new(genesis_hash, 1, [0u8; 32], vec![transfer_tx]);
+            let _ = block::insert_block(&alpha.tree, block.clone()).unwrap();
+            alpha.state = alpha.state.accept_block(block, &stakers[0].node_id, &config).unwrap();
+            alpha.export_snapshot(&snapshot_path).unwrap();
+            (alpha.state.clone(), block::get_last_accepted_hash(&alpha.tree).unwrap())
+        };
+
+        // The restarted node still holds the blocks of the snapshot, and restores its state
+        let mut alpha = alpha(&path, &snapshot_path);
+        let imported = alpha.import_snapshot(&snapshot_path, genesis_hash);
+        std::fs::remove_file(&snapshot_path).unwrap();
+        assert_eq!(imported, Ok(last_hash));
+        assert_eq!(alpha.tree.iter().count(), 2);
+        assert_eq!(alpha.state.height, exported.height);
+        assert_eq!(alpha.state.live_cells, exported.live_cells);
+        assert_eq!(alpha.state.validators, exported.validators);
+        assert_eq!(alpha.state.total_spending_capacity, exported.total_spending_capacity);
+        assert_eq!(alpha.state.total_staking_capacity, exported.total_staking_capacity);
     }
 }
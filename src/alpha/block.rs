@@ -4,6 +4,8 @@ use super::stake::StakeOperation;
 use super::types::{BlockHash, BlockHeight, VrfOutput};
 use super::Result;
 use crate::cell::Cell;
+use crate::json;
+use crate::zfx_id::Id;
 
 use std::convert::TryInto;
 
@@ -11,11 +13,15 @@ use std::convert::TryInto;
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Block {
     /// Previous block, linked to this one
+    #[serde(with = "crate::json::hex_bytes_opt")]
     pub predecessor: Option<BlockHash>,
     /// Height of the block
     pub height: BlockHeight,
     /// Proof of validity of the block
+    #[serde(with = "crate::json::hex_bytes")]
     pub vrf_out: VrfOutput,
+    /// The validator which produced the block, `None` for the genesis
+    pub producer: Option<Id>,
     /// A list of [Cell]s of this block
     pub cells: Vec<Cell>,
 }
@@ -28,6 +34,9 @@ impl std::fmt::Display for Block {
         };
         s = format!("{}block_height = {:?}\n", s, self.height);
         s = format!("{}vrf_output = {}", s, hex::encode(self.vrf_out));
+        if let Some(producer) = self.producer {
+            s = format!("{}\nproducer = {}", s, producer);
+        }
         write!(f, "{}\n", s)
     }
 }
@@ -69,12 +78,24 @@ pub fn build_genesis() -> Result<Block> {
         cells.push(stake_tx);
     }
     cells.push(allocations_tx);
-    Ok(Block { predecessor: None, height: 0u64, vrf_out: genesis_vrf_out()?, cells })
+    Ok(Block {
+        predecessor: None,
+        height: 0u64,
+        vrf_out: genesis_vrf_out()?,
+        producer: None,
+        cells,
+    })
 }
 
 impl Block {
     pub fn new(predecessor: BlockHash, height: u64, vrf_out: VrfOutput, cells: Vec<Cell>) -> Block {
-        Block { predecessor: Some(predecessor), height, vrf_out, cells }
+        Block { predecessor: Some(predecessor), height, vrf_out, producer: None, cells }
+    }
+
+    /// Attributes the block to the validator `producer`.
+    pub fn with_producer(mut self, producer: Id) -> Block {
+        self.producer = Some(producer);
+        self
     }
 
     // FIXME: Assumption: blake3 produces a big-endian hash
@@ -82,4 +103,33 @@ impl Block {
         let encoded = bincode::serialize(self)?;
         Ok(blake3::hash(&encoded).as_bytes().clone())
     }
+
+    /// Serializes the block to JSON, with hex-encoded hashes.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(json::to_json(self)?)
+    }
+
+    /// Deserializes a block from its JSON representation, see [Block::to_json].
+    pub fn from_json(s: &str) -> Result<Block> {
+        Ok(json::from_json(s)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn test_json_round_trip() {
+        let genesis = build_genesis().unwrap();
+        let block = Block::new(genesis.hash().unwrap(), 1, [7u8; 32], genesis.cells.clone())
+            .with_producer(Id::one());
+        for b in [genesis, block].iter() {
+            let json = b.to_json().unwrap();
+            assert!(json.contains(&hex::encode(b.vrf_out)));
+            let decoded = Block::from_json(&json).unwrap();
+            assert_eq!(&decoded, b);
+            assert_eq!(decoded.hash(), b.hash());
+        }
+    }
 }
@@ -0,0 +1,22 @@
+use crate::cell::types::Capacity;
+
+use super::types::BlockHeight;
+
+/// Parameters of the `alpha` chain, shared by all nodes of the network.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ChainConfig {
+    /// The capacity minted for the producer of each accepted block. As block producers are
+    /// selected in proportion to their stake, the expected rewards of a validator are
+    /// proportional to its stake.
+    pub block_reward: Capacity,
+    /// The number of blocks after which a block reward can be spent.
+    pub reward_maturity: BlockHeight,
+    /// The percentage of its stake a validator loses for equivocating.
+    pub slashing_penalty: u8,
+}
+
+impl Default for ChainConfig {
+    fn default() -> Self {
+        ChainConfig { block_reward: 10, reward_maturity: 0, slashing_penalty: 50 }
+    }
+}
@@ -1,8 +1,10 @@
+use super::chain_config::ChainConfig;
+use super::types::BlockHeight;
 use super::{Error, Result};
 use crate::cell::inputs::Inputs;
 use crate::cell::outputs::{Output, Outputs};
 use crate::cell::types::*;
-use crate::cell::{Cell, CellType};
+use crate::cell::{Cell, CellType, SignatureScheme};
 
 use std::convert::TryInto;
 
@@ -19,7 +21,14 @@ pub struct CoinbaseState;
 /// * `capacity`- starting balance for the account
 pub fn coinbase_output(recipient_address: PublicKeyHash, capacity: Capacity) -> Result<Output> {
     let data = bincode::serialize(&CoinbaseState {})?;
-    Ok(Output { capacity, cell_type: CellType::Coinbase, data, lock: recipient_address })
+    Ok(Output {
+        capacity,
+        cell_type: CellType::Coinbase,
+        data,
+        lock: recipient_address,
+        locktime: None,
+        scheme: SignatureScheme::Ed25519,
+    })
 }
 
 /// Creates a coinbase from a list of balances for each account.
@@ -52,3 +61,31 @@ impl TryInto<Cell> for CoinbaseOperation {
         Ok(Cell::new(Inputs::new(vec![]), Outputs::new(outputs)))
     }
 }
+
+/// Creates the coinbase [Cell] rewarding the producer `recipient_address` of the block at
+/// `height`, according to the `config` of the chain.
+///
+/// The reward can only be spent after [reward_maturity][ChainConfig::reward_maturity] blocks.
+/// As the locktime depends on the height, the rewards of different blocks are distinct cells.
+pub fn reward_cell(
+    recipient_address: PublicKeyHash,
+    height: BlockHeight,
+    config: &ChainConfig,
+) -> Result<Cell> {
+    let output = coinbase_output(recipient_address, config.block_reward)?
+        .with_locktime(height + config.reward_maturity);
+    Ok(Cell::new(Inputs::new(vec![]), Outputs::new(vec![output])))
+}
+
+/// Creates the coinbase [Cell] collecting the `fees` of the transactions in the block at
+/// `height` for its producer `recipient_address`.
+///
+/// The output is locked until `height`, which makes the fee cells of different blocks distinct.
+pub fn fee_cell(
+    recipient_address: PublicKeyHash,
+    height: BlockHeight,
+    fees: Capacity,
+) -> Result<Cell> {
+    let output = coinbase_output(recipient_address, fees)?.with_locktime(height);
+    Ok(Cell::new(Inputs::new(vec![]), Outputs::new(vec![output])))
+}
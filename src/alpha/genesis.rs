@@ -0,0 +1,266 @@
+//! Genesis configuration of a new network
+//!
+//! A [GenesisConfig] lists the initial allocations and the initial validators (stakers) of a
+//! network. It is read from a TOML or JSON file, for example:
+//!
+//! ```toml
+//! chain_id = 1
+//!
+//! [[allocations]]
+//! owner = "0101010101010101010101010101010101010101010101010101010101010101"
+//! capacity = 1000
+//!
+//! [[stakers]]
+//! node_id = "19oHWYAEdSALT6pFZaxzNPj9bEfJFrJfxrU9pZp622FCV35tzy"
+//! public_key = "07ca3851515c89344639fe6a4077923068d1d7fc6106701213c61d34ef8e9416"
+//! stake = 1000
+//! ```
+use crate::zfx_id::Id;
+
+use super::block::{genesis_vrf_out, Block};
+use super::coinbase::CoinbaseOperation;
+use super::stake::stake_output;
+use super::{Error, Result};
+
+use crate::cell::inputs::Inputs;
+use crate::cell::outputs::Outputs;
+use crate::cell::types::{Capacity, ChainId, PublicKeyHash, DEFAULT_CHAIN_ID};
+use crate::cell::Cell;
+
+use ed25519_dalek::PublicKey;
+
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::path::Path;
+
+/// An initial allocation of spendable capacity
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Allocation {
+    /// The public key hash of the owner
+    #[serde(with = "crate::json::hex_bytes")]
+    pub owner: PublicKeyHash,
+    pub capacity: Capacity,
+}
+
+/// An initial validator and its stake
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GenesisStaker {
+    /// The id of the validator node
+    #[serde(with = "id_string")]
+    pub node_id: Id,
+    /// The ed25519 public key of the owner of the stake
+    #[serde(with = "crate::json::hex_bytes")]
+    pub public_key: [u8; 32],
+    /// The staked capacity
+    pub stake: Capacity,
+}
+
+/// The initial allocations and validators of a network, see the [module docs][self].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GenesisConfig {
+    /// The id of the network, which the signatures of cells are bound to
+    #[serde(default = "default_chain_id")]
+    pub chain_id: ChainId,
+    #[serde(default)]
+    pub allocations: Vec<Allocation>,
+    pub stakers: Vec<GenesisStaker>,
+}
+
+fn default_chain_id() -> ChainId {
+    DEFAULT_CHAIN_ID
+}
+
+impl GenesisConfig {
+    /// Reads and validates the genesis configuration from a JSON file if the extension of
+    /// `path` is `.json`, otherwise from a TOML file.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| Error::InvalidGenesisConfig(format!("{}: {}", path.display(), e)))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => GenesisConfig::from_json(&contents),
+            _ => GenesisConfig::from_toml(&contents),
+        }
+    }
+
+    /// Parses and validates a TOML genesis configuration.
+    pub fn from_toml(s: &str) -> Result<Self> {
+        let config: GenesisConfig =
+            toml::from_str(s).map_err(|e| Error::InvalidGenesisConfig(format!("{}", e)))?;
+        let () = config.validate()?;
+        Ok(config)
+    }
+
+    /// Parses and validates a JSON genesis configuration.
+    pub fn from_json(s: &str) -> Result<Self> {
+        let config: GenesisConfig = crate::json::from_json(s)?;
+        let () = config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that there is at least one staker, that stakers have distinct node ids, valid
+    /// public keys and non-zero stakes, and that allocations are non-zero.
+    pub fn validate(&self) -> Result<()> {
+        if self.stakers.is_empty() {
+            return Err(Error::InvalidGenesisConfig("no stakers".to_owned()));
+        }
+        let mut node_ids = HashSet::new();
+        for staker in self.stakers.iter() {
+            if !node_ids.insert(staker.node_id) {
+                let msg = format!("duplicate node id {}", staker.node_id);
+                return Err(Error::InvalidGenesisConfig(msg));
+            }
+            if staker.stake == 0 {
+                let msg = format!("zero stake of {}", staker.node_id);
+                return Err(Error::InvalidGenesisConfig(msg));
+            }
+            let _ = staker.public_key_hash()?;
+        }
+        for allocation in self.allocations.iter() {
+            if allocation.capacity == 0 {
+                let msg = format!("zero allocation of {}", hex::encode(allocation.owner));
+                return Err(Error::InvalidGenesisConfig(msg));
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds the genesis [Block], with a coinbase cell of the allocations and a cell of the
+    /// initial stakes. Stakes at genesis are minted, as they have no owner's funds to consume.
+    pub fn build_genesis(&self) -> Result<Block> {
+        let mut cells = vec![];
+        if !self.allocations.is_empty() {
+            let allocations = self.allocations.iter().map(|a| (a.owner, a.capacity)).collect();
+            let allocations_tx: Cell = CoinbaseOperation::new(allocations).try_into()?;
+            cells.push(allocations_tx);
+        }
+        let mut stakes = vec![];
+        for staker in self.stakers.iter() {
+            stakes.push(stake_output(staker.node_id, staker.public_key_hash()?, staker.stake)?);
+        }
+        cells.push(Cell::new(Inputs::new(vec![]), Outputs::new(stakes)));
+        Ok(Block {
+            predecessor: None,
+            height: 0u64,
+            vrf_out: genesis_vrf_out()?,
+            producer: None,
+            cells,
+        })
+    }
+}
+
+impl GenesisStaker {
+    /// Returns the public key hash of the owner of the stake.
+    pub fn public_key_hash(&self) -> Result<PublicKeyHash> {
+        let public_key = PublicKey::from_bytes(&self.public_key)?;
+        Ok(Id::from_public_key(&public_key).bytes())
+    }
+}
+
+/// Serde helpers for [Id]s written as strings, as accepted by [Id::from_str][std::str::FromStr]
+mod id_string {
+    use crate::zfx_id::Id;
+
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use std::str::FromStr;
+
+    pub fn serialize<S: Serializer>(id: &Id, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&id.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Id, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Id::from_str(&s).map_err(|_| D::Error::custom(format!("invalid id {}", s)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::alpha::state::State;
+    use crate::cell::CellType;
+
+    use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
+
+    fn public_key_hex() -> String {
+        hex::encode(Keypair::generate(&mut OsRng {}).public.as_bytes())
+    }
+
+    #[actix_rt::test]
+    async fn test_genesis_from_file() {
+        let (pk1, pk2) = (public_key_hex(), public_key_hex());
+        let toml = format!(
+            r#"
+            chain_id = 7
+
+            [[allocations]]
+            owner = "{}"
+            capacity = 500
+
+            [[stakers]]
+            node_id = "19oHWYAEdSALT6pFZaxzNPj9bEfJFrJfxrU9pZp622FCV35tzy"
+            public_key = "{}"
+            stake = 3000
+
+            [[stakers]]
+            node_id = "1tBz2oa6DCjDbVpC8KJijh7zw8gSvRLAiaUmXktmFAFS5fzXuU"
+            public_key = "{}"
+            stake = 1000
+            "#,
+            hex::encode([1u8; 32]),
+            pk1,
+            pk2
+        );
+        let path = std::env::temp_dir().join(format!("genesis-{}.toml", pk1));
+        std::fs::write(&path, toml).unwrap();
+        let config = GenesisConfig::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(config.chain_id, 7);
+
+        let state = State::new().apply(config.build_genesis().unwrap()).unwrap();
+        assert_eq!(state.total_spending_capacity, 500);
+        assert_eq!(state.total_staking_capacity, 4000);
+        let outputs: Vec<_> = state
+            .live_cells
+            .values()
+            .flat_map(|cell| cell.outputs().iter().cloned().collect::<Vec<_>>())
+            .collect();
+        assert_eq!(outputs.len(), 3);
+        assert_eq!(outputs.iter().filter(|o| o.cell_type == CellType::Stake).count(), 2);
+
+        let (id1, id2) = (config.stakers[0].node_id, config.stakers[1].node_id);
+        assert_eq!(state.validator_weight(&id1).unwrap(), 0.75);
+        assert_eq!(state.validator_weight(&id2).unwrap(), 0.25);
+
+        // The JSON representation is equivalent
+        let json = crate::json::to_json(&config).unwrap();
+        assert_eq!(GenesisConfig::from_json(&json).unwrap(), config);
+    }
+
+    #[actix_rt::test]
+    async fn test_invalid_genesis() {
+        let staker = |node_id: &str, stake| {
+            format!(
+                "[[stakers]]\nnode_id = \"{}\"\npublic_key = \"{}\"\nstake = {}\n",
+                node_id,
+                public_key_hex(),
+                stake
+            )
+        };
+        let id = "19oHWYAEdSALT6pFZaxzNPj9bEfJFrJfxrU9pZp622FCV35tzy";
+        let duplicate = format!("{}{}", staker(id, 10), staker(id, 20));
+        assert!(matches!(
+            GenesisConfig::from_toml(&duplicate),
+            Err(Error::InvalidGenesisConfig(_))
+        ));
+        assert!(matches!(
+            GenesisConfig::from_toml(&staker(id, 0)),
+            Err(Error::InvalidGenesisConfig(_))
+        ));
+        let config = GenesisConfig::from_toml(&staker(id, 10)).unwrap();
+        assert_eq!(config.chain_id, DEFAULT_CHAIN_ID);
+    }
+}
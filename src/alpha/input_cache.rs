@@ -0,0 +1,116 @@
+//! A cache of the outputs spent by inputs, in front of the live cells of the [State][super::state::State]
+//!
+//! Resolving an input otherwise scans all live cells, which is repeated for every input of every
+//! validated cell.
+use crate::cell::outputs::Output;
+use crate::cell::types::CellHash;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// The default number of outputs kept in an [InputResolutionCache]
+pub const INPUT_CACHE_CAPACITY: usize = 4096;
+
+/// Identifies an output by the hash of its cell and its index in the cell
+pub type OutputKey = (CellHash, u8);
+
+/// A bounded cache of live outputs.
+///
+/// Outputs are cached when their cell is accepted or when they are looked up, and the oldest ones
+/// are evicted beyond the capacity. The owner has to [invalidate](InputResolutionCache::invalidate)
+/// outputs once they are spent or pruned.
+#[derive(Debug)]
+pub struct InputResolutionCache {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Clone)]
+struct Inner {
+    capacity: usize,
+    outputs: HashMap<OutputKey, Output>,
+    /// Insertion order of the keys, which may contain already invalidated keys
+    order: VecDeque<OutputKey>,
+    hits: usize,
+    misses: usize,
+}
+
+impl InputResolutionCache {
+    pub fn new(capacity: usize) -> Self {
+        let inner =
+            Inner { capacity, outputs: HashMap::new(), order: VecDeque::new(), hits: 0, misses: 0 };
+        InputResolutionCache { inner: Mutex::new(inner) }
+    }
+
+    /// Returns the cached output of `key`, or resolves it with `resolve` and caches the result.
+    pub fn get_or_resolve(
+        &self,
+        key: OutputKey,
+        resolve: impl FnOnce() -> Option<Output>,
+    ) -> Option<Output> {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if let Some(output) = inner.outputs.get(&key).cloned() {
+                inner.hits += 1;
+                return Some(output);
+            }
+            inner.misses += 1;
+        }
+        let output = resolve()?;
+        self.insert(key, output.clone());
+        Some(output)
+    }
+
+    /// Caches a live output.
+    pub fn insert(&self, key: OutputKey, output: Output) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.outputs.insert(key, output).is_none() {
+            inner.order.push_back(key);
+        }
+        while inner.outputs.len() > inner.capacity {
+            match inner.order.pop_front() {
+                Some(oldest) => {
+                    let _ = inner.outputs.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+        // Drop the keys invalidated in the meantime, so that `order` stays bounded
+        if inner.order.len() > 2 * inner.capacity {
+            let Inner { outputs, order, .. } = &mut *inner;
+            order.retain(|key| outputs.contains_key(key));
+        }
+    }
+
+    /// Removes a spent or pruned output.
+    pub fn invalidate(&self, key: &OutputKey) {
+        let _ = self.inner.lock().unwrap().outputs.remove(key);
+    }
+
+    /// Removes all outputs and resets the statistics.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.outputs.clear();
+        inner.order.clear();
+        inner.hits = 0;
+        inner.misses = 0;
+    }
+
+    /// Returns the number of lookups served from the cache, and of those which had to be resolved.
+    pub fn stats(&self) -> (usize, usize) {
+        let inner = self.inner.lock().unwrap();
+        (inner.hits, inner.misses)
+    }
+}
+
+impl Default for InputResolutionCache {
+    fn default() -> Self {
+        InputResolutionCache::new(INPUT_CACHE_CAPACITY)
+    }
+}
+
+impl Clone for InputResolutionCache {
+    fn clone(&self) -> Self {
+        let inner = self.inner.lock().unwrap().clone();
+        InputResolutionCache { inner: Mutex::new(inner) }
+    }
+}
@@ -4,14 +4,18 @@
 //! to exist (primitives for transfers and staking namely), so that there is a notion of state
 //! capacity on the network (this is necessary in order to provide sybil resistance).
 mod alpha;
+pub mod chain_config;
 pub mod types;
 
 pub mod coinbase;
+pub mod slash;
 pub mod stake;
 pub mod transfer;
 
 pub mod block;
+pub mod genesis;
 
+pub mod input_cache;
 pub mod state;
 
 pub mod initial_staker;
@@ -28,6 +32,7 @@ pub enum Error {
     Hex(String),
     Bincode(String),
     Dalek(String),
+    Json(String),
     Cell(cell::Error),
     Graph(graph::Error),
     // Alpha
@@ -43,6 +48,14 @@ pub enum Error {
     UndefinedCellIds,
     ExistingCellIds,
     ExceedsCapacity,
+    /// The block producer has no stake
+    UnknownBlockProducer,
+    /// The blocks aren't proof of a validator equivocating
+    InvalidSlashEvidence,
+    /// Undelegated capacity must be locked for the unbonding period
+    InvalidUndelegation,
+    /// The genesis configuration can't be read or is invalid
+    InvalidGenesisConfig(String),
 }
 
 impl std::error::Error for Error {}
@@ -53,6 +66,12 @@ impl std::convert::From<sled::Error> for Error {
     }
 }
 
+impl std::convert::From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Json(format!("{:?}", error))
+    }
+}
+
 impl std::convert::From<hex::FromHexError> for Error {
     fn from(error: hex::FromHexError) -> Self {
         Error::Hex(format!("{:?}", error))
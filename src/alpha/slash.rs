@@ -0,0 +1,103 @@
+//! Evidence of validators equivocating, i.e. proposing two different blocks at the same height
+use crate::zfx_id::Id;
+
+use crate::cell::inputs::Inputs;
+use crate::cell::outputs::{Output, Outputs};
+use crate::cell::{Cell, CellType, SignatureScheme};
+
+use super::block::Block;
+use super::types::BlockHeight;
+use super::{Error, Result};
+
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+
+/// A [Block] signed by the validator which proposed it.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SignedBlock {
+    pub block: Block,
+    /// The public key of the proposer, from which its [Id] is derived
+    pub public_key: PublicKey,
+    /// The signature of the block hash
+    pub signature: Signature,
+}
+
+impl SignedBlock {
+    /// Signs the hash of `block` with `keypair`.
+    pub fn new(keypair: &Keypair, block: Block) -> Result<Self> {
+        let signature = keypair.sign(&block.hash()?);
+        Ok(SignedBlock { block, public_key: keypair.public, signature })
+    }
+
+    /// Returns the [Id] of the proposer.
+    pub fn proposer(&self) -> Id {
+        Id::from_public_key(&self.public_key)
+    }
+
+    /// Checks that the signature of the block is valid.
+    pub fn verify(&self) -> Result<()> {
+        let () = self.public_key.verify(&self.block.hash()?, &self.signature)?;
+        Ok(())
+    }
+}
+
+/// Evidence that a validator proposed two different blocks at the same `height`.
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "Result<()>")]
+pub struct SlashEvidence {
+    pub height: BlockHeight,
+    pub block_a: SignedBlock,
+    pub block_b: SignedBlock,
+}
+
+impl SlashEvidence {
+    /// Returns the [Id] of the equivocating validator.
+    ///
+    /// Throws [Error::InvalidSlashEvidence] unless both blocks are at `height`, are different
+    /// and are validly signed by the same validator.
+    pub fn validator(&self) -> Result<Id> {
+        let (a, b) = (&self.block_a, &self.block_b);
+        if a.block.height != self.height
+            || b.block.height != self.height
+            || a.public_key != b.public_key
+            || a.block.hash()? == b.block.hash()?
+            || a.verify().is_err()
+            || b.verify().is_err()
+        {
+            return Err(Error::InvalidSlashEvidence);
+        }
+        Ok(a.proposer())
+    }
+}
+
+/// Creates the [slash output][CellType::Slash] recording `evidence`, locked to the id of the
+/// equivocating validator.
+///
+/// Throws [Error::InvalidSlashEvidence] if the evidence is invalid.
+pub fn slash_output(evidence: &SlashEvidence) -> Result<Output> {
+    let validator = evidence.validator()?;
+    let data = bincode::serialize(evidence)?;
+    Ok(Output {
+        capacity: 0,
+        cell_type: CellType::Slash,
+        data,
+        lock: validator.bytes(),
+        locktime: None,
+        scheme: SignatureScheme::Ed25519,
+    })
+}
+
+/// Creates the cell recording `evidence`. It pays no fee, so it is only included in a block by its
+/// producer. The stake of the validator is slashed once the block is accepted, see
+/// [State::accept_block][super::state::State::accept_block].
+pub fn slash_cell(evidence: &SlashEvidence) -> Result<Cell> {
+    let output = slash_output(evidence)?;
+    Ok(Cell::new(Inputs::new(vec![]), Outputs::new(vec![output])))
+}
+
+/// Returns the evidence recorded by the slash output of `cell`, if it has one.
+pub fn slash_evidence(cell: &Cell) -> Result<Option<SlashEvidence>> {
+    match cell.outputs().iter().find(|o| o.cell_type == CellType::Slash) {
+        Some(output) => Ok(Some(bincode::deserialize(&output.data)?)),
+        None => Ok(None),
+    }
+}
@@ -5,11 +5,13 @@ use crate::alpha::transfer;
 use crate::cell::inputs::Inputs;
 use crate::cell::outputs::{Output, Outputs};
 use crate::cell::types::*;
-use crate::cell::{Cell, CellType};
+use crate::cell::{Cell, CellType, SignatureScheme};
 
 use super::Result;
 
-use crate::cell::cell_operation::{consume_from_cell, ConsumeResult};
+use crate::cell::cell_operation::{consume_from_cell, consume_from_cell_outputs, ConsumeResult};
+
+use super::types::BlockHeight;
 use ed25519_dalek::Keypair;
 
 /// State of stake assigned to `data` property of [Output]
@@ -17,13 +19,48 @@ use ed25519_dalek::Keypair;
 pub struct StakeState {
     /// Id of a node which was responsible for staking an account
     pub node_id: Id,
+    /// The height from which the stake counts towards the committee
+    pub start_height: BlockHeight,
+    /// The height from which the stake no longer counts towards the committee
+    pub end_height: BlockHeight,
+}
+
+impl StakeState {
+    /// Whether the stake counts towards the committee at `height`.
+    pub fn is_active(&self, height: BlockHeight) -> bool {
+        self.start_height <= height && height < self.end_height
+    }
 }
 
 /// A stake output locks tokens for a specific duration and can be used to stake on the network until
 /// the time expires.
 pub fn stake_output(node_id: Id, pkh: PublicKeyHash, capacity: Capacity) -> Result<Output> {
-    let data = bincode::serialize(&StakeState { node_id })?;
-    Ok(Output { capacity, cell_type: CellType::Stake, data, lock: pkh })
+    staking_period_output(node_id, pkh, capacity, 0, BlockHeight::MAX)
+}
+
+/// A stake output which only counts towards the committee from `start_height` (inclusive)
+/// until `end_height` (exclusive).
+///
+/// Throws [Error::InvalidStake][super::Error::InvalidStake] if the period is empty.
+pub fn staking_period_output(
+    node_id: Id,
+    pkh: PublicKeyHash,
+    capacity: Capacity,
+    start_height: BlockHeight,
+    end_height: BlockHeight,
+) -> Result<Output> {
+    if start_height >= end_height {
+        return Err(super::Error::InvalidStake);
+    }
+    let data = bincode::serialize(&StakeState { node_id, start_height, end_height })?;
+    Ok(Output {
+        capacity,
+        cell_type: CellType::Stake,
+        data,
+        lock: pkh,
+        locktime: None,
+        scheme: SignatureScheme::Ed25519,
+    })
 }
 
 /// Creates a stake from [Cell] with indicated capacity for account.
@@ -36,6 +73,12 @@ pub struct StakeOperation {
     address: PublicKeyHash,
     /// The amount of capacity to stake.
     capacity: Capacity,
+    /// The height from which the stake counts towards the committee.
+    start_height: BlockHeight,
+    /// The height from which the stake no longer counts towards the committee.
+    end_height: BlockHeight,
+    /// The network the stake is signed for.
+    chain_id: ChainId,
 }
 
 impl StakeOperation {
@@ -49,7 +92,29 @@ impl StakeOperation {
     /// * `address` - account's public key for whom to stake the balance from `cell`.
     /// * `capacity` - a balance to stake for `address`.
     pub fn new(cell: Cell, node_id: Id, address: PublicKeyHash, capacity: Capacity) -> Self {
-        StakeOperation { cell, node_id, address, capacity }
+        StakeOperation {
+            cell,
+            node_id,
+            address,
+            capacity,
+            start_height: 0,
+            end_height: BlockHeight::MAX,
+            chain_id: DEFAULT_CHAIN_ID,
+        }
+    }
+
+    /// Only counts the stake towards the committee from `start_height` (inclusive) until
+    /// `end_height` (exclusive), instead of from genesis on.
+    pub fn with_period(mut self, start_height: BlockHeight, end_height: BlockHeight) -> Self {
+        self.start_height = start_height;
+        self.end_height = end_height;
+        self
+    }
+
+    /// Signs the stake for the network `chain_id` instead of the default one.
+    pub fn with_chain_id(mut self, chain_id: ChainId) -> Self {
+        self.chain_id = chain_id;
+        self
     }
 
     /// Stake balance and create a new [Cell] with list of outputs
@@ -73,10 +138,16 @@ impl StakeOperation {
     /// * `keypair` - the account's keypair for identifying outputs for staking.
     pub fn stake(&self, keypair: &Keypair) -> Result<Cell> {
         let ConsumeResult { consumed, residue, inputs } =
-            consume_from_cell(&self.cell, self.capacity, keypair)?;
+            consume_from_cell(&self.cell, self.capacity, keypair, self.chain_id)?;
 
         // Create a change output.
-        let main_output = stake_output(self.node_id.clone(), self.address.clone(), consumed)?;
+        let main_output = staking_period_output(
+            self.node_id.clone(),
+            self.address.clone(),
+            consumed,
+            self.start_height,
+            self.end_height,
+        )?;
         let outputs = if residue > FEE && residue - FEE > 0 {
             vec![main_output, transfer::transfer_output(self.address.clone(), residue - FEE)?]
         } else {
@@ -87,6 +158,138 @@ impl StakeOperation {
     }
 }
 
+/// The number of blocks after which undelegated capacity can be spent again.
+pub const UNBONDING_PERIOD: BlockHeight = 10;
+
+/// State of a delegation assigned to `data` property of [Output]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DelegateState {
+    /// Id of the validator whose weight is increased by the delegated capacity
+    pub node_id: Id,
+}
+
+/// A delegate output adds its capacity to the weight of the validator `node_id`, while the
+/// capacity remains owned by `pkh`.
+pub fn delegate_output(node_id: Id, pkh: PublicKeyHash, capacity: Capacity) -> Result<Output> {
+    let data = bincode::serialize(&DelegateState { node_id })?;
+    Ok(Output {
+        capacity,
+        cell_type: CellType::Delegate,
+        data,
+        lock: pkh,
+        locktime: None,
+        scheme: SignatureScheme::Ed25519,
+    })
+}
+
+/// Delegates capacity of an account from a [Cell] to the stake of a validator.
+pub struct DelegateOperation {
+    /// The cell being delegated in this operation.
+    cell: Cell,
+    /// The node id of the validator.
+    node_id: Id,
+    /// The owner of the delegated capacity.
+    address: PublicKeyHash,
+    /// The amount of capacity to delegate.
+    capacity: Capacity,
+    /// The network the delegation is signed for.
+    chain_id: ChainId,
+}
+
+impl DelegateOperation {
+    /// Create a delegate operation from the provided [Cell] to the validator `node_id`.
+    /// The method [delegate][DelegateOperation::delegate] should be called to complete it.
+    ///
+    /// ## Parameters
+    /// * `cell` - the requested `capacity` will be taken out from this cell,
+    /// if it has spendable outputs with enough balance for the owner with `address`.
+    /// * `node_id` - id of the validator to delegate to.
+    /// * `address` - account's public key hash which keeps owning the delegated capacity.
+    /// * `capacity` - a balance to delegate.
+    pub fn new(cell: Cell, node_id: Id, address: PublicKeyHash, capacity: Capacity) -> Self {
+        DelegateOperation { cell, node_id, address, capacity, chain_id: DEFAULT_CHAIN_ID }
+    }
+
+    /// Signs the delegation for the network `chain_id` instead of the default one.
+    pub fn with_chain_id(mut self, chain_id: ChainId) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    /// Delegate the balance and create a new [Cell] with a [delegate output][delegate_output]
+    /// and a change output, like [StakeOperation::stake].
+    ///
+    /// Only [transfer][CellType::Transfer] and [coinbase][CellType::Coinbase] outputs are
+    /// consumed, so that stakes and delegations can't be delegated again.
+    pub fn delegate(&self, keypair: &Keypair) -> Result<Cell> {
+        let ConsumeResult { consumed, residue, inputs } = consume_from_cell_outputs(
+            &self.cell,
+            self.capacity,
+            keypair,
+            self.chain_id,
+            |output| {
+                output.cell_type == CellType::Transfer || output.cell_type == CellType::Coinbase
+            },
+        )?;
+
+        let main_output = delegate_output(self.node_id.clone(), self.address.clone(), consumed)?;
+        let outputs = if residue > FEE && residue - FEE > 0 {
+            vec![main_output, transfer::transfer_output(self.address.clone(), residue - FEE)?]
+        } else {
+            vec![main_output]
+        };
+
+        Ok(Cell::new(Inputs::new(inputs), Outputs::new(outputs)))
+    }
+}
+
+/// Returns the delegated capacity of an account in a [Cell] to its control.
+pub struct UndelegateOperation {
+    /// The cell containing the delegate outputs.
+    cell: Cell,
+    /// The owner of the delegated capacity.
+    address: PublicKeyHash,
+    /// The network the undelegation is signed for.
+    chain_id: ChainId,
+}
+
+impl UndelegateOperation {
+    /// Create an undelegate operation for the delegate outputs of `address` in `cell`.
+    /// The method [undelegate][UndelegateOperation::undelegate] should be called to complete it.
+    pub fn new(cell: Cell, address: PublicKeyHash) -> Self {
+        UndelegateOperation { cell, address, chain_id: DEFAULT_CHAIN_ID }
+    }
+
+    /// Signs the undelegation for the network `chain_id` instead of the default one.
+    pub fn with_chain_id(mut self, chain_id: ChainId) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    /// Create a new [Cell] spending the delegate outputs of the owner, with a transfer output of
+    /// their capacity minus [FEE], which can only be spent after the [UNBONDING_PERIOD] from
+    /// the block `height` containing the cell.
+    pub fn undelegate(&self, keypair: &Keypair, height: BlockHeight) -> Result<Cell> {
+        let delegated: Capacity = self
+            .cell
+            .outputs_of_owner(&self.address)
+            .iter()
+            .filter(|output| output.cell_type == CellType::Delegate)
+            .map(|output| output.capacity)
+            .sum();
+        let ConsumeResult { consumed, inputs, .. } = consume_from_cell_outputs(
+            &self.cell,
+            delegated.saturating_sub(FEE),
+            keypair,
+            self.chain_id,
+            |output| output.cell_type == CellType::Delegate,
+        )?;
+        let output = transfer::transfer_output(self.address.clone(), consumed)?
+            .with_locktime(height + UNBONDING_PERIOD);
+        Ok(Cell::new(Inputs::new(inputs), Outputs::new(vec![output])))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::super::Error;
@@ -133,6 +336,19 @@ mod test {
         assert_eq!(c3.sum(), 1000 - FEE);
     }
 
+    #[actix_rt::test]
+    async fn test_stake_empty_period() {
+        let (kp1, _kp2, _pkh1, pkh2) = generate_keys();
+
+        let c1 = generate_coinbase(&kp1, 1000);
+        let stake_op = StakeOperation::new(c1.clone(), Id::generate(), pkh2, 500);
+        assert!(stake_op.with_period(5, 10).stake(&kp1).is_ok());
+        let stake_op = StakeOperation::new(c1.clone(), Id::generate(), pkh2, 500);
+        assert_eq!(stake_op.with_period(10, 10).stake(&kp1), Err(Error::InvalidStake));
+        let stake_op = StakeOperation::new(c1, Id::generate(), pkh2, 500);
+        assert_eq!(stake_op.with_period(10, 5).stake(&kp1), Err(Error::InvalidStake));
+    }
+
     fn hash_public(keypair: &Keypair) -> [u8; 32] {
         let enc = bincode::serialize(&keypair.public).unwrap();
         blake3::hash(&enc).as_bytes().clone()
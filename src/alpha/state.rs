@@ -1,16 +1,26 @@
 use crate::zfx_id::Id;
 
 use super::block::Block;
-use super::stake::StakeState;
+use super::chain_config::ChainConfig;
+use super::coinbase;
+use super::input_cache::InputResolutionCache;
+use super::slash::{self, SlashEvidence};
+use super::stake::{DelegateState, StakeState, UNBONDING_PERIOD};
+use super::types::{BlockHeight, Weight};
 use super::{Error, Result};
 
-use crate::cell::types::Capacity;
-use crate::cell::{Cell, CellId, CellIds, CellType};
+use crate::cell::cell_operation;
+use crate::cell::inputs::{Input, Inputs};
+use crate::cell::outputs::{Output, Outputs};
+use crate::cell::types::{Capacity, CellHash, ChainId, PublicKeyHash, DEFAULT_CHAIN_ID};
+use crate::cell::{self, Cell, CellId, CellIds, CellType, CellTypeRegistry};
 
 use crate::colored::Colorize;
 use crate::graph::dependency_graph::DependencyGraph;
+use crate::hail::StakingPeriod;
+use crate::util;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 /// Data structure for storing state of the [alpha][crate::alpha::Alpha] component.
 ///
@@ -27,6 +37,15 @@ pub struct State {
     pub validators: Vec<(Id, Capacity)>,
     /// A mapping of a cell ids (inputs) to unspent cell outputs.
     pub live_cells: HashMap<CellIds, Cell>,
+    /// The validators slashed for equivocating and the heights of the evidence, as recorded by
+    /// the live [slash outputs][CellType::Slash].
+    pub slashed: HashSet<(Id, BlockHeight)>,
+    /// The network the inputs of the applied cells have to be signed for.
+    pub chain_id: ChainId,
+    /// The validators of the custom outputs of the applied cells.
+    pub cell_types: CellTypeRegistry,
+    /// The live outputs recently accepted or resolved, kept in step with `live_cells`.
+    input_cache: InputResolutionCache,
 }
 
 impl State {
@@ -34,6 +53,7 @@ impl State {
     /// * `height` = 0
     /// * `total_spending_capacity` = 0
     /// * `total_staking_capacity` = 0
+    /// * `chain_id` = [DEFAULT_CHAIN_ID]
     pub fn new() -> Self {
         State {
             height: 0,
@@ -41,9 +61,62 @@ impl State {
             total_staking_capacity: 0,
             validators: vec![],
             live_cells: HashMap::default(),
+            slashed: HashSet::new(),
+            chain_id: DEFAULT_CHAIN_ID,
+            cell_types: CellTypeRegistry::new(),
+            input_cache: InputResolutionCache::default(),
         }
     }
 
+    /// Checks the signatures of the applied cells for the network `chain_id`.
+    pub fn with_chain_id(mut self, chain_id: ChainId) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    /// Checks the custom outputs of the applied cells with `cell_types`. Without registered
+    /// validators, blocks with custom outputs are refused.
+    pub fn with_cell_types(mut self, cell_types: CellTypeRegistry) -> Self {
+        self.cell_types = cell_types;
+        self
+    }
+
+    /// Restores the state at `height` from its `live_cells` and `validators`, as exported in a
+    /// snapshot, instead of applying the blocks up to `height`.
+    ///
+    /// The total capacities are derived from the live outputs and the staking capacity of the
+    /// `validators`, which is reduced by their slashing penalties. The slashed validators are
+    /// derived from the live [slash outputs][CellType::Slash].
+    pub fn restore(
+        height: BlockHeight,
+        live_cells: HashMap<CellIds, Cell>,
+        validators: Vec<(Id, Capacity)>,
+    ) -> Result<State> {
+        let mut state = State::new();
+        state.height = height;
+        for (live_cell_ids, live_cell) in live_cells.iter() {
+            for (i, output) in live_cell.outputs().iter().enumerate() {
+                let cell_id = CellId::from_output(live_cell.hash(), i as u8, output.clone())?;
+                if !live_cell_ids.contains(&cell_id) {
+                    continue;
+                }
+                match output.cell_type {
+                    CellType::Stake => (),
+                    CellType::Slash => {
+                        let evidence: SlashEvidence = bincode::deserialize(&output.data)?;
+                        let _ = state.slashed.insert((evidence.validator()?, evidence.height));
+                    }
+                    CellType::Delegate => state.total_staking_capacity += output.capacity,
+                    _ => state.total_spending_capacity += output.capacity,
+                }
+            }
+        }
+        state.total_staking_capacity += validators.iter().map(|(_, c)| c).sum::<Capacity>();
+        state.validators = validators;
+        state.live_cells = live_cells;
+        Ok(state)
+    }
+
     /// Apply a new block to the state.
     ///
     /// _NOTE: at the moment, this function is used only when constructing a genesis block
@@ -58,6 +131,11 @@ impl State {
         }
         let ordered_cells = dg.topological_cells(block.cells.clone())?;
 
+        // The fees paid by the cells of the block, and the capacity claimed from them by its
+        // producer, see `coinbase::fee_cell`.
+        let mut fees = 0u64;
+        let mut claimed_fees = 0u64;
+
         // Try to apply the cells by order of dependence.
         for cell in ordered_cells.iter() {
             // Pull all the live cell outputs which are inputs to the cell.
@@ -65,6 +143,7 @@ impl State {
             let mut consumed_cell_ids = CellIds::empty();
             let mut consumed_cell_outputs = vec![];
             let mut consumed_capacity = 0u64;
+            let mut consumed_delegated_capacity = 0u64;
             // TODO figure out if we need this
             let _intersecting_cell_ids = CellIds::empty();
             for (live_cell_ids, live_cell) in state.live_cells.iter() {
@@ -85,6 +164,9 @@ impl State {
                             consumed_cell_ids.insert(cell_id.clone());
                             consumed_cell_outputs.push(live_cell_outputs[i].clone());
                             consumed_capacity += live_cell_outputs[i].capacity;
+                            if live_cell_outputs[i].cell_type == CellType::Delegate {
+                                consumed_delegated_capacity += live_cell_outputs[i].capacity;
+                            }
                         }
                     }
                 }
@@ -94,6 +176,15 @@ impl State {
                 // println!("inputs {:?}", input_cell_ids.clone());
                 return Err(Error::UndefinedCellIds);
             }
+            // Height-locked outputs can't be spent before their locktime.
+            let () = cell_operation::validate_locktimes(&consumed_cell_outputs, state.height)?;
+            // Cells spending outputs and slash cells have to be valid transactions, the coinbase
+            // cells are checked below.
+            if !cell.inputs().is_empty() || slash::slash_evidence(cell)?.is_some() {
+                let _fee =
+                    cell::validate(cell, state.chain_id, |input| state.resolve_input(input))?;
+            }
+            let () = state.cell_types.validate(cell)?;
 
             // Verify that the cell outputs transition correctly according to their constraints.
             let mut verified_outputs = vec![];
@@ -109,23 +200,40 @@ impl State {
                 }
             }
 
+            // Undelegated capacity can't be spent before the unbonding period.
+            if consumed_delegated_capacity > 0 {
+                for output in cell.outputs().iter() {
+                    match output.locktime {
+                        Some(locktime) if locktime >= block.height + UNBONDING_PERIOD => (),
+                        _ => return Err(Error::InvalidUndelegation),
+                    }
+                }
+            }
+
             // Remove consumed output cells from the live cell map.
-            state.remove_intersection(consumed_cell_ids)?;
+            state.live_cells = state.remove_intersection(consumed_cell_ids)?;
+            for input in cell.inputs().iter() {
+                let key = (input.output_index.cell_hash, input.output_index.index);
+                state.input_cache.invalidate(&key);
+            }
 
             // Apply the primitive cell types which change the `alpha` state.
             let mut coinbase_capacity = 0u64;
+            let mut fee_capacity = 0u64;
             let mut produced_staking_capacity = 0u64;
             let mut produced_capacity = 0u64;
+            let mut slash_outputs = 0usize;
             let cell_outputs = cell.outputs();
             for i in 0..cell_outputs.len() {
                 let cell_output = cell_outputs[i].clone();
-                // If the cell output is a coinbase at genesis then add the produced capacity.
+                // If the cell output is a coinbase at genesis then add the produced capacity,
+                // afterwards it can only collect the fees of the block.
                 if cell_output.cell_type == CellType::Coinbase {
-                    if state.height == 0 {
+                    if block.height == 0 {
                         // The coinbase generates capacity without consuming it.
                         coinbase_capacity += cell_output.capacity;
                     } else {
-                        return Err(Error::InvalidCoinbase);
+                        fee_capacity += cell_output.capacity;
                     }
                 } else if cell_output.cell_type == CellType::Stake {
                     // If the cell output is a `Stake` cell then add the validator to the list of
@@ -133,6 +241,12 @@ impl State {
                     let stake_state: StakeState = bincode::deserialize(&cell_output.data)?;
                     state.validators.push((stake_state.node_id, cell_output.capacity));
                     produced_staking_capacity += cell_output.capacity;
+                } else if cell_output.cell_type == CellType::Delegate {
+                    // Delegated capacity adds to the weight of a validator, see `committee`.
+                    produced_staking_capacity += cell_output.capacity;
+                } else if cell_output.cell_type == CellType::Slash {
+                    // The evidence is applied once the block is accepted, see `accept_block`.
+                    slash_outputs += 1;
                 } else {
                     // Otherwise treat it normally.
                     produced_capacity += cell_output.capacity;
@@ -145,33 +259,344 @@ impl State {
             if let Some(_) = state.live_cells.insert(produced_cell_ids, cell.clone()) {
                 return Err(Error::ExistingCellIds);
             }
+            state.cache_outputs(cell);
 
             // Subtract the consumed capacity and add the produced capacity.
             if consumed_capacity >= produced_capacity + produced_staking_capacity
                 && consumed_capacity > 0
                 && coinbase_capacity == 0
+                && fee_capacity == 0
             {
+                fees += consumed_capacity - produced_capacity - produced_staking_capacity;
                 // println!("consumed capacity = {:?}", consumed_capacity);
                 // println!("total_spending_capacity = {:?}", state.total_spending_capacity);
                 // println!("produced_capaciy = {:?}", produced_capacity);
                 // println!("produced_staking_capacity = {:?}", produced_staking_capacity);
-                state.total_spending_capacity -= consumed_capacity;
+                state.total_spending_capacity -= consumed_capacity - consumed_delegated_capacity;
+                state.total_staking_capacity -= consumed_delegated_capacity;
                 state.total_spending_capacity += produced_capacity;
                 state.total_staking_capacity += produced_staking_capacity;
-            } else if state.height == 0
+            } else if block.height == 0
                 && coinbase_capacity > 0
                 && produced_capacity == 0
                 && produced_staking_capacity == 0
             {
                 // println!("coinbase capacity = {:?}", coinbase_capacity);
                 state.total_spending_capacity += coinbase_capacity;
+            } else if block.height == 0
+                && consumed_capacity == 0
+                && coinbase_capacity == 0
+                && produced_capacity == 0
+                && produced_staking_capacity > 0
+            {
+                // Stakes of a genesis configuration are minted.
+                state.total_staking_capacity += produced_staking_capacity;
+            } else if fee_capacity > 0
+                && consumed_capacity == 0
+                && produced_capacity == 0
+                && produced_staking_capacity == 0
+            {
+                // The fees are checked once all the cells of the block are applied.
+                claimed_fees += fee_capacity;
+            } else if fee_capacity > 0 {
+                return Err(Error::InvalidCoinbase);
+            } else if slash_outputs > 0 && consumed_capacity == 0 {
+                // Slash cells don't carry capacity.
             } else {
                 return Err(Error::ExceedsCapacity);
             }
         }
+        if claimed_fees > fees {
+            return Err(Error::InvalidCoinbase);
+        }
+        state.total_spending_capacity += claimed_fees;
+        Ok(state)
+    }
+
+    /// Apply an accepted block to the state, slash the validators whose evidence it records and
+    /// reward its `producer`.
+    pub fn accept_block(&self, block: Block, producer: &Id, config: &ChainConfig) -> Result<State> {
+        let height = block.height;
+        let mut evidences = vec![];
+        for cell in block.cells.iter() {
+            if let Some(evidence) = slash::slash_evidence(cell)? {
+                evidences.push(evidence);
+            }
+        }
+        let mut state = self.apply(block)?;
+        state.height = height;
+        for evidence in evidences.iter() {
+            state = match state.slash(evidence, config) {
+                Ok(slashed) => slashed,
+                // Evidence against a validator without stake has no effect
+                Err(Error::InvalidSlashEvidence) => state,
+                Err(e) => return Err(e),
+            };
+        }
+        state.apply_reward(producer, height, config)
+    }
+
+    /// Mint the [block reward][ChainConfig::block_reward] of the block at `height` for the
+    /// owner of the stake of `producer`.
+    ///
+    /// Throws [Error::UnknownBlockProducer] if `producer` has no stake.
+    pub fn apply_reward(
+        &self,
+        producer: &Id,
+        height: BlockHeight,
+        config: &ChainConfig,
+    ) -> Result<State> {
+        let mut state = self.clone();
+        let owner = state.stake_owner(producer)?.ok_or(Error::UnknownBlockProducer)?;
+        let reward = coinbase::reward_cell(owner, height, config)?;
+        let reward_cell_ids = CellIds::from_outputs(reward.hash(), reward.outputs())?;
+        state.cache_outputs(&reward);
+        if state.live_cells.insert(reward_cell_ids, reward).is_some() {
+            return Err(Error::ExistingCellIds);
+        }
+        state.total_spending_capacity += config.block_reward;
         Ok(state)
     }
 
+    /// Slash the stake of a validator which proposed two different blocks at the same height
+    /// by the [slashing penalty][ChainConfig::slashing_penalty], reducing its weight in the
+    /// next committee. Evidence of an already slashed equivocation is ignored.
+    ///
+    /// The live stake outputs of the validator are replaced by outputs reduced by the penalty,
+    /// so that the slashed capacity can't be unstaked.
+    ///
+    /// Throws [Error::InvalidSlashEvidence] if the evidence is invalid or the validator has no
+    /// stake.
+    pub fn slash(&self, evidence: &SlashEvidence, config: &ChainConfig) -> Result<State> {
+        let validator = evidence.validator()?;
+        let mut state = self.clone();
+        if !state.slashed.insert((validator, evidence.height)) {
+            return Ok(state);
+        }
+        if !state.validators.iter().any(|(id, _)| *id == validator) {
+            return Err(Error::InvalidSlashEvidence);
+        }
+        for (_, capacity) in state.validators.iter_mut().filter(|(id, _)| *id == validator) {
+            let penalty = *capacity * config.slashing_penalty as u64 / 100;
+            *capacity -= penalty;
+            state.total_staking_capacity -= penalty;
+        }
+        let () = state.slash_stake_outputs(&validator, config)?;
+        Ok(state)
+    }
+
+    /// Replaces the live stake outputs of `node_id` with outputs reduced by the
+    /// [slashing penalty][ChainConfig::slashing_penalty]. The reduced outputs are locked until
+    /// the current height, which keeps them distinct from the stakes they replace.
+    fn slash_stake_outputs(&mut self, node_id: &Id, config: &ChainConfig) -> Result<()> {
+        let mut stakes = vec![];
+        for (live_cell_ids, live_cell) in self.live_cells.iter() {
+            for (i, output) in live_cell.outputs().iter().enumerate() {
+                if output.cell_type != CellType::Stake {
+                    continue;
+                }
+                let cell_id = CellId::from_output(live_cell.hash(), i as u8, output.clone())?;
+                if !live_cell_ids.contains(&cell_id) {
+                    continue;
+                }
+                let stake_state: StakeState = bincode::deserialize(&output.data)?;
+                if stake_state.node_id == *node_id {
+                    stakes.push((live_cell.hash(), i as u8, cell_id, output.clone()));
+                }
+            }
+        }
+        for (cell_hash, index, cell_id, output) in stakes {
+            let mut cell_ids = CellIds::empty();
+            let _ = cell_ids.insert(cell_id);
+            self.live_cells = self.remove_intersection(cell_ids)?;
+            self.input_cache.invalidate(&(cell_hash, index));
+
+            let penalty = output.capacity * config.slashing_penalty as u64 / 100;
+            if output.capacity == penalty {
+                continue;
+            }
+            let reduced =
+                Output { capacity: output.capacity - penalty, ..output }.with_locktime(self.height);
+            let cell = Cell::new(Inputs::new(vec![]), Outputs::new(vec![reduced]));
+            let reduced_cell_ids = CellIds::from_outputs(cell.hash(), cell.outputs())?;
+            self.cache_outputs(&cell);
+            if self.live_cells.insert(reduced_cell_ids, cell).is_some() {
+                return Err(Error::ExistingCellIds);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the weight of the validator `node_id` in the committee.
+    pub fn validator_weight(&self, node_id: &Id) -> Result<Weight> {
+        let capacity: Capacity =
+            self.committee()?.iter().filter(|(id, _)| id == node_id).map(|(_, c)| c).sum();
+        Ok(util::percent_of(capacity, self.total_staking_capacity))
+    }
+
+    /// Returns the validators with their total staking capacity: their own stake and the
+    /// capacity delegated to them by live [delegate outputs][CellType::Delegate].
+    pub fn committee(&self) -> Result<Vec<(Id, Capacity)>> {
+        let mut committee: Vec<(Id, Capacity)> = vec![];
+        for (id, capacity) in self.validators.iter() {
+            match committee.iter_mut().find(|(v, _)| v == id) {
+                Some((_, total)) => *total += capacity,
+                None => committee.push((*id, *capacity)),
+            }
+        }
+        for (live_cell_ids, live_cell) in self.live_cells.iter() {
+            for (i, output) in live_cell.outputs().iter().enumerate() {
+                if output.cell_type != CellType::Delegate {
+                    continue;
+                }
+                let cell_id = CellId::from_output(live_cell.hash(), i as u8, output.clone())?;
+                if !live_cell_ids.contains(&cell_id) {
+                    continue;
+                }
+                let delegate_state: DelegateState = bincode::deserialize(&output.data)?;
+                // Capacity delegated to nodes without stake has no weight
+                if let Some((_, total)) =
+                    committee.iter_mut().find(|(v, _)| *v == delegate_state.node_id)
+                {
+                    *total += output.capacity;
+                }
+            }
+        }
+        Ok(committee)
+    }
+
+    /// Returns the staking periods of the live [stake outputs][CellType::Stake], during which
+    /// they count towards the committee.
+    pub fn staking_periods(&self) -> Result<Vec<StakingPeriod>> {
+        let mut stakes = vec![];
+        for (live_cell_ids, live_cell) in self.live_cells.iter() {
+            for (i, output) in live_cell.outputs().iter().enumerate() {
+                if output.cell_type != CellType::Stake {
+                    continue;
+                }
+                let cell_id = CellId::from_output(live_cell.hash(), i as u8, output.clone())?;
+                if !live_cell_ids.contains(&cell_id) {
+                    continue;
+                }
+                let stake_state: StakeState = bincode::deserialize(&output.data)?;
+                stakes.push(StakingPeriod {
+                    id: stake_state.node_id,
+                    capacity: output.capacity,
+                    start_height: stake_state.start_height,
+                    end_height: stake_state.end_height,
+                });
+            }
+        }
+        // Every node sends the same periods, whatever the order of its live cells
+        stakes.sort();
+        Ok(stakes)
+    }
+
+    /// Returns the [owners of the stakes](State::stake_owner) of the validators.
+    pub fn stake_owners(&self) -> Result<HashMap<Id, PublicKeyHash>> {
+        let mut owners = HashMap::default();
+        for (id, _) in self.validators.iter() {
+            if let Some(owner) = self.stake_owner(id)? {
+                let _ = owners.insert(*id, owner);
+            }
+        }
+        Ok(owners)
+    }
+
+    /// Returns the owner of the live stake of the validator `node_id`, if any.
+    ///
+    /// If the validator has several stakes, the owner of the largest one is returned, and the
+    /// lowest owner among the largest ones, so that every node picks the same owner.
+    fn stake_owner(&self, node_id: &Id) -> Result<Option<PublicKeyHash>> {
+        let mut owner: Option<(Capacity, PublicKeyHash)> = None;
+        for (live_cell_ids, live_cell) in self.live_cells.iter() {
+            for (i, output) in live_cell.outputs().iter().enumerate() {
+                if output.cell_type != CellType::Stake {
+                    continue;
+                }
+                let cell_id = CellId::from_output(live_cell.hash(), i as u8, output.clone())?;
+                if !live_cell_ids.contains(&cell_id) {
+                    continue;
+                }
+                let stake_state: StakeState = bincode::deserialize(&output.data)?;
+                if stake_state.node_id != *node_id {
+                    continue;
+                }
+                owner = match owner {
+                    Some((capacity, lock))
+                        if capacity > output.capacity
+                            || (capacity == output.capacity && lock <= output.lock) =>
+                    {
+                        Some((capacity, lock))
+                    }
+                    _ => Some((output.capacity, output.lock)),
+                };
+            }
+        }
+        Ok(owner.map(|(_, lock)| lock))
+    }
+
+    /// Returns the capacity of the live outputs of `owner` which can be spent at the current
+    /// height, excluding stakes and delegations.
+    pub fn spendable_capacity(&self, owner: &PublicKeyHash) -> Result<Capacity> {
+        let mut capacity = 0;
+        for (live_cell_ids, live_cell) in self.live_cells.iter() {
+            for (i, output) in live_cell.outputs().iter().enumerate() {
+                let cell_id = CellId::from_output(live_cell.hash(), i as u8, output.clone())?;
+                if output.lock == *owner
+                    && output.cell_type != CellType::Stake
+                    && output.cell_type != CellType::Delegate
+                    && live_cell_ids.contains(&cell_id)
+                    && output.validate_locktime(self.height).is_ok()
+                {
+                    capacity += output.capacity;
+                }
+            }
+        }
+        Ok(capacity)
+    }
+
+    /// Returns `true` unless the output `index` of the cell `cell_hash` is live, that is it was
+    /// produced by an accepted cell and wasn't spent by an accepted cell since.
+    ///
+    /// Outputs unknown to the state are reported as spent too, as they can't be spent either.
+    pub fn is_spent(&self, cell_hash: &CellHash, index: u8) -> bool {
+        self.cached_live_output(cell_hash, index).is_none()
+    }
+
+    /// Returns the live [Output] spent by `input`, or `None` if it was spent or is unknown.
+    ///
+    /// It can be passed to [cell::validate][crate::cell::validate] to validate cells against
+    /// the accepted state.
+    pub fn resolve_input(&self, input: &Input) -> Option<Output> {
+        self.cached_live_output(&input.output_index.cell_hash, input.output_index.index)
+    }
+
+    /// Like [State::live_output], but served from the `input_cache` if possible.
+    fn cached_live_output(&self, cell_hash: &CellHash, index: u8) -> Option<Output> {
+        self.input_cache.get_or_resolve((*cell_hash, index), || self.live_output(cell_hash, index))
+    }
+
+    /// Caches the outputs of a newly live `cell`.
+    fn cache_outputs(&self, cell: &Cell) {
+        for (i, output) in cell.outputs().iter().enumerate() {
+            self.input_cache.insert((cell.hash(), i as u8), output.clone());
+        }
+    }
+
+    /// Returns the output `index` of the cell `cell_hash` if it is live.
+    fn live_output(&self, cell_hash: &CellHash, index: u8) -> Option<Output> {
+        for (live_cell_ids, live_cell) in self.live_cells.iter() {
+            if live_cell.hash() != *cell_hash {
+                continue;
+            }
+            let output = live_cell.outputs().get(index as usize)?.clone();
+            let cell_id = CellId::from_output(*cell_hash, index, output.clone()).ok()?;
+            return if live_cell_ids.contains(&cell_id) { Some(output) } else { None };
+        }
+        None
+    }
+
     /// Return a new map of cells from `self.live_cells` without cells from `cell_ids`
     ///
     /// ## Parameters
@@ -182,6 +607,10 @@ impl State {
             if cell_ids.intersects_with(live_cell_ids) {
                 let intersection = cell_ids.intersect(&live_cell_ids);
                 let new_cell_ids = live_cell_ids.left_difference(&intersection);
+                // Cells with all outputs spent are removed
+                if new_cell_ids.is_empty() {
+                    continue;
+                }
                 if let Some(_) = live_cells.insert(new_cell_ids.clone(), live_cell.clone()) {
                     return Err(Error::ExistingCellIds);
                 }
@@ -212,11 +641,21 @@ mod test {
 
     use crate::alpha::block;
     // use crate::alpha::coinbase::CoinbaseOperation;
-    // use crate::alpha::transfer::TransferOperation;
     use crate::alpha::initial_staker::InitialStaker;
-    use crate::cell::types::FEE;
+    use crate::alpha::slash::SignedBlock;
+    use crate::alpha::stake::{
+        delegate_output, stake_output, DelegateOperation, StakeOperation, UndelegateOperation,
+    };
+    use crate::alpha::transfer::{transfer_output, TransferOperation};
+    use crate::cell::inputs::{Input, Inputs};
+    use crate::cell::outputs::Outputs;
+    use crate::cell::types::{DEFAULT_CHAIN_ID, FEE};
     use crate::zfx_id::Id;
 
+    use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
+
+    use std::convert::TryInto;
     use std::str::FromStr;
 
     #[actix_rt::test]
@@ -228,25 +667,590 @@ mod test {
         assert_eq!(produced_state.total_staking_capacity, 6000);
     }
 
+    #[actix_rt::test]
+    async fn test_block_rewards() {
+        const N: u64 = 5;
+        let config = ChainConfig::default();
+        let staker = &crate::alpha::initial_staker::genesis_stakers()[0];
+        let owner = staker.public_key_hash().unwrap();
+
+        let genesis = block::build_genesis().unwrap();
+        let genesis_hash = genesis.hash().unwrap();
+        let mut state = State::new().apply(genesis).unwrap();
+        let initial_capacity = state.spendable_capacity(&owner).unwrap();
+        let initial_supply = state.total_spending_capacity;
+
+        for height in 1..=N {
+            let block = Block::new(genesis_hash, height, [0u8; 32], vec![]);
+            state = state.accept_block(block, &staker.node_id, &config).unwrap();
+        }
+        assert_eq!(
+            state.spendable_capacity(&owner).unwrap(),
+            initial_capacity + N * config.block_reward
+        );
+        assert_eq!(state.total_spending_capacity, initial_supply + N * config.block_reward);
+
+        // Validators without stake don't produce blocks
+        let block = Block::new(genesis_hash, N + 1, [0u8; 32], vec![]);
+        assert!(matches!(
+            state.accept_block(block, &Id::one(), &config),
+            Err(Error::UnknownBlockProducer)
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn test_immature_block_rewards() {
+        let config = ChainConfig { reward_maturity: 2, ..ChainConfig::default() };
+        let staker = &crate::alpha::initial_staker::genesis_stakers()[0];
+        let owner = staker.public_key_hash().unwrap();
+
+        let genesis = block::build_genesis().unwrap();
+        let genesis_hash = genesis.hash().unwrap();
+        let mut state = State::new().apply(genesis).unwrap();
+        let initial_capacity = state.spendable_capacity(&owner).unwrap();
+
+        let block = Block::new(genesis_hash, 1, [0u8; 32], vec![]);
+        state = state.accept_block(block, &staker.node_id, &config).unwrap();
+        assert_eq!(state.spendable_capacity(&owner).unwrap(), initial_capacity);
+        // The reward matures at height 3
+        state.height = 3;
+        assert_eq!(state.spendable_capacity(&owner).unwrap(), initial_capacity + 10);
+    }
+
+    #[actix_rt::test]
+    async fn test_slash_equivocation() {
+        let config = ChainConfig::default();
+        let keypair = Keypair::generate(&mut OsRng {});
+        let validator = Id::from_public_key(&keypair.public);
+
+        let genesis = block::build_genesis().unwrap();
+        let genesis_hash = genesis.hash().unwrap();
+        let mut state = State::new().apply(genesis).unwrap();
+        state.validators.push((validator, 1000));
+        state.total_staking_capacity += 1000;
+        let weight = state.validator_weight(&validator).unwrap();
+
+        let block_a = Block::new(genesis_hash, 1, [1u8; 32], vec![]);
+        let block_b = Block::new(genesis_hash, 1, [2u8; 32], vec![]);
+        let evidence = SlashEvidence {
+            height: 1,
+            block_a: SignedBlock::new(&keypair, block_a.clone()).unwrap(),
+            block_b: SignedBlock::new(&keypair, block_b).unwrap(),
+        };
+        let slashed = state.slash(&evidence, &config).unwrap();
+        assert!(slashed.validator_weight(&validator).unwrap() < weight);
+        assert!(slashed.validators.contains(&(validator, 500)));
+        assert_eq!(slashed.total_staking_capacity, state.total_staking_capacity - 500);
+
+        // The same evidence is only counted once
+        let slashed_again = slashed.slash(&evidence, &config).unwrap();
+        assert_eq!(slashed_again.validators, slashed.validators);
+        assert_eq!(slashed_again.total_staking_capacity, slashed.total_staking_capacity);
+
+        // The same block twice isn't evidence
+        let evidence = SlashEvidence {
+            height: 1,
+            block_a: SignedBlock::new(&keypair, block_a.clone()).unwrap(),
+            block_b: SignedBlock::new(&keypair, block_a).unwrap(),
+        };
+        assert!(matches!(state.slash(&evidence, &config), Err(Error::InvalidSlashEvidence)));
+    }
+
+    #[actix_rt::test]
+    async fn test_staking_periods() {
+        let config = ChainConfig::default();
+        let stakers = crate::alpha::initial_staker::genesis_stakers();
+        let owner = stakers[1].public_key_hash().unwrap();
+
+        let genesis = block::build_genesis().unwrap();
+        let genesis_hash = genesis.hash().unwrap();
+        let stake_tx = genesis.cells[1].clone();
+        let state = State::new().apply(genesis).unwrap();
+        // The genesis stakes count towards every committee
+        let periods = state.staking_periods().unwrap();
+        assert_eq!(periods.len(), stakers.len());
+        assert!(periods.iter().all(|p| p.start_height == 0 && p.end_height == BlockHeight::MAX));
+
+        let node_id = Id::two();
+        let stake = StakeOperation::new(stake_tx, node_id, owner, 100)
+            .with_period(5, 10)
+            .stake(&stakers[1].keypair)
+            .unwrap();
+        let block = Block::new(genesis_hash, 1, [0u8; 32], vec![stake]);
+        let state = state.accept_block(block, &stakers[0].node_id, &config).unwrap();
+        let periods = state.staking_periods().unwrap();
+        let period = StakingPeriod { id: node_id, capacity: 100, start_height: 5, end_height: 10 };
+        assert!(periods.contains(&period));
+    }
+
+    #[actix_rt::test]
+    async fn test_slash_cell() {
+        let config = ChainConfig::default();
+        let stakers = crate::alpha::initial_staker::genesis_stakers();
+        let keypair = Keypair::generate(&mut OsRng {});
+        let validator = Id::from_public_key(&keypair.public);
+        let owner = [7u8; 32];
+
+        let genesis = block::build_genesis().unwrap();
+        let genesis_hash = genesis.hash().unwrap();
+        let mut state = State::new().apply(genesis).unwrap();
+        let stake_cell = Cell::new(
+            Inputs::new(vec![]),
+            Outputs::new(vec![stake_output(validator, owner, 1000).unwrap()]),
+        );
+        let cell_ids = CellIds::from_outputs(stake_cell.hash(), stake_cell.outputs()).unwrap();
+        let _ = state.live_cells.insert(cell_ids, stake_cell);
+        state.validators.push((validator, 1000));
+        state.total_staking_capacity += 1000;
+        let weight = state.validator_weight(&validator).unwrap();
+
+        let evidence = SlashEvidence {
+            height: 1,
+            block_a: SignedBlock::new(&keypair, Block::new(genesis_hash, 1, [1u8; 32], vec![]))
+                .unwrap(),
+            block_b: SignedBlock::new(&keypair, Block::new(genesis_hash, 1, [2u8; 32], vec![]))
+                .unwrap(),
+        };
+        let slash_cell = slash::slash_cell(&evidence).unwrap();
+        assert_eq!(crate::cell::validate(&slash_cell, DEFAULT_CHAIN_ID, |_| None), Ok(0));
+
+        let block = Block::new(genesis_hash, 1, [0u8; 32], vec![slash_cell]);
+        let slashed = state.accept_block(block, &stakers[0].node_id, &config).unwrap();
+        assert!(slashed.validator_weight(&validator).unwrap() < weight);
+        assert!(slashed.validators.contains(&(validator, 500)));
+        assert_eq!(slashed.total_staking_capacity, state.total_staking_capacity - 500);
+        assert!(slashed.slashed.contains(&(validator, 1)));
+        // The stake output is replaced by a reduced one
+        assert_eq!(slashed.stake_owner(&validator).unwrap(), Some(owner));
+        let stakes: Vec<Capacity> = slashed
+            .live_cells
+            .values()
+            .flat_map(|c| c.outputs().iter().cloned().collect::<Vec<_>>())
+            .filter(|o| o.cell_type == CellType::Stake && o.lock == owner)
+            .map(|o| o.capacity)
+            .collect();
+        assert_eq!(stakes, vec![500]);
+
+        // The same evidence is only counted once
+        let evidence = SlashEvidence {
+            height: 1,
+            block_a: evidence.block_b.clone(),
+            block_b: evidence.block_a.clone(),
+        };
+        let slash_cell = slash::slash_cell(&evidence).unwrap();
+        let block = Block::new([0u8; 32], 2, [0u8; 32], vec![slash_cell.clone()]);
+        let slashed_again = slashed.accept_block(block, &stakers[0].node_id, &config).unwrap();
+        assert_eq!(slashed_again.validators, slashed.validators);
+        assert_eq!(slashed_again.total_staking_capacity, slashed.total_staking_capacity);
+
+        // Slash cells can't carry capacity
+        let mut outputs = slash_cell.outputs();
+        outputs.push(transfer_output(owner, 100).unwrap());
+        let cell = Cell::new(Inputs::new(vec![]), outputs);
+        let block = Block::new([0u8; 32], 2, [0u8; 32], vec![cell]);
+        assert_eq!(slashed.apply(block).err(), Some(Error::Cell(crate::cell::Error::InvalidSlash)));
+    }
+
+    #[actix_rt::test]
+    async fn test_delegation() {
+        let config = ChainConfig::default();
+        let stakers = crate::alpha::initial_staker::genesis_stakers();
+        let (validator, delegator) = (&stakers[0], &stakers[1]);
+        let owner = delegator.public_key_hash().unwrap();
+
+        let genesis = block::build_genesis().unwrap();
+        let genesis_hash = genesis.hash().unwrap();
+        let stake_tx = genesis.cells[1].clone();
+        let mut state = State::new().apply(genesis).unwrap();
+        let weight = state.validator_weight(&validator.node_id).unwrap();
+        let capacity = state.spendable_capacity(&owner).unwrap();
+
+        // Delegate part of the change of the delegator's stake to the validator
+        let delegate_tx = DelegateOperation::new(stake_tx, validator.node_id, owner, 500)
+            .delegate(&delegator.keypair)
+            .unwrap();
+        let block = Block::new(genesis_hash, 1, [0u8; 32], vec![delegate_tx.clone()]);
+        state = state.accept_block(block, &validator.node_id, &config).unwrap();
+        assert!(state.validator_weight(&validator.node_id).unwrap() > weight);
+        assert!(state.committee().unwrap().contains(&(validator.node_id, 1500)));
+        assert_eq!(state.spendable_capacity(&owner).unwrap(), capacity - 500 - FEE);
+
+        // Delegated outputs can't be delegated again
+        let inputs = UndelegateOperation::new(delegate_tx.clone(), owner)
+            .undelegate(&delegator.keypair, 2)
+            .unwrap()
+            .inputs();
+        let output = delegate_output(stakers[2].node_id, owner, 400).unwrap();
+        let redelegate_tx = Cell::new(inputs, Outputs::new(vec![output]));
+        let block = Block::new(genesis_hash, 2, [0u8; 32], vec![redelegate_tx]);
+        assert!(matches!(
+            state.accept_block(block, &validator.node_id, &config),
+            Err(Error::Cell(crate::cell::Error::InvalidDelegation))
+        ));
+
+        // Undelegated capacity is spendable after the unbonding period
+        let undelegate_tx =
+            UndelegateOperation::new(delegate_tx, owner).undelegate(&delegator.keypair, 2).unwrap();
+        let block = Block::new(genesis_hash, 2, [0u8; 32], vec![undelegate_tx]);
+        state = state.accept_block(block, &validator.node_id, &config).unwrap();
+        assert_eq!(state.validator_weight(&validator.node_id).unwrap(), weight);
+        assert_eq!(state.spendable_capacity(&owner).unwrap(), capacity - 500 - FEE);
+        state.height = 2 + UNBONDING_PERIOD;
+        assert_eq!(state.spendable_capacity(&owner).unwrap(), capacity - 2 * FEE);
+    }
+
+    #[actix_rt::test]
+    async fn test_is_spent() {
+        let config = ChainConfig::default();
+        let stakers = crate::alpha::initial_staker::genesis_stakers();
+        let owner = stakers[1].public_key_hash().unwrap();
+
+        let genesis = block::build_genesis().unwrap();
+        let genesis_hash = genesis.hash().unwrap();
+        // The stake of `stakers[1]`, with a change output
+        let stake_tx = genesis.cells[1].clone();
+        let mut state = State::new().apply(genesis).unwrap();
+
+        let transfer_tx = TransferOperation::new(stake_tx.clone(), [1u8; 32], owner, 100)
+            .transfer(&stakers[1].keypair)
+            .unwrap();
+        assert_eq!(transfer_tx.inputs().len(), 1);
+        let spent = transfer_tx.inputs().iter().next().unwrap().clone();
+        let index = spent.output_index.index;
+        assert!(!state.is_spent(&stake_tx.hash(), index));
+        assert_eq!(state.resolve_input(&spent), Some(stake_tx.outputs()[index as usize].clone()));
+        assert_eq!(
+            crate::cell::validate(&transfer_tx, DEFAULT_CHAIN_ID, |i| state.resolve_input(i)),
+            Ok(FEE)
+        );
+
+        let block = Block::new(genesis_hash, 1, [0u8; 32], vec![transfer_tx.clone()]);
+        state = state.accept_block(block, &stakers[0].node_id, &config).unwrap();
+        assert!(state.is_spent(&stake_tx.hash(), index));
+        assert_eq!(state.resolve_input(&spent), None);
+        assert!(!state.is_spent(&transfer_tx.hash(), 0));
+        // The spend can't be replayed
+        assert_eq!(
+            crate::cell::validate(&transfer_tx, DEFAULT_CHAIN_ID, |i| state.resolve_input(i)),
+            Err(crate::cell::Error::UndefinedInput)
+        );
+        // Unknown outputs can't be spent
+        assert!(state.is_spent(&[7u8; 32], 0));
+        assert!(state.is_spent(&transfer_tx.hash(), 100));
+    }
+
+    #[actix_rt::test]
+    async fn test_fee_collection() {
+        let config = ChainConfig::default();
+        let stakers = crate::alpha::initial_staker::genesis_stakers();
+        let owner = stakers[1].public_key_hash().unwrap();
+        let producer = stakers[0].public_key_hash().unwrap();
+
+        let genesis = block::build_genesis().unwrap();
+        let genesis_hash = genesis.hash().unwrap();
+        let stake_tx = genesis.cells[1].clone();
+        let mut state = State::new().apply(genesis).unwrap();
+
+        // The fees are capped from the first block after the genesis on
+        let transfer_tx = TransferOperation::new(stake_tx.clone(), [1u8; 32], owner, 100)
+            .transfer(&stakers[1].keypair)
+            .unwrap();
+        let greedy_cell = coinbase::fee_cell(producer, 1, FEE + 1).unwrap();
+        let block = Block::new(genesis_hash, 1, [0u8; 32], vec![transfer_tx, greedy_cell]);
+        assert!(matches!(state.apply(block), Err(Error::InvalidCoinbase)));
+
+        let block = Block::new(genesis_hash, 1, [0u8; 32], vec![]);
+        state = state.accept_block(block, &stakers[0].node_id, &config).unwrap();
+        let supply = state.total_spending_capacity;
+
+        let transfer_tx = TransferOperation::new(stake_tx, [1u8; 32], owner, 100)
+            .transfer(&stakers[1].keypair)
+            .unwrap();
+        let fee = transfer_tx.fee(|i| state.resolve_input(i)).unwrap();
+        assert_eq!(fee, FEE);
+
+        // Without a fee cell the fee is burned
+        let block = Block::new(genesis_hash, 2, [0u8; 32], vec![transfer_tx.clone()]);
+        assert_eq!(state.apply(block).unwrap().total_spending_capacity, supply - fee);
+
+        // The producer can't claim more than the fees of the block
+        let greedy_cell = coinbase::fee_cell(producer, 2, fee + 1).unwrap();
+        let block = Block::new(genesis_hash, 2, [0u8; 32], vec![transfer_tx.clone(), greedy_cell]);
+        assert!(matches!(state.apply(block), Err(Error::InvalidCoinbase)));
+
+        let fee_cell = coinbase::fee_cell(producer, 2, fee).unwrap();
+        let block = Block::new(genesis_hash, 2, [0u8; 32], vec![transfer_tx, fee_cell.clone()]);
+        let state = state.apply(block).unwrap();
+        assert_eq!(state.total_spending_capacity, supply);
+        assert!(!state.is_spent(&fee_cell.hash(), 0));
+    }
+
+    #[actix_rt::test]
+    async fn test_invalid_unlock() {
+        let stakers = crate::alpha::initial_staker::genesis_stakers();
+        let owner = stakers[1].public_key_hash().unwrap();
+
+        let genesis = block::build_genesis().unwrap();
+        let genesis_hash = genesis.hash().unwrap();
+        let stake_tx = genesis.cells[1].clone();
+        let state = State::new().apply(genesis).unwrap();
+
+        let transfer_tx = TransferOperation::new(stake_tx.clone(), [1u8; 32], owner, 100)
+            .transfer(&stakers[1].keypair)
+            .unwrap();
+        // The same input, signed by another staker than the owner of the spent output
+        let index = transfer_tx.inputs().iter().next().unwrap().output_index.index;
+        let input = Input::new(&stakers[0].keypair, stake_tx.hash(), index).unwrap();
+        let forged_tx = Cell::new(Inputs::new(vec![input]), transfer_tx.outputs());
+        let block = Block::new(genesis_hash, 1, [0u8; 32], vec![forged_tx]);
+        let invalid_unlock = || Error::Cell(crate::cell::Error::InvalidUnlock);
+        assert_eq!(state.apply(block).err(), Some(invalid_unlock()));
+
+        // The inputs are signed for another network than the state's
+        let transfer_tx = TransferOperation::new(stake_tx, [1u8; 32], owner, 100)
+            .with_chain_id(DEFAULT_CHAIN_ID + 1)
+            .transfer(&stakers[1].keypair)
+            .unwrap();
+        let block = Block::new(genesis_hash, 1, [0u8; 32], vec![transfer_tx]);
+        assert_eq!(state.apply(block.clone()).err(), Some(invalid_unlock()));
+        let state = state.with_chain_id(DEFAULT_CHAIN_ID + 1);
+        assert!(state.apply(block).is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn test_custom_outputs() {
+        let stakers = crate::alpha::initial_staker::genesis_stakers();
+        let owner = stakers[1].public_key_hash().unwrap();
+
+        let genesis = block::build_genesis().unwrap();
+        let genesis_hash = genesis.hash().unwrap();
+        let stake_tx = genesis.cells[1].clone();
+        let state = State::new().apply(genesis).unwrap();
+
+        let transfer_tx = TransferOperation::new(stake_tx, [1u8; 32], owner, 100)
+            .transfer(&stakers[1].keypair)
+            .unwrap();
+        let mut outputs = transfer_tx.outputs();
+        outputs[0].cell_type = CellType::Custom(7);
+        outputs[0].data = vec![1, 2, 3];
+        let custom_tx = Cell::new(transfer_tx.inputs(), outputs);
+        let block = Block::new(genesis_hash, 1, [0u8; 32], vec![custom_tx]);
+        // Custom outputs without a registered validator are refused
+        assert_eq!(
+            state.apply(block.clone()).err(),
+            Some(Error::Cell(crate::cell::Error::UnknownCellType(7)))
+        );
+
+        let mut cell_types = CellTypeRegistry::new();
+        cell_types.register(
+            7,
+            Box::new(|output: &Output| match output.data.is_empty() {
+                true => Err(crate::cell::Error::InvalidCellData(7)),
+                false => Ok(()),
+            }),
+        );
+        let state = state.with_cell_types(cell_types);
+        assert!(state.apply(block).is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn test_zero_transfer() {
+        let stakers = crate::alpha::initial_staker::genesis_stakers();
+        let owner = stakers[1].public_key_hash().unwrap();
+
+        let genesis = block::build_genesis().unwrap();
+        let genesis_hash = genesis.hash().unwrap();
+        let stake_tx = genesis.cells[1].clone();
+        let state = State::new().apply(genesis).unwrap();
+
+        let transfer_tx = TransferOperation::new(stake_tx, [1u8; 32], owner, 100)
+            .transfer(&stakers[1].keypair)
+            .unwrap();
+        let mut outputs = transfer_tx.outputs();
+        outputs.push(transfer_output([2u8; 32], 0).unwrap());
+        let cell = Cell::new(transfer_tx.inputs(), outputs);
+        let block = Block::new(genesis_hash, 1, [0u8; 32], vec![cell]);
+        assert_eq!(state.apply(block).err(), Some(Error::Cell(crate::cell::Error::ZeroTransfer)));
+    }
+
+    #[actix_rt::test]
+    async fn test_restore() {
+        let config = ChainConfig::default();
+        let stakers = crate::alpha::initial_staker::genesis_stakers();
+        let owner = stakers[1].public_key_hash().unwrap();
+
+        let genesis = block::build_genesis().unwrap();
+        let genesis_hash = genesis.hash().unwrap();
+        let stake_tx = genesis.cells[1].clone();
+        let mut state = State::new().apply(genesis).unwrap();
+        // Partially spends the outputs of `stake_tx`
+        let transfer_tx = TransferOperation::new(stake_tx.clone(), [1u8; 32], owner, 100)
+            .transfer(&stakers[1].keypair)
+            .unwrap();
+        let block = Block::new(genesis_hash, 1, [0u8; 32], vec![transfer_tx.clone()]);
+        state = state.accept_block(block, &stakers[0].node_id, &config).unwrap();
+
+        // The slashing penalty isn't reflected in the stake outputs
+        let keypair = Keypair::generate(&mut OsRng {});
+        state.validators.push((Id::from_public_key(&keypair.public), 1000));
+        state.total_staking_capacity += 1000;
+        let evidence = SlashEvidence {
+            height: 1,
+            block_a: SignedBlock::new(&keypair, Block::new(genesis_hash, 1, [1u8; 32], vec![]))
+                .unwrap(),
+            block_b: SignedBlock::new(&keypair, Block::new(genesis_hash, 1, [2u8; 32], vec![]))
+                .unwrap(),
+        };
+        let slash_cell = slash::slash_cell(&evidence).unwrap();
+        let block = Block::new(genesis_hash, 2, [0u8; 32], vec![slash_cell]);
+        state = state.accept_block(block, &stakers[0].node_id, &config).unwrap();
+        assert_eq!(state.slashed.len(), 1);
+
+        let restored =
+            State::restore(state.height, state.live_cells.clone(), state.validators.clone())
+                .unwrap();
+        assert_eq!(restored.height, 2);
+        assert_eq!(restored.slashed, state.slashed);
+        assert_eq!(restored.total_spending_capacity, state.total_spending_capacity);
+        assert_eq!(restored.total_staking_capacity, state.total_staking_capacity);
+        assert_eq!(restored.validators, state.validators);
+        assert_eq!(restored.committee().unwrap(), state.committee().unwrap());
+        let spent = transfer_tx.inputs().iter().next().unwrap().clone();
+        assert!(restored.is_spent(&stake_tx.hash(), spent.output_index.index));
+        assert!(!restored.is_spent(&transfer_tx.hash(), 0));
+
+        // The slash survives the restore, the same evidence is only counted once
+        let evidence = SlashEvidence {
+            height: 1,
+            block_a: evidence.block_b.clone(),
+            block_b: evidence.block_a.clone(),
+        };
+        let slash_cell = slash::slash_cell(&evidence).unwrap();
+        let block = Block::new(genesis_hash, 3, [0u8; 32], vec![slash_cell]);
+        let slashed_again = restored.accept_block(block, &stakers[0].node_id, &config).unwrap();
+        assert_eq!(slashed_again.slashed, state.slashed);
+        assert_eq!(slashed_again.validators, state.validators);
+        assert_eq!(slashed_again.total_staking_capacity, state.total_staking_capacity);
+    }
+
+    #[actix_rt::test]
+    async fn test_input_resolution_cache() {
+        let config = ChainConfig::default();
+        let stakers = crate::alpha::initial_staker::genesis_stakers();
+        let owner = stakers[1].public_key_hash().unwrap();
+
+        let genesis = block::build_genesis().unwrap();
+        let genesis_hash = genesis.hash().unwrap();
+        let stake_tx = genesis.cells[1].clone();
+        let mut state = State::new().apply(genesis).unwrap();
+
+        let transfer_tx = TransferOperation::new(stake_tx.clone(), [1u8; 32], owner, 100)
+            .transfer(&stakers[1].keypair)
+            .unwrap();
+        let spent = transfer_tx.inputs().iter().next().unwrap().clone();
+        let output = stake_tx.outputs()[spent.output_index.index as usize].clone();
+
+        // A cold lookup is resolved from the live cells, a warm one is served from the cache
+        state.input_cache.clear();
+        assert_eq!(state.resolve_input(&spent), Some(output.clone()));
+        assert_eq!(state.input_cache.stats(), (0, 1));
+        assert_eq!(state.resolve_input(&spent), Some(output));
+        assert_eq!(state.input_cache.stats(), (1, 1));
+
+        // Validating the cell resolves its input from the cache, for the unlock and the fee.
+        // Spending the output invalidates it, the outputs of the accepted cell are cached
+        let block = Block::new(genesis_hash, 1, [0u8; 32], vec![transfer_tx.clone()]);
+        state = state.accept_block(block, &stakers[0].node_id, &config).unwrap();
+        assert_eq!(state.input_cache.stats(), (3, 1));
+        assert_eq!(state.resolve_input(&spent), None);
+        assert_eq!(state.input_cache.stats(), (3, 2));
+        assert!(!state.is_spent(&transfer_tx.hash(), 0));
+        assert_eq!(state.input_cache.stats(), (4, 2));
+    }
+
+    #[actix_rt::test]
+    async fn test_early_undelegation() {
+        let config = ChainConfig::default();
+        let stakers = crate::alpha::initial_staker::genesis_stakers();
+        let (validator, delegator) = (&stakers[0], &stakers[1]);
+        let owner = delegator.public_key_hash().unwrap();
+
+        let genesis = block::build_genesis().unwrap();
+        let genesis_hash = genesis.hash().unwrap();
+        let stake_tx = genesis.cells[1].clone();
+        let mut state = State::new().apply(genesis).unwrap();
+        let delegate_tx = DelegateOperation::new(stake_tx, validator.node_id, owner, 500)
+            .delegate(&delegator.keypair)
+            .unwrap();
+        let block = Block::new(genesis_hash, 1, [0u8; 32], vec![delegate_tx.clone()]);
+        state = state.accept_block(block, &validator.node_id, &config).unwrap();
+
+        // The unbonding period starts at the height of the block containing the cell
+        let undelegate_tx =
+            UndelegateOperation::new(delegate_tx, owner).undelegate(&delegator.keypair, 1).unwrap();
+        let block = Block::new(genesis_hash, 2, [0u8; 32], vec![undelegate_tx]);
+        assert!(matches!(
+            state.accept_block(block, &validator.node_id, &config),
+            Err(Error::InvalidUndelegation)
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn test_coinbase_after_genesis() {
+        let config = ChainConfig::default();
+        let stakers = crate::alpha::initial_staker::genesis_stakers();
+        let owner = stakers[0].public_key_hash().unwrap();
+
+        let genesis = block::build_genesis().unwrap();
+        let genesis_hash = genesis.hash().unwrap();
+        let state = State::new().apply(genesis).unwrap();
+        let supply = state.total_spending_capacity;
+
+        // The state is still at height 0 while block 1 is applied, a coinbase can't mint there
+        let coinbase_tx: Cell =
+            coinbase::CoinbaseOperation::new(vec![(owner, 1000000000)]).try_into().unwrap();
+        let block = Block::new(genesis_hash, 1, [0u8; 32], vec![coinbase_tx]);
+        assert!(matches!(
+            state.accept_block(block, &stakers[0].node_id, &config),
+            Err(Error::InvalidCoinbase)
+        ));
+        assert_eq!(state.total_spending_capacity, supply);
+    }
+
+    #[actix_rt::test]
+    async fn test_stake_owner_of_several_stakes() {
+        let node_id = Id::one();
+        let mut state = State::new();
+        for (owner, capacity) in [([2u8; 32], 1000), ([0u8; 32], 500), ([1u8; 32], 1000)] {
+            let output = stake_output(node_id, owner, capacity).unwrap();
+            let cell = Cell::new(Inputs::new(vec![]), Outputs::new(vec![output]));
+            let cell_ids = CellIds::from_outputs(cell.hash(), cell.outputs()).unwrap();
+            let _ = state.live_cells.insert(cell_ids, cell);
+        }
+        // The lowest owner of the largest stakes
+        assert_eq!(state.stake_owner(&node_id).unwrap(), Some([1u8; 32]));
+        assert_eq!(state.stake_owner(&Id::two()).unwrap(), None);
+    }
+
     // Not sure if we'll need this
     #[allow(dead_code)]
     fn initial_stakers() -> Vec<InitialStaker> {
         vec![
 	    InitialStaker::from_hex(
 		"ad7f2ee3958a7f3fa2c84931770f5773ef7694fdd0bb217d90f29a94199c9d7307ca3851515c89344639fe6a4077923068d1d7fc6106701213c61d34ef8e9416".to_owned(),
-		Id::from_str("12My22AzQQosboCy6TCDFkTQwHTSuHhFN1VDcdDRPUe3H8j3DvY").unwrap(),
+		Id::from_str("19oHWYAEdSALT6pFZaxzNPj9bEfJFrJfxrU9pZp622FCV35tzy").unwrap(),
 		2000, // 2000 allocated
 		1000, // half of it staked so that we can transfer funds later
 	    ).unwrap(),
 	    InitialStaker::from_hex(
 		"5a353c630d3faf8e2d333a0983c1c71d5e9b6aed8f4959578fbeb3d3f3172886393b576de0ac1fe86a4dd416cf032543ac1bd066eb82585f779f6ce21237c0cd".to_owned(),
-		Id::from_str("19Y53ymnBw4LWUpiAMUzPYmYqZmukRhNHm3VyAhzMqckRcuvkf").unwrap(),
+		Id::from_str("1tBz2oa6DCjDbVpC8KJijh7zw8gSvRLAiaUmXktmFAFS5fzXuU").unwrap(),
 		2000,
 		1000,
 	    ).unwrap(),
 	    InitialStaker::from_hex(
 		"6f4b736b9a6894858a81696d9c96cbdacf3d49099d212213f5abce33da18716f067f8a2b9aeb602cd4163291ebbf39e0e024634f3be19bde4c490465d9095a6b".to_owned(),
-		Id::from_str("1A2iUK1VQWMfvtmrBpXXkVJjM5eMWmTfMEcBx4TatSJeuoSH7n").unwrap(),
+		Id::from_str("12HkbZqmLUnSQTmwa9EyjCnj9cLdgSMDkYczhD9WbV7pVybzb83").unwrap(),
 		2000,
 		1000,
 	    ).unwrap(),
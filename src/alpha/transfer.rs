@@ -1,12 +1,15 @@
-use super::Result;
-use crate::cell::inputs::Inputs;
+use super::{Error, Result};
+use crate::cell::inputs::{Input, Inputs};
 use crate::cell::outputs::{Output, Outputs};
 use crate::cell::types::*;
-use crate::cell::{Cell, CellType};
+use crate::cell::{Cell, CellType, SignatureScheme};
+use crate::zfx_id::Id;
 
 use crate::cell::cell_operation::{consume_from_cell, ConsumeResult};
 use ed25519_dalek::Keypair;
 
+use std::collections::HashSet;
+
 /// Empty transfer state - capacity transfers do not need to store extra state.
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct TransferState;
@@ -20,19 +23,26 @@ pub struct TransferState;
 /// * `capacity`- amount to transfer to the account and assign it to [Output]
 pub fn transfer_output(pkh: PublicKeyHash, capacity: Capacity) -> Result<Output> {
     let data = bincode::serialize(&TransferState {})?;
-    Ok(Output { capacity, cell_type: CellType::Transfer, data, lock: pkh })
+    Ok(Output {
+        capacity,
+        cell_type: CellType::Transfer,
+        data,
+        lock: pkh,
+        locktime: None,
+        scheme: SignatureScheme::Ed25519,
+    })
 }
 
 /// Transfers capacity from one account to another.
 pub struct TransferOperation {
     /// The cell being spent in this transfer operation.
     cell: Cell,
-    /// The recipient of the transferred capacity.
-    recipient_address: PublicKeyHash,
+    /// The recipients of the transferred capacity, with the amount each of them receives.
+    recipients: Vec<(PublicKeyHash, Capacity)>,
     /// The recipient of the change capacity.
     change_address: PublicKeyHash,
-    /// The amount of capacity to transfer.
-    capacity: Capacity,
+    /// The network the transfer is signed for.
+    chain_id: ChainId,
 }
 
 impl TransferOperation {
@@ -53,7 +63,29 @@ impl TransferOperation {
         change_address: PublicKeyHash,
         capacity: Capacity,
     ) -> Self {
-        TransferOperation { cell, recipient_address, change_address, capacity }
+        TransferOperation::new_multi(cell, vec![(recipient_address, capacity)], change_address)
+    }
+
+    /// Create a transfer operation from the provided [Cell] paying several recipients at once,
+    /// each of them receiving its own [Output].
+    ///
+    /// ## Parameters
+    /// * `cell` - the total capacity of `recipients` will be taken out from this cell,
+    /// if it has outputs with enough balance for the owner with `change_address`.
+    /// * `recipients` - account's public keys with the balance to transfer to each of them.
+    /// * `change_address` - account's public key where to transfer the balance from.
+    pub fn new_multi(
+        cell: Cell,
+        recipients: Vec<(PublicKeyHash, Capacity)>,
+        change_address: PublicKeyHash,
+    ) -> Self {
+        TransferOperation { cell, recipients, change_address, chain_id: DEFAULT_CHAIN_ID }
+    }
+
+    /// Signs the transfer for the network `chain_id` instead of the default one.
+    pub fn with_chain_id(mut self, chain_id: ChainId) -> Self {
+        self.chain_id = chain_id;
+        self
     }
 
     /// Transfer balance and create a new [Cell] with list of outputs
@@ -66,28 +98,94 @@ impl TransferOperation {
     ///
     /// If the remaining balance has more capacity than [FEE], then
     /// the new cell will have:
-    /// * 1 [Output] with the transferred balance for the new owner (`recipient_address`).
+    /// * 1 [Output] with the transferred balance for each recipient.
     /// * 1 [Output] with the remaining balance minus [FEE] for the owner (`change_address`).
     ///
     /// If the remaining balance has less capacity than [FEE], then
-    /// only 1 [Output] with the transferred balance is returned
-    /// for the new owner (`recipient_address`).
+    /// only the [outputs][Output] with the transferred balance are returned
+    /// for the recipients.
+    ///
+    /// Throws [Error::ZeroTransfer] if a recipient receives no capacity, or
+    /// [Error::ExceedsAvailableFunds] if the total transferred balance plus [FEE] exceeds the
+    /// balance of the owner in the cell.
     ///
     /// ## Parameters
     /// * `keypair` - the account's keypair for identifying outputs for transfer.
     pub fn transfer(&self, keypair: &Keypair) -> Result<Cell> {
-        let ConsumeResult { consumed, residue, inputs } =
-            consume_from_cell(&self.cell, self.capacity, keypair)?;
-
-        let main_output = transfer_output(self.recipient_address, consumed)?;
-        let outputs = if residue > FEE && residue - FEE > 0 {
-            vec![main_output, transfer_output(self.change_address, residue - FEE)?]
-        } else {
-            vec![main_output]
-        };
+        let mut total: Capacity = 0;
+        for (_, capacity) in self.recipients.iter() {
+            if *capacity == 0 {
+                return Err(Error::ZeroTransfer);
+            }
+            total = total.checked_add(*capacity).ok_or(Error::ExceedsAvailableFunds)?;
+        }
+        let ConsumeResult { consumed: _, residue, inputs } =
+            consume_from_cell(&self.cell, total, keypair, self.chain_id)?;
+
+        let mut outputs = vec![];
+        for (recipient_address, capacity) in self.recipients.iter() {
+            outputs.push(transfer_output(*recipient_address, *capacity)?);
+        }
+        if residue > FEE && residue - FEE > 0 {
+            outputs.push(transfer_output(self.change_address, residue - FEE)?);
+        }
 
         Ok(Cell::new(Inputs::new(inputs), Outputs::new(outputs)))
     }
+
+    /// Sweeps all outputs of `cells` spendable with `keypair` into a single [Output] for `to`,
+    /// holding their total capacity minus [FEE]. The inputs are signed for the default chain.
+    ///
+    /// Throws the following errors:
+    /// * [Error::UnspendableCell] - if one of the cells has no outputs spendable with `keypair`
+    /// * [Error::ExceedsAvailableFunds] - if the total capacity doesn't exceed [FEE]
+    pub fn consolidate(cells: Vec<Cell>, to: PublicKeyHash, keypair: &Keypair) -> Result<Cell> {
+        TransferOperation::consolidate_on_chain(DEFAULT_CHAIN_ID, cells, to, keypair)
+    }
+
+    /// Like [consolidate][TransferOperation::consolidate], signing the inputs for the network
+    /// `chain_id`.
+    pub fn consolidate_on_chain(
+        chain_id: ChainId,
+        cells: Vec<Cell>,
+        to: PublicKeyHash,
+        keypair: &Keypair,
+    ) -> Result<Cell> {
+        let pkh = Id::from_public_key(&keypair.public).bytes();
+        let mut seen = HashSet::new();
+        let mut inputs = vec![];
+        let mut total: Capacity = 0;
+        for cell in cells.iter() {
+            let cell_hash = cell.hash();
+            // A cell passed twice is only spent once
+            if !seen.insert(cell_hash) {
+                continue;
+            }
+            let mut spendable = false;
+            for (index, output) in cell.outputs().iter().enumerate() {
+                // Like transfers, stakes and delegations are not swept
+                if output.lock != pkh
+                    || !(output.cell_type == CellType::Transfer
+                        || output.cell_type == CellType::Coinbase)
+                {
+                    continue;
+                }
+                let () = output.validate_data()?;
+                inputs.push(Input::new_on_chain(chain_id, keypair, cell_hash, index as u8)?);
+                total = total.checked_add(output.capacity).ok_or(Error::ExceedsCapacity)?;
+                spendable = true;
+            }
+            if !spendable {
+                return Err(Error::UnspendableCell);
+            }
+        }
+        if total <= FEE {
+            return Err(Error::ExceedsAvailableFunds);
+        }
+
+        let output = transfer_output(to, total - FEE)?;
+        Ok(Cell::new(Inputs::new(inputs), Outputs::new(vec![output])))
+    }
 }
 
 #[cfg(test)]
@@ -98,6 +196,7 @@ mod test {
     use crate::alpha::coinbase::CoinbaseOperation;
 
     use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
 
     use std::convert::TryInto;
 
@@ -204,6 +303,115 @@ mod test {
         assert_eq!(tx4.outputs().len(), 1);
     }
 
+    #[actix_rt::test]
+    async fn test_split_transfer() {
+        let (kp1, kp2, pkh1, pkh2) = generate_keys();
+        let pkh3 = hash_public(&Keypair::generate(&mut OsRng {}));
+
+        let coinbase_tx = generate_coinbase(&kp1, 1000);
+        let recipients = vec![(pkh2.clone(), 100), (pkh3.clone(), 200), (pkh2.clone(), 300)];
+        let transfer_op = TransferOperation::new_multi(coinbase_tx, recipients, pkh1.clone());
+        let tx = transfer_op.transfer(&kp1).unwrap();
+        assert_eq!(tx.inputs().len(), 1);
+        assert_eq!(tx.outputs().len(), 4);
+        assert_eq!(tx.sum(), 1000 - FEE);
+        let paid = |pkh: [u8; 32]| -> Capacity {
+            tx.outputs().iter().filter(|o| o.lock == pkh).map(|o| o.capacity).sum()
+        };
+        assert_eq!(paid(pkh2), 400);
+        assert_eq!(paid(pkh3), 200);
+        assert_eq!(paid(pkh1), 1000 - 600 - FEE);
+
+        // Each recipient can spend its output
+        let transfer_op = TransferOperation::new(tx.clone(), pkh1.clone(), pkh2.clone(), 350);
+        assert_eq!(transfer_op.transfer(&kp2).unwrap().sum(), 400 - FEE);
+    }
+
+    #[actix_rt::test]
+    async fn test_split_transfer_over_budget() {
+        let (kp1, _kp2, pkh1, pkh2) = generate_keys();
+
+        let coinbase_tx = generate_coinbase(&kp1, 1000);
+        // The recipients receive all of the balance, leaving nothing for the fee
+        let recipients = vec![(pkh2.clone(), 500), (pkh2.clone(), 300), (pkh1.clone(), 200)];
+        let transfer_op = TransferOperation::new_multi(coinbase_tx, recipients, pkh1.clone());
+        assert_eq!(transfer_op.transfer(&kp1), Err(Error::ExceedsAvailableFunds));
+    }
+
+    #[actix_rt::test]
+    async fn test_split_transfer_zero_recipient() {
+        let (kp1, _kp2, pkh1, pkh2) = generate_keys();
+
+        let coinbase_tx = generate_coinbase(&kp1, 1000);
+        let recipients = vec![(pkh2.clone(), 500), (pkh2.clone(), 0)];
+        let transfer_op = TransferOperation::new_multi(coinbase_tx, recipients, pkh1.clone());
+        assert_eq!(transfer_op.transfer(&kp1), Err(Error::ZeroTransfer));
+    }
+
+    #[actix_rt::test]
+    async fn test_consolidate() {
+        let (kp1, _kp2, pkh1, pkh2) = generate_keys();
+
+        // Five small outputs of `pkh1` across two cells, and one output of `pkh2`
+        let coinbase_op1 = CoinbaseOperation::new(vec![
+            (pkh1.clone(), 10),
+            (pkh1.clone(), 20),
+            (pkh1.clone(), 30),
+        ]);
+        let coinbase_tx1: Cell = coinbase_op1.try_into().unwrap();
+        let coinbase_op2 = CoinbaseOperation::new(vec![
+            (pkh1.clone(), 40),
+            (pkh2.clone(), 1000),
+            (pkh1.clone(), 50),
+        ]);
+        let coinbase_tx2: Cell = coinbase_op2.try_into().unwrap();
+
+        let cells = vec![coinbase_tx1.clone(), coinbase_tx2.clone()];
+        let tx = TransferOperation::consolidate(cells.clone(), pkh2.clone(), &kp1).unwrap();
+        assert_eq!(tx.inputs().len(), 5);
+        assert_eq!(tx.outputs().len(), 1);
+        assert_eq!(tx.outputs()[0].lock, pkh2);
+        assert_eq!(tx.sum(), 10 + 20 + 30 + 40 + 50 - FEE);
+
+        // The consolidated cell is a valid transaction
+        let resolve = |input: &Input| {
+            let index = input.output_index.index as usize;
+            cells
+                .iter()
+                .find(|cell| cell.hash() == input.output_index.cell_hash)
+                .map(|cell| cell.outputs()[index].clone())
+        };
+        assert_eq!(crate::cell::validate(&tx, DEFAULT_CHAIN_ID, resolve), Ok(FEE));
+        assert_eq!(tx.fee(resolve), Ok(FEE));
+
+        // Cells passed twice are spent once
+        let cells = vec![coinbase_tx1.clone(), coinbase_tx1.clone()];
+        let tx = TransferOperation::consolidate(cells, pkh1.clone(), &kp1).unwrap();
+        assert_eq!(tx.inputs().len(), 3);
+        assert_eq!(tx.sum(), 10 + 20 + 30 - FEE);
+    }
+
+    #[actix_rt::test]
+    async fn test_consolidate_unspendable() {
+        let (kp1, _kp2, pkh1, pkh2) = generate_keys();
+
+        let coinbase_tx1 = generate_coinbase(&kp1, 100);
+        // `pkh1` owns no output in this cell
+        let coinbase_op2 = CoinbaseOperation::new(vec![(pkh2.clone(), 100)]);
+        let coinbase_tx2: Cell = coinbase_op2.try_into().unwrap();
+        assert_eq!(
+            TransferOperation::consolidate(vec![coinbase_tx1, coinbase_tx2], pkh1.clone(), &kp1),
+            Err(Error::UnspendableCell)
+        );
+
+        // Nothing is left after paying the fee
+        let dust = generate_coinbase(&kp1, FEE);
+        assert_eq!(
+            TransferOperation::consolidate(vec![dust], pkh1.clone(), &kp1),
+            Err(Error::ExceedsAvailableFunds)
+        );
+    }
+
     fn generate_coinbase(keypair: &Keypair, amount: u64) -> Cell {
         let pkh = hash_public(keypair);
         let coinbase_op = CoinbaseOperation::new(vec![(pkh, amount)]);
@@ -1,6 +1,8 @@
-use super::inputs::Inputs;
+use super::inputs::{Input, Inputs};
 use super::outputs::{Output, Outputs};
 use super::types::*;
+use super::{Error, Result};
+use crate::json;
 
 /// Cell is an extension to the UTXO model used by [sleet][crate::sleet] and [hail][crate::hail] components
 /// when they interact with transactions by wrapping it inside [transactions](crate::sleet::tx::Tx).
@@ -47,10 +49,20 @@ impl Cell {
             .collect::<Vec<&Output>>()
     }
 
-    /// Serialize the cell and return its hash as a byte array.
+    /// Returns the canonical encoding of the cell, with its inputs sorted so that the same cell
+    /// always encodes to the same bytes, regardless of the order its inputs were inserted in.
+    ///
+    /// The outputs keep their order, as they are referred to by their index.
+    pub fn canonical_encoding(&self) -> Vec<u8> {
+        let mut inputs: Vec<&Input> = self.inputs.iter().collect();
+        inputs.sort();
+        bincode::serialize(&(inputs, &self.outputs.outputs)).unwrap()
+    }
+
+    /// Hash the [canonical encoding](Cell::canonical_encoding) of the cell and return it as a
+    /// byte array.
     pub fn hash(&self) -> CellHash {
-        let encoded = bincode::serialize(self).unwrap();
-        blake3::hash(&encoded).as_bytes().clone()
+        blake3::hash(&self.canonical_encoding()).as_bytes().clone()
     }
 
     /// Sums the output capacities.
@@ -58,8 +70,153 @@ impl Cell {
         self.outputs().sum()
     }
 
+    /// Returns the fee paid by the cell, which is the capacity of the inputs not spent by
+    /// the outputs.
+    ///
+    /// `resolve_input` returns the [Output] spent by an input, or `None` if it is unknown.
+    ///
+    /// Throws [Error::UndefinedInput] if an input can't be resolved, or
+    /// [Error::ExceedsAvailableFunds] if the outputs exceed the capacity of the inputs.
+    pub fn fee(&self, resolve_input: impl Fn(&Input) -> Option<Output>) -> Result<Capacity> {
+        let mut consumed: Capacity = 0;
+        for input in self.inputs.iter() {
+            let output = resolve_input(input).ok_or(Error::UndefinedInput)?;
+            consumed = consumed.checked_add(output.capacity).ok_or(Error::ExceedsAvailableFunds)?;
+        }
+        let produced =
+            self.outputs.iter().try_fold(0 as Capacity, |sum, o| sum.checked_add(o.capacity));
+        match produced {
+            Some(produced) if produced <= consumed => Ok(consumed - produced),
+            _ => Err(Error::ExceedsAvailableFunds),
+        }
+    }
+
+    /// Serializes the cell to JSON, with hex-encoded hashes and output data.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(json::to_json(self)?)
+    }
+
+    /// Deserializes a cell from its JSON representation, see [Cell::to_json].
+    pub fn from_json(s: &str) -> Result<Cell> {
+        Ok(json::from_json(s)?)
+    }
+
     // pub fn semantic_verify(&self, cells: &HashMap<CellIds, Cell>) -> Result<()> {
     // 	let cell_ids = CellIds::from_inputs(&self.inputs);
     // 	Ok(())
     // }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::alpha::coinbase::CoinbaseOperation;
+    use crate::alpha::stake::StakeOperation;
+    use crate::alpha::transfer::{transfer_output, TransferOperation};
+    use crate::cell::types::FEE;
+    use crate::zfx_id::Id;
+
+    use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
+
+    use std::convert::TryInto;
+
+    /// Returns a keypair and a coinbase cell allocating 1000 to it
+    fn coinbase() -> (Keypair, Cell) {
+        let mut csprng = OsRng {};
+        let keypair = Keypair::generate(&mut csprng);
+        let pkh = Id::from_public_key(&keypair.public).bytes();
+        let cell = CoinbaseOperation::new(vec![(pkh, 1000)]).try_into().unwrap();
+        (keypair, cell)
+    }
+
+    fn spending(keypair: &Keypair, spent: &Cell, capacity: Capacity) -> Cell {
+        let pkh = Id::from_public_key(&keypair.public).bytes();
+        let input = Input::new(keypair, spent.hash(), 0).unwrap();
+        let outputs = vec![transfer_output(pkh, capacity).unwrap()];
+        Cell::new(Inputs::new(vec![input]), Outputs::new(outputs))
+    }
+
+    #[actix_rt::test]
+    async fn test_json_round_trip() {
+        let (keypair, genesis) = coinbase();
+        let pkh = Id::from_public_key(&keypair.public).bytes();
+        let transfer = TransferOperation::new(genesis.clone(), [1u8; 32], pkh, 100)
+            .transfer(&keypair)
+            .unwrap();
+        let stake =
+            StakeOperation::new(genesis.clone(), Id::one(), pkh, 500).stake(&keypair).unwrap();
+        for cell in [genesis, transfer, stake].iter() {
+            let json = cell.to_json().unwrap();
+            assert!(json.contains(&hex::encode(cell.outputs()[0].lock)));
+            assert_eq!(&Cell::from_json(&json).unwrap(), cell);
+        }
+        assert!(Cell::from_json("{}").is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_canonical_hash() {
+        let (keypair, genesis) = coinbase();
+        let (keypair2, genesis2) = coinbase();
+        let pkh = Id::from_public_key(&keypair.public).bytes();
+        let input1 = Input::new(&keypair, genesis.hash(), 0).unwrap();
+        let input2 = Input::new(&keypair2, genesis2.hash(), 0).unwrap();
+        let output1 = transfer_output(pkh, 100).unwrap();
+        let output2 = transfer_output(pkh, 200).unwrap();
+
+        let mut inputs = Inputs::new(vec![]);
+        inputs.insert(input1.clone());
+        inputs.insert(input2.clone());
+        let mut outputs = Outputs::new(vec![]);
+        outputs.push(output1.clone());
+        outputs.push(output2.clone());
+        let cell = Cell::new(inputs, outputs);
+
+        let mut inputs = Inputs::new(vec![]);
+        inputs.insert(input2);
+        inputs.insert(input1);
+        let mut outputs = Outputs::new(vec![]);
+        outputs.push(output1.clone());
+        outputs.push(output2.clone());
+        let reordered = Cell::new(inputs.clone(), outputs);
+
+        assert_eq!(cell.canonical_encoding(), reordered.canonical_encoding());
+        assert_eq!(cell.hash(), reordered.hash());
+
+        // Outputs are positional, so swapping them yields another cell
+        let mut outputs = Outputs::new(vec![]);
+        outputs.push(output2);
+        outputs.push(output1);
+        let swapped = Cell::new(inputs, outputs);
+        assert_ne!(cell.hash(), swapped.hash());
+    }
+
+    #[actix_rt::test]
+    async fn test_fee_of_transfer() {
+        let (keypair, genesis) = coinbase();
+        let pkh = Id::from_public_key(&keypair.public).bytes();
+        let cell = TransferOperation::new(genesis.clone(), [1u8; 32], pkh, 100)
+            .transfer(&keypair)
+            .unwrap();
+        let output = genesis.outputs()[0].clone();
+        assert_eq!(cell.fee(|_| Some(output.clone())), Ok(FEE));
+        assert_eq!(cell.fee(|_| None), Err(Error::UndefinedInput));
+    }
+
+    #[actix_rt::test]
+    async fn test_fee_of_balanced_cell() {
+        let (keypair, genesis) = coinbase();
+        let cell = spending(&keypair, &genesis, 1000);
+        let output = genesis.outputs()[0].clone();
+        assert_eq!(cell.fee(|_| Some(output.clone())), Ok(0));
+    }
+
+    #[actix_rt::test]
+    async fn test_fee_of_overspending_cell() {
+        let (keypair, genesis) = coinbase();
+        let cell = spending(&keypair, &genesis, 1001);
+        let output = genesis.outputs()[0].clone();
+        assert_eq!(cell.fee(|_| Some(output.clone())), Err(Error::ExceedsAvailableFunds));
+    }
+}
@@ -4,16 +4,16 @@ use super::outputs::Outputs;
 use super::types::CellHash;
 use super::Result;
 
-use std::collections::HashSet;
-
-use std::cmp::Ordering;
-use std::hash::{Hash, Hasher};
+use std::collections::BTreeSet;
 
 /// Defines an id for the whole [Cell][crate::cell::Cell] by combining all [CellId]s
 /// for each of its [Output][crate::cell::output::Output].
-#[derive(Clone, Serialize, Deserialize)]
+///
+/// The [CellId]s are kept sorted, so that iterating, comparing and serializing the same set
+/// gives the same result regardless of the order in which it was built.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub struct CellIds {
-    pub inner: HashSet<CellId>,
+    pub inner: BTreeSet<CellId>,
 }
 
 impl std::fmt::Debug for CellIds {
@@ -28,26 +28,18 @@ impl std::fmt::Debug for CellIds {
 
 impl std::iter::FromIterator<[u8; 32]> for CellIds {
     fn from_iter<I: IntoIterator<Item = [u8; 32]>>(iter: I) -> Self {
-        let mut hs = HashSet::new();
-        for bytes in iter {
-            hs.insert(CellId::new(bytes));
-        }
-        CellIds { inner: hs }
+        CellIds { inner: iter.into_iter().map(CellId::new).collect() }
     }
 }
 
 impl std::iter::FromIterator<CellId> for CellIds {
     fn from_iter<I: IntoIterator<Item = CellId>>(iter: I) -> Self {
-        let mut hs = HashSet::new();
-        for cell_id in iter {
-            hs.insert(cell_id.clone());
-        }
-        CellIds { inner: hs }
+        CellIds { inner: iter.into_iter().collect() }
     }
 }
 
 impl std::ops::Deref for CellIds {
-    type Target = HashSet<CellId>;
+    type Target = BTreeSet<CellId>;
 
     fn deref(&self) -> &'_ Self::Target {
         &self.inner
@@ -60,58 +52,18 @@ impl std::ops::DerefMut for CellIds {
     }
 }
 
-impl Hash for CellIds {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        let mut v: Vec<CellId> = self.iter().cloned().collect();
-        v.sort();
-        v.hash(state);
-    }
-}
-
-impl std::cmp::Eq for CellIds {}
-
-impl std::cmp::PartialEq for CellIds {
-    fn eq(&self, other: &Self) -> bool {
-        let mut self_v: Vec<CellId> = self.iter().cloned().collect();
-        let mut other_v: Vec<CellId> = other.iter().cloned().collect();
-        self_v.sort();
-        other_v.sort();
-        self_v == other_v
-    }
-}
-
-impl std::cmp::Ord for CellIds {
-    fn cmp(&self, other: &Self) -> Ordering {
-        let mut self_v: Vec<CellId> = self.iter().cloned().collect();
-        let mut other_v: Vec<CellId> = other.iter().cloned().collect();
-        self_v.sort();
-        other_v.sort();
-        self_v.cmp(&other_v)
-    }
-}
-
-impl std::cmp::PartialOrd for CellIds {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        let mut self_v: Vec<CellId> = self.iter().cloned().collect();
-        let mut other_v: Vec<CellId> = other.iter().cloned().collect();
-        self_v.sort();
-        other_v.sort();
-        Some(self_v.cmp(&other_v))
-    }
-}
-
 impl CellIds {
     /// Create new instance from a set of [CellId]s.
     ///
     /// ## Parameters
-    /// * `hs` - set of [CellId]s for joining to a single id
-    pub fn new(hs: HashSet<CellId>) -> Self {
-        CellIds { inner: hs }
+    /// * `cell_ids` - set of [CellId]s for joining to a single id
+    pub fn new(cell_ids: BTreeSet<CellId>) -> Self {
+        CellIds { inner: cell_ids }
     }
 
     /// Create an instance with no [CellId]s.
     pub fn empty() -> Self {
-        CellIds { inner: HashSet::new() }
+        CellIds { inner: BTreeSet::new() }
     }
 
     /// Create an instance from [Inputs][crate::cell::inputs::Inputs] of a [Cell][crate::cell::Cell].
@@ -121,7 +73,7 @@ impl CellIds {
     /// ## Parameters
     /// * `inputs` - inputs of a [Cell][crate::cell::Cell]
     pub fn from_inputs(inputs: Inputs) -> Result<Self> {
-        let mut cell_ids = HashSet::new();
+        let mut cell_ids = BTreeSet::new();
         for input in inputs.iter() {
             cell_ids.insert(input.cell_id()?);
         }
@@ -136,7 +88,7 @@ impl CellIds {
     /// * `cell_hash` - hash of a [Cell][crate::cell::Cell]
     /// * `outputs` - outputs of a [Cell][crate::cell::Cell]
     pub fn from_outputs(cell_hash: CellHash, outputs: Outputs) -> Result<Self> {
-        let mut cell_ids = HashSet::new();
+        let mut cell_ids = BTreeSet::new();
         for i in 0..outputs.len() {
             cell_ids.insert(CellId::from_output(cell_hash.clone(), i as u8, outputs[i].clone())?);
         }
@@ -158,6 +110,15 @@ impl CellIds {
     pub fn left_difference(&self, other: &CellIds) -> CellIds {
         CellIds { inner: self.difference(other).cloned().collect() }
     }
+
+    /// Returns the concatenation of the sorted [CellId]s, for hashing the set.
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.len() * 32);
+        for cell_id in self.iter() {
+            bytes.extend_from_slice(&cell_id[..]);
+        }
+        bytes
+    }
 }
 
 #[cfg(test)]
@@ -187,6 +148,31 @@ mod test {
         assert_eq!(genesis_output_cell_ids.clone(), transfer_tx_input_cell_ids.clone());
     }
 
+    #[actix_rt::test]
+    async fn test_canonical_bytes() {
+        let ids: Vec<CellId> = (0..20u8).map(|i| CellId::new([i.wrapping_mul(97); 32])).collect();
+        let mut reversed = ids.clone();
+        reversed.reverse();
+
+        let forward: CellIds = ids.iter().cloned().collect();
+        let backward: CellIds = reversed.iter().cloned().collect();
+        assert_eq!(forward.canonical_bytes(), backward.canonical_bytes());
+        assert_eq!(bincode::serialize(&forward).unwrap(), bincode::serialize(&backward).unwrap());
+
+        // Set operations keep the canonical order
+        let (left, right) = ids.split_at(12);
+        let left: CellIds = left.iter().rev().cloned().collect();
+        let right: CellIds = right.iter().cloned().collect();
+        let union: CellIds = right.union(&left).cloned().collect();
+        assert_eq!(union.canonical_bytes(), forward.canonical_bytes());
+        assert_eq!(forward.left_difference(&right).canonical_bytes(), left.canonical_bytes());
+
+        let mut sorted = ids.clone();
+        sorted.sort();
+        let expected: Vec<u8> = sorted.iter().flat_map(|id| id.to_vec()).collect();
+        assert_eq!(forward.canonical_bytes(), expected);
+    }
+
     fn hash_public(keypair: &Keypair) -> [u8; 32] {
         let enc = bincode::serialize(&keypair.public).unwrap();
         blake3::hash(&enc).as_bytes().clone()
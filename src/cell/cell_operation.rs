@@ -1,11 +1,10 @@
-use crate::alpha::coinbase::CoinbaseState;
-use crate::alpha::stake::StakeState;
-use crate::alpha::transfer::TransferState;
+use crate::alpha::types::BlockHeight;
 use crate::alpha::{Error, Result};
 use crate::cell::inputs::Input;
 use crate::cell::outputs::Output;
-use crate::cell::types::{Capacity, FEE};
-use crate::cell::{Cell, CellType};
+use crate::cell::types::{Capacity, ChainId, FEE};
+use crate::cell::{self, Cell};
+use crate::zfx_id::Id;
 use ed25519_dalek::Keypair;
 
 /// A response from [consume_from_cell]
@@ -39,13 +38,25 @@ pub struct ConsumeResult {
 /// * `amount` - `amount` to take out from the `cell`.
 /// * `owner_key` - owner's keypair is used to identify [outputs][Output]
 /// where `amount` can be taken from.
+/// * `chain_id` - the network the [inputs][Input] are signed for.
 pub fn consume_from_cell(
     cell: &Cell,
     amount: Capacity,
     owner_key: &Keypair,
+    chain_id: ChainId,
 ) -> Result<ConsumeResult> {
-    let encoded_public = bincode::serialize(&owner_key.public)?;
-    let pkh = blake3::hash(&encoded_public).as_bytes().clone();
+    consume_from_cell_outputs(cell, amount, owner_key, chain_id, |_| true)
+}
+
+/// Like [consume_from_cell], but only consumes the owner's outputs satisfying `filter`.
+pub fn consume_from_cell_outputs(
+    cell: &Cell,
+    amount: Capacity,
+    owner_key: &Keypair,
+    chain_id: ChainId,
+    filter: impl Fn(&Output) -> bool,
+) -> Result<ConsumeResult> {
+    let pkh = Id::from_public_key(&owner_key.public).bytes();
 
     let mut owned_outputs = vec![];
     let mut output_indices = vec![];
@@ -54,7 +65,7 @@ pub fn consume_from_cell(
         // Validate the output to make sure it has the right form.
         let () = output.validate_capacity()?;
         let () = validate_output(output.clone())?;
-        if output.lock == pkh.clone() {
+        if output.lock == pkh.clone() && filter(output) {
             owned_outputs.push(output.clone());
             output_indices.push(output_index);
         }
@@ -73,7 +84,12 @@ pub fn consume_from_cell(
     if owned_outputs.len() > 0 {
         for output in owned_outputs.iter() {
             if consumed < amount {
-                inputs.push(Input::new(owner_key, cell.hash(), output_indices[i])?);
+                inputs.push(Input::new_on_chain(
+                    chain_id,
+                    owner_key,
+                    cell.hash(),
+                    output_indices[i],
+                )?);
                 if spending_capacity >= output.capacity {
                     spending_capacity -= output.capacity;
                     consumed += output.capacity;
@@ -93,22 +109,27 @@ pub fn consume_from_cell(
     Ok(ConsumeResult { consumed, residue, inputs })
 }
 
+/// Checks that `input` carries the signatures required to spend `output`: a valid signature
+/// of the owner, for [CellType::Multisig] outputs, valid signatures of at least
+/// `threshold` distinct owners, or for [CellType::Script] outputs, a witness satisfying
+/// the lock script (see [cell::eval]). The signatures must be made for `chain_id`.
+pub fn verify_unlock(output: &Output, input: &Input, chain_id: ChainId) -> Result<()> {
+    Ok(cell::validate_unlock(output, input, chain_id)?)
+}
+
+/// Checks that the consumed `outputs` can be spent at the chain height `height`.
+///
+/// Throws [cell::Error::LockedOutput] if an output is locked until a later height.
+pub fn validate_locktimes(outputs: &[Output], height: BlockHeight) -> Result<()> {
+    for output in outputs.iter() {
+        let () = output.validate_locktime(height)?;
+    }
+    Ok(())
+}
+
 /// Checks that the output has the right form.
 fn validate_output(output: Output) -> Result<()> {
-    match output.cell_type {
-        CellType::Coinbase => {
-            let _: CoinbaseState = bincode::deserialize(&output.data)?;
-            Ok(())
-        }
-        CellType::Transfer => {
-            let _: TransferState = bincode::deserialize(&output.data)?;
-            Ok(())
-        }
-        CellType::Stake => {
-            let _: StakeState = bincode::deserialize(&output.data)?;
-            Ok(())
-        }
-    }
+    Ok(output.validate_data()?)
 }
 
 /// Checks that the capacity is > 0 and does not exceed the sum of the outputs.
@@ -122,3 +143,100 @@ fn validate_capacity(outputs: &Vec<Output>, capacity: Capacity, fee: u64) -> Res
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::alpha::transfer::transfer_output;
+    use crate::cell::types::DEFAULT_CHAIN_ID;
+    use crate::cell::MultisigLock;
+
+    use rand::rngs::OsRng;
+
+    fn generate_keypairs(n: usize) -> Vec<Keypair> {
+        let mut csprng = OsRng {};
+        (0..n).map(|_| Keypair::generate(&mut csprng)).collect()
+    }
+
+    fn pkh(keypair: &Keypair) -> [u8; 32] {
+        Id::from_public_key(&keypair.public).bytes()
+    }
+
+    /// Returns a 2-of-3 multisig output of the `keypairs` and the hash of a cell containing it
+    fn multisig_output(keypairs: &[Keypair]) -> (Output, [u8; 32]) {
+        let lock = MultisigLock::new(2, keypairs.iter().map(pkh).collect()).unwrap();
+        (lock.output(1000).unwrap(), [7u8; 32])
+    }
+
+    #[actix_rt::test]
+    async fn test_multisig_with_threshold_signatures() {
+        let kps = generate_keypairs(3);
+        let (output, cell_hash) = multisig_output(&kps);
+        let input = Input::new_multisig(&[&kps[0], &kps[2]], cell_hash, 0).unwrap();
+        assert_eq!(verify_unlock(&output, &input, DEFAULT_CHAIN_ID), Ok(()));
+    }
+
+    #[actix_rt::test]
+    async fn test_multisig_signed_for_other_chain() {
+        let kps = generate_keypairs(3);
+        let (output, cell_hash) = multisig_output(&kps);
+        let input = Input::new_multisig_on_chain(1, &[&kps[0], &kps[2]], cell_hash, 0).unwrap();
+        assert_eq!(verify_unlock(&output, &input, 1), Ok(()));
+        assert_eq!(
+            verify_unlock(&output, &input, 2),
+            Err(Error::Cell(cell::Error::MultisigThresholdNotMet))
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_multisig_with_one_signature() {
+        let kps = generate_keypairs(3);
+        let (output, cell_hash) = multisig_output(&kps);
+        let input = Input::new_multisig(&[&kps[1]], cell_hash, 0).unwrap();
+        assert_eq!(
+            verify_unlock(&output, &input, DEFAULT_CHAIN_ID),
+            Err(Error::Cell(cell::Error::MultisigThresholdNotMet))
+        );
+        // A signature of a key which isn't an owner doesn't count
+        let other = generate_keypairs(1);
+        let input = Input::new_multisig(&[&kps[1], &other[0]], cell_hash, 0).unwrap();
+        assert_eq!(
+            verify_unlock(&output, &input, DEFAULT_CHAIN_ID),
+            Err(Error::Cell(cell::Error::MultisigThresholdNotMet))
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_multisig_with_repeated_signature() {
+        let kps = generate_keypairs(3);
+        let (output, cell_hash) = multisig_output(&kps);
+        let input = Input::new_multisig(&[&kps[0], &kps[0]], cell_hash, 0).unwrap();
+        assert_eq!(
+            verify_unlock(&output, &input, DEFAULT_CHAIN_ID),
+            Err(Error::Cell(cell::Error::MultisigThresholdNotMet))
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_spend_height_locked_output() {
+        let kps = generate_keypairs(1);
+        let output = transfer_output(pkh(&kps[0]), 1000).unwrap().with_locktime(10);
+        assert_eq!(
+            validate_locktimes(&[output.clone()], 9),
+            Err(Error::Cell(cell::Error::LockedOutput(10)))
+        );
+        assert_eq!(validate_locktimes(&[output.clone()], 10), Ok(()));
+        assert_eq!(validate_locktimes(&[output], 11), Ok(()));
+    }
+
+    #[actix_rt::test]
+    async fn test_invalid_multisig_lock() {
+        let kps = generate_keypairs(2);
+        let owners: Vec<[u8; 32]> = kps.iter().map(pkh).collect();
+        assert_eq!(MultisigLock::new(0, owners.clone()), Err(cell::Error::InvalidMultisig));
+        assert_eq!(MultisigLock::new(3, owners.clone()), Err(cell::Error::InvalidMultisig));
+        let repeated = vec![owners[0], owners[0]];
+        assert_eq!(MultisigLock::new(2, repeated), Err(cell::Error::InvalidMultisig));
+    }
+}
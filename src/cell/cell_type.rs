@@ -1,3 +1,10 @@
+use super::cell::Cell;
+use super::output::Output;
+use super::{Error, Result};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
 /// Represents a type of cell, depending on applied operation on it
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub enum CellType {
@@ -15,4 +22,85 @@ pub enum CellType {
     /// for example when form a genesis block.
     /// [StakeOperation][crate::alpha::stake::StakeOperation] creates [Output][crate::cell::output::Output] with this type.
     Stake,
+    /// This type is assigned to [Output][crate::cell::output::Output] to represent a balance
+    /// which can only be spent with the signatures of `m` of its `n` owners.
+    /// The `data` of the output is a [MultisigLock][crate::cell::MultisigLock].
+    Multisig,
+    /// This type is assigned to [Output][crate::cell::output::Output] to represent a balance
+    /// which can only be spent by an input whose witness satisfies a lock script.
+    /// The `data` of the output is a [Script][crate::cell::Script].
+    Script,
+    /// This type is assigned to [Output][crate::cell::output::Output] to represent a balance
+    /// delegated to the stake of a validator, which remains owned by its `lock`.
+    /// [DelegateOperation][crate::alpha::stake::DelegateOperation] creates [Output][crate::cell::output::Output] with this type.
+    Delegate,
+    /// This type is assigned to [Output][crate::cell::output::Output] to record the evidence of a
+    /// validator equivocating, which slashes its stake once accepted in a block. It has no
+    /// capacity and can't be spent. The `data` of the output is a
+    /// [SlashEvidence][crate::alpha::slash::SlashEvidence].
+    Slash,
+    /// This type is assigned to [Output][crate::cell::output::Output] to represent a cell of a
+    /// client chain, identified by its tag. The `data` of the output is opaque to the core and
+    /// interpreted by the validator registered in a [CellTypeRegistry].
+    Custom(u16),
+}
+
+/// The signature of the validators of [custom][CellType::Custom] outputs
+pub type CellValidator = Box<dyn Fn(&Output) -> Result<()> + Send + Sync>;
+
+/// Maps the tags of [custom cell types][CellType::Custom] to the validators of their outputs,
+/// so that client chains can interpret their own cells.
+///
+/// The built-in cell types are validated by the core and can't be registered. Clones share the
+/// validators, so that [Sleet][crate::sleet::Sleet], [Hail][crate::hail::Hail] and
+/// [Alpha][crate::alpha::Alpha] apply the same ones.
+#[derive(Clone, Default)]
+pub struct CellTypeRegistry {
+    validators: HashMap<u16, Arc<CellValidator>>,
+}
+
+impl std::fmt::Debug for CellTypeRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut tags: Vec<&u16> = self.validators.keys().collect();
+        tags.sort();
+        f.debug_struct("CellTypeRegistry").field("tags", &tags).finish()
+    }
+}
+
+impl CellTypeRegistry {
+    pub fn new() -> Self {
+        CellTypeRegistry { validators: HashMap::new() }
+    }
+
+    /// Registers `validator` for the outputs of cell type `Custom(tag)`, replacing any
+    /// previously registered validator.
+    pub fn register(&mut self, tag: u16, validator: CellValidator) {
+        let _ = self.validators.insert(tag, Arc::new(validator));
+    }
+
+    /// Returns `true` if a validator is registered for `Custom(tag)`.
+    pub fn is_registered(&self, tag: u16) -> bool {
+        self.validators.contains_key(&tag)
+    }
+
+    /// Returns the tags of the custom cell types with a registered validator.
+    pub fn tags(&self) -> Vec<u16> {
+        self.validators.keys().cloned().collect()
+    }
+
+    /// Checks the custom outputs of `cell` with their registered validators.
+    ///
+    /// Throws [Error::UnknownCellType] if no validator is registered for a custom output,
+    /// otherwise the error of the failing validator.
+    pub fn validate(&self, cell: &Cell) -> Result<()> {
+        for output in cell.outputs().iter() {
+            if let CellType::Custom(tag) = output.cell_type {
+                match self.validators.get(&tag) {
+                    Some(validator) => validator(output)?,
+                    None => return Err(Error::UnknownCellType(tag)),
+                }
+            }
+        }
+        Ok(())
+    }
 }
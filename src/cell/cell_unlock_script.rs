@@ -3,8 +3,8 @@ use std::hash::{Hash, Hasher};
 
 use ed25519_dalek::{PublicKey, Signature};
 
-/// A cells unlocking script (simple).
-/// _not in use at the moment, as transactions are not signed_
+/// A cells unlocking script (simple): an ed25519 signature along with the public key of the
+/// signer, see [UnlockScript::Ed25519](super::UnlockScript::Ed25519).
 #[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct CellUnlockScript {
     pub public_key: PublicKey,
@@ -1,12 +1,13 @@
 use super::cell_id::CellId;
-use super::cell_unlock_script::CellUnlockScript;
 use super::output_index::OutputIndex;
+use super::script::Script;
 use super::types::*;
-use super::Result;
+use super::unlock_script::UnlockScript;
+use super::{Error, Result};
 
 use std::hash::Hash;
 
-use ed25519_dalek::{Keypair, Signer};
+use ed25519_dalek::Keypair;
 
 /// Part of [Cell][crate::cell::Cell] structure which represents a
 /// reference to a spent [Output][crate::cell::output::Output] of a cell
@@ -16,8 +17,12 @@ pub struct Input {
     /// Reference to an [Output][crate::cell::output::Output] within a [Cell][crate::cell::Cell],
     /// based on its position (index) in an [Outputs][crate::cell::outputs::Outputs] list.
     pub output_index: OutputIndex,
-    /// _not in use at the moment, as transactions are not signed_
-    pub unlock: CellUnlockScript,
+    /// The signature of the owner of the spent output, in the scheme of the output.
+    pub unlock: UnlockScript,
+    /// Signatures of further owners, for spending [multisig][crate::cell::MultisigLock] outputs.
+    pub cosigners: Vec<UnlockScript>,
+    /// The witness script, for spending [script][crate::cell::Script] outputs.
+    pub witness: Script,
 }
 
 impl std::fmt::Display for Input {
@@ -27,7 +32,7 @@ impl std::fmt::Display for Input {
 }
 
 impl Input {
-    /// Create a new instance of Input.
+    /// Create a new instance of Input, signed for the [default chain][DEFAULT_CHAIN_ID].
     ///
     /// ## Parameters:
     /// * `keypair` - account's keypair for signing serialized `cell_hash` and `index`,
@@ -36,11 +41,73 @@ impl Input {
     /// * `index` - position of [Output][crate::cell::output::Output]
     /// in the list of [Outputs][crate::cell::outputs::Outputs] in [Cell][crate::cell::Cell].
     pub fn new(keypair: &Keypair, cell_hash: CellHash, index: u8) -> Result<Self> {
+        Input::new_on_chain(DEFAULT_CHAIN_ID, keypair, cell_hash, index)
+    }
+
+    /// Like [Input::new], but signed for the network `chain_id`, so that the signature is only
+    /// valid on that network.
+    pub fn new_on_chain(
+        chain_id: ChainId,
+        keypair: &Keypair,
+        cell_hash: CellHash,
+        index: u8,
+    ) -> Result<Self> {
+        let output_index = OutputIndex::new(cell_hash.clone(), index);
+        let message = signing_message(chain_id, &output_index.cell_id()?);
+        let unlock = UnlockScript::ed25519(keypair, &message);
+        Ok(Input { output_index, unlock, cosigners: vec![], witness: vec![] })
+    }
+
+    /// Like [Input::new_on_chain], but signed with a secp256k1 `signing_key`, for spending
+    /// outputs of the [Secp256k1][crate::cell::SignatureScheme::Secp256k1] scheme.
+    pub fn new_secp256k1_on_chain(
+        chain_id: ChainId,
+        signing_key: &k256::ecdsa::SigningKey,
+        cell_hash: CellHash,
+        index: u8,
+    ) -> Result<Self> {
         let output_index = OutputIndex::new(cell_hash.clone(), index);
-        let cell_id: [u8; 32] = output_index.cell_id()?.into();
-        let signature = keypair.sign(&cell_id);
-        let unlock = CellUnlockScript::new(keypair.public.clone(), signature);
-        Ok(Input { output_index, unlock })
+        let message = signing_message(chain_id, &output_index.cell_id()?);
+        let unlock = UnlockScript::secp256k1(signing_key, &message);
+        Ok(Input { output_index, unlock, cosigners: vec![], witness: vec![] })
+    }
+
+    /// Create a new instance of Input for spending a [multisig][crate::cell::MultisigLock]
+    /// output, signed by each of the `keypairs` for the [default chain][DEFAULT_CHAIN_ID].
+    ///
+    /// Throws [Error::InvalidMultisig] if `keypairs` is empty.
+    pub fn new_multisig(keypairs: &[&Keypair], cell_hash: CellHash, index: u8) -> Result<Self> {
+        Input::new_multisig_on_chain(DEFAULT_CHAIN_ID, keypairs, cell_hash, index)
+    }
+
+    /// Like [Input::new_multisig], but signed for the network `chain_id`.
+    pub fn new_multisig_on_chain(
+        chain_id: ChainId,
+        keypairs: &[&Keypair],
+        cell_hash: CellHash,
+        index: u8,
+    ) -> Result<Self> {
+        let (first, rest) = keypairs.split_first().ok_or(Error::InvalidMultisig)?;
+        let mut input = Input::new_on_chain(chain_id, first, cell_hash, index)?;
+        let message = signing_message(chain_id, &input.cell_id()?);
+        for keypair in rest.iter() {
+            input.cosigners.push(UnlockScript::ed25519(keypair, &message));
+        }
+        Ok(input)
+    }
+
+    /// Sets the witness script, which is evaluated before the lock script of the spent
+    /// [script][crate::cell::Script] output.
+    pub fn with_witness(mut self, witness: Script) -> Self {
+        self.witness = witness;
+        self
+    }
+
+    /// Returns all signatures of the input.
+    pub fn unlocks(&self) -> Vec<UnlockScript> {
+        let mut unlocks = vec![self.unlock.clone()];
+        unlocks.extend(self.cosigners.iter().cloned());
+        unlocks
     }
 
     /// Returns a cell id from `output_index`.
@@ -48,3 +115,13 @@ impl Input {
         self.output_index.cell_id()
     }
 }
+
+/// The message signed by the owners of a spent output: the id of the spent cell prefixed by
+/// the `chain_id` of the network.
+pub fn signing_message(chain_id: ChainId, cell_id: &CellId) -> Vec<u8> {
+    let cell_id: [u8; 32] = cell_id.clone().into();
+    let mut message = Vec::with_capacity(1 + cell_id.len());
+    message.push(chain_id);
+    message.extend_from_slice(&cell_id);
+    message
+}
@@ -1,5 +1,6 @@
 pub use super::input::*;
 
+use serde::de::{Deserialize, Deserializer};
 use serde::ser::{Serialize, SerializeSeq, Serializer};
 
 use std::collections::HashSet;
@@ -9,7 +10,7 @@ use std::hash::{Hash, Hasher};
 use std::ops::{Deref, DerefMut};
 
 /// An aggregated structure for storing a set of [Input]s.
-#[derive(Clone, Deserialize)]
+#[derive(Clone)]
 pub struct Inputs {
     pub inputs: HashSet<Input>,
 }
@@ -79,6 +80,16 @@ impl Serialize for Inputs {
     }
 }
 
+// Inputs are serialized as a sequence, which is also the binary encoding of the struct.
+impl<'de> Deserialize<'de> for Inputs {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Inputs { inputs: HashSet::deserialize(deserializer)? })
+    }
+}
+
 // Note: We only use this to hash the inputs for equality of a `tx`, not in the
 // hyperarc entries (otherwise conflict fails).
 impl Hash for Inputs {
@@ -12,24 +12,65 @@ mod cell_type;
 mod cell_unlock_script;
 pub mod input;
 pub mod inputs;
+mod multisig;
 pub mod output;
 mod output_index;
 pub mod outputs;
+mod script;
 pub mod types;
+mod unlock_script;
+mod validation;
 
 pub use cell::*;
 pub use cell_id::*;
 pub use cell_ids::*;
 pub use cell_type::*;
 pub use cell_unlock_script::*;
+pub use multisig::*;
+pub use script::*;
+pub use unlock_script::*;
+pub use validation::*;
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum Error {
     Hex(String),
     Bincode(String),
     Dalek(String),
+    Json(String),
     InvalidCoinbase,
     InvalidStake,
+    /// A delegated output can't be delegated again
+    InvalidDelegation,
+    /// The multisig lock can't be unlocked
+    InvalidMultisig,
+    /// Less than the threshold of owners signed a multisig input
+    MultisigThresholdNotMet,
+    /// The input isn't signed by the owner of the output
+    InvalidUnlock,
+    /// The signature scheme of the input differs from the one of the spent output
+    SchemeMismatch,
+    /// The output can't be spent before the contained block height
+    LockedOutput(crate::alpha::types::BlockHeight),
+    /// An output has zero capacity
+    ZeroTransfer,
+    /// The output spent by an input is unknown
+    UndefinedInput,
+    /// The outputs exceed the capacity of the inputs
+    ExceedsAvailableFunds,
+    /// The script of an input exceeds the step or stack limit
+    ScriptLimitExceeded,
+    /// The script of an input doesn't unlock the output
+    ScriptFailed,
+    /// A signature of an input of the cell is invalid
+    InvalidSignature(types::CellHash),
+    /// No validator is registered for the custom cell type
+    UnknownCellType(u16),
+    /// The `data` of a custom output is rejected by the validator of its cell type
+    InvalidCellData(u16),
+    /// The transaction pays a fee (the first value) below the minimum (the second value)
+    InsufficientFee(types::Capacity, types::Capacity),
+    /// A slash output isn't the only output of a cell without inputs, or records invalid evidence
+    InvalidSlash,
 }
 
 impl std::error::Error for Error {}
@@ -52,6 +93,12 @@ impl std::convert::From<ed25519_dalek::ed25519::Error> for Error {
     }
 }
 
+impl std::convert::From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Json(format!("{:?}", error))
+    }
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{:?}", self)
@@ -0,0 +1,79 @@
+use super::cell_type::CellType;
+use super::output::Output;
+use super::types::{Capacity, PublicKeyHash};
+use super::unlock_script::{SignatureScheme, UnlockScript};
+use super::{Error, Result};
+
+use std::collections::HashSet;
+
+/// The lock of an m-of-n multisig [Output], stored in its `data` field.
+///
+/// The `lock` of the output is the hash of the `MultisigLock`. Spending it requires valid
+/// signatures from at least `threshold` distinct `owners`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MultisigLock {
+    /// The number of owners required to sign (`m`)
+    pub threshold: u8,
+    /// The public key hashes of the owners (`n`)
+    pub owners: Vec<PublicKeyHash>,
+}
+
+impl MultisigLock {
+    /// Create a new multisig lock.
+    ///
+    /// Throws [Error::InvalidMultisig] if the `threshold` is 0, the `owners` aren't distinct
+    /// or there are less owners than `threshold`.
+    pub fn new(threshold: u8, owners: Vec<PublicKeyHash>) -> Result<Self> {
+        let lock = MultisigLock { threshold, owners };
+        let () = lock.validate()?;
+        Ok(lock)
+    }
+
+    /// Checks that the lock can be unlocked.
+    pub fn validate(&self) -> Result<()> {
+        let distinct: HashSet<&PublicKeyHash> = self.owners.iter().collect();
+        if self.threshold == 0
+            || distinct.len() != self.owners.len()
+            || self.threshold as usize > self.owners.len()
+        {
+            return Err(Error::InvalidMultisig);
+        }
+        Ok(())
+    }
+
+    /// Returns the hash of the lock, used as the `lock` of multisig outputs.
+    pub fn hash(&self) -> Result<PublicKeyHash> {
+        let encoded = bincode::serialize(self)?;
+        Ok(blake3::hash(&encoded).as_bytes().clone())
+    }
+
+    /// Returns an [Output] of `capacity` locked by this lock.
+    pub fn output(&self, capacity: Capacity) -> Result<Output> {
+        Ok(Output {
+            capacity,
+            cell_type: CellType::Multisig,
+            data: bincode::serialize(self)?,
+            lock: self.hash()?,
+            locktime: None,
+            scheme: SignatureScheme::Ed25519,
+        })
+    }
+
+    /// Verifies that `unlocks` contains valid signatures of `message` from at least `threshold`
+    /// distinct owners, otherwise throws [Error::MultisigThresholdNotMet].
+    ///
+    /// Signatures of other keys are ignored and signatures of the same owner are counted once.
+    pub fn verify(&self, message: &[u8], unlocks: &[UnlockScript]) -> Result<()> {
+        let mut signers = HashSet::new();
+        for unlock in unlocks.iter() {
+            let pkh = unlock.public_key_hash();
+            if self.owners.contains(&pkh) && unlock.verify(message).is_ok() {
+                let _ = signers.insert(pkh);
+            }
+        }
+        if signers.len() < self.threshold as usize {
+            return Err(Error::MultisigThresholdNotMet);
+        }
+        Ok(())
+    }
+}
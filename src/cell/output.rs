@@ -1,7 +1,14 @@
-use crate::alpha::stake::StakeState;
+use crate::alpha::coinbase::CoinbaseState;
+use crate::alpha::slash::SlashEvidence;
+use crate::alpha::stake::{DelegateState, StakeState};
+use crate::alpha::transfer::TransferState;
+use crate::alpha::types::BlockHeight;
 
 use super::cell_type::CellType;
+use super::multisig::MultisigLock;
+use super::script::{Script, MAX_SCRIPT_STEPS};
 use super::types::{Capacity, PublicKeyHash};
+use super::unlock_script::SignatureScheme;
 use super::{Error, Result};
 
 use crate::colored::Colorize;
@@ -11,6 +18,7 @@ use crate::colored::Colorize;
 /// * [CellType::Coinbase] - assigned by [CoinbaseOperation](crate::alpha::coinbase::CoinbaseOperation)
 /// * [CellType::Transfer] - assigned by [TransferOperation](crate::alpha::transfer::TransferOperation)
 /// * [CellType::Stake] - assigned by [StakeOperation](crate::alpha::stake::StakeOperation)
+/// * [CellType::Delegate] - assigned by [DelegateOperation](crate::alpha::stake::DelegateOperation)
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub struct Output {
     /// The capacity supplied by this cell output.
@@ -20,9 +28,15 @@ pub struct Output {
     /// The serialized data of different states, depending on `cell_type`,
     /// such as: [CoinbaseState][crate::alpha::coinbase::CoinbaseState],
     /// [TransferState][crate::alpha::transfer::TransferState], [StakeState][crate::alpha::stake::StakeState].
+    #[serde(with = "crate::json::hex_bytes")]
     pub data: Vec<u8>,
     /// The owner of the cell output (TODO: should be made generic).
+    #[serde(with = "crate::json::hex_bytes")]
     pub lock: PublicKeyHash,
+    /// The block height from which on the output can be spent, if any.
+    pub locktime: Option<BlockHeight>,
+    /// The signature scheme of the owner, whose public key hash is the `lock`.
+    pub scheme: SignatureScheme,
 }
 
 impl std::fmt::Debug for Output {
@@ -41,6 +55,30 @@ impl std::fmt::Debug for Output {
                 let lock = hex::encode(self.lock);
                 write!(f, "stake {} (⚴ {}) = {}", state.node_id, lock, self.capacity)
             }
+            CellType::Multisig => {
+                let lock = hex::encode(self.lock);
+                write!(f, "multisig (⚴ {}) = {}", lock, self.capacity)
+            }
+            CellType::Script => {
+                let lock = hex::encode(self.lock);
+                write!(f, "script (⚴ {}) = {}", lock, self.capacity)
+            }
+            CellType::Delegate => {
+                let state: DelegateState = bincode::deserialize(&self.data).unwrap();
+                let lock = hex::encode(self.lock);
+                write!(f, "delegate {} (⚴ {}) = {}", state.node_id, lock, self.capacity)
+            }
+            CellType::Custom(tag) => {
+                let lock = hex::encode(self.lock);
+                write!(f, "custom {} (⚴ {}) = {}", tag, lock, self.capacity)
+            }
+            CellType::Slash => {
+                let lock = hex::encode(self.lock);
+                match bincode::deserialize::<SlashEvidence>(&self.data) {
+                    Ok(evidence) => write!(f, "slash at height {} (⚴ {})", evidence.height, lock),
+                    Err(_) => write!(f, "slash (⚴ {})", lock),
+                }
+            }
         }
     }
 }
@@ -62,11 +100,104 @@ impl std::fmt::Display for Output {
                 let node_id = format!("{}", state.node_id).yellow();
                 write!(f, "{} {} = {}", "stake".cyan(), node_id, capacity)
             }
+            CellType::Multisig => {
+                let capacity = format!("{}", self.capacity).magenta();
+                write!(f, "{} = {}", "multisig".cyan(), capacity)
+            }
+            CellType::Script => {
+                let capacity = format!("{}", self.capacity).magenta();
+                write!(f, "{} = {}", "script".cyan(), capacity)
+            }
+            CellType::Delegate => {
+                let state: DelegateState = bincode::deserialize(&self.data).unwrap();
+                let capacity = format!("{}", self.capacity).magenta();
+                let node_id = format!("{}", state.node_id).yellow();
+                write!(f, "{} {} = {}", "delegate".cyan(), node_id, capacity)
+            }
+            CellType::Custom(tag) => {
+                let capacity = format!("{}", self.capacity).magenta();
+                write!(f, "{} {} = {}", "custom".cyan(), tag, capacity)
+            }
+            CellType::Slash => match bincode::deserialize::<SlashEvidence>(&self.data) {
+                Ok(evidence) => {
+                    let height = format!("{}", evidence.height).yellow();
+                    write!(f, "{} at height {}", "slash".cyan(), height)
+                }
+                Err(_) => write!(f, "{}", "slash".cyan()),
+            },
         }
     }
 }
 
 impl Output {
+    /// Checks that the `data` of the output has the form required by its `cell_type`.
+    pub fn validate_data(&self) -> Result<()> {
+        match self.cell_type {
+            CellType::Coinbase => {
+                let _: CoinbaseState = bincode::deserialize(&self.data)?;
+                Ok(())
+            }
+            CellType::Transfer => {
+                let _: TransferState = bincode::deserialize(&self.data)?;
+                Ok(())
+            }
+            CellType::Stake => {
+                let state: StakeState = bincode::deserialize(&self.data)?;
+                // The staking period can't be empty
+                if state.start_height >= state.end_height {
+                    return Err(Error::InvalidStake);
+                }
+                Ok(())
+            }
+            CellType::Multisig => {
+                let lock: MultisigLock = bincode::deserialize(&self.data)?;
+                lock.validate()
+            }
+            CellType::Script => {
+                let script: Script = bincode::deserialize(&self.data)?;
+                if script.len() > MAX_SCRIPT_STEPS {
+                    return Err(Error::ScriptLimitExceeded);
+                }
+                Ok(())
+            }
+            CellType::Delegate => {
+                let _: DelegateState = bincode::deserialize(&self.data)?;
+                Ok(())
+            }
+            // The data of custom outputs is checked by their validator in the `CellTypeRegistry`
+            CellType::Custom(_) => Ok(()),
+            CellType::Slash => {
+                let evidence: SlashEvidence = bincode::deserialize(&self.data)?;
+                match evidence.validator() {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(Error::InvalidSlash),
+                }
+            }
+        }
+    }
+
+    /// Locks the output to a public key of the signature `scheme`, instead of ed25519.
+    pub fn with_scheme(mut self, scheme: SignatureScheme) -> Self {
+        self.scheme = scheme;
+        self
+    }
+
+    /// Makes the output unspendable before the block height `locktime`.
+    pub fn with_locktime(mut self, locktime: BlockHeight) -> Self {
+        self.locktime = Some(locktime);
+        self
+    }
+
+    /// Checks that the output can be spent at the chain height `height`.
+    ///
+    /// Throws [Error::LockedOutput] if `height` is below the `locktime` of the output.
+    pub fn validate_locktime(&self, height: BlockHeight) -> Result<()> {
+        match self.locktime {
+            Some(locktime) if height < locktime => Err(Error::LockedOutput(locktime)),
+            _ => Ok(()),
+        }
+    }
+
     pub fn validate_capacity(&self) -> Result<()> {
         // TODO: Check that the cell capacity >= serialized(self)
         Ok(())
@@ -91,6 +222,21 @@ impl Output {
             }
             // Besides checking the data field, transfer cell types are verified only as a valid cell.
             CellType::Transfer => Ok(()),
+            // Multisig outputs are verified when spent, see `cell_operation::verify_unlock`.
+            CellType::Multisig => Ok(()),
+            // Script outputs are verified when spent, see `cell_operation::verify_unlock`.
+            CellType::Script => Ok(()),
+            // Custom outputs are interpreted by client chains.
+            CellType::Custom(_) => Ok(()),
+            // Slash outputs are created by cells without inputs, see `cell::validate`.
+            CellType::Slash => Ok(()),
+            CellType::Delegate => {
+                // Delegated outputs can't be delegated again, only undelegated.
+                if !outputs.is_empty() {
+                    return Err(Error::InvalidDelegation);
+                }
+                Ok(())
+            }
             CellType::Stake => {
                 // Stake operations do not consume other stake outputs.
                 if outputs.len() != 0 {
@@ -6,6 +6,7 @@ use super::Result;
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub struct OutputIndex {
     /// hash of a [Cell] being spent
+    #[serde(with = "crate::json::hex_bytes")]
     pub cell_hash: CellHash,
     /// position of [Output] in the list of [Outputs] in [Cell]
     pub index: u8,
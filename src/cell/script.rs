@@ -0,0 +1,274 @@
+//! A minimal stack-based script language for locking [outputs][Output]
+//!
+//! An output of type [CellType::Script] holds a lock script in its `data`. It is spent by an
+//! [Input][super::input::Input] whose `witness` script, followed by the lock script, evaluates
+//! to a true value on top of the stack. There are no loops or jumps, and the number of steps
+//! is limited by [MAX_SCRIPT_STEPS], so evaluation always terminates.
+//!
+//! Public keys and signatures on the stack use their `bincode` encoding, as elsewhere in cells,
+//! so `[Dup, Hash, Push(pkh), EqualVerify, CheckSig]` locks an output to the public key hash
+//! `pkh` of an ordinary [Output::lock].
+
+use super::cell_type::CellType;
+use super::output::Output;
+use super::types::{Capacity, PublicKeyHash};
+use super::unlock_script::SignatureScheme;
+use super::{Error, Result};
+
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+
+/// The maximum number of steps of evaluating a witness and a lock script.
+/// Each operation is a step, and each signature check of [ScriptOp::CheckMultisig] another one.
+pub const MAX_SCRIPT_STEPS: usize = 100;
+/// The maximum number of elements on the stack.
+pub const MAX_STACK_SIZE: usize = 100;
+
+/// An operation of a script
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum ScriptOp {
+    /// Pushes the bytes on the stack
+    Push(#[serde(with = "crate::json::hex_bytes")] Vec<u8>),
+    /// Duplicates the top element
+    Dup,
+    /// Replaces the top element with its blake3 hash
+    Hash,
+    /// Pops two elements and pushes whether they are equal
+    Equal,
+    /// Pops two elements and fails unless they are equal
+    EqualVerify,
+    /// Pops a public key and a signature and pushes whether the signature of the spent cell id
+    /// is valid
+    CheckSig,
+    /// Pops `n` public keys and `m` signatures and pushes whether each signature is a valid
+    /// signature of a distinct public key, in the order of the public keys
+    CheckMultisig { m: u8, n: u8 },
+}
+
+/// A script, evaluated from the first to the last operation
+pub type Script = Vec<ScriptOp>;
+
+/// Returns the hash of the lock script, used as the `lock` of script outputs.
+pub fn script_hash(lock_script: &Script) -> Result<PublicKeyHash> {
+    let encoded = bincode::serialize(lock_script)?;
+    Ok(blake3::hash(&encoded).as_bytes().clone())
+}
+
+/// Returns an [Output] of `capacity` locked by `lock_script`.
+pub fn script_output(lock_script: &Script, capacity: Capacity) -> Result<Output> {
+    Ok(Output {
+        capacity,
+        cell_type: CellType::Script,
+        data: bincode::serialize(lock_script)?,
+        lock: script_hash(lock_script)?,
+        locktime: None,
+        scheme: SignatureScheme::Ed25519,
+    })
+}
+
+/// Evaluates the `witness` followed by the `lock_script`, where signatures are checked against
+/// `message` (the spent cell id).
+///
+/// Throws [Error::ScriptLimitExceeded] if the scripts exceed [MAX_SCRIPT_STEPS] or
+/// [MAX_STACK_SIZE], and [Error::ScriptFailed] if evaluation fails or doesn't leave a true
+/// value on top of the stack.
+pub fn eval(witness: &Script, lock_script: &Script, message: &[u8]) -> Result<()> {
+    // Fail before evaluating anything, so that the outcome doesn't depend on the operations
+    if witness.len() + lock_script.len() > MAX_SCRIPT_STEPS {
+        return Err(Error::ScriptLimitExceeded);
+    }
+    let mut interpreter = Interpreter { stack: vec![], steps: 0, message };
+    for op in witness.iter().chain(lock_script.iter()) {
+        let () = interpreter.step(op)?;
+    }
+    match interpreter.stack.last() {
+        Some(top) if is_true(top) => Ok(()),
+        _ => Err(Error::ScriptFailed),
+    }
+}
+
+struct Interpreter<'a> {
+    stack: Vec<Vec<u8>>,
+    steps: usize,
+    message: &'a [u8],
+}
+
+impl<'a> Interpreter<'a> {
+    fn step(&mut self, op: &ScriptOp) -> Result<()> {
+        let () = self.count_steps(1)?;
+        match op {
+            ScriptOp::Push(bytes) => self.push(bytes.clone()),
+            ScriptOp::Dup => {
+                let top = self.stack.last().cloned().ok_or(Error::ScriptFailed)?;
+                self.push(top)
+            }
+            ScriptOp::Hash => {
+                let top = self.pop()?;
+                self.push(blake3::hash(&top).as_bytes().to_vec())
+            }
+            ScriptOp::Equal => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.push_bool(a == b)
+            }
+            ScriptOp::EqualVerify => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                if a != b {
+                    return Err(Error::ScriptFailed);
+                }
+                Ok(())
+            }
+            ScriptOp::CheckSig => {
+                let (public_key, signature) = (self.pop()?, self.pop()?);
+                let valid = self.check_sig(&public_key, &signature);
+                self.push_bool(valid)
+            }
+            ScriptOp::CheckMultisig { m, n } => {
+                let (m, n) = (*m as usize, *n as usize);
+                if m > n {
+                    return Err(Error::ScriptFailed);
+                }
+                let () = self.count_steps(m)?;
+                let mut public_keys = vec![];
+                for _ in 0..n {
+                    public_keys.push(self.pop()?);
+                }
+                let mut signatures = vec![];
+                for _ in 0..m {
+                    signatures.push(self.pop()?);
+                }
+                // Both were pushed in order, so are popped in reverse
+                public_keys.reverse();
+                signatures.reverse();
+                let mut keys = public_keys.iter();
+                let valid = signatures.iter().all(|signature| {
+                    keys.by_ref().any(|public_key| self.check_sig(public_key, signature))
+                });
+                self.push_bool(valid)
+            }
+        }
+    }
+
+    fn count_steps(&mut self, steps: usize) -> Result<()> {
+        self.steps += steps;
+        if self.steps > MAX_SCRIPT_STEPS {
+            return Err(Error::ScriptLimitExceeded);
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, bytes: Vec<u8>) -> Result<()> {
+        if self.stack.len() >= MAX_STACK_SIZE {
+            return Err(Error::ScriptLimitExceeded);
+        }
+        self.stack.push(bytes);
+        Ok(())
+    }
+
+    fn push_bool(&mut self, value: bool) -> Result<()> {
+        self.push(if value { vec![1] } else { vec![] })
+    }
+
+    fn pop(&mut self) -> Result<Vec<u8>> {
+        self.stack.pop().ok_or(Error::ScriptFailed)
+    }
+
+    fn check_sig(&self, public_key: &[u8], signature: &[u8]) -> bool {
+        match (
+            bincode::deserialize::<PublicKey>(public_key),
+            bincode::deserialize::<Signature>(signature),
+        ) {
+            (Ok(public_key), Ok(signature)) => public_key.verify(self.message, &signature).is_ok(),
+            _ => false,
+        }
+    }
+}
+
+fn is_true(bytes: &[u8]) -> bool {
+    bytes.iter().any(|b| *b != 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::zfx_id::Id;
+
+    use ed25519_dalek::{Keypair, Signer};
+    use rand::rngs::OsRng;
+
+    const MESSAGE: &[u8] = b"cell id";
+
+    fn generate_keypair() -> Keypair {
+        let mut csprng = OsRng {};
+        Keypair::generate(&mut csprng)
+    }
+
+    /// The witness of `keypair` for a pay-to-public-key-hash script
+    fn p2pkh_witness(keypair: &Keypair) -> Script {
+        let signature = bincode::serialize(&keypair.sign(MESSAGE)).unwrap();
+        let public_key = bincode::serialize(&keypair.public).unwrap();
+        vec![ScriptOp::Push(signature), ScriptOp::Push(public_key)]
+    }
+
+    fn p2pkh_script(pkh: PublicKeyHash) -> Script {
+        vec![
+            ScriptOp::Dup,
+            ScriptOp::Hash,
+            ScriptOp::Push(pkh.to_vec()),
+            ScriptOp::EqualVerify,
+            ScriptOp::CheckSig,
+        ]
+    }
+
+    #[actix_rt::test]
+    async fn test_pay_to_public_key_hash() {
+        let (owner, other) = (generate_keypair(), generate_keypair());
+        let lock_script = p2pkh_script(Id::from_public_key(&owner.public).bytes());
+
+        assert_eq!(eval(&p2pkh_witness(&owner), &lock_script, MESSAGE), Ok(()));
+        assert_eq!(eval(&p2pkh_witness(&other), &lock_script, MESSAGE), Err(Error::ScriptFailed));
+        assert_eq!(eval(&p2pkh_witness(&owner), &lock_script, b"other"), Err(Error::ScriptFailed));
+        assert_eq!(eval(&vec![], &lock_script, MESSAGE), Err(Error::ScriptFailed));
+    }
+
+    #[actix_rt::test]
+    async fn test_hash_preimage() {
+        let preimage = b"secret".to_vec();
+        let lock_script = vec![
+            ScriptOp::Hash,
+            ScriptOp::Push(blake3::hash(&preimage).as_bytes().to_vec()),
+            ScriptOp::Equal,
+        ];
+
+        let witness = vec![ScriptOp::Push(preimage)];
+        assert_eq!(eval(&witness, &lock_script, MESSAGE), Ok(()));
+        let witness = vec![ScriptOp::Push(b"guess".to_vec())];
+        assert_eq!(eval(&witness, &lock_script, MESSAGE), Err(Error::ScriptFailed));
+    }
+
+    #[actix_rt::test]
+    async fn test_check_multisig() {
+        let keypairs: Vec<Keypair> = (0..3).map(|_| generate_keypair()).collect();
+        let mut lock_script: Script = keypairs
+            .iter()
+            .map(|kp| ScriptOp::Push(bincode::serialize(&kp.public).unwrap()))
+            .collect();
+        lock_script.push(ScriptOp::CheckMultisig { m: 2, n: 3 });
+        let signature =
+            |kp: &Keypair| ScriptOp::Push(bincode::serialize(&kp.sign(MESSAGE)).unwrap());
+
+        let witness = vec![signature(&keypairs[0]), signature(&keypairs[2])];
+        assert_eq!(eval(&witness, &lock_script, MESSAGE), Ok(()));
+        let witness = vec![signature(&keypairs[0]), signature(&keypairs[0])];
+        assert_eq!(eval(&witness, &lock_script, MESSAGE), Err(Error::ScriptFailed));
+    }
+
+    #[actix_rt::test]
+    async fn test_step_limit_exceeded() {
+        let witness = vec![ScriptOp::Push(vec![1])];
+        let lock_script = vec![ScriptOp::Dup; MAX_SCRIPT_STEPS];
+        assert_eq!(eval(&witness, &lock_script, MESSAGE), Err(Error::ScriptLimitExceeded));
+        // Within the limit the same script succeeds
+        let lock_script = vec![ScriptOp::Dup; MAX_SCRIPT_STEPS - 1];
+        assert_eq!(eval(&witness, &lock_script, MESSAGE), Ok(()));
+    }
+}
@@ -1,6 +1,9 @@
 /// Default fee for making a transaction (ex. transfer or staking balance)
 pub const FEE: u64 = 3;
 
+/// The minimum fee a transaction has to pay to be accepted into the mempool
+pub const MIN_FEE: Capacity = FEE;
+
 /// The capacity of a particular cell (size in bytes).
 pub type Capacity = u64;
 
@@ -9,3 +12,10 @@ pub type PublicKeyHash = [u8; 32];
 
 /// The hash of a cell.
 pub type CellHash = [u8; 32];
+
+/// Identifies a network, mixed into the signatures of cells so that they can't be replayed on
+/// other networks (see [signing_message][crate::cell::input::signing_message]).
+pub type ChainId = u8;
+
+/// The chain id of the built-in network.
+pub const DEFAULT_CHAIN_ID: ChainId = 0;
@@ -0,0 +1,115 @@
+//! Signature schemes for unlocking outputs
+//!
+//! The `lock` of an [Output][super::output::Output] is the hash of the public key of its owner,
+//! and its `scheme` tells which [SignatureScheme] the key belongs to. Inputs carry an
+//! [UnlockScript] of the same scheme, which dispatches verification to the matching backend.
+//!
+use super::cell_unlock_script::CellUnlockScript;
+use super::types::PublicKeyHash;
+use super::{Error, Result};
+
+use crate::zfx_id::Id;
+
+use ed25519_dalek::{Keypair, Signer, Verifier};
+
+/// The signature schemes supported for the owners of outputs
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum SignatureScheme {
+    Ed25519,
+    /// ECDSA over the secp256k1 curve
+    Secp256k1,
+}
+
+impl Default for SignatureScheme {
+    fn default() -> Self {
+        SignatureScheme::Ed25519
+    }
+}
+
+/// A secp256k1 signature along with the public key of the signer.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Secp256k1Unlock {
+    /// The SEC1 encoded public key
+    #[serde(with = "crate::json::hex_bytes")]
+    pub public_key: Vec<u8>,
+    /// The fixed-size (64 bytes) ECDSA signature
+    #[serde(with = "crate::json::hex_bytes")]
+    pub signature: Vec<u8>,
+}
+
+/// The signature of an input, tagged with its [SignatureScheme].
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum UnlockScript {
+    Ed25519(CellUnlockScript),
+    Secp256k1(Secp256k1Unlock),
+}
+
+impl UnlockScript {
+    /// Signs `message` with an ed25519 `keypair`.
+    pub fn ed25519(keypair: &Keypair, message: &[u8]) -> Self {
+        let signature = keypair.sign(message);
+        UnlockScript::Ed25519(CellUnlockScript::new(keypair.public.clone(), signature))
+    }
+
+    /// Signs `message` with a secp256k1 `signing_key`.
+    pub fn secp256k1(signing_key: &k256::ecdsa::SigningKey, message: &[u8]) -> Self {
+        UnlockScript::Secp256k1(secp256k1_backend::sign(signing_key, message))
+    }
+
+    /// Returns the declared scheme of the signature.
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            UnlockScript::Ed25519(_) => SignatureScheme::Ed25519,
+            UnlockScript::Secp256k1(_) => SignatureScheme::Secp256k1,
+        }
+    }
+
+    /// Returns the hash of the public key of the signer, the `lock` of the outputs it can spend.
+    pub fn public_key_hash(&self) -> PublicKeyHash {
+        match self {
+            UnlockScript::Ed25519(unlock) => Id::from_public_key(&unlock.public_key).bytes(),
+            UnlockScript::Secp256k1(unlock) => {
+                Id::from_secp256k1_public_key(&unlock.public_key).bytes()
+            }
+        }
+    }
+
+    /// Verifies the signature of `message`.
+    ///
+    /// Throws [Error::InvalidUnlock] if the signature is invalid.
+    pub fn verify(&self, message: &[u8]) -> Result<()> {
+        match self {
+            UnlockScript::Ed25519(unlock) => unlock
+                .public_key
+                .verify(message, &unlock.signature)
+                .map_err(|_| Error::InvalidUnlock),
+            UnlockScript::Secp256k1(unlock) => secp256k1_backend::verify(unlock, message),
+        }
+    }
+}
+
+mod secp256k1_backend {
+    use super::*;
+
+    use k256::ecdsa::signature::{Signer, Verifier};
+    use k256::ecdsa::{Signature, SigningKey, VerifyingKey};
+    use k256::elliptic_curve::sec1::ToEncodedPoint;
+
+    use std::convert::TryFrom;
+
+    pub fn sign(signing_key: &SigningKey, message: &[u8]) -> Secp256k1Unlock {
+        let signature: Signature = signing_key.sign(message);
+        Secp256k1Unlock {
+            public_key: signing_key.verifying_key().to_encoded_point(true).as_bytes().to_vec(),
+            signature: signature.as_ref().to_vec(),
+        }
+    }
+
+    pub fn verify(unlock: &Secp256k1Unlock, message: &[u8]) -> Result<()> {
+        let public_key =
+            VerifyingKey::from_sec1_bytes(&unlock.public_key).map_err(|_| Error::InvalidUnlock)?;
+        let signature =
+            Signature::try_from(&unlock.signature[..]).map_err(|_| Error::InvalidUnlock)?;
+        public_key.verify(message, &signature).map_err(|_| Error::InvalidUnlock)
+    }
+}
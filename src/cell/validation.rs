@@ -0,0 +1,396 @@
+use super::cell::Cell;
+use super::cell_type::CellType;
+use super::input::signing_message;
+use super::inputs::Input;
+use super::multisig::MultisigLock;
+use super::output::Output;
+use super::script::{self, Script};
+use super::types::{Capacity, ChainId};
+use super::unlock_script::UnlockScript;
+use super::{Error, Result};
+
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+
+/// Checks that `cell` is a well-formed transaction, which can be broadcast on the network
+/// `chain_id`, and returns the fee it pays.
+///
+/// `resolve_input` returns the [Output] spent by an input, or `None` if it is unknown.
+///
+/// Throws the following errors:
+/// * [Error::InvalidCoinbase] - if the cell has coinbase outputs, which aren't transactions
+/// * [Error::ZeroTransfer] - if an output has zero capacity
+/// * [Error::UndefinedInput] - if an input can't be resolved
+/// * [Error::InvalidUnlock], [Error::MultisigThresholdNotMet], [Error::ScriptFailed] - if an
+///   input isn't signed by the owner(s) of the spent output for `chain_id`
+/// * [Error::SchemeMismatch] - if an input is signed in another scheme than the spent output's
+/// * [Error::InvalidSignature] - if an input carries another invalid signature, as refused in
+///   blocks by [verify_signatures]
+/// * [Error::ExceedsAvailableFunds] - if the outputs exceed the capacity of the inputs
+/// * [Error::InvalidSlash] - if the cell has a [slash output][CellType::Slash] but isn't a slash
+///   cell, without inputs and with the slash output only, or records invalid evidence
+///
+/// Slash cells only record evidence and pay no fee.
+pub fn validate(
+    cell: &Cell,
+    chain_id: ChainId,
+    resolve_input: impl Fn(&Input) -> Option<Output>,
+) -> Result<Capacity> {
+    let outputs = cell.outputs();
+    if outputs.iter().any(|o| o.cell_type == CellType::Slash) {
+        if !cell.inputs().is_empty() || outputs.len() != 1 {
+            return Err(Error::InvalidSlash);
+        }
+        let () = outputs[0].validate_data()?;
+        return Ok(0);
+    }
+    for output in cell.outputs().iter() {
+        if output.cell_type == CellType::Coinbase {
+            return Err(Error::InvalidCoinbase);
+        }
+        if output.capacity == 0 {
+            return Err(Error::ZeroTransfer);
+        }
+        let () = output.validate_capacity()?;
+        let () = output.validate_data()?;
+    }
+    for input in cell.inputs().iter() {
+        let output = resolve_input(input).ok_or(Error::UndefinedInput)?;
+        let () = validate_unlock(&output, input, chain_id)?;
+    }
+    // The signatures which don't unlock the spent outputs, such as those of the inputs spending
+    // scripts, have to be valid too, so that the blocks including the cell are accepted
+    let () = verify_signatures(std::slice::from_ref(cell), chain_id)?;
+    cell.fee(resolve_input)
+}
+
+/// Checks that `input` carries the signatures required to spend `output`: a valid signature
+/// of the owner, for [CellType::Multisig] outputs, valid signatures of at least
+/// `threshold` distinct owners, or for [CellType::Script] outputs, a witness satisfying
+/// the lock script.
+///
+/// Signatures are made over the [signing_message] of `chain_id`, so that they can't be
+/// replayed on another network. The owner's signature must be of the
+/// [scheme](super::SignatureScheme) of the output, otherwise [Error::SchemeMismatch] is thrown.
+pub fn validate_unlock(output: &Output, input: &Input, chain_id: ChainId) -> Result<()> {
+    let message = signing_message(chain_id, &input.cell_id()?);
+    match output.cell_type {
+        CellType::Multisig => {
+            let lock: MultisigLock = bincode::deserialize(&output.data)?;
+            if lock.hash()? != output.lock {
+                return Err(Error::InvalidMultisig);
+            }
+            lock.verify(&message, &input.unlocks())
+        }
+        CellType::Script => {
+            let lock_script: Script = bincode::deserialize(&output.data)?;
+            if script::script_hash(&lock_script)? != output.lock {
+                return Err(Error::ScriptFailed);
+            }
+            script::eval(&input.witness, &lock_script, &message)
+        }
+        // Slash outputs only record evidence and can't be spent.
+        CellType::Slash => Err(Error::InvalidSlash),
+        _ => {
+            let unlock = &input.unlock;
+            if unlock.scheme() != output.scheme {
+                return Err(Error::SchemeMismatch);
+            }
+            if unlock.public_key_hash() != output.lock {
+                return Err(Error::InvalidUnlock);
+            }
+            unlock.verify(&message)
+        }
+    }
+}
+
+/// Checks the signatures carried by the inputs of all `cells` for the network `chain_id`.
+///
+/// The ed25519 signatures are first verified as a single batch, which is considerably cheaper
+/// than verifying them one by one. Only if the batch fails are they verified individually, to
+/// find the offending cell. Signatures of other schemes are always verified individually.
+///
+/// Note that this doesn't check that the signers own the spent outputs, see [validate_unlock].
+///
+/// Throws [Error::InvalidSignature] with the hash of the first cell carrying an invalid
+/// signature.
+pub fn verify_signatures(cells: &[Cell], chain_id: ChainId) -> Result<()> {
+    let mut signed = vec![];
+    for cell in cells.iter() {
+        for input in cell.inputs().iter() {
+            let message = signing_message(chain_id, &input.cell_id()?);
+            for unlock in input.unlocks() {
+                match unlock {
+                    UnlockScript::Ed25519(unlock) => {
+                        signed.push((cell.hash(), message.clone(), unlock))
+                    }
+                    unlock => match unlock.verify(&message) {
+                        Err(Error::InvalidUnlock) => {
+                            return Err(Error::InvalidSignature(cell.hash()))
+                        }
+                        result => result?,
+                    },
+                }
+            }
+        }
+    }
+    if signed.is_empty() {
+        return Ok(());
+    }
+
+    let messages: Vec<&[u8]> = signed.iter().map(|(_, message, _)| &message[..]).collect();
+    let signatures: Vec<Signature> = signed.iter().map(|(_, _, u)| u.signature).collect();
+    let public_keys: Vec<PublicKey> = signed.iter().map(|(_, _, u)| u.public_key).collect();
+    if ed25519_dalek::verify_batch(&messages, &signatures, &public_keys).is_ok() {
+        return Ok(());
+    }
+
+    for (cell_hash, message, unlock) in signed.iter() {
+        if unlock.public_key.verify(message, &unlock.signature).is_err() {
+            return Err(Error::InvalidSignature(cell_hash.clone()));
+        }
+    }
+    // The batch equation is stricter than single verification, which has the final say
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::alpha::coinbase::CoinbaseOperation;
+    use crate::alpha::stake::StakeState;
+    use crate::alpha::transfer::{transfer_output, TransferOperation};
+    use crate::cell::inputs::Inputs;
+    use crate::cell::outputs::Outputs;
+    use crate::cell::types::{DEFAULT_CHAIN_ID, FEE};
+    use crate::cell::{Secp256k1Unlock, SignatureScheme};
+    use crate::zfx_id::Id;
+
+    use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
+
+    use std::collections::HashMap;
+    use std::convert::TryInto;
+
+    fn generate_keypair() -> (Keypair, [u8; 32]) {
+        let mut csprng = OsRng {};
+        let keypair = Keypair::generate(&mut csprng);
+        let pkh = Id::from_public_key(&keypair.public).bytes();
+        (keypair, pkh)
+    }
+
+    /// Resolves the inputs spending the outputs of `cell`
+    fn resolver(cell: &Cell) -> impl Fn(&Input) -> Option<Output> {
+        let mut outputs = HashMap::new();
+        for (i, output) in cell.outputs().iter().enumerate() {
+            let _ = outputs.insert((cell.hash(), i as u8), output.clone());
+        }
+        move |input: &Input| {
+            outputs.get(&(input.output_index.cell_hash, input.output_index.index)).cloned()
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_validate_transfer() {
+        let (kp1, pkh1) = generate_keypair();
+        let (_kp2, pkh2) = generate_keypair();
+        let genesis: Cell = CoinbaseOperation::new(vec![(pkh1, 1000)]).try_into().unwrap();
+        let transfer = TransferOperation::new(genesis.clone(), pkh2, pkh1, 100);
+        let cell = transfer.transfer(&kp1).unwrap();
+        assert_eq!(validate(&cell, DEFAULT_CHAIN_ID, resolver(&genesis)), Ok(FEE));
+        // Only cells spending known outputs are valid
+        assert_eq!(validate(&cell, DEFAULT_CHAIN_ID, |_| None), Err(Error::UndefinedInput));
+        assert_eq!(
+            validate(&genesis, DEFAULT_CHAIN_ID, resolver(&genesis)),
+            Err(Error::InvalidCoinbase)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_validate_zero_transfer() {
+        let (kp1, pkh1) = generate_keypair();
+        let genesis: Cell = CoinbaseOperation::new(vec![(pkh1, 1000)]).try_into().unwrap();
+        let input = Input::new(&kp1, genesis.hash(), 0).unwrap();
+        let outputs = vec![transfer_output(pkh1, 0).unwrap()];
+        let cell = Cell::new(Inputs::new(vec![input]), Outputs::new(outputs));
+        assert_eq!(validate(&cell, DEFAULT_CHAIN_ID, resolver(&genesis)), Err(Error::ZeroTransfer));
+    }
+
+    #[actix_rt::test]
+    async fn test_validate_empty_staking_period() {
+        let (kp1, pkh1) = generate_keypair();
+        let genesis: Cell = CoinbaseOperation::new(vec![(pkh1, 1000)]).try_into().unwrap();
+        let stake_cell = |start_height, end_height| {
+            let input = Input::new(&kp1, genesis.hash(), 0).unwrap();
+            let state = StakeState { node_id: Id::one(), start_height, end_height };
+            let output = Output {
+                capacity: 1000 - FEE,
+                cell_type: CellType::Stake,
+                data: bincode::serialize(&state).unwrap(),
+                lock: pkh1,
+                locktime: None,
+                scheme: SignatureScheme::Ed25519,
+            };
+            Cell::new(Inputs::new(vec![input]), Outputs::new(vec![output]))
+        };
+        assert_eq!(validate(&stake_cell(5, 10), DEFAULT_CHAIN_ID, resolver(&genesis)), Ok(FEE));
+        assert_eq!(
+            validate(&stake_cell(10, 10), DEFAULT_CHAIN_ID, resolver(&genesis)),
+            Err(Error::InvalidStake)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_validate_unbalanced() {
+        let (kp1, pkh1) = generate_keypair();
+        let genesis: Cell = CoinbaseOperation::new(vec![(pkh1, 1000)]).try_into().unwrap();
+        let input = Input::new(&kp1, genesis.hash(), 0).unwrap();
+        let outputs = vec![transfer_output(pkh1, 1001).unwrap()];
+        let cell = Cell::new(Inputs::new(vec![input]), Outputs::new(outputs));
+        assert_eq!(
+            validate(&cell, DEFAULT_CHAIN_ID, resolver(&genesis)),
+            Err(Error::ExceedsAvailableFunds)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_validate_bad_signature() {
+        let (_kp1, pkh1) = generate_keypair();
+        let (kp2, pkh2) = generate_keypair();
+        let genesis: Cell = CoinbaseOperation::new(vec![(pkh1, 1000)]).try_into().unwrap();
+        // The output of `pkh1` is signed by `kp2`
+        let input = Input::new(&kp2, genesis.hash(), 0).unwrap();
+        let outputs = vec![transfer_output(pkh2, 900).unwrap()];
+        let cell = Cell::new(Inputs::new(vec![input]), Outputs::new(outputs));
+        assert_eq!(
+            validate(&cell, DEFAULT_CHAIN_ID, resolver(&genesis)),
+            Err(Error::InvalidUnlock)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_validate_other_chain() {
+        let (kp1, pkh1) = generate_keypair();
+        let (_kp2, pkh2) = generate_keypair();
+        let genesis: Cell = CoinbaseOperation::new(vec![(pkh1, 1000)]).try_into().unwrap();
+        let transfer = TransferOperation::new(genesis.clone(), pkh2, pkh1, 100).with_chain_id(1);
+        let cell = transfer.transfer(&kp1).unwrap();
+        assert_eq!(validate(&cell, 1, resolver(&genesis)), Ok(FEE));
+        // The signatures made for chain 1 can't be replayed on chain 2
+        assert_eq!(validate(&cell, 2, resolver(&genesis)), Err(Error::InvalidUnlock));
+        assert_eq!(
+            validate(&cell, DEFAULT_CHAIN_ID, resolver(&genesis)),
+            Err(Error::InvalidUnlock)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_validate_ed25519_scheme() {
+        let (kp1, pkh1) = generate_keypair();
+        let (_kp2, pkh2) = generate_keypair();
+        let genesis: Cell = CoinbaseOperation::new(vec![(pkh1, 1000)]).try_into().unwrap();
+        assert_eq!(genesis.outputs()[0].scheme, SignatureScheme::Ed25519);
+        let cell = TransferOperation::new(genesis.clone(), pkh2, pkh1, 100).transfer(&kp1).unwrap();
+        let input = cell.inputs().iter().next().unwrap().clone();
+        assert_eq!(input.unlock.scheme(), SignatureScheme::Ed25519);
+        assert_eq!(input.unlock.public_key_hash(), pkh1);
+        assert_eq!(validate(&cell, DEFAULT_CHAIN_ID, resolver(&genesis)), Ok(FEE));
+        assert_eq!(verify_signatures(&[cell], DEFAULT_CHAIN_ID), Ok(()));
+    }
+
+    #[actix_rt::test]
+    async fn test_validate_scheme_mismatch() {
+        let (kp1, pkh1) = generate_keypair();
+        // The key hash matches, but the output expects a secp256k1 signature
+        let output = transfer_output(pkh1, 1000).unwrap().with_scheme(SignatureScheme::Secp256k1);
+        let funding = Cell::new(Inputs::new(vec![]), Outputs::new(vec![output]));
+        let input = Input::new(&kp1, funding.hash(), 0).unwrap();
+        let outputs = vec![transfer_output(pkh1, 900).unwrap()];
+        let cell = Cell::new(Inputs::new(vec![input.clone()]), Outputs::new(outputs.clone()));
+        assert_eq!(
+            validate(&cell, DEFAULT_CHAIN_ID, resolver(&funding)),
+            Err(Error::SchemeMismatch)
+        );
+
+        // A secp256k1 signature can't spend an ed25519 output
+        let genesis: Cell = CoinbaseOperation::new(vec![(pkh1, 1000)]).try_into().unwrap();
+        let mut input = Input::new(&kp1, genesis.hash(), 0).unwrap();
+        input.unlock = UnlockScript::Secp256k1(Secp256k1Unlock {
+            public_key: vec![2; 33],
+            signature: vec![0; 64],
+        });
+        let cell = Cell::new(Inputs::new(vec![input]), Outputs::new(outputs));
+        assert_eq!(
+            validate(&cell, DEFAULT_CHAIN_ID, resolver(&genesis)),
+            Err(Error::SchemeMismatch)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_validate_secp256k1_scheme() {
+        use k256::elliptic_curve::sec1::ToEncodedPoint;
+
+        let signing_key = k256::ecdsa::SigningKey::from_bytes(&rand::random::<[u8; 32]>()).unwrap();
+        let public_key = signing_key.verifying_key().to_encoded_point(true);
+        let pkh = Id::from_secp256k1_public_key(public_key.as_bytes()).bytes();
+        let output = transfer_output(pkh, 1000).unwrap().with_scheme(SignatureScheme::Secp256k1);
+        let funding = Cell::new(Inputs::new(vec![]), Outputs::new(vec![output]));
+
+        let input =
+            Input::new_secp256k1_on_chain(DEFAULT_CHAIN_ID, &signing_key, funding.hash(), 0)
+                .unwrap();
+        assert_eq!(input.unlock.scheme(), SignatureScheme::Secp256k1);
+        let (_kp2, pkh2) = generate_keypair();
+        let outputs = vec![transfer_output(pkh2, 900).unwrap()];
+        let cell = Cell::new(Inputs::new(vec![input]), Outputs::new(outputs.clone()));
+        assert_eq!(validate(&cell, DEFAULT_CHAIN_ID, resolver(&funding)), Ok(100));
+        assert_eq!(verify_signatures(&[cell.clone()], DEFAULT_CHAIN_ID), Ok(()));
+        // The signature is bound to the chain
+        assert_eq!(validate(&cell, 1, resolver(&funding)), Err(Error::InvalidUnlock));
+        assert_eq!(
+            verify_signatures(&[cell.clone()], 1),
+            Err(Error::InvalidSignature(cell.hash()))
+        );
+
+        // Another secp256k1 key can't spend the output
+        let other_key = k256::ecdsa::SigningKey::from_bytes(&rand::random::<[u8; 32]>()).unwrap();
+        let input =
+            Input::new_secp256k1_on_chain(DEFAULT_CHAIN_ID, &other_key, funding.hash(), 0).unwrap();
+        let cell = Cell::new(Inputs::new(vec![input]), Outputs::new(outputs));
+        assert_eq!(
+            validate(&cell, DEFAULT_CHAIN_ID, resolver(&funding)),
+            Err(Error::InvalidUnlock)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_validate_script_unlock() {
+        let (kp1, pkh1) = generate_keypair();
+        let preimage = b"secret".to_vec();
+        let lock_script = vec![
+            script::ScriptOp::Hash,
+            script::ScriptOp::Push(blake3::hash(&preimage).as_bytes().to_vec()),
+            script::ScriptOp::EqualVerify,
+            script::ScriptOp::Push(vec![1]),
+        ];
+        let output = script::script_output(&lock_script, 1000).unwrap();
+        let funding = Cell::new(Inputs::new(vec![]), Outputs::new(vec![output]));
+
+        let input = Input::new(&kp1, funding.hash(), 0).unwrap();
+        let outputs = vec![transfer_output(pkh1, 1000 - FEE).unwrap()];
+        let cell = Cell::new(Inputs::new(vec![input.clone()]), Outputs::new(outputs.clone()));
+        assert_eq!(validate(&cell, DEFAULT_CHAIN_ID, resolver(&funding)), Err(Error::ScriptFailed));
+
+        let input = input.with_witness(vec![script::ScriptOp::Push(preimage)]);
+        let cell = Cell::new(Inputs::new(vec![input.clone()]), Outputs::new(outputs.clone()));
+        assert_eq!(validate(&cell, DEFAULT_CHAIN_ID, resolver(&funding)), Ok(FEE));
+
+        // The signature of the input isn't needed by the script, but is checked like in blocks
+        let mut input = input;
+        input.unlock = UnlockScript::ed25519(&kp1, b"another message");
+        let cell = Cell::new(Inputs::new(vec![input]), Outputs::new(outputs));
+        let invalid = || Error::InvalidSignature(cell.hash());
+        assert_eq!(validate(&cell, DEFAULT_CHAIN_ID, resolver(&funding)), Err(invalid()));
+        assert_eq!(verify_signatures(&[cell.clone()], DEFAULT_CHAIN_ID), Err(invalid()));
+    }
+}
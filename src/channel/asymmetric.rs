@@ -1,12 +1,15 @@
 use futures::prelude::*;
 use serde::{Deserialize, Serialize};
 use tokio::io::{ReadHalf, WriteHalf};
-use tokio_serde::formats::*;
 use tokio_serde::Framed;
-use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
+use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec, LengthDelimitedCodecError};
 
+use super::codec::{Codec, DEFAULT_COMPRESSION_THRESHOLD, DEFAULT_MAX_FRAME_BYTES};
 use crate::tls::connection_stream::ConnectionStream;
 
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
 #[derive(Debug)]
 pub enum Error<I, O>
 where
@@ -16,13 +19,15 @@ where
     IO(std::io::Error),
     ReadError(<Reader<I, O> as futures::TryStream>::Error),
     WriteError(<Writer<I, O> as futures::Sink<I>>::Error),
+    /// The peer announced a frame above the maximum frame size of the channel
+    FrameTooLarge,
 }
 
 pub type Reader<I, O> =
-    Framed<FramedRead<ReadHalf<ConnectionStream>, LengthDelimitedCodec>, O, I, Bincode<O, I>>;
+    Framed<FramedRead<ReadHalf<ConnectionStream>, LengthDelimitedCodec>, O, I, Codec<O, I>>;
 
 pub type Writer<I, O> =
-    Framed<FramedWrite<WriteHalf<ConnectionStream>, LengthDelimitedCodec>, O, I, Bincode<O, I>>;
+    Framed<FramedWrite<WriteHalf<ConnectionStream>, LengthDelimitedCodec>, O, I, Codec<O, I>>;
 
 pub struct Receiver<I, O> {
     reader: Reader<I, O>,
@@ -32,15 +37,25 @@ impl<I, O> Receiver<I, O>
 where
     I: for<'de> Deserialize<'de> + Serialize,
     O: for<'de> Deserialize<'de> + Serialize,
-    Reader<I, O>: TryStream<Ok = O> + Unpin,
+    Reader<I, O>: TryStream<Ok = O, Error = std::io::Error> + Unpin,
 {
     pub async fn recv(&mut self) -> Result<Option<O>, Error<I, O>> {
-        Ok(self.reader.try_next().await.map_err(Error::ReadError)?)
+        // The announced length is checked before the frame is buffered
+        self.reader.try_next().await.map_err(|err| {
+            match err.get_ref().map(|inner| inner.is::<LengthDelimitedCodecError>()) {
+                Some(true) => Error::FrameTooLarge,
+                _ => Error::ReadError(err),
+            }
+        })
     }
 }
 
 pub struct Sender<I, O> {
     writer: Writer<I, O>,
+    /// Whether this side announces compression, shared with the codecs
+    accepts_compression: Arc<AtomicBool>,
+    /// Whether the peer accepts compression, shared with the codecs
+    peer_accepts_compression: Arc<AtomicBool>,
 }
 
 impl<I, O> Sender<I, O>
@@ -52,10 +67,23 @@ where
     pub async fn send(&mut self, item: I) -> Result<(), Error<I, O>> {
         Ok(self.writer.send(item).await.map_err(Error::WriteError)?)
     }
+
+    /// Compresses the frames to a peer which negotiated compression in the version handshake,
+    /// and announces it, see [codec][super::codec].
+    pub fn enable_compression(&self) {
+        self.accepts_compression.store(true, Ordering::Relaxed);
+        self.peer_accepts_compression.store(true, Ordering::Relaxed);
+    }
 }
 
 pub struct Channel<I, O> {
     socket: Option<ConnectionStream>,
+    /// Payloads above this size are compressed if the peer accepts it, see [codec][super::codec].
+    compression_threshold: Option<usize>,
+    /// Whether this side announces that it accepts compression from the start
+    accepts_compression: bool,
+    /// Frames above this size (in bytes) are rejected without reading them.
+    max_frame_bytes: usize,
     ghost: std::marker::PhantomData<(I, O)>,
 }
 
@@ -69,21 +97,67 @@ where
     //    }
 
     pub fn wrap(socket: ConnectionStream) -> Result<Channel<I, O>, Error<I, O>> {
-        Ok(Channel { socket: Some(socket), ghost: Default::default() })
+        Ok(Channel {
+            socket: Some(socket),
+            compression_threshold: Some(DEFAULT_COMPRESSION_THRESHOLD),
+            accepts_compression: false,
+            max_frame_bytes: DEFAULT_MAX_FRAME_BYTES,
+            ghost: Default::default(),
+        })
+    }
+
+    /// Compress payloads above `threshold`, or never compress (nor accept compression) if `None`.
+    pub fn with_compression_threshold(mut self, threshold: Option<usize>) -> Self {
+        self.compression_threshold = threshold;
+        self
+    }
+
+    /// Announce that this side accepts compression, so that a peer which announces it as well
+    /// compresses its frames. Only the side answering requests announces it to any peer, the
+    /// requesting side [enables][Sender::enable_compression] it once negotiated.
+    pub fn with_compression(mut self, accepts_compression: bool) -> Self {
+        self.accepts_compression = accepts_compression;
+        self
+    }
+
+    /// Reject frames above `max_frame_bytes`, and decompressed payloads above it.
+    pub fn with_max_frame_bytes(mut self, max_frame_bytes: usize) -> Self {
+        self.max_frame_bytes = max_frame_bytes;
+        self
     }
 
     pub fn split(&mut self) -> (Sender<I, O>, Receiver<I, O>) {
         let (reader, writer) = tokio::io::split(self.socket.take().unwrap());
 
+        let framing =
+            LengthDelimitedCodec::builder().max_frame_length(self.max_frame_bytes).new_codec();
         let reader: FramedRead<ReadHalf<_>, LengthDelimitedCodec> =
-            FramedRead::new(reader, LengthDelimitedCodec::new());
-        let reader = Framed::new(reader, Bincode::default());
+            FramedRead::new(reader, framing.clone());
+        let accepts_compression = Arc::new(AtomicBool::new(self.accepts_compression));
+        let peer_accepts_compression = Arc::new(AtomicBool::new(false));
+        let reader = Framed::new(
+            reader,
+            Codec::new(
+                self.compression_threshold,
+                accepts_compression.clone(),
+                peer_accepts_compression.clone(),
+                self.max_frame_bytes,
+            ),
+        );
 
         let writer: FramedWrite<WriteHalf<_>, LengthDelimitedCodec> =
-            FramedWrite::new(writer, LengthDelimitedCodec::new());
-        let writer = Framed::new(writer, Bincode::default());
+            FramedWrite::new(writer, framing);
+        let writer = Framed::new(
+            writer,
+            Codec::new(
+                self.compression_threshold,
+                accepts_compression.clone(),
+                peer_accepts_compression.clone(),
+                self.max_frame_bytes,
+            ),
+        );
 
-        (Sender { writer }, Receiver { reader })
+        (Sender { writer, accepts_compression, peer_accepts_compression }, Receiver { reader })
     }
 }
 
@@ -160,4 +234,30 @@ mod tests {
         handle_2.await.unwrap();
         handle_1.await.unwrap();
     }
+
+    #[actix_rt::test]
+    async fn reject_oversized_frame() {
+        use tokio::io::AsyncWriteExt;
+
+        let address: SocketAddr = "127.0.0.1:20001".parse().expect("failed to construct address");
+        let listener = TcpListener::bind(&address).await.unwrap();
+
+        let handle = tokio::spawn(async move {
+            let mut socket = TcpStream::connect(&address).await.unwrap();
+            // Announce a 4GiB frame, without sending it
+            socket.write_all(&u32::MAX.to_be_bytes()).await.unwrap();
+            socket.write_all(&[0u8; 16]).await.unwrap();
+            // Keep the connection open, so that the reader can't fail on a closed socket
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+        });
+
+        let (socket, _address) = listener.accept().await.unwrap();
+        let socket = TcpUpgrader::new().upgrade(socket).await.unwrap();
+        let mut channel: Channel<String, String> =
+            Channel::wrap(socket).unwrap().with_max_frame_bytes(1024);
+        let (_sender, mut receiver) = channel.split();
+        let result = tokio::time::timeout(std::time::Duration::from_secs(1), receiver.recv()).await;
+        assert!(matches!(result, Ok(Err(Error::FrameTooLarge))));
+        handle.abort();
+    }
 }
@@ -0,0 +1,232 @@
+//! Bincode codec of the channel with optional lz4 compression
+//!
+//! Each frame starts with a flag byte. [COMPRESSED] marks an lz4 compressed payload, and
+//! [ACCEPTS_COMPRESSION] tells the receiver that the sender can read compressed frames. A sender
+//! only compresses once both sides have announced that they accept compression, and only payloads
+//! above the compression threshold, so that small messages (like pings) go without overhead.
+//!
+//! Compression is negotiated in the version handshake, see
+//! [Capabilities::COMPRESSION][crate::version::Capabilities::COMPRESSION]. The client side of a
+//! connection only announces it to peers which advertised it, and the announcement of a peer is
+//! ignored by a side which didn't announce it itself.
+use bincode::Options;
+use bytes::{Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use tokio_serde::{Deserializer, Serializer};
+
+use std::io;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// The payload of the frame is lz4 compressed.
+pub const COMPRESSED: u8 = 0b01;
+/// The sender of the frame can read compressed frames.
+pub const ACCEPTS_COMPRESSION: u8 = 0b10;
+
+/// Payloads above this size (in bytes) are compressed by default.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024;
+/// The default maximum size of a frame, and of a decompressed payload.
+pub const DEFAULT_MAX_FRAME_BYTES: usize = 8 * 1024 * 1024;
+
+/// Codec serializing `SinkItem`s and deserializing `Item`s with the flag byte described in the
+/// [module documentation][self].
+pub struct Codec<Item, SinkItem> {
+    /// Payloads above this size are compressed, if set.
+    compression_threshold: Option<usize>,
+    /// Whether this side announces that it accepts compressed frames, shared by the reader and
+    /// the writer.
+    accepts_compression: Arc<AtomicBool>,
+    /// Whether the peer accepts compressed frames, shared by the reader and the writer.
+    peer_accepts_compression: Arc<AtomicBool>,
+    /// The maximum size of a decompressed payload.
+    max_payload_bytes: usize,
+    ghost: PhantomData<(Item, SinkItem)>,
+}
+
+impl<Item, SinkItem> Codec<Item, SinkItem> {
+    pub fn new(
+        compression_threshold: Option<usize>,
+        accepts_compression: Arc<AtomicBool>,
+        peer_accepts_compression: Arc<AtomicBool>,
+        max_payload_bytes: usize,
+    ) -> Self {
+        Codec {
+            compression_threshold,
+            accepts_compression,
+            peer_accepts_compression,
+            max_payload_bytes,
+            ghost: PhantomData,
+        }
+    }
+
+    /// Whether this side reads compressed frames.
+    fn accepts(&self) -> bool {
+        self.compression_threshold.is_some() && self.accepts_compression.load(Ordering::Relaxed)
+    }
+
+    fn options() -> impl Options {
+        // Same encoding as the `tokio_serde` bincode codec
+        bincode::DefaultOptions::new()
+    }
+}
+
+impl<Item, SinkItem> Serializer<SinkItem> for Codec<Item, SinkItem>
+where
+    SinkItem: Serialize,
+{
+    type Error = io::Error;
+
+    fn serialize(self: Pin<&mut Self>, item: &SinkItem) -> Result<Bytes, Self::Error> {
+        let payload = Self::options()
+            .serialize(item)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut flags = if self.accepts() { ACCEPTS_COMPRESSION } else { 0 };
+        let compress = self.accepts()
+            && self.peer_accepts_compression.load(Ordering::Relaxed)
+            && self.compression_threshold.map_or(false, |threshold| payload.len() > threshold);
+        let payload = if compress {
+            flags |= COMPRESSED;
+            lz4_flex::compress_prepend_size(&payload)
+        } else {
+            payload
+        };
+        let mut frame = Vec::with_capacity(payload.len() + 1);
+        frame.push(flags);
+        frame.extend_from_slice(&payload);
+        Ok(frame.into())
+    }
+}
+
+impl<Item, SinkItem> Deserializer<Item> for Codec<Item, SinkItem>
+where
+    for<'a> Item: Deserialize<'a>,
+{
+    type Error = io::Error;
+
+    fn deserialize(self: Pin<&mut Self>, src: &BytesMut) -> Result<Item, Self::Error> {
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+        let (flags, payload) = match src.split_first() {
+            Some((flags, payload)) => (*flags, payload),
+            None => return Err(invalid("empty frame".to_owned())),
+        };
+        if flags & !(COMPRESSED | ACCEPTS_COMPRESSION) != 0 {
+            return Err(invalid(format!("unknown frame flags {:#b}", flags)));
+        }
+        let accepts = self.accepts();
+        if flags & ACCEPTS_COMPRESSION != 0 && accepts {
+            self.peer_accepts_compression.store(true, Ordering::Relaxed);
+        }
+        if flags & COMPRESSED != 0 {
+            if !accepts {
+                return Err(invalid("unsolicited compressed frame".to_owned()));
+            }
+            // The decompressed size is prepended as a little endian u32
+            let size = match payload.get(..4) {
+                Some(size) => u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize,
+                None => return Err(invalid("truncated compressed frame".to_owned())),
+            };
+            if size > self.max_payload_bytes {
+                return Err(invalid(format!("decompressed frame too large: {}", size)));
+            }
+            let payload = lz4_flex::decompress_size_prepended(payload)
+                .map_err(|e| invalid(format!("{}", e)))?;
+            Self::options().deserialize(&payload).map_err(|e| invalid(format!("{}", e)))
+        } else {
+            Self::options().deserialize(payload).map_err(|e| invalid(format!("{}", e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::cell::inputs::Inputs;
+    use crate::cell::outputs::Outputs;
+    use crate::cell::Cell;
+    use crate::sleet::tx::Tx;
+    use crate::sleet::TxAncestors;
+
+    use crate::alpha::transfer::transfer_output;
+
+    fn large_tx_ancestors() -> TxAncestors {
+        let ancestors = (0..200)
+            .map(|i| {
+                let output = transfer_output([i as u8; 32], 1000 + i).unwrap();
+                let cell = Cell::new(Inputs::new(vec![]), Outputs::new(vec![output]));
+                Tx::new(vec![[i as u8; 32]], cell)
+            })
+            .collect();
+        TxAncestors { ancestors, truncated: false }
+    }
+
+    /// A codec compressing above the default threshold, which announces compression if
+    /// `accepts`, and knows whether the peer `peer_accepts` it
+    fn codec<I, O>(accepts: bool, peer_accepts: bool) -> Codec<I, O> {
+        Codec::new(
+            Some(DEFAULT_COMPRESSION_THRESHOLD),
+            Arc::new(AtomicBool::new(accepts)),
+            Arc::new(AtomicBool::new(peer_accepts)),
+            DEFAULT_MAX_FRAME_BYTES,
+        )
+    }
+
+    /// Encodes `item` with a writer codec and decodes it with a reader codec
+    fn round_trip(item: &TxAncestors, peer_accepts: bool) -> (Bytes, TxAncestors) {
+        let mut writer: Codec<(), TxAncestors> = codec(true, peer_accepts);
+        let frame = Pin::new(&mut writer).serialize(item).unwrap();
+        let mut reader: Codec<TxAncestors, ()> = codec(true, false);
+        let decoded = Pin::new(&mut reader).deserialize(&BytesMut::from(&frame[..])).unwrap();
+        (frame, decoded)
+    }
+
+    #[actix_rt::test]
+    async fn test_compressed_round_trip() {
+        let ancestors = large_tx_ancestors();
+        let encoded = bincode::DefaultOptions::new().serialize(&ancestors).unwrap();
+
+        let (frame, decoded) = round_trip(&ancestors, true);
+        assert_eq!(frame[0], ACCEPTS_COMPRESSION | COMPRESSED);
+        assert!(frame.len() < encoded.len());
+        assert_eq!(bincode::DefaultOptions::new().serialize(&decoded).unwrap(), encoded);
+
+        let (frame, decoded) = round_trip(&ancestors, false);
+        assert_eq!(frame[0], ACCEPTS_COMPRESSION);
+        assert_eq!(&frame[1..], &encoded[..]);
+        assert_eq!(bincode::DefaultOptions::new().serialize(&decoded).unwrap(), encoded);
+    }
+
+    #[actix_rt::test]
+    async fn test_small_payloads_are_not_compressed() {
+        let mut writer: Codec<(), String> = codec(true, true);
+        let frame = Pin::new(&mut writer).serialize(&"ping".to_owned()).unwrap();
+        assert_eq!(frame[0], ACCEPTS_COMPRESSION);
+
+        // Reading a frame of the peer records whether it accepts compression
+        let mut reader: Codec<String, ()> = codec(true, false);
+        let _ = Pin::new(&mut reader).deserialize(&BytesMut::from(&frame[..])).unwrap();
+        assert!(reader.peer_accepts_compression.load(Ordering::Relaxed));
+    }
+
+    #[actix_rt::test]
+    async fn test_compression_is_negotiated() {
+        let ancestors = large_tx_ancestors();
+        let encoded = bincode::DefaultOptions::new().serialize(&ancestors).unwrap();
+
+        // A side which didn't negotiate compression neither announces nor uses it
+        let mut writer: Codec<(), TxAncestors> = codec(false, true);
+        let frame = Pin::new(&mut writer).serialize(&ancestors).unwrap();
+        assert_eq!(frame[0], 0);
+        assert_eq!(&frame[1..], &encoded[..]);
+
+        // It ignores the announcement of the peer, and refuses compressed frames
+        let mut peer: Codec<(), TxAncestors> = codec(true, true);
+        let frame = Pin::new(&mut peer).serialize(&ancestors).unwrap();
+        assert_eq!(frame[0], ACCEPTS_COMPRESSION | COMPRESSED);
+        let mut reader: Codec<TxAncestors, ()> = codec(false, false);
+        assert!(Pin::new(&mut reader).deserialize(&BytesMut::from(&frame[..])).is_err());
+        assert!(!reader.peer_accepts_compression.load(Ordering::Relaxed));
+    }
+}
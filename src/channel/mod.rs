@@ -1,4 +1,5 @@
 //! Asymmetric channel for network communication
 mod asymmetric;
+pub mod codec;
 
 pub use asymmetric::*;
@@ -1,8 +1,12 @@
 //! Network client
 
-use crate::channel::Channel;
+use crate::alpha::types::Weight;
+use crate::channel::{Channel, Receiver, Sender};
+use crate::ice;
 use crate::protocol::{Request, Response};
 use crate::tls::upgrader::Upgrader;
+use crate::util::Backoff;
+use crate::version::Capabilities;
 use crate::zfx_id::Id;
 use crate::{Error, Result};
 
@@ -10,10 +14,26 @@ use tracing::{debug, error, warn};
 
 use tokio::net::TcpStream;
 
-use actix::{Actor, Context, Handler, ResponseFuture};
+use actix::{Actor, AsyncContext, Context, Handler, Recipient, ResponseFuture, WrapFuture};
+use futures::stream::{FuturesUnordered, StreamExt};
 use futures::FutureExt;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Idle connections are closed after this duration.
+/// It should be below the idle timeout of the [Server][crate::server::Server].
+pub const CONNECTION_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// The maximum number of idle connections kept to a peer.
+pub const MAX_IDLE_CONNECTIONS_PER_PEER: usize = 4;
+/// The default interval of the heartbeats sent to the peers with idle connections.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+/// The default time a peer has to answer a heartbeat, below [HEARTBEAT_INTERVAL].
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+/// The default number of consecutive missed heartbeats after which a peer is unreachable.
+pub const MAX_MISSED_HEARTBEATS: usize = 3;
 
 /// The client actor
 ///
@@ -21,25 +41,348 @@ use std::sync::Arc;
 /// Its main handler is [ClientRequest] which accepts [ClientRequest::Oneshot] or [ClientRequest::Fanout]
 ///
 /// It is capable of establishing client connections to other nodes, and upgrade the connection to use TLS
-/// if necessary.
+/// if necessary. Connections are kept in a [ConnectionPool] and reused by subsequent requests.
 pub struct Client {
     /// For upgrading a [TcpStream] to a [ConnectionStream](crate::tls::connection_stream::ConnectionStream)
     upgrader: Arc<dyn Upgrader>,
+    /// Idle connections to other nodes
+    pool: ConnectionPool,
+    /// The heartbeats sent on the idle connections, if enabled
+    heartbeat: Option<HeartbeatConfig>,
+    /// Notified of the peers which missed too many heartbeats
+    unreachable_recipients: Vec<Recipient<PeerUnreachable>>,
 }
 
 impl Client {
     /// Creates a new client with an upgrader for the channel
     /// (ex. [TCP](crate::tls::upgrader::TcpUpgrader) or [TLS](crate::tls::upgrader::TlsClientUpgrader))
     pub fn new(upgrader: Arc<dyn Upgrader>) -> Client {
-        Client { upgrader }
+        Client {
+            upgrader,
+            pool: ConnectionPool::new(),
+            heartbeat: None,
+            unreachable_recipients: vec![],
+        }
+    }
+
+    /// Sends heartbeats to the peers with idle connections according to `heartbeat`, and tears
+    /// down the connections of the peers which stop answering them.
+    pub fn with_heartbeat(mut self, heartbeat: HeartbeatConfig) -> Self {
+        self.heartbeat = Some(heartbeat);
+        self
+    }
+
+    /// The connection pool of the client
+    pub fn pool(&self) -> ConnectionPool {
+        self.pool.clone()
+    }
+}
+
+/// The settings of the heartbeats of a [Client]
+///
+/// A heartbeat is an empty [Ping][crate::ice::Ping], sent every `interval` to the peers having
+/// idle connections in the [ConnectionPool]. A peer which doesn't answer `max_missed` consecutive
+/// heartbeats within `timeout` is considered unreachable: its connections are closed and the
+/// subscribers of [SubscribeUnreachable] are notified.
+#[derive(Debug, Clone)]
+pub struct HeartbeatConfig {
+    /// The id of this node, sent with the heartbeats
+    pub node_id: Id,
+    pub interval: Duration,
+    pub timeout: Duration,
+    pub max_missed: usize,
+}
+
+impl HeartbeatConfig {
+    /// The default heartbeat settings for the node `node_id`
+    pub fn new(node_id: Id) -> Self {
+        HeartbeatConfig {
+            node_id,
+            interval: HEARTBEAT_INTERVAL,
+            timeout: HEARTBEAT_TIMEOUT,
+            max_missed: MAX_MISSED_HEARTBEATS,
+        }
+    }
+}
+
+/// Notification of a peer which missed too many heartbeats, see [HeartbeatConfig]
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+pub struct PeerUnreachable {
+    pub id: Id,
+    pub ip: SocketAddr,
+}
+
+/// Subscribes `recipient` to the [PeerUnreachable] notifications of the [Client]
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SubscribeUnreachable {
+    pub recipient: Recipient<PeerUnreachable>,
+}
+
+impl Handler<SubscribeUnreachable> for Client {
+    type Result = ();
+
+    fn handle(&mut self, msg: SubscribeUnreachable, _ctx: &mut Context<Self>) -> Self::Result {
+        self.unreachable_recipients.push(msg.recipient);
+    }
+}
+
+/// An open connection to a node
+struct Connection {
+    sender: Sender<Request, Response>,
+    receiver: Receiver<Request, Response>,
+    last_used: Instant,
+}
+
+impl Connection {
+    async fn request(&mut self, request: Request) -> Result<Option<Response>> {
+        let () = self.send(request).await?;
+        self.receive().await
+    }
+
+    async fn send(&mut self, request: Request) -> Result<()> {
+        let () = self.sender.send(request).await?;
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Option<Response>> {
+        let response = self.receiver.recv().await?;
+        self.last_used = Instant::now();
+        Ok(response)
+    }
+
+    /// Whether the node closed the connection, or sent an unsolicited message, while it was idle
+    fn is_closed(&mut self) -> bool {
+        // Nothing is readable on an open idle connection
+        self.receiver.recv().now_or_never().is_some()
+    }
+
+    /// Sends a request without counting it as a use, so that a connection only kept alive by
+    /// heartbeats still times out when idle
+    async fn exchange(&mut self, request: Request) -> Result<Option<Response>> {
+        let () = self.sender.send(request).await?;
+        let response = self.receiver.recv().await?;
+        Ok(response)
+    }
+}
+
+/// A pool of idle connections to other nodes, keyed by the node `(Id, SocketAddr)`.
+///
+/// A connection is taken out of the pool for the duration of a request, so concurrent requests
+/// to the same node use distinct connections. Connections are evicted on errors and once they
+/// are idle for longer than [CONNECTION_IDLE_TIMEOUT].
+///
+/// Connections to the nodes which advertised [Capabilities::COMPRESSION] in their [VersionAck]
+/// are compressed, see [codec][crate::channel::codec].
+#[derive(Clone)]
+pub struct ConnectionPool {
+    idle: Arc<Mutex<HashMap<(Id, SocketAddr), Vec<Connection>>>>,
+    /// The nodes with which compression was negotiated
+    compression_peers: Arc<Mutex<HashSet<Id>>>,
+    /// The number of consecutive heartbeats missed by the peers which missed the last one
+    missed_heartbeats: Arc<Mutex<HashMap<(Id, SocketAddr), usize>>>,
+    /// The number of connections established by the pool
+    connects: Arc<AtomicUsize>,
+}
+
+impl ConnectionPool {
+    pub fn new() -> Self {
+        ConnectionPool {
+            idle: Arc::new(Mutex::new(HashMap::new())),
+            compression_peers: Arc::new(Mutex::new(HashSet::new())),
+            missed_heartbeats: Arc::new(Mutex::new(HashMap::new())),
+            connects: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// The number of connections (and handshakes) made by the pool so far
+    pub fn connects(&self) -> usize {
+        self.connects.load(Ordering::Relaxed)
+    }
+
+    /// The number of idle connections in the pool
+    pub fn idle_connections(&self) -> usize {
+        self.idle.lock().unwrap().values().map(|connections| connections.len()).sum()
+    }
+
+    /// Takes an idle connection to the node, if any, evicting the timed out ones
+    fn take(&self, id: Id, ip: SocketAddr) -> Option<Connection> {
+        let mut idle = self.idle.lock().unwrap();
+        let connections = idle.get_mut(&(id, ip))?;
+        connections.retain(|connection| connection.last_used.elapsed() < CONNECTION_IDLE_TIMEOUT);
+        connections.pop()
+    }
+
+    /// Connects to the node, with compression if it was negotiated with the node
+    async fn connect(
+        &self,
+        id: Id,
+        ip: SocketAddr,
+        upgrader: Arc<dyn Upgrader>,
+    ) -> Result<Connection> {
+        let connection = connect(id, ip, upgrader).await?;
+        let _ = self.connects.fetch_add(1, Ordering::Relaxed);
+        if self.compression_peers.lock().unwrap().contains(&id) {
+            connection.sender.enable_compression();
+        }
+        Ok(connection)
+    }
+
+    /// Returns a connection to the pool after a successful request
+    fn put(&self, id: Id, ip: SocketAddr, connection: Connection) {
+        let mut idle = self.idle.lock().unwrap();
+        let connections = idle.entry((id, ip)).or_insert(vec![]);
+        if connections.len() < MAX_IDLE_CONNECTIONS_PER_PEER {
+            connections.push(connection);
+        }
+    }
+
+    /// Sends a request on an idle connection to the node or on a new one, and returns the
+    /// connection to the pool if it is still usable.
+    ///
+    /// The request is only sent again on a new connection if the idle connection was closed or
+    /// the request couldn't be written to it. Once written, the node may have processed the
+    /// request, so a missing response is returned as is.
+    pub async fn request(
+        &self,
+        id: Id,
+        ip: SocketAddr,
+        request: Request,
+        upgrader: Arc<dyn Upgrader>,
+    ) -> Result<Option<Response>> {
+        if let Some(mut connection) = self.take(id, ip) {
+            if connection.is_closed() {
+                debug!("evicting closed connection to {}", ip);
+            } else {
+                match connection.send(request.clone()).await {
+                    Ok(()) => return self.receive(id, ip, connection).await,
+                    Err(err) => debug!("evicting connection to {}: {:?}", ip, err),
+                }
+            }
+        }
+        let mut connection = self.connect(id, ip, upgrader).await?;
+        let () = connection.send(request).await?;
+        self.receive(id, ip, connection).await
+    }
+
+    /// Waits for the response on `connection`, and returns the connection to the pool if it is
+    /// still usable.
+    async fn receive(
+        &self,
+        id: Id,
+        ip: SocketAddr,
+        mut connection: Connection,
+    ) -> Result<Option<Response>> {
+        let response = connection.receive().await?;
+        if let Some(Response::VersionAck(ack)) = &response {
+            if Capabilities::current().negotiate(&ack.capabilities).supports_compression() {
+                let _ = self.compression_peers.lock().unwrap().insert(id);
+                connection.sender.enable_compression();
+            }
+        }
+        if response.is_some() {
+            self.put(id, ip, connection);
+        }
+        Ok(response)
+    }
+}
+
+impl ConnectionPool {
+    /// Sends a heartbeat to the peers with idle connections, and to the peers which missed the
+    /// previous heartbeat.
+    ///
+    /// A peer answering the heartbeat within `timeout` keeps its connection. Otherwise the
+    /// connection is closed, as a late answer would be mistaken for the response to the next
+    /// request. Returns the peers which missed `max_missed` consecutive heartbeats, whose idle
+    /// connections are closed.
+    pub async fn heartbeat(
+        &self,
+        node_id: Id,
+        timeout: Duration,
+        max_missed: usize,
+        upgrader: Arc<dyn Upgrader>,
+    ) -> Vec<(Id, SocketAddr)> {
+        let mut peers: Vec<(Id, SocketAddr)> = self.idle.lock().unwrap().keys().cloned().collect();
+        for peer in self.missed_heartbeats.lock().unwrap().keys() {
+            if !peers.contains(peer) {
+                peers.push(*peer);
+            }
+        }
+        let heartbeats = peers.into_iter().map(|(id, ip)| {
+            let upgrader = upgrader.clone();
+            async move {
+                let ping = ice::Ping { id: node_id, queries: vec![], rumours: vec![] };
+                let answered = with_timeout(Some(timeout), async {
+                    let mut connection = match self.take(id, ip) {
+                        Some(connection) => connection,
+                        None => self.connect(id, ip, upgrader).await?,
+                    };
+                    let response = connection.exchange(Request::Ping(ping)).await?;
+                    if response.is_some() {
+                        self.put(id, ip, connection);
+                    }
+                    Ok(response)
+                })
+                .await;
+                (id, ip, matches!(answered, Ok(Some(_))))
+            }
+        });
+        let mut unreachable = vec![];
+        for (id, ip, answered) in futures::future::join_all(heartbeats).await {
+            let mut missed_heartbeats = self.missed_heartbeats.lock().unwrap();
+            if answered {
+                let _ = missed_heartbeats.remove(&(id, ip));
+                continue;
+            }
+            let missed = missed_heartbeats.entry((id, ip)).or_insert(0);
+            *missed += 1;
+            debug!("{} missed {} heartbeats", ip, missed);
+            if *missed >= max_missed {
+                let _ = missed_heartbeats.remove(&(id, ip));
+                let _ = self.idle.lock().unwrap().remove(&(id, ip));
+                unreachable.push((id, ip));
+            }
+        }
+        unreachable
     }
 }
 
 impl Actor for Client {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Context<Self>) {
+    fn started(&mut self, ctx: &mut Context<Self>) {
         debug!("started client");
+        if let Some(heartbeat) = self.heartbeat.clone() {
+            let _ = ctx.run_interval(heartbeat.interval, move |act, ctx| {
+                let pool = act.pool.clone();
+                let upgrader = act.upgrader.clone();
+                let recipients = act.unreachable_recipients.clone();
+                let heartbeat = heartbeat.clone();
+                let _ = ctx.spawn(
+                    async move {
+                        let unreachable = pool
+                            .heartbeat(
+                                heartbeat.node_id,
+                                heartbeat.timeout,
+                                heartbeat.max_missed,
+                                upgrader,
+                            )
+                            .await;
+                        for (id, ip) in unreachable {
+                            warn!(
+                                "{} missed {} heartbeats, closed its connections",
+                                ip, heartbeat.max_missed
+                            );
+                            for recipient in recipients.iter() {
+                                let _ = recipient.do_send(PeerUnreachable { id, ip });
+                            }
+                        }
+                    }
+                    .into_actor(act),
+                );
+            });
+        }
     }
 }
 
@@ -49,17 +392,219 @@ impl Actor for Client {
 #[derive(Debug, Clone, Serialize, Deserialize, Message)]
 #[rtype(result = "ClientResponse")]
 pub enum ClientRequest {
-    /// Sends a single request and waits for a response
-    Oneshot { id: Id, ip: SocketAddr, request: Request },
-    /// Multicast message
-    Fanout { peers: Vec<(Id, SocketAddr)>, request: Request },
+    /// Sends a single request and waits for a response.
+    /// Each attempt is abandoned after `timeout`, if set, and failed attempts are repeated at most
+    /// `retries` times.
+    Oneshot { id: Id, ip: SocketAddr, request: Request, timeout: Option<Duration>, retries: usize },
+    /// Multicast message. The outcome of the request is returned for every peer, responses
+    /// arriving after `timeout`, if set, are reported as [ClientError::Timeout].
+    Fanout { peers: Vec<(Id, SocketAddr)>, request: Request, timeout: Option<Duration> },
 }
 
 /// Response message from the client actor
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ClientResponse {
     Oneshot(Option<Response>),
-    Fanout(Vec<Response>),
+    /// The outcome of the request for each peer, in the order of the peers
+    Fanout(Vec<(Id, FanoutResult)>),
+}
+
+/// The outcome of a request to a single peer of a [ClientRequest::Fanout]
+pub type FanoutResult = std::result::Result<Response, ClientError>;
+
+impl ClientResponse {
+    /// Returns the outcomes of a [ClientRequest::Fanout].
+    ///
+    /// Throws [ClientError::UnexpectedResponseKind] if this is the response to a
+    /// [ClientRequest::Oneshot].
+    pub fn into_fanout(self) -> std::result::Result<Vec<(Id, FanoutResult)>, ClientError> {
+        match self {
+            ClientResponse::Fanout(results) => Ok(results),
+            ClientResponse::Oneshot(_) => Err(ClientError::UnexpectedResponseKind),
+        }
+    }
+
+    /// Returns the response to a [ClientRequest::Oneshot].
+    ///
+    /// Throws [ClientError::UnexpectedResponseKind] if this is the response to a
+    /// [ClientRequest::Fanout].
+    pub fn into_oneshot(self) -> std::result::Result<Option<Response>, ClientError> {
+        match self {
+            ClientResponse::Oneshot(response) => Ok(response),
+            ClientResponse::Fanout(_) => Err(ClientError::UnexpectedResponseKind),
+        }
+    }
+}
+
+/// The reason a request didn't yield the expected response
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ClientError {
+    /// The response is of another kind than the request, e.g. a [ClientResponse::Oneshot]
+    /// to a [ClientRequest::Fanout], or a [Response] which doesn't answer the [Request]
+    UnexpectedResponseKind,
+    /// The peer didn't respond in time
+    Timeout,
+    /// The peer isn't listening at its address
+    ConnectionRefused,
+    /// The peer couldn't be reached
+    Network(String),
+    /// A message couldn't be encoded or decoded, or exceeded the maximum frame size
+    Serialization(String),
+    /// The peer refused the request, see [Response::RequestRefused]
+    PeerRefused,
+    /// The peer closed the connection without responding
+    NoResponse,
+    /// The peer violated the protocol, e.g. by presenting an unexpected certificate
+    Protocol(String),
+    /// The peer didn't answer any of the given number of attempts, see [oneshot_with_retry]
+    RetriesExhausted(usize),
+}
+
+impl ClientError {
+    fn from_result(result: Result<Option<Response>>) -> FanoutResult {
+        match result {
+            Ok(Some(Response::RequestRefused)) => Err(ClientError::PeerRefused),
+            Ok(Some(response)) => Ok(response),
+            Ok(None) => Err(ClientError::NoResponse),
+            Err(err) => Err(ClientError::from(err)),
+        }
+    }
+}
+
+impl std::convert::From<Error> for ClientError {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Timeout => ClientError::Timeout,
+            Error::IO(err) if err.kind() == std::io::ErrorKind::ConnectionRefused => {
+                ClientError::ConnectionRefused
+            }
+            Error::IO(err) => ClientError::Network(format!("{:?}", err)),
+            Error::ChannelError(err) => ClientError::Serialization(err),
+            err @ Error::FrameTooLarge => ClientError::Serialization(format!("{:?}", err)),
+            Error::Client(err) => err,
+            err => ClientError::Protocol(format!("{:?}", err)),
+        }
+    }
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Keeps the successful responses of a [ClientRequest::Fanout], logging the failures
+pub fn successful_responses(results: Vec<(Id, FanoutResult)>) -> Vec<Response> {
+    let mut responses = vec![];
+    for (id, result) in results {
+        match result {
+            Ok(response) => responses.push(response),
+            Err(err) => debug!("no response from {}: {:?}", id, err),
+        }
+    }
+    responses
+}
+
+/// Sends `request` to each of the weighted `peers` through `client`, and resolves as soon as the
+/// outcome is settled: once the weight of the responses accepted by `is_yes`, added to
+/// `initial_weight`, exceeds `threshold`, or once the outstanding peers don't weigh enough to
+/// exceed it anymore, so that slow peers don't delay a settled outcome. The requests to the
+/// remaining peers aren't cancelled though: they keep running in `client` until the peers answer
+/// or `timeout` elapses, and their outcomes are discarded.
+///
+/// Returns the outcome for each peer which answered or failed until then, in the order of
+/// arrival. Throws [Error::Client] if `client` answers with a response of the wrong kind.
+pub async fn fanout_until_settled<F>(
+    client: &Recipient<ClientRequest>,
+    peers: Vec<(Id, SocketAddr, Weight)>,
+    request: Request,
+    timeout: Option<Duration>,
+    initial_weight: Weight,
+    threshold: Weight,
+    is_yes: F,
+) -> Result<Vec<(Id, FanoutResult)>>
+where
+    F: Fn(&Response) -> bool,
+{
+    let mut outstanding_weight: Weight = peers.iter().map(|(_, _, weight)| weight).sum();
+    let mut yes_weight = initial_weight;
+    let mut queries: FuturesUnordered<_> = peers
+        .into_iter()
+        .map(|(id, ip, weight)| {
+            let request = request.clone();
+            client
+                .send(ClientRequest::Fanout { peers: vec![(id, ip)], request, timeout })
+                .map(move |response| (id, weight, response))
+        })
+        .collect();
+    let mut results = vec![];
+    while let Some((id, weight, response)) = queries.next().await {
+        let result = response
+            .map_err(Error::Actix)?
+            .into_fanout()?
+            .into_iter()
+            .find(|(peer, _)| *peer == id)
+            .map(|(_, result)| result)
+            .unwrap_or(Err(ClientError::NoResponse));
+        outstanding_weight -= weight;
+        if result.as_ref().map(|response| is_yes(response)).unwrap_or(false) {
+            yes_weight += weight;
+        }
+        results.push((id, result));
+        if yes_weight > threshold || yes_weight + outstanding_weight <= threshold {
+            break;
+        }
+    }
+    Ok(results)
+}
+
+/// How [oneshot_with_retry] repeats a request which the peer didn't answer
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// The number of retries after the first attempt
+    pub retries: usize,
+    /// The time the peer has to answer each attempt, if set
+    pub timeout: Option<Duration>,
+    /// The delay between two attempts
+    pub backoff: Backoff,
+}
+
+impl RetryPolicy {
+    pub fn new(retries: usize, timeout: Option<Duration>, backoff: Backoff) -> Self {
+        RetryPolicy { retries, timeout, backoff }
+    }
+}
+
+/// Sends `request` to the peer `id` at `ip` through `client`, repeating it after a jittered
+/// backoff for as long as the peer doesn't answer and `policy` allows.
+///
+/// Returns the first response of the peer, or [ClientError::RetriesExhausted] once all
+/// attempts failed. Throws [ClientError::PeerRefused] right away if the peer refuses the
+/// request, and [ClientError::Protocol] if `client` can't be reached.
+pub async fn oneshot_with_retry(
+    client: &Recipient<ClientRequest>,
+    id: Id,
+    ip: SocketAddr,
+    request: Request,
+    policy: RetryPolicy,
+) -> std::result::Result<Response, ClientError> {
+    let RetryPolicy { retries, timeout, mut backoff } = policy;
+    let mut attempt = 0;
+    loop {
+        let response = client
+            .send(ClientRequest::Oneshot { id, ip, request: request.clone(), timeout, retries: 0 })
+            .await
+            .map_err(|err| ClientError::from(Error::Actix(err)))?
+            .into_oneshot()?;
+        match response {
+            Some(Response::RequestRefused) => return Err(ClientError::PeerRefused),
+            Some(response) => return Ok(response),
+            None if attempt >= retries => return Err(ClientError::RetriesExhausted(attempt + 1)),
+            None => debug!("retrying request to {} (attempt {})", ip, attempt + 1),
+        }
+        attempt += 1;
+        tokio::time::sleep(backoff.next_delay(&mut rand::thread_rng())).await;
+    }
 }
 
 impl Handler<ClientRequest> for Client {
@@ -67,13 +612,28 @@ impl Handler<ClientRequest> for Client {
 
     fn handle(&mut self, msg: ClientRequest, _ctx: &mut Context<Self>) -> Self::Result {
         let upgrader = self.upgrader.clone();
+        let pool = self.pool.clone();
         match msg {
-            ClientRequest::Oneshot { id, ip, request } => Box::pin(async move {
-                let response = oneshot(id.clone(), ip.clone(), request.clone(), upgrader).await;
-                ClientResponse::Oneshot(err_to_none(response))
+            ClientRequest::Oneshot { id, ip, request, timeout, retries } => Box::pin(async move {
+                let mut attempt = 0;
+                loop {
+                    let response = with_timeout(
+                        timeout,
+                        pool.request(id, ip, request.clone(), upgrader.clone()),
+                    )
+                    .await;
+                    match response {
+                        Ok(Some(response)) => return ClientResponse::Oneshot(Some(response)),
+                        response if attempt >= retries => {
+                            return ClientResponse::Oneshot(err_to_none(response))
+                        }
+                        response => debug!("retrying request to {}: {:?}", ip, response),
+                    }
+                    attempt += 1;
+                }
             }),
-            ClientRequest::Fanout { peers, request } => Box::pin(async move {
-                ClientResponse::Fanout(fanout(peers.clone(), request.clone(), upgrader).await)
+            ClientRequest::Fanout { peers, request, timeout } => Box::pin(async move {
+                ClientResponse::Fanout(pooled_fanout(peers, request, upgrader, pool, timeout).await)
             }),
         }
     }
@@ -99,6 +659,12 @@ pub async fn oneshot(
     request: Request,
     upgrader: Arc<dyn Upgrader>,
 ) -> Result<Option<Response>> {
+    let mut connection = connect(id, ip, upgrader).await?;
+    connection.request(request).await
+}
+
+/// Connects to the node, checking the peer's identity for TLS connections.
+async fn connect(id: Id, ip: SocketAddr, upgrader: Arc<dyn Upgrader>) -> Result<Connection> {
     let socket = TcpStream::connect(&ip).await.map_err(Error::IO)?;
     let connection = upgrader.upgrade(socket).await?;
     if connection.is_tls()
@@ -108,10 +674,8 @@ pub async fn oneshot(
         return Err(Error::UnexpectedPeerConnected);
     }
     let mut channel: Channel<Request, Response> = Channel::wrap(connection)?;
-    let (mut sender, mut receiver) = channel.split();
-    let () = sender.send(request).await?;
-    let response = receiver.recv().await?;
-    Ok(response)
+    let (sender, receiver) = channel.split();
+    Ok(Connection { sender, receiver, last_used: Instant::now() })
 }
 
 /// To be used in the integration tests (TCP-only)
@@ -131,38 +695,78 @@ pub async fn fanout(
     peers: Vec<(Id, SocketAddr)>,
     request: Request,
     upgrader: Arc<dyn Upgrader>,
-) -> Vec<Response> {
+) -> Vec<(Id, FanoutResult)> {
     let mut client_futs = vec![];
     // fanout oneshot requests to the ips designated in `ips` and collect the client
     // futures.
     for (id, ip) in peers.iter().cloned() {
         let request = request.clone();
         let upgrader = upgrader.clone();
-        let client_fut =
-            tokio::spawn(
-                async move { err_to_none(oneshot(id, ip, request.clone(), upgrader).await) },
-            );
-        client_futs.push(client_fut)
+        let client_fut = tokio::spawn(async move {
+            ClientError::from_result(oneshot(id, ip, request.clone(), upgrader).await)
+        });
+        client_futs.push((id, client_fut))
     }
-    // join the futures and collect the responses
+    join_responses(client_futs).await
+}
+
+/// Like [fanout], but reuses the connections of the `pool`, and fails the requests which
+/// aren't answered within `timeout` with [ClientError::Timeout].
+pub async fn pooled_fanout(
+    peers: Vec<(Id, SocketAddr)>,
+    request: Request,
+    upgrader: Arc<dyn Upgrader>,
+    pool: ConnectionPool,
+    timeout: Option<Duration>,
+) -> Vec<(Id, FanoutResult)> {
+    let mut client_futs = vec![];
+    for (id, ip) in peers.iter().cloned() {
+        let request = request.clone();
+        let upgrader = upgrader.clone();
+        let pool = pool.clone();
+        let client_fut = tokio::spawn(async move {
+            ClientError::from_result(
+                with_timeout(timeout, pool.request(id, ip, request, upgrader)).await,
+            )
+        });
+        client_futs.push((id, client_fut))
+    }
+    join_responses(client_futs).await
+}
+
+async fn join_responses(
+    client_futs: Vec<(Id, tokio::task::JoinHandle<FanoutResult>)>,
+) -> Vec<(Id, FanoutResult)> {
+    let (ids, client_futs): (Vec<Id>, Vec<_>) = client_futs.into_iter().unzip();
+    // join the futures and collect the outcomes
     futures::future::join_all(client_futs)
         .map(|results| {
-            let mut responses = vec![];
-            for r in results.iter() {
-                match r {
-                    Ok(inner) => match inner {
-                        Some(response) => responses.push(response.clone()),
-                        None => (),
-                    },
-                    // NOTE: The error here is logged and `None` is returned
-                    Err(_) => error!("error: joining client futures"),
-                }
-            }
-            responses
+            ids.into_iter()
+                .zip(results.into_iter())
+                .map(|(id, result)| match result {
+                    Ok(result) => (id, result),
+                    Err(e) => {
+                        error!("error: joining client futures");
+                        (id, Err(ClientError::Protocol(format!("{:?}", e))))
+                    }
+                })
+                .collect()
         })
         .await
 }
 
+/// Fails with [Error::Timeout] if `fut` doesn't complete within `timeout`, if set.
+/// A pooled connection used by `fut` is dropped on timeout.
+async fn with_timeout<F>(timeout: Option<Duration>, fut: F) -> Result<Option<Response>>
+where
+    F: std::future::Future<Output = Result<Option<Response>>>,
+{
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, fut).await.map_err(|_| Error::Timeout)?,
+        None => fut.await,
+    }
+}
+
 /// Helper function to simplify the return value of the `oneshot` function
 #[inline]
 fn err_to_none<T>(x: Result<Option<T>>) -> Option<T> {
@@ -181,3 +785,471 @@ fn err_to_none<T>(x: Result<Option<T>>) -> Option<T> {
         },
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::channel::Channel;
+    use crate::tls::upgrader::TcpUpgrader;
+
+    use actix::Actor;
+    use std::collections::HashSet;
+    use std::sync::atomic::AtomicBool;
+    use tokio::net::TcpListener;
+
+    /// Serves `Unknown` to every request on the accepted connections after `delay`, counting the
+    /// connections
+    async fn mock_server(listener: TcpListener, accepted: Arc<AtomicUsize>, delay: Duration) {
+        mock_server_with_response(listener, accepted, delay, Response::Unknown).await
+    }
+
+    /// Serves `response` to every request on the accepted connections after `delay`, counting
+    /// the connections
+    async fn mock_server_with_response(
+        listener: TcpListener,
+        accepted: Arc<AtomicUsize>,
+        delay: Duration,
+        response: Response,
+    ) {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            let _ = accepted.fetch_add(1, Ordering::Relaxed);
+            let response = response.clone();
+            tokio::spawn(async move {
+                let socket = TcpUpgrader::new().upgrade(socket).await.unwrap();
+                let mut channel: Channel<Response, Request> = Channel::wrap(socket).unwrap();
+                let (mut sender, mut receiver) = channel.split();
+                while let Ok(Some(_)) = receiver.recv().await {
+                    tokio::time::sleep(delay).await;
+                    sender.send(response.clone()).await.unwrap();
+                }
+            });
+        }
+    }
+
+    /// Closes the first `failures` accepted connections without responding, and serves `Unknown`
+    /// on the following ones, counting the connections
+    async fn flaky_server(listener: TcpListener, accepted: Arc<AtomicUsize>, failures: usize) {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            let fail = accepted.fetch_add(1, Ordering::Relaxed) < failures;
+            tokio::spawn(async move {
+                let socket = TcpUpgrader::new().upgrade(socket).await.unwrap();
+                let mut channel: Channel<Response, Request> = Channel::wrap(socket).unwrap();
+                let (mut sender, mut receiver) = channel.split();
+                while let Ok(Some(_)) = receiver.recv().await {
+                    if fail {
+                        return;
+                    }
+                    sender.send(Response::Unknown).await.unwrap();
+                }
+            });
+        }
+    }
+
+    /// Answers the first request on the accepted connections, counting the requests. The
+    /// connections are closed right after the answer, or after reading a second request if
+    /// `read_second`
+    async fn one_request_server(
+        listener: TcpListener,
+        accepted: Arc<AtomicUsize>,
+        received: Arc<AtomicUsize>,
+        read_second: bool,
+    ) {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            let _ = accepted.fetch_add(1, Ordering::Relaxed);
+            let received = received.clone();
+            tokio::spawn(async move {
+                let socket = TcpUpgrader::new().upgrade(socket).await.unwrap();
+                let mut channel: Channel<Response, Request> = Channel::wrap(socket).unwrap();
+                let (mut sender, mut receiver) = channel.split();
+                if let Ok(Some(_)) = receiver.recv().await {
+                    let _ = received.fetch_add(1, Ordering::Relaxed);
+                    sender.send(Response::Unknown).await.unwrap();
+                }
+                if read_second {
+                    if let Ok(Some(_)) = receiver.recv().await {
+                        let _ = received.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_pool_doesnt_resend_written_requests() {
+        let ip: SocketAddr = "127.0.0.1:20026".parse().unwrap();
+        let (accepted, received) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let listener = TcpListener::bind(&ip).await.unwrap();
+        let server =
+            tokio::spawn(one_request_server(listener, accepted.clone(), received.clone(), true));
+
+        let pool = ConnectionPool::new();
+        let request = || pool.request(Id::zero(), ip, Request::GetLastAccepted, TcpUpgrader::new());
+        assert!(matches!(request().await, Ok(Some(Response::Unknown))));
+        assert_eq!(pool.idle_connections(), 1);
+
+        // The node reads the request on the idle connection and drops it without answering:
+        // the request may have been processed, so it isn't sent again
+        assert!(!matches!(request().await, Ok(Some(_))));
+        assert_eq!(received.load(Ordering::Relaxed), 2);
+        assert_eq!(accepted.load(Ordering::Relaxed), 1);
+        assert_eq!(pool.idle_connections(), 0);
+        server.abort();
+    }
+
+    #[actix_rt::test]
+    async fn test_pool_replaces_closed_connections() {
+        let ip: SocketAddr = "127.0.0.1:20027".parse().unwrap();
+        let (accepted, received) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let listener = TcpListener::bind(&ip).await.unwrap();
+        let server =
+            tokio::spawn(one_request_server(listener, accepted.clone(), received.clone(), false));
+
+        let pool = ConnectionPool::new();
+        let request = || pool.request(Id::zero(), ip, Request::GetLastAccepted, TcpUpgrader::new());
+        assert!(matches!(request().await, Ok(Some(Response::Unknown))));
+        assert_eq!(pool.idle_connections(), 1);
+
+        // The node closed the idle connection, so the request is sent on a new one
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(matches!(request().await, Ok(Some(Response::Unknown))));
+        assert_eq!(received.load(Ordering::Relaxed), 2);
+        assert_eq!(accepted.load(Ordering::Relaxed), 2);
+        server.abort();
+    }
+
+    #[actix_rt::test]
+    async fn test_reuse_connections() {
+        let ip: SocketAddr = "127.0.0.1:20010".parse().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let listener = TcpListener::bind(&ip).await.unwrap();
+        let server = tokio::spawn(mock_server(listener, accepted.clone(), Duration::ZERO));
+
+        let client = Client::new(TcpUpgrader::new());
+        let pool = client.pool();
+        let client = client.start();
+        for _ in 0..5 {
+            let request = ClientRequest::Oneshot {
+                id: Id::zero(),
+                ip,
+                request: Request::GetLastAccepted,
+                timeout: None,
+                retries: 0,
+            };
+            let response = client.send(request).await.unwrap();
+            assert!(matches!(response, ClientResponse::Oneshot(Some(Response::Unknown))));
+        }
+        assert_eq!(pool.connects(), 1);
+        assert_eq!(accepted.load(Ordering::Relaxed), 1);
+
+        let request = ClientRequest::Fanout {
+            peers: vec![(Id::zero(), ip)],
+            request: Request::GetLastAccepted,
+            timeout: None,
+        };
+        let response = client.send(request).await.unwrap();
+        assert!(matches!(response, ClientResponse::Fanout(responses) if responses.len() == 1));
+        assert_eq!(accepted.load(Ordering::Relaxed), 1);
+        server.abort();
+    }
+
+    /// Answers every request on the accepted connections with `Unknown` until `responsive` is
+    /// cleared, and reads the requests without answering them afterwards
+    async fn mock_server_going_silent(listener: TcpListener, responsive: Arc<AtomicBool>) {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            let responsive = responsive.clone();
+            tokio::spawn(async move {
+                let socket = TcpUpgrader::new().upgrade(socket).await.unwrap();
+                let mut channel: Channel<Response, Request> = Channel::wrap(socket).unwrap();
+                let (mut sender, mut receiver) = channel.split();
+                while let Ok(Some(_)) = receiver.recv().await {
+                    if responsive.load(Ordering::Relaxed) {
+                        sender.send(Response::Unknown).await.unwrap();
+                    }
+                }
+            });
+        }
+    }
+
+    /// Collects the [PeerUnreachable] notifications
+    struct UnreachableCollector {
+        unreachable: Arc<Mutex<Vec<(Id, SocketAddr, Instant)>>>,
+    }
+
+    impl Actor for UnreachableCollector {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<PeerUnreachable> for UnreachableCollector {
+        type Result = ();
+
+        fn handle(&mut self, msg: PeerUnreachable, _ctx: &mut Context<Self>) -> Self::Result {
+            self.unreachable.lock().unwrap().push((msg.id, msg.ip, Instant::now()));
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_heartbeats_detect_dead_peer() {
+        let ip: SocketAddr = "127.0.0.1:20020".parse().unwrap();
+        let responsive = Arc::new(AtomicBool::new(true));
+        let listener = TcpListener::bind(&ip).await.unwrap();
+        let server = tokio::spawn(mock_server_going_silent(listener, responsive.clone()));
+
+        let interval = Duration::from_millis(100);
+        let heartbeat = HeartbeatConfig {
+            node_id: Id::one(),
+            interval,
+            timeout: Duration::from_millis(50),
+            max_missed: 3,
+        };
+        let client = Client::new(TcpUpgrader::new()).with_heartbeat(heartbeat);
+        let pool = client.pool();
+        let client = client.start();
+        let unreachable = Arc::new(Mutex::new(vec![]));
+        let collector = UnreachableCollector { unreachable: unreachable.clone() }.start();
+        client.send(SubscribeUnreachable { recipient: collector.recipient() }).await.unwrap();
+
+        let request = ClientRequest::Oneshot {
+            id: Id::zero(),
+            ip,
+            request: Request::GetLastAccepted,
+            timeout: None,
+            retries: 0,
+        };
+        let response = client.send(request).await.unwrap();
+        assert!(matches!(response, ClientResponse::Oneshot(Some(Response::Unknown))));
+
+        // The heartbeats are answered on the same connection
+        tokio::time::sleep(interval * 4).await;
+        assert!(unreachable.lock().unwrap().is_empty());
+        assert_eq!(pool.connects(), 1);
+
+        // The peer stops answering and is torn down after `max_missed` heartbeats
+        responsive.store(false, Ordering::Relaxed);
+        let silent = Instant::now();
+        tokio::time::sleep(interval * 10).await;
+        let unreachable = unreachable.lock().unwrap().clone();
+        assert_eq!(unreachable.len(), 1);
+        let (unreachable_id, unreachable_ip, at) = unreachable[0];
+        assert_eq!((unreachable_id, unreachable_ip), (Id::zero(), ip));
+        assert!(at.duration_since(silent) <= interval * 8);
+        assert_eq!(pool.idle_connections(), 0);
+        server.abort();
+    }
+
+    #[actix_rt::test]
+    async fn test_request_timeouts() {
+        let fast_ip: SocketAddr = "127.0.0.1:20011".parse().unwrap();
+        let slow_ip: SocketAddr = "127.0.0.1:20012".parse().unwrap();
+        let (fast_accepted, slow_accepted) =
+            (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let fast_listener = TcpListener::bind(&fast_ip).await.unwrap();
+        let slow_listener = TcpListener::bind(&slow_ip).await.unwrap();
+        let fast = tokio::spawn(mock_server(fast_listener, fast_accepted, Duration::ZERO));
+        let slow = tokio::spawn(mock_server(
+            slow_listener,
+            slow_accepted.clone(),
+            Duration::from_secs(10),
+        ));
+
+        let client = Client::new(TcpUpgrader::new()).start();
+        let timeout = Duration::from_millis(200);
+        let request = ClientRequest::Fanout {
+            peers: vec![(Id::zero(), fast_ip), (Id::zero(), slow_ip)],
+            request: Request::GetLastAccepted,
+            timeout: Some(timeout),
+        };
+        let start = Instant::now();
+        let response = client.send(request).await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        match response {
+            ClientResponse::Fanout(results) => {
+                assert!(matches!(results[0], (_, Ok(Response::Unknown))));
+                assert!(matches!(results[1], (_, Err(ClientError::Timeout))));
+            }
+            x => panic!("unexpected: {:?}", x),
+        }
+
+        // A oneshot request is attempted `retries + 1` times
+        let request = ClientRequest::Oneshot {
+            id: Id::zero(),
+            ip: slow_ip,
+            request: Request::GetLastAccepted,
+            timeout: Some(timeout),
+            retries: 2,
+        };
+        let accepted = slow_accepted.load(Ordering::Relaxed);
+        let response = client.send(request).await.unwrap();
+        assert!(matches!(response, ClientResponse::Oneshot(None)));
+        // Timed out connections aren't reused
+        assert_eq!(slow_accepted.load(Ordering::Relaxed), accepted + 3);
+        fast.abort();
+        slow.abort();
+    }
+
+    #[actix_rt::test]
+    async fn test_fanout_until_settled() {
+        let ips: Vec<SocketAddr> =
+            (20017..20020).map(|port| format!("127.0.0.1:{}", port).parse().unwrap()).collect();
+        let mut servers = vec![];
+        for (i, ip) in ips.iter().enumerate() {
+            let listener = TcpListener::bind(ip).await.unwrap();
+            // The last peer is slow to respond
+            let delay = if i == 2 { Duration::from_secs(10) } else { Duration::ZERO };
+            servers.push(tokio::spawn(mock_server(listener, Arc::new(AtomicUsize::new(0)), delay)));
+        }
+        let weights = [0.3, 0.3, 0.4];
+        let peers: Vec<(Id, SocketAddr, Weight)> =
+            ips.iter().zip(weights.iter()).map(|(ip, w)| (Id::from_ip(ip), *ip, *w)).collect();
+        let fast: HashSet<Id> = peers[..2].iter().map(|(id, _, _)| *id).collect();
+        let client = Client::new(TcpUpgrader::new()).start().recipient();
+        let request = Request::GetLastAccepted;
+
+        // The votes of the fast peers exceed the threshold
+        let start = Instant::now();
+        let results =
+            fanout_until_settled(&client, peers.clone(), request.clone(), None, 0.0, 0.5, |_| true)
+                .await
+                .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(results.iter().map(|(id, _)| *id).collect::<HashSet<_>>(), fast);
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+
+        // The vote of the slow peer can't make the threshold anymore
+        let start = Instant::now();
+        let results =
+            fanout_until_settled(&client, peers.clone(), request.clone(), None, 0.0, 0.5, |_| {
+                false
+            })
+            .await
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(results.iter().map(|(id, _)| *id).collect::<HashSet<_>>(), fast);
+
+        // The vote of the slow peer is needed
+        let timeout = Some(Duration::from_millis(200));
+        let results =
+            fanout_until_settled(&client, peers.clone(), request, timeout, 0.0, 0.65, |_| true)
+                .await
+                .unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(matches!(results[2], (id, Err(ClientError::Timeout)) if id == peers[2].0));
+        for server in servers {
+            server.abort();
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_fanout_until_settled_outstanding_requests() {
+        let ips: Vec<SocketAddr> =
+            (20023..20026).map(|port| format!("127.0.0.1:{}", port).parse().unwrap()).collect();
+        let mut servers = vec![];
+        for (i, ip) in ips.iter().enumerate() {
+            let listener = TcpListener::bind(ip).await.unwrap();
+            // The last peer is slow to respond
+            let delay = if i == 2 { Duration::from_millis(500) } else { Duration::ZERO };
+            servers.push(tokio::spawn(mock_server(listener, Arc::new(AtomicUsize::new(0)), delay)));
+        }
+        let peers: Vec<(Id, SocketAddr, Weight)> =
+            ips.iter().map(|ip| (Id::from_ip(ip), *ip, 0.3)).collect();
+        let client = Client::new(TcpUpgrader::new());
+        let pool = client.pool();
+        let client = client.start().recipient();
+
+        let results =
+            fanout_until_settled(&client, peers, Request::GetLastAccepted, None, 0.0, 0.5, |_| {
+                true
+            })
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(pool.idle_connections(), 2);
+
+        // The request to the slow peer still completes, returning its connection to the pool
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(pool.idle_connections(), 3);
+        for server in servers {
+            server.abort();
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_fanout_errors_per_peer() {
+        let ips: Vec<SocketAddr> =
+            (20013..20016).map(|port| format!("127.0.0.1:{}", port).parse().unwrap()).collect();
+        let mut servers = vec![];
+        for (i, ip) in ips.iter().enumerate() {
+            let listener = TcpListener::bind(ip).await.unwrap();
+            // The last peer refuses the request
+            let response = if i == 2 { Response::RequestRefused } else { Response::Unknown };
+            servers.push(tokio::spawn(mock_server_with_response(
+                listener,
+                Arc::new(AtomicUsize::new(0)),
+                Duration::ZERO,
+                response,
+            )));
+        }
+
+        let client = Client::new(TcpUpgrader::new()).start();
+        let mut peers: Vec<(Id, SocketAddr)> =
+            ips.iter().map(|ip| (Id::from_ip(ip), *ip)).collect();
+        // Nothing listens at the address of the last peer
+        let unreachable_ip: SocketAddr = "127.0.0.1:20016".parse().unwrap();
+        peers.push((Id::from_ip(&unreachable_ip), unreachable_ip));
+        let request = ClientRequest::Fanout {
+            peers: peers.clone(),
+            request: Request::GetLastAccepted,
+            timeout: None,
+        };
+        let results = match client.send(request).await.unwrap() {
+            ClientResponse::Fanout(results) => results,
+            x => panic!("unexpected: {:?}", x),
+        };
+        assert_eq!(results.len(), 4);
+        assert!(matches!(&results[0], (id, Ok(Response::Unknown)) if *id == peers[0].0));
+        assert!(matches!(&results[1], (id, Ok(Response::Unknown)) if *id == peers[1].0));
+        assert!(matches!(&results[2], (id, Err(ClientError::PeerRefused)) if *id == peers[2].0));
+        assert!(
+            matches!(&results[3], (id, Err(ClientError::ConnectionRefused)) if *id == peers[3].0)
+        );
+        assert_eq!(successful_responses(results).len(), 2);
+        for server in servers {
+            server.abort();
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_oneshot_with_retry() {
+        let policy = |retries| {
+            let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(50), 0.5);
+            RetryPolicy::new(retries, Some(Duration::from_secs(1)), backoff)
+        };
+        let client = Client::new(TcpUpgrader::new()).start().recipient();
+
+        // The peer fails twice, then succeeds
+        let ip: SocketAddr = "127.0.0.1:20021".parse().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let listener = TcpListener::bind(&ip).await.unwrap();
+        let server = tokio::spawn(flaky_server(listener, accepted.clone(), 2));
+        let response =
+            oneshot_with_retry(&client, Id::zero(), ip, Request::GetLastAccepted, policy(2)).await;
+        assert!(matches!(response, Ok(Response::Unknown)));
+        assert_eq!(accepted.load(Ordering::Relaxed), 3);
+        server.abort();
+
+        // The peer fails more often than the policy retries
+        let ip: SocketAddr = "127.0.0.1:20022".parse().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let listener = TcpListener::bind(&ip).await.unwrap();
+        let server = tokio::spawn(flaky_server(listener, accepted.clone(), 3));
+        let response =
+            oneshot_with_retry(&client, Id::zero(), ip, Request::GetLastAccepted, policy(1)).await;
+        assert!(matches!(response, Err(ClientError::RetriesExhausted(2))));
+        assert_eq!(accepted.load(Ordering::Relaxed), 2);
+        server.abort();
+    }
+}
@@ -9,6 +9,8 @@ use crate::sleet::conflict_set::ConflictSet;
 use crate::sleet::BETA2;
 
 use std::collections::{hash_map::Entry, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
 
 /// Keeps track of conflicts between transactions.
 ///
@@ -24,8 +26,36 @@ pub struct ConflictGraph {
     /// Individual conflict sets
     cs: HashMap<CellHash, ConflictSet<CellHash>>,
     /// Vector to keep track of insertion order, used to select the `last` and `pref` fields for
-    /// new conflict sets
+    /// new conflict sets, when the conflicting cells already gained confidence
     insertion_order: Vec<CellHash>,
+    /// Decay of the confidence of conflict sets which weren't updated recently, if enabled
+    decay: Option<ConfidenceDecay>,
+}
+
+/// A time-based decay of the confidence counter of conflict sets.
+///
+/// When a conflict set with conflicts wasn't updated within `window`, its confidence is lowered
+/// by one for each elapsed window, but not below `floor`. This keeps a preferred cell which
+/// stopped being queried from holding on to its confidence, while a conflicting cell is
+/// actively supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfidenceDecay {
+    /// The time after which the confidence of a quiet conflict set starts decaying
+    pub window: Duration,
+    /// The confidence isn't decayed below `floor`
+    pub floor: u8,
+}
+
+impl ConfidenceDecay {
+    /// Lowers the confidence of `cs` according to the time elapsed since its last update.
+    fn apply(&self, cs: &mut ConflictSet<CellHash>) {
+        if self.window.as_nanos() == 0 || cs.cnt <= self.floor {
+            return;
+        }
+        let windows = cs.updated.elapsed().as_nanos() / self.window.as_nanos();
+        let windows = u8::try_from(windows).unwrap_or(u8::MAX);
+        cs.cnt = cs.cnt.saturating_sub(windows).max(self.floor);
+    }
 }
 
 /// Data stored in the vertices
@@ -59,9 +89,16 @@ impl ConflictGraph {
             cells: HashMap::new(),
             cs: HashMap::new(),
             insertion_order: vec![],
+            decay: None,
         }
     }
 
+    /// Enables the [confidence decay][ConfidenceDecay] of conflict sets.
+    pub fn with_confidence_decay(mut self, decay: ConfidenceDecay) -> Self {
+        self.decay = Some(decay);
+        self
+    }
+
     /// Add new cell ids as accepted vertices.
     ///
     /// _Note: this function doesn't check for duplicates._ The cell ids handled similarly as the genesis ids.
@@ -75,6 +112,11 @@ impl ConflictGraph {
     }
 
     /// Insert a [Cell][crate::cell::Cell] into the conflict graph
+    ///
+    /// The preference of a conflict set without confidence is its lowest cell hash, so that
+    /// the initial preferences of all nodes agree, regardless of the order the conflicting cells
+    /// arrived in. Once the conflicting cells gained confidence, a new cell takes over the
+    /// preference of the first of them.
     pub fn insert_cell(&mut self, cell: Cell) -> Result<()> {
         let cell_hash = cell.hash();
         match self.cells.insert(cell_hash, cell.clone()) {
@@ -109,6 +151,9 @@ impl ConflictGraph {
         for conflict_hash in conflicts.iter() {
             let set = self.cs.get_mut(conflict_hash).unwrap();
             set.conflicts.insert(cell_hash);
+            if set.cnt == 0 {
+                set.pref = lowest_hash(&set.conflicts);
+            }
             own_cset.conflicts.insert(*conflict_hash);
         }
 
@@ -116,7 +161,7 @@ impl ConflictGraph {
             let first_conflict =
                 self.insertion_order.iter().find(|&h| conflicts.contains(h)).unwrap();
             let set = self.cs.get(first_conflict).unwrap();
-            own_cset.pref = set.pref;
+            own_cset.pref = if set.cnt == 0 { lowest_hash(&own_cset.conflicts) } else { set.pref };
             // FIXME: Not sure here.
             own_cset.last = set.last;
             own_cset.cnt = set.cnt;
@@ -126,6 +171,24 @@ impl ConflictGraph {
         Ok(())
     }
 
+    /// Return the cells spending some of the outputs spent by `cell`, without inserting it.
+    ///
+    /// Throws [Error::DuplicateCell] if `cell` was already inserted, or [Error::UndefinedCell]
+    /// if it spends unknown outputs, like [ConflictGraph::insert_cell].
+    pub fn conflicts_of(&self, cell: &Cell) -> Result<HashSet<CellHash>> {
+        if self.cells.contains_key(&cell.hash()) {
+            return Err(Error::DuplicateCell);
+        }
+        let mut conflicts = HashSet::new();
+        for cell_id in CellIds::from_inputs(cell.inputs())?.iter() {
+            match self.vertices.get(cell_id) {
+                Some(data) => conflicts.extend(data.spenders.iter().cloned()),
+                None => return Err(Error::UndefinedCell),
+            }
+        }
+        Ok(conflicts)
+    }
+
     /// Once a transaction is accepted we remove all the conflicts from the graph
     /// in order to free up space for future entries.
     /// The conflicting cell hashes are returned in order to allow [`sleet`][crate::sleet] to make
@@ -255,10 +318,20 @@ impl ConflictGraph {
     ///
     /// `d1` is the [conviction][crate::graph::DAG::conviction] value of `cell_hash` in the Sleet DAG,
     /// while `d2` is the conviction of the currently preferred element.
+    ///
+    /// If [confidence decay][ConfidenceDecay] is enabled, the confidence of a non-singleton
+    /// conflict set is decayed first, according to the time since its last update.
     pub fn update_conflict_set(&mut self, cell_hash: &CellHash, d1: u8, d2: u8) -> Result<()> {
         if self.cs.len() > 0 {
+            let decay = self.decay;
             match self.cs.get_mut(cell_hash) {
                 Some(cs) => {
+                    if let Some(decay) = decay {
+                        if !cs.is_singleton() {
+                            decay.apply(cs);
+                        }
+                    }
+                    cs.updated = Instant::now();
                     if d1 > d2 {
                         cs.pref = cell_hash.clone();
                     }
@@ -296,15 +369,19 @@ impl ConflictGraph {
     }
 
     /// Returns the number of cells in the conflict graph
-    #[cfg(test)]
     pub fn len(&self) -> usize {
         self.cs.len()
     }
 }
 
+/// The deterministic initial preference of a conflict set
+fn lowest_hash(conflicts: &HashSet<CellHash>) -> CellHash {
+    *conflicts.iter().min().unwrap()
+}
+
 #[cfg(test)]
 mod test {
-    use super::ConflictGraph;
+    use super::{lowest_hash, ConfidenceDecay, ConflictGraph};
 
     use crate::alpha::coinbase::CoinbaseOperation;
     use crate::alpha::transfer;
@@ -316,6 +393,7 @@ mod test {
 
     use std::collections::HashSet;
     use std::convert::TryInto;
+    use std::time::Duration;
 
     use ed25519_dalek::Keypair;
     use rand::{thread_rng, Rng};
@@ -376,7 +454,9 @@ mod test {
                 );
                 dh.insert_cell(tx.clone()).unwrap();
                 let c = dh.conflicting_cells(&tx.hash()).unwrap();
-                assert_eq!(c.pref, origin_tx_hash); // pref must be the original one which succeeded last time
+                assert!(c.contains(&origin_tx_hash));
+                // The conflict set has no confidence, so the lowest hash is preferred
+                assert_eq!(c.pref, lowest_hash(&c.conflicts));
             }
             iteration += 1;
         }
@@ -411,7 +491,8 @@ mod test {
             dh.insert_cell(tx.clone()).unwrap();
             let tx_hash = tx.hash();
             let conflict_cell = dh.conflicting_cells(&tx_hash).unwrap();
-            assert_eq!(conflict_cell.pref, new_hash);
+            assert!(conflict_cell.contains(&new_hash));
+            assert_eq!(conflict_cell.pref, lowest_hash(&conflict_cell.conflicts));
             iteration += 1;
         }
     }
@@ -459,7 +540,7 @@ mod test {
         let c2 = dh.conflicting_cells(&tx2.hash()).unwrap();
         assert_eq!(c2.conflicts.len(), 2);
         assert_eq!(c2.conflicts, expected);
-        assert_eq!(c2.pref, tx1.hash());
+        assert_eq!(c2.pref, lowest_hash(&expected));
 
         // A transaction that spends a distinct input should not conflict.
         let tx3 = Cell::new(
@@ -516,7 +597,7 @@ mod test {
         let c2 = dh.conflicting_cells(&tx2.hash()).unwrap();
         assert_eq!(c2.conflicts.len(), 2);
         assert_eq!(c2.conflicts, expected);
-        assert_eq!(c2.pref, tx1.hash());
+        assert_eq!(c2.pref, lowest_hash(&expected));
 
         // A transaction that spends a distinct input should not conflict.
         let tx3 = Cell::new(Inputs::new(vec![input3.clone()]), Outputs::new(vec![output2.clone()]));
@@ -539,7 +620,7 @@ mod test {
         let c4 = dh.conflicting_cells(&tx4.hash()).unwrap();
         assert_eq!(c4.conflicts.len(), 4);
         assert_eq!(c4.conflicts, expected);
-        assert_eq!(c4.pref, tx1.hash());
+        assert_eq!(c4.pref, lowest_hash(&expected));
     }
 
     #[actix_rt::test]
@@ -579,7 +660,7 @@ mod test {
         let c2 = dh.conflicting_cells(&tx2.hash()).unwrap();
         assert_eq!(c2.conflicts.len(), 2);
         assert_eq!(c2.conflicts, expected);
-        assert_eq!(c2.pref, tx1.hash());
+        assert_eq!(c2.pref, lowest_hash(&expected));
 
         let conflicts_removed = dh.accept_cell(tx2.clone()).unwrap();
         let expected = vec![tx1.hash()];
@@ -628,7 +709,7 @@ mod test {
         let c2 = dh.conflicting_cells(&tx2.hash()).unwrap();
         assert_eq!(c2.conflicts.len(), 2);
         assert_eq!(c2.conflicts, expected);
-        assert_eq!(c2.pref, tx1.hash());
+        assert_eq!(c2.pref, lowest_hash(&expected));
 
         // A transaction that spends a distinct input should not conflict.
         let tx3 = Cell::new(Inputs::new(vec![input3.clone()]), Outputs::new(vec![output2.clone()]));
@@ -651,7 +732,7 @@ mod test {
         let c4 = dh.conflicting_cells(&tx4.hash()).unwrap();
         assert_eq!(c4.conflicts.len(), 4);
         assert_eq!(c4.conflicts, expected);
-        assert_eq!(c4.pref, tx1.hash());
+        assert_eq!(c4.pref, lowest_hash(&expected));
 
         let mut conflicts_removed = dh.accept_cell(tx4.clone()).unwrap();
         conflicts_removed.sort();
@@ -704,7 +785,7 @@ mod test {
         let c2 = dh.conflicting_cells(&tx2.hash()).unwrap();
         assert_eq!(c2.conflicts.len(), 2);
         assert_eq!(c2.conflicts, expected);
-        assert_eq!(c2.pref, tx1.hash());
+        assert_eq!(c2.pref, lowest_hash(&expected));
 
         // A transaction that spends some of the same inputs as `tx2`
         let output3 = transfer::transfer_output(pkh2, 800).unwrap();
@@ -717,7 +798,7 @@ mod test {
         let c3 = dh.conflicting_cells(&tx3.hash()).unwrap();
         assert_eq!(c3.conflicts.len(), 2);
         assert_eq!(c3.conflicts, expected);
-        assert_eq!(c3.pref, tx1.hash());
+        assert_eq!(c3.pref, lowest_hash(&expected));
 
         // A transaction that spends one of the same inputs as `tx3`
         let output4 = transfer::transfer_output(pkh2, 700).unwrap();
@@ -727,7 +808,7 @@ mod test {
         let c4 = dh.conflicting_cells(&tx4.hash()).unwrap();
         assert_eq!(c4.conflicts.len(), 2);
         assert_eq!(c4.conflicts, expected);
-        assert_eq!(c4.pref, tx1.hash());
+        assert_eq!(c4.pref, lowest_hash(&expected));
 
         // Another transaction that spends one of the same inputs as `tx3`
         let output5 = transfer::transfer_output(pkh2, 600).unwrap();
@@ -737,7 +818,7 @@ mod test {
         let c5 = dh.conflicting_cells(&tx5.hash()).unwrap();
         assert_eq!(c5.conflicts.len(), 2);
         assert_eq!(c5.conflicts, expected);
-        assert_eq!(c5.pref, tx1.hash());
+        assert_eq!(c5.pref, lowest_hash(&expected));
     }
 
     #[actix_rt::test]
@@ -811,7 +892,52 @@ mod test {
         let expected: HashSet<CellHash> = vec![tx1.hash(), tx4.hash()].iter().cloned().collect();
         assert_eq!(c4.conflicts.len(), 2);
         assert_eq!(c4.conflicts, expected);
-        assert_eq!(c4.pref, tx1.hash());
+        assert_eq!(c4.pref, lowest_hash(&expected));
+    }
+
+    #[actix_rt::test]
+    async fn test_deterministic_initial_preference() {
+        let (kp1, _kp2, pkh1, pkh2) = generate_keys();
+        let genesis_op = CoinbaseOperation::new(vec![(pkh1.clone(), 1000)]);
+        let genesis_tx: Cell = genesis_op.try_into().unwrap();
+        let genesis_output_cell_ids =
+            CellIds::from_outputs(genesis_tx.hash(), genesis_tx.outputs()).unwrap();
+
+        let input1 = Input::new(&kp1, genesis_tx.hash(), 0).unwrap();
+        let tx1 = Cell::new(
+            Inputs::new(vec![input1.clone()]),
+            Outputs::new(vec![transfer::transfer_output(pkh2.clone(), 900).unwrap()]),
+        );
+        let tx2 = Cell::new(
+            Inputs::new(vec![input1.clone()]),
+            Outputs::new(vec![transfer::transfer_output(pkh2.clone(), 800).unwrap()]),
+        );
+        let lowest = std::cmp::min(tx1.hash(), tx2.hash());
+
+        // Two nodes receiving the conflicting cells in opposite order
+        let mut dh1 = ConflictGraph::new(genesis_output_cell_ids.clone());
+        dh1.insert_cell(tx1.clone()).unwrap();
+        dh1.insert_cell(tx2.clone()).unwrap();
+        let mut dh2 = ConflictGraph::new(genesis_output_cell_ids.clone());
+        dh2.insert_cell(tx2.clone()).unwrap();
+        dh2.insert_cell(tx1.clone()).unwrap();
+        for dh in [&dh1, &dh2] {
+            assert_eq!(dh.get_preferred(&tx1.hash()), Ok(lowest));
+            assert_eq!(dh.get_preferred(&tx2.hash()), Ok(lowest));
+        }
+
+        // Once the preferred cell gained confidence, a new conflict doesn't change the preference
+        let highest = std::cmp::max(tx1.hash(), tx2.hash());
+        while dh1.get_confidence(&highest) == Ok(0) {
+            dh1.update_conflict_set(&highest, 2, 1).unwrap();
+        }
+        assert_eq!(dh1.get_preferred(&highest), Ok(highest));
+        let tx3 = Cell::new(
+            Inputs::new(vec![input1.clone()]),
+            Outputs::new(vec![transfer::transfer_output(pkh2.clone(), 700).unwrap()]),
+        );
+        dh1.insert_cell(tx3.clone()).unwrap();
+        assert_eq!(dh1.get_preferred(&highest), Ok(highest));
     }
 
     fn hash_public(keypair: &Keypair) -> [u8; 32] {
@@ -819,6 +945,58 @@ mod test {
         blake3::hash(&enc).as_bytes().clone()
     }
 
+    #[actix_rt::test]
+    async fn test_confidence_decay() {
+        let (kp1, _kp2, pkh1, pkh2) = generate_keys();
+        let genesis_op = CoinbaseOperation::new(vec![(pkh1.clone(), 1000), (pkh2.clone(), 1000)]);
+        let genesis_tx: Cell = genesis_op.try_into().unwrap();
+        let genesis_output_cell_ids =
+            CellIds::from_outputs(genesis_tx.hash(), genesis_tx.outputs()).unwrap();
+        let decay = ConfidenceDecay { window: Duration::from_millis(20), floor: 1 };
+        let mut dh = ConflictGraph::new(genesis_output_cell_ids).with_confidence_decay(decay);
+
+        let input1 = Input::new(&kp1, genesis_tx.hash(), 0).unwrap();
+        let input2 = Input::new(&kp1, genesis_tx.hash(), 1).unwrap();
+        let spend = |input: &Input, capacity| {
+            let output = transfer::transfer_output(pkh2, capacity).unwrap();
+            Cell::new(Inputs::new(vec![input.clone()]), Outputs::new(vec![output]))
+        };
+
+        // `tx1` gains confidence, then `tx2` conflicting with it inherits its conflict set
+        let tx1 = spend(&input1, 1000);
+        dh.insert_cell(tx1.clone()).unwrap();
+        for _ in 0..5 {
+            dh.update_conflict_set(&tx1.hash(), 1, 0).unwrap();
+        }
+        assert_eq!(dh.get_confidence(&tx1.hash()).unwrap(), 5);
+        let tx2 = spend(&input1, 900);
+        dh.insert_cell(tx2.clone()).unwrap();
+        assert_eq!(dh.get_preferred(&tx2.hash()).unwrap(), tx1.hash());
+        assert_eq!(dh.get_confidence(&tx2.hash()).unwrap(), 5);
+
+        // A non-conflicting cell with confidence
+        let tx3 = spend(&input2, 1000);
+        dh.insert_cell(tx3.clone()).unwrap();
+        for _ in 0..5 {
+            dh.update_conflict_set(&tx3.hash(), 1, 0).unwrap();
+        }
+
+        // `tx1` goes quiet, while `tx2` gets support and becomes preferred with the stale
+        // confidence decayed to the floor
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        dh.update_conflict_set(&tx2.hash(), 1, 0).unwrap();
+        assert_eq!(dh.get_preferred(&tx2.hash()).unwrap(), tx2.hash());
+        assert_eq!(dh.get_confidence(&tx2.hash()).unwrap(), 1);
+        for _ in 0..3 {
+            dh.update_conflict_set(&tx2.hash(), 2, 1).unwrap();
+        }
+        assert_eq!(dh.get_confidence(&tx2.hash()).unwrap(), 4);
+
+        // Singleton conflict sets don't decay
+        dh.update_conflict_set(&tx3.hash(), 1, 0).unwrap();
+        assert_eq!(dh.get_confidence(&tx3.hash()).unwrap(), 6);
+    }
+
     fn generate_keys() -> (Keypair, Keypair, [u8; 32], [u8; 32]) {
         let kp1_hex = "ad7f2ee3958a7f3fa2c84931770f5773ef7694fdd0bb217d90f29a94199c9d7307ca3851515c89344639fe6a4077923068d1d7fc6106701213c61d34ef8e9416".to_owned();
         let kp2_hex = "5a353c630d3faf8e2d333a0983c1c71d5e9b6aed8f4959578fbeb3d3f3172886393b576de0ac1fe86a4dd416cf032543ac1bd066eb82585f779f6ce21237c0cd".to_owned();
@@ -1,5 +1,6 @@
 use super::{Error, Result};
 
+use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::collections::{hash_map::Entry, HashMap, HashSet};
 
@@ -17,6 +18,13 @@ pub struct DAG<V> {
     inv: HashMap<V, Vec<V>>,
     /// `chits` defines a {0, 1} vote for a particular transaction.
     chits: HashMap<V, u8>,
+    /// The memoized conviction of vertices, invalidated whenever their progeny changes.
+    convictions: RefCell<HashMap<V, u8>>,
+    /// The number of convictions served from `convictions`
+    #[cfg(test)]
+    conviction_hits: std::cell::Cell<usize>,
+    /// The number of vertices without children, kept up to date by `insert_vx` and `remove_vx`
+    leaf_count: usize,
 }
 
 impl<V> std::ops::Deref for DAG<V>
@@ -32,7 +40,15 @@ where
 
 impl<V: Clone + Eq + std::hash::Hash + std::fmt::Debug> DAG<V> {
     pub fn new() -> Self {
-        DAG { g: HashMap::default(), inv: HashMap::default(), chits: HashMap::default() }
+        DAG {
+            g: HashMap::default(),
+            inv: HashMap::default(),
+            chits: HashMap::default(),
+            convictions: RefCell::new(HashMap::default()),
+            #[cfg(test)]
+            conviction_hits: std::cell::Cell::new(0),
+            leaf_count: 0,
+        }
     }
 
     /// Inserts a new vertex into the DAG.
@@ -44,12 +60,16 @@ impl<V: Clone + Eq + std::hash::Hash + std::fmt::Debug> DAG<V> {
             Entry::Occupied(_) => (),
             Entry::Vacant(v) => {
                 let _ = v.insert(vec![]);
+                self.leaf_count += 1;
             }
         }
         for ivx in edges.iter() {
             match self.inv.entry(ivx.clone()) {
                 Entry::Occupied(mut o) => {
                     let o = o.get_mut();
+                    if o.is_empty() {
+                        self.leaf_count -= 1;
+                    }
                     o.push(vx.clone());
                 }
                 Entry::Vacant(_) => return Err(Error::VacantEntry),
@@ -85,6 +105,7 @@ impl<V: Clone + Eq + std::hash::Hash + std::fmt::Debug> DAG<V> {
     /// Returns the child vertices (for Sleet to take further action where necessary)
     pub fn remove_vx(&mut self, vx: &V) -> Result<HashSet<V>> {
         let mut children_of_vx = HashSet::new();
+        self.invalidate_conviction(vx);
 
         // Remove the edge pointing to this vertex from the child vertices
         let children = self.inv.get(vx).ok_or(Error::UndefinedVertex)?;
@@ -106,12 +127,20 @@ impl<V: Clone + Eq + std::hash::Hash + std::fmt::Debug> DAG<V> {
                 Entry::Vacant(_) => return Err(Error::VacantEntry),
                 Entry::Occupied(mut o) => {
                     let vec = o.get_mut();
+                    let had_children = !vec.is_empty();
                     vec.retain(|e| e != vx);
+                    if had_children && vec.is_empty() {
+                        self.leaf_count += 1;
+                    }
                 }
             }
         }
         let _ = self.g.remove(vx);
-        let _ = self.inv.remove(vx);
+        if let Some(children) = self.inv.remove(vx) {
+            if children.is_empty() {
+                self.leaf_count -= 1;
+            }
+        }
 
         Ok(children_of_vx)
     }
@@ -126,6 +155,7 @@ impl<V: Clone + Eq + std::hash::Hash + std::fmt::Debug> DAG<V> {
 
     /// Sets the chit of a particular node.
     pub fn set_chit(&mut self, vx: V, chit: u8) -> Result<()> {
+        self.invalidate_conviction(&vx);
         match self.chits.entry(vx) {
             Entry::Occupied(mut o) => {
                 let o = o.get_mut();
@@ -145,7 +175,40 @@ impl<V: Clone + Eq + std::hash::Hash + std::fmt::Debug> DAG<V> {
 
     /// Finds the conviction of a particular node which is the breadth-first-search of
     /// the progeny of a node, summing the chits.
+    ///
+    /// The result is memoized until a chit in the progeny of the node changes, or a vertex
+    /// is added to or removed from it.
     pub fn conviction(&self, vx: V) -> Result<u8> {
+        if let Some(sum) = self.convictions.borrow().get(&vx) {
+            #[cfg(test)]
+            self.conviction_hits.set(self.conviction_hits.get() + 1);
+            return Ok(*sum);
+        }
+        let sum = self.compute_conviction(vx.clone())?;
+        let _ = self.convictions.borrow_mut().insert(vx, sum);
+        Ok(sum)
+    }
+
+    /// Drops the memoized conviction of `vx` and its ancestors, whose progeny contains `vx`.
+    fn invalidate_conviction(&mut self, vx: &V) {
+        let convictions = self.convictions.get_mut();
+        if convictions.is_empty() {
+            return;
+        }
+        let mut visited = HashSet::new();
+        let mut stack = vec![vx.clone()];
+        while let Some(elt) = stack.pop() {
+            let _ = convictions.remove(&elt);
+            for parent in self.g.get(&elt).into_iter().flatten() {
+                if visited.insert(parent.clone()) {
+                    stack.push(parent.clone());
+                }
+            }
+        }
+    }
+
+    /// Computes the conviction of `vx` without the memoized values.
+    fn compute_conviction(&self, vx: V) -> Result<u8> {
         // Mark all vertices as not visited (empty)
         let mut visited: HashMap<V, bool> = HashMap::default();
         // A queue for the breadth first search
@@ -231,6 +294,16 @@ impl<V: Clone + Eq + std::hash::Hash + std::fmt::Debug> DAG<V> {
         leaves
     }
 
+    /// The number of leaves of the DAG, without collecting them like [DAG::leaves].
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    /// Returns `true` if `vx` is a vertex of the DAG without children.
+    pub fn is_leaf(&self, vx: &V) -> bool {
+        self.inv.get(vx).map_or(false, |children| children.is_empty())
+    }
+
     /// Fetches the inverted adjacency list.
     pub fn inverse(&self) -> &HashMap<V, Vec<V>> {
         &self.inv
@@ -239,7 +312,15 @@ impl<V: Clone + Eq + std::hash::Hash + std::fmt::Debug> DAG<V> {
     /// Turns all inbound edges into outbound edges and returns the new graph.
     /// NOTE: This is only for testing.
     pub fn invert(&self) -> DAG<V> {
-        DAG { g: self.inv.clone(), inv: self.g.clone(), chits: self.chits.clone() }
+        DAG {
+            g: self.inv.clone(),
+            inv: self.g.clone(),
+            chits: self.chits.clone(),
+            convictions: RefCell::new(HashMap::default()),
+            #[cfg(test)]
+            conviction_hits: std::cell::Cell::new(0),
+            leaf_count: self.g.values().filter(|edges| edges.is_empty()).count(),
+        }
     }
 
     /// Get all the ancestors, partially ordered (parents precede children)
@@ -251,6 +332,59 @@ impl<V: Clone + Eq + std::hash::Hash + std::fmt::Debug> DAG<V> {
         return sorted;
     }
 
+    /// Get the ancestors at most `max_depth` generations away from `v`, partially ordered like
+    /// [get_ancestors][DAG::get_ancestors].
+    ///
+    /// The returned flag is `true` if the result was truncated, that is `v` has ancestors
+    /// further than `max_depth` generations away.
+    pub fn get_ancestors_to_depth(&self, v: &V, max_depth: usize) -> (Vec<V>, bool) {
+        let mut ancestors: HashSet<V> = HashSet::new();
+        let mut generation = vec![v.clone()];
+        for _ in 0..max_depth {
+            let mut next = vec![];
+            for vx in generation.iter() {
+                for parent in self.g.get(vx).into_iter().flatten() {
+                    if ancestors.insert(parent.clone()) {
+                        next.push(parent.clone());
+                    }
+                }
+            }
+            generation = next;
+        }
+        let truncated = generation.iter().any(|vx| match self.g.get(vx) {
+            Some(parents) => parents.iter().any(|p| !ancestors.contains(p)),
+            None => false,
+        });
+        (self.sort_subset(&ancestors), truncated)
+    }
+
+    /// Topologically sort the vertices of `subset`, visiting only their edges rather than the
+    /// whole DAG. Parents precede children in the result.
+    fn sort_subset(&self, subset: &HashSet<V>) -> Vec<V> {
+        let mut in_degrees: HashMap<V, usize> = subset
+            .iter()
+            .map(|vx| {
+                let parents = self.g.get(vx).into_iter().flatten();
+                (vx.clone(), parents.filter(|p| subset.contains(p)).count())
+            })
+            .collect();
+        let mut queue: VecDeque<V> =
+            in_degrees.iter().filter(|(_, d)| **d == 0).map(|(vx, _)| vx.clone()).collect();
+        let mut result = vec![];
+        while let Some(vx) = queue.pop_front() {
+            for child in self.inv.get(&vx).into_iter().flatten() {
+                if let Some(d) = in_degrees.get_mut(child) {
+                    *d -= 1;
+                    if *d == 0 {
+                        queue.push_back(child.clone());
+                    }
+                }
+            }
+            result.push(vx);
+        }
+        result
+    }
+
     /// Return the elements topologically sorted in a vector
     ///
     /// Kahn's alhorithm is used. Ancestors precede children in the result.
@@ -349,6 +483,11 @@ where
 mod test {
     use super::DAG;
 
+    use rand::Rng;
+
+    use std::collections::HashSet;
+    use std::time::Instant;
+
     #[actix_rt::test]
     async fn test_bfs() {
         let mut dag: DAG<u8> = DAG::new();
@@ -485,6 +624,31 @@ mod test {
         let res: Vec<_> = dag.leaves();
 
         assert_eq!(res, [6]);
+        assert_eq!(dag.leaf_count(), 1);
+        assert!(dag.is_leaf(&6));
+        assert!(!dag.is_leaf(&5));
+    }
+
+    #[actix_rt::test]
+    async fn test_leaf_count() {
+        let mut dag = DAG::new();
+        dag.insert_vx(0, vec![]).unwrap();
+        dag.insert_vx(1, vec![0]).unwrap();
+        dag.insert_vx(2, vec![0]).unwrap();
+        dag.insert_vx(3, vec![0, 0]).unwrap();
+        assert_eq!(dag.leaf_count(), 3);
+        dag.insert_vx(4, vec![1, 2]).unwrap();
+        assert_eq!(dag.leaf_count(), 2);
+        assert_eq!(dag.invert().leaf_count(), 1);
+
+        let _ = dag.remove_vx(&4).unwrap();
+        assert_eq!(dag.leaf_count(), 3);
+        let _ = dag.remove_vx(&3).unwrap();
+        let _ = dag.remove_vx(&2).unwrap();
+        let _ = dag.remove_vx(&1).unwrap();
+        assert_eq!(dag.leaf_count(), 1);
+        assert!(dag.is_leaf(&0));
+        assert_eq!(dag.leaf_count(), dag.leaves().len());
     }
 
     #[actix_rt::test]
@@ -581,6 +745,115 @@ mod test {
         assert_eq!(dag.conviction(0).unwrap(), 11);
     }
 
+    #[actix_rt::test]
+    async fn test_conviction_cache() {
+        let mut rng = rand::thread_rng();
+        let mut dag: DAG<u32> = DAG::new();
+        dag.insert_vx(0, vec![]).unwrap();
+        for i in 1..200 {
+            let vertices: Vec<u32> = dag.keys().cloned().collect();
+            let parents: HashSet<u32> = (0..rng.gen_range(1, 4))
+                .map(|_| vertices[rng.gen_range(0, vertices.len())])
+                .collect();
+            dag.insert_vx(i, parents.into_iter().collect()).unwrap();
+            if rng.gen_bool(0.5) {
+                let _ = dag.set_chit(vertices[rng.gen_range(0, vertices.len())], 1);
+            }
+            if i % 10 == 0 {
+                for vx in dag.keys() {
+                    assert_eq!(dag.conviction(*vx), dag.compute_conviction(*vx));
+                }
+            }
+            if i % 25 == 0 {
+                let leaves = dag.leaves();
+                let _ = dag.remove_vx(&leaves[rng.gen_range(0, leaves.len())]).unwrap();
+            }
+        }
+        for vx in dag.keys() {
+            assert_eq!(dag.conviction(*vx), dag.compute_conviction(*vx));
+        }
+    }
+
+    /// Compares the memoized and the recomputed convictions on a wide graph, querying every
+    /// vertex twice per round like `update_ancestral_preference`.
+    #[actix_rt::test]
+    async fn test_conviction_cache_speedup() {
+        const WIDTH: u32 = 50;
+        const ROUNDS: usize = 10;
+        let mut dag: DAG<u32> = DAG::new();
+        dag.insert_vx(0, vec![]).unwrap();
+        // The root has `WIDTH` children, each of which has `WIDTH` leaves
+        for i in 1..=WIDTH {
+            dag.insert_vx(i, vec![0]).unwrap();
+            dag.set_chit(i, 1).unwrap();
+            for j in 1..=WIDTH {
+                let leaf = i * (WIDTH + 1) + j;
+                dag.insert_vx(leaf, vec![i]).unwrap();
+            }
+            // Keep the conviction of the root within a `u8`
+            dag.set_chit(i * (WIDTH + 1) + 1, 1).unwrap();
+        }
+        let vertices: Vec<u32> = dag.keys().cloned().collect();
+
+        let started = Instant::now();
+        let mut recomputed = vec![];
+        for _ in 0..ROUNDS {
+            for vx in vertices.iter() {
+                recomputed.push(dag.compute_conviction(*vx).unwrap());
+                recomputed.push(dag.compute_conviction(*vx).unwrap());
+            }
+        }
+        let uncached = started.elapsed();
+
+        let started = Instant::now();
+        let mut memoized = vec![];
+        for _ in 0..ROUNDS {
+            for vx in vertices.iter() {
+                memoized.push(dag.conviction(*vx).unwrap());
+                memoized.push(dag.conviction(*vx).unwrap());
+            }
+        }
+        let cached = started.elapsed();
+
+        println!(
+            "{} conviction queries: {:?} uncached, {:?} cached",
+            memoized.len(),
+            uncached,
+            cached
+        );
+        assert_eq!(memoized, recomputed);
+        assert!(cached < uncached);
+    }
+
+    #[actix_rt::test]
+    async fn test_conviction_memoized() {
+        let mut dag: DAG<u32> = DAG::new();
+        dag.insert_vx(0, vec![]).unwrap();
+        for i in 1..=10 {
+            dag.insert_vx(i, vec![i - 1]).unwrap();
+            dag.set_chit(i, 1).unwrap();
+        }
+        assert_eq!(dag.conviction(0).unwrap(), 10);
+        assert_eq!(dag.conviction_hits.get(), 0);
+        assert_eq!(dag.conviction(0).unwrap(), 10);
+        assert_eq!(dag.conviction(0).unwrap(), 10);
+        assert_eq!(dag.conviction_hits.get(), 2);
+
+        // A new vertex in the progeny invalidates the memoized conviction
+        dag.insert_vx(11, vec![10]).unwrap();
+        dag.set_chit(11, 1).unwrap();
+        assert_eq!(dag.conviction(0).unwrap(), 11);
+        assert_eq!(dag.conviction_hits.get(), 2);
+        assert_eq!(dag.conviction(0).unwrap(), 11);
+        assert_eq!(dag.conviction_hits.get(), 3);
+
+        // A vertex outside of the progeny of `5` keeps its memoized conviction
+        assert_eq!(dag.conviction(5).unwrap(), 7);
+        dag.insert_vx(12, vec![0]).unwrap();
+        assert_eq!(dag.conviction(5).unwrap(), 7);
+        assert_eq!(dag.conviction_hits.get(), 4);
+    }
+
     #[actix_rt::test]
     async fn test_has_vertices() {
         let mut dag: DAG<u8> = DAG::new();
@@ -658,6 +931,43 @@ mod test {
         assert!(anc.is_empty());
     }
 
+    #[actix_rt::test]
+    async fn test_get_ancestors_to_depth() {
+        let mut dag = DAG::new();
+        dag.insert_vx(0, vec![]).unwrap();
+        for i in 1..10 {
+            dag.insert_vx(i, vec![i - 1]).unwrap();
+        }
+
+        assert_eq!(dag.get_ancestors_to_depth(&9, 2), (vec![7, 8], true));
+        assert_eq!(dag.get_ancestors_to_depth(&9, 0), (vec![], true));
+        assert_eq!(dag.get_ancestors_to_depth(&9, 9), (dag.get_ancestors(&9), false));
+        assert_eq!(dag.get_ancestors_to_depth(&9, 20), (dag.get_ancestors(&9), false));
+        assert_eq!(dag.get_ancestors_to_depth(&0, 2), (vec![], false));
+    }
+
+    #[actix_rt::test]
+    async fn test_get_ancestors_to_depth_partial_order() {
+        let mut dag = DAG::new();
+        dag.insert_vx(0, vec![]).unwrap();
+        dag.insert_vx(1, vec![0]).unwrap();
+        dag.insert_vx(2, vec![0]).unwrap();
+        dag.insert_vx(3, vec![1, 2]).unwrap();
+        dag.insert_vx(4, vec![3, 0]).unwrap();
+
+        let (ancestors, truncated) = dag.get_ancestors_to_depth(&4, 2);
+        assert!(!truncated);
+        assert_eq!(ancestors.len(), 4);
+        assert_eq!(ancestors[0], 0);
+        assert_eq!(ancestors[3], 3);
+
+        // Only the ancestors within reach are sorted
+        let (ancestors, truncated) = dag.get_ancestors_to_depth(&4, 1);
+        assert!(truncated);
+        assert_eq!(ancestors.len(), 2);
+        assert!(ancestors.contains(&0) && ancestors.contains(&3));
+    }
+
     #[actix_rt::test]
     async fn test_double_ancestry() {
         let mut dag = DAG::new();
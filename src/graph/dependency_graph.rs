@@ -1,8 +1,12 @@
 //! The [`DependencyGraph`] keeps track of spending dependecies between cells
+//!
+//! [`topological_order`] orders a batch of cells by their dependencies, such that they can be
+//! applied one by one, for example when replaying accepted cells into a fresh state.
 use super::{Error, Result};
+use crate::cell::types::CellHash;
 use crate::cell::{Cell, CellIds};
 
-use std::collections::{hash_map::Entry, HashMap, VecDeque};
+use std::collections::{hash_map::Entry, BTreeSet, HashMap, HashSet, VecDeque};
 
 /// The dependency graph is a cell graph which maps produced outputs to consumed inputs in cells.
 /// The cell graphs purpose is to order cells by their dependencies.
@@ -113,12 +117,75 @@ impl DependencyGraph {
     }
 }
 
+/// Orders `cells` such that the cells producing the outputs spent by a cell precede it.
+/// Independent cells keep their relative order in `cells`.
+///
+/// Fails if a cell spends an output of a cell which isn't part of `cells`, or if the inputs
+/// of the cells form a cycle.
+pub fn topological_order(cells: &[Cell]) -> Result<Vec<CellHash>> {
+    let hashes: Vec<CellHash> = cells.iter().map(|cell| cell.hash()).collect();
+    let dependencies: Vec<HashSet<CellHash>> = cells
+        .iter()
+        .map(|cell| cell.inputs().iter().map(|input| input.output_index.cell_hash).collect())
+        .collect();
+    order_by_dependencies(&hashes, &dependencies)
+}
+
+/// Orders `hashes` such that each hash comes after the hashes it depends upon, where
+/// `dependencies[i]` are the dependencies of `hashes[i]`.
+fn order_by_dependencies(
+    hashes: &[CellHash],
+    dependencies: &[HashSet<CellHash>],
+) -> Result<Vec<CellHash>> {
+    let mut positions = HashMap::new();
+    for (i, hash) in hashes.iter().enumerate() {
+        if positions.insert(*hash, i).is_some() {
+            return Err(Error::DuplicateCell);
+        }
+    }
+
+    // The positions of the dependents of each hash, and the number of unordered dependencies
+    let mut dependents: Vec<Vec<usize>> = vec![vec![]; hashes.len()];
+    let mut unordered = vec![0usize; hashes.len()];
+    for (i, deps) in dependencies.iter().enumerate() {
+        for dep in deps.iter() {
+            match positions.get(dep) {
+                Some(j) => {
+                    dependents[*j].push(i);
+                    unordered[i] += 1;
+                }
+                None => return Err(Error::MissingDependency(*dep)),
+            }
+        }
+    }
+
+    let mut ready: BTreeSet<usize> = (0..hashes.len()).filter(|i| unordered[*i] == 0).collect();
+    let mut sorted = vec![];
+    while let Some(i) = ready.iter().next().cloned() {
+        let _ = ready.remove(&i);
+        sorted.push(hashes[i]);
+        for j in dependents[i].iter() {
+            unordered[*j] -= 1;
+            if unordered[*j] == 0 {
+                let _ = ready.insert(*j);
+            }
+        }
+    }
+    // Hashes which are part of a cycle never become ready
+    if sorted.len() < hashes.len() {
+        return Err(Error::DependencyCycle);
+    }
+    Ok(sorted)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
     use crate::alpha::coinbase::CoinbaseOperation;
-    use crate::alpha::transfer::TransferOperation;
+    use crate::alpha::transfer::{self, TransferOperation};
+    use crate::cell::inputs::{Input, Inputs};
+    use crate::cell::outputs::Outputs;
 
     use std::convert::TryInto;
 
@@ -186,6 +253,91 @@ mod test {
         );
     }
 
+    #[actix_rt::test]
+    async fn test_topological_order_chain() {
+        let (kp1, _kp2, pkh1, _pkh2) = generate_keys();
+
+        let genesis_op = CoinbaseOperation::new(vec![(pkh1.clone(), 1000)]);
+        let genesis_tx: Cell = genesis_op.try_into().unwrap();
+        let tx1 = TransferOperation::new(genesis_tx.clone(), pkh1.clone(), pkh1.clone(), 900)
+            .transfer(&kp1)
+            .unwrap();
+        let tx2 = TransferOperation::new(tx1.clone(), pkh1.clone(), pkh1.clone(), 800)
+            .transfer(&kp1)
+            .unwrap();
+        let tx3 = TransferOperation::new(tx2.clone(), pkh1.clone(), pkh1.clone(), 700)
+            .transfer(&kp1)
+            .unwrap();
+
+        let expected = vec![genesis_tx.hash(), tx1.hash(), tx2.hash(), tx3.hash()];
+        let cells = vec![tx3.clone(), tx1.clone(), genesis_tx.clone(), tx2.clone()];
+        assert_eq!(topological_order(&cells).unwrap(), expected);
+        let cells = vec![genesis_tx.clone(), tx1.clone(), tx2.clone(), tx3.clone()];
+        assert_eq!(topological_order(&cells).unwrap(), expected);
+
+        // The producer of the outputs spent by `tx2` is missing
+        let cells = vec![genesis_tx.clone(), tx2.clone(), tx3.clone()];
+        assert_eq!(topological_order(&cells), Err(Error::MissingDependency(tx1.hash())));
+    }
+
+    #[actix_rt::test]
+    async fn test_topological_order_diamond() {
+        let (kp1, kp2, pkh1, pkh2) = generate_keys();
+
+        let genesis_op = CoinbaseOperation::new(vec![(pkh1.clone(), 1000), (pkh2.clone(), 1000)]);
+        let genesis_tx: Cell = genesis_op.try_into().unwrap();
+        // `tx1` and `tx2` spend different outputs of the genesis
+        let tx1 = TransferOperation::new(genesis_tx.clone(), pkh1.clone(), pkh1.clone(), 900)
+            .transfer(&kp1)
+            .unwrap();
+        let tx2 = TransferOperation::new(genesis_tx.clone(), pkh2.clone(), pkh2.clone(), 900)
+            .transfer(&kp2)
+            .unwrap();
+        // `tx3` spends outputs of both `tx1` and `tx2`
+        let inputs = vec![
+            Input::new(&kp1, tx1.hash(), 0).unwrap(),
+            Input::new(&kp2, tx2.hash(), 0).unwrap(),
+        ];
+        let tx3 = Cell::new(
+            Inputs::new(inputs),
+            Outputs::new(vec![transfer::transfer_output(pkh1.clone(), 1500).unwrap()]),
+        );
+
+        let cells = vec![tx3.clone(), tx2.clone(), tx1.clone(), genesis_tx.clone()];
+        let sorted = topological_order(&cells).unwrap();
+        // Independent cells keep their relative order
+        assert_eq!(sorted, vec![genesis_tx.hash(), tx2.hash(), tx1.hash(), tx3.hash()]);
+    }
+
+    #[actix_rt::test]
+    async fn test_topological_order_cycle() {
+        // Cells spending each others outputs can't be built, as the hash of a cell covers its
+        // inputs, so the cycle is set up on the hashes directly
+        let (h1, h2, h3, h4) = ([1u8; 32], [2u8; 32], [3u8; 32], [4u8; 32]);
+        let hashes = vec![h1, h2, h3, h4];
+        let dependencies: Vec<HashSet<CellHash>> = vec![
+            HashSet::new(),
+            vec![h1, h4].into_iter().collect(),
+            vec![h2].into_iter().collect(),
+            vec![h3].into_iter().collect(),
+        ];
+        assert_eq!(order_by_dependencies(&hashes, &dependencies), Err(Error::DependencyCycle));
+
+        // Without the edge from `h2` to `h4` the hashes can be ordered
+        let dependencies: Vec<HashSet<CellHash>> = vec![
+            HashSet::new(),
+            vec![h1].into_iter().collect(),
+            vec![h2].into_iter().collect(),
+            vec![h3].into_iter().collect(),
+        ];
+        assert_eq!(order_by_dependencies(&hashes, &dependencies), Ok(hashes.clone()));
+
+        // A cell can't depend on itself
+        let dependencies: Vec<HashSet<CellHash>> =
+            vec![vec![h1].into_iter().collect(), HashSet::new(), HashSet::new(), HashSet::new()];
+        assert_eq!(order_by_dependencies(&hashes, &dependencies), Err(Error::DependencyCycle));
+    }
+
     fn hash_public(keypair: &Keypair) -> [u8; 32] {
         let enc = bincode::serialize(&keypair.public).unwrap();
         blake3::hash(&enc).as_bytes().clone()
@@ -23,6 +23,8 @@ pub enum Error {
     DuplicateCell,
     UndefinedCell,
     UndefinedCellHash(CellHash),
+    MissingDependency(CellHash),
+    DependencyCycle,
 }
 
 impl std::error::Error for Error {}
@@ -3,9 +3,21 @@ use super::vertex::Vertex;
 use super::{Error, Result};
 use crate::alpha::block::Block;
 use crate::alpha::types::{BlockHash, BlockHeight, VrfOutput};
+use crate::cell::types::PublicKeyHash;
+use crate::zfx_id::Id;
 
 use crate::colored::Colorize;
 
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+
+/// The signature of the producer of a [HailBlock] over the hash of its inner block.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ProducerSignature {
+    /// The public key of the owner of the producer's stake
+    pub public_key: PublicKey,
+    pub signature: Signature,
+}
+
 /// The `HailBlock` is a consensus specific representation of a block which contains a real block
 /// along with a parent vertex which points to its predecessor (must be height - 1).
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -14,12 +26,38 @@ pub struct HailBlock {
     parent: Option<Vertex>,
     /// The inner block contents.
     block: Block,
+    /// The signature of the producer, `None` until the block is [signed](HailBlock::sign).
+    signature: Option<ProducerSignature>,
 }
 
 impl HailBlock {
     /// Create a new block
     pub fn new(parent: Option<Vertex>, block: Block) -> Self {
-        HailBlock { parent, block }
+        HailBlock { parent, block, signature: None }
+    }
+
+    /// Signs the hash of the inner block with `keypair`, which owns the stake of the producer.
+    pub fn sign(mut self, keypair: &Keypair) -> Result<Self> {
+        let signature = keypair.sign(&self.hash()?);
+        self.signature = Some(ProducerSignature { public_key: keypair.public, signature });
+        Ok(self)
+    }
+
+    /// Checks that the block is signed by the key whose hash is `owner`, the owner of the
+    /// producer's stake.
+    ///
+    /// Throws [Error::InvalidBlockSignature] otherwise.
+    pub fn verify_signature(&self, owner: &PublicKeyHash) -> Result<()> {
+        let hash = self.hash()?;
+        match self.signature {
+            Some(ProducerSignature { ref public_key, ref signature })
+                if Id::from_public_key(public_key).bytes() == *owner
+                    && public_key.verify(&hash, signature).is_ok() =>
+            {
+                Ok(())
+            }
+            _ => Err(Error::InvalidBlockSignature(hash)),
+        }
     }
 
     /// Returns the parent vertex of this block if this block is not genesis.
@@ -37,6 +75,11 @@ impl HailBlock {
         self.block.vrf_out.clone()
     }
 
+    /// Returns the validator which produced the block, `None` for the genesis.
+    pub fn producer(&self) -> Option<Id> {
+        self.block.producer
+    }
+
     /// Returns a vertex formed from the height and the hash of the block.
     pub fn vertex(&self) -> Result<Vertex> {
         Ok(Vertex::new(self.height(), self.hash()?))
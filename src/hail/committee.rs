@@ -1,10 +1,12 @@
 use zfx_sortition::sortition;
 
-use crate::alpha::types::{VrfOutput, Weight};
+use crate::alpha::types::{BlockHeight, VrfOutput, Weight};
+use crate::cell::types::PublicKeyHash;
 use crate::util;
 use crate::zfx_id::Id;
 
-use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 
 use tracing::*;
@@ -13,18 +15,58 @@ use crate::colored::Colorize;
 
 type StakingCapacity = u64;
 
+/// A stake of a validator which only counts towards the committee from `start_height`
+/// (inclusive) until `end_height` (exclusive), as recorded by its
+/// [StakeState][crate::alpha::stake::StakeState].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct StakingPeriod {
+    pub id: Id,
+    pub capacity: StakingCapacity,
+    pub start_height: BlockHeight,
+    pub end_height: BlockHeight,
+}
+
+impl StakingPeriod {
+    /// Whether the stake counts towards the committee at `height`.
+    pub fn is_active(&self, height: BlockHeight) -> bool {
+        self.start_height <= height && height < self.end_height
+    }
+}
+
 pub struct Committee {
     self_id: Id,
     self_staking_capacity: u64,
-    /// The list of validators and allocations which formed this committee.
+    /// The list of validators and allocations which formed this committee, including the
+    /// `stakes` which are inactive at its height.
     validators: HashMap<Id, (SocketAddr, StakingCapacity)>,
+    /// The staking periods of the live stakes, applied when advancing the committee.
+    stakes: Vec<StakingPeriod>,
     /// The validating committee.
     committee: HashMap<Id, (SocketAddr, Weight)>,
     /// The block production vrf (a vrf for `height + 1` if we are the next block producer).
     block_production_slot: Option<VrfOutput>,
     /// Whether we have already proposed a block at this height.
     block_proposed: bool,
-    block_producers: HashSet<VrfOutput>,
+    /// The inputs of the block producer sortition, for verifying the producers of received blocks.
+    selection: Option<ProducerSelection>,
+    /// The owners of the validators' stakes, which sign the blocks the validators produce.
+    stake_owners: HashMap<Id, PublicKeyHash>,
+}
+
+/// The inputs of the block producer sortition for the next height, see [is_legitimate_producer].
+#[derive(Debug, Clone)]
+struct ProducerSelection {
+    seed: VrfOutput,
+    stakes: HashMap<Id, StakingCapacity>,
+    total_stake: StakingCapacity,
+    expected_size: f64,
+}
+
+impl ProducerSelection {
+    fn is_legitimate(&self, id: Id, vrf_out: &VrfOutput) -> bool {
+        let stake = self.stakes.get(&id).cloned().unwrap_or(0);
+        is_legitimate_producer(id, stake, &self.seed, vrf_out, self.total_stake, self.expected_size)
+    }
 }
 
 impl std::ops::Deref for Committee {
@@ -40,49 +82,67 @@ fn compute_vrf_h(id: Id, vrf_out: &VrfOutput) -> [u8; 32] {
     blake3::hash(&vrf_h).as_bytes().clone()
 }
 
+/// Returns `true` if `id`, staking `stake` out of `total_stake`, is entitled to produce the block
+/// whose VRF output is `vrf_out`, given the `seed` of the sortition at the block's height.
+///
+/// `vrf_out` has to be derived from `id` and `seed`, and be selected by the sortition of
+/// `expected_size` producers. The verdict only depends on the inputs, so the producer and every
+/// node verifying its block reach the same one.
+pub fn is_legitimate_producer(
+    id: Id,
+    stake: StakingCapacity,
+    seed: &VrfOutput,
+    vrf_out: &VrfOutput,
+    total_stake: StakingCapacity,
+    expected_size: f64,
+) -> bool {
+    *vrf_out == compute_vrf_h(id, seed)
+        && sortition::select(stake, total_stake, expected_size, vrf_out) > 0
+}
+
 impl Committee {
     pub fn empty(self_id: Id) -> Self {
         Committee {
             self_id,
             self_staking_capacity: 0u64,
             validators: HashMap::default(),
+            stakes: vec![],
             committee: HashMap::default(),
             block_production_slot: None,
             block_proposed: false,
-            block_producers: HashSet::default(),
+            selection: None,
+            stake_owners: HashMap::default(),
         }
     }
 
-    fn calculate_total_staking_capacity(&self, init: StakingCapacity) -> StakingCapacity {
+    fn calculate_total_staking_capacity(
+        validators: &HashMap<Id, (SocketAddr, StakingCapacity)>,
+        init: StakingCapacity,
+    ) -> StakingCapacity {
         let mut total_staking_capacity = init;
-        for (_, (_, staking_capacity)) in self.validators.iter() {
+        for (_, (_, staking_capacity)) in validators.iter() {
             total_staking_capacity += staking_capacity;
         }
         info!("[{}] total_staking_capacity = {:?}", "committee".yellow(), total_staking_capacity);
         total_staking_capacity
     }
 
-    pub fn next_committee(
+    fn next_committee(
         &mut self,
         vrf_output: VrfOutput,
+        self_staking_capacity: StakingCapacity,
         validators: HashMap<Id, (SocketAddr, StakingCapacity)>,
-    ) -> (HashMap<Id, (SocketAddr, Weight)>, HashSet<VrfOutput>, Option<VrfOutput>) {
+    ) -> (HashMap<Id, (SocketAddr, Weight)>, ProducerSelection, Option<VrfOutput>) {
         let expected_size = (validators.len() as f64).sqrt().ceil() + 100.0;
         info!("[{}] expected_size = {:?}", "committee".yellow(), expected_size);
 
         let total_staking_capacity =
-            self.calculate_total_staking_capacity(self.self_staking_capacity);
+            Committee::calculate_total_staking_capacity(&validators, self_staking_capacity);
 
         let mut committee = HashMap::default();
-        let mut block_producers = HashSet::new();
+        let mut stakes = HashMap::default();
         for (id, (ip, staking_capacity)) in validators.iter() {
-            let vrf_h = compute_vrf_h(id.clone(), &vrf_output);
-            let s_w =
-                sortition::select(*staking_capacity, total_staking_capacity, expected_size, &vrf_h);
-            // If the sortition weight > 0 then this `id` is a block producer.
-            if s_w > 0 {
-                block_producers.insert(vrf_h.clone());
-            }
+            let _ = stakes.insert(id.clone(), *staking_capacity);
             info!("percent_of {:?}, total = {:?}", *staking_capacity, total_staking_capacity);
             let v_w = util::percent_of(*staking_capacity, total_staking_capacity);
             if let Some(_) = committee.insert(id.clone(), (ip.clone(), v_w)) {
@@ -93,18 +153,16 @@ impl Committee {
         }
 
         // Compute whether we are a block producer
-        let mut block_production_slot = None;
-        let vrf_h = compute_vrf_h(self.self_id.clone(), &vrf_output);
-        let s_w = sortition::select(
-            self.self_staking_capacity,
-            total_staking_capacity,
+        let _ = stakes.insert(self.self_id.clone(), self_staking_capacity);
+        let selection = ProducerSelection {
+            seed: vrf_output,
+            stakes,
+            total_stake: total_staking_capacity,
             expected_size,
-            &vrf_h,
-        );
-        if s_w > 0 {
-            block_producers.insert(vrf_h.clone());
-            block_production_slot = Some(vrf_h.clone());
-        }
+        };
+        let vrf_h = compute_vrf_h(self.self_id.clone(), &vrf_output);
+        let block_production_slot =
+            if selection.is_legitimate(self.self_id, &vrf_h) { Some(vrf_h) } else { None };
 
         info!(
             "[{}] is_block_producer = {:?}",
@@ -112,28 +170,74 @@ impl Committee {
             block_production_slot.is_some()
         );
 
-        (committee, block_producers, block_production_slot)
+        (committee, selection, block_production_slot)
     }
 
+    /// Sets the staking periods of the live stakes, see [Committee::next].
+    pub fn set_stakes(&mut self, stakes: Vec<StakingPeriod>) {
+        self.stakes = stakes;
+    }
+
+    /// Returns `validators` and `self_staking_capacity` without the stakes which are inactive
+    /// at `height`. Validators left without active stake are dropped.
+    fn apply_stakes(
+        &self,
+        height: BlockHeight,
+        mut self_staking_capacity: StakingCapacity,
+        mut validators: HashMap<Id, (SocketAddr, StakingCapacity)>,
+    ) -> (StakingCapacity, HashMap<Id, (SocketAddr, StakingCapacity)>) {
+        for stake in self.stakes.iter().filter(|stake| !stake.is_active(height)) {
+            if stake.id == self.self_id {
+                self_staking_capacity = self_staking_capacity.saturating_sub(stake.capacity);
+            } else if let Entry::Occupied(mut entry) = validators.entry(stake.id) {
+                let (_, capacity) = entry.get_mut();
+                *capacity = capacity.saturating_sub(stake.capacity);
+                if *capacity == 0 {
+                    let _ = entry.remove();
+                }
+            }
+        }
+        (self_staking_capacity, validators)
+    }
+
+    /// Advance the committee to `height`, formed by `validators` without the stakes which are
+    /// inactive at `height`.
     pub fn next(
         &mut self,
+        height: BlockHeight,
         self_staking_capacity: u64,
         vrf_output: VrfOutput,
         validators: HashMap<Id, (SocketAddr, StakingCapacity)>,
     ) {
         self.self_staking_capacity = self_staking_capacity;
         self.validators = validators.clone();
-        let (committee, block_producers, block_production_slot) =
-            self.next_committee(vrf_output, validators);
+        let (self_staking_capacity, validators) =
+            self.apply_stakes(height, self_staking_capacity, validators);
+        let (committee, selection, block_production_slot) =
+            self.next_committee(vrf_output, self_staking_capacity, validators);
         self.committee = committee;
-        self.block_producers = block_producers;
+        self.selection = Some(selection);
         self.block_production_slot = block_production_slot;
         self.block_proposed = false;
     }
 
-    #[allow(unused)] // Currently not used
-    pub fn is_valid_vrf(&self, vrf_output: VrfOutput) -> bool {
-        self.block_producers.contains(&vrf_output)
+    /// Returns `true` if `producer` is entitled to produce the block of the next height whose
+    /// VRF output is `vrf_output`, see [is_legitimate_producer].
+    pub fn is_legitimate_producer(&self, producer: Id, vrf_output: VrfOutput) -> bool {
+        match self.selection {
+            Some(ref selection) => selection.is_legitimate(producer, &vrf_output),
+            None => false,
+        }
+    }
+
+    /// Sets the owners of the validators' stakes, see [Committee::stake_owner].
+    pub fn set_stake_owners(&mut self, stake_owners: HashMap<Id, PublicKeyHash>) {
+        self.stake_owners = stake_owners;
+    }
+
+    /// Returns the owner of the stake of `validator`, which signs the blocks it produces.
+    pub fn stake_owner(&self, validator: &Id) -> Option<PublicKeyHash> {
+        self.stake_owners.get(validator).cloned()
     }
 
     pub fn block_production_slot(&self) -> Option<VrfOutput> {
@@ -144,6 +248,7 @@ impl Committee {
         self.self_staking_capacity.clone()
     }
 
+    /// Returns the validators which formed this committee, including the inactive stakes.
     pub fn validators(&self) -> HashMap<Id, (SocketAddr, StakingCapacity)> {
         self.validators.clone()
     }
@@ -156,3 +261,73 @@ impl Committee {
         self.block_proposed = proposed;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::alpha::block::genesis_vrf_out;
+
+    #[actix_rt::test]
+    async fn test_staking_period() {
+        let ip: SocketAddr = "0.0.0.0:1234".parse().unwrap();
+        let mut committee = Committee::empty(Id::one());
+        let mut validators = HashMap::new();
+        let _ = validators.insert(Id::two(), (ip, 1000));
+        let _ = validators.insert(Id::zero(), (ip, 1000));
+        committee.set_stakes(vec![
+            StakingPeriod { id: Id::zero(), capacity: 1000, start_height: 5, end_height: 10 },
+            StakingPeriod { id: Id::one(), capacity: 1000, start_height: 0, end_height: 8 },
+        ]);
+
+        let vrf_out = genesis_vrf_out().unwrap();
+        for height in 0..15 {
+            committee.next(height, 1000, vrf_out, validators.clone());
+            // The stake of `Id::zero()` activates at height 5 and expires at height 10
+            assert_eq!(committee.contains_key(&Id::zero()), (5..10).contains(&height));
+            assert!(committee.contains_key(&Id::two()));
+            // The stake of this node ends at height 8
+            if height >= 8 {
+                assert_eq!(committee.block_production_slot, None);
+            }
+            // The inactive stakes are still passed on
+            assert_eq!(committee.validators(), validators);
+            assert_eq!(committee.self_staking_capacity(), 1000);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_producer_and_verifier_agree() {
+        let ip: SocketAddr = "0.0.0.0:1234".parse().unwrap();
+        let (producer, verifier) = (Id::one(), Id::two());
+        let seed = genesis_vrf_out().unwrap();
+
+        let mut producer_committee = Committee::empty(producer);
+        let mut validators = HashMap::new();
+        let _ = validators.insert(verifier, (ip, 1000));
+        producer_committee.next(1, 1000, seed, validators);
+        let vrf_out = producer_committee.block_production_slot().unwrap();
+
+        let mut verifier_committee = Committee::empty(verifier);
+        let mut validators = HashMap::new();
+        let _ = validators.insert(producer, (ip, 1000));
+        verifier_committee.next(1, 1000, seed, validators);
+        assert!(verifier_committee.is_legitimate_producer(producer, vrf_out));
+        // The VRF output of the producer doesn't entitle the verifier
+        assert!(!verifier_committee.is_legitimate_producer(verifier, vrf_out));
+
+        // The same inputs yield the same verdict on every node
+        let expected_size = producer_committee.selection.as_ref().unwrap().expected_size;
+        for _ in 0..2 {
+            assert!(is_legitimate_producer(producer, 1000, &seed, &vrf_out, 2000, expected_size));
+        }
+        // The VRF output of a producer doesn't entitle another one
+        assert!(!is_legitimate_producer(verifier, 1000, &seed, &vrf_out, 2000, expected_size));
+
+        // A tampered VRF output is rejected
+        let mut tampered = vrf_out;
+        tampered[0] ^= 1;
+        assert!(!is_legitimate_producer(producer, 1000, &seed, &tampered, 2000, expected_size));
+        assert!(!verifier_committee.is_legitimate_producer(producer, tampered));
+    }
+}
@@ -6,7 +6,7 @@ use super::vertex::Vertex;
 use crate::alpha::block::Block;
 use crate::alpha::types::{BlockHash, BlockHeight};
 
-use super::hail::BETA1;
+use super::hail::{BETA1, BETA2};
 
 use std::collections::{hash_map::Entry, HashMap};
 
@@ -87,6 +87,25 @@ impl ConflictMap {
         }
     }
 
+    /// Marks the block of `vx` as preferred within its conflicting set, with the confidence of a
+    /// final block. Used for the blocks of a peer's accepted frontier, which were decided before.
+    pub fn set_accepted(&mut self, vx: &Vertex) -> Result<()> {
+        match self.inner.get_mut(&vx.height) {
+            Some(cs) => {
+                cs.pref = vx.block_hash;
+                cs.last = vx.block_hash;
+                cs.cnt = BETA2;
+                Ok(())
+            }
+            None => Err(Error::InvalidBlockHeight(vx.height)),
+        }
+    }
+
+    /// Removes the conflict sets up to `height`, which are final.
+    pub fn remove_up_to(&mut self, height: BlockHeight) {
+        self.inner.retain(|h, _| *h > height);
+    }
+
     pub fn update_conflict_set(
         &mut self,
         height: BlockHeight,
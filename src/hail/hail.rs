@@ -1,28 +1,39 @@
 use crate::zfx_id::Id;
 
 use crate::alpha::block::Block;
+use crate::alpha::coinbase;
 use crate::alpha::types::{BlockHash, BlockHeight, VrfOutput, Weight};
-use crate::cell::Cell;
-use crate::client::{ClientRequest, ClientResponse};
+use crate::cell::types::{Capacity, ChainId, PublicKeyHash, DEFAULT_CHAIN_ID};
+use crate::cell::{self, Cell, CellTypeRegistry};
+use crate::client::{self, ClientError, ClientRequest, ClientResponse};
 use crate::colored::Colorize;
 use crate::graph::DAG;
+use crate::metrics::METRICS;
 use crate::protocol::{Request, Response};
+use crate::sleet::AcceptedHeight;
 use crate::storage::hail_block as block_storage;
+use crate::storage::{KvStore, SledStore};
 use crate::util;
+use crate::view;
 
 use super::block::HailBlock;
-use super::committee::Committee;
+use super::committee::{Committee, StakingPeriod};
 use super::conflict_map::ConflictMap;
 use super::vertex::Vertex;
 use super::{Error, Result};
 
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
-use actix::{Actor, AsyncContext, Context, Handler, Recipient};
-use actix::{ActorFutureExt, ResponseActFuture};
+use actix::{Actor, ActorContext, AsyncContext, Context, Handler, Recipient};
+use actix::{ActorFutureExt, ResponseActFuture, WrapFuture};
 
-use std::collections::{HashMap, HashSet};
+use ed25519_dalek::Keypair;
+use rand::rngs::OsRng;
+
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 // Safety parameters
 
@@ -33,6 +44,11 @@ pub const BETA1: u8 = 11;
 /// Snow* parameter beta2 -- commitment threshold
 pub const BETA2: u8 = 20;
 
+// Constants
+
+/// The default maximum number of blocks returned for a [GetBlocksByRange] request
+pub const MAX_BLOCKS_RANGE: u64 = 100;
+
 /// Hail is a Snow* based consensus for blocks. `Hail` is the main actor.
 pub struct Hail {
     /// The hash of the last accepted block (at the current block height).
@@ -45,10 +61,12 @@ pub struct Hail {
     node_id: Id,
     /// The current block committee.
     committee: Committee,
+    /// The reputations of validators which misbehaved lately, biasing the sampling.
+    reputations: HashMap<Id, f64>,
     /// The set of all known blocks.
-    known_blocks: sled::Db,
+    known_blocks: Arc<dyn KvStore>,
     /// The set of all queried blocks.
-    queried_blocks: sled::Db,
+    queried_blocks: Arc<dyn KvStore>,
     /// The map of conflicting blocks at a particular height
     conflict_map: ConflictMap,
     /// A mapping of block hashes to live blocks.
@@ -57,24 +75,153 @@ pub struct Hail {
     accepted_vertices: HashSet<Vertex>,
     /// The consensus graph.
     dag: DAG<Vertex>,
+    /// Peers to bootstrap
+    bootstrap_peers: Vec<(Id, SocketAddr)>,
+    /// The accepted frontier collected from `bootstrap_peers` during bootstrapping
+    bootstrap_frontier: HashSet<Vertex>,
+    /// The previous accepted frontier, used during bootstrapping
+    old_frontier: HashSet<Vertex>,
+    /// `true` if Hail is bootstrapped
+    bootstrapped: bool,
+    /// The in-flight [FreshBlock] queries, which a [Shutdown] waits for
+    drain: util::QueryDrain,
+    /// The network the signatures of the blocks' cells are made for
+    chain_id: ChainId,
+    /// The validators of the custom outputs of the blocks' cells
+    cell_types: CellTypeRegistry,
+    /// The maximum number of blocks returned for a [GetBlocksByRange] request
+    max_blocks_range: u64,
+    /// The last checkpoint, the blocks up to its height are final and pruned from memory
+    checkpoint: Option<Vertex>,
+    /// The blocks of the chain up to the checkpoint, by height
+    finalized_blocks: Arc<dyn KvStore>,
+    /// The address collecting the fees of the blocks produced by this validator
+    fee_address: Option<PublicKeyHash>,
+    /// The keypair owning the stake of this validator, signing the blocks it produces
+    keypair: Keypair,
+    /// Slash cells to include in the next block produced by this validator
+    pending_slashes: Vec<Cell>,
+    /// The subscribers of the [AcceptedHeight] updates
+    height_recipients: Vec<Recipient<AcceptedHeight>>,
 }
 
 impl Hail {
     /// Hail is initialised with the most recent `frontier`, which is the last set of
     /// blocks yet to become final.
-    pub fn new(sender: Recipient<ClientRequest>, node_id: Id) -> Self {
+    /// * `sender` - a recipient of the [Client](crate::client::Client) for sending remote requests
+    /// to other nodes in the network.
+    /// * `node_id` - node ID
+    /// * `bootstrap_peers` - a list of peers which are used for bootstrapping the accepted frontier
+    pub fn new(
+        sender: Recipient<ClientRequest>,
+        node_id: Id,
+        bootstrap_peers: Vec<(Id, SocketAddr)>,
+    ) -> Self {
         Hail {
             last_accepted_hash: None,
             height: 0,
             sender,
             node_id: node_id.clone(),
             committee: Committee::empty(node_id),
-            known_blocks: sled::Config::new().temporary(true).open().unwrap(),
-            queried_blocks: sled::Config::new().temporary(true).open().unwrap(),
+            reputations: HashMap::default(),
+            known_blocks: Arc::new(SledStore::temporary().unwrap()),
+            queried_blocks: Arc::new(SledStore::temporary().unwrap()),
             conflict_map: ConflictMap::new(),
             live_blocks: HashMap::default(),
             accepted_vertices: HashSet::new(),
             dag: DAG::new(),
+            bootstrap_peers,
+            bootstrap_frontier: HashSet::new(),
+            old_frontier: HashSet::new(),
+            bootstrapped: false,
+            drain: Default::default(),
+            chain_id: DEFAULT_CHAIN_ID,
+            cell_types: CellTypeRegistry::new(),
+            max_blocks_range: MAX_BLOCKS_RANGE,
+            checkpoint: None,
+            finalized_blocks: Arc::new(SledStore::temporary().unwrap()),
+            fee_address: None,
+            keypair: Keypair::generate(&mut OsRng {}),
+            pending_slashes: vec![],
+            height_recipients: vec![],
+        }
+    }
+
+    /// Signs the blocks produced by this validator with `keypair`, which owns its stake.
+    pub fn with_keypair(mut self, keypair: Keypair) -> Self {
+        self.keypair = keypair;
+        self
+    }
+
+    /// Verifies the signatures of the blocks' cells for the network `chain_id`.
+    pub fn with_chain_id(mut self, chain_id: ChainId) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    /// Validates the custom outputs of the blocks' cells with `cell_types`. Without registered
+    /// validators, blocks with custom outputs are refused.
+    pub fn with_cell_types(mut self, cell_types: CellTypeRegistry) -> Self {
+        self.cell_types = cell_types;
+        self
+    }
+
+    /// Returns at most `max_blocks_range` blocks for a [GetBlocksByRange] request, instead of
+    /// [MAX_BLOCKS_RANGE].
+    pub fn with_max_blocks_range(mut self, max_blocks_range: u64) -> Self {
+        self.max_blocks_range = max_blocks_range;
+        self
+    }
+
+    /// Keeps the known and queried blocks in the given stores instead of temporary databases.
+    pub fn with_stores(
+        mut self,
+        known_blocks: Arc<dyn KvStore>,
+        queried_blocks: Arc<dyn KvStore>,
+    ) -> Self {
+        self.known_blocks = known_blocks;
+        self.queried_blocks = queried_blocks;
+        self
+    }
+
+    /// Keeps the blocks up to the checkpoint in `finalized_blocks` instead of a temporary
+    /// database, and resumes from the checkpoint recorded there.
+    pub fn with_finalized_blocks(mut self, finalized_blocks: Arc<dyn KvStore>) -> Self {
+        self.finalized_blocks = finalized_blocks;
+        match self.load_checkpoint() {
+            Ok(checkpoint) => self.checkpoint = checkpoint,
+            Err(e) => error!("[{}] couldn't load the checkpoint: {:?}", "hail".blue(), e),
+        }
+        self
+    }
+
+    /// Collects the fees of the transactions in the blocks produced by this validator for
+    /// `fee_address`. Without it, the fees are burned.
+    pub fn with_fee_address(mut self, fee_address: PublicKeyHash) -> Self {
+        self.fee_address = Some(fee_address);
+        self
+    }
+
+    /// Returns the coinbase cell collecting `fees` for the fee address in the block at `height`,
+    /// if there are fees to collect.
+    fn fee_cell(&self, height: BlockHeight, fees: Capacity) -> Option<Cell> {
+        match self.fee_address {
+            Some(address) if fees > 0 => match coinbase::fee_cell(address, height, fees) {
+                Ok(cell) => Some(cell),
+                Err(e) => {
+                    error!("[{}] couldn't create the fee cell: {:?}", "hail".blue(), e);
+                    None
+                }
+            },
+            _ => None,
+        }
+    }
+
+    fn flush(&self) {
+        for db in [&self.known_blocks, &self.queried_blocks, &self.finalized_blocks] {
+            if let Err(e) = db.flush() {
+                error!("[{}] failed to flush the database: {:?}", "hail".blue(), e);
+            }
         }
     }
 
@@ -82,6 +229,12 @@ impl Hail {
     /// Returns `true` if the block hasn't been encountered before.
     fn on_receive_block(&mut self, hail_block: HailBlock) -> Result<bool> {
         if !block_storage::is_known_block(&self.known_blocks, hail_block.hash()?).unwrap() {
+            if self.is_checkpointed(hail_block.height()) {
+                return Err(Error::BelowCheckpoint(hail_block.height()));
+            }
+            self.verify_producer(&hail_block)?;
+            Self::verify_signatures(&hail_block, self.chain_id)?;
+            Self::verify_cell_types(&hail_block, &self.cell_types)?;
             self.insert(hail_block.clone())?;
             let _ = block_storage::insert_block(&self.known_blocks, hail_block.clone());
             Ok(true)
@@ -90,6 +243,52 @@ impl Hail {
         }
     }
 
+    /// Checks that the block producer was entitled to produce a block at this height, i.e.
+    /// that the block's VRF output was selected by sortition given the producer's stake and
+    /// the committee seed, and that the block is signed by the owner of the producer's stake.
+    /// The genesis block has no producer and is not checked.
+    fn verify_producer(&self, hail_block: &HailBlock) -> Result<()> {
+        if hail_block.parent().is_none() {
+            return Ok(());
+        }
+        let producer = match hail_block.producer() {
+            Some(producer)
+                if self.committee.is_legitimate_producer(producer, hail_block.vrf_output()) =>
+            {
+                producer
+            }
+            _ => return Err(Error::InvalidBlock(hail_block.inner())),
+        };
+        match self.committee.stake_owner(&producer) {
+            Some(owner) => hail_block.verify_signature(&owner),
+            None => Err(Error::InvalidBlockSignature(hail_block.hash()?)),
+        }
+    }
+
+    /// Checks the input signatures of all cells in the block in one batch, see
+    /// [cell::verify_signatures]. The genesis block has no signed cells and is not checked.
+    fn verify_signatures(hail_block: &HailBlock, chain_id: ChainId) -> Result<()> {
+        if hail_block.parent().is_none() {
+            return Ok(());
+        }
+        match cell::verify_signatures(&hail_block.inner().cells, chain_id) {
+            Ok(()) => Ok(()),
+            Err(cell::Error::InvalidSignature(cell_hash)) => {
+                Err(Error::InvalidCellSignature(cell_hash))
+            }
+            Err(e) => Err(Error::Alpha(e.into())),
+        }
+    }
+
+    /// Checks the custom outputs of all cells in the block with their registered validators, see
+    /// [CellTypeRegistry::validate].
+    fn verify_cell_types(hail_block: &HailBlock, cell_types: &CellTypeRegistry) -> Result<()> {
+        for cell in hail_block.inner().cells.iter() {
+            let () = cell_types.validate(cell).map_err(|e| Error::Alpha(e.into()))?;
+        }
+        Ok(())
+    }
+
     // Vertices
 
     fn insert(&mut self, block: HailBlock) -> Result<()> {
@@ -97,6 +296,16 @@ impl Hail {
         let vertex = block.vertex().unwrap();
         return if !self.dag.contains_key(&vertex) {
             match block.parent() {
+                Some(parent) if self.is_checkpointed(parent.height) => {
+                    // The checkpoint was pruned from the DAG, its children are inserted without
+                    // parents like the blocks of an accepted frontier
+                    if self.checkpoint.as_ref() != Some(&parent) {
+                        return Err(Error::InvalidParent);
+                    }
+                    self.conflict_map.insert_block(inner_block.clone())?;
+                    self.dag.insert_vx(vertex, vec![])?;
+                    Ok(())
+                }
                 Some(parent) => {
                     self.conflict_map.insert_block(inner_block.clone())?;
                     self.dag.insert_vx(vertex, vec![parent])?;
@@ -137,6 +346,11 @@ impl Hail {
     /// Starts at the live edges (the leaf nodes) of the `DAG` and does a depth first
     /// search until a preferrential parent with height = `h - 1` is found.
     pub fn select_parent(&mut self, h: BlockHeight) -> Result<Vertex> {
+        if let Some(checkpoint) = self.checkpoint.as_ref() {
+            if checkpoint.height + 1 == h {
+                return Ok(checkpoint.clone());
+            }
+        }
         if self.dag.is_empty() {
             return Err(Error::EmptyDAG);
         }
@@ -168,7 +382,10 @@ impl Hail {
 
     /// The ancestral update updates the preferred path through the DAG every time a new
     /// vertex is added.
-    pub fn update_ancestral_preference(&mut self, root_vx: Vertex) -> Result<()> {
+    ///
+    /// Returns the previously preferred vertices which were superseded by a competing block.
+    pub fn update_ancestral_preference(&mut self, root_vx: Vertex) -> Result<Vec<Vertex>> {
+        let mut superseded = vec![];
         for vx in self.dag.dfs(&root_vx) {
             // conviction of T vs Pt.pref
             let pref = self.conflict_map.get_preferred(&vx.height)?;
@@ -181,6 +398,52 @@ impl Hail {
                 d1,
                 d2,
             )?;
+            if self.conflict_map.get_preferred(&vx.height)? != pref {
+                superseded.push(Vertex::new(vx.height, pref));
+            }
+        }
+        Ok(superseded)
+    }
+
+    // Reorganisation
+
+    /// Removes the `superseded` block, which lost the preference at its height to a competing
+    /// block, and all of its descendants from the live blocks.
+    fn reorg(&mut self, superseded: &Vertex) {
+        info!(
+            "[{}] reorg: block {} at height {} was superseded",
+            "hail".blue(),
+            hex::encode(superseded.block_hash),
+            superseded.height
+        );
+        let mut visited = HashSet::new();
+        let mut stack = vec![superseded.clone()];
+        while let Some(vx) = stack.pop() {
+            if !visited.insert(vx.clone()) {
+                continue;
+            }
+            let _ = self.live_blocks.remove(&vx.block_hash);
+            if let Some(children) = self.dag.inverse().get(&vx) {
+                stack.extend(children.iter().cloned());
+            }
+        }
+    }
+
+    /// Sets the tip of the chain to the highest strongly preferred live block, if any.
+    fn update_tip(&mut self) -> Result<()> {
+        let mut tip: Option<Vertex> = None;
+        for (block_hash, block) in self.live_blocks.iter() {
+            let vx = Vertex::new(block.height, block_hash.clone());
+            if tip.as_ref().map_or(true, |tip| vx.height > tip.height)
+                && self.dag.contains_key(&vx)
+                && self.is_strongly_preferred(vx.clone())?
+            {
+                tip = Some(vx);
+            }
+        }
+        if let Some(tip) = tip {
+            self.last_accepted_hash = Some(tip.block_hash);
+            self.height = tip.height;
         }
         Ok(())
     }
@@ -205,18 +468,22 @@ impl Hail {
     /// Checks whether the parent of the provided `TxHash` is final - note that we do not
     /// traverse all of the parents of the accepted parent, since a child transaction
     /// cannot be final if its parent is not also final.
+    ///
+    /// The genesis block has no parent, it is accepted once it has enough confidence itself.
     pub fn is_accepted(&self, initial_vertex: &Vertex) -> Result<bool> {
+        let parents = match self.dag.get(initial_vertex) {
+            Some(parents) => parents,
+            None => return Err(Error::InvalidBlockHash(initial_vertex.block_hash.clone())),
+        };
+        if initial_vertex.height == 0 {
+            return self.is_accepted_block(initial_vertex);
+        }
         let mut parent_accepted = true;
-        match self.dag.get(initial_vertex) {
-            Some(parents) => {
-                for parent in parents.iter() {
-                    if !self.is_accepted_block(&parent)? {
-                        parent_accepted = false;
-                        break;
-                    }
-                }
+        for parent in parents.iter() {
+            if !self.is_accepted_block(&parent)? {
+                parent_accepted = false;
+                break;
             }
-            None => return Err(Error::InvalidBlockHash(initial_vertex.block_hash.clone())),
         }
         if parent_accepted {
             self.is_accepted_block(initial_vertex)
@@ -230,9 +497,6 @@ impl Hail {
     /// The accepted frontier of the DAG is a depth-first-search on the leaves of the DAG
     /// up to a vertices considered final, collecting all the final nodes.
     pub fn get_accepted_frontier(&self) -> Result<Vec<Vertex>> {
-        if self.dag.is_empty() {
-            return Ok(vec![]);
-        }
         let mut accepted_frontier = vec![];
         let leaves = self.dag.leaves();
         for leaf in leaves {
@@ -243,9 +507,142 @@ impl Hail {
                 }
             }
         }
+        // Nothing was accepted since the checkpoint, which is the frontier then
+        if accepted_frontier.is_empty() {
+            accepted_frontier.extend(self.checkpoint.iter().cloned());
+        }
         Ok(accepted_frontier)
     }
 
+    /// Inserts a block of a peer's accepted frontier into the DAG. The block must already be
+    /// present in `known_blocks`. Since the block is final, it is inserted without parents and
+    /// marked as accepted, with the confidence of a final block.
+    fn insert_frontier_block(&mut self, vx: &Vertex) -> Result<()> {
+        if self.dag.contains_key(vx) {
+            return Ok(());
+        }
+        let (_, block) = block_storage::get_block(&self.known_blocks, vx.block_hash)
+            .map_err(|_| Error::InvalidBlockHash(vx.block_hash.clone()))?;
+        let inner_block = block.inner();
+        self.conflict_map.insert_block(inner_block.clone())?;
+        self.conflict_map.set_accepted(vx)?;
+        self.dag.insert_vx(vx.clone(), vec![])?;
+        self.dag.set_chit(vx.clone(), 1)?;
+        let _ = self.accepted_vertices.insert(vx.clone());
+        let _ = self.live_blocks.insert(vx.block_hash.clone(), inner_block);
+        Ok(())
+    }
+
+    // Checkpoints
+
+    /// Whether the blocks at `height` are below the checkpoint, that is final and pruned.
+    fn is_checkpointed(&self, height: BlockHeight) -> bool {
+        self.checkpoint.as_ref().map_or(false, |checkpoint| height <= checkpoint.height)
+    }
+
+    /// Returns the accepted vertex at `height`, at or below the accepted frontier.
+    fn accepted_vertex_at(&self, height: BlockHeight) -> Result<Vertex> {
+        for frontier_vx in self.get_accepted_frontier()? {
+            if frontier_vx.height < height {
+                continue;
+            }
+            if frontier_vx.height == height {
+                return Ok(frontier_vx);
+            }
+            for vx in self.dag.dfs(&frontier_vx) {
+                if vx.height == height {
+                    return Ok(vx.clone());
+                }
+            }
+        }
+        Err(Error::InvalidCheckpoint(height))
+    }
+
+    /// Declares the accepted block at `height` and its ancestors irreversible.
+    ///
+    /// The blocks up to `height` and the checkpoint are stored in `finalized_blocks`, nothing is
+    /// pruned if they couldn't be. The blocks are then pruned from the DAG, the
+    /// conflict map and the live blocks, along with the blocks descending from the branches
+    /// competing with the checkpoint. The checkpoint has to be at or below the accepted frontier,
+    /// and can't go backwards.
+    ///
+    /// Returns the vertex of the checkpoint.
+    pub fn set_checkpoint(&mut self, height: BlockHeight) -> Result<Vertex> {
+        if let Some(checkpoint) = self.checkpoint.as_ref() {
+            if checkpoint.height == height {
+                return Ok(checkpoint.clone());
+            }
+            if checkpoint.height > height {
+                return Err(Error::InvalidCheckpoint(height));
+            }
+        }
+        let checkpoint = self.accepted_vertex_at(height)?;
+
+        let chain: HashSet<Vertex> = self.dag.dfs(&checkpoint).cloned().collect();
+        for vx in chain.iter() {
+            let (_, block) = block_storage::get_block(&self.known_blocks, vx.block_hash)
+                .map_err(|_| Error::InvalidBlockHash(vx.block_hash))?;
+            let _ = block_storage::insert_finalized_block(&self.finalized_blocks, block)?;
+        }
+        // The blocks are pruned once they and the checkpoint are stored, so that a restart
+        // resumes from the checkpoint
+        let () = block_storage::set_checkpoint_height(&self.finalized_blocks, height)?;
+        let () = self.finalized_blocks.flush()?;
+
+        let mut pruned: HashSet<Vertex> =
+            self.dag.keys().filter(|vx| vx.height <= height).cloned().collect();
+        let mut stack: Vec<Vertex> = pruned.difference(&chain).cloned().collect();
+        while let Some(vx) = stack.pop() {
+            if let Some(children) = self.dag.inverse().get(&vx) {
+                for child in children.iter() {
+                    if pruned.insert(child.clone()) {
+                        stack.push(child.clone());
+                    }
+                }
+            }
+        }
+        // Children first, so that their edges are gone when removing their parents
+        let mut pruned: Vec<Vertex> = pruned.into_iter().collect();
+        pruned.sort_by(|a, b| b.height.cmp(&a.height));
+        for vx in pruned.iter() {
+            let _ = self.dag.remove_vx(vx)?;
+            let _ = self.live_blocks.remove(&vx.block_hash);
+            let _ = self.accepted_vertices.remove(vx);
+        }
+        self.conflict_map.remove_up_to(height);
+
+        info!(
+            "[{}] checkpoint at height {} ({}), pruned {} blocks",
+            "hail".blue(),
+            height,
+            hex::encode(checkpoint.block_hash),
+            pruned.len()
+        );
+        self.checkpoint = Some(checkpoint.clone());
+        Ok(checkpoint)
+    }
+
+    /// Returns the checkpoint recorded in `finalized_blocks`, if any.
+    fn load_checkpoint(&self) -> Result<Option<Vertex>> {
+        match block_storage::get_checkpoint_height(&self.finalized_blocks)? {
+            Some(height) => {
+                let block = block_storage::get_finalized_block(&self.finalized_blocks, height)?;
+                Ok(Some(block.vertex()?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Fetches the block of the chain at `height`, up to the checkpoint.
+    fn finalized_block_at(&self, height: BlockHeight) -> Option<Block> {
+        if !self.is_checkpointed(height) {
+            return None;
+        }
+        block_storage::get_finalized_block(&self.finalized_blocks, height)
+            .ok()
+            .map(|block| block.inner())
+    }
+
     /// Check if a transaction or one of its ancestors have become accepted
     pub fn next_accepted_vertex(&mut self, vertex: &Vertex) -> Result<Option<Vertex>> {
         for vx in self.dag.dfs(vertex) {
@@ -263,16 +660,286 @@ impl Hail {
         for (id, (ip, w)) in self.committee.iter() {
             validators.push((id.clone(), ip.clone(), w.clone()));
         }
-        util::sample_weighted(minimum_weight, validators).ok_or(Error::InsufficientWeight)
+        util::sample_weighted_biased(minimum_weight, validators, &self.reputations)
+            .ok_or(Error::InsufficientWeight)
+    }
+
+    /// The vote of this validator on a known block, as answered to a [QueryBlock].
+    fn vote(&self, block: &HailBlock) -> Result<bool> {
+        if self.is_checkpointed(block.height()) {
+            let block_hash = block.hash()?;
+            return Ok(self
+                .finalized_block_at(block.height())
+                .map_or(false, |finalized| finalized.hash().ok() == Some(block_hash)));
+        }
+        // FIXME: If we are in the middle of querying this block, wait until a decision or a
+        // synchronous timebound is reached on attempts.
+        self.is_strongly_preferred(block.vertex()?)
     }
 }
 
 impl Actor for Hail {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Context<Self>) {
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        ctx.notify(Bootstrap);
         debug!(": started");
     }
+
+    fn stopping(&mut self, _ctx: &mut Context<Self>) -> actix::Running {
+        self.flush();
+        actix::Running::Stop
+    }
+}
+
+/// A message to start the bootstrapping process of [Hail].
+/// The handler of this request fetches the accepted frontier of `bootstrap_peers`
+/// along with the missing blocks of the frontier.
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "Result<()>")]
+struct Bootstrap;
+
+impl Handler<Bootstrap> for Hail {
+    type Result = ResponseActFuture<Self, Result<()>>;
+
+    fn handle(&mut self, _msg: Bootstrap, _ctx: &mut Context<Self>) -> Self::Result {
+        let query = ClientRequest::Fanout {
+            peers: self.bootstrap_peers.clone(),
+            request: Request::GetAcceptedBlockFrontier,
+            timeout: None,
+        };
+        info!("[{}] bootstrapping...", "hail".blue());
+        self.sender
+            .send(query)
+            .into_actor(self)
+            .map(|res, act, ctx| match res {
+                Ok(response) => {
+                    let frontiers = client::successful_responses(response.into_fanout()?);
+                    info!(
+                        "[{}] received {} frontier responses for bootstrap",
+                        "hail".blue(),
+                        frontiers.len()
+                    );
+                    for f in frontiers.iter() {
+                        match f {
+                            Response::AcceptedBlockFrontier(AcceptedBlockFrontier { frontier }) => {
+                                act.bootstrap_frontier.extend(frontier.iter().cloned());
+                            }
+                            other => warn!(
+                                "[{}] {:?} to bootstrap: {:?}",
+                                "hail".blue(),
+                                ClientError::UnexpectedResponseKind,
+                                other
+                            ),
+                        }
+                    }
+
+                    let diff: HashSet<_> =
+                        act.bootstrap_frontier.difference(&act.old_frontier).cloned().collect();
+                    if !diff.is_empty() {
+                        act.old_frontier = act.bootstrap_frontier.clone();
+                        // Fetch the frontier blocks and their ancestors from the bootstrap nodes
+                        ctx.notify(FetchBlockWithAncestry { vertices: diff });
+                        Ok(())
+                    } else {
+                        info!("[{}] bootstrapped", "hail".blue());
+                        act.bootstrapped = true;
+                        Ok(())
+                    }
+                }
+                Err(e) => Err(Error::from(e)),
+            })
+            .boxed_local()
+    }
+}
+
+/// Restart the bootstrapping process from `peers`, when the node rejoins the network after
+/// being isolated from it (see [LiveNetwork][crate::alpha::LiveNetwork]).
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+pub struct Rebootstrap {
+    pub peers: Vec<(Id, SocketAddr)>,
+}
+
+impl Handler<Rebootstrap> for Hail {
+    type Result = ();
+
+    fn handle(&mut self, msg: Rebootstrap, ctx: &mut Context<Self>) -> Self::Result {
+        info!("[{}] rebootstrapping from {} peers", "hail".blue(), msg.peers.len());
+        if !msg.peers.is_empty() {
+            self.bootstrap_peers = msg.peers;
+        }
+        self.bootstrapped = false;
+        ctx.notify(Bootstrap);
+    }
+}
+
+/// Fetch blocks recursively on bootstrap.
+///
+/// The cells of the fetched blocks are verified like those of the blocks received via queries,
+/// the blocks failing are discarded along with their ancestry. Their producers were drawn by the
+/// committees at their heights, so they aren't checked against the current committee.
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+pub struct FetchBlockWithAncestry {
+    vertices: HashSet<Vertex>,
+}
+
+impl Handler<FetchBlockWithAncestry> for Hail {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(
+        &mut self,
+        FetchBlockWithAncestry { vertices }: FetchBlockWithAncestry,
+        _ctx: &mut Context<Self>,
+    ) -> Self::Result {
+        let db = self.known_blocks.clone();
+        let peers = self.bootstrap_peers.clone();
+        let sender = self.sender.clone();
+        let chain_id = self.chain_id;
+        let cell_types = self.cell_types.clone();
+        let mut block_hashes: VecDeque<BlockHash> =
+            vertices.iter().map(|vx| vx.block_hash.clone()).collect();
+        let fetch = async move {
+            while let Some(block_hash) = block_hashes.pop_front() {
+                // Fetch block from peers
+                if !block_storage::is_known_block(&db, block_hash).unwrap_or(false) {
+                    for (id, ip) in peers.iter() {
+                        match sender
+                            .send(ClientRequest::Oneshot {
+                                id: *id,
+                                ip: *ip,
+                                request: Request::FetchBlock(FetchBlock { block_hash }),
+                                timeout: None,
+                                retries: 0,
+                            })
+                            .await
+                        {
+                            Ok(ClientResponse::Oneshot(Some(Response::FetchedBlock(
+                                FetchedBlock { block: Some(block) },
+                            )))) => {
+                                // Ignore blocks which don't match the requested hash
+                                if block.hash() != Ok(block_hash) {
+                                    continue;
+                                }
+                                let verified = Hail::verify_signatures(&block, chain_id)
+                                    .and_then(|()| Hail::verify_cell_types(&block, &cell_types));
+                                if let Err(e) = verified {
+                                    warn!(
+                                        "[{}] discarding block {} from {}: {:?}",
+                                        "hail".blue(),
+                                        hex::encode(block_hash),
+                                        id,
+                                        e
+                                    );
+                                    continue;
+                                }
+                                let _ = block_storage::insert_block(&db, block.clone());
+                                // Push the parent of `block` to the queue
+                                if let Some(parent) = block.parent() {
+                                    block_hashes.push_back(parent.block_hash);
+                                }
+                                break;
+                            }
+                            // Try the next peer
+                            other => debug!(
+                                "[{}] couldn't fetch block {} from {}: {:?}",
+                                "hail".blue(),
+                                hex::encode(block_hash),
+                                id,
+                                other
+                            ),
+                        }
+                    }
+                }
+            }
+        };
+        Box::pin(fetch.into_actor(self).map(move |(), act, ctx| {
+            for vx in vertices.iter() {
+                if let Err(e) = act.insert_frontier_block(vx) {
+                    error!("[{}] failed to insert frontier block {:?}: {}", "hail".blue(), vx, e);
+                }
+            }
+            // Repeat bootstrap procedure
+            ctx.notify(Bootstrap);
+        }))
+    }
+}
+
+/// A message to get a [HailBlock] from the storage.
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "FetchedBlock")]
+pub struct FetchBlock {
+    /// Hash of the block to search in storage
+    block_hash: BlockHash,
+}
+
+/// A response for [FetchBlock] with [HailBlock] if found.
+#[derive(Debug, Clone, Serialize, Deserialize, MessageResponse)]
+pub struct FetchedBlock {
+    block: Option<HailBlock>,
+}
+
+impl Handler<FetchBlock> for Hail {
+    type Result = FetchedBlock;
+
+    fn handle(
+        &mut self,
+        FetchBlock { block_hash }: FetchBlock,
+        _ctx: &mut Context<Self>,
+    ) -> Self::Result {
+        match block_storage::get_block(&self.known_blocks, block_hash) {
+            Ok((_hash, block)) => FetchedBlock { block: Some(block) },
+            _ => FetchedBlock { block: None },
+        }
+    }
+}
+
+/// Report whether Hail has finished bootstrapping
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "bool")]
+pub struct Bootstrapped;
+
+impl Handler<Bootstrapped> for Hail {
+    type Result = bool;
+
+    fn handle(&mut self, _msg: Bootstrapped, _ctx: &mut Context<Self>) -> Self::Result {
+        self.bootstrapped
+    }
+}
+
+/// Get the accepted frontier of the block DAG
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "AcceptedBlockFrontier")]
+pub struct GetAcceptedBlockFrontier;
+
+/// A response to [GetAcceptedBlockFrontier] with the accepted frontier of [Hail]
+#[derive(Debug, Clone, Serialize, Deserialize, MessageResponse)]
+pub struct AcceptedBlockFrontier {
+    pub frontier: Vec<Vertex>,
+}
+
+impl Handler<GetAcceptedBlockFrontier> for Hail {
+    type Result = AcceptedBlockFrontier;
+
+    fn handle(&mut self, _msg: GetAcceptedBlockFrontier, _ctx: &mut Context<Self>) -> Self::Result {
+        match self.get_accepted_frontier() {
+            Ok(frontier) => AcceptedBlockFrontier { frontier },
+            Err(e) => {
+                error!("[{}] failed to compute the accepted frontier: {}", "hail".blue(), e);
+                AcceptedBlockFrontier { frontier: vec![] }
+            }
+        }
+    }
+}
+
+/// Validators with a bad reputation in the [View][crate::view::View] are sampled less often.
+impl Handler<view::PeerReputations> for Hail {
+    type Result = ();
+
+    fn handle(&mut self, msg: view::PeerReputations, _ctx: &mut Context<Self>) -> Self::Result {
+        self.reputations = msg.reputations;
+    }
 }
 
 /// Message sent by the [`alpha`][crate::alpha] protocol, containing the live validator and block information
@@ -287,6 +954,10 @@ pub struct LiveCommittee {
     pub total_staking_capacity: u64,
     pub validators: HashMap<Id, (SocketAddr, u64)>,
     pub vrf_out: VrfOutput,
+    /// The owners of the validators' stakes, which sign the blocks the validators produce
+    pub stake_owners: HashMap<Id, PublicKeyHash>,
+    /// The staking periods of the live stakes
+    pub stakes: Vec<StakingPeriod>,
 }
 
 impl Handler<LiveCommittee> for Hail {
@@ -297,7 +968,9 @@ impl Handler<LiveCommittee> for Hail {
         let _self_id = msg.self_id.clone();
         let _self_staking_capacity = msg.self_staking_capacity.clone();
 
-        self.committee.next(msg.self_staking_capacity, msg.vrf_out, msg.validators);
+        self.committee.set_stakes(msg.stakes);
+        self.committee.next(msg.height, msg.self_staking_capacity, msg.vrf_out, msg.validators);
+        self.committee.set_stake_owners(msg.stake_owners);
 
         info!(
             "[{}] last_accepted_hash = {}",
@@ -308,9 +981,12 @@ impl Handler<LiveCommittee> for Hail {
         self.last_accepted_hash = Some(msg.last_accepted_hash);
         self.height = msg.height;
 
-        // Insert the last accepted block into the DAG (else its empty and cannot be built upon).
-        self.insert(msg.last_accepted_block).unwrap();
-        info!("[{}] inserted last_accepted_block", "hail".blue());
+        // Insert the last accepted block into the DAG (else its empty and cannot be built upon),
+        // unless it is the checkpoint the DAG resumes from.
+        if !self.is_checkpointed(msg.last_accepted_block.height()) {
+            self.insert(msg.last_accepted_block).unwrap();
+            info!("[{}] inserted last_accepted_block", "hail".blue());
+        }
 
         // TODO: Check if we have pending accepted cells and build a block (block building
         // will still take place when receiving accepted cells otherwise).
@@ -333,6 +1009,24 @@ impl Handler<QueryIncomplete> for Hail {
     }
 }
 
+/// Message sent by the [`alpha`][crate::alpha] protocol once it applied an accepted block,
+/// containing the staking periods of its live stakes. They are applied from the next advance
+/// of the committee.
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+pub struct LiveStakes {
+    pub stakes: Vec<StakingPeriod>,
+}
+
+impl Handler<LiveStakes> for Hail {
+    type Result = ();
+
+    fn handle(&mut self, msg: LiveStakes, _ctx: &mut Context<Self>) -> Self::Result {
+        debug!("[{}] received {} live stakes", "hail".blue(), msg.stakes.len());
+        self.committee.set_stakes(msg.stakes);
+    }
+}
+
 /// Internal actor message for handling successful queries
 #[derive(Debug, Clone, Serialize, Deserialize, Message)]
 #[rtype(result = "()")]
@@ -345,23 +1039,36 @@ impl Handler<QueryComplete> for Hail {
     type Result = ();
 
     fn handle(&mut self, msg: QueryComplete, ctx: &mut Context<Self>) -> Self::Result {
-        // FIXME: Verify that there are no duplicate ids
         let mut outcomes = vec![];
+        let mut voted = HashSet::new();
         for ack in msg.acks.iter() {
             match ack {
                 Response::QueryBlockAck(qb_ack) => match self.committee.get(&qb_ack.id) {
-                    Some((_, w)) => outcomes.push((qb_ack.id, w.clone(), qb_ack.outcome)),
-                    None => (),
+                    // A validator votes once, its duplicate acks are ignored
+                    Some((_, w)) if voted.insert(qb_ack.id) => {
+                        outcomes.push((qb_ack.id, w.clone(), qb_ack.outcome))
+                    }
+                    _ => (),
                 },
-                // FIXME: Error
-                _ => (),
+                // A malformed vote doesn't count
+                other => warn!(
+                    "[{}] {:?} to query: {:?}",
+                    "hail".blue(),
+                    ClientError::UnexpectedResponseKind,
+                    other
+                ),
             }
         }
+        let vx = msg.block.vertex().unwrap();
+        if !self.dag.contains_key(&vx) {
+            // The block was pruned by a checkpoint while being queried
+            info!("[{}] block {} was pruned", "hail".blue(), hex::encode(vx.block_hash));
+            return;
+        }
         // if yes: set_chit(tx, 1), update ancestral preferences
         if util::sum_outcomes(outcomes) >= ALPHA {
-            let vx = msg.block.vertex().unwrap();
             self.dag.set_chit(vx.clone(), 1).unwrap();
-            self.update_ancestral_preference(vx.clone()).unwrap();
+            let superseded = self.update_ancestral_preference(vx.clone()).unwrap();
 
             let block_hash_string = hex::encode(vx.block_hash);
             info!("[{}] >>> block: {} <<<", "hail".blue(), block_hash_string.green());
@@ -370,14 +1077,20 @@ impl Handler<QueryComplete> for Hail {
             let inner_block = msg.block.inner();
             let _ = self.live_blocks.insert(vx.block_hash.clone(), inner_block.clone());
 
-            // Advance the committee, here usually we must take into account the start and end time
-            // of staking cells as well as their execution in order to modify the validator weights
-            // appropriately on subsequent blocks.
+            // Advance the committee, leaving out the stakes which are inactive at this height
             let self_staking_capacity = self.committee.self_staking_capacity();
             let validators = self.committee.validators();
-            self.committee.next(self_staking_capacity, inner_block.vrf_out, validators);
-            self.last_accepted_hash = Some(vx.block_hash.clone());
-            self.height = vx.height;
+            self.committee.next(
+                inner_block.height,
+                self_staking_capacity,
+                inner_block.vrf_out,
+                validators,
+            );
+            // Drop the branches which lost the preference, and move the tip to the preferred one
+            for superseded_vx in superseded.iter() {
+                self.reorg(superseded_vx);
+            }
+            self.update_tip().unwrap();
 
             // The block or some of its ancestors may have become accepted. Check this.
             let maybe_accepted = self.next_accepted_vertex(&vx);
@@ -411,7 +1124,11 @@ pub struct Accepted {
 impl Handler<Accepted> for Hail {
     type Result = ();
 
-    fn handle(&mut self, _msg: Accepted, _ctx: &mut Context<Self>) -> Self::Result {
+    fn handle(&mut self, msg: Accepted, _ctx: &mut Context<Self>) -> Self::Result {
+        METRICS.hail_accepted_blocks.inc();
+        for recipient in self.height_recipients.iter() {
+            let _ = recipient.do_send(AcceptedHeight { height: msg.vertex.height });
+        }
         // At this point we can be sure that the block is known
         // let (_, block) =
         //     block_storage::get_block(&self.known_blocks, msg.vertex.block_hash).unwrap();
@@ -421,6 +1138,25 @@ impl Handler<Accepted> for Hail {
     }
 }
 
+/// Subscribes `recipient` to the [AcceptedHeight] of each block accepted by [Hail], starting
+/// with the highest block accepted so far.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SubscribeAcceptedHeight {
+    pub recipient: Recipient<AcceptedHeight>,
+}
+
+impl Handler<SubscribeAcceptedHeight> for Hail {
+    type Result = ();
+
+    fn handle(&mut self, msg: SubscribeAcceptedHeight, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(height) = self.accepted_vertices.iter().map(|vx| vx.height).max() {
+            let _ = msg.recipient.do_send(AcceptedHeight { height });
+        }
+        self.height_recipients.push(msg.recipient);
+    }
+}
+
 /// Message sent for all new blocks
 ///
 /// Instead of having an infinite loop as per the paper which receives and processes
@@ -436,42 +1172,147 @@ impl Handler<FreshBlock> for Hail {
     type Result = ResponseActFuture<Self, Result<()>>;
 
     fn handle(&mut self, msg: FreshBlock, _ctx: &mut Context<Self>) -> Self::Result {
+        if self.drain.is_shutting_down() {
+            // The block stays unqueried, no new queries are started
+            return Box::pin(actix::fut::ready(Ok(())));
+        }
         let validators = self.sample(ALPHA).unwrap();
         info!("[{}] sampled {:?}", "hail".blue(), validators.clone());
 
-        // Fanout queries to sampled validators
-        let send_to_client = self.sender.send(ClientRequest::Fanout {
-            peers: validators.clone(),
-            request: Request::QueryBlock(QueryBlock {
-                id: self.node_id.clone(),
-                block: msg.block.clone(),
-            }),
-        });
+        // If this validator is sampled, its vote is evaluated locally instead of over the network
+        let node_id = self.node_id;
+        let (own, peers): (Vec<_>, Vec<_>) =
+            validators.into_iter().partition(|(id, _)| *id == node_id);
+        let own_ack = if own.is_empty() {
+            None
+        } else {
+            let block_hash = msg.block.hash().unwrap();
+            let outcome = self.vote(&msg.block).unwrap_or(false);
+            Some(Response::QueryBlockAck(QueryBlockAck { id: node_id, block_hash, outcome }))
+        };
+
+        // Fanout queries to the other sampled validators
+        let started = Instant::now();
+        let query = if peers.is_empty() {
+            None
+        } else {
+            Some(self.sender.send(ClientRequest::Fanout {
+                peers: peers.clone(),
+                request: Request::QueryBlock(QueryBlock {
+                    id: self.node_id.clone(),
+                    block: msg.block.clone(),
+                }),
+                timeout: None,
+            }))
+        };
+        let send_to_client = async move {
+            match query {
+                Some(query) => query.await,
+                None => Ok(ClientResponse::Fanout(vec![])),
+            }
+        };
+
+        self.drain.started(1);
 
         // Wrap the future so that subsequent chained handlers can access te actor.
         let send_to_client = actix::fut::wrap_future::<_, Self>(send_to_client);
 
         let update_self = send_to_client.map(move |result, _actor, ctx| {
-            match result {
-                Ok(ClientResponse::Fanout(acks)) => {
-                    // If the length of responses is the same as the length of the sampled ips,
-                    // then every peer responded.
-                    if acks.len() == validators.len() {
+            METRICS.hail_query_latency.observe(started.elapsed());
+            let outcome = match result.map(ClientResponse::into_fanout) {
+                Ok(Ok(results)) => {
+                    // The query is complete if every sampled validator responded
+                    let complete = results.len() == peers.len()
+                        && results.iter().all(|(_, result)| result.is_ok());
+                    let mut acks = client::successful_responses(results);
+                    acks.extend(own_ack);
+                    if complete {
                         Ok(ctx.notify(QueryComplete { block: msg.block.clone(), acks }))
                     } else {
                         Ok(ctx.notify(QueryIncomplete { block: msg.block.clone(), acks }))
                     }
                 }
-                Ok(ClientResponse::Oneshot(_)) => panic!("unexpected response"),
-                // FIXME
-                Err(_) => Err(Error::ActixMailboxError),
-            }
+                // None of the peers answered
+                Ok(Err(e)) => {
+                    let acks = own_ack.into_iter().collect();
+                    ctx.notify(QueryIncomplete { block: msg.block.clone(), acks });
+                    Err(Error::Client(e))
+                }
+                // The client is gone, the query is given up like when none of the peers answered
+                Err(e) => {
+                    let acks = own_ack.into_iter().collect();
+                    ctx.notify(QueryIncomplete { block: msg.block.clone(), acks });
+                    Err(Error::from(e))
+                }
+            };
+            // Handled after the outcome of the query
+            ctx.notify(QuerySettled);
+            outcome
         });
 
         Box::pin(update_self)
     }
 }
 
+/// Sent when a [FreshBlock] query is answered or fails, after its outcome was notified
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+struct QuerySettled;
+
+impl Handler<QuerySettled> for Hail {
+    type Result = ();
+
+    fn handle(&mut self, _msg: QuerySettled, ctx: &mut Context<Self>) -> Self::Result {
+        if self.drain.settled() {
+            // Let the messages notified while handling the outcome (e.g. [Accepted]) go first
+            ctx.notify(Drained);
+        }
+    }
+}
+
+/// Wakes up a pending [Shutdown] once the in-flight queries settled
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+struct Drained;
+
+impl Handler<Drained> for Hail {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Drained, _ctx: &mut Context<Self>) -> Self::Result {
+        self.drain.wake();
+    }
+}
+
+/// Shuts [Hail] down gracefully: new blocks are refused, the in-flight queries are
+/// given up to `timeout` to settle, then the databases are flushed and the actor stopped.
+/// Only the stores given with [with_stores][Hail::with_stores] and
+/// [with_finalized_blocks][Hail::with_finalized_blocks] are persistent, the default ones being
+/// temporary.
+/// Once the response arrives, messages sent to [Hail] fail with a closed mailbox.
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct Shutdown {
+    pub timeout: Duration,
+}
+
+impl Handler<Shutdown> for Hail {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(&mut self, msg: Shutdown, _ctx: &mut Context<Self>) -> Self::Result {
+        info!("[{}] shutting down, {} queries in flight", "hail".blue(), self.drain.in_flight());
+        let drained = self.drain.shut_down(msg.timeout);
+        let wait = async move {
+            if !drained.await {
+                warn!("[{}] in-flight queries didn't settle in time", "hail".blue());
+            }
+        };
+        Box::pin(wait.into_actor(self).map(|(), actor, ctx| {
+            actor.flush();
+            ctx.stop();
+        }))
+    }
+}
+
 /// External query about a block's status
 #[derive(Debug, Clone, Serialize, Deserialize, Message)]
 #[rtype(result = "QueryBlockAck")]
@@ -503,11 +1344,14 @@ impl Handler<QueryBlock> for Hail {
             Ok(false) => (),
             Err(e) => {
                 error!("[{}] failed to receive block {:?}: {}", "hail".blue(), msg.block, e);
+                return QueryBlockAck {
+                    id: self.node_id,
+                    block_hash: vx.block_hash.clone(),
+                    outcome: false,
+                };
             }
         }
-        // FIXME: If we are in the middle of querying this block, wait until a decision or a
-        // synchronous timebound is reached on attempts.
-        match self.is_strongly_preferred(vx.clone()) {
+        match self.vote(&msg.block) {
             Ok(outcome) => {
                 QueryBlockAck { id: self.node_id, block_hash: vx.block_hash.clone(), outcome }
             }
@@ -541,7 +1385,10 @@ impl Handler<GetBlock> for Hail {
     type Result = BlockAck;
 
     fn handle(&mut self, msg: GetBlock, _ctx: &mut Context<Self>) -> Self::Result {
-        BlockAck { block: self.live_blocks.get(&msg.block_hash).map(|x| x.clone()) }
+        match self.live_blocks.get(&msg.block_hash) {
+            Some(block) => BlockAck { block: Some(block.clone()) },
+            None => BlockAck { block: self.finalized_block(msg.block_hash) },
+        }
     }
 }
 
@@ -558,11 +1405,124 @@ impl Handler<GetBlockByHeight> for Hail {
     type Result = BlockAck;
 
     fn handle(&mut self, msg: GetBlockByHeight, _ctx: &mut Context<Self>) -> Self::Result {
-        let block = match self.live_blocks.iter().find(|e| e.1.height == msg.block_height) {
+        BlockAck { block: self.block_at(msg.block_height) }
+    }
+}
+
+impl Hail {
+    fn live_block_at(&self, height: BlockHeight) -> Option<Block> {
+        match self.live_blocks.iter().find(|e| e.1.height == height) {
             Some(entry) => Some(entry.1.clone()),
             None => None,
-        };
-        BlockAck { block }
+        }
+    }
+
+    /// Fetches the block at `height`, from storage if it's below the checkpoint.
+    fn block_at(&self, height: BlockHeight) -> Option<Block> {
+        self.live_block_at(height).or_else(|| self.finalized_block_at(height))
+    }
+
+    /// Fetches the block with `block_hash` from storage, if it's part of the chain up to the
+    /// checkpoint.
+    fn finalized_block(&self, block_hash: BlockHash) -> Option<Block> {
+        block_storage::get_finalized_block_by_hash(&self.finalized_blocks, block_hash)
+            .ok()
+            .filter(|block| self.is_checkpointed(block.height()))
+            .map(|block| block.inner())
+    }
+}
+
+/// Get the blocks from height `from` to `to` (inclusive), for catching up with the chain
+///
+/// The response message is [`BlocksAck`] containing the blocks in ascending order of height.
+/// At most [`MAX_BLOCKS_RANGE`] blocks are returned, and the blocks stop at the first unknown
+/// height.
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "BlocksAck")]
+pub struct GetBlocksByRange {
+    pub from: BlockHeight,
+    pub to: BlockHeight,
+}
+
+/// Reply message to [GetBlocksByRange]
+#[derive(Debug, Clone, Serialize, Deserialize, MessageResponse)]
+pub struct BlocksAck {
+    pub blocks: Vec<Block>,
+}
+
+impl Handler<GetBlocksByRange> for Hail {
+    type Result = BlocksAck;
+
+    fn handle(&mut self, msg: GetBlocksByRange, _ctx: &mut Context<Self>) -> Self::Result {
+        let mut blocks = vec![];
+        for height in (msg.from..=msg.to).take(self.max_blocks_range as usize) {
+            match self.block_at(height) {
+                Some(block) => blocks.push(block),
+                None => break,
+            }
+        }
+        BlocksAck { blocks }
+    }
+}
+
+/// Get the tip of the chain: the current height and the hash of the last accepted block
+///
+/// The response message is [`ChainTipAck`], without a tip until Hail received its first
+/// [`LiveCommittee`]
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "ChainTipAck")]
+pub struct GetChainTip;
+
+/// The tip of the chain, see [`GetChainTip`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainTip {
+    pub height: BlockHeight,
+    pub last_accepted_hash: BlockHash,
+}
+
+/// Response to [`GetChainTip`]
+#[derive(Debug, Clone, Serialize, Deserialize, MessageResponse)]
+pub struct ChainTipAck {
+    pub tip: Option<ChainTip>,
+}
+
+impl Handler<GetChainTip> for Hail {
+    type Result = ChainTipAck;
+
+    fn handle(&mut self, _msg: GetChainTip, _ctx: &mut Context<Self>) -> Self::Result {
+        let tip = self
+            .last_accepted_hash
+            .map(|last_accepted_hash| ChainTip { height: self.height, last_accepted_hash });
+        ChainTipAck { tip }
+    }
+}
+
+/// Set a checkpoint at `height`, see [Hail::set_checkpoint]
+///
+/// The response message is [`CheckpointAck`], without a checkpoint if it couldn't be set
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "CheckpointAck")]
+pub struct SetCheckpoint {
+    pub height: BlockHeight,
+}
+
+/// Reply message to [SetCheckpoint]
+#[derive(Debug, Clone, Serialize, Deserialize, MessageResponse)]
+pub struct CheckpointAck {
+    pub checkpoint: Option<Vertex>,
+}
+
+impl Handler<SetCheckpoint> for Hail {
+    type Result = CheckpointAck;
+
+    fn handle(&mut self, msg: SetCheckpoint, _ctx: &mut Context<Self>) -> Self::Result {
+        match self.set_checkpoint(msg.height) {
+            Ok(checkpoint) => CheckpointAck { checkpoint: Some(checkpoint) },
+            Err(e) => {
+                error!("[{}] couldn't set checkpoint at {}: {}", "hail".blue(), msg.height, e);
+                CheckpointAck { checkpoint: None }
+            }
+        }
     }
 }
 
@@ -584,9 +1544,19 @@ impl Handler<GenerateBlock> for Hail {
     type Result = GenerateBlockAck;
 
     fn handle(&mut self, msg: GenerateBlock, ctx: &mut Context<Self>) -> Self::Result {
+        if self.drain.is_shutting_down() {
+            warn!("[{}] shutting down, refusing new block", "hail".blue());
+            return GenerateBlockAck { block_hash: None };
+        }
         info!("[{}] selecting parent at block height = {:?}", "hail".blue(), msg.block.height);
         let parent = self.select_parent(msg.block.height).unwrap();
-        let hail_block = HailBlock::new(Some(parent), msg.block.clone());
+        let hail_block = match HailBlock::new(Some(parent), msg.block.clone()).sign(&self.keypair) {
+            Ok(hail_block) => hail_block,
+            Err(e) => {
+                error!("[{}] couldn't sign new block: {}", "hail".blue(), e);
+                return GenerateBlockAck { block_hash: None };
+            }
+        };
         info!("[{}] generating new block\n{}", "hail".blue(), hail_block.clone());
 
         match self.on_receive_block(hail_block.clone()) {
@@ -609,6 +1579,8 @@ impl Handler<GenerateBlock> for Hail {
 #[rtype(result = "()")]
 pub struct AcceptedCells {
     pub cells: Vec<Cell>,
+    /// The sum of the fees paid by `cells`, collected by the producer of the next block
+    pub fees: Capacity,
 }
 
 impl Handler<AcceptedCells> for Hail {
@@ -621,13 +1593,14 @@ impl Handler<AcceptedCells> for Hail {
             Some(vrf_out) => {
                 if !self.committee.block_proposed() {
                     // If we are the block producer at height `h + 1` then generate a new block with
-                    // the accepted cells.
-                    let block = Block::new(
-                        self.last_accepted_hash.unwrap(),
-                        self.height + 1,
-                        vrf_out,
-                        msg.cells.clone(),
-                    );
+                    // the accepted cells, and the fees they pay.
+                    let height = self.height + 1;
+                    let mut cells = msg.cells.clone();
+                    cells.extend(self.fee_cell(height, msg.fees));
+                    cells.extend(self.pending_slashes.drain(..));
+                    let block =
+                        Block::new(self.last_accepted_hash.unwrap(), height, vrf_out, cells)
+                            .with_producer(self.node_id);
                     ctx.notify(GenerateBlock { block });
                     self.committee.set_block_proposed(true);
                 }
@@ -640,3 +1613,26 @@ impl Handler<AcceptedCells> for Hail {
         }
     }
 }
+
+/// Message received from [alpha][crate::alpha] with a [slash cell][crate::alpha::slash::slash_cell].
+///
+/// Slash cells pay no fee, so they aren't sent to the mempool. Only block producers include them
+/// in their blocks.
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+pub struct PendingSlash {
+    pub cell: Cell,
+}
+
+impl Handler<PendingSlash> for Hail {
+    type Result = ();
+
+    fn handle(&mut self, msg: PendingSlash, _ctx: &mut Context<Self>) -> Self::Result {
+        if !self.pending_slashes.contains(&msg.cell) {
+            self.pending_slashes.push(msg.cell);
+        }
+    }
+}
+
+#[cfg(test)]
+mod hail_tests;
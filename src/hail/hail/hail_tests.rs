@@ -0,0 +1,710 @@
+//! Tests for Hail
+
+use super::*;
+
+use crate::alpha::block::genesis_vrf_out;
+use crate::alpha::slash::{self, SignedBlock, SlashEvidence};
+use crate::alpha::transfer::transfer_output;
+use crate::cell::inputs::{Input, Inputs};
+use crate::cell::outputs::Outputs;
+use crate::cell::CellType;
+use crate::storage::TestStore;
+
+use ed25519_dalek::Keypair;
+use rand::rngs::OsRng;
+
+use actix::{Addr, ResponseFuture};
+
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+struct DummyClient;
+
+impl Actor for DummyClient {
+    type Context = Context<Self>;
+}
+
+impl Handler<ClientRequest> for DummyClient {
+    type Result = ResponseFuture<ClientResponse>;
+
+    fn handle(&mut self, msg: ClientRequest, _ctx: &mut Context<Self>) -> Self::Result {
+        match msg {
+            ClientRequest::Fanout { .. } => Box::pin(async { ClientResponse::Fanout(vec![]) }),
+            ClientRequest::Oneshot { .. } => Box::pin(async { ClientResponse::Oneshot(None) }),
+        }
+    }
+}
+
+/// Forwards bootstrap requests to another `Hail` actor
+struct PeerClient {
+    peer: Addr<Hail>,
+}
+
+impl Actor for PeerClient {
+    type Context = Context<Self>;
+}
+
+impl Handler<ClientRequest> for PeerClient {
+    type Result = ResponseFuture<ClientResponse>;
+
+    fn handle(&mut self, msg: ClientRequest, _ctx: &mut Context<Self>) -> Self::Result {
+        let peer = self.peer.clone();
+        match msg {
+            ClientRequest::Fanout { peers, request, .. } => Box::pin(async move {
+                let r = match request {
+                    Request::GetAcceptedBlockFrontier => {
+                        let frontier = peer.send(GetAcceptedBlockFrontier).await.unwrap();
+                        Response::AcceptedBlockFrontier(frontier)
+                    }
+                    x => panic!("unexpected request: {:?}", x),
+                };
+                ClientResponse::Fanout(vec![(peers[0].0, Ok(r))])
+            }),
+            ClientRequest::Oneshot { request, .. } => Box::pin(async move {
+                let r = match request {
+                    Request::FetchBlock(fetch_block) => {
+                        Response::FetchedBlock(peer.send(fetch_block).await.unwrap())
+                    }
+                    x => panic!("unexpected request: {:?}", x),
+                };
+                ClientResponse::Oneshot(Some(r))
+            }),
+        }
+    }
+}
+
+fn mock_ip() -> SocketAddr {
+    "0.0.0.0:1234".parse().unwrap()
+}
+
+fn genesis_block() -> HailBlock {
+    let block = Block {
+        predecessor: None,
+        height: 0,
+        vrf_out: genesis_vrf_out().unwrap(),
+        producer: None,
+        cells: vec![],
+    };
+    HailBlock::new(None, block)
+}
+
+/// Creates a `Hail` instance with the genesis block inserted and a committee in which this
+/// node and one other validator hold stake. The stake of this node is owned by its keypair.
+fn start_test_env() -> (Hail, HailBlock) {
+    let sender = DummyClient {}.start();
+    let mut hail = Hail::new(sender.recipient(), Id::one(), vec![]);
+
+    let genesis = genesis_block();
+    let mut validators = HashMap::new();
+    let _ = validators.insert(Id::two(), (mock_ip(), 1000));
+    hail.committee.next(0, 1000, genesis.vrf_output(), validators);
+    let mut stake_owners = HashMap::new();
+    let _ = stake_owners.insert(Id::one(), Id::from_public_key(&hail.keypair.public).bytes());
+    let _ = stake_owners.insert(Id::two(), [2u8; 32]);
+    hail.committee.set_stake_owners(stake_owners);
+    hail.insert(genesis.clone()).unwrap();
+
+    (hail, genesis)
+}
+
+/// Appends `n` blocks to the chain starting at `parent`, voting for each of them.
+/// Returns the appended blocks.
+fn extend_chain(hail: &mut Hail, parent: &HailBlock, n: u64) -> Vec<HailBlock> {
+    extend_chain_with_cells(hail, parent, n, vec![])
+}
+
+/// Like [extend_chain], the appended blocks containing `cells`.
+fn extend_chain_with_cells(
+    hail: &mut Hail,
+    parent: &HailBlock,
+    n: u64,
+    cells: Vec<Cell>,
+) -> Vec<HailBlock> {
+    let mut blocks = vec![];
+    let mut parent_vx = parent.vertex().unwrap();
+    for _ in 0..n {
+        let height = parent_vx.height + 1;
+        let block = Block::new(parent_vx.block_hash, height, [height as u8; 32], cells.clone());
+        let hail_block = HailBlock::new(Some(parent_vx.clone()), block);
+        let vx = hail_block.vertex().unwrap();
+        hail.insert(hail_block.clone()).unwrap();
+        block_storage::insert_block(&hail.known_blocks, hail_block.clone()).unwrap();
+        hail.dag.set_chit(vx.clone(), 1).unwrap();
+        hail.update_ancestral_preference(vx.clone()).unwrap();
+        blocks.push(hail_block);
+        parent_vx = vx;
+    }
+    blocks
+}
+
+#[actix_rt::test]
+async fn test_receive_block_with_valid_vrf() {
+    let (mut hail, genesis) = start_test_env();
+    let genesis_vx = genesis.vertex().unwrap();
+
+    let vrf_out = hail.committee.block_production_slot().unwrap();
+    let block = Block::new(genesis_vx.block_hash, 1, vrf_out, vec![]).with_producer(Id::one());
+    let hail_block = HailBlock::new(Some(genesis_vx), block).sign(&hail.keypair).unwrap();
+
+    assert_eq!(hail.on_receive_block(hail_block.clone()), Ok(true));
+    assert!(hail.dag.contains_key(&hail_block.vertex().unwrap()));
+}
+
+#[actix_rt::test]
+async fn test_receive_block_with_replayed_vrf() {
+    let (mut hail, genesis) = start_test_env();
+    let genesis_vx = genesis.vertex().unwrap();
+
+    // The VRF output of `Id::one()` can't be claimed by another validator
+    let vrf_out = hail.committee.block_production_slot().unwrap();
+    let block = Block::new(genesis_vx.block_hash, 1, vrf_out, vec![]).with_producer(Id::two());
+    let hail_block = HailBlock::new(Some(genesis_vx.clone()), block.clone());
+    assert_eq!(hail.on_receive_block(hail_block.clone()), Err(Error::InvalidBlock(block)));
+    assert!(!hail.dag.contains_key(&hail_block.vertex().unwrap()));
+
+    // Nor by a block without a producer
+    let block = Block::new(genesis_vx.block_hash, 1, vrf_out, vec![]);
+    let hail_block = HailBlock::new(Some(genesis_vx), block.clone());
+    assert_eq!(hail.on_receive_block(hail_block), Err(Error::InvalidBlock(block)));
+}
+
+#[actix_rt::test]
+async fn test_receive_block_with_invalid_signature() {
+    let (mut hail, genesis) = start_test_env();
+    let genesis_vx = genesis.vertex().unwrap();
+    let vrf_out = hail.committee.block_production_slot().unwrap();
+    let block = Block::new(genesis_vx.block_hash, 1, vrf_out, vec![]).with_producer(Id::one());
+    let hash = block.hash().unwrap();
+
+    // Unsigned
+    let hail_block = HailBlock::new(Some(genesis_vx.clone()), block.clone());
+    assert_eq!(hail.on_receive_block(hail_block), Err(Error::InvalidBlockSignature(hash)));
+
+    // Signed by a key which doesn't own the stake of the producer
+    let other = Keypair::generate(&mut OsRng {});
+    let hail_block = HailBlock::new(Some(genesis_vx.clone()), block).sign(&other).unwrap();
+    assert_eq!(hail.on_receive_block(hail_block.clone()), Err(Error::InvalidBlockSignature(hash)));
+    assert!(!hail.dag.contains_key(&hail_block.vertex().unwrap()));
+}
+
+#[actix_rt::test]
+async fn test_genesis_is_accepted() {
+    let (mut hail, genesis) = start_test_env();
+    let genesis_vx = genesis.vertex().unwrap();
+
+    // Only the genesis is in the DAG, without any confidence yet
+    assert_eq!(hail.is_accepted(&genesis_vx), Ok(false));
+    assert_eq!(hail.get_accepted_frontier(), Ok(vec![]));
+
+    hail.dag.set_chit(genesis_vx.clone(), 1).unwrap();
+    for _ in 0..BETA1 {
+        assert_eq!(hail.is_accepted(&genesis_vx), Ok(false));
+        hail.update_ancestral_preference(genesis_vx.clone()).unwrap();
+    }
+    assert_eq!(hail.is_accepted(&genesis_vx), Ok(true));
+    assert_eq!(hail.get_accepted_frontier(), Ok(vec![genesis_vx.clone()]));
+
+    // Unknown blocks still aren't accepted
+    let unknown = Vertex::new(0, [1; 32]);
+    assert_eq!(hail.is_accepted(&unknown), Err(Error::InvalidBlockHash([1; 32])));
+}
+
+#[actix_rt::test]
+async fn test_receive_block_with_forged_vrf() {
+    let (mut hail, genesis) = start_test_env();
+    let genesis_vx = genesis.vertex().unwrap();
+
+    let forged_vrf_out = [7u8; 32];
+    let block =
+        Block::new(genesis_vx.block_hash, 1, forged_vrf_out, vec![]).with_producer(Id::two());
+    let hail_block = HailBlock::new(Some(genesis_vx), block.clone());
+
+    assert_eq!(hail.on_receive_block(hail_block.clone()), Err(Error::InvalidBlock(block)));
+    assert!(!hail.dag.contains_key(&hail_block.vertex().unwrap()));
+    let known = block_storage::is_known_block(&hail.known_blocks, hail_block.hash().unwrap());
+    assert!(!known.unwrap());
+}
+
+/// Creates `n` transfer cells, each spending an output of `keypair` signed for `chain_id`
+fn signed_cells(keypair: &Keypair, chain_id: ChainId, n: u8) -> Vec<Cell> {
+    let pkh = Id::from_public_key(&keypair.public).bytes();
+    let mut cells = vec![];
+    for i in 0..n {
+        let input = Input::new_on_chain(chain_id, keypair, [i; 32], 0).unwrap();
+        let output = transfer_output(pkh, 1).unwrap();
+        cells.push(Cell::new(Inputs::new(vec![input]), Outputs::new(vec![output])));
+    }
+    cells
+}
+
+#[actix_rt::test]
+async fn test_receive_block_signatures() {
+    let (mut hail, genesis) = start_test_env();
+    let genesis_vx = genesis.vertex().unwrap();
+    let vrf_out = hail.committee.block_production_slot().unwrap();
+    let keypair = Keypair::generate(&mut OsRng {});
+
+    let cells = signed_cells(&keypair, DEFAULT_CHAIN_ID, 100);
+    let block =
+        Block::new(genesis_vx.block_hash, 1, vrf_out, cells.clone()).with_producer(Id::one());
+    let hail_block = HailBlock::new(Some(genesis_vx.clone()), block).sign(&hail.keypair).unwrap();
+    assert_eq!(hail.on_receive_block(hail_block), Ok(true));
+
+    // Replace a cell by one signed for another network
+    let mut cells = cells;
+    let bad_cell = signed_cells(&keypair, 2, 1).remove(0);
+    cells[42] = bad_cell.clone();
+    let block = Block::new(genesis_vx.block_hash, 1, vrf_out, cells).with_producer(Id::one());
+    let hail_block = HailBlock::new(Some(genesis_vx), block).sign(&hail.keypair).unwrap();
+    assert_eq!(
+        hail.on_receive_block(hail_block.clone()),
+        Err(Error::InvalidCellSignature(bad_cell.hash()))
+    );
+    assert!(!hail.dag.contains_key(&hail_block.vertex().unwrap()));
+}
+
+#[actix_rt::test]
+async fn test_receive_block_custom_outputs() {
+    let (hail, genesis) = start_test_env();
+    let genesis_vx = genesis.vertex().unwrap();
+    let vrf_out = hail.committee.block_production_slot().unwrap();
+    let keypair = Keypair::generate(&mut OsRng {});
+
+    let mut cells = signed_cells(&keypair, DEFAULT_CHAIN_ID, 2);
+    let mut outputs = cells[1].outputs();
+    outputs[0].cell_type = CellType::Custom(7);
+    cells[1] = Cell::new(cells[1].inputs(), outputs);
+    let block = Block::new(genesis_vx.block_hash, 1, vrf_out, cells).with_producer(Id::one());
+    let hail_block = HailBlock::new(Some(genesis_vx), block).sign(&hail.keypair).unwrap();
+
+    // Custom outputs without a registered validator are refused
+    let mut unregistered = hail;
+    assert_eq!(
+        unregistered.on_receive_block(hail_block.clone()),
+        Err(Error::Alpha(crate::cell::Error::UnknownCellType(7).into()))
+    );
+    assert!(!unregistered.dag.contains_key(&hail_block.vertex().unwrap()));
+
+    let mut cell_types = CellTypeRegistry::new();
+    cell_types.register(7, Box::new(|_| Ok(())));
+    let mut hail = unregistered.with_cell_types(cell_types);
+    assert_eq!(hail.on_receive_block(hail_block), Ok(true));
+}
+
+#[actix_rt::test]
+async fn test_query_forged_block_is_voted_down() {
+    let (hail, genesis) = start_test_env();
+    let genesis_vx = genesis.vertex().unwrap();
+    let hail = hail.start();
+
+    let forged_vrf_out = [7u8; 32];
+    let block =
+        Block::new(genesis_vx.block_hash, 1, forged_vrf_out, vec![]).with_producer(Id::two());
+    let hail_block = HailBlock::new(Some(genesis_vx), block);
+
+    let QueryBlockAck { outcome, .. } =
+        hail.send(QueryBlock { id: Id::two(), block: hail_block }).await.unwrap();
+    assert!(!outcome);
+}
+
+#[actix_rt::test]
+async fn test_get_chain_tip() {
+    let (mut hail, genesis) = start_test_env();
+    let genesis_vx = genesis.vertex().unwrap();
+    let mut blocks = vec![];
+    let mut parent_vx = genesis_vx;
+    for height in 1..3 {
+        let block = Block::new(parent_vx.block_hash, height, [height as u8; 32], vec![]);
+        let hail_block = HailBlock::new(Some(parent_vx.clone()), block);
+        hail.insert(hail_block.clone()).unwrap();
+        parent_vx = hail_block.vertex().unwrap();
+        blocks.push(hail_block);
+    }
+    let hail = hail.start();
+    assert_eq!(hail.send(GetChainTip).await.unwrap().tip, None);
+
+    // Both blocks are voted for by the other validator
+    for block in blocks.iter() {
+        let ack = QueryBlockAck { id: Id::two(), block_hash: block.hash().unwrap(), outcome: true };
+        let acks = vec![Response::QueryBlockAck(ack)];
+        hail.send(QueryComplete { block: block.clone(), acks }).await.unwrap();
+    }
+
+    let ChainTipAck { tip } = hail.send(GetChainTip).await.unwrap();
+    let expected = ChainTip { height: 2, last_accepted_hash: blocks[1].hash().unwrap() };
+    assert_eq!(tip, Some(expected));
+}
+
+#[actix_rt::test]
+async fn test_get_blocks_by_range() {
+    let (mut hail, genesis) = start_test_env();
+    let mut blocks = vec![];
+    let mut parent = genesis;
+    for height in 1..=10 {
+        let block = child_block(&parent, height as u8);
+        hail.insert(block.clone()).unwrap();
+        parent = block.clone();
+        blocks.push(block);
+    }
+    let hail = hail.with_max_blocks_range(5).start();
+    for block in blocks.iter() {
+        let ack = QueryBlockAck { id: Id::two(), block_hash: block.hash().unwrap(), outcome: true };
+        let acks = vec![Response::QueryBlockAck(ack)];
+        hail.send(QueryComplete { block: block.clone(), acks }).await.unwrap();
+    }
+
+    let BlocksAck { blocks: range } = hail.send(GetBlocksByRange { from: 3, to: 7 }).await.unwrap();
+    let expected: Vec<Block> = blocks[2..7].iter().map(|b| b.inner()).collect();
+    assert_eq!(range, expected);
+    // The range is capped by `max_blocks_range`
+    let BlocksAck { blocks: range } =
+        hail.send(GetBlocksByRange { from: 1, to: 10 }).await.unwrap();
+    let heights: Vec<BlockHeight> = range.iter().map(|b| b.height).collect();
+    assert_eq!(heights, vec![1, 2, 3, 4, 5]);
+    // The blocks stop at the first unknown height
+    let BlocksAck { blocks: range } =
+        hail.send(GetBlocksByRange { from: 9, to: 12 }).await.unwrap();
+    let heights: Vec<BlockHeight> = range.iter().map(|b| b.height).collect();
+    assert_eq!(heights, vec![9, 10]);
+}
+
+/// Returns a block at `height` extending `parent`, distinguished by `seed`
+fn child_block(parent: &HailBlock, seed: u8) -> HailBlock {
+    let parent_vx = parent.vertex().unwrap();
+    let block = Block::new(parent_vx.block_hash, parent_vx.height + 1, [seed; 32], vec![]);
+    HailBlock::new(Some(parent_vx), block)
+}
+
+#[actix_rt::test]
+async fn test_reorg_to_competing_block() {
+    let (mut hail, genesis) = start_test_env();
+
+    // Two competing blocks at height 1, `first` being preferred initially
+    let (a, b) = (child_block(&genesis, 1), child_block(&genesis, 2));
+    let (first, second) = if a.hash().unwrap() < b.hash().unwrap() { (a, b) } else { (b, a) };
+    let first_child = child_block(&first, 3);
+    let second_child = child_block(&second, 4);
+    let second_grandchild = child_block(&second_child, 5);
+    for block in [&first, &second, &first_child, &second_child, &second_grandchild] {
+        hail.insert(block.clone()).unwrap();
+    }
+    let hail = hail.start();
+
+    let query_complete = |block: &HailBlock| {
+        let ack = QueryBlockAck { id: Id::two(), block_hash: block.hash().unwrap(), outcome: true };
+        QueryComplete { block: block.clone(), acks: vec![Response::QueryBlockAck(ack)] }
+    };
+    let tip = |block: &HailBlock| {
+        let vx = block.vertex().unwrap();
+        Some(ChainTip { height: vx.height, last_accepted_hash: vx.block_hash })
+    };
+    let is_live = |block: &HailBlock| {
+        let block_hash = block.hash().unwrap();
+        let hail = hail.clone();
+        async move { hail.send(GetBlock { block_hash }).await.unwrap().block.is_some() }
+    };
+
+    hail.send(query_complete(&first)).await.unwrap();
+    hail.send(query_complete(&first_child)).await.unwrap();
+    assert_eq!(hail.send(GetChainTip).await.unwrap().tip, tip(&first_child));
+
+    // The competing branch isn't the tip until it becomes preferred
+    hail.send(query_complete(&second)).await.unwrap();
+    hail.send(query_complete(&second_child)).await.unwrap();
+    assert_eq!(hail.send(GetChainTip).await.unwrap().tip, tip(&first_child));
+
+    // `second` gains more conviction than `first` and supersedes it
+    hail.send(query_complete(&second_grandchild)).await.unwrap();
+    assert_eq!(hail.send(GetChainTip).await.unwrap().tip, tip(&second_grandchild));
+    assert!(!is_live(&first).await);
+    assert!(!is_live(&first_child).await);
+    assert!(is_live(&second).await);
+    assert!(is_live(&second_child).await);
+    assert!(is_live(&second_grandchild).await);
+}
+
+#[actix_rt::test]
+async fn test_bootstrap_accepted_frontier() {
+    let (mut hail1, genesis) = start_test_env();
+    block_storage::insert_block(&hail1.known_blocks, genesis.clone()).unwrap();
+    let blocks = extend_chain(&mut hail1, &genesis, BETA1 as u64);
+    let frontier = hail1.get_accepted_frontier().unwrap();
+    assert_eq!(frontier, vec![blocks[0].vertex().unwrap()]);
+    let hail1 = hail1.start();
+
+    let client = PeerClient { peer: hail1 }.start();
+    let hail2 = Hail::new(client.recipient(), Id::two(), vec![(Id::one(), mock_ip())]).start();
+
+    let mut bootstrapped = false;
+    for _ in 0..50 {
+        if hail2.send(Bootstrapped).await.unwrap() {
+            bootstrapped = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(bootstrapped);
+
+    let frontier_block = blocks[0].inner();
+    let BlockAck { block } =
+        hail2.send(GetBlock { block_hash: frontier_block.hash().unwrap() }).await.unwrap();
+    assert_eq!(block, Some(frontier_block));
+
+    // The frontier block is accepted, and so is the frontier of `hail2`
+    let AcceptedBlockFrontier { frontier } = hail2.send(GetAcceptedBlockFrontier).await.unwrap();
+    assert_eq!(frontier, vec![blocks[0].vertex().unwrap()]);
+}
+
+#[actix_rt::test]
+async fn test_bootstrap_verifies_fetched_blocks() {
+    let (mut hail1, genesis) = start_test_env();
+    block_storage::insert_block(&hail1.known_blocks, genesis.clone()).unwrap();
+    let keypair = Keypair::generate(&mut OsRng {});
+    let cells = signed_cells(&keypair, DEFAULT_CHAIN_ID, 2);
+    let blocks = extend_chain_with_cells(&mut hail1, &genesis, BETA1 as u64, cells.clone());
+    let hail1 = hail1.start();
+
+    // The cells of the blocks are signed for another network than the one of `hail2`
+    let client = PeerClient { peer: hail1 }.start();
+    let hail2 = Hail::new(client.recipient(), Id::two(), vec![(Id::one(), mock_ip())])
+        .with_chain_id(2)
+        .start();
+    let mut bootstrapped = false;
+    for _ in 0..50 {
+        if hail2.send(Bootstrapped).await.unwrap() {
+            bootstrapped = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(bootstrapped);
+
+    let BlockAck { block } =
+        hail2.send(GetBlock { block_hash: blocks[0].hash().unwrap() }).await.unwrap();
+    assert_eq!(block, None);
+    let AcceptedBlockFrontier { frontier } = hail2.send(GetAcceptedBlockFrontier).await.unwrap();
+    assert!(frontier.is_empty());
+}
+
+#[actix_rt::test]
+async fn test_checkpoint() {
+    let (mut hail, genesis) = start_test_env();
+    block_storage::insert_block(&hail.known_blocks, genesis.clone()).unwrap();
+    let blocks = extend_chain(&mut hail, &genesis, 15);
+    let frontier = hail.get_accepted_frontier().unwrap();
+    assert_eq!(frontier, vec![blocks[4].vertex().unwrap()]);
+
+    // The checkpoint can't be above the accepted frontier
+    assert_eq!(hail.set_checkpoint(6), Err(Error::InvalidCheckpoint(6)));
+    assert_eq!(hail.set_checkpoint(5), Ok(blocks[4].vertex().unwrap()));
+    // ... nor go backwards
+    assert_eq!(hail.set_checkpoint(3), Err(Error::InvalidCheckpoint(3)));
+
+    let mut chain = vec![genesis];
+    chain.extend(blocks.iter().cloned());
+    for block in chain[..=5].iter() {
+        assert!(!hail.dag.contains_key(&block.vertex().unwrap()));
+    }
+    for block in chain[6..].iter() {
+        assert!(hail.dag.contains_key(&block.vertex().unwrap()));
+    }
+    // Blocks at or below the checkpoint are refused
+    let late_block = child_block(&chain[2], 42);
+    assert_eq!(hail.on_receive_block(late_block), Err(Error::BelowCheckpoint(3)));
+
+    // The blocks up to the checkpoint are still served from storage
+    let hail = hail.start();
+    for block in chain[..=5].iter() {
+        let inner = block.inner();
+        let BlockAck { block } =
+            hail.send(GetBlock { block_hash: inner.hash().unwrap() }).await.unwrap();
+        assert_eq!(block, Some(inner.clone()));
+        let BlockAck { block } =
+            hail.send(GetBlockByHeight { block_height: inner.height }).await.unwrap();
+        assert_eq!(block, Some(inner));
+    }
+    let BlocksAck { blocks: range } = hail.send(GetBlocksByRange { from: 0, to: 5 }).await.unwrap();
+    assert_eq!(range.len(), 6);
+}
+
+#[actix_rt::test]
+async fn test_checkpoint_restored() {
+    let (mut hail, genesis) = start_test_env();
+    let finalized_blocks = Arc::new(TestStore::new());
+    hail = hail.with_finalized_blocks(finalized_blocks.clone());
+    assert_eq!(hail.checkpoint, None);
+    block_storage::insert_block(&hail.known_blocks, genesis.clone()).unwrap();
+    let blocks = extend_chain(&mut hail, &genesis, 15);
+    let checkpoint = hail.set_checkpoint(5).unwrap();
+
+    // A restarted node resumes from the checkpoint
+    let sender = DummyClient {}.start();
+    let restored =
+        Hail::new(sender.recipient(), Id::one(), vec![]).with_finalized_blocks(finalized_blocks);
+    assert_eq!(restored.checkpoint, Some(checkpoint));
+    assert_eq!(restored.finalized_block_at(3), Some(blocks[2].inner()));
+    assert_eq!(restored.finalized_block_at(6), None);
+    // The blocks are also found by their hashes, without the known blocks of before the restart
+    assert_eq!(restored.finalized_block(blocks[2].hash().unwrap()), Some(blocks[2].inner()));
+    assert_eq!(restored.finalized_block(blocks[5].hash().unwrap()), None);
+}
+
+#[actix_rt::test]
+async fn test_checkpoint_not_stored() {
+    let (mut hail, genesis) = start_test_env();
+    hail = hail.with_finalized_blocks(Arc::new(TestStore::read_only()));
+    block_storage::insert_block(&hail.known_blocks, genesis.clone()).unwrap();
+    let blocks = extend_chain(&mut hail, &genesis, 15);
+
+    // Nothing is pruned unless the blocks are stored
+    assert!(matches!(hail.set_checkpoint(5), Err(Error::Storage(_))));
+    assert_eq!(hail.checkpoint, None);
+    for block in blocks.iter() {
+        assert!(hail.dag.contains_key(&block.vertex().unwrap()));
+    }
+}
+
+#[actix_rt::test]
+async fn test_shutdown_flushes_finalized_blocks() {
+    let (hail, _genesis) = start_test_env();
+    let finalized_blocks = Arc::new(TestStore::new());
+    let hail = hail.with_finalized_blocks(finalized_blocks.clone()).start();
+
+    hail.send(Shutdown { timeout: Duration::from_secs(1) }).await.unwrap();
+    assert!(finalized_blocks.flushes() >= 1);
+}
+
+/// Records the accepted heights
+struct HeightRecorder {
+    heights: Arc<Mutex<Vec<BlockHeight>>>,
+}
+
+impl Actor for HeightRecorder {
+    type Context = Context<Self>;
+}
+
+impl Handler<AcceptedHeight> for HeightRecorder {
+    type Result = ();
+
+    fn handle(&mut self, msg: AcceptedHeight, _ctx: &mut Context<Self>) -> Self::Result {
+        self.heights.lock().unwrap().push(msg.height);
+    }
+}
+
+#[actix_rt::test]
+async fn test_subscribe_accepted_height() {
+    let (mut hail, genesis) = start_test_env();
+    let blocks = extend_chain(&mut hail, &genesis, 2);
+    let _ = hail.accepted_vertices.insert(blocks[0].vertex().unwrap());
+    let hail = hail.start();
+
+    let heights = Arc::new(Mutex::new(vec![]));
+    let recorder = HeightRecorder { heights: heights.clone() }.start();
+    hail.send(SubscribeAcceptedHeight { recipient: recorder.recipient() }).await.unwrap();
+    hail.send(Accepted { vertex: blocks[1].vertex().unwrap() }).await.unwrap();
+    for _ in 0..50 {
+        if heights.lock().unwrap().len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // The subscriber learns the highest accepted height right away, then each accepted height
+    assert_eq!(*heights.lock().unwrap(), vec![1, 2]);
+}
+
+/// Records the queried blocks, the queries are left unanswered
+struct QueryRecorder {
+    queried: Arc<Mutex<Vec<HailBlock>>>,
+}
+
+impl Actor for QueryRecorder {
+    type Context = Context<Self>;
+}
+
+impl Handler<ClientRequest> for QueryRecorder {
+    type Result = ResponseFuture<ClientResponse>;
+
+    fn handle(&mut self, msg: ClientRequest, _ctx: &mut Context<Self>) -> Self::Result {
+        match msg {
+            ClientRequest::Fanout { request, .. } => {
+                if let Request::QueryBlock(QueryBlock { block, .. }) = request {
+                    self.queried.lock().unwrap().push(block);
+                }
+                Box::pin(async { ClientResponse::Fanout(vec![]) })
+            }
+            ClientRequest::Oneshot { .. } => Box::pin(async { ClientResponse::Oneshot(None) }),
+        }
+    }
+}
+
+#[actix_rt::test]
+async fn test_fee_collection() {
+    let (hail, genesis) = start_test_env();
+    let owner = Id::from_public_key(&hail.keypair.public).bytes();
+    let fee_address = [3u8; 32];
+    let mut hail = hail.with_fee_address(fee_address);
+    let queried = Arc::new(Mutex::new(vec![]));
+    hail.sender = QueryRecorder { queried: queried.clone() }.start().recipient();
+    hail.last_accepted_hash = Some(genesis.hash().unwrap());
+    let hail = hail.start();
+
+    let cells = signed_cells(&Keypair::generate(&mut OsRng), DEFAULT_CHAIN_ID, 2);
+    hail.send(AcceptedCells { cells: cells.clone(), fees: 6 }).await.unwrap();
+    for _ in 0..50 {
+        if !queried.lock().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // The produced block is signed by this validator
+    let hail_block = queried.lock().unwrap()[0].clone();
+    assert_eq!(hail_block.producer(), Some(Id::one()));
+    assert_eq!(hail_block.verify_signature(&owner), Ok(()));
+
+    // The produced block collects the fees of the accepted cells
+    let block = hail_block.inner();
+    assert_eq!(block.height, 1);
+    assert_eq!(block.cells.len(), 3);
+    assert_eq!(block.cells[..2], cells[..]);
+    let expected = coinbase::fee_cell(fee_address, 1, 6).unwrap();
+    assert_eq!(block.cells[2], expected);
+}
+
+#[actix_rt::test]
+async fn test_pending_slash() {
+    let (mut hail, genesis) = start_test_env();
+    let queried = Arc::new(Mutex::new(vec![]));
+    hail.sender = QueryRecorder { queried: queried.clone() }.start().recipient();
+    hail.last_accepted_hash = Some(genesis.hash().unwrap());
+    let hail = hail.start();
+
+    let keypair = Keypair::generate(&mut OsRng);
+    let block = |vrf_out| Block::new(genesis.hash().unwrap(), 1, vrf_out, vec![]);
+    let evidence = SlashEvidence {
+        height: 1,
+        block_a: SignedBlock::new(&keypair, block([1u8; 32])).unwrap(),
+        block_b: SignedBlock::new(&keypair, block([2u8; 32])).unwrap(),
+    };
+    let slash_cell = slash::slash_cell(&evidence).unwrap();
+    hail.send(PendingSlash { cell: slash_cell.clone() }).await.unwrap();
+    hail.send(PendingSlash { cell: slash_cell.clone() }).await.unwrap();
+
+    let cells = signed_cells(&Keypair::generate(&mut OsRng), DEFAULT_CHAIN_ID, 2);
+    hail.send(AcceptedCells { cells: cells.clone(), fees: 6 }).await.unwrap();
+    for _ in 0..50 {
+        if !queried.lock().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // The produced block includes the slash cell once, after the accepted cells
+    let hail_block = queried.lock().unwrap()[0].clone();
+    let block = hail_block.inner();
+    assert_eq!(block.cells.len(), 3);
+    assert_eq!(block.cells[..2], cells[..]);
+    assert_eq!(block.cells[2], slash_cell);
+}
@@ -4,7 +4,8 @@
 //! It is the primary consensus mechanism for all block based chains defined within the `zero.fx` network.
 //! It is specialised to blocks and ensures that no two conflicting blocks can be accepted at the same height.
 //! Similar to [`sleet`][crate::sleet], no inner verification of the block contents nor execution
-//! of state transitions is done besides on [alpha][crate::alpha] primitive cells (such as staking cells).
+//! of state transitions is done besides on [alpha][crate::alpha] primitive cells (such as staking cells)
+//! and checking the signatures of the cells' inputs.
 
 pub mod block;
 mod committee;
@@ -13,28 +14,44 @@ mod conflict_set;
 mod hail;
 mod vertex;
 
+pub use committee::StakingPeriod;
 pub use hail::*;
 pub use vertex::Vertex;
 
 use crate::alpha;
 use crate::alpha::block::Block;
 use crate::alpha::types::{BlockHash, BlockHeight};
+use crate::cell::types::CellHash;
+use crate::client::ClientError;
 use crate::graph;
+use crate::storage;
 
 /// The module's error type
 #[derive(Debug, Eq, PartialEq)]
 pub enum Error {
     ActixMailboxError,
+    /// A request to other nodes failed, or was answered with an unexpected response
+    Client(ClientError),
     Alpha(alpha::Error),
     Sled(sled::Error),
     Graph(graph::Error),
     InvalidBlock(Block),
     InvalidBlockHash(BlockHash),
     InvalidBlockHeight(BlockHeight),
+    /// The block contains a cell with an invalid input signature
+    InvalidCellSignature(CellHash),
     InvalidParent,
     InvalidConflictSet,
     InsufficientWeight,
     EmptyDAG,
+    /// A checkpoint can't be set at this height
+    InvalidCheckpoint(BlockHeight),
+    /// The block is at or below the checkpoint
+    BelowCheckpoint(BlockHeight),
+    /// The block isn't signed by the owner of its producer's stake
+    InvalidBlockSignature(BlockHash),
+    /// The finalized blocks or the checkpoint couldn't be stored
+    Storage(String),
 }
 
 impl std::error::Error for Error {}
@@ -45,12 +62,24 @@ impl std::convert::From<actix::MailboxError> for Error {
     }
 }
 
+impl std::convert::From<ClientError> for Error {
+    fn from(error: ClientError) -> Self {
+        Error::Client(error)
+    }
+}
+
 impl std::convert::From<sled::Error> for Error {
     fn from(error: sled::Error) -> Self {
         Error::Sled(error)
     }
 }
 
+impl std::convert::From<storage::Error> for Error {
+    fn from(error: storage::Error) -> Self {
+        Error::Storage(format!("{:?}", error))
+    }
+}
+
 impl std::convert::From<alpha::Error> for Error {
     fn from(error: alpha::Error) -> Self {
         Error::Alpha(error)
@@ -24,3 +24,27 @@ pub const ALPHA: f64 = 0.5;
 pub const K: usize = 2;
 /// Beta one parameter (safe precommit)
 pub const BETA1: usize = 3;
+/// Maximum number of peers held in the reservoir
+pub const RESERVOIR_CAPACITY: usize = 1024;
+
+/// Consensus settings of [Ice][super::Ice], defaulting to the constants above
+///
+/// Small test networks can use a smaller sample and confidence threshold to converge quickly.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IceConfig {
+    /// Maximum number of peers held in the reservoir
+    pub reservoir_capacity: usize,
+    /// Number of peers sampled for queries, and of votes needed for a decision
+    pub k: usize,
+    /// Percent of the `k` votes needed for a decision
+    pub alpha: f64,
+    /// Number of consecutive decisions needed to confirm the liveness of a peer
+    pub beta1: usize,
+}
+
+impl Default for IceConfig {
+    fn default() -> Self {
+        IceConfig { reservoir_capacity: RESERVOIR_CAPACITY, k: K, alpha: ALPHA, beta1: BETA1 }
+    }
+}
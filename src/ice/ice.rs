@@ -1,8 +1,9 @@
 use crate::zfx_id::Id;
 
 use crate::alpha::{self, Alpha};
-use crate::client::{ClientRequest, ClientResponse};
+use crate::client::{ClientError, ClientRequest, ClientResponse, PeerUnreachable};
 use crate::colored::Colorize;
+use crate::metrics::METRICS;
 use crate::protocol::{Request, Response};
 use crate::util;
 use crate::view::{self, View};
@@ -17,10 +18,10 @@ use super::reservoir::Reservoir;
 
 use tracing::{debug, error, info};
 
-use actix::{Actor, Addr, Context, Handler, Recipient};
+use actix::{Actor, ActorContext, Addr, Context, Handler, MailboxError, Recipient};
 use actix::{ActorFutureExt, ResponseActFuture};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 
 use actix::WrapFuture;
@@ -46,6 +47,10 @@ pub struct Ice {
     /// Address of the [`DisseminationComponent`][super::dissemination::DisseminationComponent] to
     /// pull gossip messages from
     dc_recipient: Recipient<GossipQuery>,
+    /// Peers which were live, but are no longer reachable
+    lost_peers: HashSet<Id>,
+    /// Whether all live peers were lost, i.e. this node is partitioned from the network
+    isolated: bool,
 }
 
 impl Ice {
@@ -56,7 +61,58 @@ impl Ice {
         reservoir: Reservoir,
         dc_recipient: Recipient<GossipQuery>,
     ) -> Self {
-        Ice { sender, id, ip, reservoir, bootstrapped: false, dc_recipient }
+        Ice {
+            sender,
+            id,
+            ip,
+            reservoir,
+            bootstrapped: false,
+            dc_recipient,
+            lost_peers: HashSet::new(),
+            isolated: false,
+        }
+    }
+
+    /// Use the consensus settings of `config` instead of the [defaults][IceConfig::default]
+    pub fn with_config(mut self, config: IceConfig) -> Self {
+        self.reservoir.set_config(config);
+        self
+    }
+}
+
+impl Ice {
+    fn live_peer_ids(&self) -> HashSet<Id> {
+        self.reservoir.get_live_peers().iter().map(|(id, _)| *id).collect()
+    }
+
+    /// Tracks the peers which were `live_before` a query round but aren't live anymore.
+    ///
+    /// Returns whether lost peers are live again, and whether this node rejoined the
+    /// network after being isolated.
+    fn track_lost_peers(&mut self, live_before: HashSet<Id>) -> (bool, bool) {
+        let live_now = self.live_peer_ids();
+        METRICS.ice_live_peers.set(live_now.len() as i64);
+        for id in live_before.difference(&live_now) {
+            info!("[{}] lost live peer {}", "ice".magenta(), id);
+            let _ = self.lost_peers.insert(*id);
+        }
+        let recovered: Vec<Id> = self.lost_peers.intersection(&live_now).cloned().collect();
+        for id in recovered.iter() {
+            info!("[{}] live peer {} is reachable again", "ice".magenta(), id);
+            let _ = self.lost_peers.remove(id);
+        }
+        if live_now.is_empty() && !self.lost_peers.is_empty() {
+            if !self.isolated {
+                info!("[{}] isolated from the network", "ice".magenta());
+            }
+            self.isolated = true;
+        }
+        let rejoined = self.isolated && !live_now.is_empty();
+        if rejoined {
+            info!("[{}] rejoined the network", "ice".magenta());
+            self.isolated = false;
+        }
+        (!recovered.is_empty(), rejoined)
     }
 }
 
@@ -68,6 +124,21 @@ impl Actor for Ice {
     }
 }
 
+/// Stops [Ice]: once the response arrives, messages sent to [Ice] fail with a closed mailbox,
+/// which ends the protocol rounds of [run].
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct Shutdown;
+
+impl Handler<Shutdown> for Ice {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Shutdown, ctx: &mut Context<Self>) -> Self::Result {
+        info!("[{}] shutting down", "ice".magenta());
+        ctx.stop();
+    }
+}
+
 /// Network ping message
 ///
 /// It carries gossip messages, and [queries][super::Query] about other nodes' liveness.
@@ -209,6 +280,10 @@ struct PingSuccess {
 struct Switch {
     flipped: bool,
     bootstrapped: bool,
+    /// Peers which were lost became live again
+    recovered: bool,
+    /// This node rejoined the network after being isolated
+    rejoined: bool,
 }
 
 impl Handler<PingSuccess> for Ice {
@@ -217,20 +292,22 @@ impl Handler<PingSuccess> for Ice {
     // The peer responded successfully
     fn handle(&mut self, msg: PingSuccess, _ctx: &mut Context<Self>) -> Self::Result {
         let ack = msg.ack.clone();
+        let live_before = self.live_peer_ids();
         let (is_bootstrapped, flipped) = self.reservoir.fill(ack.id, ack.outcomes);
+        let (recovered, rejoined) = self.track_lost_peers(live_before);
         if is_bootstrapped {
             if self.bootstrapped {
-                Switch { flipped, bootstrapped: true }
+                Switch { flipped, bootstrapped: true, recovered, rejoined }
             } else {
                 self.bootstrapped = true;
-                Switch { flipped: true, bootstrapped: true }
+                Switch { flipped: true, bootstrapped: true, recovered, rejoined }
             }
         } else {
             if !self.bootstrapped {
-                Switch { flipped: false, bootstrapped: false }
+                Switch { flipped: false, bootstrapped: false, recovered, rejoined }
             } else {
                 self.bootstrapped = false;
-                Switch { flipped: true, bootstrapped: false }
+                Switch { flipped: true, bootstrapped: false, recovered, rejoined }
             }
         }
     }
@@ -248,9 +325,32 @@ impl Handler<PingFailure> for Ice {
 
     // The peer did not respond or responded erroneously
     fn handle(&mut self, msg: PingFailure, _ctx: &mut Context<Self>) -> Self::Result {
+        self.peer_failed(msg.id)
+    }
+}
+
+impl Handler<PeerUnreachable> for Ice {
+    type Result = ();
+
+    // The connections to the peer missed too many heartbeats. If this reverts `ice` to a
+    // non-bootstrapped state, the `alpha` chain is alerted after the next ping.
+    fn handle(&mut self, msg: PeerUnreachable, _ctx: &mut Context<Self>) -> Self::Result {
+        info!("[{}] peer {} is unreachable", "ice".magenta(), msg.id);
+        let _ = self.peer_failed(msg.id);
+    }
+}
+
+impl Ice {
+    /// Marks the peer `id` as faulty.
+    ///
+    /// Returns `true` if this reverts `ice` to a non-bootstrapped state.
+    fn peer_failed(&mut self, id: Id) -> bool {
+        let live_before = self.live_peer_ids();
+        let is_bootstrapped = self.reservoir.update_choice(id, Choice::Faulty);
+        let _ = self.track_lost_peers(live_before);
         // If updating the choice to `Faulty` reverts `ice` to a non-bootstrapped state,
         // communicate this to the `alpha` chain.
-        if !self.reservoir.update_choice(msg.id, Choice::Faulty) {
+        if !is_bootstrapped {
             if self.bootstrapped {
                 return true;
             }
@@ -259,23 +359,30 @@ impl Handler<PingFailure> for Ice {
     }
 }
 
-/// Actor message to request a list of live peers
+/// Actor message to request the peers of the reservoir along with their liveness
 #[derive(Debug, Clone, Serialize, Deserialize, Message)]
 #[rtype(result = "LivePeers")]
 pub struct GetLivePeers;
 
-/// Message containing the list of live peers
+/// Message containing the peers of the reservoir, and whether each of them is live (has a
+/// [Live][Choice::Live] choice with sufficient conviction)
 #[derive(Debug, Clone, Serialize, Deserialize, MessageResponse)]
 pub struct LivePeers {
-    pub live_peers: Vec<(Id, SocketAddr)>,
+    pub peers: Vec<(Id, SocketAddr, bool)>,
+}
+
+impl LivePeers {
+    /// The peers which are live
+    pub fn live_peers(&self) -> Vec<(Id, SocketAddr)> {
+        self.peers.iter().filter(|(_, _, live)| *live).map(|(id, ip, _)| (*id, *ip)).collect()
+    }
 }
 
 impl Handler<GetLivePeers> for Ice {
     type Result = LivePeers;
 
-    // The peer did not respond or responded erroneously
     fn handle(&mut self, _msg: GetLivePeers, _ctx: &mut Context<Self>) -> Self::Result {
-        LivePeers { live_peers: self.reservoir.get_live_peers() }
+        LivePeers { peers: self.reservoir.get_peer_liveness() }
     }
 }
 
@@ -401,6 +508,8 @@ impl Handler<DoPing> for Ice {
                             queries: msg.queries,
                             rumours: rumours,
                         }),
+                        timeout: None,
+                        retries: 0,
                     })
                     .await
             }
@@ -417,7 +526,7 @@ impl Handler<DoPing> for Ice {
                             None => Err(Error::Crash),
                         }
                     }
-                    Ok(_) => Err(Error::InvalidResponse),
+                    Ok(_) => Err(Error::Client(ClientError::UnexpectedResponseKind)),
                     Err(e) => Err(Error::Actix(e)),
                 }
             }),
@@ -425,6 +534,20 @@ impl Handler<DoPing> for Ice {
     }
 }
 
+/// Actor message to check whether [`Ice`] is bootstrapped, without fetching the peers like
+/// [`CheckStatus`]
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "bool")]
+pub struct IsBootstrapped;
+
+impl Handler<IsBootstrapped> for Ice {
+    type Result = bool;
+
+    fn handle(&mut self, _msg: IsBootstrapped, _ctx: &mut Context<Self>) -> Self::Result {
+        self.bootstrapped
+    }
+}
+
 /// Actor message to check the status of [`Ice`]
 #[derive(Debug, Clone, Serialize, Deserialize, Message)]
 #[rtype(result = "Status")]
@@ -450,26 +573,39 @@ impl Handler<CheckStatus> for Ice {
     }
 }
 
-async fn send_ping_success(self_id: Id, ice: Addr<Ice>, alpha: Addr<Alpha>, ack: Ack) {
-    let switch = ice.send(PingSuccess { ack: ack.clone() }).await.unwrap();
-    if switch.flipped {
+async fn send_ping_success(
+    self_id: Id,
+    ice: Addr<Ice>,
+    live_network: &Recipient<alpha::LiveNetwork>,
+    faulty_network: &Recipient<alpha::FaultyNetwork>,
+    ack: Ack,
+) -> std::result::Result<(), MailboxError> {
+    let switch = ice.send(PingSuccess { ack: ack.clone() }).await?;
+    if switch.flipped && !switch.bootstrapped {
         // If flipped from `LiveNetwork` to `FaultyNetwork`, alert the `Alpha` chain.
-        if !switch.bootstrapped {
-            alpha.send(alpha::FaultyNetwork).await.unwrap();
-        } else {
-            // Otherwise alert the `Alpha` chain of a `LiveNetwork`.
-            let LivePeers { live_peers } = ice.send(GetLivePeers {}).await.unwrap();
-            alpha.send(alpha::LiveNetwork { self_id, live_peers }).await.unwrap();
-        }
+        faulty_network.send(alpha::FaultyNetwork).await?;
+    } else if switch.bootstrapped && (switch.flipped || switch.recovered || switch.rejoined) {
+        // Otherwise alert the `Alpha` chain of a `LiveNetwork`, including when lost peers
+        // are reachable again after a partition healed, so that they rejoin the committee.
+        let live_peers = ice.send(GetLivePeers {}).await?.live_peers();
+        let rejoined = switch.rejoined;
+        live_network.send(alpha::LiveNetwork { self_id, live_peers, rejoined }).await?;
     }
+    Ok(())
 }
 
-async fn send_ping_failure(ice: Addr<Ice>, alpha: Addr<Alpha>, id: Id, ip: SocketAddr) {
-    let flipped = ice.send(PingFailure { id: id.clone(), ip: ip.clone() }).await.unwrap();
+async fn send_ping_failure(
+    ice: Addr<Ice>,
+    faulty_network: &Recipient<alpha::FaultyNetwork>,
+    id: Id,
+    ip: SocketAddr,
+) -> std::result::Result<(), MailboxError> {
+    let flipped = ice.send(PingFailure { id: id.clone(), ip: ip.clone() }).await?;
     // If flipped from `LiveNetwork` to `FaultyNetwork`, alert the `Alpha` chain.
     if flipped {
-        alpha.send(alpha::FaultyNetwork).await.unwrap();
+        faulty_network.send(alpha::FaultyNetwork).await?;
     }
+    Ok(())
 }
 
 /// Run the protocol in rounds
@@ -477,33 +613,57 @@ async fn send_ping_failure(ice: Addr<Ice>, alpha: Addr<Alpha>, id: Id, ip: Socke
 /// This function drives the `Ice` component in a loop (see [PROTOCOL_PERIOD]).
 /// It samples peers to query and handles the results.
 ///
+/// The rounds end once `ice` is [shut down][Shutdown].
 pub async fn run(self_id: Id, ice: Addr<Ice>, view: Addr<View>, alpha: Addr<Alpha>) {
+    if let Err(e) = run_rounds(self_id, ice, view, alpha).await {
+        info!("[{}] stopped running the protocol: {:?}", "ice".magenta(), e);
+    }
+}
+
+async fn run_rounds(
+    self_id: Id,
+    ice: Addr<Ice>,
+    view: Addr<View>,
+    alpha: Addr<Alpha>,
+) -> std::result::Result<(), MailboxError> {
+    let live_network = alpha.clone().recipient();
+    let faulty_network = alpha.recipient();
     loop {
-        let () = ice.send(PrintReservoir).await.unwrap();
-        let network_size = ice.send(ReservoirSize).await.unwrap();
+        let () = ice.send(PrintReservoir).await?;
+        let network_size = ice.send(ReservoirSize).await?;
 
         // Sample a random peer from the view
         let view::SampleResult { sample } =
-            view.send(view::SampleK { k: ping_size(network_size) }).await.unwrap();
+            view.send(view::SampleK { k: ping_size(network_size) }).await?;
 
         for (id, ip) in sample.iter().cloned() {
             // Sample up to `k` peers from the reservoir and collect ping queries
             let Queries { queries } =
-                ice.send(SampleQueries { sample: (id.clone(), ip.clone()) }).await.unwrap();
+                ice.send(SampleQueries { sample: (id.clone(), ip.clone()) }).await?;
 
             // Ping the designated peer
 
             match ice
                 .send(DoPing { self_id, id: id.clone(), ip: ip.clone(), queries, network_size })
-                .await
-                .unwrap()
+                .await?
             {
                 Ok(ack) => {
-                    send_ping_success(self_id.clone(), ice.clone(), alpha.clone(), ack.clone())
-                        .await
+                    send_ping_success(
+                        self_id.clone(),
+                        ice.clone(),
+                        &live_network,
+                        &faulty_network,
+                        ack.clone(),
+                    )
+                    .await?
                 }
-                Err(_) => {
-                    send_ping_failure(ice.clone(), alpha.clone(), id.clone(), ip.clone()).await
+                Err(e) => {
+                    let reason = match e {
+                        Error::Byzantine => view::Misbehavior::InvalidResponse,
+                        _ => view::Misbehavior::Unresponsive,
+                    };
+                    view.send(view::ReportPeerMisbehavior { id: id.clone(), reason }).await?;
+                    send_ping_failure(ice.clone(), &faulty_network, id.clone(), ip.clone()).await?
                 }
             }
         }
@@ -517,3 +677,164 @@ pub async fn run(self_id: Id, ice: Addr<Ice>, view: Addr<View>, alpha: Addr<Alph
 fn ping_size(network_size: usize) -> usize {
     std::cmp::min(network_size, PING_MAX_SIZE)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::client::Client;
+    use crate::tls::upgrader::tcp_upgraders;
+
+    use super::dissemination::DisseminationComponent;
+
+    fn new_ice(reservoir: Reservoir) -> Ice {
+        let client = Client::new(tcp_upgraders().client).start();
+        let dc = DisseminationComponent::new().start();
+        let ip: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        Ice::new(client.recipient(), Id::from_ip(&ip), ip, reservoir, dc.recipient())
+    }
+
+    fn start_ice(reservoir: Reservoir) -> Addr<Ice> {
+        new_ice(reservoir).start()
+    }
+
+    fn test_peers(n: u16) -> Vec<(Id, SocketAddr)> {
+        (1235..1235 + n)
+            .map(|port| {
+                let ip: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+                (Id::from_ip(&ip), ip)
+            })
+            .collect()
+    }
+
+    #[actix_rt::test]
+    async fn test_get_live_peers() {
+        let peers = test_peers(4);
+        let mut reservoir = Reservoir::new();
+        reservoir.insert(peers[0].0, peers[0].1, Choice::Live, BETA1);
+        reservoir.insert(peers[1].0, peers[1].1, Choice::Live, BETA1);
+        // Not yet convinced of the liveness of the peer
+        reservoir.insert(peers[2].0, peers[2].1, Choice::Live, 0);
+        reservoir.insert(peers[3].0, peers[3].1, Choice::Faulty, BETA1);
+        let ice = start_ice(reservoir);
+
+        let mut live_peers = ice.send(GetLivePeers).await.unwrap();
+        live_peers.peers.sort();
+        let mut expected = vec![
+            (peers[0].0, peers[0].1, true),
+            (peers[1].0, peers[1].1, true),
+            (peers[2].0, peers[2].1, false),
+            (peers[3].0, peers[3].1, false),
+        ];
+        expected.sort();
+        assert_eq!(live_peers.peers, expected);
+
+        // A failed ping resets the choice of a live peer
+        let _ = ice.send(PingFailure { id: peers[0].0, ip: peers[0].1 }).await.unwrap();
+        let live_peers = ice.send(GetLivePeers).await.unwrap();
+        assert!(live_peers.peers.contains(&(peers[0].0, peers[0].1, false)));
+        assert_eq!(live_peers.live_peers(), vec![peers[1]]);
+    }
+
+    #[actix_rt::test]
+    async fn test_shutdown() {
+        let ice = start_ice(Reservoir::new());
+        assert_eq!(ice.send(ReservoirSize).await.unwrap(), 0);
+
+        ice.send(Shutdown).await.unwrap();
+        assert!(matches!(ice.send(ReservoirSize).await, Err(MailboxError::Closed)));
+        assert!(!ice.connected());
+    }
+
+    #[actix_rt::test]
+    async fn test_converge_with_tiny_reservoir() {
+        let config = IceConfig { reservoir_capacity: 3, k: 2, alpha: 0.5, beta1: 1 };
+        let ice = new_ice(Reservoir::new()).with_config(config).start();
+        let peers = test_peers(5);
+        let _ = ice.send(Bootstrap { peers: peers.clone() }).await.unwrap();
+        // Peers above the capacity are ignored
+        assert_eq!(ice.send(ReservoirSize).await.unwrap(), 3);
+
+        // A single round of `k` responders agreeing on the liveness of all peers suffices
+        let LivePeers { peers: reservoir_peers } = ice.send(GetLivePeers).await.unwrap();
+        let outcomes: Vec<Outcome> =
+            reservoir_peers.iter().map(|(id, _, _)| Outcome::new(*id, Choice::Live)).collect();
+        let mut switch = None;
+        for (responder, _, _) in reservoir_peers.iter().take(config.k) {
+            let ack = Ack { id: *responder, outcomes: outcomes.clone() };
+            switch = Some(ice.send(PingSuccess { ack }).await.unwrap());
+        }
+        let switch = switch.unwrap();
+        assert!(switch.flipped && switch.bootstrapped);
+        let live_peers = ice.send(GetLivePeers).await.unwrap();
+        assert_eq!(live_peers.live_peers().len(), 3);
+    }
+
+    /// Collects the messages which `ice` sends to `alpha`
+    struct AlphaMock {
+        live_networks: std::sync::Arc<std::sync::Mutex<Vec<alpha::LiveNetwork>>>,
+    }
+
+    impl Actor for AlphaMock {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<alpha::LiveNetwork> for AlphaMock {
+        type Result = ();
+
+        fn handle(&mut self, msg: alpha::LiveNetwork, _ctx: &mut Context<Self>) -> Self::Result {
+            self.live_networks.lock().unwrap().push(msg);
+        }
+    }
+
+    impl Handler<alpha::FaultyNetwork> for AlphaMock {
+        type Result = ();
+
+        fn handle(&mut self, _msg: alpha::FaultyNetwork, _ctx: &mut Context<Self>) {}
+    }
+
+    #[actix_rt::test]
+    async fn test_recover_from_isolation() {
+        let config = IceConfig { reservoir_capacity: 3, k: 2, alpha: 0.5, beta1: 1 };
+        let ice = new_ice(Reservoir::new()).with_config(config).start();
+        let self_id = Id::zero();
+        let live_networks = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let mock = AlphaMock { live_networks: live_networks.clone() }.start();
+        let (live_network, faulty_network) = (mock.clone().recipient(), mock.recipient());
+
+        let peers = test_peers(2);
+        let _ = ice.send(Bootstrap { peers: peers.clone() }).await.unwrap();
+        let outcomes: Vec<Outcome> =
+            peers.iter().map(|(id, _)| Outcome::new(*id, Choice::Live)).collect();
+        let query_round = || async {
+            for (responder, _) in peers.iter() {
+                let ack = Ack { id: *responder, outcomes: outcomes.clone() };
+                send_ping_success(self_id, ice.clone(), &live_network, &faulty_network, ack)
+                    .await
+                    .unwrap();
+            }
+        };
+        query_round().await;
+        assert_eq!(live_networks.lock().unwrap().len(), 1);
+        assert!(!live_networks.lock().unwrap()[0].rejoined);
+
+        // All peers become unreachable
+        for (id, ip) in peers.iter().cloned() {
+            send_ping_failure(ice.clone(), &faulty_network, id, ip).await.unwrap();
+        }
+        assert!(ice.send(GetLivePeers).await.unwrap().live_peers().is_empty());
+        live_networks.lock().unwrap().clear();
+
+        // The partition heals: the next query rounds re-establish the liveness of the peers
+        query_round().await;
+        query_round().await;
+        let emitted = live_networks.lock().unwrap().clone();
+        assert!(!emitted.is_empty());
+        assert!(emitted[0].rejoined);
+        let mut live_peers = emitted.last().unwrap().live_peers.clone();
+        live_peers.sort();
+        let mut expected = peers.clone();
+        expected.sort();
+        assert_eq!(live_peers, expected);
+    }
+}
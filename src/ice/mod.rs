@@ -23,4 +23,5 @@ pub use choice::Choice;
 pub use constants::*;
 pub use ice::*;
 pub use query::Query;
+pub use quorum::{Quorum, QuorumThreshold};
 pub use reservoir::Reservoir;
@@ -1,4 +1,6 @@
+use crate::alpha::types::Weight;
 use crate::zfx_id::Id;
+use crate::{Error, Result};
 
 use super::choice::Choice;
 use super::constants::*;
@@ -6,9 +8,50 @@ use super::constants::*;
 use std::collections::HashMap;
 
 /// A quorum is a list of choices which can be decided when `i == k`
+///
+/// In the weightless bootstrap every member counts the same. Once the validators are known, a
+/// stake-weighted quorum built with [Quorum::weighted] also records the stake of its members,
+/// so that it is only reached with enough stake to intersect any other quorum in an honest
+/// validator.
 #[derive(Debug, Clone)]
 pub struct Quorum {
     pub choices: HashMap<Id, Choice>,
+    /// The stake of the members, empty for a weightless quorum
+    stakes: HashMap<Id, Weight>,
+    /// The stake thresholds of a stake-weighted quorum
+    threshold: Option<QuorumThreshold>,
+}
+
+/// The stake thresholds which make any two stake-weighted quorums intersect in an honest
+/// validator.
+///
+/// Two quorums of more than `min_weight` out of `total_stake` share more than
+/// `2 * min_weight - total_stake` of stake, which is above the `byzantine_stake` for
+/// `min_weight = (total_stake + byzantine_stake) / 2`. The honest validators alone can only
+/// reach such a quorum if less than a third of the stake is Byzantine.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuorumThreshold {
+    pub total_stake: Weight,
+    /// The maximum stake held by Byzantine validators
+    pub byzantine_stake: Weight,
+    /// The stake a quorum has to exceed
+    pub min_weight: Weight,
+}
+
+impl QuorumThreshold {
+    /// Computes the thresholds for at most `fault_tolerance` of the `total_stake` held by
+    /// Byzantine validators.
+    ///
+    /// Throws [Error::UnsafeFaultTolerance] unless `0 <= fault_tolerance < 1/3`, as two quorums
+    /// can't otherwise be guaranteed to intersect in an honest validator.
+    pub fn new(total_stake: Weight, fault_tolerance: f64) -> Result<Self> {
+        if !(total_stake > 0.0) || !(fault_tolerance >= 0.0) || fault_tolerance * 3.0 >= 1.0 {
+            return Err(Error::UnsafeFaultTolerance(fault_tolerance));
+        }
+        let byzantine_stake = total_stake * fault_tolerance;
+        let min_weight = (total_stake + byzantine_stake) / 2.0;
+        Ok(QuorumThreshold { total_stake, byzantine_stake, min_weight })
+    }
 }
 
 impl std::fmt::Display for Quorum {
@@ -19,7 +62,14 @@ impl std::fmt::Display for Quorum {
 
 impl Quorum {
     pub fn new() -> Quorum {
-        Quorum { choices: HashMap::new() }
+        Quorum { choices: HashMap::new(), stakes: HashMap::new(), threshold: None }
+    }
+
+    /// A stake-weighted quorum out of `total_stake`, with at most `fault_tolerance` of the stake
+    /// held by Byzantine validators, see [QuorumThreshold::new].
+    pub fn weighted(total_stake: Weight, fault_tolerance: f64) -> Result<Quorum> {
+        let threshold = QuorumThreshold::new(total_stake, fault_tolerance)?;
+        Ok(Quorum { choices: HashMap::new(), stakes: HashMap::new(), threshold: Some(threshold) })
     }
 
     pub fn len(&self) -> usize {
@@ -34,11 +84,56 @@ impl Quorum {
         self.choices.insert(observer_id, choice);
     }
 
+    /// Inserts the choice of a validator holding `stake`.
+    pub fn insert_weighted(&mut self, observer_id: Id, choice: Choice, stake: Weight) {
+        self.choices.insert(observer_id, choice);
+        self.stakes.insert(observer_id, stake);
+    }
+
+    /// The stake thresholds of a stake-weighted quorum
+    pub fn threshold(&self) -> Option<QuorumThreshold> {
+        self.threshold
+    }
+
+    /// The total stake of the members
+    pub fn weight(&self) -> Weight {
+        self.stakes.values().sum()
+    }
+
+    /// Whether a stake-weighted quorum exceeds the minimum weight. A weightless quorum is never
+    /// reached by stake, see [Quorum::decide].
+    pub fn is_reached(&self) -> bool {
+        match self.threshold {
+            Some(threshold) => self.weight() > threshold.min_weight,
+            None => false,
+        }
+    }
+
+    /// Whether the two quorums share an honest validator.
+    ///
+    /// Weightless quorums have to share a member. Stake-weighted quorums have to share more
+    /// stake than the Byzantine validators may hold, which always holds for two quorums which
+    /// are [reached](Quorum::is_reached).
+    pub fn intersects(&self, other: &Quorum) -> bool {
+        let byzantine_stake = match (self.threshold, other.threshold) {
+            (None, None) => return self.choices.keys().any(|id| other.contains(id)),
+            (Some(t), None) | (None, Some(t)) => t.byzantine_stake,
+            (Some(t1), Some(t2)) => t1.byzantine_stake.max(t2.byzantine_stake),
+        };
+        let shared_stake: Weight = self
+            .stakes
+            .iter()
+            .filter(|(id, _)| other.stakes.contains_key(id))
+            .map(|(_, stake)| stake)
+            .sum();
+        shared_stake > byzantine_stake
+    }
+
     /// Make a decision whether the quorum
-    /// has more than (K * ALPHA) Live or Faulty choices.
+    /// has more than (k * alpha) Live or Faulty choices.
     ///
-    /// Return None if decision threshold didn't pass (K * ALPHA)
-    pub fn decide(&self) -> Option<Choice> {
+    /// Return None if decision threshold didn't pass (k * alpha)
+    pub fn decide(&self, config: &IceConfig) -> Option<Choice> {
         let mut n_live = 0;
         let mut n_faulty = 0;
         for (_, choice) in self.choices.iter() {
@@ -51,12 +146,104 @@ impl Quorum {
                 }
             }
         }
-        if n_live > (K as f64 * ALPHA).ceil() as usize {
+        let threshold = (config.k as f64 * config.alpha).ceil() as usize;
+        if n_live > threshold {
             return Some(Choice::Live);
         }
-        if n_faulty > (K as f64 * ALPHA).ceil() as usize {
+        if n_faulty > threshold {
             return Some(Choice::Faulty);
         }
         None
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn validators(stakes: &[Weight]) -> Vec<(Id, Weight)> {
+        stakes.iter().map(|stake| (Id::generate(), *stake)).collect()
+    }
+
+    fn quorum_of(total_stake: Weight, fault_tolerance: f64, members: &[(Id, Weight)]) -> Quorum {
+        let mut quorum = Quorum::weighted(total_stake, fault_tolerance).unwrap();
+        for (id, stake) in members.iter() {
+            quorum.insert_weighted(*id, Choice::Live, *stake);
+        }
+        quorum
+    }
+
+    #[actix_rt::test]
+    async fn test_threshold() {
+        let threshold = QuorumThreshold::new(100.0, 0.25).unwrap();
+        assert_eq!(threshold.byzantine_stake, 25.0);
+        assert_eq!(threshold.min_weight, 62.5);
+        // The honest validators can still reach a quorum on their own
+        assert!(threshold.total_stake - threshold.byzantine_stake > threshold.min_weight);
+    }
+
+    #[actix_rt::test]
+    async fn test_reached_quorums_intersect() {
+        let stakes = [30.0, 25.0, 20.0, 15.0, 10.0];
+        let total_stake: Weight = stakes.iter().sum();
+        let validators = validators(&stakes);
+
+        // Every pair of reached quorums shares more than the Byzantine stake
+        let mut reached = vec![];
+        for subset in 0..(1 << validators.len()) {
+            let members: Vec<(Id, Weight)> = (0..validators.len())
+                .filter(|i| subset & (1 << i) != 0)
+                .map(|i| validators[i])
+                .collect();
+            let quorum = quorum_of(total_stake, 0.2, &members);
+            if quorum.is_reached() {
+                reached.push(quorum);
+            }
+        }
+        assert!(reached.len() > 1);
+        for q1 in reached.iter() {
+            for q2 in reached.iter() {
+                assert!(q1.intersects(q2));
+            }
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_quorums_without_enough_shared_stake() {
+        let validators = validators(&[10.0; 10]);
+        let q1 = quorum_of(100.0, 0.25, &validators[..5]);
+        let q2 = quorum_of(100.0, 0.25, &validators[3..]);
+        assert!(!q1.is_reached());
+        assert!(q2.is_reached());
+        // Sharing 20 out of the 25 potentially Byzantine stake
+        assert!(!q1.intersects(&q2));
+
+        let q1 = quorum_of(100.0, 0.25, &validators[..7]);
+        assert!(q1.is_reached());
+        assert!(q1.intersects(&q2));
+    }
+
+    #[actix_rt::test]
+    async fn test_weightless_quorums_intersect() {
+        let (id1, id2, id3) = (Id::generate(), Id::generate(), Id::generate());
+        let mut q1 = Quorum::new();
+        q1.insert(id1, Choice::Live);
+        q1.insert(id2, Choice::Live);
+        let mut q2 = Quorum::new();
+        q2.insert(id3, Choice::Faulty);
+        assert!(!q1.intersects(&q2));
+        q2.insert(id2, Choice::Faulty);
+        assert!(q1.intersects(&q2));
+    }
+
+    #[actix_rt::test]
+    async fn test_unsafe_fault_tolerance() {
+        // With a third of the stake Byzantine, two quorums reachable by the honest validators
+        // may only share Byzantine validators
+        assert!(matches!(Quorum::weighted(100.0, 1.0 / 3.0), Err(Error::UnsafeFaultTolerance(_))));
+        assert!(matches!(Quorum::weighted(100.0, 0.5), Err(Error::UnsafeFaultTolerance(_))));
+        assert!(matches!(Quorum::weighted(100.0, -0.1), Err(Error::UnsafeFaultTolerance(_))));
+        assert!(matches!(Quorum::weighted(0.0, 0.1), Err(Error::UnsafeFaultTolerance(_))));
+        assert!(Quorum::weighted(100.0, 0.33).is_ok());
+    }
+}
@@ -21,18 +21,40 @@ pub struct Reservoir {
     decisions: HashMap<Id, (SocketAddr, Choice, usize)>,
     random_queue: Vec<(Id, (SocketAddr, Choice, usize))>,
     nbootstrapped: usize,
+    config: IceConfig,
 }
 
 impl Reservoir {
     pub fn new() -> Reservoir {
+        Reservoir::with_config(IceConfig::default())
+    }
+
+    /// Create a reservoir with the consensus settings of `config`.
+    pub fn with_config(config: IceConfig) -> Reservoir {
         Reservoir {
             quorums: HashMap::new(),
             decisions: HashMap::new(),
             random_queue: vec![],
             nbootstrapped: 0,
+            config,
         }
     }
 
+    /// The consensus settings of the reservoir.
+    pub fn config(&self) -> &IceConfig {
+        &self.config
+    }
+
+    /// Replaces the consensus settings of the reservoir.
+    pub fn set_config(&mut self, config: IceConfig) {
+        self.config = config;
+    }
+
+    /// Whether the reservoir can't hold any more peers.
+    pub fn is_full(&self) -> bool {
+        self.decisions.len() >= self.config.reservoir_capacity
+    }
+
     /// Fetches the number of recorded decisions.
     pub fn len(&self) -> usize {
         self.decisions.len()
@@ -64,7 +86,7 @@ impl Reservoir {
     /// Fetches all live peers.
     pub fn get_live_peers(&self) -> Vec<(Id, SocketAddr)> {
         self.decisions.iter().fold(vec![], |mut live_peers, (id, (ip, choice, conviction))| {
-            if *choice == Choice::Live && *conviction >= BETA1 {
+            if *choice == Choice::Live && *conviction >= self.config.beta1 {
                 live_peers.push((id.clone(), ip.clone()));
                 live_peers
             } else {
@@ -73,17 +95,40 @@ impl Reservoir {
         })
     }
 
+    /// Fetches all peers, along with whether each peer is live, i.e. has a `Live` choice with
+    /// sufficient conviction.
+    pub fn get_peer_liveness(&self) -> Vec<(Id, SocketAddr, bool)> {
+        self.decisions
+            .iter()
+            .map(|(id, (ip, choice, conviction))| {
+                (
+                    id.clone(),
+                    ip.clone(),
+                    *choice == Choice::Live && *conviction >= self.config.beta1,
+                )
+            })
+            .collect()
+    }
+
     /// Inserts an entry into the reservoir decisions, updating the previous entry.
+    /// New entries are ignored once the reservoir is full.
     pub fn insert(&mut self, peer_id: Id, ip: SocketAddr, choice: Choice, conviction: usize) {
+        if self.is_full() && !self.decisions.contains_key(&peer_id) {
+            return;
+        }
         let v = (ip.clone(), choice.clone(), conviction);
         let _ = self.decisions.insert(peer_id.clone(), v);
     }
 
-    /// Inserts an entry into the reservoir if none is already present.
+    /// Inserts an entry into the reservoir if none is already present and the reservoir
+    /// isn't full.
     pub fn insert_new(&mut self, peer_id: Id, ip: SocketAddr, choice: Choice, conviction: usize) {
         let v = (ip.clone(), choice.clone(), conviction.clone());
+        let full = self.is_full();
         if let Entry::Vacant(slot) = self.decisions.entry(peer_id.clone()) {
-            slot.insert(v);
+            if !full {
+                slot.insert(v);
+            }
         }
     }
 
@@ -107,15 +152,15 @@ impl Reservoir {
                 *conviction = 0;
             }
         }
-        self.nbootstrapped >= K
+        self.nbootstrapped >= self.config.k
     }
 
     /// Regenerates the random queue based on the current decisions.
     pub fn permute(&mut self) -> bool {
         let mut rng = rand::thread_rng();
         let queue = self.decisions.iter().fold(vec![], |mut v, (id, (ip, choice, conviction))| {
-            // If the conviction >= BETA1 then omit the entry from the queue
-            if *conviction >= BETA1 {
+            // If the conviction >= beta1 then omit the entry from the queue
+            if *conviction >= self.config.beta1 {
                 v
             } else {
                 let entry = (id.clone(), (ip.clone(), choice.clone(), conviction.clone()));
@@ -141,9 +186,9 @@ impl Reservoir {
             // The current arity of the sample.
             let mut i = 0;
             // The current sample.
-            // Accumulate elements into `s` until the sample is size `K`.
+            // Accumulate elements into `s` until the sample is size `k`.
             loop {
-                if i >= K {
+                if i >= self.config.k {
                     break;
                 } else {
                     if self.random_queue.len() > 0 {
@@ -210,7 +255,8 @@ impl Reservoir {
     /// If a decision was made under quorum, then the entry is modified to reflect the
     /// new decision.
     fn process_decision(&mut self, id: Id, quorum: Quorum) -> bool {
-        let new_decision = quorum.decide();
+        let new_decision = quorum.decide(&self.config);
+        let beta1 = self.config.beta1;
         if let Some(decision) = new_decision {
             if let Entry::Occupied(mut o) = self.decisions.entry(id.clone()) {
                 let (_, d, c) = o.get_mut();
@@ -219,10 +265,10 @@ impl Reservoir {
                     *c = 0;
                 } else {
                     *c += 1;
-                    if self.nbootstrapped > 0 && *d == Choice::Faulty && *c >= BETA1 {
+                    if self.nbootstrapped > 0 && *d == Choice::Faulty && *c >= beta1 {
                         info!("[peer] {} confirmed: {}", id.clone(), "Faulty".red());
                         self.nbootstrapped -= 1;
-                    } else if *d == Choice::Live && *c >= BETA1 {
+                    } else if *d == Choice::Live && *c >= beta1 {
                         info!("[peer] {} confirmed: {}", id.clone(), "Live".green());
                         self.nbootstrapped += 1;
                     }
@@ -231,7 +277,7 @@ impl Reservoir {
             // Clear the quorum since it was previously decided.
             let _ = self.quorums.remove(&id);
             // Return the `ice` bootstrap status.
-            self.nbootstrapped >= K
+            self.nbootstrapped >= self.config.k
         } else {
             // Clear the quorum since it was previously decided.
             let _ = self.quorums.remove(&id);
@@ -259,7 +305,7 @@ impl Reservoir {
 
         // If the quorum length == `k` then the quorum is complete and a decision
         // has been made.
-        if q.len() >= K && self.process_decision(peer_id.clone(), q.clone()) {
+        if q.len() >= self.config.k && self.process_decision(peer_id.clone(), q.clone()) {
             info!("[{}] bootstrapped {}", "ice".magenta(), "✓".magenta());
             return self.get_endpoint_having_strong_choice(&peer_id, choice).is_some();
         }
@@ -282,14 +328,14 @@ impl Reservoir {
             peers_status_changed |= self.process_outcome(responder_id.clone(), outcome.clone());
         }
 
-        (self.nbootstrapped >= K, peers_status_changed)
+        (self.nbootstrapped >= self.config.k, peers_status_changed)
     }
 
-    /// Return an endpoint matching id and choice with conviction >= BETA1
+    /// Return an endpoint matching id and choice with conviction >= beta1
     fn get_endpoint_having_strong_choice(&self, id: &Id, choice: Choice) -> Option<SocketAddr> {
         match self.decisions.get(id) {
             Some((ip, c, conviction)) => {
-                if *c == choice && *conviction >= BETA1 {
+                if *c == choice && *conviction >= self.config.beta1 {
                     Some(ip.clone())
                 } else {
                     None
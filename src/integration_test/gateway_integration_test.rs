@@ -0,0 +1,76 @@
+use std::net::SocketAddr;
+
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::info;
+
+use crate::integration_test::test_functions::*;
+use crate::integration_test::test_model::TestNodes;
+use crate::Result;
+
+/// Queries each endpoint of the HTTP gateway of a running node and checks the JSON responses
+pub async fn run_gateway_integration_test() -> Result<()> {
+    info!("Run gateway integration test: query the endpoints of the HTTP gateway");
+    let mut nodes = TestNodes::new();
+    nodes.start_minimal_and_wait().await?;
+    let node = nodes.get_node(0).unwrap();
+    let gateway = node.gateway_address;
+
+    let (code, status) = http_get_json(gateway, "/status").await?;
+    assert_eq!(code, 200);
+    assert!(status["bootstrapped"].is_boolean());
+    assert!(status["height"].is_u64());
+    assert!(status["peers"].is_array());
+    assert!(status["validators"].is_array());
+
+    let owner = hex::encode(node.public_key);
+    let (code, utxos) = http_get_json(gateway, &format!("/utxos/{}", owner)).await?;
+    assert_eq!(code, 200);
+    let utxos = utxos.as_array().unwrap();
+    assert_eq!(utxos.len(), get_utxos_by_owner(node.public_key, node.address).await?.len());
+    for utxo in utxos.iter() {
+        assert_eq!(utxo["cell_hash"].as_str().unwrap().len(), 64);
+        assert!(utxo["index"].is_u64());
+        assert!(utxo["capacity"].is_u64());
+    }
+
+    let cell_hash = get_cell_hashes(node.address).await?[0];
+    let (code, cell) = http_get_json(gateway, &format!("/cell/{}", hex::encode(cell_hash))).await?;
+    assert_eq!(code, 200);
+    assert!(cell["inputs"].is_object() || cell["inputs"].is_array());
+    assert!(cell["outputs"].is_object() || cell["outputs"].is_array());
+    let (code, _) = http_get(gateway, &format!("/cell/{}", hex::encode([0u8; 32]))).await?;
+    assert_eq!(code, 404);
+    let (code, _) = http_get(gateway, "/cell/not-a-hash").await?;
+    assert_eq!(code, 400);
+
+    let (code, block) = http_get_json(gateway, "/block/0").await?;
+    assert_eq!(code, 200);
+    assert_eq!(block["height"].as_u64(), Some(0));
+    assert!(block["cells"].is_array());
+    let (code, _) = http_get(gateway, &format!("/block/{}", u64::MAX)).await?;
+    assert_eq!(code, 404);
+
+    nodes.kill_all();
+    Result::Ok(())
+}
+
+/// Sends a `GET` request for `path` and returns the status code and the body of the response
+async fn http_get(address: SocketAddr, path: &str) -> Result<(u16, String)> {
+    let mut stream = TcpStream::connect(address).await?;
+    let request =
+        format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, address);
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = String::new();
+    let _ = stream.read_to_string(&mut response).await?;
+    // The status line is `HTTP/1.1 <code> <reason>`
+    let code = response.split(' ').nth(1).and_then(|code| code.parse().ok()).unwrap_or(0);
+    let body = response.splitn(2, "\r\n\r\n").nth(1).unwrap_or("").to_owned();
+    Ok((code, body))
+}
+
+async fn http_get_json(address: SocketAddr, path: &str) -> Result<(u16, Value)> {
+    let (code, body) = http_get(address, path).await?;
+    Ok((code, serde_json::from_str(&body).unwrap_or(Value::Null)))
+}
@@ -32,4 +32,10 @@ mod integration_test {
 
         Result::Ok(())
     }
+
+    #[cfg(feature = "http_gateway")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    async fn run_gateway_integration_test() -> Result<()> {
+        crate::integration_test::gateway_integration_test::run_gateway_integration_test().await
+    }
 }
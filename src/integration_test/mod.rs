@@ -3,10 +3,12 @@
 #![allow(unused)]
 
 mod cell_transfer_benchmark;
+#[cfg(feature = "http_gateway")]
+mod gateway_integration_test;
 mod hail_integration_test;
 mod integration_test_runner;
 mod sleet_integration_test;
 mod stress_test;
 mod test_functions;
-mod test_model;
+pub(crate) mod test_model;
 mod test_node_chaos_manager;
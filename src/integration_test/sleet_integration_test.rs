@@ -198,6 +198,8 @@ async fn test_send_cell_with_modified_owner(
                     cell_type: o.cell_type.clone(),
                     data: o.data.clone(),
                     lock: from.public_key,
+                    locktime: o.locktime,
+                    scheme: o.scheme,
                 }
             }
         })
@@ -383,8 +385,8 @@ fn assert_cell(
             "Invalid source (parent) of cell from which we consume amount"
         );
         assert_eq!(
-            from.keypair.public.as_bytes(),
-            input.unlock.public_key.as_bytes(),
+            Id::from_public_key(&from.keypair.public).bytes(),
+            input.unlock.public_key_hash(),
             "Invalid cell owner in the input"
         );
     }
@@ -15,12 +15,15 @@ use crate::alpha::types::BlockHeight;
 use crate::cell::inputs::Inputs;
 use crate::cell::outputs::{Output, Outputs};
 use crate::cell::types::{Capacity, CellHash, PublicKeyHash, FEE};
-use crate::cell::{Cell, CellType};
-use crate::hail::GetBlockByHeight;
+use crate::cell::{Cell, CellType, SignatureScheme};
+use crate::hail::{ChainTip, GetBlockByHeight, GetBlocksByRange};
 use crate::ice::Status;
 use crate::integration_test::test_model::{IntegrationTestContext, TestNode, TestNodes};
 use crate::protocol::Response;
-use crate::sleet::sleet_cell_handlers::GetAcceptedCell;
+use crate::server::Readiness;
+use crate::sleet::sleet_cell_handlers::{
+    GetAcceptedCell, GetAcceptedCellHashes, GetUtxosByOwner, MAX_ACCEPTED_CELL_HASHES_PAGE,
+};
 use crate::zfx_id::Id;
 use crate::Result;
 use crate::{client, sleet, Request};
@@ -254,6 +257,8 @@ pub async fn spend_many_from_invalid_cells(
                                 cell_type: CellType::Transfer,
                                 data: vec![],
                                 lock: from.public_key.clone(),
+                                locktime: None,
+                                scheme: SignatureScheme::Ed25519,
                             }],
                         },
                     );
@@ -372,17 +377,45 @@ pub async fn get_cell_hashes(node_address: SocketAddr) -> Result<Vec<CellHash>>
     }
 }
 
-pub async fn get_accepted_cell_hashes(node_address: SocketAddr) -> Result<Vec<CellHash>> {
-    debug!("Requesting accepted cell hashes from = {}", node_address);
-    if let Some(Response::AcceptedCellHashes(cell_hashes)) =
-        request_with_timeout(node_address, Request::GetAcceptedCellHashes).await
+/// Get the unspent outputs of accepted cells owned by `owner` from the node
+pub async fn get_utxos_by_owner(
+    owner: PublicKeyHash,
+    node_address: SocketAddr,
+) -> Result<Vec<(CellHash, u8, Capacity)>> {
+    debug!("Requesting unspent outputs of {}, from = {}", hex::encode(owner), node_address);
+    if let Some(Response::UtxosAck(utxos_ack)) =
+        request_with_timeout(node_address, Request::GetUtxosByOwner(GetUtxosByOwner { owner }))
+            .await
     {
-        Result::Ok(cell_hashes.ids)
+        Result::Ok(utxos_ack.utxos)
     } else {
         Result::Ok(vec![])
     }
 }
 
+pub async fn get_accepted_cell_hashes(node_address: SocketAddr) -> Result<Vec<CellHash>> {
+    debug!("Requesting accepted cell hashes from = {}", node_address);
+    let mut ids = vec![];
+    let mut after = None;
+    loop {
+        let request = Request::GetAcceptedCellHashesPage(GetAcceptedCellHashes {
+            after,
+            limit: MAX_ACCEPTED_CELL_HASHES_PAGE,
+        });
+        if let Some(Response::AcceptedCellHashes(mut cell_hashes)) =
+            request_with_timeout(node_address, request).await
+        {
+            ids.append(&mut cell_hashes.ids);
+            match cell_hashes.next {
+                Some(next) => after = Some(next),
+                None => return Result::Ok(ids),
+            }
+        } else {
+            return Result::Ok(ids);
+        }
+    }
+}
+
 pub async fn get_accepted_cell_from_hash(
     cell_hash: CellHash,
     node_address: SocketAddr,
@@ -436,6 +469,31 @@ pub async fn get_block(node_address: SocketAddr, height: BlockHeight) -> Result<
     return Result::Ok(None);
 }
 
+/// Get the blocks from height `from` to `to` (inclusive) in one request
+pub async fn get_blocks(
+    node_address: SocketAddr,
+    from: BlockHeight,
+    to: BlockHeight,
+) -> Result<Vec<Block>> {
+    debug!("Request to get blocks with heights {:?}..={:?}, from = {}", from, to, node_address);
+
+    let request = Request::GetBlocksByRange(GetBlocksByRange { from, to });
+    match timeout(Duration::from_secs(1), client::oneshot_tcp(node_address, request)).await {
+        Ok(Ok(Some(Response::BlocksAck(ack)))) => Result::Ok(ack.blocks),
+        _ => Result::Ok(vec![]),
+    }
+}
+
+/// Get the height and the last accepted block hash of the node
+pub async fn get_chain_tip(node_address: SocketAddr) -> Result<Option<ChainTip>> {
+    match timeout(Duration::from_secs(1), client::oneshot_tcp(node_address, Request::GetChainTip))
+        .await
+    {
+        Ok(Ok(Some(Response::ChainTipAck(ack)))) => Result::Ok(ack.tip),
+        _ => Result::Ok(None),
+    }
+}
+
 /// Get all cell hashes of the node with balances
 pub async fn get_cell_hashes_with_max_capacity(node: &TestNode) -> Vec<(CellHash, Capacity)> {
     let mut initial_cells_hashes: Vec<(CellHash, Capacity)> = vec![];
@@ -568,7 +626,7 @@ fn create_transfer_request(
     Request::GenerateTx(sleet::GenerateTx { cell: transfer_op.transfer(&from.keypair).unwrap() })
 }
 
-/// Regularly check status of the nodes until all of them are bootstrapped.
+/// Regularly check readiness of the nodes until all of them are live.
 pub async fn wait_until_nodes_start(nodes: &TestNodes) -> Result<()> {
     let mut live_nodes: HashSet<&PublicKeyHash> = HashSet::new();
     let mut timer = 0;
@@ -579,11 +637,11 @@ pub async fn wait_until_nodes_start(nodes: &TestNodes) -> Result<()> {
     while live_nodes.len() < nodes_size && timer <= timeout {
         sleep(Duration::from_secs(delay));
         timer += delay;
-        // mark a node as 'live' if its bootstrapped status is true
+        // mark a node as 'live' if all of its components are up
         for node in &nodes.get_running_nodes() {
-            match get_node_status(node.address).await? {
-                Some(s) => {
-                    if s.bootstrapped {
+            match get_node_readiness(node.address).await? {
+                Some(r) => {
+                    if r.is_ready() {
                         debug!("Node {} has been bootstrapped", &node.address);
                         live_nodes.insert(&node.public_key)
                     } else {
@@ -612,3 +670,18 @@ pub async fn get_node_status(node_address: SocketAddr) -> Result<Option<NodeStat
         _ => Result::Ok(None),
     }
 }
+
+pub async fn get_node_readiness(node_address: SocketAddr) -> Result<Option<Readiness>> {
+    match timeout(Duration::from_secs(1), client::oneshot_tcp(node_address, Request::GetReadiness))
+        .await
+    {
+        Ok(Ok(r)) => {
+            if let Some(Response::Readiness(readiness)) = r {
+                Result::Ok(Some(readiness))
+            } else {
+                Result::Ok(None)
+            }
+        }
+        _ => Result::Ok(None),
+    }
+}
@@ -1,15 +1,23 @@
-use crate::cell::types::CellHash;
+use crate::alpha::coinbase::CoinbaseOperation;
+use crate::alpha::transfer::transfer_output;
+use crate::cell::inputs::{Input, Inputs};
+use crate::cell::outputs::Outputs;
+use crate::cell::types::{Capacity, CellHash, FEE};
+use crate::cell::Cell;
 use crate::integration_test::test_functions::wait_until_nodes_start;
+use crate::server::node;
+use crate::sleet::tx::Tx;
 use crate::zfx_id::Id;
 use crate::Error;
 use ed25519_dalek::Keypair;
 use std::borrow::Borrow;
 use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
 use std::net::SocketAddr;
 use std::process::{Child, Command};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{panic, thread};
-use tracing::info;
+use tracing::{info, warn};
 use x509_parser::nom::AsBytes;
 
 pub const KEYPAIR_NODE_0 : &str = "ad7f2ee3958a7f3fa2c84931770f5773ef7694fdd0bb217d90f29a94199c9d7307ca3851515c89344639fe6a4077923068d1d7fc6106701213c61d34ef8e9416";
@@ -21,14 +29,17 @@ pub const KEYPAIR_NODE_5 : &str = "8c739c713aeb69e21a37bc2aab2ab314d08627d543575
 pub const KEYPAIR_NODE_6 : &str = "2d2ca57915c481e043744f265397fbec35c8c259c909c3ad365603b243db6de086eb292e6e4f65d14e7b35e3882af5f778924ff1fb95e815473d1eac583df1be";
 pub const KEYPAIR_NODE_7 : &str = "845821ffb4a9c6f4a4dbdc63d3d6e2e3ac1ca3a78950d9f4240092ffff9a24f8ad42f4caf9cfa37b77cddd899c692e0fa5df3dbcd206685e367649ffe6834de4";
 pub const KEYPAIR_NODE_8 : &str = "bffaff3355751ff98beea80841c90f79b6bf256268b83c13d2b68ab2ea168bf02eb8d83c4998f012b65d8b54d1ee3c2db2649495f92ed20881f34720c4a73755";
-pub const NODE_ID_0: &str = "12My22AzQQosboCy6TCDFkTQwHTSuHhFN1VDcdDRPUe3H8j3DvY";
-pub const NODE_ID_1: &str = "19Y53ymnBw4LWUpiAMUzPYmYqZmukRhNHm3VyAhzMqckRcuvkf";
-pub const NODE_ID_2: &str = "1A2iUK1VQWMfvtmrBpXXkVJjM5eMWmTfMEcBx4TatSJeuoSH7n";
+pub const NODE_ID_0: &str = "19oHWYAEdSALT6pFZaxzNPj9bEfJFrJfxrU9pZp622FCV35tzy";
+pub const NODE_ID_1: &str = "1tBz2oa6DCjDbVpC8KJijh7zw8gSvRLAiaUmXktmFAFS5fzXuU";
+pub const NODE_ID_2: &str = "12HkbZqmLUnSQTmwa9EyjCnj9cLdgSMDkYczhD9WbV7pVybzb83";
 pub const NODE_ID_3: &str = "12StzamTJk2jBxbdqGmT6gLfpctv9f39CmBXTsm8sBG2n6AdPxx";
 pub const NODE_ID_4: &str = "1tJB1qNY6R4nPGQN83hmX8bviD6dbEMXkGjfByrCVYZsNnrJSk";
 pub const NODE_ID_5: &str = "12KyV3nz5wJhqFSfEFsKAhEqMGaPD88JeeS7LA4Qsjbyf2Yqp87";
 pub const NON_EXISTING_NODE : &str = "9f4b736b9a6894858a81696d9c96cbdacf3d49099d212213f5abce33da18716f067f8a2b9aeb602cd4163291ebbf39e0e024634f3be19bde4c490465d9095a6b";
 pub const NODE_ADDRESS: &str = "127.0.0.1:123";
+pub const GATEWAY_ADDRESS: &str = "127.0.0.1:808";
+/// The time a node has to shut down gracefully before its process is killed
+pub const NODE_STOP_TIMEOUT: Duration = Duration::from_secs(node::SHUTDOWN_TIMEOUT.as_secs() + 5);
 
 /// A structure to store some information between integration test runs
 pub struct IntegrationTestContext {
@@ -120,6 +131,10 @@ impl TestNodes {
         self.nodes[id].kill();
     }
 
+    pub fn stop_node(&mut self, id: usize) {
+        self.nodes[id].stop();
+    }
+
     pub fn start_node(&mut self, id: usize) {
         if let ProcessNodeState::Stopped = self.nodes[id].state {
             self.nodes[id].start();
@@ -166,6 +181,8 @@ pub struct TestNode {
     pub keypair_as_str: String,
     pub address_as_str: String,
     pub bootstrap_address: String,
+    /// Address of the HTTP gateway (only started with the `http_gateway` feature)
+    pub gateway_address: SocketAddr,
     pub state: ProcessNodeState,
     pub id: String,
 }
@@ -188,6 +205,7 @@ impl TestNode {
         let mut bootstrap_address =
             format!("{}@{}{}", bootstrap_node_id, NODE_ADDRESS, (bootstrap_port + 4).to_string());
         address.push_str((id + 4).to_string().borrow()); // port of node 0 ends in 4, node 1 in 5, etc.
+        let gateway_address = format!("{}{}", GATEWAY_ADDRESS, id);
 
         TestNode {
             id: String::from(node_id_str),
@@ -197,6 +215,7 @@ impl TestNode {
             keypair_as_str: String::from(keypair),
             address_as_str: address,
             bootstrap_address,
+            gateway_address: gateway_address.parse().expect("failed to construct address"),
             state: ProcessNodeState::Stopped,
         }
     }
@@ -228,6 +247,39 @@ impl TestNode {
         }
     }
 
+    /// Shuts the process of running node down gracefully, returning once its actors have
+    /// flushed their storage and the process exited.
+    /// The process is killed if it doesn't exit within [NODE_STOP_TIMEOUT].
+    pub fn stop(&mut self) {
+        match self.state {
+            ProcessNodeState::Running(ref mut child) => {
+                info!("Stopping the node {}", self.address_as_str);
+                // The node shuts its actors down on `SIGTERM`
+                let signalled = Command::new("kill")
+                    .args(&["-TERM", &child.id().to_string()])
+                    .status()
+                    .map(|status| status.success())
+                    .unwrap_or(false);
+                let deadline = Instant::now() + NODE_STOP_TIMEOUT;
+                let mut exited = false;
+                while signalled && !exited && Instant::now() < deadline {
+                    exited = matches!(child.try_wait(), Ok(Some(_)));
+                    if !exited {
+                        thread::sleep(Duration::from_millis(100));
+                    }
+                }
+                if !exited {
+                    warn!("Node {} didn't stop in time, killing it", self.address_as_str);
+                    child.kill().expect("kill failed");
+                    let _ = child.wait();
+                }
+                self.state = ProcessNodeState::Stopped;
+                info!("Node {} has been stopped", self.address_as_str);
+            }
+            ProcessNodeState::Stopped => info!("Node was already stopped"),
+        }
+    }
+
     fn create_keys_of_node(keypair: &str) -> (Keypair, [u8; 32]) {
         let keypair_bytes = hex::decode(keypair).unwrap();
         let keypair = Keypair::from_bytes(&keypair_bytes).unwrap();
@@ -242,6 +294,8 @@ impl TestNode {
             format!("{}/.cargo/bin/cargo", dirs::home_dir().unwrap().to_str().unwrap().to_string());
         let mut command = Command::new(cargo_path);
         command.args(&["run", "-p", "zfx-subzero"]);
+        #[cfg(feature = "http_gateway")]
+        command.args(&["--features", "http_gateway"]);
         command.args(&["--bin", "node", "--", "-a"]);
         command.arg(&self.address_as_str);
         command.arg("-b");
@@ -250,6 +304,133 @@ impl TestNode {
         command.arg(&self.keypair_as_str);
         command.arg("--id");
         command.arg(&self.id);
+        #[cfg(feature = "http_gateway")]
+        command.args(&["--http-gateway", &self.gateway_address.to_string()]);
         command
     }
 }
+
+/// A declarative shape of a DAG of transactions, for building it with [build_dag].
+///
+/// Vertex `i` lists the indices of its parents, which have to precede it, so that the vertices
+/// are in topological order. Vertices without parents spend from the [genesis][DagSpec::genesis].
+pub struct DagSpec {
+    pub parents: Vec<Vec<usize>>,
+}
+
+impl DagSpec {
+    pub fn new(parents: Vec<Vec<usize>>) -> Self {
+        for (v, ps) in parents.iter().enumerate() {
+            let distinct: HashSet<&usize> = ps.iter().collect();
+            assert!(distinct.len() == ps.len(), "vertex {} lists a parent twice", v);
+            assert!(ps.iter().all(|p| *p < v), "the parents of vertex {} don't precede it", v);
+        }
+        DagSpec { parents }
+    }
+
+    /// `a -> b, a -> c, b -> d, c -> d`
+    pub fn diamond() -> Self {
+        DagSpec::new(vec![vec![], vec![0], vec![0], vec![1, 2]])
+    }
+
+    /// A chain of `n` vertices, each one the child of the previous one
+    pub fn chain(n: usize) -> Self {
+        DagSpec::new((0..n).map(|v| if v == 0 { vec![] } else { vec![v - 1] }).collect())
+    }
+
+    /// A root with `n` children
+    pub fn fan(n: usize) -> Self {
+        DagSpec::new((0..=n).map(|v| if v == 0 { vec![] } else { vec![0] }).collect())
+    }
+
+    /// The vertices without parents
+    pub fn roots(&self) -> Vec<usize> {
+        (0..self.parents.len()).filter(|v| self.parents[*v].is_empty()).collect()
+    }
+
+    /// The children of vertex `v`, in the order of the outputs they spend
+    pub fn children(&self, v: usize) -> Vec<usize> {
+        (0..self.parents.len()).filter(|c| self.parents[*c].contains(&v)).collect()
+    }
+
+    /// The coinbase cell spent by the roots, one output each, owned by the [dag_keypair]
+    pub fn genesis(&self) -> Cell {
+        let (_, pkh) = dag_keypair();
+        let shares = self.shares();
+        let recipients = self.roots().iter().map(|v| (pkh, shares[*v])).collect();
+        CoinbaseOperation::new(recipients).try_into().unwrap()
+    }
+
+    /// The capacity which each parent of a vertex transfers to it: enough for the shares of
+    /// its own children, the [FEE] and a change output
+    fn shares(&self) -> Vec<Capacity> {
+        let mut shares = vec![0; self.parents.len()];
+        for v in (0..self.parents.len()).rev() {
+            let needed = FEE + 1 + self.children(v).iter().map(|c| shares[*c]).sum::<Capacity>();
+            let n = std::cmp::max(self.parents[v].len() as Capacity, 1);
+            shares[v] = (needed + n - 1) / n;
+        }
+        shares
+    }
+}
+
+/// The keypair owning the cells built by [build_dag], with its public key hash
+pub fn dag_keypair() -> (Keypair, [u8; 32]) {
+    TestNode::create_keys_of_node(KEYPAIR_NODE_0)
+}
+
+/// Build the cells of the vertices of `spec`, in the same order.
+///
+/// Each vertex spends one output of each of its parents, and has an output for each of its
+/// children besides the change. Feed them to [Sleet][crate::sleet::Sleet] along with
+/// the [genesis][DagSpec::genesis], see [dag_txs].
+pub fn build_dag(spec: &DagSpec) -> Vec<Cell> {
+    let (keypair, pkh) = dag_keypair();
+    let genesis = spec.genesis();
+    let roots = spec.roots();
+    let shares = spec.shares();
+    let mut cells: Vec<Cell> = vec![];
+    for (v, parents) in spec.parents.iter().enumerate() {
+        // The nth vertex among `siblings` with the same share as `v`
+        let nth = |siblings: Vec<usize>| {
+            siblings.iter().take_while(|s| **s != v).filter(|s| shares[**s] == shares[v]).count()
+        };
+        let inputs = if parents.is_empty() {
+            let index = output_index(&genesis, shares[v], nth(roots.clone()));
+            vec![Input::new(&keypair, genesis.hash(), index).unwrap()]
+        } else {
+            parents
+                .iter()
+                .map(|p| {
+                    let index = output_index(&cells[*p], shares[v], nth(spec.children(*p)));
+                    Input::new(&keypair, cells[*p].hash(), index).unwrap()
+                })
+                .collect()
+        };
+        let capacity = shares[v] * inputs.len() as Capacity;
+        let mut outputs: Vec<_> =
+            spec.children(v).iter().map(|c| transfer_output(pkh, shares[*c]).unwrap()).collect();
+        let change = capacity - FEE - outputs.iter().map(|o| o.capacity).sum::<Capacity>();
+        outputs.push(transfer_output(pkh, change).unwrap());
+        cells.push(Cell::new(Inputs::new(inputs), Outputs::new(outputs)));
+    }
+    cells
+}
+
+/// The index of the `nth` output of `cell` with `capacity`, as the outputs of a cell are sorted
+fn output_index(cell: &Cell, capacity: Capacity, nth: usize) -> u8 {
+    let outputs = cell.outputs();
+    let mut matching = outputs.iter().enumerate().filter(|(_, o)| o.capacity == capacity);
+    matching.nth(nth).unwrap().0 as u8
+}
+
+/// The transactions of the `cells` built by [build_dag], with their parents in `spec`
+pub fn dag_txs(spec: &DagSpec, cells: &[Cell]) -> Vec<Tx> {
+    spec.parents
+        .iter()
+        .zip(cells.iter())
+        .map(|(parents, cell)| {
+            Tx::new(parents.iter().map(|p| cells[*p].hash()).collect(), cell.clone())
+        })
+        .collect()
+}
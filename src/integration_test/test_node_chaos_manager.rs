@@ -74,7 +74,7 @@ impl TestNodeChaosManager {
                 if rng.gen_range(0, 2) == 1 {
                     if test_nodes.lock().unwrap().is_running(node_id) {
                         debug!("stop the node {}", node_id);
-                        test_nodes.lock().unwrap().kill_node(node_id);
+                        test_nodes.lock().unwrap().stop_node(node_id);
                     } else {
                         debug!("start the node {}", node_id);
                         test_nodes.lock().unwrap().start_node(node_id);
@@ -0,0 +1,89 @@
+//! JSON representation of the data structures, for explorers, wallets and debugging tools
+//!
+//! Byte fields are annotated with [hex_bytes] or [hex_bytes_opt], which encode them as hex
+//! strings in human-readable formats, such as JSON. Binary formats (`bincode`) are unaffected,
+//! so hashes don't depend on these annotations.
+
+use serde::de::{DeserializeOwned, Error};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use std::convert::TryFrom;
+
+/// Serializes `value` to a JSON string.
+pub fn to_json<T: Serialize>(value: &T) -> serde_json::Result<String> {
+    serde_json::to_string(value)
+}
+
+/// Deserializes a value from the JSON string `json`.
+pub fn from_json<T: DeserializeOwned>(json: &str) -> serde_json::Result<T> {
+    serde_json::from_str(json)
+}
+
+/// Serde helpers for byte arrays and vectors (`#[serde(with = "crate::json::hex_bytes")]`)
+pub mod hex_bytes {
+    use super::*;
+
+    pub fn serialize<T, S>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: AsRef<[u8]> + Serialize,
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&hex::encode(bytes))
+        } else {
+            bytes.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: TryFrom<Vec<u8>> + Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            let encoded = String::deserialize(deserializer)?;
+            let bytes = hex::decode(&encoded).map_err(D::Error::custom)?;
+            T::try_from(bytes).map_err(|_| D::Error::custom("invalid number of bytes"))
+        } else {
+            T::deserialize(deserializer)
+        }
+    }
+}
+
+/// Serde helpers for optional byte arrays and vectors
+/// (`#[serde(with = "crate::json::hex_bytes_opt")]`)
+pub mod hex_bytes_opt {
+    use super::*;
+
+    pub fn serialize<T, S>(bytes: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: AsRef<[u8]> + Serialize,
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            bytes.as_ref().map(hex::encode).serialize(serializer)
+        } else {
+            bytes.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: TryFrom<Vec<u8>> + Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            match Option::<String>::deserialize(deserializer)? {
+                Some(encoded) => {
+                    let bytes = hex::decode(&encoded).map_err(D::Error::custom)?;
+                    let bytes = T::try_from(bytes)
+                        .map_err(|_| D::Error::custom("invalid number of bytes"))?;
+                    Ok(Some(bytes))
+                }
+                None => Ok(None),
+            }
+        } else {
+            Option::<T>::deserialize(deserializer)
+        }
+    }
+}
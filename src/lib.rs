@@ -29,6 +29,8 @@ pub mod graph;
 pub mod hail;
 pub mod ice;
 pub mod integration_test;
+pub mod json;
+pub mod metrics;
 pub mod porter;
 pub mod protocol;
 pub mod server;
@@ -48,17 +50,25 @@ pub enum Error {
     Dalek(ed25519_dalek::ed25519::Error),
     Sled(sled::Error),
     Actix(actix::MailboxError),
+    Alpha(alpha::Error),
 
     // client errors
-    InvalidResponse,
+    Client(client::ClientError),
+    /// The peer didn't respond in time
+    Timeout,
 
     // channel errors
     ChannelError(String),
+    /// The peer sent a frame above the maximum frame size
+    FrameTooLarge,
     JoinError,
 
     // ice errors
     Byzantine,
     Crash,
+    /// Two quorums with this share of Byzantine stake aren't guaranteed to intersect in an
+    /// honest validator
+    UnsafeFaultTolerance(f64),
 
     // chain errors
     GenesisUndefined,
@@ -74,22 +84,43 @@ pub enum Error {
 
     /// Peer IP and ID don't match or wrong certificate was presented
     UnexpectedPeerConnected,
+    /// The peer presented a certificate which is expired or not yet valid
+    PeerCertificateNotValid,
+    /// The peer runs an unsupported version of the protocol
+    IncompatibleVersion(version::ProtocolVersion),
+    /// The node configuration can't be read or is invalid
+    InvalidNodeConfig(String),
 }
 
 impl std::error::Error for Error {}
 
 impl std::convert::From<std::io::Error> for Error {
     fn from(error: std::io::Error) -> Self {
+        if tls::tls::is_certificate_not_valid(&error) {
+            return Error::PeerCertificateNotValid;
+        }
         Error::IO(error)
     }
 }
 
+impl std::convert::From<client::ClientError> for Error {
+    fn from(error: client::ClientError) -> Self {
+        Error::Client(error)
+    }
+}
+
 impl std::convert::From<ed25519_dalek::ed25519::Error> for Error {
     fn from(error: ed25519_dalek::ed25519::Error) -> Self {
         Error::Dalek(error)
     }
 }
 
+impl std::convert::From<alpha::Error> for Error {
+    fn from(error: alpha::Error) -> Self {
+        Error::Alpha(error)
+    }
+}
+
 impl std::convert::From<sled::Error> for Error {
     fn from(error: sled::Error) -> Self {
         Error::Sled(error)
@@ -108,6 +139,7 @@ impl std::convert::From<channel::Error<Request, Response>> for Error {
                 let s = format!("{:?}", err);
                 Error::ChannelError(s)
             }
+            channel::Error::FrameTooLarge => Error::FrameTooLarge,
         }
     }
 }
@@ -124,6 +156,7 @@ impl std::convert::From<channel::Error<Response, Request>> for Error {
                 let s = format!("{:?}", err);
                 Error::ChannelError(s)
             }
+            channel::Error::FrameTooLarge => Error::FrameTooLarge,
         }
     }
 }
@@ -0,0 +1,197 @@
+//! Process-wide metrics in the [Prometheus](https://prometheus.io/) text exposition format
+//!
+//! The actors update [METRICS] where they log the corresponding events, and the
+//! HTTP gateway (feature `http_gateway`) serves the rendered registry at `GET /metrics`.
+use lazy_static::lazy_static;
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds (in seconds) of the buckets of latency histograms
+pub const LATENCY_BUCKETS: [f64; 11] =
+    [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+lazy_static! {
+    /// The metrics of the node
+    pub static ref METRICS: Metrics = Metrics::new();
+}
+
+/// A monotonically increasing counter
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, n: u64) {
+        let _ = self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value which can go up and down
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A histogram of durations over [LATENCY_BUCKETS]
+#[derive(Debug, Default)]
+pub struct Histogram {
+    /// Non-cumulative counts of each bucket, the last bucket being `+Inf`
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let i = LATENCY_BUCKETS.iter().position(|b| seconds <= *b).unwrap_or(LATENCY_BUCKETS.len());
+        let _ = self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        let _ = self.count.fetch_add(1, Ordering::Relaxed);
+        let _ = self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// The number of observed durations
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+/// The registry of metrics updated by the actors
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Transactions accepted by [Sleet][crate::sleet::Sleet]
+    pub sleet_accepted_txs: Counter,
+    /// Transactions rejected by [Sleet][crate::sleet::Sleet] for conflicting with accepted ones
+    pub sleet_rejected_txs: Counter,
+    /// Transactions removed by [Sleet][crate::sleet::Sleet] for descending from rejected ones
+    pub sleet_removed_txs: Counter,
+    /// Round-trip latency of [Sleet][crate::sleet::Sleet] transaction queries
+    pub sleet_query_latency: Histogram,
+    /// Blocks accepted by [Hail][crate::hail::Hail]
+    pub hail_accepted_blocks: Counter,
+    /// Round-trip latency of [Hail][crate::hail::Hail] block queries
+    pub hail_query_latency: Histogram,
+    /// Peers which [Ice][crate::ice::Ice] considers live
+    pub ice_live_peers: Gauge,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut s = String::new();
+        render_counter(
+            &mut s,
+            "sleet_accepted_txs_total",
+            "Transactions accepted by sleet",
+            &self.sleet_accepted_txs,
+        );
+        render_counter(
+            &mut s,
+            "sleet_rejected_txs_total",
+            "Transactions rejected by sleet",
+            &self.sleet_rejected_txs,
+        );
+        render_counter(
+            &mut s,
+            "sleet_removed_txs_total",
+            "Transactions removed by sleet",
+            &self.sleet_removed_txs,
+        );
+        render_histogram(
+            &mut s,
+            "sleet_query_latency_seconds",
+            "Round-trip latency of sleet queries",
+            &self.sleet_query_latency,
+        );
+        render_counter(
+            &mut s,
+            "hail_accepted_blocks_total",
+            "Blocks accepted by hail",
+            &self.hail_accepted_blocks,
+        );
+        render_histogram(
+            &mut s,
+            "hail_query_latency_seconds",
+            "Round-trip latency of hail queries",
+            &self.hail_query_latency,
+        );
+        render_gauge(
+            &mut s,
+            "ice_live_peers",
+            "Peers considered live by ice",
+            &self.ice_live_peers,
+        );
+        s
+    }
+}
+
+fn render_counter(s: &mut String, name: &str, help: &str, counter: &Counter) {
+    let _ = writeln!(s, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+    let _ = writeln!(s, "{} {}", name, counter.get());
+}
+
+fn render_gauge(s: &mut String, name: &str, help: &str, gauge: &Gauge) {
+    let _ = writeln!(s, "# HELP {} {}\n# TYPE {} gauge", name, help, name);
+    let _ = writeln!(s, "{} {}", name, gauge.get());
+}
+
+fn render_histogram(s: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    let _ = writeln!(s, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+    // Bucket counts are cumulative in the exposition format
+    let mut cumulative = 0;
+    for (i, bucket) in histogram.buckets.iter().enumerate() {
+        cumulative += bucket.load(Ordering::Relaxed);
+        let le = match LATENCY_BUCKETS.get(i) {
+            Some(bound) => format!("{}", bound),
+            None => "+Inf".to_owned(),
+        };
+        let _ = writeln!(s, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative);
+    }
+    let sum = histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+    let _ = writeln!(s, "{}_sum {}", name, sum);
+    let _ = writeln!(s, "{}_count {}", name, histogram.count());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn test_render() {
+        let metrics = Metrics::new();
+        metrics.sleet_accepted_txs.inc_by(3);
+        metrics.ice_live_peers.set(2);
+        metrics.hail_query_latency.observe(Duration::from_millis(20));
+        metrics.hail_query_latency.observe(Duration::from_secs(60));
+
+        let rendered = metrics.render();
+        assert!(rendered
+            .contains("# TYPE sleet_accepted_txs_total counter\nsleet_accepted_txs_total 3\n"));
+        assert!(rendered.contains("ice_live_peers 2\n"));
+        assert!(rendered.contains("hail_query_latency_seconds_bucket{le=\"0.01\"} 0\n"));
+        assert!(rendered.contains("hail_query_latency_seconds_bucket{le=\"0.025\"} 1\n"));
+        assert!(rendered.contains("hail_query_latency_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(rendered.contains("hail_query_latency_seconds_count 2\n"));
+    }
+}
@@ -1,21 +1,133 @@
 use igd::SearchOptions;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, SocketAddrV4, SocketAddrV6};
+
+use tracing::info;
 
 use super::{
-    params::{AddMappingEntry, RouterConfig},
-    Error,
+    nat_pmp::PmpGateway,
+    params::{AddMappingEntry, AddPinholeEntry, RouterConfig},
+    Error, MappingMethod,
 };
 
+/// A gateway able to map ports with one of the [MappingMethod]s
+pub trait MappingGateway {
+    /// Maps the port described by `add_params`, returning the external address of the mapping
+    fn add_mapping(&self, add_params: &AddMappingEntry) -> Result<SocketAddrV4, Error>;
+
+    /// Returns the external IP of the gateway
+    fn get_external_ip(&self) -> Result<Ipv4Addr, Error>;
+
+    /// Opens the IPv6 pinhole described by `add_params`, returning the external address of it
+    fn add_pinhole(&self, _add_params: &AddPinholeEntry) -> Result<SocketAddrV6, Error> {
+        Err(Error::PinholeUnsupported)
+    }
+}
+
+/// Searches for the gateway supporting a mapping method
+pub trait GatewaySearch: Send + Sync {
+    fn search(
+        &self,
+        method: MappingMethod,
+        config: RouterConfig,
+    ) -> Result<Box<dyn MappingGateway>, Error>;
+}
+
+/// Searches the local network for UPnP, PCP and NAT-PMP gateways
+pub struct NetworkGatewaySearch;
+
+impl GatewaySearch for NetworkGatewaySearch {
+    fn search(
+        &self,
+        method: MappingMethod,
+        config: RouterConfig,
+    ) -> Result<Box<dyn MappingGateway>, Error> {
+        match method {
+            MappingMethod::Upnp => Ok(Box::new(Gateway::search(config)?)),
+            MappingMethod::Pcp | MappingMethod::NatPmp => {
+                Ok(Box::new(PmpGateway::new(method, config)?))
+            }
+        }
+    }
+}
+
+/// Maps a port with the first allowed method for which a gateway is available.
+///
+/// The methods are attempted in the order of [MappingMethod::ALL], falling back to the next one
+/// only if no gateway was found for the current method.
+pub fn add_mapping(
+    search: &dyn GatewaySearch,
+    config: RouterConfig,
+    add_params: &AddMappingEntry,
+) -> Result<(MappingMethod, SocketAddrV4), Error> {
+    let mut result = Err(Error::NoMappingMethod);
+    for method in MappingMethod::ALL.iter().filter(|m| config.allowed_methods.allows(**m)) {
+        result = search
+            .search(*method, config)
+            .and_then(|gw| gw.add_mapping(add_params))
+            .map(|external_address| (*method, external_address));
+        match result {
+            Err(ref e) if e.is_gateway_unavailable() => {
+                info!("{} port mapping unavailable: {}", method, e)
+            }
+            _ => break,
+        }
+    }
+    result
+}
+
+/// Opens an IPv6 pinhole with the first allowed method of which the gateway supports it.
+pub fn add_pinhole(
+    search: &dyn GatewaySearch,
+    config: RouterConfig,
+    add_params: &AddPinholeEntry,
+) -> Result<(MappingMethod, SocketAddrV6), Error> {
+    let mut result = Err(Error::NoMappingMethod);
+    for method in MappingMethod::ALL.iter().filter(|m| config.allowed_methods.allows(**m)) {
+        result = search
+            .search(*method, config)
+            .and_then(|gw| gw.add_pinhole(add_params))
+            .map(|external_address| (*method, external_address));
+        match result {
+            Err(ref e) if e.is_pinhole_unavailable() => {
+                info!("{} IPv6 pinhole unavailable: {}", method, e)
+            }
+            _ => break,
+        }
+    }
+    result
+}
+
+/// Returns the external IP reported by the first allowed method for which a gateway is available.
+pub fn get_external_ip(
+    search: &dyn GatewaySearch,
+    config: RouterConfig,
+) -> Result<Ipv4Addr, Error> {
+    let mut result = Err(Error::NoMappingMethod);
+    for method in MappingMethod::ALL.iter().filter(|m| config.allowed_methods.allows(**m)) {
+        result = search.search(*method, config).and_then(|gw| gw.get_external_ip());
+        match result {
+            Err(ref e) if e.is_gateway_unavailable() => (),
+            _ => break,
+        }
+    }
+    result
+}
+
+/// A UPnP gateway
+///
+/// IPv6 pinholes (`WANIPv6FirewallControl`) are not supported by `igd`.
 pub struct Gateway {
     gw: igd::Gateway,
 }
 
 impl Gateway {
-    pub fn new(config: RouterConfig) -> Gateway {
-        Gateway { gw: igd::search_gateway(SearchOptions::from(config)).unwrap() }
+    pub fn search(config: RouterConfig) -> Result<Gateway, Error> {
+        Ok(Gateway { gw: igd::search_gateway(SearchOptions::from(config))? })
     }
+}
 
-    pub fn add_mapping(&self, add_params: AddMappingEntry) -> Result<(), Error> {
+impl MappingGateway for Gateway {
+    fn add_mapping(&self, add_params: &AddMappingEntry) -> Result<SocketAddrV4, Error> {
         self.gw.add_port(
             add_params.protocol.into(),
             add_params.external_port,
@@ -24,10 +136,10 @@ impl Gateway {
             &add_params.node_description,
         )?;
 
-        Ok(())
+        Ok(SocketAddrV4::new(self.get_external_ip()?, add_params.external_port))
     }
 
-    pub fn get_external_ip(&self) -> Result<Ipv4Addr, Error> {
+    fn get_external_ip(&self) -> Result<Ipv4Addr, Error> {
         let ext_ip = self.gw.get_external_ip()?;
 
         Ok(ext_ip)
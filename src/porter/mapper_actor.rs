@@ -1,20 +1,44 @@
-use actix::AsyncContext;
-use actix::{Arbiter, Handler};
-use std::{net::Ipv4Addr, time::Duration};
+use actix::{ActorFutureExt, AsyncContext, SpawnHandle, WrapFuture};
+use actix::{Arbiter, Handler, Recipient, ResponseFuture};
+use std::net::{Ipv4Addr, SocketAddrV4, SocketAddrV6};
+use std::sync::Arc;
+use std::time::Duration;
 
 use actix::{Actor, Context};
 use tracing::{error, trace, warn};
 
 use super::{
-    messages::{GetExternalIpMessage, MappingMessage},
+    gateway::{self, GatewaySearch},
+    messages::{
+        AddMappingMessage, AddPinholeMessage, ExternalIpChanged, ExternalIpPolled,
+        GetExternalIpMessage, MappingMessage, WatchExternalIp,
+    },
     params::RouterConfig,
-    Error,
+    Error, MappingMethod,
 };
-use crate::porter::gateway::Gateway;
 
-#[derive(Debug)]
 pub struct MapperActor {
     pub config: RouterConfig,
+    /// Used to find the gateway for each mapping method
+    pub search: Arc<dyn GatewaySearch>,
+    /// The last polled external IP
+    external_ip: Option<Ipv4Addr>,
+    /// Notified when the external IP changes
+    ip_change_recipient: Option<Recipient<ExternalIpChanged>>,
+    /// The interval polling the external IP, replaced by every [WatchExternalIp]
+    watch_handle: Option<SpawnHandle>,
+}
+
+impl MapperActor {
+    pub fn new(config: RouterConfig, search: Arc<dyn GatewaySearch>) -> Self {
+        MapperActor {
+            config,
+            search,
+            external_ip: None,
+            ip_change_recipient: None,
+            watch_handle: None,
+        }
+    }
 }
 
 impl Actor for MapperActor {
@@ -29,26 +53,55 @@ impl Actor for MapperActor {
     }
 }
 
+/// Runs a gateway request on the blocking thread pool, so that the gateway search and the
+/// retransmissions of the request don't block the actor
+fn spawn_gateway_request<T, F>(request: F) -> ResponseFuture<std::result::Result<T, Error>>
+where
+    T: Send + 'static,
+    F: FnOnce() -> std::result::Result<T, Error> + Send + 'static,
+{
+    Box::pin(async move {
+        match tokio::task::spawn_blocking(request).await {
+            Ok(result) => result,
+            Err(e) => Err(Error::JoinError(e.to_string())),
+        }
+    })
+}
+
+impl Handler<AddMappingMessage> for MapperActor {
+    type Result = ResponseFuture<std::result::Result<(MappingMethod, SocketAddrV4), Error>>;
+
+    fn handle(&mut self, msg: AddMappingMessage, _ctx: &mut Context<Self>) -> Self::Result {
+        let (search, config) = (self.search.clone(), self.config);
+        spawn_gateway_request(move || gateway::add_mapping(search.as_ref(), config, &msg.0))
+    }
+}
+
+impl Handler<AddPinholeMessage> for MapperActor {
+    type Result = ResponseFuture<std::result::Result<(MappingMethod, SocketAddrV6), Error>>;
+
+    fn handle(&mut self, msg: AddPinholeMessage, _ctx: &mut Context<Self>) -> Self::Result {
+        let (search, config) = (self.search.clone(), self.config);
+        spawn_gateway_request(move || gateway::add_pinhole(search.as_ref(), config, &msg.0))
+    }
+}
+
 impl Handler<MappingMessage> for MapperActor {
     type Result = std::result::Result<(), Error>;
 
     fn handle(&mut self, msg: MappingMessage, ctx: &mut Context<Self>) -> Self::Result {
         match msg {
-            MappingMessage::AddMapping(add_msg) => {
-                let gw = Gateway::new(self.config);
-                gw.add_mapping(add_msg)
-            }
             MappingMessage::RefreshMapping(refresh_msg) => {
                 let conf = self.config.clone();
                 let arb = Arbiter::new();
 
-                ctx.run_interval(Duration::from_secs(10), move |_act, _ctx| {
+                ctx.run_interval(Duration::from_secs(10), move |act, _ctx| {
                     let par = refresh_msg.clone();
+                    let search = act.search.clone();
 
                     arb.spawn(async move {
-                        let gw = Gateway::new(conf);
-                        let current_external_ip =
-                            gw.get_external_ip().expect("GetExternalIp error!");
+                        let current_external_ip = gateway::get_external_ip(search.as_ref(), conf)
+                            .expect("GetExternalIp error!");
 
                         // TODO: Error log might be enough for now
                         if current_external_ip != par.external_ip {
@@ -58,8 +111,8 @@ impl Handler<MappingMessage> for MapperActor {
                             );
                         }
 
-                        match gw.add_mapping(par.add_params) {
-                            Ok(()) => trace!("Port lease has been refreshed!"),
+                        match gateway::add_mapping(search.as_ref(), conf, &par.add_params) {
+                            Ok(_) => trace!("Port lease has been refreshed!"),
                             Err(e) => error!("Port lease refresh failed: {}", e),
                         }
                     });
@@ -72,12 +125,55 @@ impl Handler<MappingMessage> for MapperActor {
 }
 
 impl Handler<GetExternalIpMessage> for MapperActor {
-    type Result = std::result::Result<Ipv4Addr, Error>;
+    type Result = ResponseFuture<std::result::Result<Ipv4Addr, Error>>;
 
     fn handle(&mut self, _msg: GetExternalIpMessage, _ctx: &mut Context<Self>) -> Self::Result {
-        let gw = Gateway::new(self.config);
-        let ext_ip = gw.get_external_ip()?;
+        let (search, config) = (self.search.clone(), self.config);
+        spawn_gateway_request(move || gateway::get_external_ip(search.as_ref(), config))
+    }
+}
+
+impl Handler<WatchExternalIp> for MapperActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: WatchExternalIp, ctx: &mut Context<Self>) -> Self::Result {
+        self.ip_change_recipient = Some(msg.recipient);
+        if let Some(handle) = self.watch_handle.take() {
+            let _ = ctx.cancel_future(handle);
+        }
 
-        Ok(ext_ip)
+        // Gateway requests are blocking, the result is sent back to the actor
+        let handle = ctx.run_interval(self.config.external_ip_poll_interval, |act, ctx| {
+            let (search, config) = (act.search.clone(), act.config);
+            let poll =
+                spawn_gateway_request(move || gateway::get_external_ip(search.as_ref(), config));
+            let _ = ctx.spawn(
+                poll.into_actor(act)
+                    .map(|external_ip, _act, ctx| ctx.notify(ExternalIpPolled(external_ip))),
+            );
+        });
+        self.watch_handle = Some(handle);
+    }
+}
+
+impl Handler<ExternalIpPolled> for MapperActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: ExternalIpPolled, _ctx: &mut Context<Self>) -> Self::Result {
+        match msg.0 {
+            Ok(new) => {
+                match self.external_ip {
+                    Some(old) if old != new => {
+                        warn!("External IP has changed! Old: {}, New: {}", old, new);
+                        if let Some(recipient) = self.ip_change_recipient.as_ref() {
+                            let _ = recipient.do_send(ExternalIpChanged { old, new });
+                        }
+                    }
+                    _ => (),
+                }
+                self.external_ip = Some(new);
+            }
+            Err(e) => error!("Polling the external IP failed: {}", e),
+        }
     }
 }
@@ -1,14 +1,18 @@
 use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
 use std::time::Duration;
 
-use actix::{Actor, Addr};
+use actix::{Actor, Addr, Recipient};
 use tracing::info;
 
-use crate::porter::messages::MappingMessage;
-use crate::porter::params::{AddMappingEntry, PortMappingEntry};
+use crate::porter::messages::{AddMappingMessage, AddPinholeMessage, MappingMessage};
+use crate::porter::params::{
+    AddMappingEntry, AddPinholeEntry, MappingEntry, PinholeEntry, PortMappingEntry,
+};
 
+use super::gateway::{GatewaySearch, NetworkGatewaySearch};
 use super::mapper_actor::MapperActor;
-use super::messages::GetExternalIpMessage;
+use super::messages::{ExternalIpChanged, GetExternalIpMessage, WatchExternalIp};
 use super::params::{NetworkConfig, RefreshMappingEntry, RouterConfig};
 use super::{Error, Protocol};
 
@@ -26,48 +30,60 @@ pub struct Mapper {
 
 impl Mapper {
     pub fn new(local_addr: SocketAddr, router_config: Option<RouterConfig>) -> Self {
+        Self::with_gateway_search(local_addr, router_config, Arc::new(NetworkGatewaySearch {}))
+    }
+
+    /// Creates a mapper which finds gateways with `search`
+    pub fn with_gateway_search(
+        local_addr: SocketAddr,
+        router_config: Option<RouterConfig>,
+        search: Arc<dyn GatewaySearch>,
+    ) -> Self {
         Self {
-            network_config: NetworkConfig {
-                local_address: match local_addr {
-                    SocketAddr::V4(addr) => addr,
-                    SocketAddr::V6(_addr) => panic!("Expected SocketAddrV4, got V6!"),
-                },
-            },
-            mapper_actor: MapperActor {
-                config: match router_config {
+            network_config: NetworkConfig { local_address: local_addr },
+            mapper_actor: MapperActor::new(
+                match router_config {
                     Some(conf) => conf,
                     None => RouterConfig::default(),
                 },
-            }
+                search.clone(),
+            )
             .start(),
-            refresher_actor: MapperActor {
-                config: match router_config {
+            refresher_actor: MapperActor::new(
+                match router_config {
                     Some(conf) => conf,
                     None => RouterConfig::default(),
                 },
-            }
+                search,
+            )
             .start(),
         }
     }
 
-    /// Adds a new port mapping
+    /// Adds a new port mapping for the IPv4 local address, using the first available mapping
+    /// method
     pub async fn add_port_mapping(
         &mut self,
         external_port: u16,
         protocol: Protocol,
         node_desc: &str,
     ) -> Result<PortMappingEntry, Error> {
+        let local_address = match self.network_config.local_address {
+            SocketAddr::V4(addr) => addr,
+            addr => return Err(Error::UnsupportedAddress(addr)),
+        };
         let add_params = AddMappingEntry::new(
-            self.network_config.local_address,
+            local_address,
             external_port,
             protocol,
             MAPPING_LEASE_DURATION,
             node_desc.to_string(),
         );
 
-        info!("Mapping {} to external port {}", self.network_config.local_address, external_port);
-        let _ =
-            self.mapper_actor.send(MappingMessage::AddMapping(add_params.clone())).await.unwrap();
+        info!("Mapping {} to external port {}", local_address, external_port);
+        let (method, external_address) =
+            self.mapper_actor.send(AddMappingMessage(add_params.clone())).await.unwrap()?;
+        info!("Mapped {} to {} using {}", local_address, external_address, method);
 
         Ok(PortMappingEntry {
             local_address: add_params.local_address,
@@ -75,9 +91,67 @@ impl Mapper {
             external_port: add_params.external_port,
             lease_duration: add_params.lease_duration,
             protocol: add_params.protocol,
+            external_address,
+            method,
+        })
+    }
+
+    /// Opens an IPv6 pinhole for the IPv6 local address, using the first mapping method
+    /// supporting it
+    pub async fn add_pinhole(
+        &mut self,
+        external_port: u16,
+        protocol: Protocol,
+        node_desc: &str,
+    ) -> Result<PinholeEntry, Error> {
+        let local_address = match self.network_config.local_address {
+            SocketAddr::V6(addr) => addr,
+            addr => return Err(Error::UnsupportedAddress(addr)),
+        };
+        let add_params = AddPinholeEntry::new(
+            local_address,
+            external_port,
+            protocol,
+            MAPPING_LEASE_DURATION,
+            node_desc.to_string(),
+        );
+
+        info!("Opening a pinhole to {} at external port {}", local_address, external_port);
+        let (method, external_address) =
+            self.mapper_actor.send(AddPinholeMessage(add_params.clone())).await.unwrap()?;
+        info!("Opened a pinhole from {} to {} using {}", external_address, local_address, method);
+
+        Ok(PinholeEntry {
+            local_address: add_params.local_address,
+            node_description: add_params.node_description,
+            external_port: add_params.external_port,
+            lease_duration: add_params.lease_duration,
+            protocol: add_params.protocol,
+            external_address,
+            method,
         })
     }
 
+    /// Maps the port of the local address of either family: IPv4 addresses with
+    /// [add_port_mapping][Mapper::add_port_mapping], IPv6 addresses with
+    /// [add_pinhole][Mapper::add_pinhole]
+    pub async fn add_mapping(
+        &mut self,
+        external_port: u16,
+        protocol: Protocol,
+        node_desc: &str,
+    ) -> Result<MappingEntry, Error> {
+        match self.network_config.local_address {
+            SocketAddr::V4(_) => self
+                .add_port_mapping(external_port, protocol, node_desc)
+                .await
+                .map(MappingEntry::V4),
+            SocketAddr::V6(_) => {
+                self.add_pinhole(external_port, protocol, node_desc).await.map(MappingEntry::V6)
+            }
+        }
+    }
+
     /// Returns the external IP of the attached IGD
     async fn get_external_ip(&self) -> Result<Ipv4Addr, Error> {
         let resp = self.mapper_actor.send(GetExternalIpMessage::GetExternalIp).await.unwrap();
@@ -105,6 +179,17 @@ impl Mapper {
         resp
     }
 
+    /// Polls the external IP periodically (see [RouterConfig::external_ip_poll_interval]),
+    /// sending [ExternalIpChanged] to `recipient` whenever it changes
+    pub async fn watch_external_ip(
+        &self,
+        recipient: Recipient<ExternalIpChanged>,
+    ) -> Result<(), Error> {
+        self.refresher_actor.send(WatchExternalIp { recipient }).await.unwrap();
+
+        Ok(())
+    }
+
     pub async fn add_and_refresh_mapping(
         &mut self,
         external_port: u16,
@@ -125,3 +210,251 @@ impl Mapper {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::porter::gateway::MappingGateway;
+    use crate::porter::params::AllowedMethods;
+    use crate::porter::MappingMethod;
+
+    use actix::{Context, Handler};
+
+    use std::collections::VecDeque;
+    use std::net::{SocketAddrV4, SocketAddrV6};
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    const EXTERNAL_IP: Ipv4Addr = Ipv4Addr::new(1, 2, 3, 4);
+
+    struct MockGateway {
+        method: MappingMethod,
+        external_ip: Ipv4Addr,
+    }
+
+    impl MappingGateway for MockGateway {
+        fn add_mapping(&self, add_params: &AddMappingEntry) -> Result<SocketAddrV4, Error> {
+            Ok(SocketAddrV4::new(self.external_ip, add_params.external_port))
+        }
+
+        fn get_external_ip(&self) -> Result<Ipv4Addr, Error> {
+            Ok(self.external_ip)
+        }
+
+        /// Only PCP gateways open pinholes, at the local address
+        fn add_pinhole(&self, add_params: &AddPinholeEntry) -> Result<SocketAddrV6, Error> {
+            match self.method {
+                MappingMethod::Pcp => Ok(add_params.local_address),
+                _ => Err(Error::PinholeUnsupported),
+            }
+        }
+    }
+
+    /// Finds gateways only for the `available` methods and records the attempted ones
+    struct MockSearch {
+        available: Vec<MappingMethod>,
+        attempted: Mutex<Vec<MappingMethod>>,
+        /// The external IPs reported by the gateways found, the last one is kept
+        external_ips: Mutex<VecDeque<Ipv4Addr>>,
+        /// The time a search takes
+        delay: Duration,
+    }
+
+    impl MockSearch {
+        fn new(available: Vec<MappingMethod>) -> Arc<Self> {
+            Self::with_external_ips(available, vec![EXTERNAL_IP])
+        }
+
+        fn with_external_ips(available: Vec<MappingMethod>, ips: Vec<Ipv4Addr>) -> Arc<Self> {
+            Arc::new(MockSearch {
+                available,
+                attempted: Mutex::new(vec![]),
+                external_ips: Mutex::new(ips.into_iter().collect()),
+                delay: Duration::ZERO,
+            })
+        }
+
+        /// Like [MockSearch::new], each search taking `delay`
+        fn slow(available: Vec<MappingMethod>, delay: Duration) -> Arc<Self> {
+            Arc::new(MockSearch {
+                available,
+                attempted: Mutex::new(vec![]),
+                external_ips: Mutex::new(vec![EXTERNAL_IP].into_iter().collect()),
+                delay,
+            })
+        }
+
+        fn next_external_ip(&self) -> Ipv4Addr {
+            let mut ips = self.external_ips.lock().unwrap();
+            if ips.len() > 1 {
+                ips.pop_front().unwrap()
+            } else {
+                ips[0]
+            }
+        }
+    }
+
+    impl GatewaySearch for MockSearch {
+        fn search(
+            &self,
+            method: MappingMethod,
+            _config: RouterConfig,
+        ) -> Result<Box<dyn MappingGateway>, Error> {
+            std::thread::sleep(self.delay);
+            self.attempted.lock().unwrap().push(method);
+            if self.available.contains(&method) {
+                Ok(Box::new(MockGateway { method, external_ip: self.next_external_ip() }))
+            } else if method == MappingMethod::Upnp {
+                Err(Error::GatewayRetrieve(igd::SearchError::InvalidResponse))
+            } else {
+                Err(Error::GatewayUnavailable(method))
+            }
+        }
+    }
+
+    fn local_addr() -> SocketAddr {
+        "192.168.1.10:1234".parse().unwrap()
+    }
+
+    fn local_addr_v6() -> SocketAddr {
+        "[2001:db8::10]:1234".parse().unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn test_upnp_mapping() {
+        let search = MockSearch::new(vec![MappingMethod::Upnp, MappingMethod::NatPmp]);
+        let mut mapper = Mapper::with_gateway_search(local_addr(), None, search.clone());
+
+        let entry = mapper.add_port_mapping(4321, Protocol::TCP, "test").await.unwrap();
+        assert_eq!(entry.method, MappingMethod::Upnp);
+        assert_eq!(entry.external_address, SocketAddrV4::new(EXTERNAL_IP, 4321));
+        assert_eq!(*search.attempted.lock().unwrap(), vec![MappingMethod::Upnp]);
+    }
+
+    #[actix_rt::test]
+    async fn test_fallback_on_upnp_failure() {
+        let search = MockSearch::new(vec![MappingMethod::NatPmp]);
+        let mut mapper = Mapper::with_gateway_search(local_addr(), None, search.clone());
+
+        let entry = mapper.add_port_mapping(4321, Protocol::TCP, "test").await.unwrap();
+        assert_eq!(entry.method, MappingMethod::NatPmp);
+        assert_eq!(entry.external_address, SocketAddrV4::new(EXTERNAL_IP, 4321));
+        assert_eq!(
+            *search.attempted.lock().unwrap(),
+            vec![MappingMethod::Upnp, MappingMethod::Pcp, MappingMethod::NatPmp]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_disallowed_methods_are_skipped() {
+        let search = MockSearch::new(vec![MappingMethod::Pcp]);
+        let mut config = RouterConfig::default();
+        config.allowed_methods = AllowedMethods { upnp: true, pcp: false, nat_pmp: true };
+        let mut mapper = Mapper::with_gateway_search(local_addr(), Some(config), search.clone());
+
+        match mapper.add_port_mapping(4321, Protocol::UDP, "test").await {
+            Err(Error::GatewayUnavailable(MappingMethod::NatPmp)) => (),
+            other => panic!("unexpected: {:?}", other),
+        }
+        assert_eq!(
+            *search.attempted.lock().unwrap(),
+            vec![MappingMethod::Upnp, MappingMethod::NatPmp]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_ipv6_pinhole() {
+        let search = MockSearch::new(MappingMethod::ALL.to_vec());
+        let mut mapper = Mapper::with_gateway_search(local_addr_v6(), None, search.clone());
+
+        let entry = mapper.add_mapping(1234, Protocol::TCP, "test").await.unwrap();
+        assert!(matches!(entry, MappingEntry::V6(PinholeEntry { method: MappingMethod::Pcp, .. })));
+        assert_eq!(entry.external_address(), local_addr_v6());
+        assert_eq!(
+            *search.attempted.lock().unwrap(),
+            vec![MappingMethod::Upnp, MappingMethod::Pcp]
+        );
+
+        // The IPv4 API refuses IPv6 addresses
+        match mapper.add_port_mapping(1234, Protocol::TCP, "test").await {
+            Err(Error::UnsupportedAddress(addr)) => assert_eq!(addr, local_addr_v6()),
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_ipv6_pinhole_unsupported() {
+        let search = MockSearch::new(vec![MappingMethod::Upnp, MappingMethod::NatPmp]);
+        let mut mapper = Mapper::with_gateway_search(local_addr_v6(), None, search.clone());
+
+        match mapper.add_pinhole(1234, Protocol::TCP, "test").await {
+            Err(Error::PinholeUnsupported) => (),
+            other => panic!("unexpected: {:?}", other),
+        }
+        assert_eq!(*search.attempted.lock().unwrap(), MappingMethod::ALL.to_vec());
+    }
+
+    #[actix_rt::test]
+    async fn test_gateway_requests_dont_block_the_actor() {
+        let delay = Duration::from_millis(500);
+        let search = MockSearch::slow(vec![MappingMethod::Upnp], delay);
+        let actor = MapperActor::new(RouterConfig::default(), search).start();
+
+        let add_params = AddMappingEntry::new(
+            "192.168.1.10:1234".parse().unwrap(),
+            4321,
+            Protocol::TCP,
+            MAPPING_LEASE_DURATION,
+            "test".to_owned(),
+        );
+        let mapping = actix_rt::spawn(actor.send(AddMappingMessage(add_params)));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // The actor handles other messages while the gateway is searched
+        let started = Instant::now();
+        let collector = IpChangeCollector { changes: Arc::new(Mutex::new(vec![])) }.start();
+        actor.send(WatchExternalIp { recipient: collector.recipient() }).await.unwrap();
+        assert!(started.elapsed() < delay / 2);
+
+        let (method, _) = mapping.await.unwrap().unwrap().unwrap();
+        assert_eq!(method, MappingMethod::Upnp);
+    }
+
+    /// Collects the received external IP changes
+    struct IpChangeCollector {
+        changes: Arc<Mutex<Vec<ExternalIpChanged>>>,
+    }
+
+    impl Actor for IpChangeCollector {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<ExternalIpChanged> for IpChangeCollector {
+        type Result = ();
+
+        fn handle(&mut self, msg: ExternalIpChanged, _ctx: &mut Context<Self>) -> Self::Result {
+            self.changes.lock().unwrap().push(msg);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_external_ip_change_notification() {
+        let new_ip = Ipv4Addr::new(5, 6, 7, 8);
+        let search =
+            MockSearch::with_external_ips(vec![MappingMethod::Upnp], vec![EXTERNAL_IP, new_ip]);
+        let mut config = RouterConfig::default();
+        config.external_ip_poll_interval = Duration::from_millis(10);
+        let mapper = Mapper::with_gateway_search(local_addr(), Some(config), search);
+
+        let changes = Arc::new(Mutex::new(vec![]));
+        let collector = IpChangeCollector { changes: changes.clone() }.start();
+        mapper.watch_external_ip(collector.recipient()).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(
+            *changes.lock().unwrap(),
+            vec![ExternalIpChanged { old: EXTERNAL_IP, new: new_ip }]
+        );
+    }
+}
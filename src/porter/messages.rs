@@ -1,14 +1,21 @@
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, SocketAddrV4, SocketAddrV6};
 
-use actix::Message;
+use actix::{Message, Recipient};
 
-use super::params::{AddMappingEntry, RefreshMappingEntry};
-use super::Error;
+use super::params::{AddMappingEntry, AddPinholeEntry, RefreshMappingEntry};
+use super::{Error, MappingMethod};
+
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "Result<(MappingMethod, SocketAddrV4), Error>")]
+pub struct AddMappingMessage(pub AddMappingEntry);
+
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "Result<(MappingMethod, SocketAddrV6), Error>")]
+pub struct AddPinholeMessage(pub AddPinholeEntry);
 
 #[derive(Debug, Clone, Message)]
 #[rtype(result = "Result<(), Error>")]
 pub enum MappingMessage {
-    AddMapping(AddMappingEntry),
     RefreshMapping(RefreshMappingEntry),
 }
 
@@ -17,3 +24,23 @@ pub enum MappingMessage {
 pub enum GetExternalIpMessage {
     GetExternalIp,
 }
+
+/// Notification sent to the registered recipient when the external IP of the gateway changes
+#[derive(Debug, Clone, PartialEq, Message)]
+#[rtype(result = "()")]
+pub struct ExternalIpChanged {
+    pub old: Ipv4Addr,
+    pub new: Ipv4Addr,
+}
+
+/// Starts polling the external IP, notifying `recipient` of every change
+#[derive(Clone, Message)]
+#[rtype(result = "()")]
+pub struct WatchExternalIp {
+    pub recipient: Recipient<ExternalIpChanged>,
+}
+
+/// Internal message carrying the result of an external IP poll
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+pub struct ExternalIpPolled(pub Result<Ipv4Addr, Error>);
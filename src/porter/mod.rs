@@ -1,19 +1,25 @@
 //! NAT traversal
 //!
 //! Wraps the `rust-igd` library for basic functions to add, refresh and remove port mappings.
+//! When no UPnP gateway can be found, the mapping falls back to PCP and NAT-PMP.
 //!
 //! ## Use
 //!
 //! - Construct the [`Mapper`][mapper_handler::Mapper] struct with the local address and the optional `RouterConfig` parameter.
 //! - Provide a correct SSDP broadcast address with [`RouterConfig`][params::RouterConfig] if upnp gateway retrieval is unsuccessful.
-//! - If mapping is successful, it returns the newly mapped entry
+//! - Disable mapping methods with [`AllowedMethods`][params::AllowedMethods] if needed.
+//! - If mapping is successful, it returns the newly mapped entry along with the method which mapped it
+//! - IPv4 addresses are mapped with any of the methods, for IPv6 addresses a PCP pinhole is opened with `add_pinhole`, `add_mapping` handles both address families
 //! - To dinamically refresh port lease, call `refresh_mapping` with the `add_port_mapping`] return value and the mapping refresh interval
+//! - To get notified of external IP changes, register a recipient of [`ExternalIpChanged`] with `watch_external_ip`
 mod gateway;
 mod mapper_actor;
 pub mod mapper_handler;
 mod messages;
+mod nat_pmp;
 pub mod params;
 
+pub use messages::ExternalIpChanged;
 pub use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 
 use self::params::{AddMappingEntry, PortMappingEntry};
@@ -26,6 +32,37 @@ pub enum Error {
     PortRemove(igd::RemovePortError),
     ExternalIpChanged(String),
     MappingRefresh(String),
+    /// The gateway didn't answer or doesn't support the mapping method
+    GatewayUnavailable(MappingMethod),
+    /// The PCP / NAT-PMP gateway refused the request
+    NatPmp(String),
+    /// All mapping methods are disabled in the router config
+    NoMappingMethod,
+    /// The gateway can't open IPv6 pinholes
+    PinholeUnsupported,
+    /// The operation isn't supported for the address family of the address
+    UnsupportedAddress(SocketAddr),
+    /// The blocking gateway request didn't run to completion
+    JoinError(String),
+}
+
+impl Error {
+    /// Whether the error means that no gateway supporting the mapping method was found,
+    /// in which case the next mapping method can be attempted.
+    pub fn is_gateway_unavailable(&self) -> bool {
+        match self {
+            Error::GatewayRetrieve(_) | Error::GatewayUnavailable(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the next mapping method can be attempted when opening an IPv6 pinhole
+    pub fn is_pinhole_unavailable(&self) -> bool {
+        match self {
+            Error::PinholeUnsupported => true,
+            e => e.is_gateway_unavailable(),
+        }
+    }
 }
 
 impl std::convert::From<igd::SearchError> for Error {
@@ -67,6 +104,34 @@ pub enum Protocol {
     UDP,
 }
 
+/// Represents the methods available for port mapping, in the order they are attempted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MappingMethod {
+    Upnp,
+    Pcp,
+    NatPmp,
+}
+
+impl MappingMethod {
+    /// All mapping methods, in the order they are attempted.
+    pub const ALL: [MappingMethod; 3] =
+        [MappingMethod::Upnp, MappingMethod::Pcp, MappingMethod::NatPmp];
+}
+
+impl std::fmt::Display for MappingMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                MappingMethod::Upnp => "UPnP",
+                MappingMethod::Pcp => "PCP",
+                MappingMethod::NatPmp => "NAT-PMP",
+            }
+        )
+    }
+}
+
 impl From<PortMappingEntry> for AddMappingEntry {
     fn from(pme: PortMappingEntry) -> AddMappingEntry {
         AddMappingEntry::new(
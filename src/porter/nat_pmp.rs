@@ -0,0 +1,280 @@
+//! Minimal PCP ([RFC 6887]) and NAT-PMP ([RFC 6886]) clients, used when no UPnP gateway is found.
+//!
+//! [RFC 6887]: https://datatracker.ietf.org/doc/html/rfc6887
+//! [RFC 6886]: https://datatracker.ietf.org/doc/html/rfc6886
+use rand::Rng;
+
+use std::convert::TryInto;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket};
+use std::time::Duration;
+
+use super::gateway::MappingGateway;
+use super::params::{AddMappingEntry, AddPinholeEntry, RouterConfig};
+use super::{Error, MappingMethod, Protocol};
+
+/// The port PCP and NAT-PMP servers listen on
+const PMP_SERVER_PORT: u16 = 5351;
+/// Number of times a request is sent before giving up
+const PMP_MAX_TRIES: u32 = 4;
+/// Initial response timeout, doubled on every retry
+const PMP_INITIAL_TIMEOUT: Duration = Duration::from_millis(250);
+
+const NAT_PMP_VERSION: u8 = 0;
+const PCP_VERSION: u8 = 2;
+const PCP_OPCODE_MAP: u8 = 1;
+/// The `R` bit which is set on responses
+const RESPONSE_BIT: u8 = 128;
+/// Result code returned for unsupported protocol versions (same for PCP and NAT-PMP)
+const UNSUPP_VERSION: u16 = 1;
+
+/// A PCP or NAT-PMP gateway
+pub struct PmpGateway {
+    method: MappingMethod,
+    gateway: Option<SocketAddrV4>,
+    /// The gateway for IPv6 pinholes (PCP only)
+    gateway_v6: Option<SocketAddrV6>,
+}
+
+impl PmpGateway {
+    /// Creates a client for `method` (either [MappingMethod::Pcp] or [MappingMethod::NatPmp]).
+    /// The gateway address is taken from the router config, or else from the default route.
+    /// For PCP, the IPv6 gateway is only taken from the router config.
+    pub fn new(method: MappingMethod, config: RouterConfig) -> Result<Self, Error> {
+        let gateway = config
+            .pmp_gateway
+            .or_else(default_gateway)
+            .map(|ip| SocketAddrV4::new(ip, PMP_SERVER_PORT));
+        let gateway_v6 = match method {
+            MappingMethod::Pcp => {
+                config.pcp_v6_gateway.map(|ip| SocketAddrV6::new(ip, PMP_SERVER_PORT, 0, 0))
+            }
+            _ => None,
+        };
+        if gateway.is_none() && gateway_v6.is_none() {
+            return Err(Error::GatewayUnavailable(method));
+        }
+        Ok(PmpGateway { method, gateway, gateway_v6 })
+    }
+
+    fn gateway(&self) -> Result<SocketAddr, Error> {
+        self.gateway.map(SocketAddr::V4).ok_or(Error::GatewayUnavailable(self.method))
+    }
+
+    /// Sends `request` to `gateway`, retransmitting with an exponential back-off until
+    /// a response with the expected opcode is received.
+    fn request(&self, gateway: SocketAddr, request: &[u8], opcode: u8) -> Result<Vec<u8>, Error> {
+        let bind_addr = match gateway {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = UdpSocket::bind(bind_addr).map_err(|e| io_error(self.method, e))?;
+        socket.connect(gateway).map_err(|e| io_error(self.method, e))?;
+        let mut timeout = PMP_INITIAL_TIMEOUT;
+        let mut buf = [0u8; 1100];
+        for _ in 0..PMP_MAX_TRIES {
+            socket.set_read_timeout(Some(timeout)).map_err(|e| io_error(self.method, e))?;
+            socket.send(request).map_err(|e| io_error(self.method, e))?;
+            match socket.recv(&mut buf) {
+                Ok(n) if n >= 4 && buf[1] == RESPONSE_BIT | opcode => return Ok(buf[..n].to_vec()),
+                // Ignore unrelated datagrams
+                Ok(_) => (),
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                    timeout *= 2
+                }
+                Err(e) => return Err(io_error(self.method, e)),
+            }
+        }
+        Err(Error::GatewayUnavailable(self.method))
+    }
+
+    /// Checks the result code of a response, of which the position differs per protocol
+    fn check_result(&self, result_code: u16) -> Result<(), Error> {
+        match result_code {
+            0 => Ok(()),
+            UNSUPP_VERSION => Err(Error::GatewayUnavailable(self.method)),
+            code => Err(Error::NatPmp(format!(
+                "{} request failed with result code {}",
+                self.method, code
+            ))),
+        }
+    }
+
+    /// Sends a PCP `MAP` request to `gateway`, returning the external address and port
+    fn pcp_map(
+        &self,
+        gateway: SocketAddr,
+        protocol: Protocol,
+        lease_duration: Duration,
+        local_address: SocketAddr,
+        external_port: u16,
+    ) -> Result<(Ipv6Addr, u16), Error> {
+        let nonce: [u8; 12] = rand::thread_rng().gen();
+        let request =
+            pcp_map_request(protocol, lease_duration, local_address, external_port, &nonce);
+        let response = self.request(gateway, &request, PCP_OPCODE_MAP)?;
+        // Checked first, as a NAT-PMP gateway answers with a short UNSUPP_VERSION response
+        self.check_result(response[3] as u16)?;
+        if response.len() < 60 {
+            return Err(Error::NatPmp("truncated PCP response".to_owned()));
+        }
+        if response[24..36] != nonce {
+            return Err(Error::NatPmp("PCP response nonce mismatch".to_owned()));
+        }
+        let external_port = u16::from_be_bytes([response[42], response[43]]);
+        let external_ip: [u8; 16] = response[44..60].try_into().unwrap();
+        Ok((Ipv6Addr::from(external_ip), external_port))
+    }
+
+    fn add_pcp_mapping(&self, add_params: &AddMappingEntry) -> Result<SocketAddrV4, Error> {
+        let (external_ip, external_port) = self.pcp_map(
+            self.gateway()?,
+            add_params.protocol,
+            add_params.lease_duration,
+            SocketAddr::V4(add_params.local_address),
+            add_params.external_port,
+        )?;
+        let external_ip = external_ip
+            .to_ipv4()
+            .ok_or(Error::NatPmp("PCP response contains no IPv4 address".to_owned()))?;
+        Ok(SocketAddrV4::new(external_ip, external_port))
+    }
+
+    fn add_nat_pmp_mapping(&self, add_params: &AddMappingEntry) -> Result<SocketAddrV4, Error> {
+        let opcode = nat_pmp_opcode(add_params.protocol);
+        let mut request = vec![NAT_PMP_VERSION, opcode, 0, 0];
+        request.extend_from_slice(&add_params.local_address.port().to_be_bytes());
+        request.extend_from_slice(&add_params.external_port.to_be_bytes());
+        request.extend_from_slice(&(add_params.lease_duration.as_secs() as u32).to_be_bytes());
+        let response = self.request(self.gateway()?, &request, opcode)?;
+        self.check_result(u16::from_be_bytes([response[2], response[3]]))?;
+        if response.len() < 16 {
+            return Err(Error::NatPmp("truncated NAT-PMP response".to_owned()));
+        }
+        let external_port = u16::from_be_bytes([response[10], response[11]]);
+        Ok(SocketAddrV4::new(self.get_external_ip()?, external_port))
+    }
+}
+
+impl MappingGateway for PmpGateway {
+    fn add_mapping(&self, add_params: &AddMappingEntry) -> Result<SocketAddrV4, Error> {
+        match self.method {
+            MappingMethod::Pcp => self.add_pcp_mapping(add_params),
+            _ => self.add_nat_pmp_mapping(add_params),
+        }
+    }
+
+    /// PCP has no request for the external address alone, so the NAT-PMP request is used in
+    /// both cases (PCP servers are expected to support NAT-PMP as well).
+    fn get_external_ip(&self) -> Result<Ipv4Addr, Error> {
+        let response = self.request(self.gateway()?, &[NAT_PMP_VERSION, 0], 0)?;
+        self.check_result(u16::from_be_bytes([response[2], response[3]]))?;
+        if response.len() < 12 {
+            return Err(Error::NatPmp("truncated NAT-PMP response".to_owned()));
+        }
+        Ok(Ipv4Addr::new(response[8], response[9], response[10], response[11]))
+    }
+
+    /// Only PCP supports IPv6, with a `MAP` request for the IPv6 address of the node.
+    fn add_pinhole(&self, add_params: &AddPinholeEntry) -> Result<SocketAddrV6, Error> {
+        if self.method != MappingMethod::Pcp {
+            return Err(Error::PinholeUnsupported);
+        }
+        let gateway = self.gateway_v6.ok_or(Error::GatewayUnavailable(self.method))?;
+        let (external_ip, external_port) = self.pcp_map(
+            SocketAddr::V6(gateway),
+            add_params.protocol,
+            add_params.lease_duration,
+            SocketAddr::V6(add_params.local_address),
+            add_params.external_port,
+        )?;
+        Ok(SocketAddrV6::new(external_ip, external_port, 0, 0))
+    }
+}
+
+fn io_error(method: MappingMethod, error: std::io::Error) -> Error {
+    Error::NatPmp(format!("{}: {}", method, error))
+}
+
+fn nat_pmp_opcode(protocol: Protocol) -> u8 {
+    match protocol {
+        Protocol::UDP => 1,
+        Protocol::TCP => 2,
+    }
+}
+
+fn pcp_protocol_number(protocol: Protocol) -> u8 {
+    match protocol {
+        Protocol::TCP => 6,
+        Protocol::UDP => 17,
+    }
+}
+
+/// PCP carries IPv4 addresses as IPv4-mapped IPv6 addresses
+fn pcp_address(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+        IpAddr::V6(ip) => ip.octets(),
+    }
+}
+
+/// Builds a PCP `MAP` request, suggesting any external address of the family of `local_address`
+fn pcp_map_request(
+    protocol: Protocol,
+    lease_duration: Duration,
+    local_address: SocketAddr,
+    external_port: u16,
+    nonce: &[u8; 12],
+) -> Vec<u8> {
+    let suggested_ip = match local_address {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let mut request = vec![PCP_VERSION, PCP_OPCODE_MAP, 0, 0];
+    request.extend_from_slice(&(lease_duration.as_secs() as u32).to_be_bytes());
+    request.extend_from_slice(&pcp_address(local_address.ip()));
+    request.extend_from_slice(nonce);
+    request.extend_from_slice(&[pcp_protocol_number(protocol), 0, 0, 0]);
+    request.extend_from_slice(&local_address.port().to_be_bytes());
+    request.extend_from_slice(&external_port.to_be_bytes());
+    request.extend_from_slice(&pcp_address(suggested_ip));
+    request
+}
+
+/// Reads the IPv4 default gateway from the routing table (Linux only).
+fn default_gateway() -> Option<Ipv4Addr> {
+    let routes = std::fs::read_to_string("/proc/net/route").ok()?;
+    for line in routes.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() > 2 && fields[1] == "00000000" {
+            let gateway = u32::from_str_radix(fields[2], 16).ok()?;
+            return Some(Ipv4Addr::from(gateway.to_le_bytes()));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn test_pcp_falls_back_on_unsupported_version() {
+        // A NAT-PMP only gateway answers PCP requests with a short UNSUPP_VERSION response
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let gateway = server.local_addr().unwrap();
+        let responder = std::thread::spawn(move || {
+            let mut buf = [0u8; 1100];
+            let (_, client) = server.recv_from(&mut buf).unwrap();
+            let response = [NAT_PMP_VERSION, RESPONSE_BIT | PCP_OPCODE_MAP, 0, 1, 0, 0, 0, 0];
+            let _ = server.send_to(&response, client).unwrap();
+        });
+
+        let pcp = PmpGateway { method: MappingMethod::Pcp, gateway: None, gateway_v6: None };
+        let local_address: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        let result =
+            pcp.pcp_map(gateway, Protocol::TCP, Duration::from_secs(60), local_address, 1234);
+        assert!(matches!(result, Err(Error::GatewayUnavailable(MappingMethod::Pcp))));
+        responder.join().unwrap();
+    }
+}
@@ -1,11 +1,11 @@
 use std::{
-    net::{Ipv4Addr, SocketAddrV4},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
     time::Duration,
 };
 
 use igd::SearchOptions;
 
-use crate::porter::Protocol;
+use crate::porter::{MappingMethod, Protocol};
 
 /// Represents a port mapping result
 #[derive(Debug, Clone)]
@@ -20,6 +20,54 @@ pub struct PortMappingEntry {
     pub lease_duration: Duration,
     /// description of the mapping entry
     pub node_description: String,
+    /// The external address the port was mapped to
+    pub external_address: SocketAddrV4,
+    /// The method which mapped the port
+    pub method: MappingMethod,
+}
+
+/// Represents an IPv6 pinhole result
+#[derive(Debug, Clone)]
+pub struct PinholeEntry {
+    /// This address is where the traffic is sent to
+    pub local_address: SocketAddrV6,
+    /// The external port traffic will go through
+    pub external_port: u16,
+    /// Represents the protocols available for port mapping.
+    pub protocol: Protocol,
+    /// Duration the lease is aquired for
+    pub lease_duration: Duration,
+    /// description of the pinhole
+    pub node_description: String,
+    /// The external address the pinhole was opened at
+    pub external_address: SocketAddrV6,
+    /// The method which opened the pinhole
+    pub method: MappingMethod,
+}
+
+/// The result of mapping a port of either address family
+#[derive(Debug, Clone)]
+pub enum MappingEntry {
+    V4(PortMappingEntry),
+    V6(PinholeEntry),
+}
+
+impl MappingEntry {
+    /// The external address the port was mapped to
+    pub fn external_address(&self) -> SocketAddr {
+        match self {
+            MappingEntry::V4(entry) => SocketAddr::V4(entry.external_address),
+            MappingEntry::V6(entry) => SocketAddr::V6(entry.external_address),
+        }
+    }
+
+    /// The method which mapped the port
+    pub fn method(&self) -> MappingMethod {
+        match self {
+            MappingEntry::V4(entry) => entry.method,
+            MappingEntry::V6(entry) => entry.method,
+        }
+    }
 }
 
 /// Represents the parmeters required for port binding
@@ -55,6 +103,39 @@ impl AddMappingEntry {
     }
 }
 
+/// Represents the parameters required for opening an IPv6 pinhole
+#[derive(Debug, Clone)]
+pub struct AddPinholeEntry {
+    /// This address is where the traffic is sent to
+    pub local_address: SocketAddrV6,
+    /// The external port traffic will go through
+    pub external_port: u16,
+    /// Represents the protocols available for port mapping.
+    pub protocol: Protocol,
+    /// Duration the lease is aquired for
+    pub lease_duration: Duration,
+    /// description of the pinhole
+    pub node_description: String,
+}
+
+impl AddPinholeEntry {
+    pub fn new(
+        local_address: SocketAddrV6,
+        external_port: u16,
+        protocol: Protocol,
+        duration: Duration,
+        node_desc: String,
+    ) -> Self {
+        Self {
+            local_address,
+            external_port,
+            protocol,
+            lease_duration: duration,
+            node_description: node_desc,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RefreshMappingEntry {
     /// Port mapping is refresh every `mapping_update_interval`
@@ -77,6 +158,31 @@ impl RefreshMappingEntry {
     }
 }
 
+/// The port mapping methods which may be attempted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AllowedMethods {
+    pub upnp: bool,
+    pub pcp: bool,
+    pub nat_pmp: bool,
+}
+
+impl AllowedMethods {
+    /// Whether `method` may be attempted
+    pub fn allows(&self, method: MappingMethod) -> bool {
+        match method {
+            MappingMethod::Upnp => self.upnp,
+            MappingMethod::Pcp => self.pcp,
+            MappingMethod::NatPmp => self.nat_pmp,
+        }
+    }
+}
+
+impl Default for AllowedMethods {
+    fn default() -> Self {
+        Self { upnp: true, pcp: true, nat_pmp: true }
+    }
+}
+
 /// Contains the parameters for the IGD gateway search
 #[derive(Debug, Clone, Copy)]
 pub struct RouterConfig {
@@ -86,6 +192,14 @@ pub struct RouterConfig {
     pub broadcast_addr: SocketAddrV4,
     /// Timeout for gateway search
     pub search_timeout: Option<Duration>,
+    /// The mapping methods which may be attempted (defaults to all)
+    pub allowed_methods: AllowedMethods,
+    /// Address of the PCP / NAT-PMP gateway (defaults to the default route)
+    pub pmp_gateway: Option<Ipv4Addr>,
+    /// Address of the PCP gateway for IPv6 pinholes (no pinholes are opened if unset)
+    pub pcp_v6_gateway: Option<Ipv6Addr>,
+    /// Interval of polling the external IP for changes (defaults to 60 seconds)
+    pub external_ip_poll_interval: Duration,
 }
 
 impl RouterConfig {
@@ -98,6 +212,10 @@ impl RouterConfig {
             bind_addr: bind_addr,
             broadcast_addr: broadcast_addr,
             search_timeout: search_timeout,
+            allowed_methods: AllowedMethods::default(),
+            pmp_gateway: None,
+            pcp_v6_gateway: None,
+            external_ip_poll_interval: Duration::from_secs(60),
         }
     }
 }
@@ -108,6 +226,10 @@ impl Default for RouterConfig {
             bind_addr: SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0),
             broadcast_addr: "239.255.255.250:1900".parse().unwrap(),
             search_timeout: Some(Duration::from_secs(10)),
+            allowed_methods: AllowedMethods::default(),
+            pmp_gateway: None,
+            pcp_v6_gateway: None,
+            external_ip_poll_interval: Duration::from_secs(60),
         }
     }
 }
@@ -123,6 +245,6 @@ impl From<RouterConfig> for SearchOptions {
 }
 
 pub struct NetworkConfig {
-    pub local_address: SocketAddrV4,
+    pub local_address: SocketAddr,
     //pub external_address: SocketAddrV4,
 }
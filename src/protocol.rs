@@ -2,6 +2,7 @@
 use crate::alpha;
 use crate::hail;
 use crate::ice;
+use crate::server;
 use crate::sleet;
 use crate::version;
 
@@ -19,6 +20,7 @@ pub enum Request {
     GetNodeStatus,
     // State
     GetCellHashes,
+    /// The first page of [GetAcceptedCellHashesPage][Request::GetAcceptedCellHashesPage]
     GetAcceptedCellHashes,
     // Sleet
     GetCell(sleet::GetCell),
@@ -33,6 +35,44 @@ pub enum Request {
     GetBlock(hail::GetBlock),
     GetBlockByHeight(hail::GetBlockByHeight),
     QueryBlock(hail::QueryBlock),
+    GetAcceptedBlockFrontier,
+    FetchBlock(hail::FetchBlock),
+    // Sleet
+    GetUtxosByOwner(sleet::GetUtxosByOwner),
+    // Hail
+    GetChainTip,
+    GetBlocksByRange(hail::GetBlocksByRange),
+    // Chain Bootstrapping
+    GetReadiness,
+    // Sleet
+    GetMempoolStats,
+    GetCellHistory(sleet::GetCellHistory),
+    QueryTxBatch(sleet::QueryTxBatch),
+    GetConflictSet(sleet::GetConflictSet),
+    // State
+    GetAcceptedCellHashesPage(sleet::sleet_cell_handlers::GetAcceptedCellHashes),
+    // Sleet
+    ValidateTx(sleet::ValidateTx),
+}
+
+impl Request {
+    /// Whether the request takes part in consensus, and thus can't be served until [Ice][ice::Ice]
+    /// is bootstrapped. Other requests are read from storage, and are served while bootstrapping.
+    ///
+    /// Until then the node doesn't know which validators are live, so it can't sample them for
+    /// its own queries nor vote on theirs. The [Router][crate::server::Router] refuses these
+    /// requests with [Response::IceUninitialised]: a refused [QueryTx][Request::QueryTx] counts as
+    /// a missing vote, and clients retry a refused [GenerateTx][Request::GenerateTx] once the
+    /// node is [ready][Request::GetReadiness].
+    pub fn requires_live_consensus(&self) -> bool {
+        matches!(
+            self,
+            Request::GenerateTx(_)
+                | Request::QueryTx(_)
+                | Request::QueryTxBatch(_)
+                | Request::QueryBlock(_)
+        )
+    }
 }
 
 /// Response returned for the [Request], used in the [Router][crate::server::Router]
@@ -64,4 +104,28 @@ pub enum Response {
     Unknown,
     /// Refuse a validator-only request from a non-validator
     RequestRefused,
+    // Hail
+    AcceptedBlockFrontier(hail::AcceptedBlockFrontier),
+    FetchedBlock(hail::FetchedBlock),
+    // Sleet
+    UtxosAck(sleet::UtxosAck),
+    // Handshake
+    /// Refuse the handshake of a node with an unsupported version
+    VersionRefused(version::VersionRefused),
+    // Hail
+    ChainTipAck(hail::ChainTipAck),
+    BlocksAck(hail::BlocksAck),
+    // Chain Bootstrapping
+    Readiness(server::Readiness),
+    // Sleet
+    MempoolStats(sleet::MempoolStats),
+    CellHistory(sleet::CellHistory),
+    QueryTxBatchAck(sleet::QueryTxBatchAck),
+    ConflictSetAck(sleet::ConflictSetAck),
+    // Error
+    /// Refuse a request which [requires live consensus][Request::requires_live_consensus] while
+    /// [Ice][ice::Ice] is bootstrapping
+    IceUninitialised,
+    // Sleet
+    ValidateTxAck(sleet::ValidateTxAck),
 }
@@ -0,0 +1,596 @@
+//! Configuration of a node executable
+//!
+//! A [NodeConfig] holds the settings of a node, read from a TOML file passed with `--config`
+//! and from the command line, with the command line taking precedence. For example:
+//!
+//! ```toml
+//! listener_ip = "127.0.0.1:1234"
+//! bootstrap_peers = ["1tBz2oa6DCjDbVpC8KJijh7zw8gSvRLAiaUmXktmFAFS5fzXuU@127.0.0.1:1235"]
+//! keypair = "ad7f2ee3958a7f3fa2c84931770f5773ef7694fdd0bb217d90f29a94199c9d7307ca3851515c89344639fe6a4077923068d1d7fc6106701213c61d34ef8e9416"
+//! use_tls = true
+//! cert_path = "deployment/test-certs/node0.crt"
+//! pk_path = "deployment/test-certs/node0.key"
+//!
+//! [consensus]
+//! max_dag_width = 512
+//!
+//! [consensus.ice]
+//! k = 4
+//! ```
+//!
+//! The [ConsensusConfig] of the `[consensus]` table can only be set in the file.
+//!
+//! [NodeConfig::validate] checks the settings and turns them into the [NodeArgs] of [node::run].
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::alpha::genesis::GenesisConfig;
+use crate::cell::CellTypeRegistry;
+use crate::graph::conflict_graph::ConfidenceDecay;
+use crate::ice::IceConfig;
+use crate::server::node;
+use crate::server::rate_limiter::{QUERY_BURST, QUERY_RATE};
+use crate::server::{DEFAULT_LISTEN_BACKLOG, DEFAULT_MAX_CONNECTIONS};
+use crate::sleet::{
+    FRONTIER_BATCH_SIZE, FRONTIER_RECOMPUTE_INTERVAL_MS, NPARENTS, REBROADCAST_AGE_MS,
+    REBROADCAST_INTERVAL_MS,
+};
+use crate::storage::{DurabilityConfig, StorageConfig};
+use crate::util;
+use crate::zfx_id::Id;
+use crate::{Error, Result};
+use ed25519_dalek::Keypair;
+
+/// The settings of a node, as read from a configuration file or the command line
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    /// IP address and port of the node (ex. 127.0.0.1:1234)
+    pub listener_ip: Option<String>,
+    /// Peers used for bootstrapping, in the format <node_id>@<node_ip_address>
+    pub bootstrap_peers: Vec<String>,
+    /// A hex keypair for the node
+    pub keypair: Option<String>,
+    /// Whether to use TLS connections, requires `cert_path` and `pk_path`. Plain TCP is used
+    /// unless set
+    pub use_tls: Option<bool>,
+    /// Path to the TLS certificate of the node
+    pub cert_path: Option<String>,
+    /// Path to the TLS private key of the node
+    pub pk_path: Option<String>,
+    /// Id of the node
+    pub node_id: Option<String>,
+    /// Path to a TOML or JSON genesis configuration
+    pub genesis_file: Option<String>,
+    /// IP address and port of the HTTP gateway
+    pub http_gateway: Option<String>,
+    /// The maximum number of open inbound connections, see
+    /// [Server::with_max_connections][crate::server::Server::with_max_connections]
+    pub max_connections: Option<usize>,
+    /// The maximum number of pending connections in the listen queue
+    pub listen_backlog: Option<u32>,
+    /// Path to a snapshot of the chain, restored on startup and saved when shutting down
+    pub snapshot_path: Option<String>,
+    /// Directory of the databases and the keypair of the node, kept in a subdirectory named
+    /// after the node id. The temporary directory of the system is used if unset
+    pub data_dir: Option<String>,
+    /// The limits of the consensus components, which keep their defaults if unset
+    pub consensus: ConsensusConfig,
+    /// The durability of the stores, which rely on the flushing of their backend if unset
+    pub storage: StorageSettings,
+}
+
+/// The settings of the `[storage]` table, turned into a [StorageConfig] by
+/// [NodeConfig::validate]
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageSettings {
+    /// Flush the accepted blocks after every `n` writes, `1` flushing every write
+    pub flush_every_n: Option<u64>,
+    /// Flush the accepted blocks on a write if the last flush is older than the interval
+    pub flush_interval_ms: Option<u64>,
+}
+
+impl StorageSettings {
+    /// Returns the settings with those present in `overrides` replacing ours.
+    pub fn override_with(self, overrides: StorageSettings) -> StorageSettings {
+        StorageSettings {
+            flush_every_n: overrides.flush_every_n.or(self.flush_every_n),
+            flush_interval_ms: overrides.flush_interval_ms.or(self.flush_interval_ms),
+        }
+    }
+
+    /// Checks that at most one flushing policy is set, and returns the [StorageConfig] with it.
+    fn validate(&self) -> Result<StorageConfig> {
+        let invalid = |msg: &str| Err(Error::InvalidNodeConfig(msg.to_owned()));
+        let durability = match (self.flush_every_n, self.flush_interval_ms) {
+            (None, None) => DurabilityConfig::Backend,
+            (Some(0), None) => return invalid("flush_every_n must be positive"),
+            (Some(1), None) => DurabilityConfig::FlushEveryWrite,
+            (Some(n), None) => DurabilityConfig::FlushEveryN(n),
+            (None, Some(ms)) => DurabilityConfig::FlushInterval(Duration::from_millis(ms)),
+            (Some(_), Some(_)) => {
+                return invalid("flush_every_n and flush_interval_ms are exclusive")
+            }
+        };
+        Ok(StorageConfig { durability, ..Default::default() })
+    }
+}
+
+/// The limits of the consensus components of a node
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsensusConfig {
+    /// The soft cap on the number of leaves of the DAG, see
+    /// [Sleet::with_max_dag_width][crate::sleet::Sleet::with_max_dag_width]
+    pub max_dag_width: Option<usize>,
+    /// The settings of [Ice][crate::ice::Ice], see [IceConfig]. Unset fields of the
+    /// `[consensus.ice]` table keep their defaults
+    pub ice: Option<IceConfig>,
+    /// The time after which the confidence of a quiet conflict set of
+    /// [Sleet][crate::sleet::Sleet] starts decaying, see [ConfidenceDecay]. No confidence
+    /// decays if unset
+    pub confidence_decay_window_ms: Option<u64>,
+    /// The confidence below which conflict sets don't decay, `0` if unset
+    pub confidence_decay_floor: Option<u8>,
+    /// The number of live cells and transactions [Sleet][crate::sleet::Sleet] keeps in memory,
+    /// see [Sleet::with_mempool_capacity][crate::sleet::Sleet::with_mempool_capacity]
+    pub mempool_capacity: Option<usize>,
+    /// The number of blocks [Hail][crate::hail::Hail] serves per request, see
+    /// [Hail::with_max_blocks_range][crate::hail::Hail::with_max_blocks_range]
+    pub max_blocks_range: Option<u64>,
+    /// The number of queries a peer may send at once, [QUERY_BURST] if unset
+    pub query_burst: Option<u32>,
+    /// The number of queries a peer may send per second, [QUERY_RATE] if unset
+    pub query_rate: Option<u32>,
+    /// The number of parents above which [Sleet][crate::sleet::Sleet] refuses transactions, see
+    /// [Sleet::with_max_parents][crate::sleet::Sleet::with_max_parents]
+    pub max_parents: Option<usize>,
+    /// The number of accepted transactions after which [Sleet][crate::sleet::Sleet] recomputes
+    /// its accepted frontier, [FRONTIER_BATCH_SIZE] if unset
+    pub frontier_batch_size: Option<usize>,
+    /// The time after an acceptance at which the accepted frontier is recomputed at the latest,
+    /// [FRONTIER_RECOMPUTE_INTERVAL_MS] if unset
+    pub frontier_interval_ms: Option<u64>,
+    /// The interval at which [Sleet][crate::sleet::Sleet] looks for transactions to
+    /// re-broadcast, [REBROADCAST_INTERVAL_MS] if unset
+    pub rebroadcast_interval_ms: Option<u64>,
+    /// The time without a complete query after which a transaction is re-broadcast,
+    /// [REBROADCAST_AGE_MS] if unset
+    pub rebroadcast_age_ms: Option<u64>,
+}
+
+impl ConsensusConfig {
+    /// Returns the configuration with the settings present in `overrides` replacing ours.
+    pub fn override_with(self, overrides: ConsensusConfig) -> ConsensusConfig {
+        ConsensusConfig {
+            max_dag_width: overrides.max_dag_width.or(self.max_dag_width),
+            ice: overrides.ice.or(self.ice),
+            confidence_decay_window_ms: overrides
+                .confidence_decay_window_ms
+                .or(self.confidence_decay_window_ms),
+            confidence_decay_floor: overrides
+                .confidence_decay_floor
+                .or(self.confidence_decay_floor),
+            mempool_capacity: overrides.mempool_capacity.or(self.mempool_capacity),
+            max_blocks_range: overrides.max_blocks_range.or(self.max_blocks_range),
+            query_burst: overrides.query_burst.or(self.query_burst),
+            query_rate: overrides.query_rate.or(self.query_rate),
+            max_parents: overrides.max_parents.or(self.max_parents),
+            frontier_batch_size: overrides.frontier_batch_size.or(self.frontier_batch_size),
+            frontier_interval_ms: overrides.frontier_interval_ms.or(self.frontier_interval_ms),
+            rebroadcast_interval_ms: overrides
+                .rebroadcast_interval_ms
+                .or(self.rebroadcast_interval_ms),
+            rebroadcast_age_ms: overrides.rebroadcast_age_ms.or(self.rebroadcast_age_ms),
+        }
+    }
+
+    /// The re-broadcasting of the stale transactions as `(interval, age)`, if either of them is
+    /// set
+    pub fn rebroadcast(&self) -> Option<(Duration, Duration)> {
+        if self.rebroadcast_interval_ms.is_none() && self.rebroadcast_age_ms.is_none() {
+            return None;
+        }
+        let interval = self.rebroadcast_interval_ms.unwrap_or(REBROADCAST_INTERVAL_MS);
+        let age = self.rebroadcast_age_ms.unwrap_or(REBROADCAST_AGE_MS);
+        Some((Duration::from_millis(interval), Duration::from_millis(age)))
+    }
+
+    /// The batching of the accepted frontier recomputations as `(batch_size, interval)`, if
+    /// either of them is set
+    pub fn frontier_batching(&self) -> Option<(usize, Duration)> {
+        if self.frontier_batch_size.is_none() && self.frontier_interval_ms.is_none() {
+            return None;
+        }
+        let interval = self.frontier_interval_ms.unwrap_or(FRONTIER_RECOMPUTE_INTERVAL_MS);
+        Some((
+            self.frontier_batch_size.unwrap_or(FRONTIER_BATCH_SIZE),
+            Duration::from_millis(interval),
+        ))
+    }
+
+    /// The query rate limit of the [Router][crate::server::Router] as `(burst, rate)`, if either
+    /// of them is set
+    pub fn query_rate_limit(&self) -> Option<(u32, u32)> {
+        if self.query_burst.is_none() && self.query_rate.is_none() {
+            return None;
+        }
+        Some((self.query_burst.unwrap_or(QUERY_BURST), self.query_rate.unwrap_or(QUERY_RATE)))
+    }
+
+    /// The confidence decay of the conflict sets, if enabled
+    pub fn confidence_decay(&self) -> Option<ConfidenceDecay> {
+        self.confidence_decay_window_ms.map(|window| ConfidenceDecay {
+            window: Duration::from_millis(window),
+            floor: self.confidence_decay_floor.unwrap_or(0),
+        })
+    }
+
+    /// Checks that the limits are positive, and that the `alpha` of [Ice][crate::ice::Ice] is a
+    /// share of its sample.
+    fn validate(&self) -> Result<()> {
+        let invalid = |msg: &str| Err(Error::InvalidNodeConfig(msg.to_owned()));
+        if self.max_dag_width == Some(0) {
+            return invalid("max_dag_width must be positive");
+        }
+        if self.mempool_capacity == Some(0) {
+            return invalid("mempool_capacity must be positive");
+        }
+        if self.max_blocks_range == Some(0) {
+            return invalid("max_blocks_range must be positive");
+        }
+        if self.query_burst == Some(0) || self.query_rate == Some(0) {
+            return invalid("query_burst and query_rate must be positive");
+        }
+        if matches!(self.max_parents, Some(max_parents) if max_parents < NPARENTS) {
+            return invalid("max_parents must not be below the parents of a generated transaction");
+        }
+        if self.frontier_batch_size == Some(0) {
+            return invalid("frontier_batch_size must be positive");
+        }
+        if self.rebroadcast_interval_ms == Some(0) {
+            return invalid("rebroadcast_interval_ms must be positive");
+        }
+        if self.confidence_decay_floor.is_some() && self.confidence_decay_window_ms.is_none() {
+            return invalid("confidence_decay_floor requires confidence_decay_window_ms");
+        }
+        if let Some(ice) = self.ice.as_ref() {
+            if ice.reservoir_capacity == 0 || ice.k == 0 || ice.beta1 == 0 {
+                return invalid("reservoir_capacity, k and beta1 must be positive");
+            }
+            if !(ice.alpha > 0.0 && ice.alpha <= 1.0) {
+                return invalid("alpha must be in (0, 1]");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The validated arguments of [node::run]
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeArgs {
+    pub ip: String,
+    pub bootstrap_peers: Vec<String>,
+    pub keypair: String,
+    pub use_tls: bool,
+    pub cert_path: Option<String>,
+    pub pk_path: Option<String>,
+    pub node_id: Option<Id>,
+    pub genesis_config: Option<GenesisConfig>,
+    pub gateway_ip: Option<SocketAddr>,
+    pub max_connections: usize,
+    pub listen_backlog: u32,
+    pub snapshot_path: Option<String>,
+    pub data_dir: PathBuf,
+    pub consensus: ConsensusConfig,
+    pub storage: StorageConfig,
+}
+
+impl NodeConfig {
+    /// Reads a node configuration from a TOML file.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| Error::InvalidNodeConfig(format!("{}: {}", path.display(), e)))?;
+        NodeConfig::from_toml(&contents)
+    }
+
+    /// Parses a TOML node configuration.
+    pub fn from_toml(s: &str) -> Result<Self> {
+        toml::from_str(s).map_err(|e| Error::InvalidNodeConfig(format!("{}", e)))
+    }
+
+    /// Returns the configuration with the settings present in `overrides` replacing ours.
+    pub fn override_with(self, overrides: NodeConfig) -> NodeConfig {
+        NodeConfig {
+            listener_ip: overrides.listener_ip.or(self.listener_ip),
+            bootstrap_peers: if overrides.bootstrap_peers.is_empty() {
+                self.bootstrap_peers
+            } else {
+                overrides.bootstrap_peers
+            },
+            keypair: overrides.keypair.or(self.keypair),
+            use_tls: overrides.use_tls.or(self.use_tls),
+            cert_path: overrides.cert_path.or(self.cert_path),
+            pk_path: overrides.pk_path.or(self.pk_path),
+            node_id: overrides.node_id.or(self.node_id),
+            genesis_file: overrides.genesis_file.or(self.genesis_file),
+            http_gateway: overrides.http_gateway.or(self.http_gateway),
+            max_connections: overrides.max_connections.or(self.max_connections),
+            listen_backlog: overrides.listen_backlog.or(self.listen_backlog),
+            snapshot_path: overrides.snapshot_path.or(self.snapshot_path),
+            data_dir: overrides.data_dir.or(self.data_dir),
+            consensus: self.consensus.override_with(overrides.consensus),
+            storage: self.storage.override_with(overrides.storage),
+        }
+    }
+
+    /// Checks that the settings are complete and well-formed, and loads the genesis file if any.
+    pub fn validate(self) -> Result<NodeArgs> {
+        let invalid = |msg: String| Error::InvalidNodeConfig(msg);
+
+        let ip = self.listener_ip.ok_or_else(|| invalid("missing listener_ip".to_owned()))?;
+        let _ = ip
+            .to_socket_addrs()
+            .map_err(|e| invalid(format!("invalid listener_ip {}: {}", ip, e)))?;
+        for peer in self.bootstrap_peers.iter() {
+            let _ = util::parse_id_and_ip(peer)
+                .map_err(|_| invalid(format!("invalid bootstrap peer {}", peer)))?;
+        }
+        let keypair = self.keypair.ok_or_else(|| invalid("missing keypair".to_owned()))?;
+        let _ = hex::decode(&keypair)
+            .ok()
+            .and_then(|bytes| Keypair::from_bytes(&bytes).ok())
+            .ok_or_else(|| invalid("invalid keypair".to_owned()))?;
+        let use_tls = self.use_tls.unwrap_or(false);
+        if use_tls {
+            if self.cert_path.is_none() {
+                return Err(invalid("use_tls requires cert_path".to_owned()));
+            }
+            if self.pk_path.is_none() {
+                return Err(invalid("use_tls requires pk_path".to_owned()));
+            }
+        } else if self.cert_path.is_some() || self.pk_path.is_some() {
+            return Err(invalid("cert_path and pk_path require use_tls".to_owned()));
+        }
+        let node_id = match self.node_id {
+            Some(id) => {
+                Some(Id::from_str(&id).map_err(|_| invalid(format!("invalid node_id {}", id)))?)
+            }
+            None => None,
+        };
+        let genesis_config = match self.genesis_file {
+            Some(path) => Some(
+                GenesisConfig::from_file(Path::new(&path))
+                    .map_err(|e| invalid(format!("invalid genesis_file: {:?}", e)))?,
+            ),
+            None => None,
+        };
+        let gateway_ip = match self.http_gateway {
+            Some(ip) => Some(
+                ip.parse::<SocketAddr>()
+                    .map_err(|e| invalid(format!("invalid http_gateway {}: {}", ip, e)))?,
+            ),
+            None => None,
+        };
+        let max_connections = self.max_connections.unwrap_or(DEFAULT_MAX_CONNECTIONS);
+        if max_connections == 0 {
+            return Err(invalid("max_connections must be positive".to_owned()));
+        }
+        let () = self.consensus.validate()?;
+        let storage = self.storage.validate()?;
+        Ok(NodeArgs {
+            ip,
+            bootstrap_peers: self.bootstrap_peers,
+            keypair,
+            use_tls,
+            cert_path: self.cert_path,
+            pk_path: self.pk_path,
+            node_id,
+            genesis_config,
+            gateway_ip,
+            max_connections,
+            listen_backlog: self.listen_backlog.unwrap_or(DEFAULT_LISTEN_BACKLOG),
+            snapshot_path: self.snapshot_path,
+            data_dir: self.data_dir.map(PathBuf::from).unwrap_or_else(std::env::temp_dir),
+            consensus: self.consensus,
+            storage,
+        })
+    }
+}
+
+impl NodeArgs {
+    /// Runs the node, see [node::run].
+    pub fn run(self) -> Result<node::Node> {
+        self.run_with_cell_types(CellTypeRegistry::new())
+    }
+
+    /// Runs the node, validating the custom outputs of client chains with `cell_types`.
+    pub fn run_with_cell_types(self, cell_types: CellTypeRegistry) -> Result<node::Node> {
+        node::run(
+            self.ip,
+            self.bootstrap_peers,
+            Some(self.keypair),
+            self.use_tls,
+            self.cert_path,
+            self.pk_path,
+            self.node_id,
+            self.genesis_config,
+            self.gateway_ip,
+            self.max_connections,
+            self.listen_backlog,
+            self.snapshot_path,
+            self.data_dir,
+            self.consensus,
+            self.storage,
+            cell_types,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEYPAIR: &str = "ad7f2ee3958a7f3fa2c84931770f5773ef7694fdd0bb217d90f29a94199c9d7307ca3851515c89344639fe6a4077923068d1d7fc6106701213c61d34ef8e9416";
+    const PEER: &str = "1tBz2oa6DCjDbVpC8KJijh7zw8gSvRLAiaUmXktmFAFS5fzXuU@127.0.0.1:1235";
+
+    fn sample_config() -> String {
+        format!(
+            r#"
+            listener_ip = "127.0.0.1:1234"
+            bootstrap_peers = ["{}"]
+            keypair = "{}"
+            use_tls = true
+            cert_path = "deployment/test-certs/node0.crt"
+            pk_path = "deployment/test-certs/node0.key"
+            node_id = "19oHWYAEdSALT6pFZaxzNPj9bEfJFrJfxrU9pZp622FCV35tzy"
+            http_gateway = "127.0.0.1:8080"
+            max_connections = 256
+            snapshot_path = "/var/lib/zfx/snapshot"
+            data_dir = "/var/lib/zfx"
+
+            [consensus]
+            max_dag_width = 512
+            confidence_decay_window_ms = 30000
+            mempool_capacity = 5000
+            max_blocks_range = 50
+            query_rate = 200
+            max_parents = 8
+            frontier_batch_size = 16
+            rebroadcast_age_ms = 20000
+
+            [consensus.ice]
+            k = 4
+
+            [storage]
+            flush_every_n = 10
+            "#,
+            PEER, KEYPAIR
+        )
+    }
+
+    #[actix_rt::test]
+    async fn test_config_from_file() {
+        let path = std::env::temp_dir().join(format!("node-config-{}.toml", Id::generate()));
+        std::fs::write(&path, sample_config()).unwrap();
+        let config = NodeConfig::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let args = config.validate().unwrap();
+        let expected = NodeArgs {
+            ip: "127.0.0.1:1234".to_owned(),
+            bootstrap_peers: vec![PEER.to_owned()],
+            keypair: KEYPAIR.to_owned(),
+            use_tls: true,
+            cert_path: Some("deployment/test-certs/node0.crt".to_owned()),
+            pk_path: Some("deployment/test-certs/node0.key".to_owned()),
+            node_id: Some(
+                Id::from_str("19oHWYAEdSALT6pFZaxzNPj9bEfJFrJfxrU9pZp622FCV35tzy").unwrap(),
+            ),
+            genesis_config: None,
+            gateway_ip: Some("127.0.0.1:8080".parse().unwrap()),
+            max_connections: 256,
+            listen_backlog: DEFAULT_LISTEN_BACKLOG,
+            snapshot_path: Some("/var/lib/zfx/snapshot".to_owned()),
+            data_dir: PathBuf::from("/var/lib/zfx"),
+            consensus: ConsensusConfig {
+                max_dag_width: Some(512),
+                ice: Some(IceConfig { k: 4, ..Default::default() }),
+                confidence_decay_window_ms: Some(30000),
+                mempool_capacity: Some(5000),
+                max_blocks_range: Some(50),
+                query_rate: Some(200),
+                max_parents: Some(8),
+                frontier_batch_size: Some(16),
+                rebroadcast_age_ms: Some(20000),
+                ..Default::default()
+            },
+            storage: StorageConfig {
+                durability: DurabilityConfig::FlushEveryN(10),
+                ..Default::default()
+            },
+        };
+        assert_eq!(args, expected);
+    }
+
+    #[actix_rt::test]
+    async fn test_cli_overrides_config() {
+        let config = NodeConfig::from_toml(&sample_config()).unwrap();
+        let cli =
+            NodeConfig { listener_ip: Some("127.0.0.1:4321".to_owned()), ..Default::default() };
+        let args = config.override_with(cli).validate().unwrap();
+        assert_eq!(args.ip, "127.0.0.1:4321");
+        assert_eq!(args.bootstrap_peers, vec![PEER.to_owned()]);
+        assert!(args.use_tls);
+    }
+
+    #[actix_rt::test]
+    async fn test_config_without_keypair() {
+        let config =
+            NodeConfig { listener_ip: Some("127.0.0.1:1234".to_owned()), ..Default::default() };
+        assert!(matches!(config.validate(), Err(Error::InvalidNodeConfig(_))));
+
+        let config = NodeConfig {
+            listener_ip: Some("127.0.0.1:1234".to_owned()),
+            keypair: Some("00".to_owned()),
+            ..Default::default()
+        };
+        assert!(matches!(config.validate(), Err(Error::InvalidNodeConfig(_))));
+    }
+
+    #[actix_rt::test]
+    async fn test_invalid_config() {
+        let missing_cert = NodeConfig {
+            listener_ip: Some("127.0.0.1:1234".to_owned()),
+            keypair: Some(KEYPAIR.to_owned()),
+            use_tls: Some(true),
+            pk_path: Some("deployment/test-certs/node0.key".to_owned()),
+            ..Default::default()
+        };
+        assert!(matches!(missing_cert.validate(), Err(Error::InvalidNodeConfig(_))));
+
+        let missing_ip = NodeConfig { keypair: Some(KEYPAIR.to_owned()), ..Default::default() };
+        assert!(matches!(missing_ip.validate(), Err(Error::InvalidNodeConfig(_))));
+
+        let zero_width = NodeConfig {
+            listener_ip: Some("127.0.0.1:1234".to_owned()),
+            keypair: Some(KEYPAIR.to_owned()),
+            consensus: ConsensusConfig { max_dag_width: Some(0), ..Default::default() },
+            ..Default::default()
+        };
+        assert!(matches!(zero_width.validate(), Err(Error::InvalidNodeConfig(_))));
+
+        let ice = IceConfig { alpha: 1.5, ..Default::default() };
+        let invalid_alpha = NodeConfig {
+            listener_ip: Some("127.0.0.1:1234".to_owned()),
+            keypair: Some(KEYPAIR.to_owned()),
+            consensus: ConsensusConfig { ice: Some(ice), ..Default::default() },
+            ..Default::default()
+        };
+        assert!(matches!(invalid_alpha.validate(), Err(Error::InvalidNodeConfig(_))));
+
+        let floor_without_window = NodeConfig {
+            listener_ip: Some("127.0.0.1:1234".to_owned()),
+            keypair: Some(KEYPAIR.to_owned()),
+            consensus: ConsensusConfig { confidence_decay_floor: Some(2), ..Default::default() },
+            ..Default::default()
+        };
+        assert!(matches!(floor_without_window.validate(), Err(Error::InvalidNodeConfig(_))));
+
+        let two_flush_policies = NodeConfig {
+            listener_ip: Some("127.0.0.1:1234".to_owned()),
+            keypair: Some(KEYPAIR.to_owned()),
+            storage: StorageSettings { flush_every_n: Some(10), flush_interval_ms: Some(500) },
+            ..Default::default()
+        };
+        assert!(matches!(two_flush_policies.validate(), Err(Error::InvalidNodeConfig(_))));
+
+        assert!(matches!(
+            NodeConfig::from_toml("listener_ip = 1234"),
+            Err(Error::InvalidNodeConfig(_))
+        ));
+    }
+}
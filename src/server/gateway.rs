@@ -0,0 +1,135 @@
+//! Read-only HTTP gateway serving the state of a node as JSON (feature `http_gateway`)
+//!
+//! The gateway exposes the following endpoints, dispatching to the same actors as the
+//! [Router][super::Router]:
+//! * `GET /status` - the [NodeStatus][crate::alpha::status_handler::NodeStatus]
+//! * `GET /cell/{hash}` - the [Cell][crate::cell::Cell] with the hex encoded `hash`
+//! * `GET /block/{height}` - the [Block][crate::alpha::block::Block] at `height`
+//! * `GET /utxos/{owner}` - the unspent outputs of the hex encoded public key hash `owner`
+//! * `GET /metrics` - the [metrics][crate::metrics] of the node in the Prometheus text format
+//!
+//! Missing cells and blocks are answered with `404 Not Found`, malformed parameters with
+//! `400 Bad Request`.
+use crate::alpha::{self, Alpha};
+use crate::cell::types::{Capacity, CellHash, PublicKeyHash};
+use crate::hail::{self, Hail};
+use crate::metrics::METRICS;
+use crate::sleet::{self, Sleet};
+use crate::{Error, Result};
+
+use actix::Addr;
+use actix_web::{web, App, HttpResponse, HttpServer};
+use tracing::info;
+
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+
+/// An unspent output in the response of `GET /utxos/{owner}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Utxo {
+    #[serde(with = "crate::json::hex_bytes")]
+    pub cell_hash: CellHash,
+    pub index: u8,
+    pub capacity: Capacity,
+}
+
+/// The HTTP gateway of a node
+pub struct Gateway {
+    /// The ip address and port which the gateway binds to.
+    ip: SocketAddr,
+    alpha: Addr<Alpha>,
+    sleet: Addr<Sleet>,
+    hail: Addr<Hail>,
+}
+
+/// The actors queried by the gateway, shared by the handlers
+#[derive(Clone)]
+struct Actors {
+    alpha: Addr<Alpha>,
+    sleet: Addr<Sleet>,
+    hail: Addr<Hail>,
+}
+
+impl Gateway {
+    pub fn new(ip: SocketAddr, alpha: Addr<Alpha>, sleet: Addr<Sleet>, hail: Addr<Hail>) -> Self {
+        Gateway { ip, alpha, sleet, hail }
+    }
+
+    /// Serves the endpoints of the gateway until the server is stopped.
+    pub async fn listen(&self) -> Result<()> {
+        let actors = Actors {
+            alpha: self.alpha.clone(),
+            sleet: self.sleet.clone(),
+            hail: self.hail.clone(),
+        };
+        info!("HTTP gateway listening on {:?}", self.ip);
+        HttpServer::new(move || {
+            App::new()
+                .app_data(web::Data::new(actors.clone()))
+                .route("/status", web::get().to(get_status))
+                .route("/cell/{hash}", web::get().to(get_cell))
+                .route("/block/{height}", web::get().to(get_block))
+                .route("/utxos/{owner}", web::get().to(get_utxos))
+                .route("/metrics", web::get().to(get_metrics))
+        })
+        .bind(self.ip)?
+        .run()
+        .await
+        .map_err(Error::IO)
+    }
+}
+
+async fn get_status(actors: web::Data<Actors>) -> HttpResponse {
+    match actors.alpha.send(alpha::status_handler::GetNodeStatus).await {
+        Ok(Ok(status)) => HttpResponse::Ok().json(status),
+        _ => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+async fn get_cell(actors: web::Data<Actors>, hash: web::Path<String>) -> HttpResponse {
+    let cell_hash = match parse_hash(&hash) {
+        Some(cell_hash) => cell_hash,
+        None => return HttpResponse::BadRequest().body("invalid cell hash"),
+    };
+    match actors.sleet.send(sleet::GetCell { cell_hash }).await {
+        Ok(sleet::CellAck { cell: Some(cell) }) => HttpResponse::Ok().json(cell),
+        Ok(sleet::CellAck { cell: None }) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+async fn get_block(actors: web::Data<Actors>, height: web::Path<u64>) -> HttpResponse {
+    let block_height = height.into_inner();
+    match actors.hail.send(hail::GetBlockByHeight { block_height }).await {
+        Ok(hail::BlockAck { block: Some(block) }) => HttpResponse::Ok().json(block),
+        Ok(hail::BlockAck { block: None }) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+async fn get_utxos(actors: web::Data<Actors>, owner: web::Path<String>) -> HttpResponse {
+    let owner: PublicKeyHash = match parse_hash(&owner) {
+        Some(owner) => owner,
+        None => return HttpResponse::BadRequest().body("invalid owner"),
+    };
+    match actors.sleet.send(sleet::GetUtxosByOwner { owner }).await {
+        Ok(sleet::UtxosAck { utxos }) => {
+            let utxos: Vec<Utxo> = utxos
+                .into_iter()
+                .map(|(cell_hash, index, capacity)| Utxo { cell_hash, index, capacity })
+                .collect();
+            HttpResponse::Ok().json(utxos)
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+async fn get_metrics() -> HttpResponse {
+    HttpResponse::Ok().content_type("text/plain; version=0.0.4").body(METRICS.render())
+}
+
+/// Parses a hex encoded 32 byte hash
+fn parse_hash(hex_hash: &str) -> Option<[u8; 32]> {
+    let bytes = hex::decode(hex_hash).ok()?;
+    <[u8; 32]>::try_from(bytes).ok()
+}
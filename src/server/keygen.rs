@@ -0,0 +1,110 @@
+//! Generation of fresh node identities, used by the `keygen` subcommand of the node executable
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::tls::certificate;
+use crate::zfx_id::{Id, ID_HRP};
+use ed25519_dalek::Keypair;
+use rand::rngs::OsRng;
+
+/// The name of the generated TLS certificate in the output directory
+pub const CERT_FILE: &str = "node.crt";
+/// The name of the generated TLS private key in the output directory
+pub const PRIV_KEY_FILE: &str = "node.key";
+
+/// A freshly generated node identity
+pub struct NodeKeys {
+    /// The ED25519 keypair of the node
+    pub keypair: Keypair,
+    /// The [Id] derived from the public key of `keypair`
+    pub id: Id,
+    /// Paths of the TLS certificate and private key, if they were written
+    pub tls_paths: Option<(PathBuf, PathBuf)>,
+}
+
+impl NodeKeys {
+    /// The keypair in the hex format expected by the `--keypair` flag
+    pub fn keypair_hex(&self) -> String {
+        hex::encode(self.keypair.to_bytes())
+    }
+}
+
+/// Prints the identity one `name: value` pair per line
+impl fmt::Display for NodeKeys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "keypair: {}", self.keypair_hex())?;
+        writeln!(f, "id: {}", self.id.to_bech32(ID_HRP))?;
+        if let Some((cert_path, pk_path)) = &self.tls_paths {
+            writeln!(f, "cert-path: {}", cert_path.display())?;
+            writeln!(f, "priv-key-path: {}", pk_path.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Generates an ED25519 keypair and derives the node [Id] from it.
+///
+/// If `out_dir` is given, a self-signed TLS certificate and private key for the same keypair
+/// are written there, to be used with the `--cert-path` and `--priv-key-path` flags.
+pub fn generate(out_dir: Option<&Path>) -> certificate::Result<NodeKeys> {
+    let mut csprng = OsRng {};
+    let keypair = Keypair::generate(&mut csprng);
+    let id = Id::from_public_key(&keypair.public);
+    let tls_paths = match out_dir {
+        Some(out_dir) => {
+            let (cert, key) = certificate::generate_node_cert_from_keypair(&keypair)?;
+            let cert_path = out_dir.join(CERT_FILE);
+            let pk_path = out_dir.join(PRIV_KEY_FILE);
+            certificate::write_node_cert(&cert, &key, &cert_path, &pk_path)?;
+            Some((cert_path, pk_path))
+        }
+        None => None,
+    };
+    Ok(NodeKeys { keypair, id, tls_paths })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    fn parse_output(output: &str) -> HashMap<String, String> {
+        output
+            .lines()
+            .map(|line| {
+                let (name, value) = line.split_once(": ").unwrap();
+                (name.to_owned(), value.to_owned())
+            })
+            .collect()
+    }
+
+    #[actix_rt::test]
+    async fn test_keygen() {
+        let keys = generate(None).unwrap();
+        let output = parse_output(&format!("{}", keys));
+        assert_eq!(output.len(), 2);
+
+        let id = Id::from_str(&output["id"]).unwrap();
+        let keypair = Keypair::from_bytes(&hex::decode(&output["keypair"]).unwrap()).unwrap();
+        assert_eq!(id, keys.id);
+        assert_eq!(id, Id::from_public_key(&keypair.public));
+    }
+
+    #[actix_rt::test]
+    async fn test_keygen_with_tls() {
+        let out_dir = std::env::temp_dir().join(format!("keygen-{}", Id::generate()));
+        let keys = generate(Some(&out_dir)).unwrap();
+        let output = parse_output(&format!("{}", keys));
+        assert_eq!(output.len(), 4);
+
+        let id = Id::from_str(&output["id"]).unwrap();
+        let (cert, _key) = certificate::load_node_cert(
+            Path::new(&output["cert-path"]),
+            Path::new(&output["priv-key-path"]),
+        )
+        .unwrap();
+        assert_eq!(certificate::id_from_cert(&cert).unwrap(), id);
+        std::fs::remove_dir_all(out_dir).unwrap();
+    }
+}
@@ -1,5 +1,10 @@
 //! Server-side code
+pub mod config;
+#[cfg(feature = "http_gateway")]
+pub mod gateway;
+pub mod keygen;
 pub mod node;
+pub mod rate_limiter;
 mod router;
 mod server;
 
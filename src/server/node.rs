@@ -1,32 +1,95 @@
 //! [`run`] starts a node executable
+use std::collections::HashSet;
 use std::io::{BufReader, Read, Write};
-use std::net::{SocketAddr, ToSocketAddrs};
-use std::path::Path;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::alpha::Alpha;
-use crate::client::Client;
-use crate::hail::Hail;
+use crate::alpha::block::build_genesis;
+use crate::alpha::genesis::GenesisConfig;
+use crate::alpha::types::BlockHeight;
+use crate::alpha::{self, Alpha};
+use crate::cell::types::DEFAULT_CHAIN_ID;
+use crate::cell::CellTypeRegistry;
+use crate::client::{Client, HeartbeatConfig, SubscribeUnreachable};
+use crate::hail::{self, Hail, SubscribeAcceptedHeight};
 use crate::ice::dissemination::DisseminationComponent;
 use crate::ice::{self, Ice, Reservoir};
-use crate::server::{Router, Server};
-use crate::sleet::Sleet;
+use crate::server::config::ConsensusConfig;
+use crate::server::{PreferredIps, Router, Server};
+use crate::sleet::{self, Sleet};
+use crate::storage::{peer as peer_storage, SledStore, StorageConfig};
 use crate::tls;
 use crate::util;
-use crate::view::{self, View};
+use crate::version::Capabilities;
+use crate::view::{self, SubscribeReputations, View};
 use crate::zfx_id::Id;
 use crate::{Error, Result};
-use actix::{Actor, Arbiter};
+use actix::{Actor, Addr, Arbiter};
 use ed25519_dalek::Keypair;
 use rand::rngs::OsRng;
-use tracing::info;
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+/// The maximum time to wait for the in-flight consensus queries when shutting down,
+/// longer than the timeout of the queries themselves
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The interval of setting a checkpoint of [Hail], see [run_checkpoints]
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The number of accepted blocks kept above the checkpoint of [Hail]
+pub const CHECKPOINT_DEPTH: BlockHeight = 100;
+
+/// A handle to a node started by [run], used to shut it down gracefully
+pub struct Node {
+    /// The consensus actors and [Alpha], sent once they are started
+    consensus: oneshot::Receiver<(Addr<Ice>, Addr<Sleet>, Addr<Hail>, Addr<Alpha>)>,
+}
+
+impl Node {
+    /// Stops [Sleet] and [Hail] from accepting new transactions and blocks, waits up to `timeout`
+    /// for their in-flight queries to settle and stops them, flushing the finalized blocks of
+    /// [Hail] (the other consensus databases are temporary). Then exports the snapshot of
+    /// [Alpha] if configured, and stops [Ice]. The actor system can be stopped afterwards.
+    pub async fn shutdown(self, timeout: Duration) {
+        let (ice, sleet, hail, alpha) = match self.consensus.await {
+            Ok(consensus) => consensus,
+            Err(_) => {
+                warn!("the node was stopped before starting consensus");
+                return;
+            }
+        };
+        let (sleet_result, hail_result) = futures::join!(
+            sleet.send(sleet::Shutdown { timeout }),
+            hail.send(hail::Shutdown { timeout })
+        );
+        if let Err(e) = sleet_result {
+            warn!("failed to shut down sleet: {:?}", e);
+        }
+        if let Err(e) = hail_result {
+            warn!("failed to shut down hail: {:?}", e);
+        }
+        info!("consensus stopped");
+        match alpha.send(alpha::ExportSnapshot).await {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => warn!("failed to export the snapshot: {:?}", e),
+            Err(e) => warn!("failed to export the snapshot: {:?}", e),
+        }
+        if let Err(e) = ice.send(ice::Shutdown).await {
+            warn!("failed to shut down ice: {:?}", e);
+        }
+    }
+}
 
 /// Runs a node with all components and connects to the network from `bootstrap_peers`.
-/// On startup, it stores the provided keypair into `/tmp/<node_id>/<node_id>.keypair`.
+/// On startup, it stores the provided keypair into `<data_dir>/<node_id>/<node_id>.keypair`.
 ///
 /// ## Parameters:
 /// * `ip` - IP address and port of the node (ex. 127.0.0.1:1234)
 /// * `bootstrap_peers` - a list of peers which this node will use for bootstrapping,
-/// in the format <node_id>@<node_ip_address> (ex. 19Y53ymnBw4LWUpiAMUzPYmYqZmukRhNHm3VyAhzMqckRcuvkf@127.0.0.1)
+/// in the format <node_id>@<node_ip_address> (ex. 1tBz2oa6DCjDbVpC8KJijh7zw8gSvRLAiaUmXktmFAFS5fzXuU@127.0.0.1)
 /// * `keypair` - a hex keypair for the node in String format. Error is thrown if not provided
 /// * `use_tls` - indicate whether to use TLS connection.
 /// If true, then `cert_path` and `pk_path` are mandatory parameters.
@@ -35,7 +98,23 @@ use tracing::info;
 /// A sample of certificate can be found in `./deployment/test-certs/*.crt`.
 /// * `pk_path` - path to a private key for the node. Mandatory parameter if `use_tls` flag is true.
 /// A sample of private key can be found in `./deployment/test-certs/*.key`
-/// * `node_id` - Id of a node in a hex String format (ex. 19Y53ymnBw4LWUpiAMUzPYmYqZmukRhNHm3VyAhzMqckRcuvkf).
+/// * `node_id` - Id of a node in a hex String format (ex. 1tBz2oa6DCjDbVpC8KJijh7zw8gSvRLAiaUmXktmFAFS5fzXuU).
+/// * `genesis_config` - the genesis of the network, if it doesn't use the built-in genesis.
+/// * `gateway_ip` - IP address and port of the [HTTP gateway][crate::server::gateway], if any.
+/// Requires the `http_gateway` feature.
+/// * `max_connections` - the number of open inbound connections beyond which new ones are refused,
+/// except those from the addresses of the bootstrap and known peers, and of the committee.
+/// * `listen_backlog` - the maximum number of pending connections in the listen queue.
+/// * `snapshot_path` - a file the chain is restored from on startup if it exists, and saved to
+/// when shutting down.
+/// * `data_dir` - the directory of the databases and the keypair of the node, which are kept in a
+/// subdirectory named after the node id.
+/// * `consensus_config` - the limits of the consensus components, which keep their defaults if unset.
+/// * `storage_config` - when the accepted blocks are flushed to disk.
+/// * `cell_types` - the validators of the custom cell types of client chains, see
+/// [CellTypeRegistry].
+///
+/// Returns a [Node] handle for shutting the node down.
 pub fn run(
     ip: String,
     bootstrap_peers: Vec<String>,
@@ -45,7 +124,16 @@ pub fn run(
     pk_path: Option<String>,
     // FIXME this is a temporary workaround
     node_id: Option<Id>,
-) -> Result<()> {
+    genesis_config: Option<GenesisConfig>,
+    gateway_ip: Option<SocketAddr>,
+    max_connections: usize,
+    listen_backlog: u32,
+    snapshot_path: Option<String>,
+    data_dir: PathBuf,
+    consensus_config: ConsensusConfig,
+    storage_config: StorageConfig,
+    cell_types: CellTypeRegistry,
+) -> Result<Node> {
     let listener_ip: SocketAddr =
         ip.to_socket_addrs().map_err(|_| Error::PeerParseError)?.next().unwrap();
     let converted_bootstrap_peers = bootstrap_peers
@@ -54,14 +142,17 @@ pub fn run(
         .collect::<Vec<(Id, SocketAddr)>>();
 
     // This is temporary until we have TLS setup
+    // The keypair the node id is derived from, which signs the accepted frontier of `sleet`
+    let mut id_keypair = None;
     let (node_id, upgraders) = if use_tls {
         let (cert, key) = tls::certificate::get_node_cert(
             Path::new(&cert_path.unwrap()),
             Path::new(&pk_path.unwrap()),
         )
         .unwrap();
+        id_keypair = Some(tls::certificate::keypair_from_key(&key).unwrap());
         let upgraders = tls::upgrader::tls_upgraders(&cert, &key);
-        (Id::new(&cert), upgraders)
+        (tls::certificate::id_from_cert(&cert).unwrap(), upgraders)
         // FIXME, until we change alpha and genesis
         // (Id::from_ip(&listener_ip), upgraders)
     } else {
@@ -72,31 +163,54 @@ pub fn run(
         }
     };
     let node_id_str = hex::encode(node_id.as_bytes());
+    let node_dir = data_dir.join(&node_id_str);
+    std::fs::create_dir_all(&node_dir)?;
 
     info!("Node {} is starting", node_id);
 
-    match keypair {
+    let keypair = match keypair {
         Some(keypair_hex) => {
-            let dir_path = vec!["/tmp/", &node_id_str].concat();
-            let file_path = vec!["/tmp/", &node_id_str, "/", &node_id_str, ".keypair"].concat();
-            std::fs::create_dir_all(&dir_path)
-                .expect(&format!("Couldn't create directory: {}", dir_path));
-            let mut file = std::fs::File::create(file_path).unwrap();
-            file.write_all(keypair_hex.as_bytes()).unwrap();
-            let keypair_bytes = hex::decode(keypair_hex).unwrap();
-            Keypair::from_bytes(&keypair_bytes).unwrap()
+            let keypair = decode_keypair(&keypair_hex)?;
+            let () = write_keypair(&keypair_path(&node_dir, &node_id_str), &keypair_hex)?;
+            keypair
         }
-        None => panic!("Keypair is mandatory"),
+        None => return Err(Error::InvalidNodeConfig("missing keypair".to_owned())),
+    };
+
+    // Bootstrap peers have to share the genesis of this node
+    let genesis = match &genesis_config {
+        Some(genesis_config) => genesis_config.build_genesis()?,
+        None => build_genesis()?,
     };
+    let genesis_hash = genesis.hash()?;
 
+    let (consensus_sender, consensus) = oneshot::channel();
     let execution = async move {
         // Create the 'client' actor
-        let client = Client::new(upgraders.client.clone());
+        let client =
+            Client::new(upgraders.client.clone()).with_heartbeat(HeartbeatConfig::new(node_id));
         let client_addr = client.start();
 
-        // Initialise a view with the bootstrap ips and start its actor
-        let mut view = View::new(client_addr.clone().recipient(), listener_ip, node_id);
+        // Initialise a view with the bootstrap ips and the peers remembered from previous runs,
+        // and start its actor
+        let peer_store = SledStore::open(&node_dir.join("peers.sled")).unwrap();
+        let capabilities = Capabilities::current().with_cell_types(cell_types.tags());
+        let mut view = View::new(client_addr.clone().recipient(), listener_ip, node_id)
+            .with_peer_store(Arc::new(peer_store))
+            .with_capabilities(capabilities);
         view.init(converted_bootstrap_peers.clone());
+        let mut converted_bootstrap_peers = converted_bootstrap_peers;
+        match view.load_peers(peer_storage::DEFAULT_PEER_MAX_AGE) {
+            Ok(peers) => {
+                info!("loaded {} known peers", peers.len());
+                for peer in peers {
+                    if !converted_bootstrap_peers.contains(&peer) {
+                        converted_bootstrap_peers.push(peer);
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("failed to load known peers: {:?}", e),
+        }
         let view_addr = view.start();
 
         // Create Dissemination Component
@@ -112,11 +226,38 @@ pub fn run(
             reservoir,
             dc_addr.clone().recipient(),
         );
+        let ice = match consensus_config.ice {
+            Some(ice_config) => ice.with_config(ice_config),
+            None => ice,
+        };
         let ice_addr = ice.start();
 
-        // Create the `hail` actor
-        let hail = Hail::new(client_addr.clone().recipient(), node_id);
+        // Let `ice` and `view` know about the peers whose connections stopped answering
+        client_addr.do_send(SubscribeUnreachable { recipient: ice_addr.clone().recipient() });
+        client_addr.do_send(SubscribeUnreachable { recipient: view_addr.clone().recipient() });
+
+        // Connections from the known peers and the committee are preferred when the server
+        // is busy
+        let peer_ips: HashSet<IpAddr> =
+            converted_bootstrap_peers.iter().map(|(_, ip)| ip.ip()).collect();
+        let preferred_ips = PreferredIps::new(peer_ips);
+
+        // Create the `hail` actor, keeping the blocks up to its checkpoint across restarts
+        let chain_id = genesis_config.as_ref().map_or(DEFAULT_CHAIN_ID, |config| config.chain_id);
+        let finalized_blocks = SledStore::open(&node_dir.join("finalized_blocks.sled")).unwrap();
+        let hail =
+            Hail::new(client_addr.clone().recipient(), node_id, converted_bootstrap_peers.clone())
+                .with_chain_id(chain_id)
+                .with_fee_address(Id::from_public_key(&keypair.public).bytes())
+                .with_keypair(Keypair::from_bytes(&keypair.to_bytes()).unwrap())
+                .with_finalized_blocks(Arc::new(finalized_blocks))
+                .with_cell_types(cell_types.clone());
+        let hail = match consensus_config.max_blocks_range {
+            Some(max_blocks_range) => hail.with_max_blocks_range(max_blocks_range),
+            None => hail,
+        };
         let hail_addr = hail.start();
+        actix::spawn(run_checkpoints(hail_addr.clone()));
 
         // Create the `sleet` actor
         // FIXME: Sleet has to be initialised with the genesis utxo ids.
@@ -126,21 +267,78 @@ pub fn run(
             node_id,
             listener_ip,
             converted_bootstrap_peers,
-        );
+        )
+        .with_genesis_hash(genesis_hash)
+        .with_chain_id(chain_id)
+        .with_peer_meta(view_addr.clone().recipient())
+        .with_cell_types(cell_types.clone());
+        let sleet = match consensus_config.max_dag_width {
+            Some(max_dag_width) => sleet.with_max_dag_width(max_dag_width),
+            None => sleet,
+        };
+        let sleet = match consensus_config.mempool_capacity {
+            Some(capacity) => sleet.with_mempool_capacity(capacity),
+            None => sleet,
+        };
+        let sleet = match consensus_config.max_parents {
+            Some(max_parents) => sleet.with_max_parents(max_parents),
+            None => sleet,
+        };
+        let sleet = match consensus_config.frontier_batching() {
+            Some((batch_size, interval)) => sleet.with_frontier_batching(batch_size, interval),
+            None => sleet,
+        };
+        let sleet = match consensus_config.rebroadcast() {
+            Some((interval, age)) => sleet.with_rebroadcast(interval, age),
+            None => sleet,
+        };
+        let sleet = match consensus_config.confidence_decay() {
+            Some(decay) => sleet.with_confidence_decay(decay),
+            None => sleet,
+        };
+        let sleet = match id_keypair {
+            Some(id_keypair) => sleet.with_keypair(id_keypair),
+            None => {
+                warn!("the ids of the peers aren't authenticated without TLS");
+                sleet.with_keypair(keypair).with_unauthenticated_ids()
+            }
+        };
         let sleet_addr = sleet.start();
 
+        // Let `hail` and `sleet` sample the validators with a bad reputation less often
+        view_addr.do_send(SubscribeReputations { recipient: hail_addr.clone().recipient() });
+        view_addr.do_send(SubscribeReputations { recipient: sleet_addr.clone().recipient() });
+
+        // Let `sleet` spend the locked outputs once `hail` accepted the unlocking block
+        hail_addr.do_send(SubscribeAcceptedHeight { recipient: sleet_addr.clone().recipient() });
+
         // Create the `alpha` actor
-        let db_path = vec!["/tmp/", &node_id_str, "/alpha.sled"].concat();
         let alpha = Alpha::create(
             client_addr.clone().recipient(),
             node_id,
-            Path::new(&db_path),
+            &node_dir.join("alpha.sled"),
             ice_addr.clone(),
             sleet_addr.clone(),
             hail_addr.clone(),
         )
-        .unwrap();
+        .unwrap()
+        .with_durability(storage_config.durability)
+        .with_cell_types(cell_types);
+        let alpha = match genesis_config {
+            Some(genesis_config) => alpha.with_genesis_config(genesis_config),
+            None => alpha,
+        };
+        let alpha = match snapshot_path {
+            Some(snapshot_path) => alpha.with_snapshot_path(PathBuf::from(snapshot_path)),
+            None => alpha,
+        };
         let alpha_addr = alpha.start();
+        let _ = consensus_sender.send((
+            ice_addr.clone(),
+            sleet_addr.clone(),
+            hail_addr.clone(),
+            alpha_addr.clone(),
+        ));
 
         // Bootstrap the view
         let view_addr_clone = view_addr.clone();
@@ -159,16 +357,47 @@ pub fn run(
             arbiter.spawn(ice_execution);
         };
 
+        #[cfg(feature = "http_gateway")]
+        if let Some(gateway_ip) = gateway_ip {
+            let gateway = crate::server::gateway::Gateway::new(
+                gateway_ip,
+                alpha_addr.clone(),
+                sleet_addr.clone(),
+                hail_addr.clone(),
+            );
+            // The HTTP server isn't `Send`, so it is spawned from within its arbiter
+            let arbiter = Arbiter::new();
+            arbiter.spawn_fn(move || {
+                actix::spawn(async move {
+                    if let Err(e) = gateway.listen().await {
+                        tracing::error!("HTTP gateway failed: {:?}", e);
+                    }
+                });
+            });
+        }
+        #[cfg(not(feature = "http_gateway"))]
+        if gateway_ip.is_some() {
+            tracing::warn!("the HTTP gateway requires the `http_gateway` feature");
+        }
+
+        let query_rate_limit = consensus_config.query_rate_limit();
         let listener_execution = async move {
             // Setup the router
-            let router = Router::new(view_addr, ice_addr, alpha_addr, sleet_addr, hail_addr);
+            let router = Router::new(view_addr, ice_addr, alpha_addr, sleet_addr, hail_addr)
+                .with_preferred_ips(preferred_ips.clone());
+            let router = match query_rate_limit {
+                Some((burst, rate)) => router.with_query_rate_limit(burst, rate),
+                None => router,
+            };
             let router_addr = router.start();
             // Setup the server
             let server = Server::new(
                 format!("0.0.0.0:{}", listener_ip.port()).parse().unwrap(),
                 router_addr,
                 upgraders.server.clone(),
-            );
+            )
+            .with_max_connections(max_connections, preferred_ips)
+            .with_backlog(listen_backlog);
             // Listen for incoming connections
             server.listen().await.unwrap()
         };
@@ -181,34 +410,110 @@ pub fn run(
     let arbiter = Arbiter::new();
     arbiter.spawn(execution);
 
-    Ok(())
+    Ok(Node { consensus })
+}
+
+/// Sets a checkpoint of [Hail] [CHECKPOINT_DEPTH] blocks below its accepted frontier every
+/// [CHECKPOINT_INTERVAL], which bounds the blocks it keeps in memory. Stops with [Hail].
+async fn run_checkpoints(hail: Addr<Hail>) {
+    let mut interval = tokio::time::interval(CHECKPOINT_INTERVAL);
+    loop {
+        let _ = interval.tick().await;
+        let accepted = match hail.send(hail::GetAcceptedBlockFrontier).await {
+            Ok(hail::AcceptedBlockFrontier { frontier }) => {
+                frontier.iter().map(|vx| vx.height).max()
+            }
+            Err(_) => return,
+        };
+        let height = match accepted {
+            Some(height) if height > CHECKPOINT_DEPTH => height - CHECKPOINT_DEPTH,
+            _ => continue,
+        };
+        if hail.send(hail::SetCheckpoint { height }).await.is_err() {
+            return;
+        }
+    }
 }
 
+/// The path of the keypair of the node `node_id` in its directory `node_dir`
+fn keypair_path(node_dir: &Path, node_id: &str) -> PathBuf {
+    node_dir.join(format!("{}.keypair", node_id))
+}
+
+/// Reads the keypair stored in the directory of the node, or generates and stores a new one.
 #[allow(unused)] // TODO check if we need this after config is done
-fn read_or_generate_keypair(node_id: String) -> Result<Keypair> {
-    let tmp_dir = vec!["/tmp/", &node_id].concat();
-    std::fs::create_dir_all(&tmp_dir).expect(&format!("Couldn't create directory: {}", tmp_dir));
-    let keypair_path = vec![&tmp_dir[..], "/", &node_id, ".keypair"].concat();
-    match std::fs::File::open(keypair_path.clone()) {
+fn read_or_generate_keypair(node_dir: &Path, node_id: &str) -> Result<Keypair> {
+    let keypair_path = keypair_path(node_dir, node_id);
+    match std::fs::File::open(&keypair_path) {
         Ok(file) => {
             let mut buf_reader = BufReader::new(file);
             let mut contents = String::new();
             buf_reader.read_to_string(&mut contents)?;
-            info!("keypair => {:?}", contents.clone());
-            let keypair_bytes = hex::decode(contents).unwrap();
-            let keypair = Keypair::from_bytes(&keypair_bytes)?;
-            Ok(keypair)
+            decode_keypair(contents.trim())
         }
         Err(_) => {
-            let dir_path = vec!["/tmp/", &node_id].concat();
             let mut csprng = OsRng {};
             let keypair = Keypair::generate(&mut csprng);
-            let keypair_string = hex::encode(keypair.to_bytes());
-            info!("keypair => {:?}", keypair_string.clone());
-            std::fs::create_dir_all(dir_path).unwrap();
-            let mut file = std::fs::File::create(keypair_path)?;
-            file.write_all(keypair_string.as_bytes())?;
+            let () = write_keypair(&keypair_path, &hex::encode(keypair.to_bytes()))?;
+            info!("Generated a keypair into {}", keypair_path.display());
             Ok(keypair)
         }
     }
 }
+
+/// Decodes a hex keypair.
+fn decode_keypair(keypair_hex: &str) -> Result<Keypair> {
+    let keypair_bytes = hex::decode(keypair_hex)
+        .map_err(|e| Error::InvalidNodeConfig(format!("invalid keypair: {}", e)))?;
+    Keypair::from_bytes(&keypair_bytes).map_err(Error::Dalek)
+}
+
+/// Writes a hex keypair to `path`, readable and writable by the owner only.
+fn write_keypair(path: &Path, keypair_hex: &str) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    let _ = options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        let _ = options.mode(0o600);
+        // `mode` only applies to new files
+        if path.exists() {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    let mut file = options.open(path)?;
+    file.write_all(keypair_hex.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn test_read_or_generate_keypair() {
+        let node_id = hex::encode(Id::generate().as_bytes());
+        let node_dir = std::env::temp_dir().join(&node_id);
+        std::fs::create_dir_all(&node_dir).unwrap();
+        let keypair = read_or_generate_keypair(&node_dir, &node_id).unwrap();
+        let path = keypair_path(&node_dir, &node_id);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // The stored keypair is read back
+        let stored = read_or_generate_keypair(&node_dir, &node_id).unwrap();
+        assert_eq!(stored.to_bytes(), keypair.to_bytes());
+
+        // A corrupted keypair is an error rather than a panic
+        std::fs::write(&path, "not hex").unwrap();
+        assert!(matches!(
+            read_or_generate_keypair(&node_dir, &node_id),
+            Err(Error::InvalidNodeConfig(_))
+        ));
+        std::fs::remove_dir_all(&node_dir).unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+//! Per-peer rate limiting of inbound requests
+//!
+//! Each peer has a token bucket holding up to `burst` tokens, refilled at `rate` tokens per
+//! second. A request takes one token, and is refused if the bucket of its peer is empty.
+//! A full bucket is the same as no bucket, so full buckets are dropped once the limiter tracks
+//! [MAX_PEERS] of them.
+use crate::zfx_id::Id;
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Instant;
+
+/// The number of queries a peer may send at once
+pub const QUERY_BURST: u32 = 1000;
+/// The number of queries per second a peer may send in the long run
+pub const QUERY_RATE: u32 = 500;
+/// The number of peers whose buckets are tracked
+pub const MAX_PEERS: usize = 10_000;
+
+/// Identifies the bucket of a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PeerKey {
+    /// A peer authenticated by its TLS certificate
+    Id(Id),
+    /// A peer connected over plain TCP, whose [Id] is derived from its ephemeral port and thus
+    /// changes with each connection
+    Ip(IpAddr),
+}
+
+/// Token bucket rate limiter keyed by the [PeerKey] of the peers
+pub struct RateLimiter {
+    burst: f64,
+    rate: f64,
+    max_peers: usize,
+    buckets: HashMap<PeerKey, Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(burst: u32, rate: u32) -> Self {
+        RateLimiter {
+            burst: burst as f64,
+            rate: rate as f64,
+            max_peers: MAX_PEERS,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Takes a token from the bucket of `peer`, returns `false` if the request is to be refused.
+    pub fn check(&mut self, peer: PeerKey) -> bool {
+        self.check_at(peer, Instant::now())
+    }
+
+    fn check_at(&mut self, peer: PeerKey, now: Instant) -> bool {
+        if !self.buckets.contains_key(&peer) && self.buckets.len() >= self.max_peers {
+            self.evict(now);
+        }
+        let burst = self.burst;
+        let bucket =
+            self.buckets.entry(peer).or_insert_with(|| Bucket { tokens: burst, updated: now });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Drops the full buckets, or the least recently used one if none is full.
+    fn evict(&mut self, now: Instant) {
+        let (burst, rate) = (self.burst, self.rate);
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens + elapsed * rate < burst
+        });
+        if self.buckets.len() >= self.max_peers {
+            let oldest = self.buckets.iter().min_by_key(|(_, bucket)| bucket.updated);
+            if let Some(peer) = oldest.map(|(peer, _)| *peer) {
+                let _ = self.buckets.remove(&peer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[actix_rt::test]
+    async fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(10, 5);
+        let peer1 = PeerKey::Id(Id::one());
+        let peer2 = PeerKey::Ip("127.0.0.1".parse().unwrap());
+        let start = Instant::now();
+
+        // A flood of queries from `peer1` exhausts its bucket
+        let accepted = (0..100).filter(|_| limiter.check_at(peer1, start)).count();
+        assert_eq!(accepted, 10);
+        assert!(!limiter.check_at(peer1, start));
+
+        // Queries of `peer2` are still accepted
+        for _ in 0..10 {
+            assert!(limiter.check_at(peer2, start));
+        }
+
+        // The bucket of `peer1` is refilled over time, up to the burst size
+        let later = start + Duration::from_millis(400);
+        assert!(limiter.check_at(peer1, later));
+        assert!(limiter.check_at(peer1, later));
+        assert!(!limiter.check_at(peer1, later));
+        let much_later = later + Duration::from_secs(60);
+        let accepted = (0..100).filter(|_| limiter.check_at(peer1, much_later)).count();
+        assert_eq!(accepted, 10);
+    }
+
+    #[actix_rt::test]
+    async fn test_rate_limiter_max_peers() {
+        let mut limiter = RateLimiter::new(10, 5);
+        limiter.max_peers = 2;
+        let peers: Vec<PeerKey> = (1..=3u8).map(|i| PeerKey::Ip([10, 0, 0, i].into())).collect();
+        let start = Instant::now();
+        for _ in 0..10 {
+            assert!(limiter.check_at(peers[0], start));
+        }
+        let later = start + Duration::from_millis(200);
+        assert!(limiter.check_at(peers[1], later));
+
+        // The bucket of `peers[1]` is full again and is dropped first
+        let much_later = later + Duration::from_secs(1);
+        assert!(limiter.check_at(peers[2], much_later));
+        assert_eq!(limiter.buckets.len(), 2);
+        assert!(limiter.buckets.contains_key(&peers[0]));
+
+        // Otherwise the least recently used bucket is dropped
+        let last = much_later + Duration::from_millis(100);
+        assert!(limiter.check_at(peers[0], last));
+        assert!(limiter.check_at(peers[1], last));
+        assert_eq!(limiter.buckets.len(), 2);
+        assert!(!limiter.buckets.contains_key(&peers[2]));
+    }
+}
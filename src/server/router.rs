@@ -1,6 +1,9 @@
-use crate::hail::Hail;
-use crate::ice::Ice;
+use crate::alpha::types::BlockHeight;
+use crate::hail::{self, Hail};
+use crate::ice::{self, Ice};
 use crate::protocol::{Request, Response};
+use crate::server::rate_limiter::{self, PeerKey, RateLimiter};
+use crate::server::PreferredIps;
 use crate::sleet::Sleet;
 use crate::view::View;
 use crate::zfx_id::Id;
@@ -9,10 +12,11 @@ use crate::{alpha, alpha::Alpha};
 use tracing::{debug, error, info, trace};
 
 use std::collections::HashSet;
+use std::net::IpAddr;
 use std::sync::Arc;
 
 use crate::sleet;
-use actix::{Actor, Addr, AsyncContext, Context, Handler, ResponseFuture};
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Recipient, ResponseFuture};
 
 /// The `Router` has the addresses of all components which are able to receive requests and
 /// its main responsibility is to delegate a request to the correct component.
@@ -30,6 +34,10 @@ pub struct Router {
     sleet: Addr<Sleet>,
     hail: Addr<Hail>,
     validators: Arc<HashSet<Id>>,
+    /// Limits the rate of `QueryTx` and `QueryBlock` requests per peer
+    query_limiter: RateLimiter,
+    /// Updated with the addresses of the committee, see [CommitteeIps]
+    preferred_ips: Option<PreferredIps>,
 }
 
 impl Router {
@@ -40,7 +48,29 @@ impl Router {
         sleet: Addr<Sleet>,
         hail: Addr<Hail>,
     ) -> Self {
-        Router { view, ice, alpha, sleet, hail, validators: Arc::new(HashSet::new()) }
+        Router {
+            view,
+            ice,
+            alpha,
+            sleet,
+            hail,
+            validators: Arc::new(HashSet::new()),
+            query_limiter: RateLimiter::new(rate_limiter::QUERY_BURST, rate_limiter::QUERY_RATE),
+            preferred_ips: None,
+        }
+    }
+
+    /// Updates `preferred_ips` with the addresses of the committee, so that the
+    /// [Server](crate::server::Server) accepts their connections when it is busy
+    pub fn with_preferred_ips(mut self, preferred_ips: PreferredIps) -> Self {
+        self.preferred_ips = Some(preferred_ips);
+        self
+    }
+
+    /// Sets the number of queries a peer may send at once (`burst`) and per second (`rate`)
+    pub fn with_query_rate_limit(mut self, burst: u32, rate: u32) -> Self {
+        self.query_limiter = RateLimiter::new(burst, rate);
+        self
     }
 }
 
@@ -78,6 +108,80 @@ impl Handler<ValidatorSet> for Router {
     }
 }
 
+/// A request structure for updating the addresses of the live validators of the committee
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+pub struct CommitteeIps {
+    pub ips: HashSet<IpAddr>,
+}
+
+impl Handler<CommitteeIps> for Router {
+    type Result = ();
+
+    fn handle(&mut self, CommitteeIps { ips }: CommitteeIps, _ctx: &mut Context<Self>) {
+        if let Some(preferred_ips) = self.preferred_ips.as_ref() {
+            preferred_ips.set_committee(ips);
+        }
+    }
+}
+
+/// Get the readiness of the node, combining the state of [Ice], [Sleet] and [Hail]
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "Readiness")]
+pub struct GetReadiness;
+
+/// Response to [GetReadiness]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, MessageResponse)]
+pub struct Readiness {
+    /// True if [Ice] is bootstrapped, i.e. enough peers are live
+    pub ice_live: bool,
+    /// True if [Sleet] has finished bootstrapping
+    pub sleet_bootstrapped: bool,
+    /// True if [Hail] has finished bootstrapping
+    pub hail_ready: bool,
+    /// Height of the last accepted block, `0` if no block was accepted yet
+    pub chain_height: BlockHeight,
+}
+
+impl Readiness {
+    /// Whether all components are up, i.e. the node is live rather than bootstrapping
+    pub fn is_ready(&self) -> bool {
+        self.ice_live && self.sleet_bootstrapped && self.hail_ready
+    }
+}
+
+impl Handler<GetReadiness> for Router {
+    type Result = ResponseFuture<Readiness>;
+
+    fn handle(&mut self, _msg: GetReadiness, _ctx: &mut Context<Self>) -> Self::Result {
+        Box::pin(check_readiness(
+            self.ice.clone().recipient(),
+            self.sleet.clone().recipient(),
+            self.hail.clone().recipient(),
+            self.hail.clone().recipient(),
+        ))
+    }
+}
+
+/// Queries the components of the node for their readiness
+async fn check_readiness(
+    ice: Recipient<ice::CheckStatus>,
+    sleet: Recipient<sleet::Bootstrapped>,
+    hail: Recipient<hail::Bootstrapped>,
+    chain_tip: Recipient<hail::GetChainTip>,
+) -> Readiness {
+    let ice_status = ice.send(ice::CheckStatus).await.unwrap();
+    let sleet_bootstrapped = sleet.send(sleet::Bootstrapped).await.unwrap();
+    let hail_ready = hail.send(hail::Bootstrapped).await.unwrap();
+    let hail::ChainTipAck { tip } = chain_tip.send(hail::GetChainTip).await.unwrap();
+    Readiness {
+        ice_live: ice_status.bootstrapped,
+        sleet_bootstrapped,
+        hail_ready,
+        chain_height: tip.map_or(0, |tip| tip.height),
+    }
+}
+
 /// Wrapper for a [Request](crate::protocol::Request), augmenting it with the peer's ID.
 /// Its handler is responsible for taking a request and route it to a relevant component from the [Router].
 /// This request is passed from the [Server::process_stream][crate::server::Server::process_stream]
@@ -88,6 +192,8 @@ pub struct RouterRequest {
     /// ID of the peer. meaningful only when using TLS where the ID is generated from the certificate
     /// presented during handshake
     pub peer_id: Id,
+    /// IP address of the peer
+    pub peer_ip: IpAddr,
     /// Whether the peer ID needs to be checked
     pub check_peer: bool,
     /// The request received
@@ -99,7 +205,7 @@ impl Handler<RouterRequest> for Router {
 
     fn handle(
         &mut self,
-        RouterRequest { peer_id, check_peer, request }: RouterRequest,
+        RouterRequest { peer_id, peer_ip, check_peer, request }: RouterRequest,
         _ctx: &mut Context<Self>,
     ) -> Self::Result {
         let view = self.view.clone();
@@ -108,6 +214,18 @@ impl Handler<RouterRequest> for Router {
         let sleet = self.sleet.clone();
         let hail = self.hail.clone();
         let validators = self.validators.clone();
+        let requires_live_consensus = request.requires_live_consensus();
+        // Queries refused to non-validators don't count against the limit of the peer, which is
+        // only known by its IP address without TLS
+        let rate_limited = match request {
+            Request::QueryTx(_) | Request::QueryTxBatch(_) | Request::QueryBlock(_)
+                if !check_peer || validators.contains(&peer_id) =>
+            {
+                let key = if check_peer { PeerKey::Id(peer_id) } else { PeerKey::Ip(peer_ip) };
+                !self.query_limiter.check(key)
+            }
+            _ => false,
+        };
         Box::pin(async move {
             trace!(
                 "Handling incoming msg: needs_checking: {}, id: {}, validator: {}",
@@ -115,12 +233,19 @@ impl Handler<RouterRequest> for Router {
                 peer_id,
                 validators.contains(&peer_id)
             );
+            // Read-only requests are served from storage while `ice` is bootstrapping
+            if requires_live_consensus && !ice.send(ice::IsBootstrapped).await.unwrap() {
+                debug!("Refusing {:?} from peer {}: ice is uninitialised", request, peer_id);
+                return Response::IceUninitialised;
+            }
             match request {
                 // Handshake
                 Request::Version(version) => {
                     debug!("routing Version -> View");
-                    let version_ack = view.send(version).await.unwrap();
-                    Response::VersionAck(version_ack)
+                    match view.send(version).await.unwrap() {
+                        Ok(version_ack) => Response::VersionAck(version_ack),
+                        Err(version_refused) => Response::VersionRefused(version_refused),
+                    }
                 }
                 // Ice external requests
                 Request::Ping(ping) => {
@@ -144,12 +269,28 @@ impl Handler<RouterRequest> for Router {
                     let cell_ack = sleet.send(get_cell).await.unwrap();
                     Response::CellAck(cell_ack)
                 }
+                Request::GetCellHistory(get_history) => {
+                    debug!("routing GetCellHistory -> Sleet");
+                    let history = sleet.send(get_history).await.unwrap();
+                    Response::CellHistory(history)
+                }
+                Request::GetConflictSet(get_conflict_set) => {
+                    debug!("routing GetConflictSet -> Sleet");
+                    let conflict_set = sleet.send(get_conflict_set).await.unwrap();
+                    Response::ConflictSetAck(conflict_set)
+                }
                 Request::GetAcceptedCellHashes => {
                     debug!("routing GetAcceptedCellHashes -> Sleet");
-                    let cell_hashes = sleet
-                        .send(sleet::sleet_cell_handlers::GetAcceptedCellHashes)
-                        .await
-                        .unwrap();
+                    let get_cell_hashes = sleet::sleet_cell_handlers::GetAcceptedCellHashes {
+                        after: None,
+                        limit: sleet::sleet_cell_handlers::MAX_ACCEPTED_CELL_HASHES_PAGE,
+                    };
+                    let cell_hashes = sleet.send(get_cell_hashes).await.unwrap();
+                    Response::AcceptedCellHashes(cell_hashes)
+                }
+                Request::GetAcceptedCellHashesPage(get_cell_hashes) => {
+                    debug!("routing GetAcceptedCellHashesPage -> Sleet");
+                    let cell_hashes = sleet.send(get_cell_hashes).await.unwrap();
                     Response::AcceptedCellHashes(cell_hashes)
                 }
                 Request::GetAcceptedCell(get_cell) => {
@@ -157,21 +298,57 @@ impl Handler<RouterRequest> for Router {
                     let cell_ack = sleet.send(get_cell).await.unwrap();
                     Response::AcceptedCellAck(cell_ack)
                 }
+                Request::GetUtxosByOwner(get_utxos) => {
+                    debug!("routing GetUtxosByOwner -> Sleet");
+                    let utxos_ack = sleet.send(get_utxos).await.unwrap();
+                    Response::UtxosAck(utxos_ack)
+                }
                 Request::GenerateTx(generate_tx) => {
                     debug!("routing GenerateTx -> Sleet");
                     let receive_tx_ack = sleet.send(generate_tx).await.unwrap();
                     Response::GenerateTxAck(receive_tx_ack)
                 }
+                Request::ValidateTx(validate_tx) => {
+                    debug!("routing ValidateTx -> Sleet");
+                    let validate_tx_ack = sleet.send(validate_tx).await.unwrap();
+                    Response::ValidateTxAck(validate_tx_ack)
+                }
                 Request::QueryTx(query_tx) => {
                     // This request is only accepted from validators
                     if check_peer && !validators.contains(&peer_id) {
                         info!("Refusing validator request {:?} from peer {}", query_tx, peer_id);
                         return Response::RequestRefused;
                     }
+                    if rate_limited {
+                        debug!("Refusing QueryTx from peer {}: rate limit exceeded", peer_id);
+                        return Response::RequestRefused;
+                    }
                     debug!("routing QueryTx -> Sleet");
                     let query_tx_ack = sleet.send(query_tx).await.unwrap();
                     Response::QueryTxAck(query_tx_ack)
                 }
+                Request::QueryTxBatch(query_tx_batch) => {
+                    // This request is only accepted from validators
+                    if check_peer && !validators.contains(&peer_id) {
+                        info!("Refusing validator request QueryTxBatch from peer {}", peer_id);
+                        return Response::RequestRefused;
+                    }
+                    if rate_limited {
+                        debug!("Refusing QueryTxBatch from peer {}: rate limit exceeded", peer_id);
+                        return Response::RequestRefused;
+                    }
+                    if query_tx_batch.txs.len() > sleet::MAX_QUERY_BATCH {
+                        info!(
+                            "Refusing QueryTxBatch of {} transactions from peer {}",
+                            query_tx_batch.txs.len(),
+                            peer_id
+                        );
+                        return Response::RequestRefused;
+                    }
+                    debug!("routing QueryTxBatch -> Sleet");
+                    let query_tx_batch_ack = sleet.send(query_tx_batch).await.unwrap();
+                    Response::QueryTxBatchAck(query_tx_batch_ack)
+                }
                 Request::GetTxAncestors(get_ancestors) => {
                     // This request is only accepted from validators
                     if check_peer && !validators.contains(&peer_id) {
@@ -182,8 +359,13 @@ impl Handler<RouterRequest> for Router {
                         return Response::RequestRefused;
                     }
                     debug!("routing QueryTx -> Sleet");
-                    let ancestors = sleet.send(get_ancestors).await.unwrap();
-                    Response::TxAncestors(ancestors)
+                    match sleet.send(get_ancestors).await.unwrap() {
+                        Ok(ancestors) => Response::TxAncestors(ancestors),
+                        Err(e) => {
+                            error!("couldn't get the ancestors of a transaction: {:?}", e);
+                            Response::Unknown
+                        }
+                    }
                 }
                 Request::GetAcceptedFrontier => {
                     debug!("routing GetAcceptedFrontier -> Sleet");
@@ -200,6 +382,11 @@ impl Handler<RouterRequest> for Router {
                     let frontier = sleet.send(sleet::GetLiveFrontier).await.unwrap();
                     Response::LiveFrontier(frontier)
                 }
+                Request::GetMempoolStats => {
+                    debug!("routing GetMempoolStats -> Sleet");
+                    let stats = sleet.send(sleet::GetMempoolStats).await.unwrap();
+                    Response::MempoolStats(stats)
+                }
                 // Hail external requests
                 Request::GetBlock(get_block) => {
                     debug!("routing GetBlock -> Hail");
@@ -211,22 +398,57 @@ impl Handler<RouterRequest> for Router {
                     let block_ack = hail.send(get_block).await.unwrap();
                     Response::BlockAck(block_ack)
                 }
+                Request::GetBlocksByRange(get_blocks) => {
+                    debug!("routing GetBlocksByRange -> Hail");
+                    let blocks_ack = hail.send(get_blocks).await.unwrap();
+                    Response::BlocksAck(blocks_ack)
+                }
+                Request::GetChainTip => {
+                    debug!("routing GetChainTip -> Hail");
+                    let chain_tip_ack = hail.send(hail::GetChainTip).await.unwrap();
+                    Response::ChainTipAck(chain_tip_ack)
+                }
                 Request::QueryBlock(query_block) => {
                     // This request is only accepted from validators
                     if check_peer && !validators.contains(&peer_id) {
                         info!("Refusing validator request {:?} from peer {}", query_block, peer_id);
                         return Response::RequestRefused;
                     }
+                    if rate_limited {
+                        debug!("Refusing QueryBlock from peer {}: rate limit exceeded", peer_id);
+                        return Response::RequestRefused;
+                    }
                     debug!("routing QueryBlock -> Hail");
                     let query_block_ack = hail.send(query_block).await.unwrap();
                     Response::QueryBlockAck(query_block_ack)
                 }
+                Request::GetAcceptedBlockFrontier => {
+                    debug!("routing GetAcceptedBlockFrontier -> Hail");
+                    let frontier = hail.send(hail::GetAcceptedBlockFrontier).await.unwrap();
+                    Response::AcceptedBlockFrontier(frontier)
+                }
+                Request::FetchBlock(fetch_block) => {
+                    debug!("routing FetchBlock -> Hail");
+                    let fetched_block = hail.send(fetch_block).await.unwrap();
+                    Response::FetchedBlock(fetched_block)
+                }
                 Request::GetNodeStatus => {
                     debug!("routing GetNodeStatus -> Alpha");
                     let status =
                         alpha.send(alpha::status_handler::GetNodeStatus).await.unwrap().unwrap();
                     Response::NodeStatus(status)
                 }
+                Request::GetReadiness => {
+                    debug!("routing GetReadiness -> Ice, Sleet, Hail");
+                    let readiness = check_readiness(
+                        ice.recipient(),
+                        sleet.clone().recipient(),
+                        hail.clone().recipient(),
+                        hail.recipient(),
+                    )
+                    .await;
+                    Response::Readiness(readiness)
+                }
                 req => {
                     error!("received unknown request / not implemented = {:?}", req);
                     Response::Unknown
@@ -235,3 +457,189 @@ impl Handler<RouterRequest> for Router {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::alpha::block::Block;
+    use crate::client::Client;
+    use crate::hail::block::HailBlock;
+    use crate::hail::ChainTip;
+    use crate::ice::dissemination::DisseminationComponent;
+    use crate::ice::{Reservoir, Status};
+    use crate::tls::upgrader::tcp_upgraders;
+
+    use std::net::SocketAddr;
+
+    /// Stands in for [Ice], [Sleet] and [Hail], reporting the configured readiness
+    struct ComponentsMock {
+        readiness: Readiness,
+    }
+
+    impl Actor for ComponentsMock {
+        type Context = Context<Self>;
+    }
+
+    #[derive(Debug, Clone, Message)]
+    #[rtype(result = "()")]
+    struct SetReadiness(Readiness);
+
+    impl Handler<SetReadiness> for ComponentsMock {
+        type Result = ();
+
+        fn handle(&mut self, msg: SetReadiness, _ctx: &mut Context<Self>) -> Self::Result {
+            self.readiness = msg.0;
+        }
+    }
+
+    impl Handler<ice::CheckStatus> for ComponentsMock {
+        type Result = Status;
+
+        fn handle(&mut self, _msg: ice::CheckStatus, _ctx: &mut Context<Self>) -> Self::Result {
+            Status { bootstrapped: self.readiness.ice_live, peers: vec![] }
+        }
+    }
+
+    impl Handler<sleet::Bootstrapped> for ComponentsMock {
+        type Result = bool;
+
+        fn handle(&mut self, _msg: sleet::Bootstrapped, _ctx: &mut Context<Self>) -> Self::Result {
+            self.readiness.sleet_bootstrapped
+        }
+    }
+
+    impl Handler<hail::Bootstrapped> for ComponentsMock {
+        type Result = bool;
+
+        fn handle(&mut self, _msg: hail::Bootstrapped, _ctx: &mut Context<Self>) -> Self::Result {
+            self.readiness.hail_ready
+        }
+    }
+
+    impl Handler<hail::GetChainTip> for ComponentsMock {
+        type Result = hail::ChainTipAck;
+
+        fn handle(&mut self, _msg: hail::GetChainTip, _ctx: &mut Context<Self>) -> Self::Result {
+            let height = self.readiness.chain_height;
+            let tip = if height > 0 {
+                Some(ChainTip { height, last_accepted_hash: [0u8; 32] })
+            } else {
+                None
+            };
+            hail::ChainTipAck { tip }
+        }
+    }
+
+    async fn readiness_of(mock: &Addr<ComponentsMock>) -> Readiness {
+        check_readiness(
+            mock.clone().recipient(),
+            mock.clone().recipient(),
+            mock.clone().recipient(),
+            mock.clone().recipient(),
+        )
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_readiness() {
+        let bootstrapping = Readiness {
+            ice_live: false,
+            sleet_bootstrapped: false,
+            hail_ready: false,
+            chain_height: 0,
+        };
+        let mock = ComponentsMock { readiness: bootstrapping.clone() }.start();
+
+        // Nothing is up while the node is bootstrapping
+        let readiness = readiness_of(&mock).await;
+        assert_eq!(readiness, bootstrapping);
+        assert!(!readiness.is_ready());
+
+        // The node is not ready until all of the components are up
+        let ice_live = Readiness { ice_live: true, ..bootstrapping.clone() };
+        mock.send(SetReadiness(ice_live.clone())).await.unwrap();
+        assert!(!readiness_of(&mock).await.is_ready());
+
+        let sleet_bootstrapped = Readiness { sleet_bootstrapped: true, ..ice_live };
+        mock.send(SetReadiness(sleet_bootstrapped.clone())).await.unwrap();
+        assert!(!readiness_of(&mock).await.is_ready());
+
+        let live = Readiness { hail_ready: true, chain_height: 3, ..sleet_bootstrapped };
+        mock.send(SetReadiness(live.clone())).await.unwrap();
+        let readiness = readiness_of(&mock).await;
+        assert_eq!(readiness, live);
+        assert!(readiness.is_ready());
+    }
+
+    #[actix_rt::test]
+    async fn test_read_only_requests_during_bootstrap() {
+        let client = Client::new(tcp_upgraders().client).start();
+        let ip: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        let id = Id::from_ip(&ip);
+        let dc = DisseminationComponent::new().start();
+        let ice =
+            Ice::new(client.clone().recipient(), id, ip, Reservoir::new(), dc.recipient()).start();
+        let view = View::new(client.clone().recipient(), ip, id).start();
+        let hail = Hail::new(client.clone().recipient(), id, vec![]).start();
+        let sleet =
+            Sleet::new(client.clone().recipient(), hail.clone().recipient(), id, ip, vec![])
+                .start();
+        let path = std::env::temp_dir().join(format!("router-{}", Id::generate()));
+        let alpha =
+            Alpha::create(client.recipient(), id, &path, ice.clone(), sleet.clone(), hail.clone())
+                .unwrap()
+                .start();
+        let router = Router::new(view, ice, alpha, sleet, hail).start();
+        let route = |request| {
+            router.send(RouterRequest {
+                peer_id: Id::one(),
+                peer_ip: ip.ip(),
+                check_peer: false,
+                request,
+            })
+        };
+
+        // Reads are served from storage while `ice` is bootstrapping
+        match route(Request::GetChainTip).await.unwrap() {
+            Response::ChainTipAck(hail::ChainTipAck { tip: None }) => (),
+            other => panic!("unexpected response: {:?}", other),
+        }
+        let get_cell = sleet::GetCell { cell_hash: [0u8; 32] };
+        match route(Request::GetCell(get_cell)).await.unwrap() {
+            Response::CellAck(sleet::CellAck { cell: None }) => (),
+            other => panic!("unexpected response: {:?}", other),
+        }
+
+        // A limit of 0 is raised to 1, so that an empty page is the end of the listing
+        let get_cell_hashes =
+            sleet::sleet_cell_handlers::GetAcceptedCellHashes { after: None, limit: 0 };
+        match route(Request::GetAcceptedCellHashesPage(get_cell_hashes)).await.unwrap() {
+            Response::AcceptedCellHashes(page) => {
+                assert!(page.ids.is_empty() && page.next.is_none())
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+        match route(Request::GetAcceptedCellHashes).await.unwrap() {
+            Response::AcceptedCellHashes(page) => {
+                assert!(page.ids.is_empty() && page.next.is_none())
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+
+        // Consensus queries are refused
+        let block = Block {
+            predecessor: None,
+            height: 1,
+            vrf_out: [0u8; 32],
+            producer: None,
+            cells: vec![],
+        };
+        let query_block = hail::QueryBlock { id: Id::one(), block: HailBlock::new(None, block) };
+        match route(Request::QueryBlock(query_block)).await.unwrap() {
+            Response::IceUninitialised => (),
+            other => panic!("unexpected response: {:?}", other),
+        }
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -3,15 +3,29 @@ use crate::channel::Channel;
 use crate::protocol::{Request, Response};
 use crate::tls::upgrader::Upgrader;
 use crate::{Error, Result};
-use tracing::{error, info};
+use tracing::{debug, info, warn};
 
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
 
 use actix::Addr;
 use actix_rt::net::TcpStream;
 use actix_service::fn_service;
 
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::timeout;
+
+/// Connections without requests are closed after this duration.
+pub const CONNECTION_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// The default maximum number of open inbound connections.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+/// The default maximum number of pending connections in the listen queue.
+pub const DEFAULT_LISTEN_BACKLOG: u32 = 2048;
+/// The time a new connection waits for a slot when the maximum number of connections is open,
+/// before being refused.
+pub const CONNECTION_GRACE: Duration = Duration::from_millis(500);
 
 /// Implements a server for handling incoming connections.
 pub struct Server {
@@ -20,11 +34,39 @@ pub struct Server {
     /// The address of the router.
     router: Addr<Router>,
     upgrader: Arc<dyn Upgrader>,
+    /// The maximum number of pending connections in the listen queue.
+    backlog: u32,
+    /// Limits the number of open connections.
+    limit: ConnectionLimit,
 }
 
 impl Server {
     pub fn new(ip: SocketAddr, router: Addr<Router>, upgrader: Arc<dyn Upgrader>) -> Server {
-        Server { ip, router, upgrader }
+        Server {
+            ip,
+            router,
+            upgrader,
+            backlog: DEFAULT_LISTEN_BACKLOG,
+            limit: ConnectionLimit::new(DEFAULT_MAX_CONNECTIONS, PreferredIps::default()),
+        }
+    }
+
+    /// Refuses new connections once `max_connections` are open, unless they come from
+    /// `preferred_ips` (e.g. those of the bootstrap peers and of the committee). Open connections
+    /// are never dropped.
+    pub fn with_max_connections(
+        mut self,
+        max_connections: usize,
+        preferred_ips: PreferredIps,
+    ) -> Self {
+        self.limit = ConnectionLimit::new(max_connections, preferred_ips);
+        self
+    }
+
+    /// Sets the maximum number of pending connections in the listen queue
+    pub fn with_backlog(mut self, backlog: u32) -> Self {
+        self.backlog = backlog;
+        self
     }
 
     /// Starts an actix server that listens for incoming connections.
@@ -33,18 +75,33 @@ impl Server {
         let ip = self.ip.clone();
         let router = self.router.clone();
         let upgrader = self.upgrader.clone();
+        let limit = self.limit.clone();
         info!("listening on {:?}", ip);
 
         actix_server::Server::build()
+            .backlog(self.backlog)
             .bind("listener", ip, move || {
                 let router = router.clone();
                 let upgrader = upgrader.clone();
+                let limit = limit.clone();
 
                 // creates a service process that runs for each incoming connection
                 fn_service(move |stream: TcpStream| {
                     let router = router.clone();
                     let upgrader = upgrader.clone();
-                    async move { Server::process_stream(stream, router, upgrader).await }
+                    let limit = limit.clone();
+                    async move {
+                        let peer_ip = stream.peer_addr()?.ip();
+                        // The slot is released when the connection is closed
+                        let _slot = match limit.acquire(peer_ip).await {
+                            Some(slot) => slot,
+                            None => {
+                                warn!("refusing connection from {}: too many connections", peer_ip);
+                                return Ok(());
+                            }
+                        };
+                        Server::process_stream(stream, router, upgrader).await
+                    }
                 })
             })?
             .run()
@@ -63,21 +120,211 @@ impl Server {
         // however for TLS it safely identifies the peer
         let check_peer = upgrader.is_tls();
         let peer_id = connection.get_id().unwrap();
-        let mut channel: Channel<Response, Request> = Channel::wrap(connection).unwrap();
+        let peer_ip = connection.peer_addr()?.ip();
+        // Responses are only compressed to clients which negotiated it and announce it
+        let mut channel: Channel<Response, Request> =
+            Channel::wrap(connection).unwrap().with_compression(true);
         let (mut sender, mut receiver) = channel.split();
-        let request = receiver.recv().await.unwrap();
-        match request.clone() {
-            Some(request) => {
-                let response = router
-                    .send(RouterRequest { peer_id, check_peer, request: request.clone() })
-                    .await
-                    .unwrap();
-                //debug!("sending response = {:?}", response);
-                sender.send(response).await.unwrap();
+        // Clients may reuse the connection for further requests, see `client::ConnectionPool`
+        loop {
+            let request = match timeout(CONNECTION_IDLE_TIMEOUT, receiver.recv()).await {
+                Ok(request) => request?,
+                Err(_) => {
+                    debug!("closing idle connection of {}", peer_id);
+                    break;
+                }
+            };
+            match request {
+                Some(request) => {
+                    let response = router
+                        .send(RouterRequest {
+                            peer_id,
+                            peer_ip,
+                            check_peer,
+                            request: request.clone(),
+                        })
+                        .await
+                        .unwrap();
+                    //debug!("sending response = {:?}", response);
+                    let refused = matches!(response, Response::VersionRefused(_));
+                    sender.send(response).await?;
+                    // Don't serve nodes with an incompatible protocol version
+                    if refused {
+                        break;
+                    }
+                }
+                // The client closed the connection
+                None => break,
             }
-            None => error!("received None"),
         }
 
         Ok(())
     }
 }
+
+/// The addresses whose connections are accepted beyond the maximum number of connections of a
+/// [Server]: the fixed addresses of the known peers, and those of the committee, which are updated
+/// by the [Router] (see [ValidatorSet][super::router::ValidatorSet]).
+#[derive(Debug, Clone, Default)]
+pub struct PreferredIps {
+    known: Arc<HashSet<IpAddr>>,
+    committee: Arc<RwLock<HashSet<IpAddr>>>,
+}
+
+impl PreferredIps {
+    /// Prefers the connections from the `known` addresses
+    pub fn new(known: HashSet<IpAddr>) -> Self {
+        PreferredIps { known: Arc::new(known), committee: Default::default() }
+    }
+
+    /// Replaces the addresses of the committee
+    pub fn set_committee(&self, committee: HashSet<IpAddr>) {
+        *self.committee.write().unwrap() = committee;
+    }
+
+    fn contains(&self, ip: &IpAddr) -> bool {
+        self.known.contains(ip) || self.committee.read().unwrap().contains(ip)
+    }
+}
+
+/// Counts the open connections of a [Server] against a maximum
+#[derive(Clone)]
+struct ConnectionLimit {
+    /// Connections from these addresses are accepted beyond the maximum
+    preferred_ips: PreferredIps,
+    /// A permit for each connection which can still be opened
+    slots: Arc<Semaphore>,
+}
+
+/// An open connection, counted by its [ConnectionLimit] until dropped. The connections from
+/// preferred addresses aren't counted.
+struct ConnectionSlot {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl ConnectionLimit {
+    fn new(max_connections: usize, preferred_ips: PreferredIps) -> Self {
+        ConnectionLimit { preferred_ips, slots: Arc::new(Semaphore::new(max_connections)) }
+    }
+
+    /// Returns a slot for a new connection from `ip`, waiting up to [CONNECTION_GRACE] for
+    /// one to be released if the maximum number of connections is open.
+    /// Returns `None` if the connection should be refused.
+    async fn acquire(&self, ip: IpAddr) -> Option<ConnectionSlot> {
+        if self.preferred_ips.contains(&ip) {
+            return Some(ConnectionSlot { _permit: None });
+        }
+        match timeout(CONNECTION_GRACE, self.slots.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => Some(ConnectionSlot { _permit: Some(permit) }),
+            // The semaphore is never closed
+            Ok(Err(_)) | Err(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::alpha::Alpha;
+    use crate::channel::{Receiver, Sender};
+    use crate::client::Client;
+    use crate::hail::Hail;
+    use crate::ice::dissemination::DisseminationComponent;
+    use crate::ice::{Ice, Reservoir};
+    use crate::sleet::Sleet;
+    use crate::tls::upgrader::{tcp_upgraders, TcpUpgrader};
+    use crate::view::View;
+    use crate::zfx_id::Id;
+
+    use actix::Actor;
+    use tokio::net::TcpSocket;
+
+    type Connection = (Sender<Request, Response>, Receiver<Request, Response>);
+
+    /// Connects to `ip` from the local address `local_ip`
+    async fn connect_from(local_ip: IpAddr, ip: SocketAddr) -> Connection {
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.bind(SocketAddr::new(local_ip, 0)).unwrap();
+        let stream = socket.connect(ip).await.unwrap();
+        let stream = TcpUpgrader::new().upgrade(stream).await.unwrap();
+        let mut channel: Channel<Request, Response> = Channel::wrap(stream).unwrap();
+        channel.split()
+    }
+
+    /// Whether the server answers a request on `connection`
+    async fn is_served(connection: &mut Connection) -> bool {
+        let (sender, receiver) = connection;
+        if sender.send(Request::GetChainTip).await.is_err() {
+            return false;
+        }
+        matches!(receiver.recv().await, Ok(Some(Response::ChainTipAck(_))))
+    }
+
+    #[actix_rt::test]
+    async fn test_max_connections() {
+        // A free port, released for the server
+        let ip = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let client = Client::new(tcp_upgraders().client).start();
+        let id = Id::from_ip(&ip);
+        let dc = DisseminationComponent::new().start();
+        let ice =
+            Ice::new(client.clone().recipient(), id, ip, Reservoir::new(), dc.recipient()).start();
+        let view = View::new(client.clone().recipient(), ip, id).start();
+        let hail = Hail::new(client.clone().recipient(), id, vec![]).start();
+        let sleet =
+            Sleet::new(client.clone().recipient(), hail.clone().recipient(), id, ip, vec![])
+                .start();
+        let path = std::env::temp_dir().join(format!("server-{}", Id::generate()));
+        let alpha =
+            Alpha::create(client.recipient(), id, &path, ice.clone(), sleet.clone(), hail.clone())
+                .unwrap()
+                .start();
+        let router = Router::new(view, ice, alpha, sleet, hail).start();
+
+        // The validators connect from other loopback addresses
+        let flooder: IpAddr = "127.0.0.1".parse().unwrap();
+        let validator: IpAddr = "127.0.0.2".parse().unwrap();
+        let committee_member: IpAddr = "127.0.0.3".parse().unwrap();
+        let preferred_ips = PreferredIps::new(vec![validator].into_iter().collect());
+        let server = Server::new(ip, router, TcpUpgrader::new())
+            .with_max_connections(2, preferred_ips.clone());
+        let listener = actix::spawn(async move { server.listen().await });
+        actix::clock::sleep(Duration::from_millis(100)).await;
+
+        let mut connections = vec![];
+        for _ in 0..2 {
+            let mut connection = connect_from(flooder, ip).await;
+            assert!(is_served(&mut connection).await);
+            connections.push(connection);
+        }
+        // One connection beyond the maximum is refused after the grace period
+        let mut refused = connect_from(flooder, ip).await;
+        assert!(!is_served(&mut refused).await);
+
+        // The validator can still connect, and the open connections are kept
+        let mut connection = connect_from(validator, ip).await;
+        assert!(is_served(&mut connection).await);
+        for connection in connections.iter_mut() {
+            assert!(is_served(connection).await);
+        }
+
+        // So can the members of the committee
+        let mut refused = connect_from(committee_member, ip).await;
+        assert!(!is_served(&mut refused).await);
+        preferred_ips.set_committee(vec![committee_member].into_iter().collect());
+        let mut member_connection = connect_from(committee_member, ip).await;
+        assert!(is_served(&mut member_connection).await);
+        drop(member_connection);
+
+        // Closing connections makes room for new ones
+        drop(connection);
+        let _ = connections.pop();
+        actix::clock::sleep(Duration::from_millis(100)).await;
+        let mut connection = connect_from(flooder, ip).await;
+        assert!(is_served(&mut connection).await);
+
+        listener.abort();
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -1,5 +1,6 @@
 //! [ConflictSet] maintains a set of conflicting transaction
 use std::collections::HashSet;
+use std::time::Instant;
 
 /// `ConflictSet` represents a set of conflicting transaction in [`sleet`][crate::sleet]
 ///
@@ -21,6 +22,8 @@ pub struct ConflictSet<T: Eq + std::hash::Hash> {
     pub last: T,
     /// Confidence count in the preferred elements
     pub cnt: u8,
+    /// The time of the last update of the conflict set
+    pub updated: Instant,
 }
 
 impl<T> std::ops::Deref for ConflictSet<T>
@@ -51,7 +54,7 @@ where
     pub fn new(t: T) -> Self {
         let mut conflicts = HashSet::new();
         conflicts.insert(t.clone());
-        ConflictSet { conflicts, pref: t.clone(), last: t, cnt: 0 }
+        ConflictSet { conflicts, pref: t.clone(), last: t, cnt: 0, updated: Instant::now() }
     }
 
     /// Equivalence relation for conflict sets.
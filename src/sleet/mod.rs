@@ -12,12 +12,16 @@ pub use sleet::*;
 
 use crate::alpha::types::TxHash;
 use crate::cell;
+use crate::client::ClientError;
 use crate::graph;
 use crate::storage;
+use crate::zfx_id::Id;
 
 #[derive(Debug)]
 pub enum Error {
     Actix(actix::MailboxError),
+    /// A request to other nodes failed, or was answered with an unexpected response
+    Client(ClientError),
     Sled(sled::Error),
     Cell(cell::Error),
     Storage(storage::Error),
@@ -28,6 +32,19 @@ pub enum Error {
     Graph(graph::Error),
     InsufficientWeight,
     MissingAncestry,
+    /// The transaction has more parents than allowed
+    TooManyParents(usize),
+    /// The transaction doesn't extend any leaf, while the DAG is at its maximum width
+    DagTooWide,
+    /// Bootstrapping failed, with [crate::Error::InvalidGenesis] if none of the bootstrap peers
+    /// shares the genesis of this node
+    Bootstrap(crate::Error),
+    /// The accepted frontier of a peer isn't signed by the key of the peer
+    InvalidFrontierSignature(Id),
+    /// Too few of the bootstrap peers sent a signed frontier: (signed frontiers, bootstrap peers)
+    FrontierQuorum(usize, usize),
+    /// Slash cells pay no fee, they are only included in blocks by their producers
+    InvalidSlashTransaction(cell::Cell),
 }
 
 impl std::error::Error for Error {}
@@ -38,6 +55,12 @@ impl std::convert::From<sled::Error> for Error {
     }
 }
 
+impl std::convert::From<ClientError> for Error {
+    fn from(error: ClientError) -> Self {
+        Error::Client(error)
+    }
+}
+
 impl std::convert::From<cell::Error> for Error {
     fn from(error: cell::Error) -> Self {
         Error::Cell(error)
@@ -5,31 +5,42 @@
 use crate::colored::Colorize;
 use crate::zfx_id::Id;
 
-use crate::alpha::types::{TxHash, Weight};
-use crate::cell::types::CellHash;
-use crate::cell::{Cell, CellIds};
-use crate::client::{ClientRequest, ClientResponse};
-use crate::graph::conflict_graph::ConflictGraph;
+use crate::alpha::types::{BlockHash, BlockHeight, TxHash, Weight};
+use crate::cell::inputs::Input;
+use crate::cell::outputs::Output;
+use crate::cell::types::{Capacity, CellHash, ChainId, PublicKeyHash, DEFAULT_CHAIN_ID, MIN_FEE};
+use crate::cell::{self, Cell, CellIds, CellTypeRegistry};
+use crate::client::{self, ClientError, ClientRequest, ClientResponse, RetryPolicy};
+use crate::graph::conflict_graph::{ConfidenceDecay, ConflictGraph};
 use crate::graph::DAG;
 use crate::hail::AcceptedCells;
+use crate::metrics::METRICS;
 use crate::protocol::{Request, Response};
 use crate::storage::tx as tx_storage;
+use crate::storage::{KvStore, SledStore};
 use crate::util;
+use crate::version::Capabilities;
+use crate::view;
 
 use super::tx::{Tx, TxStatus};
 use super::{Error, Result};
 
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 use actix::WrapFuture;
-use actix::{Actor, AsyncContext, Context, Handler, Recipient};
+use actix::{Actor, ActorContext, AsyncContext, Context, Handler, Recipient};
 use actix::{ActorFutureExt, ResponseActFuture, ResponseFuture};
 
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use futures::future::{AbortHandle, Abortable};
+use rand::rngs::OsRng;
 use tokio::sync::oneshot;
 use tokio::time::{self, Duration};
 
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
 
 use self::sleet_utils::{BoundedHashMap, BoundedHashSet};
 mod sleet_utils;
@@ -38,6 +49,8 @@ mod sleet_utils;
 
 /// Max number of parents to assign for a received transaction
 pub const NPARENTS: usize = 3;
+/// The default maximum number of parents of a received transaction
+pub const MAX_PARENTS: usize = 4 * NPARENTS;
 
 // Safety parameters
 
@@ -51,6 +64,62 @@ pub const BETA2: u8 = 20;
 
 /// Timeout for answering a `QueryTx` message
 const QUERY_RESPONSE_TIMEOUT_MS: u64 = 5000;
+/// Timeout for the responses to a `QueryTx` fanout, leaving time to answer within
+/// [QUERY_RESPONSE_TIMEOUT_MS]
+const QUERY_TIMEOUT_MS: u64 = QUERY_RESPONSE_TIMEOUT_MS + 1000;
+/// Timeout for fetching transactions or their ancestors from a peer
+const FETCH_TIMEOUT_MS: u64 = 5000;
+/// The number of times a failed fetch of transactions is repeated
+const FETCH_RETRIES: usize = 2;
+/// The initial delay before fetching again from a peer after a failed fetch
+const FETCH_BACKOFF_BASE_MS: u64 = 500;
+/// The maximum delay before fetching again from a peer after repeated failed fetches
+const FETCH_BACKOFF_CAP_MS: u64 = 30_000;
+/// The share of the fetch backoff delay which is randomised
+const FETCH_BACKOFF_JITTER: f64 = 0.2;
+/// The maximum number of transactions queried in a single [QueryTxBatch]
+pub const MAX_QUERY_BATCH: usize = 64;
+/// The maximum number of generations of ancestors sent in a [TxAncestors] response
+pub const MAX_ANCESTORS_DEPTH: usize = 50;
+/// The maximum number of [GetTxAncestors] requests sent for the ancestry of one transaction,
+/// following truncated responses
+pub const MAX_ANCESTORS_ROUNDS: usize = 20;
+/// The maximum number of inputs of a cell submitted with [GenerateTx]
+pub const MAX_CELL_INPUTS: usize = 64;
+/// The maximum number of outputs of a cell submitted with [GenerateTx]
+pub const MAX_CELL_OUTPUTS: usize = 64;
+/// The maximum encoded size of a cell submitted with [GenerateTx], in bytes
+pub const MAX_CELL_SIZE: u64 = 64 * 1024;
+/// The default maximum number of live cells and accepted transactions kept in memory, and of
+/// undecided transactions in the mempool
+pub const MEMPOOL_CAPACITY: usize = 3000;
+/// The share of the mempool capacity above which new transactions are rejected, so that accepting
+/// the undecided ones doesn't evict still relevant live cells
+pub const MEMPOOL_HIGH_WATER_MARK: f64 = 0.9;
+/// The default soft cap on the number of leaves of the DAG, above which the queried transactions
+/// not extending any leaf are deferred
+pub const MAX_DAG_WIDTH: usize = 256;
+/// The maximum number of queries waiting for missing ancestry or for the DAG to narrow down,
+/// beyond which new ones are answered negatively right away
+pub const MAX_PENDING_QUERIES: usize = 1024;
+/// The default interval of re-evaluating the pending queries, well within
+/// [QUERY_RESPONSE_TIMEOUT_MS]
+pub const CHECK_PENDING_INTERVAL_MS: u64 = 250;
+/// The default number of accepted transactions after which the accepted frontier is recomputed
+pub const FRONTIER_BATCH_SIZE: usize = 1;
+/// The default delay after which the accepted frontier is recomputed, if fewer than the batch
+/// size of transactions were accepted
+pub const FRONTIER_RECOMPUTE_INTERVAL_MS: u64 = 100;
+/// The default interval of looking for transactions to re-broadcast with [RebroadcastPending]
+pub const REBROADCAST_INTERVAL_MS: u64 = 1000;
+/// The default time after its last query that a transaction without a complete query is
+/// re-broadcast, well beyond [QUERY_TIMEOUT_MS]
+pub const REBROADCAST_AGE_MS: u64 = 10_000;
+/// The share of the bootstrap peers whose signed frontiers an accepted transaction has to be part
+/// of to be merged, so that a single peer can't inject bogus transactions
+pub const FRONTIER_QUORUM: f64 = 0.5;
+/// The delay before bootstrapping again, when too few bootstrap peers sent a signed frontier
+const BOOTSTRAP_RETRY_MS: u64 = 5000;
 
 /// Sleet is a consensus bearing `mempool` for transactions conflicting on spent inputs.
 ///
@@ -68,16 +137,27 @@ pub struct Sleet {
     node_ip: SocketAddr,
     /// The weighted validator set.
     committee: HashMap<Id, (SocketAddr, Weight)>,
+    /// The reputations of validators which misbehaved lately, biasing the sampling.
+    reputations: HashMap<Id, f64>,
     /// The set of all known transactions in storage.
-    known_txs: sled::Db,
+    known_txs: Arc<dyn KvStore>,
+    /// The network the signatures of the received transactions are checked for
+    chain_id: ChainId,
     /// The graph of conflicting transactions (potentially multi-input).
     conflict_graph: ConflictGraph,
     /// A mapping of a cell hashes to unspent cells.
     live_cells: BoundedHashMap<CellHash, Cell>,
+    /// An index of the unspent outputs of accepted cells by their owner.
+    utxos: HashMap<PublicKeyHash, HashSet<(CellHash, u8, Capacity)>>,
+    /// The height of the last block applied to the chain state, below which the locked
+    /// outputs can't be spent
+    height: BlockHeight,
     /// The map contains transactions already accepted, used by the integration tests
     accepted_txs: BoundedHashSet<TxHash>,
     /// Incoming queries pending that couldn't be processed because of missing ancestry
     pending_queries: Vec<(Tx, oneshot::Sender<bool>)>,
+    /// Ancestors from truncated [TxAncestors] responses, waiting for their own ancestry
+    orphan_ancestors: Vec<Tx>,
     /// The consensus graph. Contains the accepted frontier and the undecided transactions
     dag: DAG<TxHash>,
     /// The accepted frontier of the DAG is a depth-first-search on the leaves of the DAG
@@ -89,6 +169,60 @@ pub struct Sleet {
     old_frontier: HashSet<TxHash>,
     /// `true` if Sleet is bootstrapped
     bootstrapped: bool,
+    /// The hash of the genesis block, which the bootstrap peers have to share
+    genesis_hash: Option<BlockHash>,
+    /// The in-flight [FreshTx] queries, which a [Shutdown] waits for
+    drain: util::QueryDrain,
+    /// The handles of the in-flight [FreshTx] queries by transaction and query number, to
+    /// cancel them once the transaction is rejected or removed
+    query_handles: HashMap<TxHash, HashMap<u64, AbortHandle>>,
+    /// The number of the next [FreshTx] query
+    next_query_id: u64,
+    /// The interval of re-evaluating the pending queries with [CheckPending]
+    check_pending_interval: Duration,
+    /// The maximum number of live cells, accepted and undecided transactions kept in memory
+    mempool_capacity: usize,
+    /// The validators of the custom cell types accepted in transactions
+    cell_types: CellTypeRegistry,
+    /// The maximum number of parents of a received transaction
+    max_parents: usize,
+    /// The number of leaves of the DAG above which the queried transactions widening it are
+    /// deferred
+    max_dag_width: usize,
+    /// The number of accepted transactions after which the accepted frontier is recomputed
+    frontier_batch_size: usize,
+    /// The delay after which the accepted frontier is recomputed, if fewer transactions were
+    /// accepted than `frontier_batch_size`
+    frontier_recompute_interval: Duration,
+    /// The number of transactions accepted since the accepted frontier was last recomputed
+    accepted_since_pruning: usize,
+    /// `true` if a [PruneAcceptedFrontier] is scheduled
+    pruning_scheduled: bool,
+    /// The number of known transactions by status
+    tx_counts: TxCounts,
+    /// The fees paid by the undecided transactions, recorded when they are received, as the
+    /// outputs they spend may be evicted from `live_cells` before they are accepted
+    fees: HashMap<TxHash, Capacity>,
+    /// The interval of looking for transactions to re-broadcast with [RebroadcastPending]
+    rebroadcast_interval: Duration,
+    /// The time after its last query that a transaction without a complete query is re-broadcast
+    rebroadcast_age: Duration,
+    /// The undecided transactions without a complete query, with the time of their last query
+    unsettled_txs: HashMap<TxHash, Instant>,
+    /// The transactions with a re-broadcast query in flight
+    rebroadcast_txs: HashSet<TxHash>,
+    /// The missing ancestors being fetched with [AskForAncestors]
+    pending_fetches: HashSet<TxHash>,
+    /// The backoff of the peers whose last fetch failed, with the time before which they aren't
+    /// asked again
+    fetch_backoffs: HashMap<Id, (util::Backoff, Instant)>,
+    /// The keypair signing the accepted frontier sent to bootstrapping peers
+    keypair: Keypair,
+    /// Looks up the capabilities negotiated with the validators, see [view::PeerMeta]
+    peer_meta: Option<Recipient<view::GetPeerMeta>>,
+    /// Whether the [Id]s of the peers are derived from their keys, in which case the frontiers
+    /// have to be signed by the key of the peer sending them
+    authenticated_ids: bool,
 }
 
 impl Sleet {
@@ -113,33 +247,171 @@ impl Sleet {
             node_id,
             node_ip,
             committee: HashMap::default(),
-            known_txs: sled::Config::new().temporary(true).open().unwrap(),
+            reputations: HashMap::default(),
+            known_txs: Arc::new(SledStore::temporary().unwrap()),
+            chain_id: DEFAULT_CHAIN_ID,
             conflict_graph: ConflictGraph::new(CellIds::empty()),
-            live_cells: BoundedHashMap::new(3000),
-            accepted_txs: BoundedHashSet::new(3000),
+            live_cells: BoundedHashMap::new(MEMPOOL_CAPACITY),
+            utxos: HashMap::new(),
+            height: 0,
+            accepted_txs: BoundedHashSet::new(MEMPOOL_CAPACITY),
             pending_queries: vec![],
+            orphan_ancestors: vec![],
             dag: DAG::new(),
             accepted_frontier: HashSet::new(),
             bootstrap_peers,
             old_frontier: HashSet::new(),
             bootstrapped: false,
+            genesis_hash: None,
+            drain: Default::default(),
+            query_handles: HashMap::default(),
+            next_query_id: 0,
+            check_pending_interval: Duration::from_millis(CHECK_PENDING_INTERVAL_MS),
+            mempool_capacity: MEMPOOL_CAPACITY,
+            cell_types: CellTypeRegistry::new(),
+            max_parents: MAX_PARENTS,
+            max_dag_width: MAX_DAG_WIDTH,
+            frontier_batch_size: FRONTIER_BATCH_SIZE,
+            frontier_recompute_interval: Duration::from_millis(FRONTIER_RECOMPUTE_INTERVAL_MS),
+            accepted_since_pruning: 0,
+            pruning_scheduled: false,
+            tx_counts: TxCounts::default(),
+            fees: HashMap::new(),
+            rebroadcast_interval: Duration::from_millis(REBROADCAST_INTERVAL_MS),
+            rebroadcast_age: Duration::from_millis(REBROADCAST_AGE_MS),
+            unsettled_txs: HashMap::new(),
+            rebroadcast_txs: HashSet::new(),
+            pending_fetches: HashSet::new(),
+            fetch_backoffs: HashMap::new(),
+            keypair: Keypair::generate(&mut OsRng {}),
+            peer_meta: None,
+            authenticated_ids: true,
         }
     }
 
+    /// Keeps the known transactions in `known_txs` instead of a temporary database.
+    pub fn with_known_txs(mut self, known_txs: Arc<dyn KvStore>) -> Self {
+        self.known_txs = known_txs;
+        self
+    }
+
+    /// Only bootstraps from peers having the genesis block with `genesis_hash`.
+    pub fn with_genesis_hash(mut self, genesis_hash: BlockHash) -> Self {
+        self.genesis_hash = Some(genesis_hash);
+        self
+    }
+
+    /// Checks the signatures of the received transactions for the network `chain_id`.
+    pub fn with_chain_id(mut self, chain_id: ChainId) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    /// Signs the accepted frontier with `keypair`, from which the [Id] of this node is derived
+    pub fn with_keypair(mut self, keypair: Keypair) -> Self {
+        self.keypair = keypair;
+        self
+    }
+
+    /// Uses the optional features which the validators support, as looked up with `peer_meta`.
+    /// Otherwise none of them are used.
+    pub fn with_peer_meta(mut self, peer_meta: Recipient<view::GetPeerMeta>) -> Self {
+        self.peer_meta = Some(peer_meta);
+        self
+    }
+
+    /// Accepts the validly signed frontiers of the bootstrap peers whose [Id] isn't derived from
+    /// the signing key, as with plain TCP connections. The peers aren't authenticated then.
+    pub fn with_unauthenticated_ids(mut self) -> Self {
+        self.authenticated_ids = false;
+        self
+    }
+
+    /// Re-evaluates the pending queries every `interval`, instead of every
+    /// [CHECK_PENDING_INTERVAL_MS].
+    pub fn with_check_pending_interval(mut self, interval: Duration) -> Self {
+        self.check_pending_interval = interval;
+        self
+    }
+
+    /// Keeps at most `capacity` live cells and transactions in memory, instead of
+    /// [MEMPOOL_CAPACITY].
+    pub fn with_mempool_capacity(mut self, capacity: usize) -> Self {
+        self.mempool_capacity = capacity;
+        self.live_cells = BoundedHashMap::new(capacity);
+        self.accepted_txs = BoundedHashSet::new(capacity);
+        self
+    }
+
+    /// Validates the custom outputs of transactions with `cell_types`. Without registered
+    /// validators, transactions with custom outputs are refused.
+    pub fn with_cell_types(mut self, cell_types: CellTypeRegistry) -> Self {
+        self.cell_types = cell_types;
+        self
+    }
+
+    /// Refuses received transactions with more than `max_parents` parents.
+    pub fn with_max_parents(mut self, max_parents: usize) -> Self {
+        self.max_parents = max_parents;
+        self
+    }
+
+    /// Defers the queried transactions which don't extend any leaf of the DAG while it has
+    /// `max_dag_width` leaves, instead of [MAX_DAG_WIDTH]. Generated transactions then select
+    /// up to `max_parents` parents, so that the DAG narrows down.
+    pub fn with_max_dag_width(mut self, max_dag_width: usize) -> Self {
+        self.max_dag_width = max_dag_width;
+        self
+    }
+
+    /// Decays the confidence of the conflict sets which stopped being queried, see
+    /// [ConfidenceDecay].
+    pub fn with_confidence_decay(mut self, decay: ConfidenceDecay) -> Self {
+        self.conflict_graph = self.conflict_graph.with_confidence_decay(decay);
+        self
+    }
+
+    /// Recomputes the accepted frontier once `batch_size` transactions were accepted, or at the
+    /// latest `interval` after an acceptance, instead of after every batch of [NewAccepted]
+    /// transactions. Reads of the frontier bring it up to date first.
+    pub fn with_frontier_batching(mut self, batch_size: usize, interval: Duration) -> Self {
+        self.frontier_batch_size = batch_size;
+        self.frontier_recompute_interval = interval;
+        self
+    }
+
+    /// Looks for transactions to re-broadcast every `interval`, and re-broadcasts those without a
+    /// complete query for `age`, instead of [REBROADCAST_INTERVAL_MS] and [REBROADCAST_AGE_MS].
+    pub fn with_rebroadcast(mut self, interval: Duration, age: Duration) -> Self {
+        self.rebroadcast_interval = interval;
+        self.rebroadcast_age = age;
+        self
+    }
+
+    /// Returns `true` if the undecided transactions, i.e. the pending and queried ones, reached the
+    /// [high-water mark][MEMPOOL_HIGH_WATER_MARK] of the mempool capacity.
+    fn is_mempool_full(&self) -> bool {
+        let high_water_mark = (self.mempool_capacity as f64 * MEMPOOL_HIGH_WATER_MARK) as usize;
+        let undecided = self.tx_counts.pending + self.tx_counts.queried;
+        undecided >= high_water_mark
+    }
+
     /// Called for all newly discovered transactions, sets its status to [TxStatus::Pending]
     /// and [inserts](Sleet::insert) it in [Sleet] state and database.
     ///
-    /// Throws [Error::MissingAncestry] if `sleet_tx` has no parents.
+    /// Throws [Error::MissingAncestry] if `sleet_tx` has no parents, [Error::TooManyParents] if it
+    /// has more than `max_parents`, or [Error::Cell] if a custom output is rejected by the
+    /// [registered validator][CellTypeRegistry] of its type.
     ///
     /// Returns `true` if the transaction haven't been encountered before
     ///
     /// * `sleet_tx` - a [Tx] to record in [Sleet]
     fn on_receive_tx(&mut self, mut sleet_tx: Tx) -> Result<bool> {
-        // Skip adding coinbase transactions (block rewards / initial allocations) to the
-        // mempool.
-        if util::has_coinbase_output(&sleet_tx.cell) {
-            return Err(Error::InvalidCoinbaseTransaction(sleet_tx.cell));
+        // Checked before the ancestry, so that no work is spent on fetching the parents
+        if sleet_tx.parents.len() > self.max_parents {
+            return Err(Error::TooManyParents(sleet_tx.parents.len()));
         }
+        let () = self.check_cell(&sleet_tx.cell)?;
 
         // Insert transaction if it is new, or it is a re-issued transaction that
         // was removed due to conflicting ancestry
@@ -149,9 +421,12 @@ impl Sleet {
             if !self.has_parents(&sleet_tx) {
                 return Err(Error::MissingAncestry);
             }
+            let fee = self.validate_cell(&sleet_tx.cell)?;
             sleet_tx.status = TxStatus::Pending;
             self.insert(sleet_tx.clone())?;
-            let _ = tx_storage::insert_tx(&self.known_txs, sleet_tx.clone());
+            let _ = self.fees.insert(sleet_tx.hash(), fee);
+            let _ = self.tx_counts.insert_tx(&*self.known_txs, sleet_tx.clone());
+            let _ = self.unsettled_txs.insert(sleet_tx.hash(), Instant::now());
             Ok(true)
         } else {
             info!(
@@ -164,6 +439,76 @@ impl Sleet {
         }
     }
 
+    /// The checks of `cell` which don't depend on the outputs it spends, done before fetching its
+    /// ancestry: coinbase and slash cells are refused, and its custom outputs have to be accepted
+    /// by the [registered validators][CellTypeRegistry] of their type.
+    fn check_cell(&self, cell: &Cell) -> Result<()> {
+        // Coinbase transactions (block rewards / initial allocations) aren't added to the
+        // mempool
+        if util::has_coinbase_output(cell) {
+            return Err(Error::InvalidCoinbaseTransaction(cell.clone()));
+        }
+        if util::has_slash_output(cell) {
+            return Err(Error::InvalidSlashTransaction(cell.clone()));
+        }
+        let () = self.cell_types.validate(cell)?;
+        Ok(())
+    }
+
+    /// Checks that `cell`, which passed [Sleet::check_cell], can be added to the mempool, as a
+    /// received transaction or for [ValidateTx]: it has to be [valid][cell::validate] on the
+    /// network `chain_id`, paying at least [MIN_FEE], and the outputs it spends can't be locked
+    /// beyond the current `height`.
+    ///
+    /// Returns the fee paid by `cell`.
+    fn validate_cell(&mut self, cell: &Cell) -> Result<Capacity> {
+        let spent = self.spent_outputs(cell);
+        let resolve = |input: &Input| {
+            spent.get(&(input.output_index.cell_hash, input.output_index.index)).cloned()
+        };
+        let fee = cell::validate(cell, self.chain_id, resolve)?;
+        if fee < MIN_FEE {
+            return Err(Error::Cell(cell::Error::InsufficientFee(fee, MIN_FEE)));
+        }
+        for output in spent.values() {
+            let () = output.validate_locktime(self.height)?;
+        }
+        Ok(fee)
+    }
+
+    /// Returns the outputs spent by the inputs of `cell`, which are looked up in the live cells
+    /// and the known transactions. Unknown outputs are left out.
+    fn spent_outputs(&mut self, cell: &Cell) -> HashMap<(CellHash, u8), Output> {
+        let mut spent = HashMap::new();
+        for input in cell.inputs().iter() {
+            let output_index = &input.output_index;
+            let spent_cell = match self.live_cells.get(&output_index.cell_hash) {
+                Some(spent_cell) => Some(spent_cell.clone()),
+                None => tx_storage::get_tx(&self.known_txs, output_index.cell_hash)
+                    .ok()
+                    .map(|(_, tx)| tx.cell),
+            };
+            if let Some(output) =
+                spent_cell.and_then(|c| c.outputs().get(output_index.index as usize).cloned())
+            {
+                let _ = spent.insert((output_index.cell_hash, output_index.index), output);
+            }
+        }
+        spent
+    }
+
+    /// Returns `true` if inserting the unknown transaction `tx` would add a leaf to the DAG,
+    /// while it already has `max_dag_width` leaves.
+    ///
+    /// Only the queried transactions are limited. The ancestors fetched for the pending queries
+    /// are needed to settle these, and their number is bounded by [MAX_PENDING_QUERIES]. Local
+    /// transactions aren't limited either, but build on up to `max_parents` leaves instead.
+    fn exceeds_dag_width(&self, tx: &Tx) -> bool {
+        self.dag.leaf_count() >= self.max_dag_width
+            && !self.dag.contains_key(&tx.hash())
+            && !tx.parents.iter().any(|p| self.dag.is_leaf(p))
+    }
+
     /// Insert transaction into the DAG and Conflict Graph
     fn insert(&mut self, tx: Tx) -> Result<()> {
         let cell = tx.cell.clone();
@@ -176,11 +521,17 @@ impl Sleet {
     /// Check if Sleet has all the parents for a transaction
     /// otherwise the ancestry needs to be fetched
     fn has_parents(&self, tx: &Tx) -> bool {
+        self.missing_parents(tx).is_empty()
+    }
+
+    /// Returns the parents of `tx` which are neither in the DAG nor accepted.
+    fn missing_parents(&self, tx: &Tx) -> Vec<TxHash> {
         match self.dag.has_vertices(&tx.parents) {
-            Ok(()) => true,
+            Ok(()) => vec![],
             Err(missing_parents) => missing_parents
-                .iter()
-                .all(|p| tx_storage::is_accepted_tx(&self.known_txs, p).unwrap_or(false)),
+                .into_iter()
+                .filter(|p| !tx_storage::is_accepted_tx(&self.known_txs, p).unwrap_or(false))
+                .collect(),
         }
     }
 
@@ -329,17 +680,25 @@ impl Sleet {
         let mut children: VecDeque<TxHash> = VecDeque::new();
         for hash in rejected {
             info!("Rejected {}", hex::encode(hash));
-            tx_storage::set_status(&self.known_txs, &hash, TxStatus::Rejected)?;
+            METRICS.sleet_rejected_txs.inc();
+            self.tx_counts.set_status(&*self.known_txs, &hash, TxStatus::Rejected)?;
+            self.cancel_queries(&hash);
+            let _ = self.fees.remove(&hash);
+            self.unindex_outputs(&hash);
             let ch = self.dag.remove_vx(&hash)?;
             children.extend(ch.iter());
         }
 
         // Remove the progeny of conflicting transactions
         while let Some(hash) = children.pop_front() {
-            tx_storage::set_status(&self.known_txs, &hash, TxStatus::Removed)?;
+            self.tx_counts.set_status(&*self.known_txs, &hash, TxStatus::Removed)?;
+            self.cancel_queries(&hash);
+            let _ = self.fees.remove(&hash);
             self.conflict_graph.remove_cell(&hash)?;
+            self.unindex_outputs(&hash);
             // Ignore errors here, as they happen when `children` contains duplicates
             info!("Removed: {}", hex::encode(hash.clone()));
+            METRICS.sleet_removed_txs.inc();
             match self.dag.remove_vx(&hash) {
                 Ok(ch) => children.extend(ch.iter()),
                 _ => (),
@@ -349,6 +708,61 @@ impl Sleet {
         Ok(())
     }
 
+    /// Cancels the in-flight [FreshTx] queries on `tx_hash`, whose responses are then ignored
+    fn cancel_queries(&mut self, tx_hash: &TxHash) {
+        if let Some(handles) = self.query_handles.remove(tx_hash) {
+            debug!("cancelling {} queries on {}", handles.len(), hex::encode(tx_hash));
+            for (_, handle) in handles {
+                handle.abort();
+            }
+        }
+    }
+
+    /// Forgets the handle of a settled [FreshTx] query
+    fn forget_query(&mut self, tx_hash: &TxHash, query_id: u64) {
+        if let Some(handles) = self.query_handles.get_mut(tx_hash) {
+            let _ = handles.remove(&query_id);
+            if handles.is_empty() {
+                let _ = self.query_handles.remove(tx_hash);
+            }
+        }
+    }
+
+    // Unspent outputs
+
+    /// Adds the outputs of `cell` to the index of unspent outputs by owner.
+    fn index_outputs(&mut self, cell: &Cell) {
+        let cell_hash = cell.hash();
+        for (i, output) in cell.outputs().iter().enumerate() {
+            let _ = self.utxos.entry(output.lock).or_default().insert((
+                cell_hash,
+                i as u8,
+                output.capacity,
+            ));
+        }
+    }
+
+    /// Removes the outputs spent by the inputs of `cell` from the index of unspent outputs.
+    fn unindex_spent_outputs(&mut self, cell: &Cell) {
+        let spent: HashSet<(CellHash, u8)> = cell
+            .inputs()
+            .iter()
+            .map(|input| (input.output_index.cell_hash, input.output_index.index))
+            .collect();
+        for utxos in self.utxos.values_mut() {
+            utxos.retain(|(cell_hash, i, _)| !spent.contains(&(*cell_hash, *i)));
+        }
+        self.utxos.retain(|_, utxos| !utxos.is_empty());
+    }
+
+    /// Removes the outputs of the cell `cell_hash` from the index of unspent outputs.
+    fn unindex_outputs(&mut self, cell_hash: &CellHash) {
+        for utxos in self.utxos.values_mut() {
+            utxos.retain(|(h, _, _)| h != cell_hash);
+        }
+        self.utxos.retain(|_, utxos| !utxos.is_empty());
+    }
+
     // Accepted Frontier
 
     /// The accepted frontier of the DAG is a depth-first-search on the leaves of the DAG
@@ -374,6 +788,7 @@ impl Sleet {
 
     /// Remove transactions from the dag above the accepted frontier
     fn prune_at_accepted_frontier(&mut self) {
+        self.accepted_since_pruning = 0;
         self.compute_accepted_frontier();
         let mut to_be_pruned = HashSet::new();
         for f in self.accepted_frontier.iter() {
@@ -401,12 +816,17 @@ impl Sleet {
             {
                 new.push(t.clone());
                 let () = self.accepted_txs.insert(t.clone());
-                tx_storage::set_status(&self.known_txs, t, TxStatus::Accepted).unwrap();
+                self.tx_counts.set_status(&*self.known_txs, t, TxStatus::Accepted).unwrap();
             }
         }
         new
     }
 
+    /// Returns the weight of a validator in the `committee`, or `0.0` if it isn't a member.
+    fn weight(&self, id: &Id) -> Weight {
+        self.committee.get(id).map(|(_, w)| *w).unwrap_or(0.0)
+    }
+
     /// Returns a list of validators with total minimum combined weight from the `committee` of [Sleet].
     ///
     /// Throws [Error::InsufficientWeight] if `committee` doesn't have validators with sufficient weight.
@@ -415,7 +835,22 @@ impl Sleet {
         for (id, (ip, w)) in self.committee.iter() {
             validators.push((id.clone(), ip.clone(), w.clone()));
         }
-        util::sample_weighted(minimum_weight, validators).ok_or(Error::InsufficientWeight)
+        util::sample_weighted_biased(minimum_weight, validators, &self.reputations)
+            .ok_or(Error::InsufficientWeight)
+    }
+
+    /// The vote of this validator on a known transaction, as answered to a [QueryTx].
+    fn vote(&self, tx_hash: &TxHash) -> bool {
+        // We may have accepted or rejected the transaction already when the query comes in
+        if tx_storage::is_accepted_tx(&self.known_txs, tx_hash).unwrap_or(false) {
+            return true;
+        }
+        if tx_storage::cannot_be_accepted(&self.known_txs, tx_hash).unwrap_or(false) {
+            return false;
+        }
+        // FIXME: If we are in the middle of querying this transaction, wait until a
+        // decision or a synchronous timebound is reached on attempts.
+        self.is_strongly_preferred(tx_hash.clone()).unwrap()
     }
 }
 
@@ -424,6 +859,13 @@ impl Actor for Sleet {
 
     fn started(&mut self, ctx: &mut Context<Self>) {
         ctx.notify(Bootstrap);
+        // The ancestry of pending queries may also arrive without a message to Sleet
+        let _ = ctx.run_interval(self.check_pending_interval, |_act, ctx| {
+            ctx.notify(CheckPending);
+        });
+        let _ = ctx.run_interval(self.rebroadcast_interval, |_act, ctx| {
+            ctx.notify(RebroadcastPending);
+        });
         debug!("started sleet");
     }
 
@@ -436,6 +878,17 @@ impl Actor for Sleet {
 /// A message to start the bootstrapping process of the node for [Sleet].
 /// The handler of this request communicates with `bootstrap_peers` of [Sleet]
 /// to synchronize it with other nodes.
+///
+/// The frontiers which aren't signed by the key of the peer are discarded. Without
+/// [authenticated ids][Sleet::with_unauthenticated_ids], the key can't be tied to the peer, so
+/// the frontiers are only checked to be signed by the key they carry.
+///
+/// The frontiers of peers with a different genesis are discarded with
+/// [crate::Error::InvalidGenesis], and bootstrapping is aborted with it if none of the peers
+/// shares the genesis of this node. It fails with [Error::FrontierQuorum] and is retried later if
+/// no more than [FRONTIER_QUORUM] of the bootstrap peers sent a signed frontier of this genesis.
+/// Only the transactions in the frontiers of more than [FRONTIER_QUORUM] of the bootstrap peers are
+/// merged, so peers which don't respond or are on another chain count against the quorum.
 #[derive(Debug, Clone, Serialize, Deserialize, Message)]
 #[rtype(result = "Result<()>")]
 struct Bootstrap;
@@ -447,25 +900,99 @@ impl Handler<Bootstrap> for Sleet {
         let query = ClientRequest::Fanout {
             peers: self.bootstrap_peers.clone(),
             request: Request::GetAcceptedFrontier,
+            timeout: None,
         };
         info!("{} bootstrapping...", "[sleet]".cyan());
         self.sender
             .send(query)
             .into_actor(self)
             .map(|res, act, ctx| match res {
-                Ok(ClientResponse::Fanout(frontiers)) => {
+                Ok(response) => {
+                    let mut frontiers = vec![];
+                    let mut foreign = 0;
+                    for (id, result) in response.into_fanout()? {
+                        match result {
+                            Ok(Response::AcceptedFrontier(frontier)) => {
+                                let signed = if act.authenticated_ids {
+                                    frontier.verify(&id)
+                                } else {
+                                    frontier.verify_signature(&id)
+                                };
+                                match signed {
+                                    Ok(()) if frontier.genesis_hash != act.genesis_hash => {
+                                        error!(
+                                            "{} discarding the frontier of {}: {:?}",
+                                            "[sleet]".cyan(),
+                                            id,
+                                            crate::Error::InvalidGenesis
+                                        );
+                                        foreign += 1;
+                                    }
+                                    Ok(()) => frontiers.push(frontier),
+                                    Err(e) => warn!(
+                                        "{} discarding the frontier of {}: {:?}",
+                                        "[sleet]".cyan(),
+                                        id,
+                                        e
+                                    ),
+                                }
+                            }
+                            Err(e) => debug!("no response from {}: {:?}", id, e),
+                            Ok(other) => warn!(
+                                "{} {:?} to bootstrap: {:?}",
+                                "[sleet]".cyan(),
+                                ClientError::UnexpectedResponseKind,
+                                other
+                            ),
+                        }
+                    }
+                    if frontiers.is_empty() && foreign > 0 {
+                        error!(
+                            "{} no bootstrap peer shares the genesis of this node, aborting",
+                            "[sleet]".cyan()
+                        );
+                        return Err(Error::Bootstrap(crate::Error::InvalidGenesis));
+                    }
                     info!(
-                        "{} received {} frontier responses for bootstrap",
+                        "{} received {} signed frontiers for bootstrap{}",
                         "[sleet]".cyan(),
-                        frontiers.len()
+                        frontiers.len(),
+                        if act.authenticated_ids { "" } else { " (unauthenticated)" }
                     );
-                    for f in frontiers.iter() {
-                        match f {
-                            Response::AcceptedFrontier(AcceptedFrontier { frontier }) => {
-                                act.accepted_frontier =
-                                    act.accepted_frontier.union(frontier).cloned().collect();
-                            }
-                            _ => panic!("unexpected response"),
+                    let quorum = act.bootstrap_peers.len() as f64 * FRONTIER_QUORUM;
+                    if !act.bootstrap_peers.is_empty() && frontiers.len() as f64 <= quorum {
+                        warn!(
+                            "{} only {} of {} bootstrap peers sent a signed frontier, retrying",
+                            "[sleet]".cyan(),
+                            frontiers.len(),
+                            act.bootstrap_peers.len()
+                        );
+                        let _ = ctx
+                            .run_later(Duration::from_millis(BOOTSTRAP_RETRY_MS), |_act, ctx| {
+                                ctx.notify(Bootstrap)
+                            });
+                        return Err(Error::FrontierQuorum(
+                            frontiers.len(),
+                            act.bootstrap_peers.len(),
+                        ));
+                    }
+                    let mut corroborations: HashMap<TxHash, usize> = HashMap::new();
+                    for AcceptedFrontier { frontier, .. } in frontiers.iter() {
+                        for tx_hash in frontier.iter() {
+                            *corroborations.entry(*tx_hash).or_default() += 1;
+                        }
+                    }
+                    for (tx_hash, count) in corroborations {
+                        if count as f64 > quorum {
+                            let _ = act.accepted_frontier.insert(tx_hash);
+                        } else {
+                            warn!(
+                                "{} {} is only in the frontiers of {} of {} peers, skipping",
+                                "[sleet]".cyan(),
+                                hex::encode(tx_hash),
+                                count,
+                                act.bootstrap_peers.len()
+                            );
                         }
                     }
 
@@ -487,13 +1014,33 @@ impl Handler<Bootstrap> for Sleet {
                         Ok(())
                     }
                 }
-                Ok(ClientResponse::Oneshot(_)) => panic!("unexpected response"),
                 Err(e) => Err(Error::Actix(e)),
             })
             .boxed_local()
     }
 }
 
+/// Restart the bootstrapping process from `peers`, when the node rejoins the network after
+/// being isolated from it (see [LiveNetwork][crate::alpha::LiveNetwork]).
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+pub struct Rebootstrap {
+    pub peers: Vec<(Id, SocketAddr)>,
+}
+
+impl Handler<Rebootstrap> for Sleet {
+    type Result = ();
+
+    fn handle(&mut self, msg: Rebootstrap, ctx: &mut Context<Self>) -> Self::Result {
+        info!("{} rebootstrapping from {} peers", "[sleet]".cyan(), msg.peers.len());
+        if !msg.peers.is_empty() {
+            self.bootstrap_peers = msg.peers;
+        }
+        self.bootstrapped = false;
+        ctx.notify(Bootstrap);
+    }
+}
+
 /// Fetch transactions recursively on bootstrap
 #[derive(Debug, Clone, Serialize, Deserialize, Message)]
 #[rtype(result = "()")]
@@ -513,6 +1060,15 @@ impl Handler<FetchWithAncestry> for Sleet {
         let peers = self.bootstrap_peers.clone();
         let sender = self.sender.clone();
         let act = ctx.address();
+        let policy = RetryPolicy::new(
+            FETCH_RETRIES,
+            Some(Duration::from_millis(FETCH_TIMEOUT_MS)),
+            util::Backoff::new(
+                Duration::from_millis(FETCH_BACKOFF_BASE_MS),
+                Duration::from_millis(FETCH_BACKOFF_CAP_MS),
+                FETCH_BACKOFF_JITTER,
+            ),
+        );
         Box::pin(async move {
             let mut txs: VecDeque<TxHash> = VecDeque::new();
             txs.extend(initial_txs.iter());
@@ -520,24 +1076,33 @@ impl Handler<FetchWithAncestry> for Sleet {
                 // Fetch tx from peers
                 if !tx_storage::is_known_tx(&db, tx_hash).unwrap_or(false) {
                     for (id, ip) in peers.iter() {
-                        match sender
-                            .send(ClientRequest::Oneshot {
-                                id: *id,
-                                ip: *ip,
-                                request: Request::FetchTx(FetchTx { tx_hash }),
-                            })
+                        let request = Request::FetchTx(FetchTx { tx_hash });
+                        match client::oneshot_with_retry(&sender, *id, *ip, request, policy.clone())
                             .await
                         {
-                            Ok(ClientResponse::Oneshot(Some(Response::FetchedTx(FetchedTx {
-                                tx: Some(tx),
-                            })))) => {
+                            Ok(Response::FetchedTx(FetchedTx { tx: Some(tx) })) => {
                                 // Insert into DB
                                 let _ = tx_storage::insert_tx(&db, tx.clone());
                                 // Push parents of `tx` to the queue
                                 txs.extend(tx.parents.iter());
                                 break;
                             }
-                            _ => (), // TODO
+                            Ok(response) => {
+                                debug!(
+                                    "{} couldn't provide {}: {:?}",
+                                    id,
+                                    hex::encode(tx_hash),
+                                    response
+                                )
+                            }
+                            Err(err) => {
+                                debug!(
+                                    "failed to fetch {} from {}: {}",
+                                    hex::encode(tx_hash),
+                                    id,
+                                    err
+                                )
+                            }
                         }
                     }
                 }
@@ -592,16 +1157,68 @@ impl Handler<Bootstrapped> for Sleet {
 pub struct GetAcceptedFrontier;
 
 /// A response to [GetAcceptedFrontier] with a set of [TxHash] from `accepted_frontier` of [Sleet]
+/// and the hash of its genesis block, signed by the responding node
 #[derive(Debug, Clone, Serialize, Deserialize, MessageResponse)]
 pub struct AcceptedFrontier {
     frontier: HashSet<TxHash>,
+    genesis_hash: Option<BlockHash>,
+    /// The public key of the responding node, from which its [Id] is derived
+    public_key: PublicKey,
+    /// The signature of the frontier and the genesis hash
+    signature: Signature,
+}
+
+impl AcceptedFrontier {
+    /// Signs `frontier` and `genesis_hash` with `keypair`.
+    pub fn new(
+        keypair: &Keypair,
+        frontier: HashSet<TxHash>,
+        genesis_hash: Option<BlockHash>,
+    ) -> Self {
+        let signature = keypair.sign(&Self::signing_message(&frontier, &genesis_hash));
+        AcceptedFrontier { frontier, genesis_hash, public_key: keypair.public, signature }
+    }
+
+    /// The signed message, listing the frontier in order so that it doesn't depend on the
+    /// iteration order of the set
+    fn signing_message(frontier: &HashSet<TxHash>, genesis_hash: &Option<BlockHash>) -> Vec<u8> {
+        let mut frontier: Vec<&TxHash> = frontier.iter().collect();
+        frontier.sort();
+        // Serializing into a `Vec` can't fail
+        bincode::serialize(&(frontier, genesis_hash)).unwrap()
+    }
+
+    /// Checks that the frontier is validly signed by the node `id`.
+    ///
+    /// Throws [Error::InvalidFrontierSignature] otherwise.
+    pub fn verify(&self, id: &Id) -> Result<()> {
+        if Id::from_public_key(&self.public_key) != *id {
+            return Err(Error::InvalidFrontierSignature(id.clone()));
+        }
+        self.verify_signature(id)
+    }
+
+    /// Checks that the frontier is validly signed by its public key, without checking that it
+    /// belongs to the node `id` it was received from.
+    ///
+    /// Throws [Error::InvalidFrontierSignature] otherwise.
+    pub fn verify_signature(&self, id: &Id) -> Result<()> {
+        let message = Self::signing_message(&self.frontier, &self.genesis_hash);
+        if self.public_key.verify(&message, &self.signature).is_err() {
+            return Err(Error::InvalidFrontierSignature(id.clone()));
+        }
+        Ok(())
+    }
 }
 
 impl Handler<GetAcceptedFrontier> for Sleet {
     type Result = AcceptedFrontier;
 
     fn handle(&mut self, _msg: GetAcceptedFrontier, _ctx: &mut Context<Self>) -> Self::Result {
-        AcceptedFrontier { frontier: self.accepted_frontier.clone() }
+        if self.accepted_since_pruning > 0 {
+            self.prune_at_accepted_frontier();
+        }
+        AcceptedFrontier::new(&self.keypair, self.accepted_frontier.clone(), self.genesis_hash)
     }
 }
 
@@ -624,6 +1241,15 @@ impl Handler<GetLiveFrontier> for Sleet {
     }
 }
 
+/// Validators with a bad reputation in the [View][crate::view::View] are sampled less often.
+impl Handler<view::PeerReputations> for Sleet {
+    type Result = ();
+
+    fn handle(&mut self, msg: view::PeerReputations, _ctx: &mut Context<Self>) -> Self::Result {
+        self.reputations = msg.reputations;
+    }
+}
+
 /// When the committee is initialised in [Alpha][crate::alpha::Alpha] or when it comes back online due to a
 /// [FaultyNetwork][crate::alpha::FaultyNetwork] received message in
 /// [Alpha](crate::alpha::Alpha), [Sleet] is updated with the latest relevant chain state.
@@ -634,6 +1260,8 @@ pub struct LiveCommittee {
     pub validators: HashMap<Id, (SocketAddr, f64)>,
     /// live cells in the [State](crate::alpha::state::State)
     pub live_cells: HashMap<CellHash, Cell>,
+    /// the height of the [State](crate::alpha::state::State)
+    pub height: BlockHeight,
 }
 
 impl Handler<LiveCommittee> for Sleet {
@@ -654,6 +1282,7 @@ impl Handler<LiveCommittee> for Sleet {
             cell_ids_set = cell_ids_set.union(&cell_ids).cloned().collect();
 
             if !self.live_cells.contains_key(&cell_hash) {
+                self.index_outputs(&cell);
                 self.live_cells.insert(cell_hash, cell);
             }
         }
@@ -669,6 +1298,24 @@ impl Handler<LiveCommittee> for Sleet {
         info!("{}", s);
 
         self.committee = msg.validators;
+        self.height = msg.height;
+    }
+}
+
+/// Sent by [Hail][crate::hail::Hail] once it accepted the block at `height`, from which on the
+/// outputs locked until `height` can be spent.
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+pub struct AcceptedHeight {
+    pub height: BlockHeight,
+}
+
+impl Handler<AcceptedHeight> for Sleet {
+    type Result = ();
+
+    fn handle(&mut self, msg: AcceptedHeight, _ctx: &mut Context<Self>) -> Self::Result {
+        // The heights may arrive out of order
+        self.height = self.height.max(msg.height);
     }
 }
 
@@ -689,9 +1336,21 @@ impl Handler<QueryIncomplete> for Sleet {
     type Result = ();
 
     fn handle(&mut self, msg: QueryIncomplete, _ctx: &mut Context<Self>) -> Self::Result {
-        self.reset_ancestor_confidence(&msg.tx.hash()).unwrap();
-        // Mark as `Queried`, since the transaction won't be queried again
-        tx_storage::set_status(&self.known_txs, &msg.tx.hash(), TxStatus::Queried).unwrap();
+        let tx_hash = msg.tx.hash();
+        // The transaction was rejected or removed after the query settled
+        if !self.dag.contains_key(&tx_hash) {
+            return;
+        }
+        // A failed re-broadcast doesn't take away the confidence gained since the first query
+        if !self.rebroadcast_txs.remove(&tx_hash) {
+            self.reset_ancestor_confidence(&tx_hash).unwrap();
+        }
+        // Re-broadcast later, unless the transaction was decided in the meantime
+        if let Some(queried_at) = self.unsettled_txs.get_mut(&tx_hash) {
+            *queried_at = Instant::now();
+        }
+        // Mark as `Queried`, the transaction is only queried again by [RebroadcastPending]
+        self.tx_counts.set_status(&*self.known_txs, &msg.tx.hash(), TxStatus::Queried).unwrap();
     }
 }
 
@@ -714,15 +1373,30 @@ impl Handler<QueryComplete> for Sleet {
     type Result = ();
 
     fn handle(&mut self, msg: QueryComplete, ctx: &mut Context<Self>) -> Self::Result {
-        // FIXME: Verify that there are no duplicate ids
+        // The transaction was rejected or removed after the query settled
+        if !self.dag.contains_key(&msg.tx.hash()) {
+            return;
+        }
+        let _ = self.rebroadcast_txs.remove(&msg.tx.hash());
+        let _ = self.unsettled_txs.remove(&msg.tx.hash());
         let mut outcomes = vec![];
+        let mut voted = HashSet::new();
         for ack in msg.acks.iter() {
             match ack {
                 Response::QueryTxAck(qtx_ack) => match self.committee.get(&qtx_ack.id) {
-                    Some((_, w)) => outcomes.push((qtx_ack.id, w.clone(), qtx_ack.outcome)),
-                    None => (),
+                    // A validator votes once, its duplicate acks are ignored
+                    Some((_, w)) if voted.insert(qtx_ack.id) => {
+                        outcomes.push((qtx_ack.id, w.clone(), qtx_ack.outcome))
+                    }
+                    _ => (),
                 },
-                _ => panic!("QueryTxAck: unexpected response"),
+                // A malformed vote doesn't count
+                other => warn!(
+                    "[{}] {:?} to query: {:?}",
+                    "sleet".cyan(),
+                    ClientError::UnexpectedResponseKind,
+                    other
+                ),
             }
         }
         //   if yes: set_chit(tx, 1), update ancestral preferences
@@ -743,7 +1417,7 @@ impl Handler<QueryComplete> for Sleet {
             self.reset_ancestor_confidence(&msg.tx.hash()).unwrap();
         }
         //   if no:  set_chit(tx, 0) -- happens in `insert_vx`
-        tx_storage::set_status(&self.known_txs, &msg.tx.hash(), TxStatus::Queried).unwrap();
+        self.tx_counts.set_status(&*self.known_txs, &msg.tx.hash(), TxStatus::Queried).unwrap();
     }
 }
 
@@ -760,8 +1434,9 @@ pub struct NewAccepted {
 impl Handler<NewAccepted> for Sleet {
     type Result = ();
 
-    fn handle(&mut self, msg: NewAccepted, _ctx: &mut Context<Self>) -> Self::Result {
+    fn handle(&mut self, msg: NewAccepted, ctx: &mut Context<Self>) -> Self::Result {
         let mut cells = vec![];
+        let mut fees: Capacity = 0;
 
         for tx_hash in msg.tx_hashes.iter().cloned() {
             // At this point we can be sure that the tx is known
@@ -775,12 +1450,41 @@ impl Handler<NewAccepted> for Sleet {
                 }
             }
             info!("[{}] transaction is accepted\n{}", "sleet".cyan(), tx.clone());
+            METRICS.sleet_accepted_txs.inc();
+            // The fee was recorded when the transaction was received, which all the
+            // transactions inserted in the DAG are
+            fees = fees.saturating_add(self.fees.remove(&tx_hash).unwrap_or(0));
+            self.unindex_spent_outputs(&tx.cell);
+            self.index_outputs(&tx.cell);
             cells.push(tx.cell);
         }
 
-        self.prune_at_accepted_frontier();
+        self.accepted_since_pruning += msg.tx_hashes.len();
+        if self.accepted_since_pruning >= self.frontier_batch_size {
+            self.prune_at_accepted_frontier();
+        } else if !self.pruning_scheduled {
+            self.pruning_scheduled = true;
+            let _ = ctx.notify_later(PruneAcceptedFrontier, self.frontier_recompute_interval);
+        }
 
-        let _ = self.hail_recipient.do_send(AcceptedCells { cells });
+        let _ = self.hail_recipient.do_send(AcceptedCells { cells, fees });
+    }
+}
+
+/// Recomputes the accepted frontier if transactions were accepted since it was last computed,
+/// sent with a delay when the [frontier batch](Sleet::with_frontier_batching) isn't complete.
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+struct PruneAcceptedFrontier;
+
+impl Handler<PruneAcceptedFrontier> for Sleet {
+    type Result = ();
+
+    fn handle(&mut self, _msg: PruneAcceptedFrontier, _ctx: &mut Context<Self>) -> Self::Result {
+        self.pruning_scheduled = false;
+        if self.accepted_since_pruning > 0 {
+            self.prune_at_accepted_frontier();
+        }
     }
 }
 
@@ -803,43 +1507,173 @@ impl Handler<FreshTx> for Sleet {
     type Result = ResponseActFuture<Self, Result<()>>;
 
     fn handle(&mut self, msg: FreshTx, _ctx: &mut Context<Self>) -> Self::Result {
+        if self.drain.is_shutting_down() {
+            // The transaction stays pending, no new queries are started
+            return Box::pin(actix::fut::ready(Ok(())));
+        }
         let validators = self.sample(ALPHA).unwrap();
         info!("[{}] Querying\n{}", "sleet".cyan(), msg.tx.clone());
         info!("[{}] sampled {:?}", "sleet".cyan(), validators.clone());
 
-        // Fanout queries to sampled validators
-        let send_to_client = self.sender.send(ClientRequest::Fanout {
-            peers: validators.clone(),
-            request: Request::QueryTx(QueryTx {
-                id: self.node_id.clone(),
-                ip: self.node_ip.clone(),
-                tx: msg.tx.clone(),
-            }),
+        // If this validator is sampled, its vote is evaluated locally instead of over the network
+        let node_id = self.node_id;
+        let (own, peers): (Vec<_>, Vec<_>) =
+            validators.into_iter().partition(|(id, _)| *id == node_id);
+        let own_ack = if own.is_empty() {
+            None
+        } else {
+            let tx_hash = msg.tx.hash();
+            let outcome = self.vote(&tx_hash);
+            Some(Response::QueryTxAck(QueryTxAck { id: node_id, tx_hash, outcome }))
+        };
+
+        // Fanout queries to the other sampled validators, until their votes settle the outcome
+        let started = Instant::now();
+        let own_weight = match &own_ack {
+            Some(Response::QueryTxAck(QueryTxAck { outcome: true, .. })) => self.weight(&node_id),
+            _ => 0.0,
+        };
+        let peers: Vec<_> = peers.into_iter().map(|(id, ip)| (id, ip, self.weight(&id))).collect();
+        let sender = self.sender.clone();
+        let request = Request::QueryTx(QueryTx {
+            id: self.node_id.clone(),
+            ip: self.node_ip.clone(),
+            tx: msg.tx.clone(),
         });
+        let tx_hash = msg.tx.hash();
+        let send_to_client = async move {
+            client::fanout_until_settled(
+                &sender,
+                peers,
+                request,
+                // Validators which don't answer in time count as not responding
+                Some(Duration::from_millis(QUERY_TIMEOUT_MS)),
+                own_weight,
+                ALPHA,
+                |response| match response {
+                    Response::QueryTxAck(ack) => ack.outcome && ack.tx_hash == tx_hash,
+                    _ => false,
+                },
+            )
+            .await
+        };
+
+        self.drain.started(1);
+
+        // The query is cancelled if the transaction is rejected or removed in the meantime
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        let query_id = self.next_query_id;
+        self.next_query_id += 1;
+        let tx_hash = msg.tx.hash();
+        let _ = self.query_handles.entry(tx_hash).or_default().insert(query_id, abort_handle);
+        let send_to_client = Abortable::new(send_to_client, abort_registration);
 
         // Wrap the future so that subsequent chained handlers can access the actor.
         let send_to_client = actix::fut::wrap_future::<_, Self>(send_to_client);
 
-        let update_self = send_to_client.map(move |result, _actor, ctx| {
-            match result {
-                Ok(ClientResponse::Fanout(acks)) => {
-                    // If the length of responses is the same as the length of the sampled ips,
-                    // then every peer responded.
-                    if acks.len() == validators.len() {
+        let update_self = send_to_client.map(move |result, actor, ctx| {
+            METRICS.sleet_query_latency.observe(started.elapsed());
+            actor.forget_query(&tx_hash, query_id);
+            let result = match result {
+                Ok(result) => result,
+                Err(_aborted) => {
+                    info!("[{}] query cancelled on {}", "sleet".cyan(), hex::encode(tx_hash));
+                    ctx.notify(QuerySettled);
+                    return Ok(());
+                }
+            };
+            let outcome = match result {
+                Ok(results) => {
+                    // The query is complete if every validator queried until the outcome settled
+                    // responded
+                    let complete = results.iter().all(|(_, result)| result.is_ok());
+                    let mut acks = client::successful_responses(results);
+                    acks.extend(own_ack);
+                    if complete {
                         Ok(ctx.notify(QueryComplete { tx: msg.tx.clone(), acks }))
                     } else {
                         Ok(ctx.notify(QueryIncomplete { tx: msg.tx.clone(), acks }))
                     }
                 }
-                Ok(ClientResponse::Oneshot(_)) => panic!("unexpected response"),
-                Err(e) => Err(Error::Actix(e)),
-            }
+                // None of the peers answered, the transaction is re-broadcast later
+                Err(e) => {
+                    let acks = own_ack.into_iter().collect();
+                    ctx.notify(QueryIncomplete { tx: msg.tx.clone(), acks });
+                    match e {
+                        crate::Error::Actix(e) => Err(Error::Actix(e)),
+                        e => Err(Error::Client(ClientError::from(e))),
+                    }
+                }
+            };
+            // Handled after the outcome of the query
+            ctx.notify(QuerySettled);
+            outcome
         });
 
         Box::pin(update_self)
     }
 }
 
+/// Sent when a [FreshTx] query is answered or fails, after its outcome was notified
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+struct QuerySettled;
+
+impl Handler<QuerySettled> for Sleet {
+    type Result = ();
+
+    fn handle(&mut self, _msg: QuerySettled, ctx: &mut Context<Self>) -> Self::Result {
+        if self.drain.settled() {
+            // Let the messages notified while handling the outcome (e.g. [NewAccepted]) go first
+            ctx.notify(Drained);
+        }
+    }
+}
+
+/// Wakes up a pending [Shutdown] once the in-flight queries settled
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+struct Drained;
+
+impl Handler<Drained> for Sleet {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Drained, _ctx: &mut Context<Self>) -> Self::Result {
+        self.drain.wake();
+    }
+}
+
+/// Shuts [Sleet] down gracefully: new transactions are refused, the in-flight queries are
+/// given up to `timeout` to settle, then the known transactions are flushed and the actor
+/// stopped. The flush only persists them if they are kept in a store given with
+/// [with_known_txs][Sleet::with_known_txs], the default database being temporary.
+/// Once the response arrives, messages sent to [Sleet] fail with a closed mailbox.
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct Shutdown {
+    pub timeout: Duration,
+}
+
+impl Handler<Shutdown> for Sleet {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(&mut self, msg: Shutdown, _ctx: &mut Context<Self>) -> Self::Result {
+        info!("[{}] shutting down, {} queries in flight", "sleet".cyan(), self.drain.in_flight());
+        let drained = self.drain.shut_down(msg.timeout);
+        let wait = async move {
+            if !drained.await {
+                warn!("[{}] in-flight queries didn't settle in time", "sleet".cyan());
+            }
+        };
+        Box::pin(wait.into_actor(self).map(|(), actor, ctx| {
+            if let Err(e) = actor.known_txs.flush() {
+                error!("[{}] failed to flush the database: {:?}", "sleet".cyan(), e);
+            }
+            ctx.stop();
+        }))
+    }
+}
+
 /// A request structure for generating a new transaction from the received [Cell](crate::cell::Cell).
 /// Its handler is an entrypoint for transactions, received by node.
 /// To generate a [Tx], it selects a [min number of parents][NPARENTS] and inserts it
@@ -859,13 +1693,67 @@ pub struct GenerateTx {
 pub struct GenerateTxAck {
     /// hash of applied transaction
     pub cell_hash: Option<CellHash>,
+    /// the reason the node refused the transaction, if the client should back off or try
+    /// another node
+    pub rejected: Option<RejectReason>,
+}
+
+/// The reason a node refused a [GenerateTx] request, see [GenerateTxAck]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RejectReason {
+    /// The mempool of the node is at capacity
+    MempoolFull,
+    /// The cell has more than [MAX_CELL_INPUTS] inputs
+    TooManyInputs,
+    /// The cell has more than [MAX_CELL_OUTPUTS] outputs
+    TooManyOutputs,
+    /// The encoded cell is larger than [MAX_CELL_SIZE]
+    DataTooLarge,
+    /// The cell can't be spent, e.g. its inputs are unknown or its outputs are refused
+    Invalid(String),
+    /// The transaction of the cell is already known
+    Duplicate,
+    /// Coinbase cells are only created for block rewards and initial allocations
+    CoinbaseNotAllowed,
+    /// The node is shutting down, see [Shutdown]
+    ShuttingDown,
+}
+
+/// Checks the size limits of a cell received with [GenerateTx].
+fn check_cell_limits(cell: &Cell) -> std::result::Result<(), RejectReason> {
+    if cell.inputs().len() > MAX_CELL_INPUTS {
+        return Err(RejectReason::TooManyInputs);
+    }
+    if cell.outputs().len() > MAX_CELL_OUTPUTS {
+        return Err(RejectReason::TooManyOutputs);
+    }
+    match bincode::serialized_size(cell) {
+        Ok(size) if size <= MAX_CELL_SIZE => Ok(()),
+        _ => Err(RejectReason::DataTooLarge),
+    }
 }
 
 impl Handler<GenerateTx> for Sleet {
     type Result = GenerateTxAck;
 
     fn handle(&mut self, msg: GenerateTx, ctx: &mut Context<Self>) -> Self::Result {
-        let parents = self.select_parents(NPARENTS).unwrap();
+        if self.drain.is_shutting_down() {
+            warn!("[{}] shutting down, refusing new transaction", "sleet".cyan());
+            return GenerateTxAck { cell_hash: None, rejected: Some(RejectReason::ShuttingDown) };
+        }
+        if self.is_mempool_full() {
+            warn!("[{}] mempool full, refusing new transaction", "sleet".cyan());
+            return GenerateTxAck { cell_hash: None, rejected: Some(RejectReason::MempoolFull) };
+        }
+        // Checked before anything is stored or validated
+        if let Err(reason) = check_cell_limits(&msg.cell) {
+            warn!("[{}] refusing new transaction: {:?}", "sleet".cyan(), reason);
+            return GenerateTxAck { cell_hash: None, rejected: Some(reason) };
+        }
+        // Build on as many leaves as possible when the DAG is too wide
+        let nparents =
+            if self.dag.leaf_count() >= self.max_dag_width { self.max_parents } else { NPARENTS };
+        let parents = self.select_parents(nparents).unwrap();
         let sleet_tx = Tx::new(parents, msg.cell.clone());
         let tx_hash = sleet_tx.hash();
         info!(
@@ -878,9 +1766,9 @@ impl Handler<GenerateTx> for Sleet {
         match self.on_receive_tx(sleet_tx.clone()) {
             Ok(true) => {
                 ctx.notify(FreshTx { tx: sleet_tx });
-                GenerateTxAck { cell_hash: Some(msg.cell.hash()) }
+                GenerateTxAck { cell_hash: Some(msg.cell.hash()), rejected: None }
             }
-            Ok(false) => GenerateTxAck { cell_hash: None },
+            Ok(false) => GenerateTxAck { cell_hash: None, rejected: Some(RejectReason::Duplicate) },
 
             Err(e) => {
                 error!(
@@ -890,8 +1778,66 @@ impl Handler<GenerateTx> for Sleet {
                     sleet_tx,
                     e
                 );
-                GenerateTxAck { cell_hash: None }
+                let reason = match e {
+                    Error::InvalidCoinbaseTransaction(_) => RejectReason::CoinbaseNotAllowed,
+                    e => RejectReason::Invalid(format!("{}", e)),
+                };
+                GenerateTxAck { cell_hash: None, rejected: Some(reason) }
+            }
+        }
+    }
+}
+
+/// Dry-run of [GenerateTx]: checks whether the node would accept `cell`, and which cells it
+/// conflicts with, without recording it or querying other nodes.
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "ValidateTxAck")]
+pub struct ValidateTx {
+    pub cell: Cell,
+}
+
+/// Response to [ValidateTx]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, MessageResponse)]
+pub struct ValidateTxAck {
+    /// the reason [GenerateTx] would refuse the cell, `None` if it would be accepted
+    pub rejected: Option<RejectReason>,
+    /// the cells spending some of the same outputs. An accepted cell with conflicts competes
+    /// with them, and at most one of them is accepted by consensus.
+    pub conflicts: Vec<CellHash>,
+}
+
+impl Handler<ValidateTx> for Sleet {
+    type Result = ValidateTxAck;
+
+    fn handle(&mut self, msg: ValidateTx, _ctx: &mut Context<Self>) -> Self::Result {
+        let rejected = |reason| ValidateTxAck { rejected: Some(reason), conflicts: vec![] };
+        if self.is_mempool_full() {
+            return rejected(RejectReason::MempoolFull);
+        }
+        if let Err(reason) = check_cell_limits(&msg.cell) {
+            return rejected(reason);
+        }
+        // The checks of `on_receive_tx` and `insert`, without side effects
+        match self.check_cell(&msg.cell).and_then(|()| self.validate_cell(&msg.cell)) {
+            Ok(_fee) => (),
+            Err(Error::InvalidCoinbaseTransaction(_)) => {
+                return rejected(RejectReason::CoinbaseNotAllowed)
+            }
+            Err(e) => return rejected(RejectReason::Invalid(format!("{}", e))),
+        }
+        let cell_hash = msg.cell.hash();
+        if tx_storage::is_known_tx(&self.known_txs, cell_hash).unwrap_or(false)
+            && !tx_storage::is_removed_tx(&self.known_txs, &cell_hash).unwrap_or(false)
+        {
+            return rejected(RejectReason::Duplicate);
+        }
+        match self.conflict_graph.conflicts_of(&msg.cell) {
+            Ok(conflicts) => {
+                let mut conflicts: Vec<CellHash> = conflicts.into_iter().collect();
+                conflicts.sort();
+                ValidateTxAck { rejected: None, conflicts }
             }
+            Err(e) => rejected(RejectReason::Invalid(format!("{}", Error::from(e)))),
         }
     }
 }
@@ -933,33 +1879,55 @@ impl Handler<QueryTx> for Sleet {
         info!("[{}] Received query for transaction {}", "sleet".cyan(), hex::encode(msg.tx.hash()));
         let id = self.node_id.clone();
         let tx_hash = msg.tx.hash();
-        match self.on_receive_tx(msg.tx.clone()) {
+        // The missing ancestry is fetched first, the width is checked once it is complete
+        let received = if self.has_parents(&msg.tx) && self.exceeds_dag_width(&msg.tx) {
+            Err(Error::DagTooWide)
+        } else {
+            self.on_receive_tx(msg.tx.clone())
+        };
+        match received {
             Ok(is_new) => {
                 if is_new {
                     ctx.notify(FreshTx { tx: msg.tx.clone() });
-                    // TODO we might want this to be a periodic check
+                    // Don't wait for the periodic check, the tx may complete pending ancestry
                     ctx.notify(CheckPending);
                 };
-
-                // We may have accepted or rejected the transaction already when the query comes in
-                if tx_storage::is_accepted_tx(&self.known_txs, &tx_hash).unwrap_or(false) {
-                    return Box::pin(async move { QueryTxAck { id, tx_hash, outcome: true } });
-                }
-                if tx_storage::cannot_be_accepted(&self.known_txs, &tx_hash).unwrap_or(false) {
-                    return Box::pin(async move { QueryTxAck { id, tx_hash, outcome: false } });
-                }
-
-                // FIXME: If we are in the middle of querying this transaction, wait until a
-                // decision or a synchronous timebound is reached on attempts.
-                let outcome = self.is_strongly_preferred(tx_hash.clone()).unwrap();
+                let outcome = self.vote(&tx_hash);
                 Box::pin(async move { QueryTxAck { id, tx_hash, outcome } })
             }
-            Err(Error::MissingAncestry) => {
-                info!("[{}] Transaction query: fetching ancestry for {}", "sleet".cyan(), msg.tx);
+            Err(e) if matches!(e, Error::MissingAncestry | Error::DagTooWide) => {
+                if self.pending_queries.len() >= MAX_PENDING_QUERIES {
+                    warn!(
+                        "[{}] Transaction query: too many pending queries, refusing {}",
+                        "sleet".cyan(),
+                        hex::encode(tx_hash)
+                    );
+                    return Box::pin(async move { QueryTxAck { id, tx_hash, outcome: false } });
+                }
                 let (sender, receiver) = oneshot::channel();
                 self.pending_queries.push((msg.tx.clone(), sender));
-                // Ask the querying node to send us the ancestors of the queried transaction
-                ctx.notify(AskForAncestors { tx_hash: msg.tx.hash(), id: msg.id, ip: msg.ip });
+                if let Error::MissingAncestry = e {
+                    info!(
+                        "[{}] Transaction query: fetching ancestry for {}",
+                        "sleet".cyan(),
+                        msg.tx
+                    );
+                    // Ask the querying node to send us the ancestors of the queried transaction
+                    ctx.notify(AskForAncestors {
+                        tx_hash: msg.tx.hash(),
+                        missing: self.missing_parents(&msg.tx),
+                        id: msg.id,
+                        ip: msg.ip,
+                        round: 0,
+                    });
+                } else {
+                    // Inserted once the DAG narrows down
+                    info!(
+                        "[{}] Transaction query: DAG too wide, deferring {}",
+                        "sleet".cyan(),
+                        msg.tx
+                    );
+                }
                 Box::pin(async move {
                     let timeout = time::sleep(Duration::from_millis(QUERY_RESPONSE_TIMEOUT_MS));
                     tokio::select! {
@@ -978,9 +1946,9 @@ impl Handler<QueryTx> for Sleet {
                         }
                         },
                         () = timeout => {
-                            // Sleet couldn't fetch all ancestors
+                            // Sleet couldn't fetch all ancestors, or the DAG didn't narrow down
                             // TODO: we may also respond with a timeout-like message
-                            info!("Timeout: Couldn't fetch ancestry for {}", hex::encode(tx_hash));
+                            info!("Timeout: Couldn't insert pending {}", hex::encode(tx_hash));
                             QueryTxAck { id, tx_hash, outcome: false }
                         }
                     }
@@ -1000,6 +1968,97 @@ impl Handler<QueryTx> for Sleet {
     }
 }
 
+/// Query a validator about several transactions at once, answered like one [QueryTx] per
+/// transaction. Only sent to peers which advertise [Capabilities::BATCH_QUERY], see [query_txs].
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "QueryTxBatchAck")]
+pub struct QueryTxBatch {
+    /// the node's own Id
+    pub id: Id,
+    /// the node's own listening address, for sending queries back ([GetTxAncestors] in particular)
+    pub ip: SocketAddr,
+    /// the queried transactions, at most [MAX_QUERY_BATCH]
+    pub txs: Vec<Tx>,
+}
+
+/// Response for [QueryTxBatch], with the acks in the order of the queried transactions
+#[derive(Debug, Clone, Serialize, Deserialize, MessageResponse)]
+pub struct QueryTxBatchAck {
+    pub acks: Vec<QueryTxAck>,
+}
+
+impl Handler<QueryTxBatch> for Sleet {
+    type Result = ResponseFuture<QueryTxBatchAck>;
+
+    fn handle(&mut self, msg: QueryTxBatch, ctx: &mut Context<Self>) -> Self::Result {
+        let sleet = ctx.address();
+        let node_id = self.node_id;
+        let QueryTxBatch { id, ip, txs } = msg;
+        Box::pin(async move {
+            let queries =
+                txs.into_iter().map(|tx| {
+                    let tx_hash = tx.hash();
+                    let query = sleet.send(QueryTx { id, ip, tx });
+                    async move {
+                        query.await.unwrap_or(QueryTxAck { id: node_id, tx_hash, outcome: false })
+                    }
+                });
+            QueryTxBatchAck { acks: futures::future::join_all(queries).await }
+        })
+    }
+}
+
+/// Queries the validator `peer` about `txs`, with [QueryTxBatch]es if the `capabilities`
+/// negotiated with the peer support them, or with one [QueryTx] per transaction otherwise.
+///
+/// Returns the acks in the order of `txs`.
+///
+/// ## Parameters
+/// * `client` - the client for sending the queries
+/// * `id`, `ip` - the Id and listening address of this node
+/// * `peer` - the Id and listening address of the queried validator
+/// * `capabilities` - the capabilities negotiated with the peer, see [PeerMeta][crate::view::PeerMeta]
+/// * `txs` - the queried transactions
+pub async fn query_txs(
+    client: &Recipient<ClientRequest>,
+    id: Id,
+    ip: SocketAddr,
+    peer: (Id, SocketAddr),
+    capabilities: &Capabilities,
+    txs: Vec<Tx>,
+) -> Result<Vec<QueryTxAck>> {
+    let requests: Vec<Request> = if capabilities.supports_batch_query() {
+        txs.chunks(MAX_QUERY_BATCH)
+            .map(|chunk| Request::QueryTxBatch(QueryTxBatch { id, ip, txs: chunk.to_vec() }))
+            .collect()
+    } else {
+        txs.into_iter().map(|tx| Request::QueryTx(QueryTx { id, ip, tx })).collect()
+    };
+    // The queries are sent at once, a transaction may only be answered after its parents
+    let responses = futures::future::join_all(requests.into_iter().map(|request| {
+        client.send(ClientRequest::Oneshot {
+            id: peer.0,
+            ip: peer.1,
+            request,
+            timeout: Some(Duration::from_millis(QUERY_TIMEOUT_MS)),
+            retries: 0,
+        })
+    }))
+    .await;
+    let mut acks = vec![];
+    for response in responses {
+        match response.map_err(Error::Actix)?.into_oneshot()? {
+            Some(Response::QueryTxAck(ack)) => acks.push(ack),
+            Some(Response::QueryTxBatchAck(QueryTxBatchAck { acks: mut batch })) => {
+                acks.append(&mut batch)
+            }
+            Some(_) => return Err(Error::Client(ClientError::UnexpectedResponseKind)),
+            None => return Err(Error::Client(ClientError::NoResponse)),
+        }
+    }
+    Ok(acks)
+}
+
 /// Request structure to check and process pending queries from `pending_queries` of [Sleet]
 /// with transactions. If there are - then sends [FreshTx] for new transactions,
 /// or a validator outcome if pending [Tx] is strongly preferred.
@@ -1013,7 +2072,7 @@ impl Handler<CheckPending> for Sleet {
     fn handle(&mut self, _msg: CheckPending, ctx: &mut Context<Self>) -> Self::Result {
         let mut remaining = vec![];
         while let Some((tx, sender)) = self.pending_queries.pop() {
-            if self.has_parents(&tx) {
+            if self.has_parents(&tx) && !self.exceeds_dag_width(&tx) {
                 match self.on_receive_tx(tx.clone()) {
                     Ok(is_new) => {
                         if is_new {
@@ -1036,7 +2095,7 @@ impl Handler<CheckPending> for Sleet {
                 }
             } else if sender.is_closed() {
                 // The pending query timed out, drop the transaction
-                // as we were unable the get its ancestry
+                // as we were unable the get its ancestry, or to fit it in the DAG
                 info!("Dropping pending transaction: {}", tx);
             } else {
                 remaining.push((tx, sender));
@@ -1047,17 +2106,199 @@ impl Handler<CheckPending> for Sleet {
     }
 }
 
+/// Request structure to re-broadcast the undecided transactions of [Sleet] whose last query
+/// didn't complete, for example because the sampled validators were unreachable.
+///
+/// Transactions without a complete query for longer than the re-broadcast age are queried again
+/// with [FreshTx], from a fresh sample of validators. They aren't inserted again, and a failing
+/// re-broadcast doesn't reset the confidence they already gained through their descendants.
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+pub struct RebroadcastPending;
+
+impl Handler<RebroadcastPending> for Sleet {
+    type Result = ();
+
+    fn handle(&mut self, _msg: RebroadcastPending, ctx: &mut Context<Self>) -> Self::Result {
+        if self.drain.is_shutting_down() {
+            return;
+        }
+        let now = Instant::now();
+        let known_txs = self.known_txs.clone();
+        let dag = &self.dag;
+        // Forget the transactions which were decided or pruned in the meantime
+        self.unsettled_txs.retain(|tx_hash, _| {
+            let undecided = match tx_storage::get_tx(&known_txs, *tx_hash) {
+                Ok((_, tx)) => tx.status == TxStatus::Pending || tx.status == TxStatus::Queried,
+                Err(_) => false,
+            };
+            undecided && dag.get_chit(*tx_hash).map(|chit| chit == 0).unwrap_or(false)
+        });
+        let mut rebroadcast = vec![];
+        for (tx_hash, queried_at) in self.unsettled_txs.iter_mut() {
+            if self.rebroadcast_txs.contains(tx_hash)
+                || now.duration_since(*queried_at) < self.rebroadcast_age
+            {
+                continue;
+            }
+            *queried_at = now;
+            rebroadcast.push(*tx_hash);
+        }
+        let mut txs = vec![];
+        for tx_hash in rebroadcast {
+            match tx_storage::get_tx(&self.known_txs, tx_hash) {
+                Ok((_, tx)) => {
+                    info!("[{}] re-broadcasting {}", "sleet".cyan(), hex::encode(tx_hash));
+                    let _ = self.rebroadcast_txs.insert(tx_hash);
+                    txs.push(tx);
+                }
+                Err(e) => {
+                    error!(
+                        "[{}] Couldn't re-broadcast {}: {}",
+                        "sleet".cyan(),
+                        hex::encode(tx_hash),
+                        e
+                    );
+                    let _ = self.unsettled_txs.remove(&tx_hash);
+                }
+            }
+        }
+        // Several transactions are queried at once, with batches if the validators support them
+        if txs.len() > 1 {
+            ctx.notify(FreshTxBatch { txs });
+        } else {
+            for tx in txs {
+                ctx.notify(FreshTx { tx });
+            }
+        }
+    }
+}
+
+/// Queries the validators sampled once about several transactions, like [FreshTx] does for a
+/// single one. Each validator is queried with [query_txs], and the outcome of each transaction
+/// is notified with [QueryComplete] or [QueryIncomplete].
+///
+/// Unlike [FreshTx], the queries don't stop once the outcome is settled with
+/// [fanout_until_settled][client::fanout_until_settled]: a validator answers about all the
+/// transactions at once, whose outcomes settle at different times, so every validator is waited
+/// for (or its queries time out). The outcome on a transaction rejected or removed in the meantime
+/// is ignored.
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+struct FreshTxBatch {
+    txs: Vec<Tx>,
+}
+
+impl Handler<FreshTxBatch> for Sleet {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(&mut self, msg: FreshTxBatch, ctx: &mut Context<Self>) -> Self::Result {
+        if self.drain.is_shutting_down() {
+            return Box::pin(actix::fut::ready(()));
+        }
+        let validators = match self.sample(ALPHA) {
+            Ok(validators) => validators,
+            Err(e) => {
+                // The transactions are queried again by [RebroadcastPending]
+                error!("[{}] couldn't sample validators: {:?}", "sleet".cyan(), e);
+                for tx in msg.txs {
+                    ctx.notify(QueryIncomplete { tx, acks: vec![] });
+                }
+                return Box::pin(actix::fut::ready(()));
+            }
+        };
+        info!("[{}] Querying {} transactions", "sleet".cyan(), msg.txs.len());
+        info!("[{}] sampled {:?}", "sleet".cyan(), validators.clone());
+
+        // If this validator is sampled, its votes are evaluated locally
+        let node_id = self.node_id;
+        let node_ip = self.node_ip;
+        let (own, peers): (Vec<_>, Vec<_>) =
+            validators.into_iter().partition(|(id, _)| *id == node_id);
+        let own_acks: Vec<Option<Response>> = msg
+            .txs
+            .iter()
+            .map(|tx| {
+                if own.is_empty() {
+                    return None;
+                }
+                let tx_hash = tx.hash();
+                let outcome = self.vote(&tx_hash);
+                Some(Response::QueryTxAck(QueryTxAck { id: node_id, tx_hash, outcome }))
+            })
+            .collect();
+
+        let started = Instant::now();
+        let sender = self.sender.clone();
+        let peer_meta = self.peer_meta.clone();
+        let txs = msg.txs.clone();
+        let queries = peers.into_iter().map(move |peer| {
+            let sender = sender.clone();
+            let peer_meta = peer_meta.clone();
+            let txs = txs.clone();
+            async move {
+                // Peers whose capabilities aren't known are queried about each transaction
+                let capabilities = match peer_meta {
+                    Some(peer_meta) => match peer_meta.send(view::GetPeerMeta { id: peer.0 }).await
+                    {
+                        Ok(Some(meta)) => meta.capabilities,
+                        _ => Capabilities::none(),
+                    },
+                    None => Capabilities::none(),
+                };
+                query_txs(&sender, node_id, node_ip, peer, &capabilities, txs).await
+            }
+        });
+        let queries = futures::future::join_all(queries);
+
+        self.drain.started(msg.txs.len());
+
+        let queries = actix::fut::wrap_future::<_, Self>(queries);
+        Box::pin(queries.map(move |results, _act, ctx| {
+            METRICS.sleet_query_latency.observe(started.elapsed());
+            for (i, (tx, own_ack)) in msg.txs.into_iter().zip(own_acks).enumerate() {
+                // The query is complete if every sampled validator voted on the transaction
+                let mut complete = true;
+                let mut acks = vec![];
+                for result in results.iter() {
+                    match result.as_ref().map(|acks| acks.get(i)) {
+                        Ok(Some(ack)) if ack.tx_hash == tx.hash() => {
+                            acks.push(Response::QueryTxAck(ack.clone()))
+                        }
+                        _ => complete = false,
+                    }
+                }
+                acks.extend(own_ack);
+                if complete {
+                    ctx.notify(QueryComplete { tx, acks });
+                } else {
+                    ctx.notify(QueryIncomplete { tx, acks });
+                }
+                // Handled after the outcome of the query
+                ctx.notify(QuerySettled);
+            }
+        }))
+    }
+}
+
 /// A request structure for getting ancestors of a selected transaction from a node.
 /// Notifies [Sleet] with [FreshTx] for each newly received ancestor.
+///
+/// Requests whose missing ancestors are all being fetched already are dropped, even for another
+/// transaction, and a peer whose fetches failed is asked again only after a backoff delay.
 #[derive(Debug, Clone, Serialize, Deserialize, Message)]
 #[rtype(result = "()")]
 pub struct AskForAncestors {
     /// hash of [Tx] to find ancestors for
     pub tx_hash: TxHash,
+    /// the parents of the transaction which are missing, see [Sleet::missing_parents]
+    pub missing: Vec<TxHash>,
     /// the node's own ID
     pub id: Id,
     /// the node's own listening address
     pub ip: SocketAddr,
+    /// the number of truncated responses this request follows, at most [MAX_ANCESTORS_ROUNDS]
+    pub round: usize,
 }
 
 impl Handler<AskForAncestors> for Sleet {
@@ -1065,82 +2306,180 @@ impl Handler<AskForAncestors> for Sleet {
 
     fn handle(
         &mut self,
-        AskForAncestors { tx_hash, id, ip }: AskForAncestors,
+        AskForAncestors { tx_hash, missing, id, ip, round }: AskForAncestors,
         _ctx: &mut Context<Self>,
     ) -> Self::Result {
-        self.sender
-            .send(ClientRequest::Oneshot {
-                id,
-                ip,
-                request: Request::GetTxAncestors(GetTxAncestors { tx_hash }),
-            })
-            .into_actor(self)
-            .map(|res, act, ctx| match res {
+        let missing: Vec<TxHash> =
+            missing.into_iter().filter(|p| !self.pending_fetches.contains(p)).collect();
+        if missing.is_empty() {
+            debug!(
+                "[{}] The missing ancestors of {} are already being fetched",
+                "sleet".cyan(),
+                hex::encode(tx_hash)
+            );
+            return Box::pin(actix::fut::ready(()));
+        }
+        self.pending_fetches.extend(missing.iter().cloned());
+        let backoff = self
+            .fetch_backoffs
+            .get(&id)
+            .map(|(_, retry_at)| retry_at.saturating_duration_since(Instant::now()));
+        let sender = self.sender.clone();
+        let get_ancestors = GetTxAncestors { tx_hash, max_depth: MAX_ANCESTORS_DEPTH };
+        async move {
+            if let Some(backoff) = backoff {
+                time::sleep(backoff).await;
+            }
+            sender
+                .send(ClientRequest::Oneshot {
+                    id,
+                    ip,
+                    request: Request::GetTxAncestors(get_ancestors),
+                    timeout: Some(Duration::from_millis(FETCH_TIMEOUT_MS)),
+                    retries: FETCH_RETRIES,
+                })
+                .await
+        }
+        .into_actor(self)
+        .map(move |res, act, ctx| {
+            for parent in missing.iter() {
+                let _ = act.pending_fetches.remove(parent);
+            }
+            match res {
                 Ok(ClientResponse::Oneshot(Some(Response::TxAncestors(TxAncestors {
-                    ancestors,
+                    mut ancestors,
+                    truncated,
                 })))) => {
-                    for ancestor in ancestors {
-                        match act.on_receive_tx(ancestor.clone()) {
-                            Ok(is_new) => {
-                                if is_new {
-                                    // Start querying
-                                    ctx.notify(FreshTx { tx: ancestor });
-                                };
-                            }
-                            Err(Error::MissingAncestry) => {
-                                // TODO check if this can happen here
-                                info!(
-                                    "[{}] Couldn't insert transaction (missing ancestry): {}",
-                                    "sleet".cyan(),
-                                    ancestor
-                                );
-                            }
-                            Err(e) => {
-                                error!(
-                                    "AskForAncestors: [{}] Couldn't insert new transaction: {}\n{}:\n {}",
-                                    "sleet".cyan(),
-                                    hex::encode(ancestor.hash()),
-                                    ancestor,
-                                    e
-                                );
+                    let _ = act.fetch_backoffs.remove(&id);
+                    // Orphans of earlier responses may be completed by these ancestors
+                    ancestors.append(&mut act.orphan_ancestors);
+                    let orphans = act.insert_ancestors(ancestors, ctx);
+                    if truncated && round + 1 < MAX_ANCESTORS_ROUNDS {
+                        // Ask for the rest of the ancestry, starting from the oldest
+                        // transactions we couldn't insert. Asking again for `tx_hash` would
+                        // make no progress.
+                        let orphan_hashes: HashSet<TxHash> =
+                            orphans.iter().map(|tx| tx.hash()).collect();
+                        for tx in orphans.iter() {
+                            let hash = tx.hash();
+                            if hash != tx_hash
+                                && !tx.parents.iter().any(|p| orphan_hashes.contains(p))
+                            {
+                                let missing = act.missing_parents(tx);
+                                let round = round + 1;
+                                ctx.notify(AskForAncestors {
+                                    tx_hash: hash,
+                                    missing,
+                                    id,
+                                    ip,
+                                    round,
+                                });
                             }
                         }
+                        act.orphan_ancestors = orphans;
+                    } else {
+                        for tx in orphans.iter() {
+                            info!(
+                                "[{}] Couldn't insert transaction (missing ancestry): {}",
+                                "sleet".cyan(),
+                                tx
+                            );
+                        }
                     }
                     // Check if there are pending transactions whose ancestry just arrived
                     ctx.notify(CheckPending);
                 }
-                other => error!("[{}] Unexpected response {:?}", "sleet".cyan(), other),
-            })
-            .boxed_local()
+                other => {
+                    error!("[{}] Unexpected response {:?}", "sleet".cyan(), other);
+                    act.back_off_fetches(id);
+                }
+            }
+        })
+        .boxed_local()
+    }
+}
+
+impl Sleet {
+    /// Delays the next fetch from the peer `id` after a failed one, the delay growing with
+    /// each consecutive failure.
+    fn back_off_fetches(&mut self, id: Id) {
+        let (backoff, retry_at) = self.fetch_backoffs.entry(id).or_insert_with(|| {
+            let backoff = util::Backoff::new(
+                Duration::from_millis(FETCH_BACKOFF_BASE_MS),
+                Duration::from_millis(FETCH_BACKOFF_CAP_MS),
+                FETCH_BACKOFF_JITTER,
+            );
+            (backoff, Instant::now())
+        });
+        *retry_at = Instant::now() + backoff.next_delay(&mut rand::thread_rng());
+    }
+}
+
+impl Sleet {
+    /// Inserts the fetched `ancestors`, parents preceding children, and starts querying
+    /// the new ones.
+    ///
+    /// Returns the ancestors which couldn't be inserted because of missing ancestry.
+    fn insert_ancestors(&mut self, ancestors: Vec<Tx>, ctx: &mut Context<Self>) -> Vec<Tx> {
+        let mut missing = vec![];
+        for ancestor in ancestors {
+            match self.on_receive_tx(ancestor.clone()) {
+                Ok(is_new) => {
+                    if is_new {
+                        // Start querying
+                        ctx.notify(FreshTx { tx: ancestor });
+                    };
+                }
+                Err(Error::MissingAncestry) => missing.push(ancestor),
+                Err(e) => {
+                    error!(
+                        "AskForAncestors: [{}] Couldn't insert new transaction: {}\n{}:\n {}",
+                        "sleet".cyan(),
+                        hex::encode(ancestor.hash()),
+                        ancestor,
+                        e
+                    );
+                }
+            }
+        }
+        missing
     }
 }
 
+/// A request for the ancestors of a transaction, up to `max_depth` generations away from it
+/// (capped at [MAX_ANCESTORS_DEPTH]).
 #[derive(Debug, Clone, Serialize, Deserialize, Message)]
-#[rtype(result = "TxAncestors")]
+#[rtype(result = "Result<TxAncestors>")]
 pub struct GetTxAncestors {
     tx_hash: TxHash,
+    max_depth: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, MessageResponse)]
 pub struct TxAncestors {
+    /// The ancestors, parents preceding children
     pub ancestors: Vec<Tx>,
+    /// `true` if the transaction has older ancestors, which can be requested with
+    /// [GetTxAncestors] for the oldest of `ancestors`
+    pub truncated: bool,
 }
 
 impl Handler<GetTxAncestors> for Sleet {
-    type Result = TxAncestors;
+    type Result = Result<TxAncestors>;
 
     fn handle(
         &mut self,
-        GetTxAncestors { tx_hash }: GetTxAncestors,
+        GetTxAncestors { tx_hash, max_depth }: GetTxAncestors,
         _ctx: &mut Context<Self>,
     ) -> Self::Result {
         let mut ancestors = vec![];
-        let tx_hashes = self.dag.get_ancestors(&tx_hash);
+        let (tx_hashes, truncated) =
+            self.dag.get_ancestors_to_depth(&tx_hash, max_depth.min(MAX_ANCESTORS_DEPTH));
         for hash in tx_hashes {
-            let (_, tx) = tx_storage::get_tx(&self.known_txs, hash).unwrap();
+            let (_, tx) = tx_storage::get_tx(&self.known_txs, hash)?;
             ancestors.push(tx);
         }
-        TxAncestors { ancestors }
+        Ok(TxAncestors { ancestors, truncated })
     }
 }
 
@@ -1150,6 +2489,7 @@ pub mod sleet_status_handler;
 
 /// Re-export message types
 pub use sleet_cell_handlers::*;
+pub use sleet_status_handler::{GetMempoolStats, MempoolStats, TxCounts};
 
 #[cfg(test)]
 mod sleet_tests;
@@ -1,11 +1,14 @@
-use crate::cell::types::CellHash;
+use crate::cell::types::{Capacity, CellHash, PublicKeyHash};
 use crate::cell::Cell;
+use crate::colored::Colorize;
 use crate::sleet::Sleet;
-use crate::storage::tx as tx_storage;
+use crate::storage::tx::{self as tx_storage, StatusTransition};
 use actix::{Context, Handler};
+use tracing::error;
 
 /// A message to get a cell by its hash.
-/// If found, the requested cell is returned from in-memory live-cells which were accepted by consensus (sleet-component).
+/// If found, the requested cell is returned from in-memory live-cells which were accepted by consensus (sleet-component),
+/// or else from the known transactions in storage, whatever their status.
 #[derive(Debug, Clone, Serialize, Deserialize, Message)]
 #[rtype(result = "CellAck")]
 pub struct GetCell {
@@ -21,7 +24,77 @@ impl Handler<GetCell> for Sleet {
     type Result = CellAck;
 
     fn handle(&mut self, msg: GetCell, _ctx: &mut Context<Self>) -> Self::Result {
-        CellAck { cell: self.live_cells.get(&msg.cell_hash).map(|x| x.clone()) }
+        match self.live_cells.get(&msg.cell_hash) {
+            Some(cell) => CellAck { cell: Some(cell.clone()) },
+            None => {
+                let cell =
+                    tx_storage::get_tx(&self.known_txs, msg.cell_hash).ok().map(|(_, tx)| tx.cell);
+                CellAck { cell }
+            }
+        }
+    }
+}
+
+/// A message to get the status transitions of the transaction of a cell, for tracing why it was
+/// accepted or dropped.
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "CellHistory")]
+pub struct GetCellHistory {
+    pub cell_hash: CellHash,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, MessageResponse)]
+pub struct CellHistory {
+    /// The statuses of the transaction, oldest first, with the time they were set in
+    /// milliseconds since the Unix epoch. Empty if the transaction is unknown.
+    pub history: Vec<StatusTransition>,
+}
+
+impl Handler<GetCellHistory> for Sleet {
+    type Result = CellHistory;
+
+    fn handle(&mut self, msg: GetCellHistory, _ctx: &mut Context<Self>) -> Self::Result {
+        let history =
+            tx_storage::get_status_history(&*self.known_txs, &msg.cell_hash).unwrap_or_default();
+        CellHistory { history }
+    }
+}
+
+/// A message to get the conflict set of a cell, for explaining why its transaction isn't
+/// accepted, e.g. because a conflicting cell is preferred.
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "ConflictSetAck")]
+pub struct GetConflictSet {
+    pub cell_hash: CellHash,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, MessageResponse)]
+pub struct ConflictSetAck {
+    /// The sorted cells spending some of the same outputs, including the requested one.
+    /// Empty if the cell isn't in the conflict graph, and a singleton if it has no conflicts.
+    pub members: Vec<CellHash>,
+    /// The preferred cell of the conflict set, or the requested one if it is unknown
+    pub preferred: CellHash,
+    /// The confidence counter of the conflict set
+    pub confidence: u8,
+}
+
+impl Handler<GetConflictSet> for Sleet {
+    type Result = ConflictSetAck;
+
+    fn handle(&mut self, msg: GetConflictSet, _ctx: &mut Context<Self>) -> Self::Result {
+        match self.conflict_graph.conflicting_cells(&msg.cell_hash) {
+            Some(conflict_set) => {
+                let mut members: Vec<CellHash> = conflict_set.conflicts.iter().cloned().collect();
+                members.sort();
+                ConflictSetAck {
+                    members,
+                    preferred: conflict_set.pref,
+                    confidence: conflict_set.cnt,
+                }
+            }
+            None => ConflictSetAck { members: vec![], preferred: msg.cell_hash, confidence: 0 },
+        }
     }
 }
 
@@ -44,22 +117,68 @@ impl Handler<GetCellHashes> for Sleet {
     }
 }
 
+/// A message to get the unspent outputs of accepted cells owned by `owner`, so that wallets
+/// don't need to fetch every live cell.
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "UtxosAck")]
+pub struct GetUtxosByOwner {
+    pub owner: PublicKeyHash,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, MessageResponse)]
+pub struct UtxosAck {
+    /// The cell hash, output index and capacity of each unspent output
+    pub utxos: Vec<(CellHash, u8, Capacity)>,
+}
+
+impl Handler<GetUtxosByOwner> for Sleet {
+    type Result = UtxosAck;
+
+    fn handle(&mut self, msg: GetUtxosByOwner, _ctx: &mut Context<Self>) -> Self::Result {
+        let mut utxos: Vec<(CellHash, u8, Capacity)> = match self.utxos.get(&msg.owner) {
+            Some(utxos) => utxos.iter().cloned().collect(),
+            None => vec![],
+        };
+        utxos.sort();
+        UtxosAck { utxos }
+    }
+}
+
+/// The maximum number of hashes in a page of [AcceptedCellHashes]
+pub const MAX_ACCEPTED_CELL_HASHES_PAGE: usize = 1000;
+
+/// A message to get a page of the hashes of accepted cells, ordered by hash.
 #[derive(Debug, Clone, Serialize, Deserialize, Message)]
 #[rtype(result = "AcceptedCellHashes")]
-pub struct GetAcceptedCellHashes;
+pub struct GetAcceptedCellHashes {
+    /// Only return hashes after this one, i.e. the `next` cursor of the previous page
+    pub after: Option<CellHash>,
+    /// The maximum number of hashes in the page, clamped to `1..=`[MAX_ACCEPTED_CELL_HASHES_PAGE].
+    pub limit: usize,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, MessageResponse)]
 pub struct AcceptedCellHashes {
     pub ids: Vec<CellHash>,
+    /// The cursor for requesting the next page, `None` if this is the last page
+    pub next: Option<CellHash>,
 }
 
 impl Handler<GetAcceptedCellHashes> for Sleet {
     type Result = AcceptedCellHashes;
 
-    fn handle(&mut self, _msg: GetAcceptedCellHashes, _ctx: &mut Context<Self>) -> Self::Result {
-        return AcceptedCellHashes {
-            ids: self.accepted_txs.iter().cloned().collect::<Vec<CellHash>>(),
-        };
+    fn handle(&mut self, msg: GetAcceptedCellHashes, _ctx: &mut Context<Self>) -> Self::Result {
+        let limit = msg.limit.max(1).min(MAX_ACCEPTED_CELL_HASHES_PAGE);
+        match tx_storage::get_accepted_page(&self.known_txs, msg.after.as_ref(), limit) {
+            Ok((ids, more)) => {
+                let next = if more { ids.last().cloned() } else { None };
+                AcceptedCellHashes { ids, next }
+            }
+            Err(e) => {
+                error!("[{}] couldn't read the accepted cells: {:?}", "sleet".cyan(), e);
+                AcceptedCellHashes { ids: vec![], next: None }
+            }
+        }
     }
 }
 
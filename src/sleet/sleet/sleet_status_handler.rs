@@ -1,5 +1,7 @@
-use crate::alpha::types::Weight;
+use crate::alpha::types::{TxHash, Weight};
+use crate::sleet::tx::{Tx, TxStatus};
 use crate::sleet::Sleet;
+use crate::storage::{self, tx as tx_storage, KvStore};
 use crate::zfx_id::Id;
 use actix::{Context, Handler};
 use std::net::SocketAddr;
@@ -29,3 +31,105 @@ impl Handler<CheckStatus> for Sleet {
         Status { node_id: self.node_id, validators }
     }
 }
+
+/// The number of known transactions by [TxStatus], kept in step with the statuses in storage.
+/// Only the transactions received since the node started are counted.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TxCounts {
+    pub pending: usize,
+    pub queried: usize,
+    pub accepted: usize,
+    pub rejected: usize,
+    pub removed: usize,
+}
+
+impl TxCounts {
+    fn count_mut(&mut self, status: &TxStatus) -> &mut usize {
+        match status {
+            TxStatus::Pending => &mut self.pending,
+            TxStatus::Queried => &mut self.queried,
+            TxStatus::Accepted => &mut self.accepted,
+            TxStatus::Rejected => &mut self.rejected,
+            TxStatus::Removed => &mut self.removed,
+        }
+    }
+
+    fn update(&mut self, previous: Option<&TxStatus>, status: &TxStatus) {
+        if let Some(previous) = previous {
+            let count = self.count_mut(previous);
+            *count = count.saturating_sub(1);
+        }
+        *self.count_mut(status) += 1;
+    }
+
+    /// Stores a new or re-issued transaction, counting it under its status.
+    pub(super) fn insert_tx(&mut self, db: &dyn KvStore, tx: Tx) -> storage::Result<()> {
+        let status = tx.status.clone();
+        let previous = match tx_storage::insert_tx(db, tx)? {
+            Some(v) => Some(bincode::deserialize::<Tx>(&v)?.status),
+            None => None,
+        };
+        self.update(previous.as_ref(), &status);
+        Ok(())
+    }
+
+    /// Sets the status of a known transaction, moving it to the matching count. Unknown
+    /// transactions are left out of the counts.
+    pub(super) fn set_status(
+        &mut self,
+        db: &dyn KvStore,
+        tx_hash: &TxHash,
+        status: TxStatus,
+    ) -> storage::Result<()> {
+        let mut previous = None;
+        let _ = tx_storage::update_and_fetch(db, tx_hash, |tx| {
+            tx.map(|mut tx| {
+                previous = Some(tx.status);
+                tx.status = status.clone();
+                tx
+            })
+        })?;
+        if previous.is_some() {
+            self.update(previous.as_ref(), &status);
+        }
+        Ok(())
+    }
+}
+
+/// A message to get the state of the mempool of [sleet](crate::sleet), for monitoring.
+/// Returns [MempoolStats], computed without traversing the DAG.
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "MempoolStats")]
+pub struct GetMempoolStats;
+
+/// Response for [GetMempoolStats]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, MessageResponse)]
+pub struct MempoolStats {
+    /// The number of transactions in each status
+    pub txs: TxCounts,
+    /// The number of vertices in the consensus DAG
+    pub dag_len: usize,
+    /// The number of transactions in the conflict graph
+    pub conflict_graph_len: usize,
+    /// The number of live cells held in memory
+    pub live_cells: usize,
+    /// The maximum number of live cells held in memory
+    pub live_cells_capacity: usize,
+    /// The number of transactions on the accepted frontier
+    pub accepted_frontier: usize,
+}
+
+impl Handler<GetMempoolStats> for Sleet {
+    type Result = MempoolStats;
+
+    fn handle(&mut self, _msg: GetMempoolStats, _ctx: &mut Context<Self>) -> Self::Result {
+        MempoolStats {
+            txs: self.tx_counts.clone(),
+            dag_len: self.dag.len(),
+            conflict_graph_len: self.conflict_graph.len(),
+            live_cells: self.live_cells.len(),
+            live_cells_capacity: self.mempool_capacity,
+            accepted_frontier: self.accepted_frontier.len(),
+        }
+    }
+}
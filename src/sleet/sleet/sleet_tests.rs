@@ -2,15 +2,24 @@
 
 use super::*;
 
+use crate::alpha::block::Block;
 use crate::alpha::coinbase::CoinbaseOperation;
-use crate::alpha::transfer::TransferOperation;
-use crate::cell::Cell;
-
-use actix::{Addr, ResponseFuture};
+use crate::alpha::slash::{self, SignedBlock, SlashEvidence};
+use crate::alpha::transfer::{transfer_output, TransferOperation};
+use crate::cell::inputs::{Input, Inputs};
+use crate::cell::output::Output;
+use crate::cell::outputs::Outputs;
+use crate::cell::types::{FEE, MIN_FEE};
+use crate::cell::{Cell, CellType, SignatureScheme};
+use crate::integration_test::test_model::{build_dag, dag_txs, DagSpec};
+use crate::storage::{InMemoryStore, TestStore};
+
+use actix::{Addr, MailboxError, ResponseFuture};
 use ed25519_dalek::Keypair;
 use rand::rngs::OsRng;
 
 use std::convert::TryInto;
+use std::sync::Mutex;
 use std::time::Instant;
 
 fn generate_coinbase(keypair: &Keypair, amount: u64) -> Cell {
@@ -83,11 +92,12 @@ pub struct GetStatus;
 #[allow(unused)] // Some fields are never read currently
 #[derive(Debug, Clone, MessageResponse)]
 pub struct SleetStatus {
-    known_txs: sled::Db,
+    known_txs: Arc<dyn KvStore>,
     conflict_graph_len: usize,
     live_cells: HashMap<CellHash, Cell>,
     accepted_txs: HashSet<TxHash>,
     dag_len: usize,
+    dag_leaves: usize,
     accepted_frontier: HashSet<TxHash>,
 }
 
@@ -101,6 +111,7 @@ impl Handler<GetStatus> for Sleet {
             live_cells: self.live_cells.clone(),
             accepted_txs: self.accepted_txs.clone(),
             dag_len: self.dag.len(),
+            dag_leaves: self.dag.leaf_count(),
             accepted_frontier: self.accepted_frontier.clone(),
         }
     }
@@ -127,7 +138,7 @@ fn make_live_committee(cells: Vec<Cell>) -> LiveCommittee {
     for c in cells {
         live_cells.insert(c.hash(), c.clone());
     }
-    LiveCommittee { validators, live_cells }
+    LiveCommittee { validators, live_cells, height: 0 }
 }
 
 struct DummyClient {
@@ -135,12 +146,27 @@ struct DummyClient {
     pub responses: Vec<(Id, bool)>,
     // For answering `GetAncestors` messages
     pub ancestors: Vec<Tx>,
+    // Whether the `GetAncestors` responses are truncated
+    pub truncated: bool,
+    // Delay before answering `QueryTx` and `GetTxAncestors` queries
+    pub delay: Option<Duration>,
+    // The validators which were sent `QueryTx` queries
+    pub queried: Arc<Mutex<Vec<Id>>>,
+    // The transactions whose ancestors were requested
+    pub fetched: Arc<Mutex<Vec<TxHash>>>,
 }
 
 /// Client substitute for answering `QueryTx` queries
 impl DummyClient {
     pub fn new() -> Self {
-        Self { responses: vec![], ancestors: vec![] }
+        Self {
+            responses: vec![],
+            ancestors: vec![],
+            truncated: false,
+            delay: None,
+            queried: Default::default(),
+            fetched: Default::default(),
+        }
     }
 }
 impl Actor for DummyClient {
@@ -189,35 +215,63 @@ async fn set_ancestors(client: Addr<DummyClient>, ancestors: Vec<Tx>) {
     client.send(SetAncestors { ancestors }).await.unwrap();
 }
 
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+struct SetDelay {
+    pub delay: Option<Duration>,
+}
+impl Handler<SetDelay> for DummyClient {
+    type Result = ();
+
+    fn handle(&mut self, SetDelay { delay }: SetDelay, _ctx: &mut Context<Self>) -> Self::Result {
+        self.delay = delay;
+    }
+}
+
 impl Handler<ClientRequest> for DummyClient {
     type Result = ResponseFuture<ClientResponse>;
 
     fn handle(&mut self, msg: ClientRequest, _ctx: &mut Context<Self>) -> Self::Result {
         let responses = self.responses.clone();
+        let delay = self.delay;
         match msg {
-            ClientRequest::Fanout { peers: _, request } => Box::pin(async move {
-                let r = match request {
-                    Request::QueryTx(QueryTx { tx, .. }) => responses
-                        .iter()
-                        .map(|(id, outcome)| {
-                            Response::QueryTxAck(QueryTxAck {
-                                id: id.clone(),
-                                tx_hash: tx.hash(),
-                                outcome: outcome.clone(),
+            ClientRequest::Fanout { peers, request, .. } => {
+                self.queried.lock().unwrap().extend(peers.iter().map(|(id, _)| *id));
+                Box::pin(async move {
+                    if let Some(delay) = delay {
+                        tokio::time::sleep(delay).await;
+                    }
+                    let r = match request {
+                        Request::QueryTx(QueryTx { tx, .. }) => responses
+                            .iter()
+                            .map(|(id, outcome)| {
+                                let ack = Response::QueryTxAck(QueryTxAck {
+                                    id: id.clone(),
+                                    tx_hash: tx.hash(),
+                                    outcome: outcome.clone(),
+                                });
+                                (id.clone(), Ok(ack))
                             })
-                        })
-                        .collect(),
-                    x => panic!("unexpected request: {:?}", x),
-                };
-                ClientResponse::Fanout(r)
-            }),
-            ClientRequest::Oneshot { id: _, ip: _, request } => {
+                            .collect(),
+                        x => panic!("unexpected request: {:?}", x),
+                    };
+                    ClientResponse::Fanout(r)
+                })
+            }
+            ClientRequest::Oneshot { id: _, ip: _, request, .. } => {
                 let ancestors = self.ancestors.clone();
+                let truncated = self.truncated;
+                if let Request::GetTxAncestors(GetTxAncestors { tx_hash, .. }) = &request {
+                    self.fetched.lock().unwrap().push(*tx_hash);
+                }
                 Box::pin(async move {
+                    if let Some(delay) = delay {
+                        tokio::time::sleep(delay).await;
+                    }
                     let r = match request {
                         Request::GetTxAncestors(GetTxAncestors { .. }) => {
                             println!("GetAncestors");
-                            Response::TxAncestors(TxAncestors { ancestors })
+                            Response::TxAncestors(TxAncestors { ancestors, truncated })
                         }
                         x => panic!("unexpected request: {:?}", x),
                     };
@@ -231,10 +285,11 @@ impl Handler<ClientRequest> for DummyClient {
 /// Receives accepted transactions from Sleet and stores them in a vector
 struct HailMock {
     pub accepted: Vec<Cell>,
+    pub fees: Capacity,
 }
 impl HailMock {
     pub fn new() -> Self {
-        Self { accepted: vec![] }
+        Self { accepted: vec![], fees: 0 }
     }
 }
 impl Actor for HailMock {
@@ -247,7 +302,8 @@ impl Handler<AcceptedCells> for HailMock {
     type Result = ();
 
     fn handle(&mut self, msg: AcceptedCells, _ctx: &mut Context<Self>) -> Self::Result {
-        self.accepted.extend_from_slice(&msg.cells[..])
+        self.accepted.extend_from_slice(&msg.cells[..]);
+        self.fees += msg.fees;
     }
 }
 
@@ -263,6 +319,18 @@ impl Handler<GetAcceptedCells> for HailMock {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "Capacity")]
+struct GetAcceptedFees;
+
+impl Handler<GetAcceptedFees> for HailMock {
+    type Result = Capacity;
+
+    fn handle(&mut self, _msg: GetAcceptedFees, _ctx: &mut Context<Self>) -> Self::Result {
+        self.fees
+    }
+}
+
 async fn start_test_env() -> (Addr<Sleet>, Addr<DummyClient>, Addr<HailMock>, Keypair, Cell) {
     // Uncomment to see Sleet's logs
     // let _ = tracing_subscriber::fmt().compact().with_max_level(tracing::Level::INFO).try_init();
@@ -379,13 +447,13 @@ async fn test_duplicate_tx() {
     let cell = generate_transfer(&root_kp, genesis_tx.clone(), 1);
     let hash = cell.hash();
     match sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap() {
-        GenerateTxAck { cell_hash: Some(h) } => assert!(hash == h),
+        GenerateTxAck { cell_hash: Some(h), rejected: None } => assert!(hash == h),
         other => panic!("unexpected: {:?}", other),
     }
 
     // Trying the same tx a second time
     match sleet.send(GenerateTx { cell }).await.unwrap() {
-        GenerateTxAck { cell_hash: None } => (),
+        GenerateTxAck { cell_hash: None, rejected: Some(RejectReason::Duplicate) } => (),
         other => panic!("unexpected: {:?}", other),
     }
 
@@ -397,6 +465,290 @@ async fn test_duplicate_tx() {
     assert!(accepted.is_empty());
 }
 
+#[actix_rt::test]
+async fn test_mempool_full() {
+    let mut client = DummyClient::new();
+    // Nothing gets accepted, so that the transactions stay in the mempool
+    client.responses = vec![(mock_validator_id(), false)];
+    let client = client.start();
+    let hail = HailMock::new().start();
+    let sleet = Sleet::new(client.recipient(), hail.recipient(), Id::zero(), mock_ip(), vec![])
+        .with_mempool_capacity(10)
+        .start();
+    let root_kp = Keypair::generate(&mut OsRng {});
+    let genesis_tx = generate_coinbase(&root_kp, 10000);
+    sleet.send(make_live_committee(vec![genesis_tx.clone()])).await.unwrap();
+
+    // The high-water mark is at 9 undecided transactions
+    let mut cell = genesis_tx;
+    for i in 0..9 {
+        cell = generate_transfer(&root_kp, cell, i + 1);
+        match sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap() {
+            GenerateTxAck { cell_hash: Some(_), rejected: None } => (),
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+    for i in 0..3 {
+        let next = generate_transfer(&root_kp, cell.clone(), 10 + i);
+        match sleet.send(GenerateTx { cell: next }).await.unwrap() {
+            GenerateTxAck { cell_hash: None, rejected: Some(RejectReason::MempoolFull) } => (),
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+}
+
+#[actix_rt::test]
+async fn test_accepted_txs_leave_the_mempool() {
+    let mut client = DummyClient::new();
+    client.responses = vec![(mock_validator_id(), true)];
+    let client = client.start();
+    let hail = HailMock::new().start();
+    let sleet = Sleet::new(client.recipient(), hail.recipient(), Id::zero(), mock_ip(), vec![])
+        .with_mempool_capacity(20)
+        .start();
+    let root_kp = Keypair::generate(&mut OsRng {});
+    let genesis_tx = generate_coinbase(&root_kp, 10000);
+    sleet.send(make_live_committee(vec![genesis_tx.clone()])).await.unwrap();
+
+    // Far more transactions than the capacity, but only the last `BETA1` stay undecided
+    let mut cell = genesis_tx;
+    for i in 0..60 {
+        cell = generate_transfer(&root_kp, cell, i + 1);
+        match sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap() {
+            GenerateTxAck { cell_hash: Some(_), rejected: None } => (),
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+}
+
+#[actix_rt::test]
+async fn test_fee_of_evicted_spent_output() {
+    let mut client = DummyClient::new();
+    client.responses = vec![(mock_validator_id(), true)];
+    let client = client.start();
+    let hail = HailMock::new().start();
+    let sleet =
+        Sleet::new(client.recipient(), hail.clone().recipient(), Id::zero(), mock_ip(), vec![])
+            .with_mempool_capacity(20)
+            .start();
+    let root_kp = Keypair::generate(&mut OsRng {});
+    let genesis_tx = generate_coinbase(&root_kp, 10000);
+    sleet.send(make_live_committee(vec![genesis_tx.clone()])).await.unwrap();
+
+    let cell0 = generate_transfer(&root_kp, genesis_tx, 1);
+    sleet.send(GenerateTx { cell: cell0.clone() }).await.unwrap();
+    // The spent genesis output is evicted from the live cells before `cell0` is accepted
+    let other_cells = (0..20).map(|i| generate_coinbase(&root_kp, 100 + i)).collect();
+    sleet.send(make_live_committee(other_cells)).await.unwrap();
+    let mut cell = cell0.clone();
+    for i in 1..BETA1 as u64 {
+        cell = generate_transfer(&root_kp, cell, i + 1);
+        sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap();
+    }
+
+    assert_eq!(hail.send(GetAcceptedCells).await.unwrap(), vec![cell0]);
+    assert_eq!(hail.send(GetAcceptedFees).await.unwrap(), FEE);
+}
+
+#[actix_rt::test]
+async fn test_get_cell_from_storage() {
+    let mut client = DummyClient::new();
+    client.responses = vec![(mock_validator_id(), true)];
+    let client = client.start();
+    let hail = HailMock::new().start();
+    let sleet =
+        Sleet::new(client.recipient(), hail.clone().recipient(), Id::zero(), mock_ip(), vec![])
+            .with_mempool_capacity(20)
+            .start();
+    let root_kp = Keypair::generate(&mut OsRng {});
+    let genesis_tx = generate_coinbase(&root_kp, 10000);
+    sleet.send(make_live_committee(vec![genesis_tx.clone()])).await.unwrap();
+
+    let mut cells = vec![];
+    let mut cell = genesis_tx;
+    for i in 0..30 {
+        cell = generate_transfer(&root_kp, cell, i + 1);
+        sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap();
+        cells.push(cell.clone());
+    }
+    let accepted = hail.send(GetAcceptedCells).await.unwrap();
+    assert!(accepted.contains(&cells[0]));
+
+    // The first cell was accepted and evicted from the live cells, but is still known
+    let CellHashes { ids } = sleet.send(GetCellHashes).await.unwrap();
+    assert!(!ids.contains(&cells[0].hash()));
+    let CellAck { cell } = sleet.send(GetCell { cell_hash: cells[0].hash() }).await.unwrap();
+    assert_eq!(cell, Some(cells[0].clone()));
+
+    let CellAck { cell } = sleet.send(GetCell { cell_hash: [7; 32] }).await.unwrap();
+    assert_eq!(cell, None);
+}
+
+#[actix_rt::test]
+async fn test_max_dag_width() {
+    const MAX_WIDTH: usize = 8;
+
+    let mut client = DummyClient::new();
+    // Nothing gets accepted, so that the leaves stay in the DAG
+    client.responses = vec![(mock_validator_id(), false)];
+    let client = client.start();
+    let hail = HailMock::new().start();
+    let sleet = Sleet::new(client.recipient(), hail.recipient(), Id::zero(), mock_ip(), vec![])
+        .with_max_dag_width(MAX_WIDTH)
+        .with_check_pending_interval(Duration::from_millis(50))
+        .start();
+    let root_kp = Keypair::generate(&mut OsRng {});
+    let genesis_txs: Vec<Cell> = (0..41).map(|i| generate_coinbase(&root_kp, 10000 + i)).collect();
+    sleet.send(make_live_committee(genesis_txs.clone())).await.unwrap();
+
+    // A flood of independent transactions
+    for genesis_tx in genesis_txs[..40].iter() {
+        let tx = Tx::new(vec![], generate_transfer(&root_kp, genesis_tx.clone(), 1));
+        sleet.do_send(QueryTx { id: Id::two(), ip: mock_ip(), tx });
+    }
+    sleep_ms(100).await;
+    let SleetStatus { dag_len, dag_leaves, .. } = sleet.send(GetStatus).await.unwrap();
+    assert_eq!(dag_leaves, MAX_WIDTH);
+    assert_eq!(dag_len, MAX_WIDTH);
+
+    // Generated transactions narrow the DAG down, making room for the deferred ones
+    let mut cell = genesis_txs[40].clone();
+    for i in 0..3 {
+        cell = generate_transfer(&root_kp, cell, i + 1);
+        let ack = sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap();
+        assert!(ack.cell_hash.is_some());
+        sleep_ms(200).await;
+        let SleetStatus { dag_leaves, .. } = sleet.send(GetStatus).await.unwrap();
+        assert!(dag_leaves <= MAX_WIDTH);
+    }
+    let SleetStatus { dag_len, .. } = sleet.send(GetStatus).await.unwrap();
+    assert_eq!(dag_len, 4 * MAX_WIDTH);
+}
+
+#[actix_rt::test]
+async fn test_max_dag_width_fetched_ancestors() {
+    const MAX_WIDTH: usize = 8;
+
+    let mut client = DummyClient::new();
+    client.responses = vec![(mock_validator_id(), false)];
+    let client = client.start();
+    let hail = HailMock::new().start();
+    let sleet =
+        Sleet::new(client.clone().recipient(), hail.recipient(), Id::zero(), mock_ip(), vec![])
+            .with_max_dag_width(MAX_WIDTH)
+            .with_check_pending_interval(Duration::from_millis(50))
+            .start();
+    let root_kp = Keypair::generate(&mut OsRng {});
+    let genesis_txs: Vec<Cell> =
+        (0..MAX_WIDTH + 1).map(|i| generate_coinbase(&root_kp, 10000 + i as u64)).collect();
+    sleet.send(make_live_committee(genesis_txs.clone())).await.unwrap();
+    for genesis_tx in genesis_txs[..MAX_WIDTH].iter() {
+        let tx = Tx::new(vec![], generate_transfer(&root_kp, genesis_tx.clone(), 1));
+        sleet.do_send(QueryTx { id: Id::two(), ip: mock_ip(), tx });
+    }
+    sleep_ms(100).await;
+
+    // Querying `parent` directly would add a leaf, it is deferred
+    let parent = Tx::new(vec![], generate_transfer(&root_kp, genesis_txs[MAX_WIDTH].clone(), 1));
+    let child = Tx::new(vec![parent.hash()], generate_transfer(&root_kp, parent.cell.clone(), 2));
+    sleet.do_send(QueryTx { id: Id::two(), ip: mock_ip(), tx: parent.clone() });
+    sleep_ms(200).await;
+    let SleetStatus { dag_len, .. } = sleet.send(GetStatus).await.unwrap();
+    assert_eq!(dag_len, MAX_WIDTH);
+
+    // `parent` is inserted when fetched as the ancestor of the queried `child`
+    set_ancestors(client, vec![parent]).await;
+    sleet.do_send(QueryTx { id: Id::two(), ip: mock_ip(), tx: child });
+    sleep_ms(200).await;
+    let SleetStatus { dag_len, dag_leaves, .. } = sleet.send(GetStatus).await.unwrap();
+    assert_eq!(dag_len, MAX_WIDTH + 2);
+    assert_eq!(dag_leaves, MAX_WIDTH + 1);
+}
+
+#[actix_rt::test]
+async fn test_max_pending_queries() {
+    let client = DummyClient::new().start();
+    let hail = HailMock::new().start();
+    let sleet = Sleet::new(client.recipient(), hail.recipient(), Id::zero(), mock_ip(), vec![])
+        .with_max_dag_width(1)
+        .start();
+    let root_kp = Keypair::generate(&mut OsRng {});
+    let genesis_txs: Vec<Cell> = (0..MAX_PENDING_QUERIES + 2)
+        .map(|i| generate_coinbase(&root_kp, 1000 + i as u64))
+        .collect();
+    sleet.send(make_live_committee(genesis_txs.clone())).await.unwrap();
+    let txs: Vec<Tx> = genesis_txs
+        .iter()
+        .map(|genesis_tx| Tx::new(vec![], generate_transfer(&root_kp, genesis_tx.clone(), 1)))
+        .collect();
+
+    // The first transaction fills the DAG, the following ones are deferred
+    for tx in txs[..MAX_PENDING_QUERIES + 1].iter() {
+        sleet.do_send(QueryTx { id: Id::two(), ip: mock_ip(), tx: tx.clone() });
+    }
+    // Beyond the limit, queries are answered right away
+    let tx = txs[MAX_PENDING_QUERIES + 1].clone();
+    let ack = tokio::time::timeout(
+        Duration::from_millis(QUERY_RESPONSE_TIMEOUT_MS / 2),
+        sleet.send(QueryTx { id: Id::two(), ip: mock_ip(), tx }),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert!(!ack.outcome);
+}
+
+#[actix_rt::test]
+async fn test_custom_cell_type() {
+    let client = DummyClient::new().start();
+    let hail = HailMock::new().start();
+    let mut cell_types = CellTypeRegistry::new();
+    cell_types.register(
+        7,
+        Box::new(|output: &Output| {
+            if output.data.is_empty() {
+                return Err(crate::cell::Error::InvalidCellData(7));
+            }
+            Ok(())
+        }),
+    );
+    let sleet = Sleet::new(client.recipient(), hail.recipient(), Id::zero(), mock_ip(), vec![])
+        .with_cell_types(cell_types)
+        .start();
+    let root_kp = Keypair::generate(&mut OsRng {});
+    let genesis_tx = generate_coinbase(&root_kp, 1000);
+    sleet.send(make_live_committee(vec![genesis_tx.clone()])).await.unwrap();
+
+    let custom_cell = |index: u8, tag: u16, data: Vec<u8>| {
+        let input = Input::new(&root_kp, genesis_tx.hash(), index).unwrap();
+        let output = Output {
+            capacity: 900,
+            cell_type: CellType::Custom(tag),
+            data,
+            lock: new_pkh(),
+            locktime: None,
+            scheme: SignatureScheme::Ed25519,
+        };
+        Cell::new(Inputs::new(vec![input]), Outputs::new(vec![output]))
+    };
+
+    // The registered validator rejects custom outputs without data
+    match sleet.send(GenerateTx { cell: custom_cell(0, 7, vec![]) }).await.unwrap() {
+        GenerateTxAck { cell_hash: None, .. } => (),
+        other => panic!("unexpected: {:?}", other),
+    }
+    // Custom cell types without a validator are refused
+    match sleet.send(GenerateTx { cell: custom_cell(1, 8, vec![1, 2, 3]) }).await.unwrap() {
+        GenerateTxAck { cell_hash: None, .. } => (),
+        other => panic!("unexpected: {:?}", other),
+    }
+    let valid = custom_cell(2, 7, vec![1, 2, 3]);
+    match sleet.send(GenerateTx { cell: valid.clone() }).await.unwrap() {
+        GenerateTxAck { cell_hash: Some(hash), .. } => assert_eq!(hash, valid.hash()),
+        other => panic!("unexpected: {:?}", other),
+    }
+}
+
 #[actix_rt::test]
 async fn test_coinbase_tx() {
     let (sleet, _client, _hail, root_kp, _genesis_tx) = start_test_env().await;
@@ -406,7 +758,7 @@ async fn test_coinbase_tx() {
 
     // Trying to insert a coinbase tx
     match sleet.send(GenerateTx { cell }).await.unwrap() {
-        GenerateTxAck { cell_hash: None } => (),
+        GenerateTxAck { cell_hash: None, rejected: Some(RejectReason::CoinbaseNotAllowed) } => (),
         other => panic!("unexpected: {:?}", other),
     }
 
@@ -415,6 +767,30 @@ async fn test_coinbase_tx() {
     assert!(!hashes.ids.contains(&hash));
 }
 
+#[actix_rt::test]
+async fn test_slash_tx() {
+    let (sleet, _client, _hail, _root_kp, genesis_tx) = start_test_env().await;
+
+    let keypair = Keypair::generate(&mut OsRng);
+    let block = |vrf_out| Block::new(genesis_tx.hash(), 1, vrf_out, vec![]);
+    let evidence = SlashEvidence {
+        height: 1,
+        block_a: SignedBlock::new(&keypair, block([1u8; 32])).unwrap(),
+        block_b: SignedBlock::new(&keypair, block([2u8; 32])).unwrap(),
+    };
+    let cell = slash::slash_cell(&evidence).unwrap();
+
+    // Slash cells pay no fee and are refused by the mempool
+    match sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap() {
+        GenerateTxAck { cell_hash: None, rejected: Some(RejectReason::Invalid(_)) } => (),
+        other => panic!("unexpected: {:?}", other),
+    }
+    match sleet.send(ValidateTx { cell }).await.unwrap() {
+        ValidateTxAck { rejected: Some(RejectReason::Invalid(_)), .. } => (),
+        other => panic!("unexpected: {:?}", other),
+    }
+}
+
 #[actix_rt::test]
 async fn test_spend_nonexistent_funds() {
     let (sleet, _client, _hail, root_kp, _genesis_tx) = start_test_env().await;
@@ -423,7 +799,167 @@ async fn test_spend_nonexistent_funds() {
     let bad_cell = generate_transfer(&root_kp, unknown_coinbase, 1);
 
     match sleet.send(GenerateTx { cell: bad_cell }).await.unwrap() {
-        GenerateTxAck { cell_hash: None } => (),
+        GenerateTxAck { cell_hash: None, rejected: Some(RejectReason::Invalid(_)) } => (),
+        other => panic!("unexpected: {:?}", other),
+    }
+}
+
+#[actix_rt::test]
+async fn test_cell_limits() {
+    let (sleet, _client, _hail, root_kp, genesis_tx) = start_test_env().await;
+
+    let output = |data: Vec<u8>| Output {
+        capacity: 1,
+        cell_type: CellType::Transfer,
+        data,
+        lock: new_pkh(),
+        locktime: None,
+        scheme: SignatureScheme::Ed25519,
+    };
+    let input = |index: u8| Input::new(&root_kp, genesis_tx.hash(), index).unwrap();
+
+    let inputs = (0..=MAX_CELL_INPUTS).map(|i| input(i as u8)).collect();
+    let cell = Cell::new(Inputs::new(inputs), Outputs::new(vec![output(vec![])]));
+    match sleet.send(GenerateTx { cell }).await.unwrap() {
+        GenerateTxAck { cell_hash: None, rejected: Some(RejectReason::TooManyInputs) } => (),
+        other => panic!("unexpected: {:?}", other),
+    }
+
+    let outputs = (0..=MAX_CELL_OUTPUTS).map(|_| output(vec![])).collect();
+    let cell = Cell::new(Inputs::new(vec![input(0)]), Outputs::new(outputs));
+    match sleet.send(GenerateTx { cell }).await.unwrap() {
+        GenerateTxAck { cell_hash: None, rejected: Some(RejectReason::TooManyOutputs) } => (),
+        other => panic!("unexpected: {:?}", other),
+    }
+
+    let data = vec![0; MAX_CELL_SIZE as usize];
+    let cell = Cell::new(Inputs::new(vec![input(0)]), Outputs::new(vec![output(data)]));
+    match sleet.send(GenerateTx { cell }).await.unwrap() {
+        GenerateTxAck { cell_hash: None, rejected: Some(RejectReason::DataTooLarge) } => (),
+        other => panic!("unexpected: {:?}", other),
+    }
+
+    // Nothing was stored
+    let stats = sleet.send(GetMempoolStats).await.unwrap();
+    assert_eq!(stats.txs, TxCounts::default());
+}
+
+#[actix_rt::test]
+async fn test_invalid_unlock() {
+    let (sleet, _client, _hail, root_kp, genesis_tx) = start_test_env().await;
+
+    // Signed by another key than the owner of the spent output
+    let other_kp = Keypair::generate(&mut OsRng {});
+    let capacity = genesis_tx.outputs()[0].capacity;
+    let input = Input::new(&other_kp, genesis_tx.hash(), 0).unwrap();
+    let output = transfer_output(new_pkh(), capacity - FEE).unwrap();
+    let cell = Cell::new(Inputs::new(vec![input]), Outputs::new(vec![output]));
+    let invalid_unlock = format!("{}", Error::Cell(cell::Error::InvalidUnlock));
+    let ack = sleet.send(ValidateTx { cell: cell.clone() }).await.unwrap();
+    assert_eq!(ack.rejected, Some(RejectReason::Invalid(invalid_unlock.clone())));
+    match sleet.send(GenerateTx { cell }).await.unwrap() {
+        GenerateTxAck { cell_hash: None, rejected: Some(RejectReason::Invalid(reason)) } => {
+            assert_eq!(reason, invalid_unlock)
+        }
+        other => panic!("unexpected: {:?}", other),
+    }
+
+    // Signed by the owner, but for another network
+    let pkh = Id::from_public_key(&root_kp.public).bytes();
+    let cell = TransferOperation::new(genesis_tx, new_pkh(), pkh, 1)
+        .with_chain_id(DEFAULT_CHAIN_ID + 1)
+        .transfer(&root_kp)
+        .unwrap();
+    let ack = sleet.send(ValidateTx { cell }).await.unwrap();
+    assert_eq!(ack.rejected, Some(RejectReason::Invalid(invalid_unlock)));
+}
+
+#[actix_rt::test]
+async fn test_zero_transfer() {
+    let (sleet, _client, _hail, root_kp, genesis_tx) = start_test_env().await;
+
+    let capacity = genesis_tx.outputs()[0].capacity;
+    let input = Input::new(&root_kp, genesis_tx.hash(), 0).unwrap();
+    let outputs = vec![
+        transfer_output(new_pkh(), capacity - FEE).unwrap(),
+        transfer_output(new_pkh(), 0).unwrap(),
+    ];
+    let cell = Cell::new(Inputs::new(vec![input]), Outputs::new(outputs));
+    let zero_transfer = format!("{}", Error::Cell(cell::Error::ZeroTransfer));
+    match sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap() {
+        GenerateTxAck { cell_hash: None, rejected: Some(RejectReason::Invalid(reason)) } => {
+            assert_eq!(reason, zero_transfer)
+        }
+        other => panic!("unexpected: {:?}", other),
+    }
+    let ack = sleet.send(ValidateTx { cell }).await.unwrap();
+    assert_eq!(ack.rejected, Some(RejectReason::Invalid(zero_transfer)));
+}
+
+#[actix_rt::test]
+async fn test_invalid_cell_ancestry_not_fetched() {
+    let client = DummyClient::new();
+    let fetched = client.fetched.clone();
+    let client = client.start();
+    let hail = HailMock::new().start();
+    let sleet =
+        Sleet::new(client.recipient(), hail.recipient(), Id::zero(), mock_ip(), vec![]).start();
+    let root_kp = Keypair::generate(&mut OsRng {});
+    let genesis_tx = generate_coinbase(&root_kp, 1000);
+    sleet.send(make_live_committee(vec![genesis_tx])).await.unwrap();
+
+    // A coinbase cell is refused before the ancestry of its transaction is fetched
+    let tx = Tx::new(vec![[7u8; 32]], generate_coinbase(&root_kp, 1000));
+    let ack = sleet.send(QueryTx { id: Id::two(), ip: mock_ip(), tx }).await.unwrap();
+    assert!(!ack.outcome);
+    sleep_ms(100).await;
+    assert!(fetched.lock().unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn test_locked_output() {
+    let (sleet, _client, _hail, root_kp, genesis_tx) = start_test_env().await;
+
+    let enc = bincode::serialize(&root_kp.public).unwrap();
+    let pkh = blake3::hash(&enc).as_bytes().clone();
+    let locked = transfer_output(pkh, 1000).unwrap().with_locktime(5);
+    let funding = Cell::new(Inputs::new(vec![]), Outputs::new(vec![locked]));
+    sleet.send(make_live_committee(vec![genesis_tx, funding.clone()])).await.unwrap();
+
+    let cell = generate_transfer(&root_kp, funding, 100);
+    let ack = sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap();
+    assert_eq!(ack.cell_hash, None);
+
+    // The output can be spent once the block at its locktime is applied
+    sleet.send(AcceptedHeight { height: 5 }).await.unwrap();
+    let ack = sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap();
+    assert_eq!(ack.cell_hash, Some(cell.hash()));
+}
+
+#[actix_rt::test]
+async fn test_insufficient_fee() {
+    let (sleet, _client, _hail, root_kp, genesis_tx) = start_test_env().await;
+
+    let capacity = genesis_tx.outputs()[0].capacity;
+    let paying_fee = |fee: Capacity| {
+        let input = Input::new(&root_kp, genesis_tx.hash(), 0).unwrap();
+        let output = transfer_output(new_pkh(), capacity - fee).unwrap();
+        Cell::new(Inputs::new(vec![input]), Outputs::new(vec![output]))
+    };
+
+    let ack = sleet.send(ValidateTx { cell: paying_fee(MIN_FEE - 1) }).await.unwrap();
+    assert!(matches!(ack.rejected, Some(RejectReason::Invalid(_))));
+    match sleet.send(GenerateTx { cell: paying_fee(MIN_FEE - 1) }).await.unwrap() {
+        GenerateTxAck { cell_hash: None, rejected: Some(RejectReason::Invalid(reason)) } => {
+            let e = Error::Cell(cell::Error::InsufficientFee(MIN_FEE - 1, MIN_FEE));
+            assert_eq!(reason, format!("{}", e))
+        }
+        other => panic!("unexpected: {:?}", other),
+    }
+
+    let cell = paying_fee(MIN_FEE);
+    match sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap() {
+        GenerateTxAck { cell_hash: Some(hash), .. } => assert_eq!(hash, cell.hash()),
         other => panic!("unexpected: {:?}", other),
     }
 }
@@ -456,6 +992,58 @@ async fn test_sleet_accept_one() {
     assert!(accepted == vec![cell0]);
 }
 
+#[actix_rt::test]
+async fn test_metrics() {
+    use crate::metrics::METRICS;
+
+    // The registry is shared with the tests running in parallel
+    let accepted_before = METRICS.sleet_accepted_txs.get();
+    let queries_before = METRICS.sleet_query_latency.count();
+    let (sleet, _client, hail, root_kp, genesis_tx) = start_test_env().await;
+
+    let mut spend_cell = genesis_tx.clone();
+    for i in 0..BETA1 as usize {
+        let cell = generate_transfer(&root_kp, spend_cell.clone(), 1 + i as u64);
+        sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap();
+        spend_cell = cell;
+    }
+    let accepted = hail.send(GetAcceptedCells).await.unwrap();
+    assert_eq!(accepted.len(), 1);
+
+    assert!(METRICS.sleet_accepted_txs.get() >= accepted_before + 1);
+    assert!(METRICS.sleet_query_latency.count() >= queries_before + BETA1 as u64);
+    let rendered = METRICS.render();
+    assert!(rendered.contains("# TYPE sleet_accepted_txs_total counter"));
+    assert!(rendered.contains("sleet_query_latency_seconds_bucket{le=\"+Inf\"}"));
+}
+
+#[actix_rt::test]
+async fn test_get_utxos_by_owner() {
+    const MIN_CHILDREN_NEEDED: usize = BETA1 as usize;
+
+    let (sleet, _client, hail, root_kp, genesis_tx) = start_test_env().await;
+
+    let recipient = new_pkh();
+    let cell0 = generate_transfer_whith_recipient(&root_kp, genesis_tx.clone(), recipient, 42);
+    sleet.send(GenerateTx { cell: cell0.clone() }).await.unwrap();
+    let utxos = sleet.send(GetUtxosByOwner { owner: recipient }).await.unwrap();
+    assert!(utxos.utxos.is_empty());
+
+    // Spend the change of `cell0` until it is accepted
+    let mut spend_cell = cell0.clone();
+    for i in 1..MIN_CHILDREN_NEEDED {
+        let cell = generate_transfer(&root_kp, spend_cell.clone(), 1 + i as u64);
+        sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap();
+        spend_cell = cell;
+    }
+    let accepted = hail.send(GetAcceptedCells).await.unwrap();
+    assert_eq!(accepted, vec![cell0.clone()]);
+
+    let index = cell0.outputs().iter().position(|o| o.lock == recipient).unwrap();
+    let utxos = sleet.send(GetUtxosByOwner { owner: recipient }).await.unwrap();
+    assert_eq!(utxos.utxos, vec![(cell0.hash(), index as u8, 42)]);
+}
+
 #[actix_rt::test]
 async fn test_sleet_accept_many() {
     const N: usize = 500;
@@ -487,67 +1075,63 @@ async fn test_sleet_accept_many() {
 }
 
 #[actix_rt::test]
-async fn test_sleet_accept_with_conflict() {
-    const CHILDREN_NEEDED: usize = BETA2 as usize;
-    let (sleet, client, hail, root_kp, genesis_tx) = start_test_env().await;
-
-    let first_cell = generate_transfer(&root_kp, genesis_tx.clone(), 100);
-    println!("First cell: {} {}", hex::encode(first_cell.hash()), first_cell.clone());
-    sleet.send(GenerateTx { cell: first_cell.clone() }).await.unwrap();
-
-    // Spends the same outputs, will conflict with `first_cell`
-    let conflicting_cell = generate_transfer(&root_kp, genesis_tx.clone(), 42);
-    // Make sure the mock validator votes against
-    set_validator_response(client.clone(), false).await;
-    sleet.send(GenerateTx { cell: conflicting_cell.clone() }).await.unwrap();
-    println!(
-        "Conflicting cell: {} {}",
-        hex::encode(conflicting_cell.hash()),
-        conflicting_cell.clone()
-    );
-    sleep_ms(100).await;
-    set_validator_response(client.clone(), true).await;
+async fn test_accepted_cell_hashes_pages() {
+    // A chain of transfers, all accepted but the last `BETA1`
+    const N: usize = 250 + BETA1 as usize - 1;
 
-    let mut spend_cell = first_cell.clone();
-    for i in 0..CHILDREN_NEEDED {
-        println!("Spending: {}\n {}", hex::encode(spend_cell.hash()), spend_cell.clone());
-        let cell = generate_transfer(&root_kp, spend_cell.clone(), 1 + i as u64);
+    let mut client = DummyClient::new();
+    client.responses = vec![(mock_validator_id(), true)];
+    let client = client.start();
+    let hail = HailMock::new().start();
+    // Fewer transactions than the accepted ones are kept in memory
+    let sleet =
+        Sleet::new(client.recipient(), hail.clone().recipient(), Id::zero(), mock_ip(), vec![])
+            .with_mempool_capacity(100)
+            .start();
+    let root_kp = Keypair::generate(&mut OsRng {});
+    let genesis_tx = generate_coinbase(&root_kp, 10000);
+    sleet.send(make_live_committee(vec![genesis_tx.clone()])).await.unwrap();
+    let addr = new_pkh();
+    let mut spend_cell = genesis_tx.clone();
+    for _ in 0..N {
+        let cell = generate_transfer_whith_recipient(&root_kp, spend_cell.clone(), addr, 1);
         sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap();
-        println!("Cell: {}", cell.clone());
         spend_cell = cell;
     }
-    let hashes = sleet.send(GetCellHashes).await.unwrap();
-    // + 2: `genesis_tx` and `first_cell`, the voted down tx won't be added to `live_cells`
-    assert_eq!(hashes.ids.len(), CHILDREN_NEEDED + 2);
-
-    // Wait a bit for 'Hail' to receive the message
-    sleep_ms(10).await;
-
     let accepted = hail.send(GetAcceptedCells).await.unwrap();
-    for a in accepted.iter() {
-        println!("Accepted: {}", hex::encode(a.hash()));
+    assert_eq!(accepted.len(), 250);
+
+    let mut pages = vec![];
+    let mut after = None;
+    loop {
+        let page = sleet.send(GetAcceptedCellHashes { after, limit: 100 }).await.unwrap();
+        assert!(page.ids.len() <= 100);
+        pages.push(page.ids.clone());
+        match page.next {
+            Some(next) => {
+                assert_eq!(page.ids.last(), Some(&next));
+                after = Some(next);
+            }
+            None => break,
+        }
     }
-    let _ = sleet.send(DumpDAG).await.unwrap();
+    assert_eq!(pages.iter().map(|page| page.len()).collect::<Vec<_>>(), vec![100, 100, 50]);
 
-    let SleetStatus { dag_len, accepted_frontier, .. } = sleet.send(GetStatus).await.unwrap();
-    let accepted_frontier_len = accepted_frontier.len();
-    println!("dag_len: {}", dag_len);
-    println!("accepted_frontier_len: {}", accepted_frontier_len);
-    assert!(dag_len == 10 + accepted_frontier_len);
-    // The conflicting transaction is accepted after BETA2 queries,
-    // and its non-conflictiong children after BETA1
-    assert_eq!(accepted.len(), 11);
-    assert!(accepted.contains(&first_cell));
+    // The pages are ordered and cover every accepted cell exactly once
+    let ids: Vec<CellHash> = pages.concat();
+    let mut expected: Vec<CellHash> = accepted.iter().map(|cell| cell.hash()).collect();
+    expected.sort();
+    assert_eq!(ids, expected);
 }
 
 #[actix_rt::test]
-async fn test_sleet_dont_accept() {
-    const N: usize = 30;
-    let (sleet, client, hail, root_kp, genesis_tx) = start_test_env().await;
+async fn test_mempool_stats() {
+    const N: usize = 15;
 
-    // all transactions will be voted against,
-    // none will be accepted
-    set_validator_response(client, false).await;
+    let (sleet, _client, _hail, root_kp, genesis_tx) = start_test_env().await;
+    let stats = sleet.send(GetMempoolStats).await.unwrap();
+    assert_eq!(stats.txs, TxCounts::default());
+    assert_eq!(stats.live_cells, 1);
 
     let mut spend_cell = genesis_tx.clone();
     for i in 0..N {
@@ -556,20 +1140,381 @@ async fn test_sleet_dont_accept() {
         spend_cell = cell;
     }
 
-    // The cells won't be added to `live_cells`, as all of them were voted against
-    let hashes = sleet.send(GetCellHashes).await.unwrap();
-    assert_eq!(hashes.ids.len(), 1);
+    // The last `BETA1 - 1` transactions are queried, but not yet accepted
+    let stats = sleet.send(GetMempoolStats).await.unwrap();
+    let expected = TxCounts {
+        pending: 0,
+        queried: BETA1 as usize - 1,
+        accepted: N + 1 - BETA1 as usize,
+        rejected: 0,
+        removed: 0,
+    };
+    assert_eq!(stats.txs, expected);
+    assert_eq!(stats.live_cells, N + 1);
+    assert_eq!(stats.live_cells_capacity, MEMPOOL_CAPACITY);
+    assert_eq!(stats.accepted_frontier, 1);
+    assert_eq!(stats.dag_len, BETA1 as usize);
+    assert_eq!(stats.conflict_graph_len, N);
+}
 
-    let accepted = hail.send(GetAcceptedCells).await.unwrap();
-    println!("Accepted: {:?}", accepted);
-    assert!(accepted.is_empty());
+#[actix_rt::test]
+async fn test_tx_counts_of_unknown_tx() {
+    let db = SledStore::temporary().unwrap();
+    let mut counts = TxCounts::default();
+    assert!(counts.set_status(&db, &[7u8; 32], TxStatus::Accepted).is_err());
+    assert_eq!(counts, TxCounts::default());
+
+    let root_kp = Keypair::generate(&mut OsRng {});
+    let tx = Tx::new(vec![], generate_coinbase(&root_kp, 1000));
+    counts.insert_tx(&db, tx.clone()).unwrap();
+    counts.set_status(&db, &tx.hash(), TxStatus::Accepted).unwrap();
+    assert_eq!(counts, TxCounts { accepted: 1, ..TxCounts::default() });
 }
 
 #[actix_rt::test]
-async fn test_sleet_many_conflicts() {
-    const N: usize = BETA2 as usize;
+async fn test_self_vote() {
+    const N: usize = BETA1 as usize + 1;
 
-    let (sleet, client, hail, root_kp, genesis_tx) = start_test_env().await;
+    let client = DummyClient::new();
+    let queried = client.queried.clone();
+    let client = client.start();
+    let hail = HailMock::new().start();
+    let sleet =
+        Sleet::new(client.recipient(), hail.recipient(), Id::zero(), mock_ip(), vec![]).start();
+    let root_kp = Keypair::generate(&mut OsRng {});
+    let genesis_tx = generate_coinbase(&root_kp, 10000);
+
+    // This validator is the only member of the committee
+    let mut live_committee = make_live_committee(vec![genesis_tx.clone()]);
+    live_committee.validators = vec![(Id::zero(), (mock_ip(), 1.0))].into_iter().collect();
+    sleet.send(live_committee).await.unwrap();
+
+    let mut spend_cell = genesis_tx.clone();
+    for i in 0..N {
+        let cell = generate_transfer(&root_kp, spend_cell.clone(), 1 + i as u64);
+        sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap();
+        spend_cell = cell;
+    }
+
+    // The own votes are counted, without querying this validator over the network
+    let stats = sleet.send(GetMempoolStats).await.unwrap();
+    assert_eq!(stats.txs.accepted, N + 1 - BETA1 as usize);
+    assert!(queried.lock().unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn test_rebroadcast_pending() {
+    // The acks of the first query are dropped
+    let client = DummyClient::new();
+    let queried = client.queried.clone();
+    let client = client.start();
+    let hail = HailMock::new().start();
+    let sleet = Sleet::new(
+        client.clone().recipient(),
+        hail.clone().recipient(),
+        Id::zero(),
+        mock_ip(),
+        vec![],
+    )
+    .with_rebroadcast(Duration::from_millis(50), Duration::from_millis(100))
+    .start();
+    let root_kp = Keypair::generate(&mut OsRng {});
+    let genesis_tx = generate_coinbase(&root_kp, 10000);
+    sleet.send(make_live_committee(vec![genesis_tx.clone()])).await.unwrap();
+
+    let cell0 = generate_transfer(&root_kp, genesis_tx.clone(), 1);
+    sleet.send(GenerateTx { cell: cell0.clone() }).await.unwrap();
+    assert_eq!(queried.lock().unwrap().len(), 1);
+
+    // The re-broadcast query is answered, and only sent once
+    set_validator_response(client.clone(), true).await;
+    sleep_ms(400).await;
+    assert_eq!(queried.lock().unwrap().len(), 2);
+
+    // Without the chit of the re-broadcast, `cell0` would miss one vote to reach `BETA1`
+    let mut spend_cell = cell0.clone();
+    for i in 1..BETA1 as usize {
+        let cell = generate_transfer(&root_kp, spend_cell.clone(), 1 + i as u64);
+        sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap();
+        spend_cell = cell;
+    }
+    let accepted = hail.send(GetAcceptedCells).await.unwrap();
+    assert_eq!(accepted, vec![cell0.clone()]);
+
+    let stats = sleet.send(GetMempoolStats).await.unwrap();
+    assert_eq!(stats.txs.accepted + stats.txs.queried, BETA1 as usize);
+}
+
+/// Accepts `cells` one after the other, returns the number of accepted transactions, the time
+/// it took, and the final accepted frontier
+async fn accept_sequentially(
+    cells: &[Cell],
+    genesis_tx: &Cell,
+    batch_size: usize,
+) -> (usize, Duration, HashSet<TxHash>) {
+    let mut client = DummyClient::new();
+    client.responses = vec![(mock_validator_id(), true)];
+    let client = client.start();
+    let hail = HailMock::new().start();
+    // The accepted frontier is only recomputed once `batch_size` transactions were accepted
+    let sleet = Sleet::new(client.recipient(), hail.recipient(), Id::zero(), mock_ip(), vec![])
+        .with_frontier_batching(batch_size, Duration::from_secs(3600))
+        .start();
+    sleet.send(make_live_committee(vec![genesis_tx.clone()])).await.unwrap();
+
+    let started = Instant::now();
+    for cell in cells.iter() {
+        sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap();
+    }
+    // The remaining accepted transactions are taken into account
+    sleet.send(PruneAcceptedFrontier).await.unwrap();
+    let elapsed = started.elapsed();
+    let accepted = sleet.send(GetMempoolStats).await.unwrap().txs.accepted;
+    let AcceptedFrontier { frontier, .. } = sleet.send(GetAcceptedFrontier).await.unwrap();
+    (accepted, elapsed, frontier)
+}
+
+#[actix_rt::test]
+async fn test_frontier_batching() {
+    const N: usize = 2000;
+
+    let root_kp = Keypair::generate(&mut OsRng {});
+    let genesis_tx = generate_coinbase(&root_kp, 100000);
+    let addr = new_pkh();
+    let mut cells = vec![];
+    let mut spend_cell = genesis_tx.clone();
+    for _ in 0..N {
+        let cell = generate_transfer_whith_recipient(&root_kp, spend_cell, addr, 1);
+        cells.push(cell.clone());
+        spend_cell = cell;
+    }
+
+    // Without batching, the accepted frontier is recomputed for each accepted transaction
+    let (accepted, unbatched, unbatched_frontier) =
+        accept_sequentially(&cells, &genesis_tx, 1).await;
+    assert_eq!(accepted, N + 1 - BETA1 as usize);
+
+    let (accepted, batched, batched_frontier) = accept_sequentially(&cells, &genesis_tx, 10).await;
+    assert_eq!(accepted, N + 1 - BETA1 as usize);
+    println!("{} sequential accepts: {:?} unbatched, {:?} batched", N, unbatched, batched);
+
+    assert_eq!(unbatched_frontier.len(), 1);
+    assert_eq!(batched_frontier, unbatched_frontier);
+}
+
+#[actix_rt::test]
+async fn test_sleet_accept_with_conflict() {
+    const CHILDREN_NEEDED: usize = BETA2 as usize;
+    let (sleet, client, hail, root_kp, genesis_tx) = start_test_env().await;
+
+    let first_cell = generate_transfer(&root_kp, genesis_tx.clone(), 100);
+    println!("First cell: {} {}", hex::encode(first_cell.hash()), first_cell.clone());
+    sleet.send(GenerateTx { cell: first_cell.clone() }).await.unwrap();
+
+    // Spends the same outputs, will conflict with `first_cell`
+    let conflicting_cell = generate_transfer(&root_kp, genesis_tx.clone(), 42);
+    // Make sure the mock validator votes against
+    set_validator_response(client.clone(), false).await;
+    sleet.send(GenerateTx { cell: conflicting_cell.clone() }).await.unwrap();
+    println!(
+        "Conflicting cell: {} {}",
+        hex::encode(conflicting_cell.hash()),
+        conflicting_cell.clone()
+    );
+    sleep_ms(100).await;
+    set_validator_response(client.clone(), true).await;
+
+    let mut spend_cell = first_cell.clone();
+    for i in 0..CHILDREN_NEEDED {
+        println!("Spending: {}\n {}", hex::encode(spend_cell.hash()), spend_cell.clone());
+        let cell = generate_transfer(&root_kp, spend_cell.clone(), 1 + i as u64);
+        sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap();
+        println!("Cell: {}", cell.clone());
+        spend_cell = cell;
+    }
+    let hashes = sleet.send(GetCellHashes).await.unwrap();
+    // + 2: `genesis_tx` and `first_cell`, the voted down tx won't be added to `live_cells`
+    assert_eq!(hashes.ids.len(), CHILDREN_NEEDED + 2);
+
+    // Wait a bit for 'Hail' to receive the message
+    sleep_ms(10).await;
+
+    let accepted = hail.send(GetAcceptedCells).await.unwrap();
+    for a in accepted.iter() {
+        println!("Accepted: {}", hex::encode(a.hash()));
+    }
+    let _ = sleet.send(DumpDAG).await.unwrap();
+
+    let SleetStatus { dag_len, accepted_frontier, .. } = sleet.send(GetStatus).await.unwrap();
+    let accepted_frontier_len = accepted_frontier.len();
+    println!("dag_len: {}", dag_len);
+    println!("accepted_frontier_len: {}", accepted_frontier_len);
+    assert!(dag_len == 10 + accepted_frontier_len);
+    // The conflicting transaction is accepted after BETA2 queries,
+    // and its non-conflictiong children after BETA1
+    assert_eq!(accepted.len(), 11);
+    assert!(accepted.contains(&first_cell));
+}
+
+#[actix_rt::test]
+async fn test_cancel_query_of_rejected_tx() {
+    const CHILDREN_NEEDED: usize = BETA2 as usize;
+    let (sleet, client, _hail, root_kp, genesis_tx) = start_test_env().await;
+
+    let first_cell = generate_transfer(&root_kp, genesis_tx.clone(), 100);
+    sleet.send(GenerateTx { cell: first_cell.clone() }).await.unwrap();
+    sleep_ms(50).await;
+
+    // The vote against `conflicting_cell` only arrives after it was rejected
+    let conflicting_cell = generate_transfer(&root_kp, genesis_tx.clone(), 42);
+    set_validator_response(client.clone(), false).await;
+    client.send(SetDelay { delay: Some(Duration::from_millis(1000)) }).await.unwrap();
+    sleet.send(GenerateTx { cell: conflicting_cell.clone() }).await.unwrap();
+    sleep_ms(50).await;
+    client.send(SetDelay { delay: None }).await.unwrap();
+    set_validator_response(client.clone(), true).await;
+
+    // Accept `first_cell`, rejecting `conflicting_cell`
+    let mut spend_cell = first_cell.clone();
+    for i in 0..CHILDREN_NEEDED {
+        let cell = generate_transfer(&root_kp, spend_cell.clone(), 1 + i as u64);
+        sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap();
+        spend_cell = cell;
+    }
+    sleep_ms(50).await;
+    let SleetStatus { known_txs, .. } = sleet.send(GetStatus).await.unwrap();
+    let (_, tx) = tx_storage::get_tx(&known_txs, conflicting_cell.hash()).unwrap();
+    assert_eq!(tx.status, TxStatus::Rejected);
+    let before = sleet.send(GetConflictSet { cell_hash: spend_cell.hash() }).await.unwrap();
+    assert!(before.confidence > 0);
+
+    // The late vote is dropped
+    sleep_ms(1100).await;
+    let history = sleet.send(GetCellHistory { cell_hash: conflicting_cell.hash() }).await.unwrap();
+    let statuses: Vec<TxStatus> = history.history.into_iter().map(|(status, _)| status).collect();
+    assert_eq!(statuses, vec![TxStatus::Pending, TxStatus::Rejected]);
+    let after = sleet.send(GetConflictSet { cell_hash: spend_cell.hash() }).await.unwrap();
+    assert_eq!(after, before);
+}
+
+#[actix_rt::test]
+async fn test_duplicate_acks() {
+    let (sleet, client, _hail, root_kp, genesis_tx) = start_test_env().await;
+    // Neither validator weighs enough to settle a query on its own
+    let mut live_committee = make_live_committee(vec![genesis_tx.clone()]);
+    let _ = live_committee.validators.insert(mock_validator_id(), (mock_ip(), 0.3));
+    let _ = live_committee.validators.insert(Id::two(), (mock_ip(), 0.4));
+    sleet.send(live_committee).await.unwrap();
+    let responses = vec![(mock_validator_id(), false), (Id::two(), false)];
+    client.send(SetResponses { responses }).await.unwrap();
+
+    let cell = generate_transfer(&root_kp, genesis_tx.clone(), 100);
+    sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap();
+    sleep_ms(50).await;
+    let SleetStatus { known_txs, .. } = sleet.send(GetStatus).await.unwrap();
+    let (_, tx) = tx_storage::get_tx(&known_txs, cell.hash()).unwrap();
+    let ack = |id: Id| Response::QueryTxAck(QueryTxAck { id, tx_hash: tx.hash(), outcome: true });
+
+    // The repeated vote of a validator only counts once
+    let acks = vec![ack(mock_validator_id()), ack(mock_validator_id()), ack(mock_validator_id())];
+    sleet.send(QueryComplete { tx: tx.clone(), acks }).await.unwrap();
+    let hashes = sleet.send(GetCellHashes).await.unwrap();
+    assert!(!hashes.ids.contains(&cell.hash()));
+
+    let acks = vec![ack(mock_validator_id()), ack(Id::two())];
+    sleet.send(QueryComplete { tx: tx.clone(), acks }).await.unwrap();
+    let hashes = sleet.send(GetCellHashes).await.unwrap();
+    assert!(hashes.ids.contains(&cell.hash()));
+}
+
+#[actix_rt::test]
+async fn test_get_conflict_set() {
+    let (sleet, client, _hail, root_kp, genesis_tx) = start_test_env().await;
+
+    let first_cell = generate_transfer(&root_kp, genesis_tx.clone(), 100);
+    sleet.send(GenerateTx { cell: first_cell.clone() }).await.unwrap();
+    sleep_ms(50).await;
+    let ack = sleet.send(GetConflictSet { cell_hash: first_cell.hash() }).await.unwrap();
+    assert_eq!(ack.members, vec![first_cell.hash()]);
+    assert_eq!(ack.preferred, first_cell.hash());
+
+    // Spends the same outputs, voted against
+    let conflicting_cell = generate_transfer(&root_kp, genesis_tx.clone(), 42);
+    set_validator_response(client.clone(), false).await;
+    sleet.send(GenerateTx { cell: conflicting_cell.clone() }).await.unwrap();
+    sleep_ms(50).await;
+
+    let mut members = vec![first_cell.hash(), conflicting_cell.hash()];
+    members.sort();
+    for cell_hash in members.iter() {
+        let ack = sleet.send(GetConflictSet { cell_hash: *cell_hash }).await.unwrap();
+        assert_eq!(ack.members, members);
+        assert_eq!(ack.preferred, first_cell.hash());
+    }
+
+    // Unknown cells have an empty conflict set
+    let unknown = new_pkh();
+    let ack = sleet.send(GetConflictSet { cell_hash: unknown }).await.unwrap();
+    assert_eq!(ack, ConflictSetAck { members: vec![], preferred: unknown, confidence: 0 });
+}
+
+#[actix_rt::test]
+async fn test_validate_tx() {
+    let (sleet, _client, _hail, root_kp, genesis_tx) = start_test_env().await;
+
+    // A valid cell is reported acceptable, but isn't recorded
+    let cell = generate_transfer(&root_kp, genesis_tx.clone(), 100);
+    let ack = sleet.send(ValidateTx { cell: cell.clone() }).await.unwrap();
+    assert_eq!(ack, ValidateTxAck { rejected: None, conflicts: vec![] });
+    let hashes = sleet.send(GetCellHashes).await.unwrap();
+    assert!(!hashes.ids.contains(&cell.hash()));
+    let SleetStatus { known_txs, dag_len, .. } = sleet.send(GetStatus).await.unwrap();
+    assert!(!tx_storage::is_known_tx(&known_txs, cell.hash()).unwrap());
+    assert_eq!(dag_len, 0);
+
+    // Once it is submitted, a cell spending the same outputs is reported as conflicting
+    sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap();
+    let conflicting_cell = generate_transfer(&root_kp, genesis_tx.clone(), 42);
+    let ack = sleet.send(ValidateTx { cell: conflicting_cell }).await.unwrap();
+    assert_eq!(ack, ValidateTxAck { rejected: None, conflicts: vec![cell.hash()] });
+
+    // Resubmitting a known cell is refused
+    let ack = sleet.send(ValidateTx { cell }).await.unwrap();
+    assert_eq!(ack.rejected, Some(RejectReason::Duplicate));
+    let ack = sleet.send(ValidateTx { cell: genesis_tx }).await.unwrap();
+    assert_eq!(ack.rejected, Some(RejectReason::CoinbaseNotAllowed));
+}
+
+#[actix_rt::test]
+async fn test_sleet_dont_accept() {
+    const N: usize = 30;
+    let (sleet, client, hail, root_kp, genesis_tx) = start_test_env().await;
+
+    // all transactions will be voted against,
+    // none will be accepted
+    set_validator_response(client, false).await;
+
+    let mut spend_cell = genesis_tx.clone();
+    for i in 0..N {
+        let cell = generate_transfer(&root_kp, spend_cell.clone(), 1 + i as u64);
+        sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap();
+        spend_cell = cell;
+    }
+
+    // The cells won't be added to `live_cells`, as all of them were voted against
+    let hashes = sleet.send(GetCellHashes).await.unwrap();
+    assert_eq!(hashes.ids.len(), 1);
+
+    let accepted = hail.send(GetAcceptedCells).await.unwrap();
+    println!("Accepted: {:?}", accepted);
+    assert!(accepted.is_empty());
+}
+
+#[actix_rt::test]
+async fn test_sleet_many_conflicts() {
+    const N: usize = BETA2 as usize;
+
+    let (sleet, client, hail, root_kp, genesis_tx) = start_test_env().await;
 
     let mut spend_cell = genesis_tx.clone();
     let mut cell0: Cell = genesis_tx.clone(); // value irrelevant, will be initialised later
@@ -641,6 +1586,28 @@ async fn test_sleet_tx_no_parents() {
     assert!(elapsed >= QUERY_RESPONSE_TIMEOUT_MS as u128);
 }
 
+#[actix_rt::test]
+async fn test_sleet_too_many_parents() {
+    let (sleet, _client, _hail, root_kp, genesis_tx) = start_test_env().await;
+
+    let cell = generate_transfer(&root_kp, genesis_tx.clone(), 1);
+    let parents = (0..10_000u32).map(|i| blake3::hash(&i.to_le_bytes()).into()).collect();
+    let tx = Tx::new(parents, cell.clone());
+
+    // The query is refused right away, instead of waiting for the ancestry
+    let now = Instant::now();
+    let QueryTxAck { outcome, .. } =
+        sleet.send(QueryTx { id: Id::zero(), ip: mock_ip(), tx }).await.unwrap();
+    assert!(!outcome);
+    assert!(now.elapsed().as_millis() < QUERY_RESPONSE_TIMEOUT_MS as u128);
+
+    let SleetStatus { known_txs, .. } = sleet.send(GetStatus).await.unwrap();
+    assert!(!tx_storage::is_known_tx(&known_txs, cell.hash()).unwrap());
+    let stats = sleet.send(GetMempoolStats).await.unwrap();
+    assert_eq!(stats.txs, TxCounts::default());
+    assert_eq!(stats.dag_len, 0);
+}
+
 #[actix_rt::test]
 async fn test_sleet_tx_late_parents() {
     let (sleet1, sleet2, _client, _hail, root_kp, genesis_tx) =
@@ -674,6 +1641,38 @@ async fn test_sleet_tx_late_parents() {
     assert!(rx.await.unwrap());
 }
 
+#[actix_rt::test]
+async fn test_pending_query_checked_periodically() {
+    let (sleet1, _sleet2, client, hail, root_kp, genesis_tx) =
+        start_test_env_with_two_sleet_actors().await;
+    let known_txs: Arc<dyn KvStore> = Arc::new(SledStore::temporary().unwrap());
+    let sleet2 = Sleet::new(client.recipient(), hail.recipient(), Id::one(), mock_ip(), vec![])
+        .with_known_txs(known_txs.clone())
+        .start();
+    let cell1 = generate_transfer(&root_kp, genesis_tx.clone(), 1);
+    sleet1.send(GenerateTx { cell: cell1.clone() }).await.unwrap();
+    // `sleet2` knows `cell1` from an accepted block
+    sleet2.send(make_live_committee(vec![genesis_tx, cell1.clone()])).await.unwrap();
+    let cell2 = generate_transfer(&root_kp, cell1.clone(), 2);
+    sleet1.send(GenerateTx { cell: cell2.clone() }).await.unwrap();
+    let SleetStatus { known_txs: known_txs1, .. } = sleet1.send(GetStatus).await.unwrap();
+    let (_, mut tx1) = tx_storage::get_tx(&known_txs1, cell1.hash()).unwrap();
+    let (_, tx2) = tx_storage::get_tx(&known_txs1, cell2.hash()).unwrap();
+
+    let now = Instant::now();
+    let query = sleet2.send(QueryTx { id: Id::zero(), ip: mock_ip(), tx: tx2 });
+    let query = tokio::spawn(query);
+
+    // The parent shows up as accepted in storage, without any message to `sleet2`
+    sleep_ms(500).await;
+    tx1.status = TxStatus::Accepted;
+    let _ = tx_storage::insert_tx(&known_txs, tx1).unwrap();
+
+    let QueryTxAck { outcome, .. } = query.await.unwrap().unwrap();
+    assert!(now.elapsed() < Duration::from_millis(QUERY_RESPONSE_TIMEOUT_MS));
+    assert!(outcome);
+}
+
 #[actix_rt::test]
 async fn test_sleet_tx_two_late_parents() {
     let (sleet1, sleet2, _client, _hail, root_kp, genesis_tx) =
@@ -760,6 +1759,44 @@ async fn test_sleet_tx_missing_parent() {
     assert!(rx1.await.unwrap());
 }
 
+#[actix_rt::test]
+async fn test_build_dag_diamond() {
+    let (sleet, _client, _hail, _root_kp, _genesis_tx) = start_test_env().await;
+    let spec = DagSpec::diamond();
+    sleet.send(make_live_committee(vec![spec.genesis()])).await.unwrap();
+
+    let cells = build_dag(&spec);
+    let hashes: Vec<TxHash> = cells.iter().map(|cell| cell.hash()).collect();
+    // `d` spends an output of both `b` and `c`
+    let spent: HashSet<CellHash> =
+        cells[3].inputs().iter().map(|input| input.output_index.cell_hash).collect();
+    assert_eq!(spent, vec![hashes[1], hashes[2]].into_iter().collect());
+
+    for tx in dag_txs(&spec, &cells) {
+        let QueryTxAck { outcome, .. } =
+            sleet.send(QueryTx { id: Id::zero(), ip: mock_ip(), tx }).await.unwrap();
+        assert!(outcome);
+    }
+    // None of the cells conflict
+    for hash in hashes.iter() {
+        let ack = sleet.send(GetConflictSet { cell_hash: *hash }).await.unwrap();
+        assert_eq!(ack.members, vec![*hash]);
+    }
+
+    let LiveFrontier { frontier } = sleet.send(GetLiveFrontier).await.unwrap();
+    assert_eq!(frontier, vec![hashes[3]].into_iter().collect());
+    let expected = vec![(1, vec![0]), (2, vec![0]), (3, vec![0, 1, 2])];
+    for (v, ancestors) in expected {
+        let TxAncestors { ancestors: got, .. } = sleet
+            .send(GetTxAncestors { tx_hash: hashes[v], max_depth: 10 })
+            .await
+            .unwrap()
+            .unwrap();
+        let got: HashSet<TxHash> = got.iter().map(|tx| tx.hash()).collect();
+        assert_eq!(got, ancestors.iter().map(|a| hashes[*a]).collect());
+    }
+}
+
 #[actix_rt::test]
 async fn test_sleet_get_single_ancestor() {
     let (sleet1, sleet2, client, _hail, root_kp, genesis_tx) =
@@ -784,6 +1821,111 @@ async fn test_sleet_get_single_ancestor() {
     assert!(outcome);
 }
 
+#[actix_rt::test]
+async fn test_coalesce_ancestor_fetches() {
+    let (sleet1, _sleet2, _client, hail, root_kp, genesis_tx) =
+        start_test_env_with_two_sleet_actors().await;
+
+    let cell1 = generate_transfer(&root_kp, genesis_tx.clone(), 1);
+    sleet1.send(GenerateTx { cell: cell1.clone() }).await.unwrap();
+    let cell2 = generate_transfer(&root_kp, cell1.clone(), 2);
+    sleet1.send(GenerateTx { cell: cell2.clone() }).await.unwrap();
+
+    let SleetStatus { known_txs, .. } = sleet1.send(GetStatus).await.unwrap();
+    let (_, tx1) = tx_storage::get_tx(&known_txs, cell1.hash()).unwrap();
+    let (_, tx2) = tx_storage::get_tx(&known_txs, cell2.hash()).unwrap();
+
+    // The ancestors of `tx2` take a while to arrive
+    let mut client = DummyClient::new();
+    client.responses = vec![(mock_validator_id(), true)];
+    client.ancestors = vec![tx1];
+    client.delay = Some(Duration::from_millis(200));
+    let fetched = client.fetched.clone();
+    let sleet3 =
+        Sleet::new(client.start().recipient(), hail.recipient(), Id::two(), mock_ip(), vec![])
+            .start();
+    sleet3.send(make_live_committee(vec![genesis_tx])).await.unwrap();
+
+    // Both queries miss the same ancestor, which is fetched only once
+    let query = QueryTx { id: Id::zero(), ip: mock_ip(), tx: tx2.clone() };
+    let (ack1, ack2) = futures::join!(sleet3.send(query.clone()), sleet3.send(query));
+    assert!(ack1.unwrap().outcome);
+    assert!(ack2.unwrap().outcome);
+    assert_eq!(*fetched.lock().unwrap(), vec![tx2.hash()]);
+}
+
+#[actix_rt::test]
+async fn test_coalesce_fetches_of_a_shared_ancestor() {
+    let (sleet1, _sleet2, _client, hail, root_kp, genesis_tx) =
+        start_test_env_with_two_sleet_actors().await;
+
+    let cell1 = generate_transfer(&root_kp, genesis_tx.clone(), 1);
+    sleet1.send(GenerateTx { cell: cell1.clone() }).await.unwrap();
+    let cell2 = generate_transfer(&root_kp, cell1.clone(), 2);
+    sleet1.send(GenerateTx { cell: cell2.clone() }).await.unwrap();
+
+    let SleetStatus { known_txs, .. } = sleet1.send(GetStatus).await.unwrap();
+    let (_, tx1) = tx_storage::get_tx(&known_txs, cell1.hash()).unwrap();
+    let (_, tx2) = tx_storage::get_tx(&known_txs, cell2.hash()).unwrap();
+    // Another child of `tx1`, spending another live cell
+    let other_coinbase = generate_coinbase(&root_kp, 5000);
+    let other_tx =
+        Tx::new(vec![tx1.hash()], generate_transfer(&root_kp, other_coinbase.clone(), 3));
+
+    // The ancestors of `tx2` take a while to arrive
+    let mut client = DummyClient::new();
+    client.responses = vec![(mock_validator_id(), true)];
+    client.ancestors = vec![tx1];
+    client.delay = Some(Duration::from_millis(200));
+    let fetched = client.fetched.clone();
+    let sleet3 =
+        Sleet::new(client.start().recipient(), hail.recipient(), Id::two(), mock_ip(), vec![])
+            .start();
+    sleet3.send(make_live_committee(vec![genesis_tx, other_coinbase])).await.unwrap();
+
+    // Both transactions miss `tx1`, which is fetched only once
+    let (ack1, ack2) = futures::join!(
+        sleet3.send(QueryTx { id: Id::zero(), ip: mock_ip(), tx: tx2.clone() }),
+        sleet3.send(QueryTx { id: Id::zero(), ip: mock_ip(), tx: other_tx })
+    );
+    assert!(ack1.unwrap().outcome);
+    assert!(ack2.unwrap().outcome);
+    assert_eq!(*fetched.lock().unwrap(), vec![tx2.hash()]);
+}
+
+#[actix_rt::test]
+async fn test_truncated_ancestors_without_progress() {
+    let (sleet1, _sleet2, _client, hail, root_kp, genesis_tx) =
+        start_test_env_with_two_sleet_actors().await;
+
+    let cell1 = generate_transfer(&root_kp, genesis_tx.clone(), 1);
+    sleet1.send(GenerateTx { cell: cell1.clone() }).await.unwrap();
+    let cell2 = generate_transfer(&root_kp, cell1.clone(), 2);
+    sleet1.send(GenerateTx { cell: cell2.clone() }).await.unwrap();
+    let cell3 = generate_transfer(&root_kp, cell2.clone(), 3);
+    sleet1.send(GenerateTx { cell: cell3.clone() }).await.unwrap();
+
+    let SleetStatus { known_txs, .. } = sleet1.send(GetStatus).await.unwrap();
+    let (_, tx2) = tx_storage::get_tx(&known_txs, cell2.hash()).unwrap();
+    let (_, tx3) = tx_storage::get_tx(&known_txs, cell3.hash()).unwrap();
+
+    // The peer keeps answering with the same truncated ancestry, never sending `tx1`
+    let mut client = DummyClient::new();
+    client.responses = vec![(mock_validator_id(), true)];
+    client.ancestors = vec![tx2.clone()];
+    client.truncated = true;
+    let fetched = client.fetched.clone();
+    let sleet3 =
+        Sleet::new(client.start().recipient(), hail.recipient(), Id::two(), mock_ip(), vec![])
+            .start();
+    sleet3.send(make_live_committee(vec![genesis_tx])).await.unwrap();
+
+    let _ = sleet3.send(QueryTx { id: Id::zero(), ip: mock_ip(), tx: tx3.clone() }).await.unwrap();
+    sleep_ms(200).await;
+    // `tx2` is asked for once, as asking again would make no progress
+    assert_eq!(*fetched.lock().unwrap(), vec![tx3.hash(), tx2.hash()]);
+}
+
 #[actix_rt::test]
 async fn test_sleet_get_wrong_ancestor() {
     let (sleet1, sleet2, client, _hail, root_kp, genesis_tx) =
@@ -875,9 +2017,27 @@ async fn test_sleet_remove_children_of_rejected() {
 
     // Re-try `tx3`
     match sleet1.send(GenerateTx { cell: cell3.clone() }).await.unwrap() {
-        GenerateTxAck { cell_hash: Some(_) } => (),
-        GenerateTxAck { cell_hash: None } => panic!("re-issuing transaction failed"),
+        GenerateTxAck { cell_hash: Some(_), .. } => (),
+        GenerateTxAck { cell_hash: None, .. } => panic!("re-issuing transaction failed"),
     }
+
+    // The histories list every transition of the transactions, in order
+    let statuses = |history: CellHistory| -> Vec<TxStatus> {
+        history.history.into_iter().map(|(status, _)| status).collect()
+    };
+    let history = sleet1.send(GetCellHistory { cell_hash: cell2.hash() }).await.unwrap();
+    assert_eq!(statuses(history), vec![TxStatus::Pending, TxStatus::Queried, TxStatus::Accepted]);
+    let history = sleet1.send(GetCellHistory { cell_hash: cell2_rogue.hash() }).await.unwrap();
+    assert_eq!(statuses(history), vec![TxStatus::Pending, TxStatus::Queried, TxStatus::Rejected]);
+    let history = sleet1.send(GetCellHistory { cell_hash: cell3.hash() }).await.unwrap();
+    assert!(history.history.windows(2).all(|w| w[0].1 <= w[1].1));
+    assert_eq!(
+        statuses(history)[..4],
+        [TxStatus::Pending, TxStatus::Queried, TxStatus::Removed, TxStatus::Pending]
+    );
+
+    let history = sleet1.send(GetCellHistory { cell_hash: new_pkh() }).await.unwrap();
+    assert!(history.history.is_empty());
 }
 
 #[actix_rt::test]
@@ -910,7 +2070,587 @@ async fn test_strongly_preferred() {
     sleet.insert(stx2.clone()).unwrap();
     sleet.insert(stx3.clone()).unwrap();
 
-    // Coinbase transactions will all conflict, since `tx1` was inserted first it will
-    // be the only preferred parent.
-    assert_eq!(sleet.select_parents(3).unwrap(), vec![stx1.cell.hash(),]);
+    // Coinbase transactions will all conflict, the one with the lowest hash is initially
+    // preferred, so it will be the only preferred parent.
+    let lowest = [stx1.cell.hash(), stx2.cell.hash(), stx3.cell.hash()].iter().min().cloned();
+    assert_eq!(sleet.select_parents(3).unwrap(), vec![lowest.unwrap()]);
+}
+
+#[actix_rt::test]
+async fn test_get_ancestors_depth() {
+    let client = DummyClient::new();
+    let sender = client.start();
+    let hail_mock = HailMock::new();
+    let receiver = hail_mock.start();
+
+    let mut csprng = OsRng {};
+    let root_kp = Keypair::generate(&mut csprng);
+    let genesis_tx = generate_coinbase(&root_kp, 10000);
+    let genesis_cell_ids = CellIds::from_outputs(genesis_tx.hash(), genesis_tx.outputs()).unwrap();
+    let mut sleet =
+        Sleet::new(sender.recipient(), receiver.recipient(), Id::zero(), mock_ip(), vec![]);
+    sleet.conflict_graph = ConflictGraph::new(genesis_cell_ids);
+
+    // A chain of 10 transactions, each spending and having its predecessor as parent
+    let mut chain: Vec<Tx> = vec![];
+    let mut cell = genesis_tx;
+    for i in 0..10 {
+        cell = generate_transfer(&root_kp, cell, 10 + i);
+        let parents = chain.last().map(|tx| vec![tx.hash()]).unwrap_or_default();
+        let tx = Tx::new(parents, cell.clone());
+        sleet.insert(tx.clone()).unwrap();
+        let _ = tx_storage::insert_tx(&sleet.known_txs, tx.clone());
+        chain.push(tx);
+    }
+    let sleet = sleet.start();
+
+    let TxAncestors { ancestors, truncated } = sleet
+        .send(GetTxAncestors { tx_hash: chain[9].hash(), max_depth: 2 })
+        .await
+        .unwrap()
+        .unwrap();
+    let hashes: Vec<TxHash> = ancestors.iter().map(|tx| tx.hash()).collect();
+    assert_eq!(hashes, vec![chain[7].hash(), chain[8].hash()]);
+    assert!(truncated);
+
+    let TxAncestors { ancestors, truncated } = sleet
+        .send(GetTxAncestors { tx_hash: chain[9].hash(), max_depth: 9 })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(ancestors.len(), 9);
+    assert!(!truncated);
+}
+
+#[actix_rt::test]
+async fn test_shutdown_drains_queries() {
+    let delay = Duration::from_millis(300);
+    let mut client = DummyClient::new();
+    client.responses = vec![(mock_validator_id(), true)];
+    client.delay = Some(delay);
+    let sender = client.start();
+    let receiver = HailMock::new().start();
+
+    let store = Arc::new(TestStore::new());
+    let sleet = Sleet::new(sender.recipient(), receiver.recipient(), Id::zero(), mock_ip(), vec![])
+        .with_known_txs(store.clone())
+        .start();
+
+    let mut csprng = OsRng {};
+    let root_kp = Keypair::generate(&mut csprng);
+    let genesis_tx = generate_coinbase(&root_kp, 10000);
+    sleet.send(make_live_committee(vec![genesis_tx.clone()])).await.unwrap();
+
+    // The query of the transaction is in flight when shutting down
+    let cell = generate_transfer(&root_kp, genesis_tx.clone(), 1);
+    let ack = sleet.send(GenerateTx { cell }).await.unwrap();
+    let queried = ack.cell_hash.unwrap();
+
+    let started = Instant::now();
+    let shutdown = sleet.send(Shutdown { timeout: Duration::from_secs(5) });
+
+    // New transactions are refused while the queries are draining
+    let cell = generate_transfer(&root_kp, genesis_tx.clone(), 2);
+    let ack = sleet.send(GenerateTx { cell }).await.unwrap();
+    assert_eq!(ack.cell_hash, None);
+    assert_eq!(ack.rejected, Some(RejectReason::ShuttingDown));
+
+    shutdown.await.unwrap();
+    assert!(started.elapsed() >= delay / 2);
+    // The store was flushed with the queried transaction
+    let flushed = InMemoryStore::new();
+    for (k, v) in store.last_flush().unwrap() {
+        let _ = flushed.insert(&k, v).unwrap();
+    }
+    let (_, tx) = tx_storage::get_tx(&flushed, queried).unwrap();
+    assert_eq!(tx.status, TxStatus::Queried);
+
+    // The actor stopped, new transactions don't reach it anymore
+    let cell = generate_transfer(&root_kp, genesis_tx, 3);
+    assert!(matches!(sleet.send(GenerateTx { cell }).await, Err(MailboxError::Closed)));
+    assert!(!sleet.connected());
+}
+
+/// Client substitute answering every request with a response of the wrong kind
+struct WrongKindClient;
+
+impl Actor for WrongKindClient {
+    type Context = Context<Self>;
+}
+
+impl Handler<ClientRequest> for WrongKindClient {
+    type Result = ResponseFuture<ClientResponse>;
+
+    fn handle(&mut self, msg: ClientRequest, _ctx: &mut Context<Self>) -> Self::Result {
+        match msg {
+            ClientRequest::Fanout { .. } => Box::pin(async { ClientResponse::Oneshot(None) }),
+            ClientRequest::Oneshot { .. } => Box::pin(async { ClientResponse::Fanout(vec![]) }),
+        }
+    }
+}
+
+#[actix_rt::test]
+async fn test_unexpected_response_kind() {
+    let sender = WrongKindClient.start();
+    let receiver = HailMock::new().start();
+    let sleet =
+        Sleet::new(sender.recipient(), receiver.recipient(), Id::zero(), mock_ip(), vec![]).start();
+    let root_kp = Keypair::generate(&mut OsRng {});
+    let genesis_tx = generate_coinbase(&root_kp, 1000);
+    sleet.send(make_live_committee(vec![genesis_tx.clone()])).await.unwrap();
+
+    let cell = generate_transfer(&root_kp, genesis_tx.clone(), 1);
+    sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap();
+    let SleetStatus { known_txs, .. } = sleet.send(GetStatus).await.unwrap();
+    let (_, tx) = tx_storage::get_tx(&*known_txs, cell.hash()).unwrap();
+
+    // The query fails instead of crashing Sleet
+    match sleet.send(FreshTx { tx }).await.unwrap() {
+        Err(Error::Client(ClientError::UnexpectedResponseKind)) => (),
+        other => panic!("unexpected: {:?}", other),
+    }
+    let stats = sleet.send(GetMempoolStats).await.unwrap();
+    assert_eq!(stats.txs.queried, 1);
+    assert_eq!(stats.txs.accepted, 0);
+}
+
+/// Client substitute forwarding the bootstrap and query requests to a peer
+struct PeerClient {
+    peer: Addr<Sleet>,
+    // The `Oneshot` requests which were sent
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl Actor for PeerClient {
+    type Context = Context<Self>;
+}
+
+impl Handler<ClientRequest> for PeerClient {
+    type Result = ResponseFuture<ClientResponse>;
+
+    fn handle(&mut self, msg: ClientRequest, _ctx: &mut Context<Self>) -> Self::Result {
+        let peer = self.peer.clone();
+        let requests = self.requests.clone();
+        match msg {
+            ClientRequest::Fanout { peers, request, .. } => Box::pin(async move {
+                let r = match request {
+                    Request::GetAcceptedFrontier => {
+                        Response::AcceptedFrontier(peer.send(GetAcceptedFrontier).await.unwrap())
+                    }
+                    x => panic!("unexpected request: {:?}", x),
+                };
+                ClientResponse::Fanout(peers.iter().map(|(id, _)| (*id, Ok(r.clone()))).collect())
+            }),
+            ClientRequest::Oneshot { request, .. } => Box::pin(async move {
+                requests.lock().unwrap().push(request.clone());
+                let r = match request {
+                    Request::FetchTx(fetch_tx) => {
+                        Response::FetchedTx(peer.send(fetch_tx).await.unwrap())
+                    }
+                    Request::QueryTx(query_tx) => {
+                        Response::QueryTxAck(peer.send(query_tx).await.unwrap())
+                    }
+                    Request::QueryTxBatch(query_tx_batch) => {
+                        Response::QueryTxBatchAck(peer.send(query_tx_batch).await.unwrap())
+                    }
+                    x => panic!("unexpected request: {:?}", x),
+                };
+                ClientResponse::Oneshot(Some(r))
+            }),
+        }
+    }
+}
+
+#[actix_rt::test]
+async fn test_bootstrap_genesis_mismatch() {
+    let sender = DummyClient::new().start();
+    let receiver = HailMock::new().start();
+
+    let mut csprng = OsRng {};
+    let root_kp = Keypair::generate(&mut csprng);
+    let genesis_tx = generate_coinbase(&root_kp, 1000);
+    let tx = Tx::new(vec![], generate_transfer(&root_kp, genesis_tx, 1));
+
+    // `sleet1` has `tx` in its accepted frontier
+    let genesis_hash = [1u8; 32];
+    let keypair = Keypair::generate(&mut csprng);
+    let id1 = Id::from_public_key(&keypair.public);
+    let mut sleet1 =
+        Sleet::new(sender.recipient(), receiver.clone().recipient(), id1, mock_ip(), vec![])
+            .with_genesis_hash(genesis_hash)
+            .with_keypair(keypair);
+    tx_storage::insert_tx(&sleet1.known_txs, tx.clone()).unwrap();
+    let _ = sleet1.accepted_frontier.insert(tx.hash());
+    let sleet1 = sleet1.start();
+
+    let bootstrap = |genesis_hash: BlockHash| {
+        let client = PeerClient { peer: sleet1.clone(), requests: Default::default() }.start();
+        let peers = vec![(id1, mock_ip())];
+        Sleet::new(client.recipient(), receiver.clone().recipient(), Id::two(), mock_ip(), peers)
+            .with_genesis_hash(genesis_hash)
+            .start()
+    };
+
+    // A node on a different network refuses to merge the frontier of `sleet1`
+    let sleet2 = bootstrap([2u8; 32]);
+    sleep_ms(200).await;
+    assert!(!sleet2.send(Bootstrapped).await.unwrap());
+    let status = sleet2.send(GetStatus).await.unwrap();
+    assert!(status.accepted_frontier.is_empty());
+    assert_eq!(status.dag_len, 0);
+    assert!(!tx_storage::is_known_tx(&status.known_txs, tx.hash()).unwrap());
+
+    // A node sharing the genesis bootstraps from `sleet1`
+    let sleet3 = bootstrap(genesis_hash);
+    let mut bootstrapped = false;
+    for _ in 0..50 {
+        if sleet3.send(Bootstrapped).await.unwrap() {
+            bootstrapped = true;
+            break;
+        }
+        sleep_ms(10).await;
+    }
+    assert!(bootstrapped);
+    let status = sleet3.send(GetStatus).await.unwrap();
+    assert_eq!(status.accepted_frontier, vec![tx.hash()].into_iter().collect());
+    assert!(tx_storage::is_known_tx(&status.known_txs, tx.hash()).unwrap());
+}
+
+/// Client substitute forwarding the bootstrap requests to the peer they are addressed to
+struct PeersClient {
+    peers: HashMap<Id, Addr<Sleet>>,
+}
+
+impl Actor for PeersClient {
+    type Context = Context<Self>;
+}
+
+impl Handler<ClientRequest> for PeersClient {
+    type Result = ResponseFuture<ClientResponse>;
+
+    fn handle(&mut self, msg: ClientRequest, _ctx: &mut Context<Self>) -> Self::Result {
+        let peers = self.peers.clone();
+        match msg {
+            ClientRequest::Fanout { request: Request::GetAcceptedFrontier, .. } => {
+                Box::pin(async move {
+                    let mut responses = vec![];
+                    for (id, peer) in peers {
+                        let frontier = peer.send(GetAcceptedFrontier).await.unwrap();
+                        responses.push((id, Ok(Response::AcceptedFrontier(frontier))));
+                    }
+                    ClientResponse::Fanout(responses)
+                })
+            }
+            ClientRequest::Oneshot { id, request: Request::FetchTx(fetch_tx), .. } => {
+                Box::pin(async move {
+                    let fetched = peers[&id].send(fetch_tx).await.unwrap();
+                    ClientResponse::Oneshot(Some(Response::FetchedTx(fetched)))
+                })
+            }
+            x => panic!("unexpected request: {:?}", x),
+        }
+    }
+}
+
+#[actix_rt::test]
+async fn test_bootstrap_frontier_quorum() {
+    let sender = DummyClient::new().start();
+    let receiver = HailMock::new().start();
+
+    let mut csprng = OsRng {};
+    let root_kp = Keypair::generate(&mut csprng);
+    let genesis_tx = generate_coinbase(&root_kp, 1000);
+    let tx = Tx::new(vec![], generate_transfer(&root_kp, genesis_tx, 1));
+    let bogus = [9u8; 32];
+
+    // All three peers have `tx` in their accepted frontier, the last one adds a bogus hash
+    let genesis_hash = [1u8; 32];
+    let mut peers = HashMap::new();
+    for i in 0..3 {
+        let keypair = Keypair::generate(&mut csprng);
+        let id = Id::from_public_key(&keypair.public);
+        let mut sleet = Sleet::new(
+            sender.clone().recipient(),
+            receiver.clone().recipient(),
+            id,
+            mock_ip(),
+            vec![],
+        )
+        .with_genesis_hash(genesis_hash)
+        .with_keypair(keypair);
+        tx_storage::insert_tx(&sleet.known_txs, tx.clone()).unwrap();
+        let _ = sleet.accepted_frontier.insert(tx.hash());
+        if i == 2 {
+            let _ = sleet.accepted_frontier.insert(bogus);
+        }
+        let _ = peers.insert(id, sleet.start());
+    }
+
+    // A frontier only verifies against the `Id` of the peer which signed it
+    let (id, peer) = peers.iter().next().unwrap();
+    let frontier = peer.send(GetAcceptedFrontier).await.unwrap();
+    assert!(frontier.verify(id).is_ok());
+    assert!(matches!(frontier.verify(&Id::one()), Err(Error::InvalidFrontierSignature(_))));
+
+    let bootstrap_peers = peers.keys().map(|id| (*id, mock_ip())).collect();
+    let client = PeersClient { peers }.start();
+    let sleet =
+        Sleet::new(client.recipient(), receiver.recipient(), Id::two(), mock_ip(), bootstrap_peers)
+            .with_genesis_hash(genesis_hash)
+            .start();
+    let mut bootstrapped = false;
+    for _ in 0..50 {
+        if sleet.send(Bootstrapped).await.unwrap() {
+            bootstrapped = true;
+            break;
+        }
+        sleep_ms(10).await;
+    }
+    assert!(bootstrapped);
+
+    // The bogus hash isn't corroborated by the other peers
+    let status = sleet.send(GetStatus).await.unwrap();
+    assert_eq!(status.accepted_frontier, vec![tx.hash()].into_iter().collect());
+    assert!(tx_storage::is_known_tx(&status.known_txs, tx.hash()).unwrap());
+}
+
+#[actix_rt::test]
+async fn test_bootstrap_discards_foreign_genesis() {
+    let sender = DummyClient::new().start();
+    let receiver = HailMock::new().start();
+
+    let mut csprng = OsRng {};
+    let root_kp = Keypair::generate(&mut csprng);
+    let genesis_tx = generate_coinbase(&root_kp, 1000);
+    let tx = Tx::new(vec![], generate_transfer(&root_kp, genesis_tx, 1));
+    let bogus = [9u8; 32];
+
+    // Three of the four peers share the genesis, the bogus hash is in the frontiers of two of
+    // them and of the peer on another chain
+    let genesis_hash = [1u8; 32];
+    let mut peers = HashMap::new();
+    for i in 0..4 {
+        let keypair = Keypair::generate(&mut csprng);
+        let id = Id::from_public_key(&keypair.public);
+        let mut sleet = Sleet::new(
+            sender.clone().recipient(),
+            receiver.clone().recipient(),
+            id,
+            mock_ip(),
+            vec![],
+        )
+        .with_genesis_hash(if i == 3 { [2u8; 32] } else { genesis_hash })
+        .with_keypair(keypair);
+        tx_storage::insert_tx(&sleet.known_txs, tx.clone()).unwrap();
+        let _ = sleet.accepted_frontier.insert(tx.hash());
+        if i > 0 {
+            let _ = sleet.accepted_frontier.insert(bogus);
+        }
+        let _ = peers.insert(id, sleet.start());
+    }
+
+    let bootstrap_peers = peers.keys().map(|id| (*id, mock_ip())).collect();
+    let client = PeersClient { peers }.start();
+    let sleet =
+        Sleet::new(client.recipient(), receiver.recipient(), Id::two(), mock_ip(), bootstrap_peers)
+            .with_genesis_hash(genesis_hash)
+            .start();
+    let mut bootstrapped = false;
+    for _ in 0..50 {
+        if sleet.send(Bootstrapped).await.unwrap() {
+            bootstrapped = true;
+            break;
+        }
+        sleep_ms(10).await;
+    }
+    assert!(bootstrapped);
+
+    // The peer on another chain doesn't corroborate the bogus hash
+    let status = sleet.send(GetStatus).await.unwrap();
+    assert_eq!(status.accepted_frontier, vec![tx.hash()].into_iter().collect());
+}
+
+#[actix_rt::test]
+async fn test_bootstrap_quorum_of_all_peers() {
+    let sender = DummyClient::new().start();
+    let receiver = HailMock::new().start();
+
+    let mut csprng = OsRng {};
+    let root_kp = Keypair::generate(&mut csprng);
+    let genesis_tx = generate_coinbase(&root_kp, 1000);
+    let tx = Tx::new(vec![], generate_transfer(&root_kp, genesis_tx, 1));
+
+    // The peer isn't known under the `Id` derived from its key, as with plain TCP connections
+    let genesis_hash = [1u8; 32];
+    let mut peer =
+        Sleet::new(sender.recipient(), receiver.clone().recipient(), Id::one(), mock_ip(), vec![])
+            .with_genesis_hash(genesis_hash)
+            .with_keypair(Keypair::generate(&mut csprng));
+    tx_storage::insert_tx(&peer.known_txs, tx.clone()).unwrap();
+    let _ = peer.accepted_frontier.insert(tx.hash());
+    let peer = peer.start();
+
+    let bootstrap = |bootstrap_peers: Vec<Id>, authenticated_ids: bool| {
+        let peers = vec![(Id::one(), peer.clone())].into_iter().collect();
+        let client = PeersClient { peers }.start();
+        let bootstrap_peers = bootstrap_peers.into_iter().map(|id| (id, mock_ip())).collect();
+        let sleet = Sleet::new(
+            client.recipient(),
+            receiver.clone().recipient(),
+            Id::two(),
+            mock_ip(),
+            bootstrap_peers,
+        )
+        .with_genesis_hash(genesis_hash);
+        if authenticated_ids {
+            sleet.start()
+        } else {
+            sleet.with_unauthenticated_ids().start()
+        }
+    };
+    let bootstrapped_frontier = |sleet: Addr<Sleet>| async move {
+        for _ in 0..50 {
+            if sleet.send(Bootstrapped).await.unwrap() {
+                return Some(sleet.send(GetStatus).await.unwrap().accepted_frontier);
+            }
+            sleep_ms(10).await;
+        }
+        None
+    };
+
+    // The frontier isn't signed by the key of `Id::one()`, so there is no quorum
+    let sleet = bootstrap(vec![Id::one()], true);
+    assert_eq!(bootstrapped_frontier(sleet).await, None);
+
+    // Without authenticated ids, only the signature is checked
+    let sleet = bootstrap(vec![Id::one()], false);
+    assert_eq!(bootstrapped_frontier(sleet).await, Some(vec![tx.hash()].into_iter().collect()));
+
+    // The bootstrap peers which don't respond count against the quorum
+    let sleet = bootstrap(vec![Id::one(), Id::new(&[3u8; 32]), Id::new(&[4u8; 32])], false);
+    assert_eq!(bootstrapped_frontier(sleet).await, None);
+}
+
+#[actix_rt::test]
+async fn test_query_txs_without_batch_support() {
+    let (peer, _client, _hail, root_kp, genesis_tx) = start_test_env().await;
+    let requests = Arc::new(Mutex::new(vec![]));
+    let client = PeerClient { peer, requests: requests.clone() }.start().recipient();
+    let txs: Vec<Tx> = (1..4)
+        .map(|amount| Tx::new(vec![], generate_transfer(&root_kp, genesis_tx.clone(), amount)))
+        .collect();
+    let hashes: Vec<TxHash> = txs.iter().map(|tx| tx.hash()).collect();
+    let peer_id = (Id::one(), mock_ip());
+
+    // The peer lacks batch support, so each transaction is queried separately
+    let capabilities = Capabilities::current()
+        .negotiate(&Capabilities::current().with_feature(Capabilities::BATCH_QUERY, false));
+    let acks = query_txs(&client, Id::two(), mock_ip(), peer_id, &capabilities, txs.clone())
+        .await
+        .unwrap();
+    assert_eq!(acks.iter().map(|ack| ack.tx_hash).collect::<Vec<_>>(), hashes);
+    assert!(matches!(
+        requests.lock().unwrap().as_slice(),
+        [Request::QueryTx(_), Request::QueryTx(_), Request::QueryTx(_)]
+    ));
+
+    // A peer supporting batches is queried about all transactions at once
+    requests.lock().unwrap().clear();
+    let capabilities = Capabilities::current().negotiate(&Capabilities::current());
+    let acks = query_txs(&client, Id::two(), mock_ip(), peer_id, &capabilities, txs).await.unwrap();
+    assert_eq!(acks.iter().map(|ack| ack.tx_hash).collect::<Vec<_>>(), hashes);
+    assert!(matches!(
+        requests.lock().unwrap().as_slice(),
+        [Request::QueryTxBatch(QueryTxBatch { txs, .. })] if txs.len() == 3
+    ));
+}
+
+/// Client substitute failing the [FreshTx] queries, and forwarding the queries of the
+/// re-broadcasts to a peer
+struct RebroadcastClient {
+    peer: Addr<PeerClient>,
+}
+
+impl Actor for RebroadcastClient {
+    type Context = Context<Self>;
+}
+
+impl Handler<ClientRequest> for RebroadcastClient {
+    type Result = ResponseFuture<ClientResponse>;
+
+    fn handle(&mut self, msg: ClientRequest, _ctx: &mut Context<Self>) -> Self::Result {
+        let peer = self.peer.clone();
+        Box::pin(async move {
+            match msg {
+                ClientRequest::Fanout { peers, request: Request::QueryTx(_), .. } => {
+                    let results = peers.iter().map(|(id, _)| (*id, Err(ClientError::NoResponse)));
+                    ClientResponse::Fanout(results.collect())
+                }
+                oneshot => peer.send(oneshot).await.unwrap(),
+            }
+        })
+    }
+}
+
+/// View substitute with the same capabilities for all peers
+struct PeerMetaMock {
+    capabilities: Capabilities,
+}
+
+impl Actor for PeerMetaMock {
+    type Context = Context<Self>;
+}
+
+impl Handler<view::GetPeerMeta> for PeerMetaMock {
+    type Result = Option<view::PeerMeta>;
+
+    fn handle(&mut self, _msg: view::GetPeerMeta, _ctx: &mut Context<Self>) -> Self::Result {
+        let version = crate::version::CURRENT_VERSION;
+        Some(view::PeerMeta { version, capabilities: self.capabilities.clone() })
+    }
+}
+
+/// Re-broadcasts three transactions whose first queries failed, returns the requests sent to the
+/// validator with `capabilities`
+async fn rebroadcast_to_validator(capabilities: Capabilities) -> Vec<Request> {
+    let (peer, _client, hail, root_kp, genesis_tx) = start_test_env().await;
+    let requests = Arc::new(Mutex::new(vec![]));
+    let peer = PeerClient { peer, requests: requests.clone() }.start();
+    let client = RebroadcastClient { peer }.start();
+    // The transactions are all generated before the first look for re-broadcasts, so that they
+    // are re-broadcast together
+    let sleet = Sleet::new(client.recipient(), hail.recipient(), Id::two(), mock_ip(), vec![])
+        .with_rebroadcast(Duration::from_millis(300), Duration::from_millis(100))
+        .with_peer_meta(PeerMetaMock { capabilities }.start().recipient())
+        .start();
+    let mut live_committee = make_live_committee(vec![genesis_tx.clone()]);
+    live_committee.validators = vec![(Id::zero(), (mock_ip(), 1.0))].into_iter().collect();
+    sleet.send(live_committee).await.unwrap();
+
+    let mut spend_cell = genesis_tx;
+    for i in 0..3 {
+        let cell = generate_transfer(&root_kp, spend_cell.clone(), 1 + i as u64);
+        sleet.send(GenerateTx { cell: cell.clone() }).await.unwrap();
+        spend_cell = cell;
+    }
+    sleep_ms(450).await;
+    let requests = requests.lock().unwrap().clone();
+    requests
+}
+
+#[actix_rt::test]
+async fn test_rebroadcast_with_batches() {
+    // The validator answering batches is queried about all transactions at once
+    let requests = rebroadcast_to_validator(Capabilities::current()).await;
+    assert!(matches!(
+        requests.as_slice(),
+        [Request::QueryTxBatch(QueryTxBatch { txs, .. })] if txs.len() == 3
+    ));
+
+    // The validator lacking batch support is queried about each transaction
+    let requests = rebroadcast_to_validator(Capabilities::none()).await;
+    assert!(matches!(
+        requests.as_slice(),
+        [Request::QueryTx(_), Request::QueryTx(_), Request::QueryTx(_)]
+    ));
 }
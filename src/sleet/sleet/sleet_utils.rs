@@ -1,39 +1,85 @@
 //! Utility data structures to keep Sleet memory use bounded
 
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::hash::Hash;
 use std::ops::Deref;
 
-/// A `HashSet` replacement with a maximum capacity, once full the oldest element gets removed
+/// Tracks the order in which keys were last used, for evicting the least recently used one
+struct Recency<K> {
+    /// Incremented on every use
+    clock: u64,
+    /// The time of the last use of each key
+    last_used: HashMap<K, u64>,
+    /// The keys by the time of their last use
+    order: BTreeMap<u64, K>,
+}
+
+impl<K: Clone + Eq + Hash> Recency<K> {
+    fn with_capacity(size: usize) -> Self {
+        Recency { clock: 0, last_used: HashMap::with_capacity(size + 1), order: BTreeMap::new() }
+    }
+
+    /// Marks `k` as the most recently used key.
+    fn touch(&mut self, k: &K) {
+        self.clock += 1;
+        if let Some(t) = self.last_used.insert(k.clone(), self.clock) {
+            let _ = self.order.remove(&t);
+        }
+        let _ = self.order.insert(self.clock, k.clone());
+    }
+
+    /// Removes and returns the least recently used key.
+    fn pop_lru(&mut self) -> Option<K> {
+        let t = *self.order.keys().next()?;
+        let k = self.order.remove(&t)?;
+        let _ = self.last_used.remove(&k);
+        Some(k)
+    }
+}
+
+/// A `HashSet` replacement with a maximum capacity, once full the least recently used element
+/// gets removed
 pub struct BoundedHashSet<T> {
     size: usize,
     elems: HashSet<T>,
-    queue: VecDeque<T>,
+    recency: Recency<T>,
 }
 
 impl<T: Clone + Eq + Hash> BoundedHashSet<T> {
     /// Creates a new instance with `size` as max allowed capacity.
-    /// When it reaches the max capacity, the oldest elements must be removed upon insert.
+    /// When it reaches the max capacity, the least recently used elements are removed upon
+    /// insert.
     pub fn new(size: usize) -> Self {
         BoundedHashSet {
             size,
             elems: HashSet::with_capacity(size + 1),
-            queue: VecDeque::with_capacity(size + 1),
+            recency: Recency::with_capacity(size),
         }
     }
 
-    /// Insert an element into the hash set.
-    /// When it reaches the max capacity, the first oldest element is removed on FIFO basis.
+    /// Insert an element into the hash set, or mark it as used if it's already present.
+    /// When it reaches the max capacity, the least recently used element is removed.
     pub fn insert(&mut self, elem: T) {
-        let duplicate = !self.elems.insert(elem.clone());
-        if duplicate {
+        self.recency.touch(&elem);
+        if !self.elems.insert(elem) {
             return;
         }
-        if self.elems.len() >= self.size {
-            let e = self.queue.pop_front().unwrap();
-            let _ = self.elems.remove(&e);
+        if self.elems.len() > self.size {
+            if let Some(e) = self.recency.pop_lru() {
+                let _ = self.elems.remove(&e);
+            }
+        }
+    }
+
+    /// Returns `true` if the set contains `elem`, marking it as used.
+    #[allow(unused)] // Currently only used by tests
+    pub fn contains(&mut self, elem: &T) -> bool {
+        if self.elems.contains(elem) {
+            self.recency.touch(elem);
+            true
+        } else {
+            false
         }
-        self.queue.push_back(elem);
     }
 }
 
@@ -45,35 +91,51 @@ impl<T: Clone + Eq + Hash> Deref for BoundedHashSet<T> {
     }
 }
 
-/// A `HashMap` replacement with a maximum capacity, once full the oldest element gets removed
+/// A `HashMap` replacement with a maximum capacity, once full the least recently used element
+/// gets removed
 pub struct BoundedHashMap<K, V> {
     size: usize,
     elems: HashMap<K, V>,
-    queue: VecDeque<K>,
+    recency: Recency<K>,
 }
 
 impl<K: Clone + Eq + Hash, V> BoundedHashMap<K, V> {
     /// Creates a new instance with `size` as max allowed capacity.
-    /// When it reaches the max capacity, the oldest elements must be removed upon insert.
+    /// When it reaches the max capacity, the least recently used elements are removed upon
+    /// insert.
     pub fn new(size: usize) -> Self {
         BoundedHashMap {
             size,
             elems: HashMap::with_capacity(size + 1),
-            queue: VecDeque::with_capacity(size + 1),
+            recency: Recency::with_capacity(size),
         }
     }
 
-    /// Insert an element into the hash map.
-    /// When it reaches the max capacity, the first oldest element is removed on FIFO basis.
+    /// Insert an element into the hash map, replacing and marking as used an existing one.
+    /// When it reaches the max capacity, the least recently used element is removed.
     pub fn insert(&mut self, k: K, v: V) {
-        if let Some(_) = self.elems.insert(k.clone(), v) {
+        self.recency.touch(&k);
+        if self.elems.insert(k, v).is_some() {
             return;
         }
-        if self.elems.len() >= self.size {
-            let e = self.queue.pop_front().unwrap();
-            let _ = self.elems.remove(&e);
+        if self.elems.len() > self.size {
+            if let Some(e) = self.recency.pop_lru() {
+                let _ = self.elems.remove(&e);
+            }
+        }
+    }
+
+    /// Returns the value of `k`, marking it as used.
+    pub fn get(&mut self, k: &K) -> Option<&V> {
+        if self.elems.contains_key(k) {
+            self.recency.touch(k);
         }
-        self.queue.push_back(k);
+        self.elems.get(k)
+    }
+
+    /// Returns `true` if the map contains `k`, marking it as used.
+    pub fn contains_key(&mut self, k: &K) -> bool {
+        self.get(k).is_some()
     }
 }
 
@@ -117,4 +179,49 @@ mod test {
 
         assert!(!h.contains(&1));
     }
+
+    #[actix_rt::test]
+    async fn bounded_hashmap_lru_test() {
+        let mut h = BoundedHashMap::new(3);
+        h.insert(1, 1);
+        h.insert(2, 2);
+        h.insert(3, 3);
+        // Touch 1 and 2, leaving 3 as the least recently used
+        assert_eq!(h.get(&1), Some(&1));
+        assert!(h.contains_key(&2));
+
+        h.insert(4, 4);
+        assert_eq!(h.len(), 3);
+        assert!(!h.contains_key(&3));
+        // 1 is now the least recently used, then 2
+        h.insert(5, 5);
+        assert!(!h.contains_key(&1));
+        // Replacing a value marks the key as used
+        h.insert(2, 20);
+        h.insert(6, 6);
+        assert!(!h.contains_key(&4));
+        assert_eq!(h.get(&2), Some(&20));
+        assert!(h.contains_key(&5));
+        assert!(h.contains_key(&6));
+    }
+
+    #[actix_rt::test]
+    async fn bounded_hashset_lru_test() {
+        let mut h = BoundedHashSet::new(3);
+        h.insert(1);
+        h.insert(2);
+        h.insert(3);
+        assert!(h.contains(&1));
+        // Re-inserting marks the element as used
+        h.insert(2);
+
+        h.insert(4);
+        assert_eq!(h.len(), 3);
+        assert!(!h.contains(&3));
+        h.insert(5);
+        assert!(!h.contains(&1));
+        assert!(h.contains(&2));
+        assert!(h.contains(&4));
+        assert!(h.contains(&5));
+    }
 }
@@ -1,6 +1,9 @@
-use super::{Error, Result};
+use super::{cell, Error, KvStore, Result};
 use crate::alpha::block::Block;
 use crate::alpha::types::{BlockHash, BlockHeight};
+use crate::cell::types::CellHash;
+
+use std::collections::HashSet;
 
 use byteorder::BigEndian;
 use zerocopy::{byteorder::U64, AsBytes, FromBytes, Unaligned};
@@ -31,7 +34,7 @@ impl KeyPrefix {
 }
 
 /// Checks if the genesis block exists (the first block in the database).
-pub fn exists_genesis(db: &sled::Db) -> bool {
+pub fn exists_genesis(db: &dyn KvStore) -> bool {
     if let Ok(Some(_)) = db.first() {
         true
     } else {
@@ -40,7 +43,7 @@ pub fn exists_genesis(db: &sled::Db) -> bool {
 }
 
 /// Fetches the genesis block (the first block in the database).
-pub fn get_genesis(db: &sled::Db) -> Result<(BlockHash, Block)> {
+pub fn get_genesis(db: &dyn KvStore) -> Result<(BlockHash, Block)> {
     match db.first() {
         Ok(Some((k, v))) => {
             let key: Key = Key::read_from(k.as_bytes()).unwrap();
@@ -48,21 +51,21 @@ pub fn get_genesis(db: &sled::Db) -> Result<(BlockHash, Block)> {
             Ok((key.hash.clone(), block))
         }
         Ok(None) => Err(Error::InvalidGenesis),
-        Err(err) => Err(Error::Sled(err)),
+        Err(err) => Err(err),
     }
 }
 
 /// Inserts the genesis block into the database, returning its hash.
-pub fn accept_genesis(db: &sled::Db, genesis: Block) -> Result<BlockHash> {
+pub fn accept_genesis(db: &dyn KvStore, genesis: Block) -> Result<BlockHash> {
     let encoded = bincode::serialize(&genesis)?;
     let key = Key::new(genesis.height, genesis.hash()?);
-    let _ = db.insert(key.as_bytes(), encoded.clone())?;
+    let _ = db.insert(key.as_bytes(), encoded)?;
     let h = genesis.hash()?;
     Ok(h)
 }
 
 /// Accepts a next block, ensuring that the previous block height = `height - 1`.
-pub fn accept_next_block(db: sled::Db, block: Block) -> Result<BlockHash> {
+pub fn accept_next_block(db: &dyn KvStore, block: Block) -> Result<BlockHash> {
     match db.last() {
         Ok(Some((k, _v))) => {
             let key: Key = Key::read_from(k.as_bytes()).ok_or(Error::InvalidLast)?;
@@ -78,60 +81,54 @@ pub fn accept_next_block(db: sled::Db, block: Block) -> Result<BlockHash> {
             let encoded = bincode::serialize(&block)?;
             let hash = block.hash()?;
             let key = Key::new(block.height, hash.clone());
-            let _ = db.insert(key.as_bytes(), encoded.clone())?;
+            let _ = db.insert(key.as_bytes(), encoded)?;
             Ok(hash)
         }
         Ok(None) => Err(Error::UndefinedGenesis),
-        Err(err) => Err(Error::Sled(err)),
+        Err(err) => Err(err),
     }
 }
 
 /// Checks whether the block hash at a given height is exists.
-pub fn is_known_block(db: &sled::Db, h: BlockHeight, block_hash: BlockHash) -> Result<bool> {
+pub fn is_known_block(db: &dyn KvStore, h: BlockHeight, block_hash: BlockHash) -> Result<bool> {
     let key = Key::new(h, block_hash);
-    match db.contains_key(key.as_bytes()) {
-        Ok(r) => Ok(r),
-        Err(err) => Err(Error::Sled(err)),
-    }
+    db.contains_key(key.as_bytes())
 }
 
 /// Inserts a new block into the database.
-pub fn insert_block(db: &sled::Db, block: Block) -> Result<Option<sled::IVec>> {
+pub fn insert_block(db: &dyn KvStore, block: Block) -> Result<Option<Vec<u8>>> {
     let encoded = bincode::serialize(&block)?;
     let key = Key::new(block.height, block.hash()?);
-    match db.insert(key.as_bytes(), encoded) {
-        Ok(v) => Ok(v),
-        Err(err) => Err(Error::Sled(err)),
-    }
+    db.insert(key.as_bytes(), encoded)
 }
 
 /// Gets the last stored blocks hash.
-pub fn get_last_accepted_hash(db: &sled::Db) -> Result<BlockHash> {
+pub fn get_last_accepted_hash(db: &dyn KvStore) -> Result<BlockHash> {
     match db.last() {
         Ok(Some((k, _))) => {
             let key: Key = Key::read_from(k.as_bytes()).unwrap();
             Ok(key.hash.clone())
         }
         Ok(None) => Err(Error::InvalidLast),
-        Err(err) => Err(Error::Sled(err)),
+        Err(err) => Err(err),
     }
 }
 
 /// Gets the last accepted block and its hash.
-pub fn get_last_accepted(db: &sled::Db) -> Result<(BlockHash, Block)> {
+pub fn get_last_accepted(db: &dyn KvStore) -> Result<(BlockHash, Block)> {
     match db.last() {
         Ok(Some((k, v))) => {
             let key: Key = Key::read_from(k.as_bytes()).unwrap();
             Ok((key.hash.clone(), bincode::deserialize(v.as_bytes())?))
         }
         Ok(None) => Err(Error::InvalidLast),
-        Err(err) => Err(Error::Sled(err)),
+        Err(err) => Err(err),
     }
 }
 
 /// Gets all blocks within a specific range of heights / hashes.
 pub fn get_blocks_in_range(
-    db: sled::Db,
+    db: &dyn KvStore,
     start_height: u64,
     start_hash: BlockHash,
     end_height: u64,
@@ -140,22 +137,103 @@ pub fn get_blocks_in_range(
     let mut blocks = vec![];
     let start = Key::new(start_height, start_hash);
     let end = Key::new(end_height, end_hash);
-    for kv in db.range(start.as_bytes()..end.as_bytes()).rev() {
-        match kv {
-            Ok((_k, v)) => {
-                let block = bincode::deserialize(v.as_bytes())?;
-                blocks.push(block);
-            }
-            Err(err) => return Err(Error::Sled(err)),
+    for kv in db.iter().rev() {
+        let (k, v) = kv?;
+        if k.as_slice() >= start.as_bytes() && k.as_slice() < end.as_bytes() {
+            let block = bincode::deserialize(v.as_bytes())?;
+            blocks.push(block);
         }
     }
     Ok(blocks)
 }
 
+/// Removes the blocks below `height` and their cells from `cells`, returning the number of
+/// removed blocks.
+///
+/// The genesis block and the last accepted block are always kept, as well as the cells in
+/// `live_cells` (cells with unspent outputs).
+pub fn prune_below(
+    db: &dyn KvStore,
+    cells: &dyn KvStore,
+    height: BlockHeight,
+    live_cells: &HashSet<CellHash>,
+) -> Result<u64> {
+    let last_key = match db.last()? {
+        Some((k, _)) => k,
+        None => return Ok(0),
+    };
+    let mut pruned = 0;
+    for kv in db.iter() {
+        let (k, v) = kv?;
+        let key: Key = Key::read_from(k.as_bytes()).ok_or(Error::InvalidHeight)?;
+        let block_height = u64::from(key.height);
+        if block_height >= height {
+            break;
+        }
+        if block_height == 0 || k == last_key {
+            continue;
+        }
+        let block: Block = bincode::deserialize(v.as_bytes())?;
+        for cell in block.cells.iter() {
+            let cell_hash = cell.hash();
+            if !live_cells.contains(&cell_hash) {
+                let _ = cell::remove_cell(cells, cell_hash)?;
+            }
+        }
+        let _ = db.remove(&k)?;
+        pruned += 1;
+    }
+    Ok(pruned)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::alpha::block::{build_genesis, Block};
+    use crate::alpha::coinbase::CoinbaseOperation;
+    use crate::cell::Cell;
+    use crate::storage::InMemoryStore;
+
+    use std::convert::TryInto;
+
+    #[actix_rt::test]
+    async fn test_prune_below() {
+        let db = InMemoryStore::new();
+        let cells = InMemoryStore::new();
+
+        // Write 100 blocks with a cell each
+        let genesis = build_genesis().unwrap();
+        let mut hashes = vec![accept_genesis(&db, genesis.clone()).unwrap()];
+        let mut block_cells = vec![];
+        for h in 1..100u64 {
+            let cell: Cell =
+                CoinbaseOperation::new(vec![([h as u8; 32], 1000)]).try_into().unwrap();
+            let _ = cell::insert_cell(&cells, cell.clone()).unwrap();
+            let block = Block::new(hashes[h as usize - 1], h, [0u8; 32], vec![cell.clone()]);
+            hashes.push(block.hash().unwrap());
+            let _ = insert_block(&db, block).unwrap();
+            block_cells.push(cell.hash());
+        }
+        // The outputs of the cells of blocks 10 and 20 are unspent
+        let live_cells: HashSet<CellHash> =
+            vec![block_cells[9], block_cells[19]].into_iter().collect();
+
+        assert_eq!(prune_below(&db, &cells, 50, &live_cells), Ok(49));
+
+        assert_eq!(get_genesis(&db), Ok((hashes[0], genesis)));
+        for h in 1..50u64 {
+            assert_eq!(is_known_block(&db, h, hashes[h as usize]), Ok(false));
+        }
+        for h in 50..100u64 {
+            assert_eq!(is_known_block(&db, h, hashes[h as usize]), Ok(true));
+        }
+        assert_eq!(get_last_accepted_hash(&db), Ok(hashes[99]));
+
+        for (i, cell_hash) in block_cells.iter().enumerate() {
+            let retained = i + 1 >= 50 || live_cells.contains(cell_hash);
+            assert_eq!(cell::is_known_cell(&cells, *cell_hash), Ok(retained));
+        }
+    }
 
     #[actix_rt::test]
     async fn test_block_height_prefix() {
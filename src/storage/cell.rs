@@ -1,4 +1,4 @@
-use super::{Error, Result};
+use super::{Error, KvStore, Result};
 
 use crate::cell::types::CellHash;
 use crate::cell::Cell;
@@ -18,27 +18,27 @@ impl Key {
 }
 
 /// Whether this cell exists in storage.
-pub fn is_known_cell(db: &sled::Db, cell_hash: CellHash) -> Result<bool> {
+pub fn is_known_cell(db: &dyn KvStore, cell_hash: CellHash) -> Result<bool> {
     let key = Key::new(cell_hash);
-    match db.contains_key(key.as_bytes()) {
-        Ok(r) => Ok(r),
-        Err(err) => Err(Error::Sled(err)),
-    }
+    db.contains_key(key.as_bytes())
 }
 
 /// Inserts a new cell into storage.
-pub fn insert_cell(db: &sled::Db, cell: Cell) -> Result<Option<sled::IVec>> {
+pub fn insert_cell(db: &dyn KvStore, cell: Cell) -> Result<Option<Vec<u8>>> {
     let h = cell.hash();
     let encoded = bincode::serialize(&cell)?;
     let key = Key::new(h);
-    match db.insert(key.as_bytes(), encoded) {
-        Ok(v) => Ok(v),
-        Err(err) => Err(Error::Sled(err)),
-    }
+    db.insert(key.as_bytes(), encoded)
+}
+
+/// Removes a cell from storage.
+pub fn remove_cell(db: &dyn KvStore, cell_hash: CellHash) -> Result<Option<Vec<u8>>> {
+    let key = Key::new(cell_hash);
+    db.remove(key.as_bytes())
 }
 
 /// Fetches the genesis block (the first block in the database).
-pub fn get_cell(db: &sled::Db, cell_hash: CellHash) -> Result<(CellHash, Cell)> {
+pub fn get_cell(db: &dyn KvStore, cell_hash: CellHash) -> Result<(CellHash, Cell)> {
     let key = Key::new(cell_hash);
     match db.get(key.as_bytes()) {
         Ok(Some(v)) => {
@@ -46,6 +46,6 @@ pub fn get_cell(db: &sled::Db, cell_hash: CellHash) -> Result<(CellHash, Cell)>
             Ok((key.hash.clone(), cell))
         }
         Ok(None) => Err(Error::InvalidCell),
-        Err(err) => Err(Error::Sled(err)),
+        Err(err) => Err(err),
     }
 }
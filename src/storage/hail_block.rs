@@ -1,8 +1,11 @@
-use super::{Error, Result};
+use super::{Error, KvStore, Result};
 
-use crate::alpha::types::BlockHash;
+use crate::alpha::types::{BlockHash, BlockHeight};
 use crate::hail::block::HailBlock;
 
+use std::collections::HashSet;
+use std::convert::TryInto;
+
 use zerocopy::{AsBytes, FromBytes, Unaligned};
 
 #[derive(Clone, FromBytes, AsBytes, Unaligned)]
@@ -18,27 +21,21 @@ impl Key {
 }
 
 /// Whether this block exists in storage.
-pub fn is_known_block(db: &sled::Db, block_hash: BlockHash) -> Result<bool> {
+pub fn is_known_block(db: &dyn KvStore, block_hash: BlockHash) -> Result<bool> {
     let key = Key::new(block_hash);
-    match db.contains_key(key.as_bytes()) {
-        Ok(r) => Ok(r),
-        Err(err) => Err(Error::Sled(err)),
-    }
+    db.contains_key(key.as_bytes())
 }
 
 /// Inserts a new block into storage.
-pub fn insert_block(db: &sled::Db, block: HailBlock) -> Result<Option<sled::IVec>> {
+pub fn insert_block(db: &dyn KvStore, block: HailBlock) -> Result<Option<Vec<u8>>> {
     let h = block.hash()?;
     let encoded = bincode::serialize(&block)?;
     let key = Key::new(h);
-    match db.insert(key.as_bytes(), encoded) {
-        Ok(v) => Ok(v),
-        Err(err) => Err(Error::Sled(err)),
-    }
+    db.insert(key.as_bytes(), encoded)
 }
 
 /// Fetches a hail block.
-pub fn get_block(db: &sled::Db, block_hash: BlockHash) -> Result<(BlockHash, HailBlock)> {
+pub fn get_block(db: &dyn KvStore, block_hash: BlockHash) -> Result<(BlockHash, HailBlock)> {
     let key = Key::new(block_hash);
     match db.get(key.as_bytes()) {
         Ok(Some(v)) => {
@@ -46,6 +43,76 @@ pub fn get_block(db: &sled::Db, block_hash: BlockHash) -> Result<(BlockHash, Hai
             Ok((key.hash.clone(), block))
         }
         Ok(None) => Err(Error::InvalidHailBlock),
-        Err(err) => Err(Error::Sled(err)),
+        Err(err) => Err(err),
+    }
+}
+
+/// Inserts a block of the chain up to a checkpoint, by its height. The height is also stored
+/// under the hash of the block, see [get_finalized_block_by_hash].
+pub fn insert_finalized_block(db: &dyn KvStore, block: HailBlock) -> Result<Option<Vec<u8>>> {
+    let encoded = bincode::serialize(&block)?;
+    let height = block.height().to_be_bytes();
+    let _ = db.insert(&block.hash()?, height.to_vec())?;
+    db.insert(&height, encoded)
+}
+
+/// Fetches the block of the chain up to a checkpoint at `height`.
+pub fn get_finalized_block(db: &dyn KvStore, height: BlockHeight) -> Result<HailBlock> {
+    match db.get(&height.to_be_bytes()) {
+        Ok(Some(v)) => Ok(bincode::deserialize(v.as_bytes())?),
+        Ok(None) => Err(Error::InvalidHailBlock),
+        Err(err) => Err(err),
+    }
+}
+
+/// Fetches the block of the chain up to a checkpoint with `block_hash`.
+pub fn get_finalized_block_by_hash(db: &dyn KvStore, block_hash: BlockHash) -> Result<HailBlock> {
+    match db.get(&block_hash)? {
+        Some(v) => {
+            let bytes: [u8; 8] = v.as_slice().try_into().map_err(|_| Error::InvalidHailBlock)?;
+            get_finalized_block(db, BlockHeight::from_be_bytes(bytes))
+        }
+        None => Err(Error::InvalidHailBlock),
+    }
+}
+
+/// The key of the height of the checkpoint, stored along the finalized blocks
+const CHECKPOINT_KEY: &[u8] = b"checkpoint";
+
+/// Records the height of the checkpoint, up to which the finalized blocks are stored.
+pub fn set_checkpoint_height(db: &dyn KvStore, height: BlockHeight) -> Result<()> {
+    let _ = db.insert(CHECKPOINT_KEY, height.to_be_bytes().to_vec())?;
+    Ok(())
+}
+
+/// Fetches the height of the checkpoint, if one was recorded.
+pub fn get_checkpoint_height(db: &dyn KvStore) -> Result<Option<BlockHeight>> {
+    match db.get(CHECKPOINT_KEY)? {
+        Some(v) => {
+            let bytes: [u8; 8] = v.as_slice().try_into().map_err(|_| Error::InvalidHailBlock)?;
+            Ok(Some(BlockHeight::from_be_bytes(bytes)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Removes the blocks below `height`, except the ones in `frontier`.
+/// Returns the number of removed blocks.
+pub fn prune_below(
+    db: &dyn KvStore,
+    height: BlockHeight,
+    frontier: &HashSet<BlockHash>,
+) -> Result<u64> {
+    let mut pruned = vec![];
+    for kv in db.iter() {
+        let (k, v) = kv?;
+        let block: HailBlock = bincode::deserialize(v.as_bytes())?;
+        if block.height() < height && !frontier.contains(&block.hash()?) {
+            pruned.push(k);
+        }
+    }
+    for k in pruned.iter() {
+        let _ = db.remove(k)?;
     }
+    Ok(pruned.len() as u64)
 }
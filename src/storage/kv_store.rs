@@ -0,0 +1,467 @@
+use super::{Error, Result};
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// A key-value pair as stored in a [KvStore]
+pub type KvPair = (Vec<u8>, Vec<u8>);
+
+/// A key-value store backend for the storage routines.
+///
+/// Keys are ordered lexicographically, which the block storage relies on for ordering blocks
+/// by height.
+pub trait KvStore: std::fmt::Debug + Send + Sync {
+    /// Fetches the value of `key`.
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Inserts `value` under `key`, returning the previous value.
+    fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>>;
+
+    /// Removes `key`, returning its value.
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Atomically replaces the value of `key` with the result of `f`, removing it on `None`.
+    /// Returns the new value.
+    ///
+    /// `f` may be called more than once if the value is changed concurrently.
+    fn update_and_fetch(
+        &self,
+        key: &[u8],
+        f: &mut dyn FnMut(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>>;
+
+    /// Iterates over all key-value pairs in ascending order of the keys.
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = Result<KvPair>> + '_>;
+
+    /// Persists the pending writes.
+    fn flush(&self) -> Result<()>;
+
+    /// Whether `key` exists in the store.
+    fn contains_key(&self, key: &[u8]) -> Result<bool> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// Returns the key-value pair with the lowest key.
+    fn first(&self) -> Result<Option<KvPair>> {
+        self.iter().next().transpose()
+    }
+
+    /// Returns the key-value pair with the highest key.
+    fn last(&self) -> Result<Option<KvPair>> {
+        self.iter().next_back().transpose()
+    }
+
+    /// Iterates over the key-value pairs from `start` on, in ascending order of the keys.
+    fn range_from(&self, start: &[u8]) -> Box<dyn Iterator<Item = Result<KvPair>> + '_> {
+        let start = start.to_vec();
+        Box::new(self.iter().filter(move |kv| kv.as_ref().map_or(true, |(k, _)| *k >= start)))
+    }
+}
+
+/// Shared stores, as held by the consensus actors
+impl<S: KvStore + ?Sized> KvStore for Arc<S> {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        (**self).get(key)
+    }
+
+    fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        (**self).insert(key, value)
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        (**self).remove(key)
+    }
+
+    fn update_and_fetch(
+        &self,
+        key: &[u8],
+        f: &mut dyn FnMut(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>> {
+        (**self).update_and_fetch(key, f)
+    }
+
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = Result<KvPair>> + '_> {
+        (**self).iter()
+    }
+
+    fn flush(&self) -> Result<()> {
+        (**self).flush()
+    }
+
+    fn contains_key(&self, key: &[u8]) -> Result<bool> {
+        (**self).contains_key(key)
+    }
+
+    fn first(&self) -> Result<Option<KvPair>> {
+        (**self).first()
+    }
+
+    fn last(&self) -> Result<Option<KvPair>> {
+        (**self).last()
+    }
+
+    fn range_from(&self, start: &[u8]) -> Box<dyn Iterator<Item = Result<KvPair>> + '_> {
+        (**self).range_from(start)
+    }
+}
+
+/// [KvStore] backed by a [`sled`](http://docs.rs/sled/) database.
+#[derive(Debug, Clone)]
+pub struct SledStore {
+    db: sled::Db,
+}
+
+impl SledStore {
+    pub fn new(db: sled::Db) -> Self {
+        SledStore { db }
+    }
+
+    /// Opens the database at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(SledStore { db: sled::open(path)? })
+    }
+
+    /// Opens a temporary database, which is removed when dropped.
+    pub fn temporary() -> Result<Self> {
+        Ok(SledStore { db: sled::Config::new().temporary(true).open()? })
+    }
+}
+
+impl KvStore for SledStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.get(key)?.map(|v| v.to_vec()))
+    }
+
+    fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.insert(key, value)?.map(|v| v.to_vec()))
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.remove(key)?.map(|v| v.to_vec()))
+    }
+
+    fn update_and_fetch(
+        &self,
+        key: &[u8],
+        f: &mut dyn FnMut(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.update_and_fetch(key, |old| f(old))?.map(|v| v.to_vec()))
+    }
+
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = Result<KvPair>> + '_> {
+        Box::new(self.db.iter().map(|kv| match kv {
+            Ok((k, v)) => Ok((k.to_vec(), v.to_vec())),
+            Err(err) => Err(Error::Sled(err)),
+        }))
+    }
+
+    fn flush(&self) -> Result<()> {
+        let _ = self.db.flush()?;
+        Ok(())
+    }
+
+    fn contains_key(&self, key: &[u8]) -> Result<bool> {
+        Ok(self.db.contains_key(key)?)
+    }
+
+    fn first(&self) -> Result<Option<KvPair>> {
+        Ok(self.db.first()?.map(|(k, v)| (k.to_vec(), v.to_vec())))
+    }
+
+    fn last(&self) -> Result<Option<KvPair>> {
+        Ok(self.db.last()?.map(|(k, v)| (k.to_vec(), v.to_vec())))
+    }
+
+    fn range_from(&self, start: &[u8]) -> Box<dyn Iterator<Item = Result<KvPair>> + '_> {
+        Box::new(self.db.range(start..).map(|kv| match kv {
+            Ok((k, v)) => Ok((k.to_vec(), v.to_vec())),
+            Err(err) => Err(Error::Sled(err)),
+        }))
+    }
+}
+
+/// When the writes to a [DurableStore] are persisted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DurabilityConfig {
+    /// Rely on the background flushing of the backend, e.g. every 500ms for sled
+    Backend,
+    /// Flush after every write
+    FlushEveryWrite,
+    /// Flush after every `n` writes
+    FlushEveryN(u64),
+    /// Flush on a write if the last flush is older than the interval
+    FlushInterval(Duration),
+}
+
+impl Default for DurabilityConfig {
+    fn default() -> Self {
+        DurabilityConfig::Backend
+    }
+}
+
+/// [KvStore] flushing the writes to the wrapped store according to a [DurabilityConfig],
+/// so that they survive a crash.
+#[derive(Debug)]
+pub struct DurableStore {
+    inner: Arc<dyn KvStore>,
+    durability: DurabilityConfig,
+    /// The number of writes since the last flush
+    writes: AtomicU64,
+    last_flush: Mutex<Instant>,
+}
+
+impl DurableStore {
+    pub fn new(inner: Arc<dyn KvStore>, durability: DurabilityConfig) -> Self {
+        DurableStore {
+            inner,
+            durability,
+            writes: AtomicU64::new(0),
+            last_flush: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Flushes the inner store if the durability policy requires it after a write
+    fn after_write(&self) -> Result<()> {
+        let writes = self.writes.fetch_add(1, Ordering::SeqCst) + 1;
+        let flush = match self.durability {
+            DurabilityConfig::Backend => false,
+            DurabilityConfig::FlushEveryWrite => true,
+            DurabilityConfig::FlushEveryN(n) => writes >= n,
+            DurabilityConfig::FlushInterval(interval) => {
+                self.last_flush.lock().unwrap().elapsed() >= interval
+            }
+        };
+        if flush {
+            self.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl KvStore for DurableStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key)
+    }
+
+    fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let previous = self.inner.insert(key, value)?;
+        self.after_write()?;
+        Ok(previous)
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let previous = self.inner.remove(key)?;
+        self.after_write()?;
+        Ok(previous)
+    }
+
+    fn update_and_fetch(
+        &self,
+        key: &[u8],
+        f: &mut dyn FnMut(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>> {
+        let updated = self.inner.update_and_fetch(key, f)?;
+        self.after_write()?;
+        Ok(updated)
+    }
+
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = Result<KvPair>> + '_> {
+        self.inner.iter()
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.inner.flush()?;
+        self.writes.store(0, Ordering::SeqCst);
+        *self.last_flush.lock().unwrap() = Instant::now();
+        Ok(())
+    }
+
+    fn contains_key(&self, key: &[u8]) -> Result<bool> {
+        self.inner.contains_key(key)
+    }
+
+    fn first(&self) -> Result<Option<KvPair>> {
+        self.inner.first()
+    }
+
+    fn last(&self) -> Result<Option<KvPair>> {
+        self.inner.last()
+    }
+
+    fn range_from(&self, start: &[u8]) -> Box<dyn Iterator<Item = Result<KvPair>> + '_> {
+        self.inner.range_from(start)
+    }
+}
+
+/// Non-persistent [KvStore], mainly for tests.
+#[derive(Debug, Default)]
+pub struct InMemoryStore {
+    map: RwLock<BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        InMemoryStore::default()
+    }
+}
+
+impl KvStore for InMemoryStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.map.read().unwrap().get(key).cloned())
+    }
+
+    fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        Ok(self.map.write().unwrap().insert(key.to_vec(), value))
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.map.write().unwrap().remove(key))
+    }
+
+    fn update_and_fetch(
+        &self,
+        key: &[u8],
+        f: &mut dyn FnMut(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>> {
+        let mut map = self.map.write().unwrap();
+        match f(map.get(key).map(|v| v.as_slice())) {
+            Some(value) => {
+                let _ = map.insert(key.to_vec(), value.clone());
+                Ok(Some(value))
+            }
+            None => {
+                let _ = map.remove(key);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Iterates over a snapshot of the store
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = Result<KvPair>> + '_> {
+        let pairs: Vec<KvPair> =
+            self.map.read().unwrap().iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        Box::new(pairs.into_iter().map(Ok))
+    }
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Iterates over a snapshot of the range
+    fn range_from(&self, start: &[u8]) -> Box<dyn Iterator<Item = Result<KvPair>> + '_> {
+        let map = self.map.read().unwrap();
+        let pairs: Vec<KvPair> =
+            map.range(start.to_vec()..).map(|(k, v)| (k.clone(), v.clone())).collect();
+        Box::new(pairs.into_iter().map(Ok))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::alpha::coinbase::CoinbaseOperation;
+    use crate::cell::Cell;
+    use crate::storage::{cell, TestStore};
+    use crate::zfx_id::Id;
+
+    use std::convert::TryInto;
+    use std::path::PathBuf;
+
+    /// Copies the files of the database at `from` as they are on disk, like after a crash
+    fn copy_dir(from: &Path, to: &Path) {
+        std::fs::create_dir_all(to).unwrap();
+        for entry in std::fs::read_dir(from).unwrap() {
+            let entry = entry.unwrap();
+            if entry.file_type().unwrap().is_dir() {
+                copy_dir(&entry.path(), &to.join(entry.file_name()));
+            } else {
+                let _ = std::fs::copy(entry.path(), to.join(entry.file_name())).unwrap();
+            }
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}", name, Id::generate()))
+    }
+
+    #[actix_rt::test]
+    async fn test_flush_every_write_survives_crash() {
+        let path = temp_path("durable");
+        let crashed = temp_path("durable-crashed");
+        // Don't let the background flush persist the write
+        let db = sled::Config::new().path(&path).flush_every_ms(None).open().unwrap();
+        let store =
+            DurableStore::new(Arc::new(SledStore::new(db)), DurabilityConfig::FlushEveryWrite);
+        let cell: Cell = CoinbaseOperation::new(vec![([1u8; 32], 1000)]).try_into().unwrap();
+        let _ = cell::insert_cell(&store, cell.clone()).unwrap();
+
+        // Reopen the files as they were when the node crashed, without stopping
+        copy_dir(&path, &crashed);
+        let reopened = SledStore::open(&crashed).unwrap();
+        assert_eq!(cell::get_cell(&reopened, cell.hash()).unwrap(), (cell.hash(), cell));
+
+        drop(store);
+        let _ = std::fs::remove_dir_all(path);
+        let _ = std::fs::remove_dir_all(crashed);
+    }
+
+    #[actix_rt::test]
+    async fn test_flush_every_n_writes() {
+        let inner = Arc::new(TestStore::new());
+        let durable = DurableStore::new(inner.clone(), DurabilityConfig::FlushEveryN(3));
+        for i in 0..7u8 {
+            let _ = durable.insert(&[i], vec![i]).unwrap();
+        }
+        assert_eq!(inner.flushes(), 2);
+
+        let durable = DurableStore::new(inner.clone(), DurabilityConfig::Backend);
+        let _ = durable.insert(&[0], vec![]).unwrap();
+        assert_eq!(inner.flushes(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_range_from() {
+        let stores: Vec<Box<dyn KvStore>> =
+            vec![Box::new(InMemoryStore::new()), Box::new(SledStore::temporary().unwrap())];
+        for store in stores {
+            for key in [vec![3u8], vec![1], vec![2, 0], vec![2]] {
+                let _ = store.insert(&key, vec![]).unwrap();
+            }
+            let keys: Vec<Vec<u8>> = store.range_from(&[2]).map(|kv| kv.unwrap().0).collect();
+            assert_eq!(keys, vec![vec![2], vec![2, 0], vec![3]]);
+            assert_eq!(store.range_from(&[4]).count(), 0);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_concurrent_update_and_fetch() {
+        let stores: Vec<Arc<dyn KvStore>> =
+            vec![Arc::new(InMemoryStore::new()), Arc::new(SledStore::temporary().unwrap())];
+        for store in stores {
+            let threads: Vec<_> = (0..4)
+                .map(|_| {
+                    let store = store.clone();
+                    std::thread::spawn(move || {
+                        for _ in 0..100 {
+                            let _ = store
+                                .update_and_fetch(&[0], &mut |old| {
+                                    let n = old
+                                        .map_or(0, |v| u32::from_be_bytes(v.try_into().unwrap()));
+                                    Some((n + 1).to_be_bytes().to_vec())
+                                })
+                                .unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            assert_eq!(store.get(&[0]).unwrap(), Some(400u32.to_be_bytes().to_vec()));
+        }
+    }
+}
@@ -1,5 +1,8 @@
-//! Database storage layer using [`sled`](http://docs.rs/sled/) as backend
+//! Database storage layer over a pluggable [KvStore] backend
+//!
+//! [SledStore] uses [`sled`](http://docs.rs/sled/) as backend, [InMemoryStore] keeps the data in memory.
 use crate::alpha;
+use crate::alpha::types::BlockHeight;
 use crate::cell as inner_cell;
 use crate::hail;
 
@@ -9,13 +12,58 @@ pub mod block;
 pub mod cell;
 /// Code for [Hail][crate::hail] storage
 pub mod hail_block;
+/// Key-value store backends
+pub mod kv_store;
+/// Storage of known peers, remembered across restarts
+pub mod peer;
+/// Export and import of snapshots of the accepted state
+pub mod snapshot;
+/// A [KvStore] double for tests
+#[cfg(test)]
+pub mod test_store;
 /// Storage routines for [Sleet][crate::sleet] transactions
 pub mod tx;
 
+pub use kv_store::{DurabilityConfig, DurableStore, InMemoryStore, KvStore, SledStore};
+pub use snapshot::{
+    export_snapshot, export_snapshot_file, import_snapshot, import_snapshot_file,
+    SnapshotValidators,
+};
+#[cfg(test)]
+pub use test_store::TestStore;
+
+/// Configuration of the storage layer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StorageConfig {
+    /// The number of most recent blocks which are kept when pruning
+    pub retention_blocks: u64,
+    /// When the writes of accepted blocks and cells are persisted
+    pub durability: DurabilityConfig,
+}
+
+impl StorageConfig {
+    /// Returns the height below which blocks can be pruned, given the last accepted height
+    pub fn retention_height(&self, last_height: BlockHeight) -> BlockHeight {
+        last_height.saturating_sub(self.retention_blocks)
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig { retention_blocks: 10_000, durability: DurabilityConfig::default() }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum Error {
     Bincode(String),
     Sled(sled::Error),
+    /// Error of a custom [KvStore] backend
+    Backend(String),
+    /// I/O error while reading or writing a snapshot
+    IO(String),
+    /// A frame of a snapshot announces more than [snapshot::MAX_FRAME_BYTES]
+    OversizedFrame(u64),
     Cell(inner_cell::Error),
     Alpha(alpha::Error),
     Hail(hail::Error),
@@ -27,6 +75,10 @@ pub enum Error {
     InvalidCell,
     InvalidTx,
     InvalidHailBlock,
+    /// The entries of a snapshot don't match its Merkle root
+    InvalidMerkleRoot,
+    /// The local chain is already at the contained height, which the snapshot doesn't exceed
+    StaleSnapshot(crate::alpha::types::BlockHeight),
 }
 
 impl std::convert::From<Box<bincode::ErrorKind>> for Error {
@@ -0,0 +1,81 @@
+use super::{KvStore, Result};
+
+use crate::zfx_id::Id;
+
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Peers which weren't seen for longer than this are dropped by default
+pub const DEFAULT_PEER_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// A known-good peer, remembered across restarts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerRecord {
+    pub id: Id,
+    pub ip: SocketAddr,
+    /// The last time the peer was seen, in seconds since the Unix epoch
+    pub last_seen: u64,
+    /// The score of the peer, see [ReportPeerMisbehavior][crate::view::ReportPeerMisbehavior]
+    pub score: i64,
+}
+
+impl PeerRecord {
+    /// A record of the peer `id` at `ip`, seen now
+    pub fn new(id: Id, ip: SocketAddr, score: i64) -> Self {
+        PeerRecord { id, ip, last_seen: now(), score }
+    }
+}
+
+/// Seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Inserts or updates a peer in storage.
+pub fn insert_peer(db: &dyn KvStore, record: &PeerRecord) -> Result<Option<Vec<u8>>> {
+    let encoded = bincode::serialize(record)?;
+    db.insert(record.id.as_bytes(), encoded)
+}
+
+/// Fetches the peers seen within `max_age`, removing older peers from storage.
+pub fn get_peers(db: &dyn KvStore, max_age: Duration) -> Result<Vec<PeerRecord>> {
+    let oldest = now().saturating_sub(max_age.as_secs());
+    let mut peers = vec![];
+    let mut stale = vec![];
+    for kv in db.iter() {
+        let (k, v) = kv?;
+        let record: PeerRecord = bincode::deserialize(&v)?;
+        if record.last_seen < oldest {
+            stale.push(k);
+        } else {
+            peers.push(record);
+        }
+    }
+    for k in stale.iter() {
+        let _ = db.remove(k)?;
+    }
+    Ok(peers)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::storage::InMemoryStore;
+
+    #[actix_rt::test]
+    async fn test_drop_stale_peers() {
+        let db = InMemoryStore::new();
+        let ip: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        let fresh = PeerRecord::new(Id::from_ip(&ip), ip, 0);
+        let ip: SocketAddr = "127.0.0.1:1235".parse().unwrap();
+        let stale =
+            PeerRecord { last_seen: now() - 120, ..PeerRecord::new(Id::from_ip(&ip), ip, -5) };
+        let _ = insert_peer(&db, &fresh).unwrap();
+        let _ = insert_peer(&db, &stale).unwrap();
+
+        let peers = get_peers(&db, Duration::from_secs(60)).unwrap();
+        assert_eq!(peers, vec![fresh]);
+        assert!(!db.contains_key(stale.id.as_bytes()).unwrap());
+    }
+}
@@ -0,0 +1,424 @@
+use super::kv_store::KvPair;
+use super::{block, Error, KvStore, Result};
+
+use crate::alpha::block::Block;
+use crate::alpha::types::{BlockHash, BlockHeight};
+use crate::cell::types::Capacity;
+use crate::cell::Cell;
+use crate::zfx_id::Id;
+
+use zerocopy::AsBytes;
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// The maximum size of a frame of a snapshot, so that a corrupt length prefix can't make the
+/// import allocate arbitrary amounts of memory
+pub const MAX_FRAME_BYTES: u64 = 64 * 1024 * 1024;
+
+/// The validators of the chain at the height of a snapshot, which can't be derived from its
+/// live cells
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotValidators {
+    /// The validators with their staking capacity, less the slashing penalties
+    pub validators: Vec<(Id, Capacity)>,
+}
+
+/// The header of a snapshot, followed by the entries of the genesis block and the last accepted
+/// block, and the `cells` entries of the cell store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SnapshotHeader {
+    genesis: BlockHash,
+    last_accepted: BlockHash,
+    height: BlockHeight,
+    blocks: u64,
+    cells: u64,
+    validators: SnapshotValidators,
+    /// The [merkle_root] of the header fields above and the entries
+    merkle_root: [u8; 32],
+}
+
+/// Writes a length-prefixed bincode frame
+fn write_frame<W: Write, T: serde::Serialize>(writer: &mut W, value: &T) -> Result<()> {
+    let encoded = bincode::serialize(value)?;
+    writer.write_all(&(encoded.len() as u64).to_be_bytes()).map_err(io_error)?;
+    writer.write_all(&encoded).map_err(io_error)
+}
+
+/// Reads a length-prefixed bincode frame of at most [MAX_FRAME_BYTES]
+fn read_frame<R: Read, T: serde::de::DeserializeOwned>(reader: &mut R) -> Result<T> {
+    let mut len = [0u8; 8];
+    reader.read_exact(&mut len).map_err(io_error)?;
+    let len = u64::from_be_bytes(len);
+    if len > MAX_FRAME_BYTES {
+        return Err(Error::OversizedFrame(len));
+    }
+    // The buffer grows with the data actually read, not with the announced length
+    let mut encoded = vec![];
+    let _ = reader.take(len).read_to_end(&mut encoded).map_err(io_error)?;
+    if encoded.len() as u64 != len {
+        return Err(Error::IO("truncated snapshot frame".to_owned()));
+    }
+    Ok(bincode::deserialize(&encoded)?)
+}
+
+fn io_error(error: std::io::Error) -> Error {
+    Error::IO(format!("{:?}", error))
+}
+
+fn entries(db: &dyn KvStore) -> Result<Vec<KvPair>> {
+    db.iter().collect()
+}
+
+/// The root of the Merkle tree over the fields of `header`, other than its root, followed by the
+/// block entries and the cell entries, each leaf being the hash of an encoded key-value pair. An
+/// odd node is paired with itself.
+///
+/// As the root is stored in the snapshot it is computed from, it only detects a corrupt or
+/// truncated file: it isn't a trust anchor, a snapshot has to be obtained from a trusted source.
+fn merkle_root(header: &SnapshotHeader, blocks: &[KvPair], cells: &[KvPair]) -> Result<[u8; 32]> {
+    let header_fields = (
+        &header.genesis,
+        &header.last_accepted,
+        header.height,
+        header.blocks,
+        header.cells,
+        &header.validators,
+    );
+    let mut level = vec![*blake3::hash(&bincode::serialize(&header_fields)?).as_bytes()];
+    for entry in blocks.iter().chain(cells.iter()) {
+        level.push(*blake3::hash(&bincode::serialize(entry)?).as_bytes());
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let mut hasher = blake3::Hasher::new();
+                let _ = hasher.update(&pair[0]);
+                let _ = hasher.update(pair.get(1).unwrap_or(&pair[0]));
+                *hasher.finalize().as_bytes()
+            })
+            .collect();
+    }
+    Ok(level[0])
+}
+
+/// Writes the genesis block and the last accepted block of `blocks`, the entries of `cells`,
+/// holding the live cells, and the `validators` to `writer`, along with the Merkle root of the
+/// entries.
+///
+/// The earlier blocks aren't part of the snapshot, so that its size doesn't grow with the length
+/// of the chain.
+pub fn export_snapshot<W: Write>(
+    blocks: &dyn KvStore,
+    cells: &dyn KvStore,
+    validators: SnapshotValidators,
+    writer: &mut W,
+) -> Result<()> {
+    let (genesis, genesis_block) = block::get_genesis(blocks)?;
+    let (last_accepted, last) = block::get_last_accepted(blocks)?;
+    let mut block_entries = vec![block_entry(genesis_block)?];
+    if last_accepted != genesis {
+        block_entries.push(block_entry(last.clone())?);
+    }
+    let cell_entries = entries(cells)?;
+    let mut header = SnapshotHeader {
+        genesis,
+        last_accepted,
+        height: last.height,
+        blocks: block_entries.len() as u64,
+        cells: cell_entries.len() as u64,
+        validators,
+        merkle_root: [0u8; 32],
+    };
+    header.merkle_root = merkle_root(&header, &block_entries, &cell_entries)?;
+    write_frame(writer, &header)?;
+    for entry in block_entries.iter().chain(cell_entries.iter()) {
+        write_frame(writer, entry)?;
+    }
+    writer.flush().map_err(io_error)
+}
+
+/// Reads a snapshot written by [export_snapshot] from `reader`, replacing the contents of
+/// `blocks` and `cells`. Returns the hash of the last accepted block of the snapshot and its
+/// validators.
+///
+/// The snapshot is only imported if its genesis block hash is `genesis`, and its header and
+/// entries match its Merkle root. If the last accepted block of `blocks` is the one of the
+/// snapshot, as after a restart, `blocks` are kept and only `cells` are replaced.
+/// [Error::StaleSnapshot] is thrown if `blocks` already hold another block at the height of the
+/// snapshot or a block above it, which would otherwise be rolled back.
+pub fn import_snapshot<R: Read>(
+    blocks: &dyn KvStore,
+    cells: &dyn KvStore,
+    reader: &mut R,
+    genesis: BlockHash,
+) -> Result<(BlockHash, SnapshotValidators)> {
+    let header: SnapshotHeader = read_frame(reader)?;
+    if header.genesis != genesis {
+        return Err(Error::InvalidGenesis);
+    }
+    let up_to_date = match block::get_last_accepted(blocks) {
+        Ok((hash, last)) if last.height == header.height && hash == header.last_accepted => true,
+        Ok((_, last)) if last.height >= header.height => {
+            return Err(Error::StaleSnapshot(last.height))
+        }
+        _ => false,
+    };
+    let mut block_entries: Vec<KvPair> = vec![];
+    for _ in 0..header.blocks {
+        block_entries.push(read_frame(reader)?);
+    }
+    let mut cell_entries: Vec<KvPair> = vec![];
+    for _ in 0..header.cells {
+        cell_entries.push(read_frame(reader)?);
+    }
+    if merkle_root(&header, &block_entries, &cell_entries)? != header.merkle_root {
+        return Err(Error::InvalidMerkleRoot);
+    }
+
+    let mut snapshot_blocks: Vec<Block> = vec![];
+    for (_, v) in block_entries.iter() {
+        snapshot_blocks.push(bincode::deserialize(v)?);
+    }
+    for (_, v) in cell_entries.iter() {
+        let _: Cell = bincode::deserialize(v)?;
+    }
+    match snapshot_blocks.first() {
+        Some(first) if first.height == 0 && first.hash()? == genesis => (),
+        _ => return Err(Error::InvalidGenesis),
+    }
+    match snapshot_blocks.last() {
+        Some(last) if last.height != header.height => return Err(Error::InvalidHeight),
+        Some(last) if last.hash()? == header.last_accepted => (),
+        _ => return Err(Error::InvalidLast),
+    }
+
+    // Overwrite the stores only after the whole snapshot was read
+    if !up_to_date {
+        clear(blocks)?;
+        for (k, v) in block_entries.into_iter() {
+            let _ = blocks.insert(&k, v)?;
+        }
+        blocks.flush()?;
+    }
+    clear(cells)?;
+    for (k, v) in cell_entries.into_iter() {
+        let _ = cells.insert(&k, v)?;
+    }
+    cells.flush()?;
+    Ok((header.last_accepted, header.validators))
+}
+
+/// Returns the entry of `block` in the block store
+fn block_entry(block: Block) -> Result<KvPair> {
+    let key = block::Key::new(block.height, block.hash()?);
+    Ok((key.as_bytes().to_vec(), bincode::serialize(&block)?))
+}
+
+/// Writes a snapshot to the file at `path`, see [export_snapshot].
+///
+/// The snapshot is written to a temporary file which then replaces `path`, so that an interrupted
+/// export leaves a previous snapshot intact.
+pub fn export_snapshot_file(
+    blocks: &dyn KvStore,
+    cells: &dyn KvStore,
+    validators: SnapshotValidators,
+    path: &Path,
+) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path).map_err(io_error)?);
+    export_snapshot(blocks, cells, validators, &mut writer)?;
+    let file = writer.into_inner().map_err(|e| io_error(e.into_error()))?;
+    file.sync_all().map_err(io_error)?;
+    std::fs::rename(&tmp_path, path).map_err(io_error)
+}
+
+/// Reads the snapshot in the file at `path`, see [import_snapshot].
+pub fn import_snapshot_file(
+    blocks: &dyn KvStore,
+    cells: &dyn KvStore,
+    path: &Path,
+    genesis: BlockHash,
+) -> Result<(BlockHash, SnapshotValidators)> {
+    let mut reader = BufReader::new(File::open(path).map_err(io_error)?);
+    import_snapshot(blocks, cells, &mut reader, genesis)
+}
+
+fn clear(db: &dyn KvStore) -> Result<()> {
+    let keys = db.iter().map(|kv| kv.map(|(k, _)| k)).collect::<Result<Vec<Vec<u8>>>>()?;
+    for k in keys.iter() {
+        let _ = db.remove(k)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::alpha::block::build_genesis;
+    use crate::alpha::coinbase::CoinbaseOperation;
+    use crate::cell::types::CellHash;
+    use crate::storage::{cell, SledStore};
+    use crate::zfx_id::Id;
+
+    use std::convert::TryInto;
+
+    fn cell_hashes(cells: &dyn KvStore) -> Vec<CellHash> {
+        cells
+            .iter()
+            .map(|kv| bincode::deserialize::<Cell>(&kv.unwrap().1).unwrap().hash())
+            .collect()
+    }
+
+    /// Stores the genesis block and `n` blocks with a cell each
+    fn populate(blocks: &dyn KvStore, cells: &dyn KvStore, n: u64) -> BlockHash {
+        let genesis = build_genesis().unwrap();
+        let genesis_hash = block::accept_genesis(blocks, genesis.clone()).unwrap();
+        for c in genesis.cells.iter() {
+            let _ = cell::insert_cell(cells, c.clone()).unwrap();
+        }
+        let mut predecessor = genesis_hash;
+        for h in 1..=n {
+            let c: Cell = CoinbaseOperation::new(vec![([h as u8; 32], 1000)]).try_into().unwrap();
+            let _ = cell::insert_cell(cells, c.clone()).unwrap();
+            let b = Block::new(predecessor, h, [0u8; 32], vec![c]);
+            predecessor = b.hash().unwrap();
+            let _ = block::insert_block(blocks, b).unwrap();
+        }
+        genesis_hash
+    }
+
+    fn validators() -> SnapshotValidators {
+        SnapshotValidators { validators: vec![(Id::one(), 1000)] }
+    }
+
+    #[actix_rt::test]
+    async fn test_snapshot_round_trip() {
+        let (blocks, cells) = (SledStore::temporary().unwrap(), SledStore::temporary().unwrap());
+        let genesis_hash = populate(&blocks, &cells, 10);
+        let mut snapshot = vec![];
+        export_snapshot(&blocks, &cells, validators(), &mut snapshot).unwrap();
+
+        let (blocks2, cells2) = (SledStore::temporary().unwrap(), SledStore::temporary().unwrap());
+        let (last_accepted, snapshot_validators) =
+            import_snapshot(&blocks2, &cells2, &mut snapshot.as_slice(), genesis_hash).unwrap();
+        assert_eq!(Ok(last_accepted), block::get_last_accepted_hash(&blocks));
+        assert_eq!(snapshot_validators, validators());
+        assert_eq!(cell_hashes(&cells2), cell_hashes(&cells));
+        assert_eq!(block::get_genesis(&blocks2), block::get_genesis(&blocks));
+        assert_eq!(block::get_last_accepted(&blocks2), block::get_last_accepted(&blocks));
+        // Only the genesis and the last accepted block are kept
+        assert_eq!(blocks2.iter().count(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_stale_snapshot() {
+        let (blocks, cells) = (SledStore::temporary().unwrap(), SledStore::temporary().unwrap());
+        let genesis_hash = populate(&blocks, &cells, 2);
+        let mut snapshot = vec![];
+        export_snapshot(&blocks, &cells, validators(), &mut snapshot).unwrap();
+
+        // A node which accepted more blocks since the snapshot doesn't roll back
+        let (blocks2, cells2) = (SledStore::temporary().unwrap(), SledStore::temporary().unwrap());
+        let _ = populate(&blocks2, &cells2, 3);
+        let result = import_snapshot(&blocks2, &cells2, &mut snapshot.as_slice(), genesis_hash);
+        assert_eq!(result, Err(Error::StaleSnapshot(3)));
+        assert_eq!(blocks2.iter().count(), 4);
+
+        // A node which accepted another block at the height of the snapshot doesn't either
+        let (blocks4, cells4) = (SledStore::temporary().unwrap(), SledStore::temporary().unwrap());
+        let _ = populate(&blocks4, &cells4, 1);
+        let c: Cell = CoinbaseOperation::new(vec![([9u8; 32], 1000)]).try_into().unwrap();
+        let (predecessor, _) = block::get_last_accepted(&blocks4).unwrap();
+        let _ =
+            block::insert_block(&blocks4, Block::new(predecessor, 2, [0u8; 32], vec![c])).unwrap();
+        let result = import_snapshot(&blocks4, &cells4, &mut snapshot.as_slice(), genesis_hash);
+        assert_eq!(result, Err(Error::StaleSnapshot(2)));
+
+        // The node which exported the snapshot imports it again, keeping its blocks
+        let result = import_snapshot(&blocks, &cells, &mut snapshot.as_slice(), genesis_hash);
+        assert_eq!(result.map(|(hash, _)| hash), block::get_last_accepted_hash(&blocks));
+        assert_eq!(blocks.iter().count(), 3);
+
+        // A node behind the snapshot imports it
+        let (blocks3, cells3) = (SledStore::temporary().unwrap(), SledStore::temporary().unwrap());
+        let _ = populate(&blocks3, &cells3, 1);
+        let result = import_snapshot(&blocks3, &cells3, &mut snapshot.as_slice(), genesis_hash);
+        assert_eq!(result.map(|(hash, _)| hash), block::get_last_accepted_hash(&blocks));
+    }
+
+    #[actix_rt::test]
+    async fn test_snapshot_genesis_mismatch() {
+        let (blocks, cells) = (SledStore::temporary().unwrap(), SledStore::temporary().unwrap());
+        let _ = populate(&blocks, &cells, 2);
+        let mut snapshot = vec![];
+        export_snapshot(&blocks, &cells, validators(), &mut snapshot).unwrap();
+
+        let (blocks2, cells2) = (SledStore::temporary().unwrap(), SledStore::temporary().unwrap());
+        let genesis_hash = populate(&blocks2, &cells2, 0);
+        let result = import_snapshot(&blocks2, &cells2, &mut snapshot.as_slice(), [1u8; 32]);
+        assert_eq!(result, Err(Error::InvalidGenesis));
+        // The existing state is left untouched
+        assert_eq!(blocks2.iter().count(), 1);
+        assert_eq!(block::get_last_accepted_hash(&blocks2), Ok(genesis_hash));
+    }
+
+    #[actix_rt::test]
+    async fn test_snapshot_merkle_root() {
+        let (blocks, cells) = (SledStore::temporary().unwrap(), SledStore::temporary().unwrap());
+        let genesis_hash = populate(&blocks, &cells, 3);
+        let mut snapshot = vec![];
+        export_snapshot(&blocks, &cells, validators(), &mut snapshot).unwrap();
+
+        // Tamper with the data of the last cell
+        let last = snapshot.len() - 1;
+        snapshot[last] ^= 1;
+        let (blocks2, cells2) = (SledStore::temporary().unwrap(), SledStore::temporary().unwrap());
+        let result = import_snapshot(&blocks2, &cells2, &mut snapshot.as_slice(), genesis_hash);
+        assert_eq!(result, Err(Error::InvalidMerkleRoot));
+        assert_eq!(blocks2.iter().count(), 0);
+        assert_eq!(cells2.iter().count(), 0);
+
+        // Tamper with the validators in the header
+        let mut snapshot = vec![];
+        let mut tampered = validators();
+        tampered.validators[0].1 = 2000;
+        export_snapshot(&blocks, &cells, validators(), &mut snapshot).unwrap();
+        let mut tampered_snapshot = vec![];
+        export_snapshot(&blocks, &cells, tampered, &mut tampered_snapshot).unwrap();
+        let header_len = 8 + u64::from_be_bytes(snapshot[..8].try_into().unwrap()) as usize;
+        snapshot.splice(..header_len - 32, tampered_snapshot[..header_len - 32].iter().cloned());
+        let result = import_snapshot(&blocks2, &cells2, &mut snapshot.as_slice(), genesis_hash);
+        assert_eq!(result, Err(Error::InvalidMerkleRoot));
+    }
+
+    #[actix_rt::test]
+    async fn test_snapshot_oversized_frame() {
+        let (blocks, cells) = (SledStore::temporary().unwrap(), SledStore::temporary().unwrap());
+        let snapshot = u64::MAX.to_be_bytes();
+        let result = import_snapshot(&blocks, &cells, &mut &snapshot[..], [0u8; 32]);
+        assert_eq!(result, Err(Error::OversizedFrame(u64::MAX)));
+
+        // A length within bounds but beyond the end of the file is truncated
+        let mut snapshot = 100u64.to_be_bytes().to_vec();
+        snapshot.extend_from_slice(&[0u8; 10]);
+        let result = import_snapshot(&blocks, &cells, &mut snapshot.as_slice(), [0u8; 32]);
+        assert!(matches!(result, Err(Error::IO(_))));
+    }
+
+    #[actix_rt::test]
+    async fn test_snapshot_file() {
+        let (blocks, cells) = (SledStore::temporary().unwrap(), SledStore::temporary().unwrap());
+        let genesis_hash = populate(&blocks, &cells, 2);
+        let path = std::env::temp_dir().join(format!("snapshot-{}", Id::generate()));
+        export_snapshot_file(&blocks, &cells, validators(), &path).unwrap();
+
+        let (blocks2, cells2) = (SledStore::temporary().unwrap(), SledStore::temporary().unwrap());
+        let imported = import_snapshot_file(&blocks2, &cells2, &path, genesis_hash);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(imported.map(|(hash, _)| hash), block::get_last_accepted_hash(&blocks));
+        assert_eq!(cell_hashes(&cells2), cell_hashes(&cells));
+    }
+}
@@ -0,0 +1,78 @@
+use super::kv_store::KvPair;
+use super::{Error, InMemoryStore, KvStore, Result};
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// An in-memory [KvStore] for tests, which records its flushes and can refuse all writes
+#[derive(Debug, Default)]
+pub struct TestStore {
+    store: InMemoryStore,
+    read_only: bool,
+    flushes: AtomicU64,
+    last_flush: Mutex<Option<Vec<KvPair>>>,
+}
+
+impl TestStore {
+    pub fn new() -> Self {
+        TestStore::default()
+    }
+
+    /// A store refusing all writes
+    pub fn read_only() -> Self {
+        TestStore { read_only: true, ..Default::default() }
+    }
+
+    /// The number of flushes so far
+    pub fn flushes(&self) -> u64 {
+        self.flushes.load(Ordering::SeqCst)
+    }
+
+    /// The content of the store when it was last flushed
+    pub fn last_flush(&self) -> Option<Vec<KvPair>> {
+        self.last_flush.lock().unwrap().clone()
+    }
+}
+
+impl KvStore for TestStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.store.get(key)
+    }
+
+    fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        if self.read_only {
+            return Err(Error::Backend("read-only".to_string()));
+        }
+        self.store.insert(key, value)
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.store.remove(key)
+    }
+
+    fn update_and_fetch(
+        &self,
+        key: &[u8],
+        f: &mut dyn FnMut(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>> {
+        if self.read_only {
+            return Err(Error::Backend("read-only".to_string()));
+        }
+        self.store.update_and_fetch(key, f)
+    }
+
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = Result<KvPair>> + '_> {
+        self.store.iter()
+    }
+
+    fn range_from(&self, start: &[u8]) -> Box<dyn Iterator<Item = Result<KvPair>> + '_> {
+        self.store.range_from(start)
+    }
+
+    fn flush(&self) -> Result<()> {
+        let pairs = self.store.iter().collect::<Result<Vec<KvPair>>>()?;
+        *self.last_flush.lock().unwrap() = Some(pairs);
+        let _ = self.flushes.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
@@ -1,10 +1,26 @@
-use super::{Error, Result};
+use super::{Error, KvStore, Result};
 
 use crate::alpha::types::TxHash;
 use crate::sleet::tx::{Tx, TxStatus};
 
 use zerocopy::{AsBytes, FromBytes, Unaligned};
 
+use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A status of a transaction and the time it was set, in milliseconds since the Unix epoch
+pub type StatusTransition = (TxStatus, u64);
+
+/// Prefix of the keys of the status histories, which sets them apart from the keys of the
+/// transactions
+const HISTORY_PREFIX: &[u8] = b"history:";
+
+/// The maximum number of status transitions kept per transaction, the oldest are dropped first
+pub const MAX_STATUS_HISTORY: usize = 16;
+
+/// Prefix of the keys of the index of the accepted transactions, ordered by hash
+const ACCEPTED_PREFIX: &[u8] = b"accepted:";
+
 #[derive(Clone, FromBytes, AsBytes, Unaligned)]
 #[repr(C)]
 pub struct Key {
@@ -18,27 +34,23 @@ impl Key {
 }
 
 /// Whether this tx exists in storage.
-pub fn is_known_tx(db: &sled::Db, tx_hash: TxHash) -> Result<bool> {
+pub fn is_known_tx(db: &dyn KvStore, tx_hash: TxHash) -> Result<bool> {
     let key = Key::new(tx_hash);
-    match db.contains_key(key.as_bytes()) {
-        Ok(r) => Ok(r),
-        Err(err) => Err(Error::Sled(err)),
-    }
+    db.contains_key(key.as_bytes())
 }
 
-/// Inserts a new tx into storage.
-pub fn insert_tx(db: &sled::Db, tx: Tx) -> Result<Option<sled::IVec>> {
+/// Inserts a new tx into storage, appending its status to its status history.
+pub fn insert_tx(db: &dyn KvStore, tx: Tx) -> Result<Option<Vec<u8>>> {
     let h = tx.hash();
     let encoded = bincode::serialize(&tx)?;
     let key = Key::new(h);
-    match db.insert(key.as_bytes(), encoded) {
-        Ok(v) => Ok(v),
-        Err(err) => Err(Error::Sled(err)),
-    }
+    let previous = db.insert(key.as_bytes(), encoded)?;
+    let () = append_status_history(db, &h, tx.status)?;
+    Ok(previous)
 }
 
 /// Fetches a transaction.
-pub fn get_tx(db: &sled::Db, tx_hash: TxHash) -> Result<(TxHash, Tx)> {
+pub fn get_tx(db: &dyn KvStore, tx_hash: TxHash) -> Result<(TxHash, Tx)> {
     let key = Key::new(tx_hash);
     match db.get(key.as_bytes()) {
         Ok(Some(v)) => {
@@ -46,12 +58,12 @@ pub fn get_tx(db: &sled::Db, tx_hash: TxHash) -> Result<(TxHash, Tx)> {
             Ok((key.hash.clone(), tx))
         }
         Ok(None) => Err(Error::InvalidTx),
-        Err(err) => Err(Error::Sled(err)),
+        Err(err) => Err(err),
     }
 }
 
 /// Checks if we have the transaction accepted in the database
-pub fn is_accepted_tx(db: &sled::Db, tx_hash: &TxHash) -> Result<bool> {
+pub fn is_accepted_tx(db: &dyn KvStore, tx_hash: &TxHash) -> Result<bool> {
     let key = Key::new(*tx_hash);
     match db.get(key.as_bytes()) {
         Ok(Some(v)) => {
@@ -59,12 +71,12 @@ pub fn is_accepted_tx(db: &sled::Db, tx_hash: &TxHash) -> Result<bool> {
             Ok(tx.status == TxStatus::Accepted)
         }
         Ok(None) => Err(Error::InvalidTx),
-        Err(err) => Err(Error::Sled(err)),
+        Err(err) => Err(err),
     }
 }
 
 /// Checks if we have the transaction marked as 'removed' in the database
-pub fn is_removed_tx(db: &sled::Db, tx_hash: &TxHash) -> Result<bool> {
+pub fn is_removed_tx(db: &dyn KvStore, tx_hash: &TxHash) -> Result<bool> {
     let key = Key::new(*tx_hash);
     match db.get(key.as_bytes()) {
         Ok(Some(v)) => {
@@ -72,12 +84,12 @@ pub fn is_removed_tx(db: &sled::Db, tx_hash: &TxHash) -> Result<bool> {
             Ok(tx.status == TxStatus::Removed)
         }
         Ok(None) => Err(Error::InvalidTx),
-        Err(err) => Err(Error::Sled(err)),
+        Err(err) => Err(err),
     }
 }
 
 /// Checks if the transaction has a chance to be accepted
-pub fn cannot_be_accepted(db: &sled::Db, tx_hash: &TxHash) -> Result<bool> {
+pub fn cannot_be_accepted(db: &dyn KvStore, tx_hash: &TxHash) -> Result<bool> {
     let key = Key::new(*tx_hash);
     match db.get(key.as_bytes()) {
         Ok(Some(v)) => {
@@ -85,42 +97,97 @@ pub fn cannot_be_accepted(db: &sled::Db, tx_hash: &TxHash) -> Result<bool> {
             Ok(tx.status == TxStatus::Removed || tx.status == TxStatus::Rejected)
         }
         Ok(None) => Err(Error::InvalidTx),
-        Err(err) => Err(Error::Sled(err)),
+        Err(err) => Err(err),
     }
 }
 
-/// Fetch and update a transaction in the DB. Returns the new value.
-pub fn update_and_fetch<F>(db: &sled::Db, tx_hash: &TxHash, mut f: F) -> Result<Tx>
+/// Fetch and update a transaction in the DB atomically. Returns the new value.
+///
+/// A change of the status is appended to the status history of the transaction, which is
+/// deleted with it.
+pub fn update_and_fetch<F>(db: &dyn KvStore, tx_hash: &TxHash, mut f: F) -> Result<Tx>
 where
     F: FnMut(Option<Tx>) -> Option<Tx>,
 {
     let key = Key::new(tx_hash.clone());
-    let updated = db.update_and_fetch(key.as_bytes(), |maybe_tx| {
-        let maybe_tx = if let Some(tx) = maybe_tx {
-            Some(bincode::deserialize(tx.as_bytes()).ok()?)
-        } else {
-            None
+    let mut error = None;
+    let updated = db.update_and_fetch(key.as_bytes(), &mut |old| {
+        error = None;
+        let maybe_tx = match old.map(bincode::deserialize).transpose() {
+            Ok(maybe_tx) => maybe_tx,
+            Err(err) => {
+                // Leave the stored value untouched
+                error = Some(err);
+                return old.map(|v| v.to_vec());
+            }
         };
-
-        let result = f(maybe_tx);
-        if let Some(ref tx) = result {
-            Some(bincode::serialize(tx).ok()?)
-        } else {
-            None
+        match f(maybe_tx).map(|tx| bincode::serialize(&tx)).transpose() {
+            Ok(updated) => updated,
+            Err(err) => {
+                error = Some(err);
+                old.map(|v| v.to_vec())
+            }
         }
-    });
+    })?;
+    if let Some(err) = error {
+        return Err(err.into());
+    }
     match updated {
-        Ok(Some(v)) => {
-            let tx: Tx = bincode::deserialize(v.as_bytes())?;
+        Some(v) => {
+            let tx: Tx = bincode::deserialize(&v)?;
+            let () = append_status_history(db, tx_hash, tx.status.clone())?;
+            if tx.status == TxStatus::Accepted {
+                let _ = db.insert(&accepted_key(tx_hash), vec![])?;
+            }
             Ok(tx)
         }
-        Ok(None) => Err(Error::InvalidTx),
-        Err(err) => Err(Error::Sled(err)),
+        None => {
+            let _ = db.remove(&accepted_key(tx_hash))?;
+            let _ = db.remove(&history_key(tx_hash))?;
+            Err(Error::InvalidTx)
+        }
     }
 }
 
+fn accepted_key(tx_hash: &TxHash) -> Vec<u8> {
+    let mut key = ACCEPTED_PREFIX.to_vec();
+    key.extend_from_slice(tx_hash);
+    key
+}
+
+/// Returns up to `limit` hashes of accepted transactions in ascending order, starting after
+/// `after` if set, and whether more follow.
+pub fn get_accepted_page(
+    db: &dyn KvStore,
+    after: Option<&TxHash>,
+    limit: usize,
+) -> Result<(Vec<TxHash>, bool)> {
+    let start = match after {
+        // The smallest key following `after`
+        Some(after) => {
+            let mut start = accepted_key(after);
+            start.push(0);
+            start
+        }
+        None => ACCEPTED_PREFIX.to_vec(),
+    };
+    let mut hashes = vec![];
+    for kv in db.range_from(&start) {
+        let (key, _) = kv?;
+        if !key.starts_with(ACCEPTED_PREFIX) {
+            break;
+        }
+        if hashes.len() == limit {
+            return Ok((hashes, true));
+        }
+        let hash: TxHash = key[ACCEPTED_PREFIX.len()..].try_into().map_err(|_| Error::InvalidTx)?;
+        hashes.push(hash);
+    }
+    Ok((hashes, false))
+}
+
 /// Set transaction status
-pub fn set_status(db: &sled::Db, tx_hash: &TxHash, status: TxStatus) -> Result<()> {
+pub fn set_status(db: &dyn KvStore, tx_hash: &TxHash, status: TxStatus) -> Result<()> {
     let result = update_and_fetch(db, tx_hash, |tx| {
         if let Some(mut tx) = tx {
             tx.status = status.clone();
@@ -134,3 +201,132 @@ pub fn set_status(db: &sled::Db, tx_hash: &TxHash, status: TxStatus) -> Result<(
         Err(error) => Err(error),
     }
 }
+
+fn history_key(tx_hash: &TxHash) -> Vec<u8> {
+    let mut key = HISTORY_PREFIX.to_vec();
+    key.extend_from_slice(tx_hash);
+    key
+}
+
+/// Appends `status` to the status history of a transaction, unless it is already its last status.
+///
+/// Only the last [MAX_STATUS_HISTORY] transitions are kept, so that a transaction issued over and
+/// over doesn't grow its history without bound.
+pub fn append_status_history(db: &dyn KvStore, tx_hash: &TxHash, status: TxStatus) -> Result<()> {
+    let key = history_key(tx_hash);
+    let mut history = get_status_history(db, tx_hash)?;
+    if history.last().map(|(last, _)| *last == status).unwrap_or(false) {
+        return Ok(());
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+    history.push((status, now as u64));
+    if history.len() > MAX_STATUS_HISTORY {
+        let _ = history.drain(..history.len() - MAX_STATUS_HISTORY);
+    }
+    let _ = db.insert(&key, bincode::serialize(&history)?)?;
+    Ok(())
+}
+
+/// Fetches the status transitions of a transaction, oldest first. Unknown transactions have an
+/// empty history.
+pub fn get_status_history(db: &dyn KvStore, tx_hash: &TxHash) -> Result<Vec<StatusTransition>> {
+    match db.get(&history_key(tx_hash))? {
+        Some(v) => Ok(bincode::deserialize(&v)?),
+        None => Ok(vec![]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alpha::coinbase::CoinbaseOperation;
+    use crate::storage::{InMemoryStore, SledStore};
+
+    use std::convert::TryInto;
+
+    fn test_tx() -> Tx {
+        let cell = CoinbaseOperation::new(vec![([1u8; 32], 1000)]).try_into().unwrap();
+        Tx::new(vec![], cell)
+    }
+
+    fn tx_round_trip(db: &dyn KvStore) {
+        let tx = test_tx();
+        assert_eq!(is_known_tx(db, tx.hash()), Ok(false));
+        assert_eq!(get_tx(db, tx.hash()), Err(Error::InvalidTx));
+
+        assert_eq!(insert_tx(db, tx.clone()), Ok(None));
+        assert_eq!(is_known_tx(db, tx.hash()), Ok(true));
+        assert_eq!(get_tx(db, tx.hash()), Ok((tx.hash(), tx.clone())));
+        assert_eq!(is_accepted_tx(db, &tx.hash()), Ok(false));
+
+        set_status(db, &tx.hash(), TxStatus::Accepted).unwrap();
+        assert_eq!(is_accepted_tx(db, &tx.hash()), Ok(true));
+        let (_, stored) = get_tx(db, tx.hash()).unwrap();
+        assert_eq!(stored.status, TxStatus::Accepted);
+
+        set_status(db, &tx.hash(), TxStatus::Removed).unwrap();
+        assert_eq!(is_removed_tx(db, &tx.hash()), Ok(true));
+        assert_eq!(cannot_be_accepted(db, &tx.hash()), Ok(true));
+
+        // Setting the same status again isn't a transition
+        set_status(db, &tx.hash(), TxStatus::Removed).unwrap();
+        let history = get_status_history(db, &tx.hash()).unwrap();
+        let statuses: Vec<TxStatus> = history.iter().map(|(status, _)| status.clone()).collect();
+        assert_eq!(statuses, vec![TxStatus::Pending, TxStatus::Accepted, TxStatus::Removed]);
+        assert!(history.windows(2).all(|w| w[0].1 <= w[1].1));
+    }
+
+    #[actix_rt::test]
+    async fn test_status_history_pruning() {
+        let db = InMemoryStore::new();
+        let tx = test_tx();
+        let _ = insert_tx(&db, tx.clone()).unwrap();
+        for _ in 0..MAX_STATUS_HISTORY {
+            set_status(&db, &tx.hash(), TxStatus::Removed).unwrap();
+            set_status(&db, &tx.hash(), TxStatus::Pending).unwrap();
+        }
+
+        // Only the latest transitions are kept
+        let history = get_status_history(&db, &tx.hash()).unwrap();
+        assert_eq!(history.len(), MAX_STATUS_HISTORY);
+        assert_eq!(history.last().unwrap().0, TxStatus::Pending);
+
+        // The history goes with the transaction
+        assert_eq!(update_and_fetch(&db, &tx.hash(), |_| None), Err(Error::InvalidTx));
+        assert_eq!(is_known_tx(&db, tx.hash()), Ok(false));
+        assert_eq!(get_status_history(&db, &tx.hash()), Ok(vec![]));
+    }
+
+    #[actix_rt::test]
+    async fn test_accepted_pages() {
+        let db = InMemoryStore::new();
+        let mut accepted = vec![];
+        for i in 0..5u64 {
+            let cell = CoinbaseOperation::new(vec![([1u8; 32], 1000 + i)]).try_into().unwrap();
+            let tx = Tx::new(vec![], cell);
+            let _ = insert_tx(&db, tx.clone()).unwrap();
+            // Every other transaction is accepted
+            if i % 2 == 0 {
+                set_status(&db, &tx.hash(), TxStatus::Accepted).unwrap();
+                accepted.push(tx.hash());
+            }
+        }
+        accepted.sort();
+
+        let (page, more) = get_accepted_page(&db, None, 2).unwrap();
+        assert_eq!((page.as_slice(), more), (&accepted[..2], true));
+        let (page, more) = get_accepted_page(&db, Some(&accepted[1]), 2).unwrap();
+        assert_eq!((page.as_slice(), more), (&accepted[2..], false));
+        assert_eq!(get_accepted_page(&db, Some(&accepted[2]), 2), Ok((vec![], false)));
+    }
+
+    #[actix_rt::test]
+    async fn test_tx_round_trip_sled() {
+        tx_round_trip(&SledStore::temporary().unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn test_tx_round_trip_in_memory() {
+        tx_round_trip(&InMemoryStore::new());
+    }
+}
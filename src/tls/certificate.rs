@@ -3,6 +3,8 @@
 //! The [get_node_cert] function is the main entry point, which either generates or loads the private key and certificate.
 //!
 //! The generated X.509 certificates are self-signed, containing ED25519 keys.
+use crate::zfx_id::Id;
+
 use derive_more::{Display, Error, From};
 use pem::Pem;
 use pem::PemError;
@@ -26,22 +28,71 @@ pub fn get_node_cert(cert_file: &Path, priv_key_file: &Path) -> Result<(Vec<u8>,
     } else {
         // No certificate at the given path, generate one
         let (cert, priv_key) = generate_node_cert()?;
-        let pem_cert = der_to_pem(&cert, "CERTIFICATE");
-        let pem_key = der_to_pem(&priv_key, "PRIVATE KEY");
-        if let Some(cert_path) = cert_file.parent() {
-            fs::create_dir_all(cert_path)
-                .expect(&format!("Couldn't create directory: {:?}", &cert_path));
-        };
-        if let Some(pk_path) = priv_key_file.parent() {
-            fs::create_dir_all(pk_path)
-                .expect(&format!("Couldn't create directory: {:?}", &pk_path));
-        };
-        fs::write(cert_file, &pem_cert)?;
-        fs::write(priv_key_file, &pem_key)?;
+        write_node_cert(&cert, &priv_key, cert_file, priv_key_file)?;
         Ok((cert, priv_key))
     }
 }
 
+/// Writes the DER-encoded certificate and private key to `cert_file` and `priv_key_file`
+/// in the PEM format, creating the parent directories if needed
+pub fn write_node_cert(
+    cert: &[u8],
+    priv_key: &[u8],
+    cert_file: &Path,
+    priv_key_file: &Path,
+) -> Result<()> {
+    let pem_cert = der_to_pem(cert, "CERTIFICATE");
+    let pem_key = der_to_pem(priv_key, "PRIVATE KEY");
+    if let Some(cert_path) = cert_file.parent() {
+        fs::create_dir_all(cert_path)
+            .expect(&format!("Couldn't create directory: {:?}", &cert_path));
+    };
+    if let Some(pk_path) = priv_key_file.parent() {
+        fs::create_dir_all(pk_path).expect(&format!("Couldn't create directory: {:?}", &pk_path));
+    };
+    fs::write(cert_file, &pem_cert)?;
+    fs::write(priv_key_file, &pem_key)?;
+    Ok(())
+}
+
+/// Loads the certificate and private key from `cert_file` and `priv_key_file`,
+/// checking that the key belongs to the certificate
+pub fn load_node_cert(cert_file: &Path, priv_key_file: &Path) -> Result<(Vec<u8>, Vec<u8>)> {
+    let cert: Vec<u8> = pem::parse(fs::read(cert_file)?)?.contents;
+    let key = pem::parse(fs::read(priv_key_file)?)?.contents;
+    let (_rest, x509) = X509Certificate::from_der(&cert)?;
+    let key_pair = KeyPair::from_der(&key)?;
+    if x509.public_key().subject_public_key.data != key_pair.public_key_raw() {
+        return Err(Error::KeyMismatch);
+    }
+    Ok((cert, key))
+}
+
+/// Derives the node [Id] from the ED25519 public key of the DER-encoded certificate `cert`
+pub fn id_from_cert(cert: &[u8]) -> Result<Id> {
+    let (_rest, x509) = X509Certificate::from_der(cert)?;
+    let public_key =
+        ed25519_dalek::PublicKey::from_bytes(&x509.public_key().subject_public_key.data)
+            .map_err(|_| Error::InvalidPublicKey)?;
+    Ok(Id::from_public_key(&public_key))
+}
+
+/// Recovers the ED25519 keypair of the DER-encoded PKCS#8 private key `key`, as generated by
+/// [generate_node_cert] or [generate_node_cert_from_keypair], so that the node can sign with the
+/// key its [Id] is derived from
+pub fn keypair_from_key(key: &[u8]) -> Result<ed25519_dalek::Keypair> {
+    // Both PKCS#8 versions start with a fixed header followed by the secret key
+    if key.len() < 48
+        || (key[..16] != ED25519_PKCS8_V1_PREFIX && key[..16] != ED25519_PKCS8_V2_PREFIX)
+    {
+        return Err(Error::InvalidPrivateKey);
+    }
+    let secret =
+        ed25519_dalek::SecretKey::from_bytes(&key[16..48]).map_err(|_| Error::InvalidPrivateKey)?;
+    let public = ed25519_dalek::PublicKey::from(&secret);
+    Ok(ed25519_dalek::Keypair { secret, public })
+}
+
 /// Generate a valid, self signed X.509 certificate and private key
 ///
 pub fn generate_node_cert() -> Result<(Vec<u8>, Vec<u8>)> {
@@ -59,6 +110,36 @@ pub fn generate_node_cert() -> Result<(Vec<u8>, Vec<u8>)> {
     Ok((cert, private_key))
 }
 
+/// Generate a valid, self signed X.509 certificate for an existing ED25519 `keypair`,
+/// so that the node [Id] derived from the certificate is the [Id] of the keypair
+pub fn generate_node_cert_from_keypair(
+    keypair: &ed25519_dalek::Keypair,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    // A PKCS#8 v1 document (RFC 8410) is the fixed ED25519 header followed by the secret key
+    let mut pkcs8 = ED25519_PKCS8_V1_PREFIX.to_vec();
+    pkcs8.extend_from_slice(keypair.secret.as_bytes());
+    let key_pair = KeyPair::from_der(&pkcs8)?;
+    let san = "zfx-node".to_owned();
+    let mut params = CertificateParams::new(vec![san]);
+    params.alg = &PKCS_ED25519;
+    params.key_pair = Some(key_pair);
+    params.key_identifier_method = KeyIdMethod::Sha256;
+
+    let cert = Certificate::from_params(params)?;
+    let private_key = cert.serialize_private_key_der();
+    let cert = cert.serialize_der()?;
+    Ok((cert, private_key))
+}
+
+const ED25519_PKCS8_V1_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+
+/// The header of a PKCS#8 v2 document, which also contains the public key after the secret key
+const ED25519_PKCS8_V2_PREFIX: [u8; 16] = [
+    0x30, 0x53, 0x02, 0x01, 0x01, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+
 /// Convenience wrapper around `pem::encode(&Pem)`
 #[inline]
 fn der_to_pem(contents: &[u8], tag: &str) -> String {
@@ -75,6 +156,16 @@ pub enum Error {
     CertificateGenError(RcgenError),
     CertificateReadError(PemError),
     CertificateParseError(x509_parser::nom::Err<X509Error>),
+    /// The private key doesn't belong to the certificate
+    KeyMismatch,
+    /// The certificate is expired or not yet valid
+    CertificateNotValid,
+    /// The certificate would change the ID of the node
+    NodeIdChanged,
+    /// The certificate doesn't contain an ED25519 public key
+    InvalidPublicKey,
+    /// The private key isn't an ED25519 key in the PKCS#8 format
+    InvalidPrivateKey,
 }
 
 #[cfg(test)]
@@ -117,6 +208,45 @@ mod test {
         matches!(get_node_cert(&cert_file, &priv_key_file), Ok((_cert, _key)));
     }
 
+    #[actix_rt::test]
+    async fn id_from_cert_is_id_of_public_key() {
+        let (cert, key) = generate_node_cert().unwrap();
+        let key_pair = KeyPair::from_der(&key).unwrap();
+        let public_key = ed25519_dalek::PublicKey::from_bytes(&key_pair.public_key_raw()).unwrap();
+        assert_eq!(id_from_cert(&cert).unwrap(), Id::from_public_key(&public_key));
+
+        let (other_cert, _) = generate_node_cert().unwrap();
+        assert_ne!(id_from_cert(&other_cert).unwrap(), id_from_cert(&cert).unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn cert_from_keypair_has_id_of_keypair() {
+        let mut csprng = rand::rngs::OsRng {};
+        let keypair = ed25519_dalek::Keypair::generate(&mut csprng);
+        let (cert, key) = generate_node_cert_from_keypair(&keypair).unwrap();
+        assert_eq!(id_from_cert(&cert).unwrap(), Id::from_public_key(&keypair.public));
+
+        let fname = rand_fname();
+        let crt = generate_file_in_tmp_dir(&fname, String::from("crt"));
+        let pk = generate_file_in_tmp_dir(&fname, String::from("key"));
+        write_node_cert(&cert, &key, &crt, &pk).unwrap();
+        assert_eq!(load_node_cert(&crt, &pk).unwrap(), (cert, key));
+    }
+
+    #[actix_rt::test]
+    async fn keypair_from_key_has_id_of_cert() {
+        let (cert, key) = generate_node_cert().unwrap();
+        let keypair = keypair_from_key(&key).unwrap();
+        assert_eq!(id_from_cert(&cert).unwrap(), Id::from_public_key(&keypair.public));
+
+        let mut csprng = rand::rngs::OsRng {};
+        let keypair = ed25519_dalek::Keypair::generate(&mut csprng);
+        let (_, key) = generate_node_cert_from_keypair(&keypair).unwrap();
+        assert_eq!(keypair_from_key(&key).unwrap().to_bytes(), keypair.to_bytes());
+
+        assert!(matches!(keypair_from_key(&key[..40]), Err(Error::InvalidPrivateKey)));
+    }
+
     fn generate_file_in_tmp_dir(name: &String, extension: String) -> PathBuf {
         temp_dir().join(format!("{}.{}", name, extension))
     }
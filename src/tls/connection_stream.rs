@@ -6,6 +6,7 @@ use tokio::{
     net::TcpStream,
 };
 
+use super::certificate;
 use crate::zfx_id::Id;
 
 /// A unified type for TCP and TLS streams for uniform handling of connections
@@ -85,7 +86,8 @@ fn id_from_first_cert(certs: Option<&[rustls::Certificate]>) -> io::Result<Id> {
                     "no certificates present in TLS state",
                 ));
             }
-            Ok(Id::new(&certs[0].0))
+            certificate::id_from_cert(&certs[0].0)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{}", e)))
         }
         None => Err(io::Error::new(io::ErrorKind::Other, "no certificates present in TLS state")),
     }
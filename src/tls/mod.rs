@@ -6,5 +6,6 @@
 
 pub mod certificate;
 pub mod connection_stream;
+pub mod rotation;
 pub mod tls;
 pub mod upgrader;
@@ -0,0 +1,141 @@
+//! Rotation of the node's TLS certificate
+//!
+//! The [CertificateRotator] swaps the certificate and private key of the [Upgraders] for new
+//! handshakes, while established connections keep using the previous certificate.
+//! As peers know the node by the ID derived from its certificate, a certificate which would
+//! change the node ID is rejected.
+
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use actix::{Actor, Context, Handler, Message};
+use tokio_rustls::rustls::Certificate;
+use tracing::info;
+
+use super::certificate::{self, Error, Result};
+use super::tls::check_validity_period;
+use super::upgrader::Upgraders;
+use crate::zfx_id::Id;
+
+/// Replace the node's certificate and private key with the ones found at the given paths
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "Result<()>")]
+pub struct RotateCertificate {
+    pub cert_path: PathBuf,
+    pub pk_path: PathBuf,
+}
+
+/// Rotates the certificate used by the upgraders of a node
+pub struct CertificateRotator {
+    node_id: Id,
+    upgraders: Upgraders,
+}
+
+impl CertificateRotator {
+    pub fn new(node_id: Id, upgraders: Upgraders) -> Self {
+        CertificateRotator { node_id, upgraders }
+    }
+}
+
+impl Actor for CertificateRotator {
+    type Context = Context<Self>;
+}
+
+impl Handler<RotateCertificate> for CertificateRotator {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: RotateCertificate, _ctx: &mut Context<Self>) -> Self::Result {
+        let (cert, key) = certificate::load_node_cert(&msg.cert_path, &msg.pk_path)?;
+        check_validity_period(&Certificate(cert.clone()), SystemTime::now())
+            .map_err(|_| Error::CertificateNotValid)?;
+        if certificate::id_from_cert(&cert)? != self.node_id {
+            return Err(Error::NodeIdChanged);
+        }
+        self.upgraders.client.set_certificate(&cert, &key);
+        self.upgraders.server.set_certificate(&cert, &key);
+        info!("rotated certificate to {:?}", msg.cert_path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tls::upgrader::tls_upgraders;
+    use std::env::temp_dir;
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn rand_fname() -> String {
+        use rand::{distributions::Alphanumeric, thread_rng, Rng};
+
+        let mut rng = thread_rng();
+        std::iter::repeat(()).map(|()| rng.sample(Alphanumeric) as char).take(8).collect()
+    }
+
+    /// Generates a certificate and key into a temporary directory, returning the paths
+    fn generate_cert_files() -> (PathBuf, PathBuf) {
+        let fname = rand_fname();
+        let cert_path = temp_dir().join(format!("{}.crt", fname));
+        let pk_path = temp_dir().join(format!("{}.key", fname));
+        let _ = certificate::get_node_cert(&cert_path, &pk_path).unwrap();
+        (cert_path, pk_path)
+    }
+
+    /// Copies the certificate and key to new paths
+    fn copy_cert_files(cert_path: &PathBuf, pk_path: &PathBuf) -> (PathBuf, PathBuf) {
+        let fname = rand_fname();
+        let new_cert_path = temp_dir().join(format!("{}.crt", fname));
+        let new_pk_path = temp_dir().join(format!("{}.key", fname));
+        let _ = std::fs::copy(cert_path, &new_cert_path).unwrap();
+        let _ = std::fs::copy(pk_path, &new_pk_path).unwrap();
+        (new_cert_path, new_pk_path)
+    }
+
+    /// Connects to a server using `upgraders`, returning the ID presented by the server
+    async fn handshake(upgraders: &Upgraders) -> Id {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_upgrader = upgraders.server.clone();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            server_upgrader.upgrade(stream).await.unwrap()
+        });
+
+        let (cert, key) = certificate::generate_node_cert().unwrap();
+        let stream = TcpStream::connect(&addr).await.unwrap();
+        let connection = tls_upgraders(&cert, &key).client.upgrade(stream).await.unwrap();
+        let _ = server.await.unwrap();
+        connection.get_id().unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn rotate_to_certificate_with_same_id() {
+        let (cert_path, pk_path) = generate_cert_files();
+        let (cert, _key) = certificate::load_node_cert(&cert_path, &pk_path).unwrap();
+        let node_id = certificate::id_from_cert(&cert).unwrap();
+        let (other_cert, other_key) = certificate::generate_node_cert().unwrap();
+        // Start with a different certificate to see the rotated one being used
+        let upgraders = tls_upgraders(&other_cert, &other_key);
+        let rotator = CertificateRotator::new(node_id, upgraders.clone()).start();
+
+        let (new_cert_path, new_pk_path) = copy_cert_files(&cert_path, &pk_path);
+        let msg = RotateCertificate { cert_path: new_cert_path, pk_path: new_pk_path };
+        rotator.send(msg).await.unwrap().unwrap();
+        assert_eq!(handshake(&upgraders).await, node_id);
+    }
+
+    #[actix_rt::test]
+    async fn rotate_to_certificate_with_different_id() {
+        let (cert, key) = certificate::generate_node_cert().unwrap();
+        let node_id = certificate::id_from_cert(&cert).unwrap();
+        let upgraders = tls_upgraders(&cert, &key);
+        let rotator = CertificateRotator::new(node_id, upgraders.clone()).start();
+
+        let (cert_path, pk_path) = generate_cert_files();
+        match rotator.send(RotateCertificate { cert_path, pk_path }).await.unwrap() {
+            Err(Error::NodeIdChanged) => (),
+            other => panic!("unexpected: {:?}", other),
+        }
+        assert_eq!(handshake(&upgraders).await, node_id);
+    }
+}
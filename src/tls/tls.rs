@@ -2,30 +2,62 @@
 //!
 //! Client side authenication is enforced, and the server doesn't need a certificate chain,
 //! as both use single self-signed certificates.
+//! The peer's certificate is only accepted within its validity period.
 
 use lazy_static::lazy_static;
 use std::convert::TryFrom;
-use std::{sync::Arc, time::SystemTime};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{io, sync::Arc};
 use tokio_rustls::rustls::{
     self, client::ServerCertVerifier, server::ClientCertVerifier, Certificate, ClientConfig,
     ServerConfig, ServerName,
 };
+use x509_parser::certificate::X509Certificate;
+use x509_parser::prelude::FromDer;
 
 lazy_static! {
     /// Dummy server name ("example.org"), used by client-initiated connections
     pub static ref DUMMY_DOMAIN: ServerName = ServerName::try_from("example.org").unwrap();
 }
 
-/// Client verification: enforce the presence and check a single certificate
+/// Error message for certificates presented outside of their validity period
+const CERTIFICATE_NOT_VALID: &str = "certificate expired or not yet valid";
+
+/// Check that `now` is within the `not_before`/`not_after` bounds of the certificate
+pub(crate) fn check_validity_period(
+    cert: &Certificate,
+    now: SystemTime,
+) -> Result<(), rustls::Error> {
+    let (_rest, cert) = X509Certificate::from_der(&cert.0)
+        .map_err(|_| rustls::Error::InvalidCertificateEncoding)?;
+    let now = now.duration_since(UNIX_EPOCH).map_err(|_| rustls::Error::FailedToGetCurrentTime)?;
+    let now = now.as_secs() as i64;
+    let validity = cert.validity();
+    if now < validity.not_before.timestamp() || now > validity.not_after.timestamp() {
+        return Err(rustls::Error::InvalidCertificateData(CERTIFICATE_NOT_VALID.to_owned()));
+    }
+    Ok(())
+}
+
+/// True if the handshake failed because the peer's certificate is outside of its validity period
+pub fn is_certificate_not_valid(error: &io::Error) -> bool {
+    match error.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>()) {
+        Some(rustls::Error::InvalidCertificateData(msg)) => msg == CERTIFICATE_NOT_VALID,
+        _ => false,
+    }
+}
+
+/// Client verification: enforce the presence and check the validity period of a single certificate
 pub struct ZfxClientCertVerifier;
 
 impl ClientCertVerifier for ZfxClientCertVerifier {
     fn verify_client_cert(
         &self,
-        _end_entity: &Certificate,
+        end_entity: &Certificate,
         _intermediates: &[Certificate],
-        _now: SystemTime,
+        now: SystemTime,
     ) -> Result<rustls::server::ClientCertVerified, rustls::Error> {
+        check_validity_period(end_entity, now)?;
         Ok(rustls::server::ClientCertVerified::assertion())
     }
     fn client_auth_root_subjects(&self) -> Option<rustls::DistinguishedNames> {
@@ -41,19 +73,21 @@ impl ClientCertVerifier for ZfxClientCertVerifier {
     }
 }
 
-/// Server verification: don't check certificate chain and domain name, just the presence of a certificate
+/// Server verification: don't check certificate chain and domain name,
+/// just the presence and validity period of a certificate
 pub struct ZfxServerCertVerifier;
 
 impl ServerCertVerifier for ZfxServerCertVerifier {
     fn verify_server_cert(
         &self,
-        _end_entity: &Certificate,
+        end_entity: &Certificate,
         _intermediates: &[Certificate],
         _server_name: &ServerName,
         _scts: &mut dyn Iterator<Item = &[u8]>,
         _ocsp_response: &[u8],
-        _now: SystemTime,
+        now: SystemTime,
     ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        check_validity_period(end_entity, now)?;
         Ok(rustls::client::ServerCertVerified::assertion())
     }
 }
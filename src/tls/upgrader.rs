@@ -6,7 +6,8 @@
 //! while for TCP, it is practically a no-op.
 
 use std::future::Future;
-use std::{pin::Pin, sync::Arc};
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
 
 use crate::tls::tls::{client_tls_config, server_tls_config, DUMMY_DOMAIN};
 use tokio::net::TcpStream;
@@ -25,6 +26,9 @@ pub trait Upgrader: Sync + Send {
 
     /// True if if the `Upgrader` upgrades to TLS, false for TCP
     fn is_tls(&self) -> bool;
+
+    /// Replace the certificate and private key used for new connections (no-op for TCP)
+    fn set_certificate(&self, _cert: &[u8], _key: &[u8]) {}
 }
 
 /// Generic [Upgrader] for TCP
@@ -32,12 +36,12 @@ pub struct TcpUpgrader {}
 
 /// TLS [Upgrader] for client connections
 pub struct TlsClientUpgrader {
-    connector: TlsConnector,
+    connector: RwLock<TlsConnector>,
 }
 
 /// TLS [Upgrader] for server connections
 pub struct TlsServerUpgrader {
-    acceptor: TlsAcceptor,
+    acceptor: RwLock<TlsAcceptor>,
 }
 
 /// Future type that can be safely held across `.await` boundaries
@@ -66,13 +70,13 @@ impl TlsClientUpgrader {
     pub fn new(cert: &[u8], key: &[u8]) -> Arc<dyn Upgrader> {
         let config = client_tls_config(cert, key);
         let connector = TlsConnector::from(Arc::new(config));
-        Arc::new(TlsClientUpgrader { connector })
+        Arc::new(TlsClientUpgrader { connector: RwLock::new(connector) })
     }
 }
 
 impl Upgrader for TlsClientUpgrader {
     fn upgrade(&self, c: TcpStream) -> UpgradeOutput {
-        let connector = self.connector.clone();
+        let connector = self.connector.read().unwrap().clone();
         let fut = async move {
             match connector.connect(DUMMY_DOMAIN.clone(), c).await {
                 Ok(tls_stream) => Ok(ConnectionStream::TlsClient(tls_stream)),
@@ -85,19 +89,24 @@ impl Upgrader for TlsClientUpgrader {
     fn is_tls(&self) -> bool {
         true
     }
+
+    fn set_certificate(&self, cert: &[u8], key: &[u8]) {
+        let config = client_tls_config(cert, key);
+        *self.connector.write().unwrap() = TlsConnector::from(Arc::new(config));
+    }
 }
 
 impl TlsServerUpgrader {
     pub fn new(cert: &[u8], key: &[u8]) -> Arc<dyn Upgrader> {
         let config = server_tls_config(cert, key);
         let acceptor = TlsAcceptor::from(Arc::new(config));
-        Arc::new(TlsServerUpgrader { acceptor })
+        Arc::new(TlsServerUpgrader { acceptor: RwLock::new(acceptor) })
     }
 }
 
 impl Upgrader for TlsServerUpgrader {
     fn upgrade(&self, c: TcpStream) -> UpgradeOutput {
-        let acc = self.acceptor.clone();
+        let acc = self.acceptor.read().unwrap().clone();
         let fut = async move {
             match acc.accept(c).await {
                 Ok(tls_stream) => Ok(ConnectionStream::TlsServer(tls_stream)),
@@ -110,9 +119,15 @@ impl Upgrader for TlsServerUpgrader {
     fn is_tls(&self) -> bool {
         true
     }
+
+    fn set_certificate(&self, cert: &[u8], key: &[u8]) {
+        let config = server_tls_config(cert, key);
+        *self.acceptor.write().unwrap() = TlsAcceptor::from(Arc::new(config));
+    }
 }
 
 /// A pair of client and server-side upgraders
+#[derive(Clone)]
 pub struct Upgraders {
     pub client: Arc<dyn Upgrader>,
     pub server: Arc<dyn Upgrader>,
@@ -154,6 +169,36 @@ mod test {
         let _upgraders = tls_upgraders(&cert, &key);
     }
 
+    /// Generates a self-signed certificate which expired on 2020-01-01
+    fn generate_expired_cert() -> (Vec<u8>, Vec<u8>) {
+        let alg = &rcgen::PKCS_ED25519;
+        let mut params = rcgen::CertificateParams::new(vec!["zfx-node".to_owned()]);
+        params.alg = alg;
+        params.key_pair = Some(rcgen::KeyPair::generate(alg).unwrap());
+        params.not_before = rcgen::date_time_ymd(2019, 1, 1);
+        params.not_after = rcgen::date_time_ymd(2020, 1, 1);
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        (cert.serialize_der().unwrap(), cert.serialize_private_key_der())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn expired_certificate_is_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (cert, key) = generate_expired_cert();
+            tls_upgraders(&cert, &key).server.upgrade(stream).await.map(|_| ())
+        });
+
+        let stream = TcpStream::connect(&addr).await.unwrap();
+        let (cert, key) = certificate::generate_node_cert().unwrap();
+        let err = tls_upgraders(&cert, &key).client.upgrade(stream).await.unwrap_err();
+        assert!(matches!(crate::Error::from(err), crate::Error::PeerCertificateNotValid));
+        assert!(server.await.unwrap().is_err());
+    }
+
     type Res = Result<(), String>;
 
     #[tokio::test(flavor = "multi_thread")]
@@ -190,7 +235,7 @@ mod test {
         println!("incoming TCP connection from {:}", &c_addr);
 
         let (cert, key) = certificate::generate_node_cert().unwrap();
-        let server_id = Id::new(&cert);
+        let server_id = certificate::id_from_cert(&cert).unwrap();
         let upgraders = tls_upgraders(&cert, &key);
         let mut tls_stream = upgraders.server.upgrade(stream).await.unwrap();
         assert!(tls_stream.is_tls());
@@ -214,7 +259,7 @@ mod test {
         let stream = TcpStream::connect(&addr).await.expect("couldnt connect");
         println!("TCP connection to {:}", &addr);
         let (cert, key) = certificate::generate_node_cert().unwrap();
-        let client_id = Id::new(&cert);
+        let client_id = certificate::id_from_cert(&cert).unwrap();
         let upgraders = tls_upgraders(&cert, &key);
         let mut tls_stream = upgraders.client.upgrade(stream).await.unwrap();
         assert!(tls_stream.is_tls());
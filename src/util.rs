@@ -1,7 +1,14 @@
 //! Utility functions for consensus algorithms
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
 use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Duration;
 
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use tokio::sync::oneshot;
+use tokio::time;
 
 use crate::alpha::types::Weight;
 use crate::cell::{Cell, CellType};
@@ -23,13 +30,104 @@ pub fn sum_outcomes(outcomes: Vec<(Id, Weight, bool)>) -> f64 {
 }
 
 /// Sample the required weight from a list of validators
+///
+/// The validators are shuffled and taken in turn until their combined weight reaches `min_w`,
+/// so each entry of `validators` is sampled at most once. A validator listed more than once may
+/// however be sampled repeatedly, see [sample_weighted_distinct].
+/// Returns `None` if the validators don't have enough weight altogether.
 #[inline]
 pub fn sample_weighted(
+    min_w: Weight,
+    validators: Vec<(Id, SocketAddr, Weight)>,
+) -> Option<Vec<(Id, SocketAddr)>> {
+    sample_weighted_with(&mut rand::thread_rng(), min_w, validators)
+}
+
+/// Sample the required weight from a list of distinct validators
+///
+/// Like [sample_weighted], but only the first entry of a validator listed more than once is
+/// kept, so that no validator appears twice in the sample and its weight is counted only once.
+pub fn sample_weighted_distinct(
+    min_w: Weight,
+    validators: Vec<(Id, SocketAddr, Weight)>,
+) -> Option<Vec<(Id, SocketAddr)>> {
+    let mut seen = HashSet::new();
+    let validators = validators.into_iter().filter(|(id, _, _)| seen.insert(*id)).collect();
+    sample_weighted(min_w, validators)
+}
+
+/// The lowest sampling bias of a validator, reached by validators without any reputation left
+///
+/// Bounding the bias keeps the stake weight the dominant term: a validator with the worst
+/// reputation is still placed ahead of one with a perfect reputation a third of the time.
+pub const MIN_REPUTATION_BIAS: f64 = 0.5;
+
+/// Sample the required weight from a list of distinct validators, favouring validators with a
+/// good reputation.
+///
+/// Like [sample_weighted_distinct], but the validators are taken in a random order biased by
+/// their reputation in `reputations`, from `0.0` (worst) to `1.0` (best, the default for
+/// validators which aren't listed). The reputation is mapped to a bias between
+/// [MIN_REPUTATION_BIAS] and `1.0`, so that flaky validators are sampled less often without
+/// ever being left out. The sample still has to reach `min_w` by stake weight.
+pub fn sample_weighted_biased(
+    min_w: Weight,
+    validators: Vec<(Id, SocketAddr, Weight)>,
+    reputations: &HashMap<Id, f64>,
+) -> Option<Vec<(Id, SocketAddr)>> {
+    sample_weighted_biased_with(&mut rand::thread_rng(), min_w, validators, reputations)
+}
+
+fn sample_weighted_biased_with<R: Rng>(
+    rng: &mut R,
+    min_w: Weight,
+    validators: Vec<(Id, SocketAddr, Weight)>,
+    reputations: &HashMap<Id, f64>,
+) -> Option<Vec<(Id, SocketAddr)>> {
+    let mut seen = HashSet::new();
+    // Weighted random permutation: each validator draws the key `u^(1/bias)`, `u` being
+    // uniform in `0..1`, and the validators are taken by decreasing key
+    let mut keyed: Vec<(f64, (Id, SocketAddr, Weight))> = validators
+        .into_iter()
+        .filter(|(id, _, _)| seen.insert(*id))
+        .map(|v| {
+            let reputation = reputations.get(&v.0).cloned().unwrap_or(1.0).max(0.0).min(1.0);
+            let bias = MIN_REPUTATION_BIAS + (1.0 - MIN_REPUTATION_BIAS) * reputation;
+            (rng.gen::<f64>().powf(1.0 / bias), v)
+        })
+        .collect();
+    keyed.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    take_weight(min_w, keyed.into_iter().map(|(_, v)| v))
+}
+
+/// Sample the required weight from a list of validators, drawing the randomness from `seed`.
+///
+/// The validators are sorted by id before shuffling, so the same seed and validator set
+/// always yield the same sample, regardless of the order they were passed in.
+/// Meant for tests and simulations which need reproducible queries.
+pub fn sample_weighted_seeded(
+    seed: u64,
     min_w: Weight,
     mut validators: Vec<(Id, SocketAddr, Weight)>,
 ) -> Option<Vec<(Id, SocketAddr)>> {
-    let mut rng = rand::thread_rng();
-    validators.shuffle(&mut rng);
+    validators.sort_by(|a, b| a.0.cmp(&b.0));
+    sample_weighted_with(&mut StdRng::seed_from_u64(seed), min_w, validators)
+}
+
+fn sample_weighted_with<R: Rng>(
+    rng: &mut R,
+    min_w: Weight,
+    mut validators: Vec<(Id, SocketAddr, Weight)>,
+) -> Option<Vec<(Id, SocketAddr)>> {
+    validators.shuffle(rng);
+    take_weight(min_w, validators.into_iter())
+}
+
+/// Take validators in turn until their combined weight reaches `min_w`
+fn take_weight(
+    min_w: Weight,
+    validators: impl Iterator<Item = (Id, SocketAddr, Weight)>,
+) -> Option<Vec<(Id, SocketAddr)>> {
     let mut sample = vec![];
     let mut w = 0.0;
     for (id, ip, w_v) in validators {
@@ -74,6 +172,103 @@ pub fn has_coinbase_output(cell: &Cell) -> bool {
     false
 }
 
+/// Check if a cell records slash evidence.
+pub fn has_slash_output(cell: &Cell) -> bool {
+    cell.outputs().iter().any(|o| o.cell_type == CellType::Slash)
+}
+
+/// Exponential backoff between retries: the delay starts at `base` and doubles with every
+/// attempt up to `cap`. A random share of up to `jitter` of each delay is subtracted, so that
+/// nodes retrying at the same time spread out.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    base: Duration,
+    cap: Duration,
+    jitter: f64,
+    attempt: u32,
+}
+
+impl Backoff {
+    /// Creates a backoff from `base` to `cap`, `jitter` being clamped to `0.0..=1.0`.
+    pub fn new(base: Duration, cap: Duration, jitter: f64) -> Self {
+        Backoff { base, cap, jitter: jitter.max(0.0).min(1.0), attempt: 0 }
+    }
+
+    /// Returns the delay before the next attempt, and grows the delay of the following one.
+    pub fn next_delay<R: Rng>(&mut self, rng: &mut R) -> Duration {
+        let delay = self.base.checked_mul(1 << self.attempt.min(31)).unwrap_or(self.cap);
+        let delay = delay.min(self.cap);
+        if delay < self.cap {
+            self.attempt += 1;
+        }
+        delay.mul_f64(1.0 - self.jitter * rng.gen::<f64>())
+    }
+
+    /// Restarts the backoff from `base`, after a successful attempt.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+/// Tracks the in-flight queries of a consensus actor, so that shutting it down can wait for
+/// them to settle.
+#[derive(Debug, Default)]
+pub struct QueryDrain {
+    in_flight: usize,
+    waiters: Vec<oneshot::Sender<()>>,
+    shutting_down: bool,
+}
+
+impl QueryDrain {
+    /// The number of queries awaiting responses
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// `true` once [shut_down][QueryDrain::shut_down] was called, new work should be refused
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down
+    }
+
+    /// Records `n` new queries.
+    pub fn started(&mut self, n: usize) {
+        self.in_flight += n;
+    }
+
+    /// Records a settled query. Returns `true` if it was the last one and a shutdown is waiting,
+    /// which should then be woken up with [wake][QueryDrain::wake].
+    pub fn settled(&mut self) -> bool {
+        self.in_flight = self.in_flight.saturating_sub(1);
+        self.in_flight == 0 && !self.waiters.is_empty()
+    }
+
+    /// Wakes up the pending shutdowns.
+    pub fn wake(&mut self) {
+        for waiter in self.waiters.drain(..) {
+            let _ = waiter.send(());
+        }
+    }
+
+    /// Starts shutting down, and returns a future which waits up to `timeout` for the in-flight
+    /// queries to settle. The future yields `false` if they didn't settle in time.
+    pub fn shut_down(&mut self, timeout: Duration) -> impl Future<Output = bool> {
+        self.shutting_down = true;
+        let drained = if self.in_flight > 0 {
+            let (sender, receiver) = oneshot::channel();
+            self.waiters.push(sender);
+            Some(receiver)
+        } else {
+            None
+        };
+        async move {
+            match drained {
+                Some(drained) => time::timeout(timeout, drained).await.is_ok(),
+                None => true,
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -122,6 +317,109 @@ mod test {
         }
     }
 
+    #[actix_rt::test]
+    async fn test_distinct_sampling() {
+        let dummy_ip: SocketAddr = "0.0.0.0:1111".parse().unwrap();
+
+        // The sample needs the weight of all three validators, one of them being listed twice
+        let v = vec![
+            (Id::one(), dummy_ip, 0.3),
+            (Id::two(), dummy_ip, 0.3),
+            (Id::one(), dummy_ip, 0.3),
+            (Id::zero(), dummy_ip, 0.3),
+        ];
+        for _ in 0..100 {
+            let sample = sample_weighted_distinct(0.85, v.clone()).unwrap();
+            let ids: HashSet<Id> = sample.iter().map(|(id, _)| *id).collect();
+            assert_eq!(ids.len(), sample.len());
+            assert_eq!(ids.len(), 3);
+        }
+
+        // A repeated validator doesn't make up for missing weight
+        let v = vec![(Id::one(), dummy_ip, 0.5), (Id::one(), dummy_ip, 0.5)];
+        assert_eq!(sample_weighted_distinct(0.66, v), None);
+    }
+
+    #[actix_rt::test]
+    async fn test_reputation_biased_sampling() {
+        let dummy_ip: SocketAddr = "0.0.0.0:1111".parse().unwrap();
+        let mut rng = StdRng::seed_from_u64(42);
+
+        // Either validator has enough stake on its own, so the sample holds only one of them
+        let v = vec![(Id::one(), dummy_ip, 0.5), (Id::two(), dummy_ip, 0.5)];
+        let mut reputations = HashMap::new();
+        let _ = reputations.insert(Id::two(), 0.0);
+
+        let mut sampled = HashMap::new();
+        for _ in 0..1000 {
+            let sample = sample_weighted_biased_with(&mut rng, 0.5, v.clone(), &reputations);
+            let sample = sample.unwrap();
+            assert_eq!(sample.len(), 1);
+            *sampled.entry(sample[0].0).or_insert(0) += 1;
+        }
+        let good = sampled.get(&Id::one()).cloned().unwrap_or(0);
+        let bad = sampled.get(&Id::two()).cloned().unwrap_or(0);
+        // The expected split is 2:1 with the minimum bias
+        assert!(good > bad * 3 / 2, "good = {}, bad = {}", good, bad);
+        assert!(bad > 200, "bad = {}", bad);
+
+        // The reputation doesn't make up for missing stake
+        let v = vec![(Id::one(), dummy_ip, 0.3), (Id::two(), dummy_ip, 0.3)];
+        assert_eq!(sample_weighted_biased(0.66, v, &reputations), None);
+    }
+
+    #[actix_rt::test]
+    async fn test_seeded_sampling() {
+        let validators: Vec<(Id, SocketAddr, Weight)> = (0..20)
+            .map(|i| {
+                let ip: SocketAddr = format!("0.0.0.0:{}", 1000 + i).parse().unwrap();
+                (Id::from_ip(&ip), ip, 0.05)
+            })
+            .collect();
+
+        let s1 = sample_weighted_seeded(42, 0.66, validators.clone()).unwrap();
+        let s2 = sample_weighted_seeded(42, 0.66, validators.clone()).unwrap();
+        assert_eq!(s1, s2);
+
+        // The order of the input doesn't matter
+        let mut reversed = validators.clone();
+        reversed.reverse();
+        let s3 = sample_weighted_seeded(42, 0.66, reversed).unwrap();
+        assert_eq!(s1, s3);
+
+        // A different seed picks a different sample
+        let s4 = sample_weighted_seeded(43, 0.66, validators).unwrap();
+        assert_ne!(s1, s4);
+    }
+
+    #[actix_rt::test]
+    async fn test_backoff() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let ms = Duration::from_millis;
+
+        // Without jitter the delays double up to the cap
+        let mut backoff = Backoff::new(ms(100), ms(1000), 0.0);
+        let delays: Vec<Duration> = (0..7).map(|_| backoff.next_delay(&mut rng)).collect();
+        assert_eq!(delays, vec![ms(100), ms(200), ms(400), ms(800), ms(1000), ms(1000), ms(1000)]);
+        // After a success, the backoff starts over
+        backoff.reset();
+        assert_eq!(backoff.next_delay(&mut rng), ms(100));
+        assert_eq!(backoff.next_delay(&mut rng), ms(200));
+
+        // The jitter shortens each delay by up to its share
+        let mut backoff = Backoff::new(ms(100), ms(1000), 0.5);
+        for expected in [100, 200, 400, 800, 1000, 1000] {
+            let delay = backoff.next_delay(&mut rng);
+            assert!(delay <= ms(expected) && delay >= ms(expected / 2));
+        }
+
+        // The delays don't overflow after many attempts
+        let mut backoff = Backoff::new(ms(100), Duration::from_secs(3600), 0.0);
+        for _ in 0..100 {
+            assert!(backoff.next_delay(&mut rng) <= Duration::from_secs(3600));
+        }
+    }
+
     #[actix_rt::test]
     async fn test_sum_outcomes() {
         let zid = Id::zero();
@@ -193,4 +491,23 @@ mod test {
             other => panic!("Unexpected {:?}", other),
         }
     }
+
+    #[actix_rt::test]
+    async fn test_query_drain() {
+        let mut drain = QueryDrain::default();
+        assert!(drain.shut_down(Duration::from_millis(10)).await);
+
+        let mut drain = QueryDrain::default();
+        drain.started(2);
+        let shutdown = drain.shut_down(Duration::from_secs(10));
+        assert!(drain.is_shutting_down());
+        assert!(!drain.settled());
+        assert!(drain.settled());
+        drain.wake();
+        assert!(shutdown.await);
+
+        let mut drain = QueryDrain::default();
+        drain.started(1);
+        assert!(!drain.shut_down(Duration::from_millis(10)).await);
+    }
 }
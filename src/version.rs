@@ -1,16 +1,163 @@
 //! Messages for querying and replying with the node version
+//!
+//! The [Version] handshake is refused with [VersionRefused] if the [ProtocolVersion] of the
+//! peer isn't supported, see [ProtocolVersion::is_supported].
+//!
+//! Both [Version] and [VersionAck] carry the [Capabilities] of the node. Only the optional
+//! features which both sides support are used, see [Capabilities::negotiate].
 
+use crate::cell::CellType;
 use crate::zfx_id::Id;
+use crate::{Error, Result};
 use std::net::SocketAddr;
 
+/// The version of the network protocol. Peers with the same `major` version can communicate,
+/// as long as `minor` isn't older than the [MIN_SUPPORTED_VERSION].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ProtocolVersion {
+    pub major: u16,
+    pub minor: u16,
+}
+
+/// The protocol version of this node.
+///
+/// The `minor` version is bumped with every change of the messages. New requests and responses
+/// are appended at the end of [Request][crate::protocol::Request] and
+/// [Response][crate::protocol::Response], so that the messages of older peers still decode. A
+/// change of the payload of an existing message also raises the [MIN_SUPPORTED_VERSION].
+///
+/// * 1.0: the version handshake
+/// * 1.1: `TxAncestors` are limited in depth and flag truncated responses
+/// * 1.2: `GetChainTip`
+/// * 1.3: `GenerateTxAck` has the reason a transaction is rejected
+/// * 1.4: `GetBlocksByRange`
+/// * 1.5: `GetReadiness`
+/// * 1.6: `AcceptedFrontier` has the genesis hash of the peer
+/// * 1.7: `GetMempoolStats`
+/// * 1.8: inputs and outputs of cells have a signature scheme
+/// * 1.9: `GetCellHistory`
+/// * 1.10: `Version` and `VersionAck` have the capabilities of the node, `QueryTxBatch`
+/// * 1.11: stakes have a start and an end height
+/// * 1.12: `GetConflictSet`
+/// * 1.13: `IceUninitialised`
+/// * 1.14: `AcceptedCellHashes` have a cursor for `GetAcceptedCellHashesPage`
+/// * 1.15: `ValidateTx`
+/// * 1.16: `AcceptedFrontier` is signed by the peer
+/// * 1.17: `CellType::Custom` follows `Slash`
+pub const CURRENT_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 17 };
+/// The oldest protocol version this node can communicate with.
+pub const MIN_SUPPORTED_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 17 };
+
+impl ProtocolVersion {
+    /// Whether this node can communicate with a peer of this version.
+    pub fn is_supported(&self) -> bool {
+        self.major == CURRENT_VERSION.major && *self >= MIN_SUPPORTED_VERSION
+    }
+}
+
+impl std::fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// The optional protocol features supported by a node.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, MessageResponse)]
+pub struct Capabilities {
+    /// Bitset of the supported features, e.g. [Capabilities::BATCH_QUERY]
+    pub features: u32,
+    /// The sorted tags of the supported [custom cell types][CellType::Custom]
+    pub cell_types: Vec<u16>,
+}
+
+impl Capabilities {
+    /// The node answers [QueryTxBatch][crate::sleet::QueryTxBatch] queries
+    pub const BATCH_QUERY: u32 = 1 << 0;
+    /// The node reads lz4 compressed frames, see [codec][crate::channel::codec]
+    pub const COMPRESSION: u32 = 1 << 1;
+
+    /// No optional features, as assumed of peers whose capabilities aren't known.
+    pub fn none() -> Self {
+        Capabilities { features: 0, cell_types: vec![] }
+    }
+
+    /// The features implemented by this version of the node.
+    pub fn current() -> Self {
+        let features = Capabilities::BATCH_QUERY | Capabilities::COMPRESSION;
+        Capabilities { features, cell_types: vec![] }
+    }
+
+    /// Enable or disable the `feature` bits.
+    pub fn with_feature(mut self, feature: u32, enabled: bool) -> Self {
+        if enabled {
+            self.features |= feature;
+        } else {
+            self.features &= !feature;
+        }
+        self
+    }
+
+    /// Advertise support for the custom cell types with the given `tags`.
+    pub fn with_cell_types(mut self, tags: impl IntoIterator<Item = u16>) -> Self {
+        self.cell_types.extend(tags);
+        self.cell_types.sort();
+        self.cell_types.dedup();
+        self
+    }
+
+    pub fn supports_batch_query(&self) -> bool {
+        self.features & Capabilities::BATCH_QUERY != 0
+    }
+
+    pub fn supports_compression(&self) -> bool {
+        self.features & Capabilities::COMPRESSION != 0
+    }
+
+    /// Whether the node can validate outputs of `cell_type`. The built-in cell types are always
+    /// supported.
+    pub fn supports_cell_type(&self, cell_type: &CellType) -> bool {
+        match cell_type {
+            CellType::Custom(tag) => self.cell_types.binary_search(tag).is_ok(),
+            _ => true,
+        }
+    }
+
+    /// The capabilities which both this node and the peer with `other` capabilities support.
+    /// Feature bits unknown to either side are dropped.
+    pub fn negotiate(&self, other: &Capabilities) -> Capabilities {
+        let cell_types =
+            self.cell_types.iter().filter(|tag| other.cell_types.contains(tag)).cloned().collect();
+        Capabilities { features: self.features & other.features, cell_types }
+    }
+}
+
 /// Query the version of the other node.
 ///
 /// See [Request][crate::protocol::Request]
 #[derive(Debug, Clone, Serialize, Deserialize, Message)]
-#[rtype(result = "VersionAck")]
+#[rtype(result = "std::result::Result<VersionAck, VersionRefused>")]
 pub struct Version {
     pub id: Id,
     pub ip: SocketAddr,
+    pub version: ProtocolVersion,
+    pub capabilities: Capabilities,
+}
+
+impl Version {
+    /// Create a query with the [CURRENT_VERSION] and the `capabilities` of this node.
+    pub fn new(id: Id, ip: SocketAddr, capabilities: Capabilities) -> Self {
+        Version { id, ip, version: CURRENT_VERSION, capabilities }
+    }
+
+    /// Checks that the version of the querying node is supported.
+    ///
+    /// Throws [Error::IncompatibleVersion] otherwise.
+    pub fn validate(&self) -> Result<()> {
+        if !self.version.is_supported() {
+            return Err(Error::IncompatibleVersion(self.version));
+        }
+        Ok(())
+    }
 }
 
 /// Reply to  a [Version] query
@@ -20,5 +167,65 @@ pub struct Version {
 pub struct VersionAck {
     pub id: Id,
     pub ip: SocketAddr,
+    pub version: ProtocolVersion,
+    pub capabilities: Capabilities,
     pub peer_list: Vec<(Id, SocketAddr)>,
 }
+
+/// Refusal of a [Version] query from a node with an unsupported version.
+/// The connection is closed after sending it.
+///
+/// See [Response][crate::protocol::Response]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, MessageResponse)]
+pub struct VersionRefused {
+    /// The version of the refusing node
+    pub version: ProtocolVersion,
+    /// The oldest version supported by the refusing node
+    pub min_version: ProtocolVersion,
+}
+
+impl VersionRefused {
+    pub fn new() -> Self {
+        VersionRefused { version: CURRENT_VERSION, min_version: MIN_SUPPORTED_VERSION }
+    }
+}
+
+impl std::convert::From<VersionRefused> for Error {
+    fn from(refused: VersionRefused) -> Self {
+        Error::IncompatibleVersion(refused.version)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn test_supported_versions() {
+        assert!(CURRENT_VERSION.is_supported());
+        let newer_minor = ProtocolVersion { minor: CURRENT_VERSION.minor + 1, ..CURRENT_VERSION };
+        assert!(newer_minor.is_supported());
+        let newer_major = ProtocolVersion { major: CURRENT_VERSION.major + 1, minor: 0 };
+        assert!(!newer_major.is_supported());
+        let older_major = ProtocolVersion { major: MIN_SUPPORTED_VERSION.major - 1, minor: 9 };
+        assert!(!older_major.is_supported());
+    }
+
+    #[actix_rt::test]
+    async fn test_negotiate_capabilities() {
+        let ours = Capabilities::current().with_cell_types(vec![7, 3, 3]);
+        assert_eq!(ours.cell_types, vec![3, 7]);
+        let theirs = Capabilities::none()
+            .with_feature(Capabilities::BATCH_QUERY | 1 << 31, true)
+            .with_cell_types(vec![7, 9]);
+        let negotiated = ours.negotiate(&theirs);
+        assert_eq!(negotiated, theirs.negotiate(&ours));
+        assert!(negotiated.supports_batch_query());
+        // Unknown features are dropped
+        assert_eq!(negotiated.features, Capabilities::BATCH_QUERY);
+        assert!(!Capabilities::none().negotiate(&ours).supports_batch_query());
+        assert!(negotiated.supports_cell_type(&CellType::Custom(7)));
+        assert!(!negotiated.supports_cell_type(&CellType::Custom(3)));
+        assert!(negotiated.supports_cell_type(&CellType::Transfer));
+    }
+}
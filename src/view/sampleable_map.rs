@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use rand::seq::SliceRandom;
 
@@ -9,6 +9,8 @@ pub struct SampleableMap<K: Eq + std::hash::Hash + Clone, V: Clone> {
     map: HashMap<K, V>,
     /// A queue of elements from `map` which is used in getting random elements upon request
     queue: Vec<(K, V)>,
+    /// Keys of elements which are kept in `map`, but never sampled
+    excluded: HashSet<K>,
 }
 
 impl<K, V> std::ops::Deref for SampleableMap<K, V>
@@ -36,7 +38,7 @@ where
 impl<K: Clone + Eq + std::hash::Hash, V: Clone> SampleableMap<K, V> {
     /// Create new instance with empty elements.
     pub fn new() -> Self {
-        Self { map: HashMap::default(), queue: vec![] }
+        Self { map: HashMap::default(), queue: vec![], excluded: HashSet::new() }
     }
 
     /// Returns a list of random `k`-elements.
@@ -55,8 +57,11 @@ impl<K: Clone + Eq + std::hash::Hash, V: Clone> SampleableMap<K, V> {
             } else {
                 match self.queue.pop() {
                     Some(val) => {
-                        result.push(val);
-                        i += 1;
+                        // The element may have been excluded after the queue was filled
+                        if !self.excluded.contains(&val.0) {
+                            result.push(val);
+                            i += 1;
+                        }
                     }
                     None => {
                         let mut rng = rand::thread_rng();
@@ -73,7 +78,34 @@ impl<K: Clone + Eq + std::hash::Hash, V: Clone> SampleableMap<K, V> {
         result
     }
 
+    /// Excludes the element with `key` from sampling, until it is [included][Self::include] again.
+    ///
+    /// Returns whether the element wasn't already excluded.
+    pub fn exclude(&mut self, key: K) -> bool {
+        self.excluded.insert(key)
+    }
+
+    /// Includes the element with `key` in sampling again.
+    ///
+    /// Returns whether the element was excluded.
+    pub fn include(&mut self, key: &K) -> bool {
+        self.excluded.remove(key)
+    }
+
+    /// The number of elements which can be sampled.
+    pub fn sampleable_len(&self) -> usize {
+        self.keys().filter(|k| !self.excluded.contains(*k)).count()
+    }
+
+    /// Whether the element with `key` is excluded from sampling.
+    pub fn is_excluded(&self, key: &K) -> bool {
+        self.excluded.contains(key)
+    }
+
     fn next_queue(&self) -> Vec<(K, V)> {
-        self.iter().map(|(k, v)| (k.clone(), v.clone())).collect::<Vec<(K, V)>>()
+        self.iter()
+            .filter(|(k, _)| !self.excluded.contains(*k))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<(K, V)>>()
     }
 }
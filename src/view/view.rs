@@ -1,23 +1,82 @@
 use super::sampleable_map::SampleableMap;
 
-use crate::client::{ClientRequest, ClientResponse};
+use crate::client::{self, ClientError, ClientRequest, ClientResponse};
 use crate::colored::Colorize;
 use crate::ice::{self, Ice};
 use crate::protocol::{Request, Response};
-use crate::version::{Version, VersionAck};
+use crate::storage::{self, peer as peer_storage, KvStore};
+use crate::util::Backoff;
+use crate::version::{
+    Capabilities, ProtocolVersion, Version, VersionAck, VersionRefused, CURRENT_VERSION,
+};
 use crate::zfx_id::Id;
 use crate::{Error, Result};
 
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
-use actix::{Actor, Addr, Context, Handler, Recipient};
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Recipient};
 use actix::{ActorFutureExt, ResponseActFuture};
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 const PEER_LIST_MAX: usize = 3;
 const BOOTSTRAP_QUORUM: usize = 2;
+/// The delay before retrying to bootstrap after the first failed attempt
+pub const BOOTSTRAP_BACKOFF_BASE_MS: u64 = 1000;
+/// The maximum delay between attempts to bootstrap
+pub const BOOTSTRAP_BACKOFF_CAP_MS: u64 = 30_000;
+/// The maximum share of the delay between attempts to bootstrap which is randomly skipped
+pub const BOOTSTRAP_BACKOFF_JITTER: f64 = 0.2;
+/// Peers with a score below this are excluded from sampling
+const MISBEHAVIOR_THRESHOLD: i64 = -100;
+/// Points per second by which a negative peer score recovers towards zero
+const SCORE_RECOVERY_PER_SECOND: i64 = 2;
+/// The interval of publishing the recovered reputations of peers, see [SubscribeReputations]
+const REPUTATION_UPDATE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Misbehavior of a peer, lowering its score by a [penalty][Misbehavior::penalty]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Misbehavior {
+    /// The peer didn't answer a query
+    Unresponsive,
+    /// The peer answered a query with an unexpected response
+    InvalidResponse,
+    /// The peer sent an invalid block
+    InvalidBlock,
+    /// The peer presented a different identity than expected
+    UnexpectedPeer,
+}
+
+impl Misbehavior {
+    pub fn penalty(&self) -> i64 {
+        match self {
+            Misbehavior::Unresponsive => 10,
+            Misbehavior::InvalidResponse => 50,
+            Misbehavior::InvalidBlock => 100,
+            Misbehavior::UnexpectedPeer => 100,
+        }
+    }
+}
+
+/// The score of a peer, which is non-positive and decays back to zero over time
+#[derive(Debug, Clone)]
+struct PeerScore {
+    score: i64,
+    /// The last time the score recovered
+    updated: Instant,
+}
+
+/// What a peer advertised in its handshake
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerMeta {
+    /// The protocol version of the peer
+    pub version: ProtocolVersion,
+    /// The capabilities supported by both the peer and this node, see [Capabilities::negotiate]
+    pub capabilities: Capabilities,
+}
 
 /// The view contains the most up to date set of peer metadata.
 #[derive(Debug)]
@@ -32,6 +91,18 @@ pub struct View {
     peers: SampleableMap<Id, SocketAddr>,
     /// A set of peers for bootstrapping this node
     peer_list: HashSet<(Id, SocketAddr)>,
+    /// Scores of peers which misbehaved, see [ReportPeerMisbehavior]
+    scores: HashMap<Id, PeerScore>,
+    /// Points per second by which the score of a peer recovers
+    score_recovery_per_second: i64,
+    /// Storage of known-good peers, which are reloaded on restart
+    peer_store: Option<Arc<dyn KvStore>>,
+    /// The capabilities advertised by this node in the handshake
+    capabilities: Capabilities,
+    /// The metadata of the reachable peers in the view which completed a handshake
+    peer_meta: HashMap<Id, PeerMeta>,
+    /// The subscribers of [PeerReputations] updates
+    reputation_recipients: Vec<Recipient<PeerReputations>>,
 }
 
 impl std::ops::Deref for View {
@@ -56,7 +127,81 @@ impl View {
     /// * `ip` - node IP address
     /// * `node_id` - node Id
     pub fn new(sender: Recipient<ClientRequest>, ip: SocketAddr, node_id: Id) -> Self {
-        Self { sender, ip, node_id, peers: SampleableMap::new(), peer_list: HashSet::new() }
+        Self {
+            sender,
+            ip,
+            node_id,
+            peers: SampleableMap::new(),
+            peer_list: HashSet::new(),
+            scores: HashMap::new(),
+            score_recovery_per_second: SCORE_RECOVERY_PER_SECOND,
+            peer_store: None,
+            capabilities: Capabilities::current(),
+            peer_meta: HashMap::new(),
+            reputation_recipients: vec![],
+        }
+    }
+
+    /// Advertise `capabilities` instead of the [current ones][Capabilities::current]
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Store the `capabilities` of peer `id`, keeping only those which this node supports as
+    /// well. Peers which left the view in the meantime are ignored.
+    fn update_peer_meta(&mut self, id: Id, version: ProtocolVersion, capabilities: &Capabilities) {
+        if !self.peers.contains_key(&id) || self.peers.is_excluded(&id) {
+            return;
+        }
+        let capabilities = self.capabilities.negotiate(capabilities);
+        debug!("negotiated capabilities with {}: {:?}", id, capabilities);
+        let _ = self.peer_meta.insert(id, PeerMeta { version, capabilities });
+    }
+
+    /// Remember the peers which answered a handshake of this node in `peer_store`
+    pub fn with_peer_store(mut self, peer_store: Arc<dyn KvStore>) -> Self {
+        self.peer_store = Some(peer_store);
+        self
+    }
+
+    /// Add the peers of the peer store which were seen within `max_age`, along with their
+    /// scores. Older peers are dropped from the store.
+    ///
+    /// Returns the loaded peers, which can be used for bootstrapping.
+    pub fn load_peers(&mut self, max_age: Duration) -> storage::Result<Vec<(Id, SocketAddr)>> {
+        let records = match &self.peer_store {
+            Some(peer_store) => peer_storage::get_peers(peer_store.as_ref(), max_age)?,
+            None => return Ok(vec![]),
+        };
+        let peers: Vec<(Id, SocketAddr)> = records.iter().map(|r| (r.id, r.ip)).collect();
+        self.init(peers.clone());
+        for record in records.iter().filter(|record| record.score < 0) {
+            let _ = self
+                .scores
+                .insert(record.id, PeerScore { score: record.score, updated: Instant::now() });
+            if record.score < MISBEHAVIOR_THRESHOLD {
+                let _ = self.peers.exclude(record.id);
+            }
+        }
+        Ok(peers)
+    }
+
+    /// Store peer `id` at `ip` with its current score, if there is a peer store
+    fn remember_peer(&self, id: Id, ip: SocketAddr) {
+        if let Some(peer_store) = &self.peer_store {
+            let score = self.scores.get(&id).map(|s| s.score).unwrap_or(0);
+            let record = peer_storage::PeerRecord::new(id, ip, score);
+            if let Err(e) = peer_storage::insert_peer(peer_store.as_ref(), &record) {
+                warn!("failed to store peer {}: {:?}", id, e);
+            }
+        }
+    }
+
+    /// Recover the scores of peers by `points_per_second` instead of [SCORE_RECOVERY_PER_SECOND]
+    pub fn with_score_recovery(mut self, points_per_second: i64) -> Self {
+        self.score_recovery_per_second = points_per_second;
+        self
     }
 
     /// Add `peers` to the current `View`
@@ -96,9 +241,80 @@ impl View {
         }
     }
 
-    /// Get random `k`-peers
+    /// Lower the score of peer `id` by the penalty of `reason`, excluding the peer from sampling
+    /// once its score drops below [MISBEHAVIOR_THRESHOLD].
+    ///
+    /// Returns the new score of the peer.
+    pub fn report_misbehavior(&mut self, id: Id, reason: Misbehavior) -> i64 {
+        self.recover_scores();
+        let peer_score =
+            self.scores.entry(id).or_insert(PeerScore { score: 0, updated: Instant::now() });
+        peer_score.score -= reason.penalty();
+        let score = peer_score.score;
+        debug!("{} misbehaved ({:?}), score = {}", id, reason, score);
+        if score < MISBEHAVIOR_THRESHOLD && self.peers.exclude(id) {
+            info!("excluding {} from sampling (score = {})", id, score);
+            let _ = self.peer_meta.remove(&id);
+        }
+        score
+    }
+
+    /// Recover the scores of peers since their last update, including peers in sampling again
+    /// once their score is back above [MISBEHAVIOR_THRESHOLD].
+    fn recover_scores(&mut self) {
+        let now = Instant::now();
+        let rate = self.score_recovery_per_second;
+        let mut recovered = vec![];
+        for (id, peer_score) in self.scores.iter_mut() {
+            let points = now.duration_since(peer_score.updated).as_millis() as i64 * rate / 1000;
+            // Keep the timestamp until at least a point is recovered, so that it isn't lost
+            if points > 0 {
+                peer_score.score = std::cmp::min(0, peer_score.score + points);
+                peer_score.updated = now;
+            }
+            if peer_score.score >= MISBEHAVIOR_THRESHOLD {
+                recovered.push((id.clone(), peer_score.score));
+            }
+        }
+        for (id, score) in recovered {
+            if self.peers.include(&id) {
+                info!("including {} in sampling again (score = {})", id, score);
+            }
+            if score == 0 {
+                let _ = self.scores.remove(&id);
+            }
+        }
+    }
+
+    /// Returns the reputations of the peers which misbehaved lately, from `0.0` for a score at
+    /// [MISBEHAVIOR_THRESHOLD] or below to `1.0` for a fully recovered score. Peers which aren't
+    /// listed have a reputation of `1.0`.
+    pub fn reputations(&mut self) -> HashMap<Id, f64> {
+        self.recover_scores();
+        self.scores
+            .iter()
+            .map(|(id, peer_score)| {
+                let reputation = 1.0 - peer_score.score as f64 / MISBEHAVIOR_THRESHOLD as f64;
+                (id.clone(), reputation.max(0.0))
+            })
+            .collect()
+    }
+
+    /// Send the current [reputations][View::reputations] to the subscribers
+    fn publish_reputations(&mut self) {
+        if self.reputation_recipients.is_empty() {
+            return;
+        }
+        let reputations = self.reputations();
+        for recipient in self.reputation_recipients.iter() {
+            let _ = recipient.do_send(PeerReputations { reputations: reputations.clone() });
+        }
+    }
+
+    /// Get random `k`-peers, besides peers excluded for misbehavior
     pub fn sample_k(&mut self, k: usize) -> Vec<(Id, SocketAddr)> {
-        if self.len() >= k {
+        self.recover_scores();
+        if self.sampleable_len() >= k {
             self.sample(k)
         } else {
             vec![]
@@ -109,26 +325,43 @@ impl View {
 impl Actor for View {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Context<Self>) {
-        debug!(": started")
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        debug!(": started");
+        // Scores recover over time, so the subscribers are kept up to date periodically
+        let _ = ctx.run_interval(REPUTATION_UPDATE_INTERVAL, |act, _ctx| {
+            act.publish_reputations();
+        });
     }
 }
 
 impl Handler<Version> for View {
-    type Result = VersionAck;
+    type Result = std::result::Result<VersionAck, VersionRefused>;
 
     fn handle(&mut self, msg: Version, _ctx: &mut Context<Self>) -> Self::Result {
-        // TODO: verify / extend `Version`
+        // Peers on an incompatible version may not understand our messages
+        if let Err(e) = msg.validate() {
+            info!("refusing handshake of {}: {:?}", msg.id, e);
+            return Err(VersionRefused::new());
+        }
         let ip = msg.ip.clone();
         let id = msg.id.clone();
+        // The claimed id and address aren't checked, so the peer isn't remembered until it
+        // answers a handshake of ours
         let _ = self.insert_update(id, ip);
+        self.update_peer_meta(id, msg.version, &msg.capabilities);
 
         // Fetch the peer list
         let mut peer_vec = vec![];
         for peer in self.peer_list.iter().cloned() {
             peer_vec.push(peer);
         }
-        VersionAck { ip: self.ip.clone(), id: self.node_id.clone(), peer_list: peer_vec }
+        Ok(VersionAck {
+            ip: self.ip.clone(),
+            id: self.node_id.clone(),
+            version: CURRENT_VERSION,
+            capabilities: self.capabilities.clone(),
+            peer_list: peer_vec,
+        })
     }
 }
 
@@ -155,6 +388,21 @@ impl Handler<GetPeers> for View {
     }
 }
 
+/// Request for the metadata of peer `id`, if it completed a handshake.
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "Option<PeerMeta>")]
+pub struct GetPeerMeta {
+    pub id: Id,
+}
+
+impl Handler<GetPeerMeta> for View {
+    type Result = Option<PeerMeta>;
+
+    fn handle(&mut self, msg: GetPeerMeta, _ctx: &mut Context<Self>) -> Self::Result {
+        self.peer_meta.get(&msg.id).cloned()
+    }
+}
+
 /// Request from [View] to bootstrap other nodes from the list of `peers`.
 #[derive(Debug, Clone, Serialize, Deserialize, Message)]
 #[rtype(result = "Result<BootstrapResult>")]
@@ -172,6 +420,7 @@ impl Handler<Bootstrap> for View {
     fn handle(&mut self, _msg: Bootstrap, _ctx: &mut Context<Self>) -> Self::Result {
         let ip = self.ip.clone();
         let id = self.node_id;
+        let capabilities = self.capabilities.clone();
         // Use all seeded ips as bootstrap ips (besides self_ip)
         let mut bootstrap_peers = vec![];
         for (id, ip) in self.iter() {
@@ -183,14 +432,17 @@ impl Handler<Bootstrap> for View {
         // Fanout requests to the bootstrap seeds
         let send_to_client = self.sender.send(ClientRequest::Fanout {
             peers: bootstrap_peers.clone(),
-            request: Request::Version(Version { id, ip }),
+            request: Request::Version(Version::new(id, ip, capabilities)),
+            timeout: None,
         });
         // Wrap the future so that subsequent chained handlers can access the actor
         let send_to_client = actix::fut::wrap_future::<_, Self>(send_to_client);
 
         let handle_response = send_to_client.map(move |result, _actor, _ctx| match result {
-            Ok(ClientResponse::Fanout(responses)) => Ok(BootstrapResult { responses }),
-            Ok(_) => Err(Error::InvalidResponse),
+            Ok(ClientResponse::Fanout(results)) => {
+                Ok(BootstrapResult { responses: client::successful_responses(results) })
+            }
+            Ok(_) => Err(Error::Client(ClientError::UnexpectedResponseKind)),
             Err(e) => Err(Error::Actix(e)),
         });
 
@@ -219,27 +471,103 @@ impl Handler<UpdatePeers> for View {
     fn handle(&mut self, msg: UpdatePeers, _ctx: &mut Context<Self>) -> Self::Result {
         // Update the view with successful responses
         let mut updates = vec![];
+        let mut accepted = 0;
         for response in msg.responses.iter() {
             match response {
-                Response::VersionAck(VersionAck { ip, id: peer_id, peer_list }) => {
+                Response::VersionAck(VersionAck {
+                    ip,
+                    id: peer_id,
+                    version,
+                    capabilities,
+                    peer_list,
+                }) => {
+                    if !version.is_supported() {
+                        info!("ignoring {}: {:?}", peer_id, Error::IncompatibleVersion(*version));
+                        continue;
+                    }
+                    accepted += 1;
+                    self.remember_peer(*peer_id, *ip);
                     if self.insert_update(peer_id.clone(), ip.clone()) {
                         updates.push((peer_id.clone(), ip.clone()));
                     }
+                    self.update_peer_meta(*peer_id, *version, capabilities);
                     for (peer_id, peer_ip) in peer_list {
                         if self.insert_update(peer_id.clone(), peer_ip.clone()) {
                             updates.push((peer_id.clone(), peer_ip.clone()));
                         }
                     }
                 }
+                Response::VersionRefused(version_refused) => {
+                    info!("handshake refused: {:?}", Error::from(version_refused.clone()))
+                }
                 // FIXME: Other responses are invalid #nosec
                 _ => (),
             }
         }
-        let bootstrapped = msg.responses.len() >= BOOTSTRAP_QUORUM;
+        let bootstrapped = accepted >= BOOTSTRAP_QUORUM;
         Updated { updates, bootstrapped }
     }
 }
 
+/// Report misbehavior of a peer to the [View], lowering its score.
+///
+/// Peers with a score below [MISBEHAVIOR_THRESHOLD] are excluded from sampling until their
+/// score recovers.
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+pub struct ReportPeerMisbehavior {
+    pub id: Id,
+    pub reason: Misbehavior,
+}
+
+impl Handler<ReportPeerMisbehavior> for View {
+    type Result = ();
+
+    fn handle(&mut self, msg: ReportPeerMisbehavior, _ctx: &mut Context<Self>) -> Self::Result {
+        let _ = self.report_misbehavior(msg.id, msg.reason);
+        self.publish_reputations();
+    }
+}
+
+/// A peer which missed too many heartbeats is reported as [Misbehavior::Unresponsive].
+impl Handler<client::PeerUnreachable> for View {
+    type Result = ();
+
+    fn handle(&mut self, msg: client::PeerUnreachable, _ctx: &mut Context<Self>) -> Self::Result {
+        // The capabilities are advertised again in the next handshake
+        let _ = self.peer_meta.remove(&msg.id);
+        let _ = self.report_misbehavior(msg.id, Misbehavior::Unresponsive);
+        self.publish_reputations();
+    }
+}
+
+/// The reputations of the peers which misbehaved lately, see [View::reputations].
+///
+/// Meant to bias the sampling of validators, see
+/// [sample_weighted_biased][crate::util::sample_weighted_biased].
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct PeerReputations {
+    pub reputations: HashMap<Id, f64>,
+}
+
+/// Subscribes `recipient` to the [PeerReputations] of the [View], which are sent right away,
+/// after each reported misbehavior and periodically as the scores recover.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SubscribeReputations {
+    pub recipient: Recipient<PeerReputations>,
+}
+
+impl Handler<SubscribeReputations> for View {
+    type Result = ();
+
+    fn handle(&mut self, msg: SubscribeReputations, _ctx: &mut Context<Self>) -> Self::Result {
+        let _ = msg.recipient.do_send(PeerReputations { reputations: self.reputations() });
+        self.reputation_recipients.push(msg.recipient);
+    }
+}
+
 /// Sample random `k`-peers from the view.
 #[derive(Debug, Clone, Serialize, Deserialize, Message)]
 #[rtype(result = "SampleResult")]
@@ -262,17 +590,29 @@ impl Handler<SampleK> for View {
     }
 }
 
-/// Retry to bootstrap until the quorum is reached.
+/// Retry to bootstrap until the quorum is reached, with the default [Backoff] between
+/// attempts.
 ///
 /// ## Parameters:
 /// * `view` - address of [View] actor
 /// * `ice` - address of [Ice][crate::ice::Ice] actor
 pub async fn bootstrap(view: Addr<View>, ice: Addr<Ice>) {
-    let mut i = 3;
+    let backoff = Backoff::new(
+        Duration::from_millis(BOOTSTRAP_BACKOFF_BASE_MS),
+        Duration::from_millis(BOOTSTRAP_BACKOFF_CAP_MS),
+        BOOTSTRAP_BACKOFF_JITTER,
+    );
+    bootstrap_with_backoff(view, ice, backoff).await
+}
+
+/// Retry to bootstrap until the quorum is reached, waiting for `backoff` between attempts.
+/// The backoff is reset whenever some of the bootstrap peers could be reached.
+pub async fn bootstrap_with_backoff(view: Addr<View>, ice: Addr<Ice>, mut backoff: Backoff) {
     loop {
         let BootstrapResult { responses } = view.send(Bootstrap {}).await.unwrap().unwrap();
         let lim = responses.len();
         if lim > 0 {
+            backoff.reset();
             let Updated { bootstrapped, .. } =
                 view.send(UpdatePeers { responses: responses.clone() }).await.unwrap();
             if bootstrapped {
@@ -285,8 +625,185 @@ pub async fn bootstrap(view: Addr<View>, ice: Addr<Ice>) {
                 }
             }
         }
-        let duration = tokio::time::Duration::from_millis(1000) * i;
+        let duration = backoff.next_delay(&mut rand::thread_rng());
         actix::clock::sleep(duration).await;
-        i += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::client::Client;
+    use crate::tls::upgrader::tcp_upgraders;
+
+    fn new_view() -> View {
+        let client = Client::new(tcp_upgraders().client).start();
+        let ip: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        View::new(client.recipient(), ip, Id::from_ip(&ip))
+    }
+
+    fn start_view() -> Addr<View> {
+        let client = Client::new(tcp_upgraders().client).start();
+        let ip: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        View::new(client.recipient(), ip, Id::from_ip(&ip)).start()
+    }
+
+    #[actix_rt::test]
+    async fn test_refuse_unsupported_version() {
+        let view = start_view();
+        let ip: SocketAddr = "127.0.0.1:1235".parse().unwrap();
+        let handshake = Version::new(Id::from_ip(&ip), ip, Capabilities::current());
+        let version = ProtocolVersion { major: CURRENT_VERSION.major + 1, minor: 0 };
+        let refused =
+            view.send(Version { version, ..handshake.clone() }).await.unwrap().unwrap_err();
+        assert_eq!(refused, VersionRefused::new());
+        assert!(matches!(
+            Error::from(refused),
+            Error::IncompatibleVersion(version) if version == CURRENT_VERSION
+        ));
+        // The refused peer isn't added to the view
+        let PeersResult { peers } = view.send(GetPeers).await.unwrap();
+        assert!(peers.is_empty());
+
+        // A newer minor version is compatible
+        let version = ProtocolVersion { minor: CURRENT_VERSION.minor + 1, ..CURRENT_VERSION };
+        let ack = view.send(Version { version, ..handshake.clone() }).await.unwrap().unwrap();
+        assert_eq!(ack.version, CURRENT_VERSION);
+    }
+
+    #[actix_rt::test]
+    async fn test_store_negotiated_capabilities() {
+        let ip: SocketAddr = "127.0.0.1:1235".parse().unwrap();
+        let id = Id::from_ip(&ip);
+        let old_ip: SocketAddr = "127.0.0.1:1236".parse().unwrap();
+        let old_id = Id::from_ip(&old_ip);
+        let view = start_view();
+        assert_eq!(view.send(GetPeerMeta { id }).await.unwrap(), None);
+
+        // The peer lacks batch queries and advertises a custom cell type unknown to us
+        let capabilities = Capabilities::current()
+            .with_feature(Capabilities::BATCH_QUERY, false)
+            .with_cell_types(vec![1]);
+        let ack = view.send(Version::new(id, ip, capabilities)).await.unwrap().unwrap();
+        assert_eq!(ack.capabilities, Capabilities::current());
+        let _ = view.send(Version::new(old_id, old_ip, Capabilities::none())).await.unwrap();
+
+        let meta = view.send(GetPeerMeta { id }).await.unwrap().unwrap();
+        assert_eq!(meta.version, CURRENT_VERSION);
+        assert!(!meta.capabilities.supports_batch_query());
+        assert!(meta.capabilities.cell_types.is_empty());
+
+        // The peer advertising no capabilities is used without optional features
+        let meta = view.send(GetPeerMeta { id: old_id }).await.unwrap().unwrap();
+        assert_eq!(meta.capabilities, Capabilities::none());
+
+        // The metadata of unreachable peers is dropped
+        view.send(client::PeerUnreachable { id, ip }).await.unwrap();
+        assert_eq!(view.send(GetPeerMeta { id }).await.unwrap(), None);
+    }
+
+    #[actix_rt::test]
+    async fn test_exclude_misbehaving_peer() {
+        let peers: Vec<(Id, SocketAddr)> = (1235..1238)
+            .map(|port| {
+                let ip: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+                (Id::from_ip(&ip), ip)
+            })
+            .collect();
+        let (faulty, _) = peers[0];
+        let mut view = new_view().with_score_recovery(100);
+        view.init(peers.clone());
+
+        // A single fault doesn't exclude the peer
+        assert_eq!(view.report_misbehavior(faulty, Misbehavior::Unresponsive), -10);
+        assert!(!view.is_excluded(&faulty));
+
+        let score = view.report_misbehavior(faulty, Misbehavior::InvalidBlock);
+        assert!(score < MISBEHAVIOR_THRESHOLD);
+        assert!(view.is_excluded(&faulty));
+        for _ in 0..10 {
+            let sample = view.sample_k(2);
+            assert_eq!(sample.len(), 2);
+            assert!(sample.iter().all(|(id, _)| *id != faulty));
+        }
+        // Only the other 2 peers can be sampled
+        assert!(view.sample_k(3).is_empty());
+
+        // The score recovers above the threshold after the decay window
+        actix::clock::sleep(std::time::Duration::from_millis(1100)).await;
+        let mut sampled = false;
+        for _ in 0..10 {
+            sampled |= view.sample_k(2).iter().any(|(id, _)| *id == faulty);
+        }
+        assert!(sampled);
+        assert!(!view.is_excluded(&faulty));
+    }
+
+    #[actix_rt::test]
+    async fn test_peer_reputations() {
+        let ip: SocketAddr = "127.0.0.1:1235".parse().unwrap();
+        let (flaky, faulty) = (Id::from_ip(&ip), Id::one());
+        let mut view = new_view().with_score_recovery(0);
+        view.init(vec![(flaky, ip)]);
+        assert!(view.reputations().is_empty());
+
+        let _ = view.report_misbehavior(flaky, Misbehavior::InvalidResponse);
+        let _ = view.report_misbehavior(faulty, Misbehavior::InvalidBlock);
+        let _ = view.report_misbehavior(faulty, Misbehavior::InvalidBlock);
+        let reputations = view.reputations();
+        assert_eq!(reputations.len(), 2);
+        assert_eq!(reputations.get(&flaky), Some(&0.5));
+        // The reputation doesn't drop below zero
+        assert_eq!(reputations.get(&faulty), Some(&0.0));
+    }
+
+    #[actix_rt::test]
+    async fn test_reload_peers_after_restart() {
+        let peer_store: Arc<dyn KvStore> = Arc::new(storage::InMemoryStore::new());
+        let peers: Vec<(Id, SocketAddr)> = (1235..1238)
+            .map(|port| {
+                let ip: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+                (Id::from_ip(&ip), ip)
+            })
+            .collect();
+
+        // Peers which answer a handshake are remembered
+        let view = new_view().with_peer_store(peer_store.clone()).start();
+        let responses = peers
+            .iter()
+            .map(|(id, ip)| {
+                Response::VersionAck(VersionAck {
+                    id: *id,
+                    ip: *ip,
+                    version: CURRENT_VERSION,
+                    capabilities: Capabilities::current(),
+                    peer_list: vec![],
+                })
+            })
+            .collect();
+        let _ = view.send(UpdatePeers { responses }).await.unwrap();
+
+        // Peers which only sent a handshake are not
+        let ip: SocketAddr = "127.0.0.1:1239".parse().unwrap();
+        let handshake = Version::new(Id::from_ip(&ip), ip, Capabilities::current());
+        let _ = view.send(handshake).await.unwrap().unwrap();
+        let ip: SocketAddr = "127.0.0.1:1238".parse().unwrap();
+        let stale = peer_storage::PeerRecord {
+            last_seen: peer_storage::now() - 120,
+            ..peer_storage::PeerRecord::new(Id::from_ip(&ip), ip, 0)
+        };
+        let _ = peer_storage::insert_peer(peer_store.as_ref(), &stale).unwrap();
+
+        // After a restart, the view starts without bootstrap peers and reloads the recent ones
+        let mut view = new_view().with_peer_store(peer_store);
+        let mut loaded = view.load_peers(Duration::from_secs(60)).unwrap();
+        loaded.sort();
+        let mut expected = peers.clone();
+        expected.sort();
+        assert_eq!(loaded, expected);
+        let mut sample = view.sample_k(3);
+        sample.sort();
+        assert_eq!(sample, expected);
     }
 }
@@ -12,15 +12,35 @@ use base58check::{FromBase58Check, ToBase58Check};
 use blake2::digest::{Update, VariableOutput};
 use blake2::Blake2bVar;
 use rand::{self, Rng};
+use subtle::ConstantTimeEq;
 
 /// Generic hash-based ID for use throughout the system
 ///
 /// The `Id` wraps a 32-byte hash, used as identifier for various objects, for example for network peers.
 ///
-/// They are displayed using the Base58check format.
-#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Serialize, Deserialize, Default)]
+/// They are displayed using the Base58check format. For copy-pasting, [Id::to_bech32] provides
+/// a checksummed encoding which catches typos, which is accepted by [Id::from_str] as well.
+///
+/// Equality is checked in constant time, as `Id`s are compared when authenticating peers.
+/// Ordering is not constant-time.
+#[derive(Ord, PartialOrd, Copy, Clone, Serialize, Deserialize, Default)]
 pub struct Id([u8; 32]);
 
+impl PartialEq for Id {
+    fn eq(&self, other: &Id) -> bool {
+        self.0.ct_eq(&other.0).into()
+    }
+}
+
+impl Eq for Id {}
+
+// Implemented by hand as `PartialEq` is, hashing the same bytes as the derived implementation
+impl std::hash::Hash for Id {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
 impl std::fmt::Debug for Id {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.0.to_base58check(0))
@@ -36,8 +56,11 @@ impl std::fmt::Display for Id {
 impl FromStr for Id {
     type Err = crate::Error;
 
-    /// Converts a base58check encoded string to bytes of an Id
+    /// Converts a bech32 or base58check encoded string to bytes of an Id
     fn from_str(id_str: &str) -> Result<Self, crate::Error> {
+        if let Ok(id) = Id::from_bech32(id_str) {
+            return Ok(id);
+        }
         let (vsn, bytes) =
             id_str.from_base58check().map_err(|_| crate::Error::TryFromStringError)?;
         if vsn != 0 {
@@ -55,6 +78,22 @@ impl Id {
         Id(hash(bytes))
     }
 
+    /// Derives the `Id` of an ed25519 public key, which is the blake3 hash of the serialized key.
+    ///
+    /// This is the same hash that cell outputs are locked to, and the one nodes are identified
+    /// by from their TLS certificate, so the `Id` of a node is tied to its signing key.
+    pub fn from_public_key(public_key: &ed25519_dalek::PublicKey) -> Id {
+        // Serializing into a `Vec` can't fail
+        let encoded = bincode::serialize(public_key).unwrap();
+        Id(*blake3::hash(&encoded).as_bytes())
+    }
+
+    /// Derives the `Id` of a secp256k1 public key from the blake3 hash of its SEC1 encoding,
+    /// which is what outputs of the secp256k1 scheme are locked to.
+    pub fn from_secp256k1_public_key(public_key: &[u8]) -> Id {
+        Id(*blake3::hash(public_key).as_bytes())
+    }
+
     /// Sets the bytes of an Id explicitly (expects a hash)
     pub fn from_hash(bytes: &[u8]) -> Id {
         let mut byte_vec = bytes.to_vec();
@@ -96,6 +135,46 @@ impl Id {
         Id([2u8; 32])
     }
 
+    /// Encodes the Id in [bech32](https://github.com/bitcoin/bips/blob/master/bip-0173.mediawiki)
+    /// with the human-readable part `hrp` (for example [ID_HRP]).
+    pub fn to_bech32(&self, hrp: &str) -> String {
+        let hrp = hrp.to_lowercase();
+        let mut data = convert_bits(&self.0, 8, 5, true).unwrap();
+        let checksum = bech32_checksum(&hrp, &data);
+        data.extend_from_slice(&checksum);
+        let mut encoded = hrp;
+        encoded.push('1');
+        encoded.extend(data.iter().map(|d| BECH32_CHARSET[*d as usize] as char));
+        encoded
+    }
+
+    /// Decodes a bech32 encoded Id with any human-readable part, verifying the checksum
+    pub fn from_bech32(id_str: &str) -> Result<Id, crate::Error> {
+        if id_str.to_lowercase() != id_str && id_str.to_uppercase() != id_str {
+            return Err(crate::Error::TryFromStringError);
+        }
+        let id_str = id_str.to_lowercase();
+        let separator = id_str.rfind('1').ok_or(crate::Error::TryFromStringError)?;
+        let (hrp, data) = (&id_str[..separator], &id_str.as_bytes()[separator + 1..]);
+        if hrp.is_empty() || !hrp.bytes().all(|c| (33..=126).contains(&c)) || data.len() < 6 {
+            return Err(crate::Error::TryFromStringError);
+        }
+        let data = data
+            .iter()
+            .map(|c| BECH32_CHARSET.iter().position(|d| d == c).map(|d| d as u8))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or(crate::Error::TryFromStringError)?;
+        let mut values = hrp_expand(hrp);
+        values.extend_from_slice(&data);
+        if bech32_polymod(&values) != 1 {
+            return Err(crate::Error::TryFromStringError);
+        }
+        let bytes = convert_bits(&data[..data.len() - 6], 5, 8, false)?;
+        let bytes: [u8; 32] =
+            bytes.as_slice().try_into().map_err(|_| crate::Error::TryFromStringError)?;
+        Ok(Id(bytes))
+    }
+
     /// Returns the wrapped byte array containing the hash
     pub fn bytes(&self) -> [u8; 32] {
         self.0
@@ -162,6 +241,68 @@ impl Index<std::ops::Range<usize>> for Id {
     }
 }
 
+/// The human-readable part of bech32 encoded node Ids
+pub const ID_HRP: &str = "zfx";
+
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut chk: u32 = 1;
+    for v in values.iter() {
+        let top = chk >> 25;
+        chk = (chk & 0x1ffffff) << 5 ^ (*v as u32);
+        for (i, g) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    expanded.push(0);
+    expanded.extend(hrp.bytes().map(|c| c & 31));
+    expanded
+}
+
+fn bech32_checksum(hrp: &str, data: &[u8]) -> [u8; 6] {
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0u8; 6]);
+    let polymod = bech32_polymod(&values) ^ 1;
+    let mut checksum = [0u8; 6];
+    for (i, c) in checksum.iter_mut().enumerate() {
+        *c = ((polymod >> (5 * (5 - i))) & 31) as u8;
+    }
+    checksum
+}
+
+/// Regroups `data` from groups of `from` bits into groups of `to` bits
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>, crate::Error> {
+    let (mut acc, mut bits) = (0u32, 0u32);
+    let max = (1u32 << to) - 1;
+    let mut converted = vec![];
+    for d in data.iter() {
+        acc = (acc << from) | (*d as u32);
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            converted.push(((acc >> bits) & max) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            converted.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || (acc << (to - bits)) & max != 0 {
+        return Err(crate::Error::TryFromStringError);
+    }
+    Ok(converted)
+}
+
 // This function is the replacement for `zfx_crypto`s `hash!` macro
 fn hash(input: &[u8]) -> [u8; 32] {
     let mut hasher = Blake2bVar::new(32).unwrap();
@@ -170,3 +311,85 @@ fn hash(input: &[u8]) -> [u8; 32] {
     hasher.finalize_variable(&mut buf).unwrap();
     buf
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn test_constant_time_eq() {
+        let id = Id::generate();
+        assert_eq!(id, Id::from_hash(&id.bytes()));
+        assert_ne!(Id::zero(), Id::one());
+        let mut last_byte = id.bytes();
+        last_byte[31] ^= 1;
+        assert_ne!(id, Id::from_hash(&last_byte));
+
+        let mut set = std::collections::HashSet::new();
+        assert!(set.insert(id));
+        assert!(!set.insert(Id::from_hash(&id.bytes())));
+        assert!(Id::zero() < Id::one());
+    }
+
+    #[actix_rt::test]
+    async fn test_from_public_key() {
+        let mut csprng = rand::rngs::OsRng {};
+        let keypair1 = ed25519_dalek::Keypair::generate(&mut csprng);
+        let keypair2 = ed25519_dalek::Keypair::generate(&mut csprng);
+        let id1 = Id::from_public_key(&keypair1.public);
+        assert_eq!(id1, Id::from_public_key(&keypair1.public));
+        assert_ne!(id1, Id::from_public_key(&keypair2.public));
+        // The same hash is used for locking cell outputs
+        let encoded = bincode::serialize(&keypair1.public).unwrap();
+        assert_eq!(id1.bytes(), *blake3::hash(&encoded).as_bytes());
+    }
+
+    #[actix_rt::test]
+    async fn test_from_secp256k1_public_key() {
+        // The compressed SEC1 encoding of the secp256k1 generator point
+        let public_key =
+            hex::decode("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+                .unwrap();
+        let expected =
+            hex::decode("1a15d6547364ad963c3b840e885639bb8136a4de757c0e7cf63f5de2f1cfbdbe")
+                .unwrap();
+        assert_eq!(Id::from_secp256k1_public_key(&public_key), Id::from_hash(&expected));
+    }
+
+    #[actix_rt::test]
+    async fn test_bech32_round_trip() {
+        for id in [Id::zero(), Id::max(), Id::generate()].iter() {
+            let encoded = id.to_bech32(ID_HRP);
+            assert!(encoded.starts_with("zfx1"));
+            assert_eq!(Id::from_bech32(&encoded).unwrap(), *id);
+            assert_eq!(Id::from_bech32(&encoded.to_uppercase()).unwrap(), *id);
+            assert_eq!(encoded.parse::<Id>().unwrap(), *id);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_bech32_known_encoding() {
+        let encoded = "zfx1qyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqspp9lrd";
+        assert_eq!(Id::one().to_bech32(ID_HRP), encoded);
+    }
+
+    #[actix_rt::test]
+    async fn test_legacy_format_still_parses() {
+        let id = Id::generate();
+        assert_eq!(format!("{}", id).parse::<Id>().unwrap(), id);
+    }
+
+    #[actix_rt::test]
+    async fn test_bech32_corrupted_checksum() {
+        let encoded = Id::one().to_bech32(ID_HRP);
+        let mut corrupted = encoded.clone().into_bytes();
+        let i = corrupted.len() - 10;
+        corrupted[i] = if corrupted[i] == b'q' { b'p' } else { b'q' };
+        let corrupted = String::from_utf8(corrupted).unwrap();
+        assert!(matches!(Id::from_bech32(&corrupted), Err(crate::Error::TryFromStringError)));
+        assert!(matches!(corrupted.parse::<Id>(), Err(crate::Error::TryFromStringError)));
+        // Mixed case is not valid bech32
+        let mixed = format!("ZFX{}", &encoded[3..]);
+        assert!(matches!(Id::from_bech32(&mixed), Err(crate::Error::TryFromStringError)));
+    }
+}